@@ -0,0 +1,333 @@
+// Acceleration structures of the scene, for ray tracing. Every mesh gets a bottom-level
+// acceleration structure (BLAS) of its triangles the first time it is traced, and the top-level
+// one (TLAS) holds an instance of the BLAS of every loaded entity, placed with its transform.
+//
+// The TLAS only refers to the BLASes by their device addresses, which vulkano can't synchronize
+// with, so each BLAS is built in a command buffer of its own that is waited on. The TLAS is built
+// anew every frame into new memory, so that frames still in flight keep theirs, in the frame's
+// command buffer ahead of the rays that are traced through it.
+
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    acceleration_structure::{
+        AccelerationStructure, AccelerationStructureBuildGeometryInfo,
+        AccelerationStructureBuildRangeInfo, AccelerationStructureBuildType,
+        AccelerationStructureCreateInfo, AccelerationStructureGeometries,
+        AccelerationStructureGeometryInstancesData, AccelerationStructureGeometryTrianglesData,
+        AccelerationStructureInstance, AccelerationStructureType, BuildAccelerationStructureFlags,
+        GeometryFlags,
+    },
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, IndexBuffer, Subbuffer},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+    },
+    device::{DeviceOwned, Queue},
+    format::Format,
+    memory::{
+        allocator::{
+            AllocationCreateInfo, DeviceLayout, MemoryTypeFilter, StandardMemoryAllocator,
+        },
+        DeviceAlignment,
+    },
+    sync::{self, GpuFuture},
+    Packed24_8,
+};
+
+use crate::{
+    components::{MaterialOverride, MeshHandle, Transform},
+    debug_utils,
+    material::Material,
+    mesh::{Mesh, MeshAddresses, MeshVertex},
+    scene::Scene,
+};
+
+/// What the ray tracing shaders shade an instance with, found by its custom index.
+#[derive(BufferContents, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct InstanceData {
+    pub mesh: MeshAddresses,
+    pub base_color: [f32; 4],
+}
+
+/// The TLAS of a frame, with the data of each of its instances.
+pub struct Tlas {
+    pub acceleration_structure: Arc<AccelerationStructure>,
+    pub instances: Subbuffer<[InstanceData]>,
+}
+
+pub struct AccelerationStructures {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    queue: Arc<Queue>,
+    /// The BLAS of every mesh traced so far, by its address. The meshes are kept alive so that
+    /// their addresses aren't reused, and the BLASes because TLASes in flight may refer to them.
+    blases: HashMap<*const Mesh, (Arc<Mesh>, Arc<AccelerationStructure>)>,
+}
+
+impl AccelerationStructures {
+    /// The device must support ray tracing or ray queries, as `Capabilities` tells, and the meshes
+    /// must have been created on it since, so that their buffers can be built from.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: Arc<Queue>,
+    ) -> Self {
+        AccelerationStructures {
+            memory_allocator,
+            command_buffer_allocator,
+            queue,
+            blases: HashMap::new(),
+        }
+    }
+
+    /// Records a build of the TLAS of every loaded entity in `scene`, at full detail, first
+    /// building the BLAS of any mesh that hasn't been traced before. Returns `None` if nothing
+    /// has loaded yet.
+    pub fn build_tlas<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+    ) -> Option<Tlas> {
+        let mut instances = Vec::new();
+        let mut instance_data = Vec::new();
+        let mut query = scene.world.query::<(
+            &Transform,
+            &MeshHandle,
+            &Material,
+            Option<&MaterialOverride>,
+        )>();
+        for (transform, mesh, material, material_override) in query.iter() {
+            let Some(mesh) = mesh.0.get() else {
+                continue;
+            };
+            let Some(addresses) = mesh.addresses() else {
+                continue;
+            };
+            if mesh.index_buffer.len() < 3 {
+                continue;
+            }
+            let blas = self.blas(&mesh);
+            let material = material_override.map_or(material, |o| &o.0);
+
+            // The transform is a 3x4 matrix in rows.
+            let rows = transform.0.transpose();
+            instances.push(AccelerationStructureInstance {
+                transform: [
+                    rows.x_axis.to_array(),
+                    rows.y_axis.to_array(),
+                    rows.z_axis.to_array(),
+                ],
+                instance_custom_index_and_mask: Packed24_8::new(instance_data.len() as u32, 0xff),
+                acceleration_structure_reference: blas.device_address().get(),
+                ..Default::default()
+            });
+            instance_data.push(InstanceData {
+                mesh: addresses,
+                base_color: material.base_color.to_array(),
+            });
+        }
+        if instances.is_empty() {
+            return None;
+        }
+
+        let instance_count = instances.len() as u32;
+        // The instances must start at an address that is a multiple of 16, which the layout of
+        // their type alone doesn't ask for.
+        let instance_buffer = Buffer::new(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY
+                    | BufferUsage::SHADER_DEVICE_ADDRESS,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            DeviceLayout::new_unsized::<[AccelerationStructureInstance]>(instance_count.into())
+                .unwrap()
+                .align_to(DeviceAlignment::new(16).unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+        let instance_buffer =
+            Subbuffer::new(instance_buffer).reinterpret::<[AccelerationStructureInstance]>();
+        instance_buffer.write().unwrap().copy_from_slice(&instances);
+        let instance_data = Buffer::from_iter(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            instance_data,
+        )
+        .unwrap();
+
+        let geometries = AccelerationStructureGeometries::Instances(
+            AccelerationStructureGeometryInstancesData::new(instance_buffer.into()),
+        );
+        // SAFETY: every instance refers to a BLAS that has been built and waited on, and that is
+        // kept alive for as long as `self`.
+        let acceleration_structure = unsafe {
+            self.record_build(
+                builder,
+                geometries,
+                instance_count,
+                AccelerationStructureType::TopLevel,
+            )
+        };
+
+        Some(Tlas {
+            acceleration_structure,
+            instances: instance_data,
+        })
+    }
+
+    /// The BLAS of `mesh`, built and waited on the first time.
+    fn blas(&mut self, mesh: &Arc<Mesh>) -> Arc<AccelerationStructure> {
+        if let Some((_, blas)) = self.blases.get(&Arc::as_ptr(mesh)) {
+            return blas.clone();
+        }
+
+        // Positions come first in each vertex.
+        let triangles = AccelerationStructureGeometryTrianglesData {
+            flags: GeometryFlags::OPAQUE,
+            vertex_data: Some(mesh.vertex_buffer.clone().into_bytes()),
+            vertex_stride: size_of::<MeshVertex>() as u32,
+            max_vertex: (mesh.vertex_buffer.len() as u32).saturating_sub(1),
+            index_data: Some(IndexBuffer::U32(mesh.index_buffer.clone())),
+            ..AccelerationStructureGeometryTrianglesData::new(Format::R32G32B32_SFLOAT)
+        };
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+        // SAFETY: the index buffer only refers to vertices of the vertex buffer, which
+        // `max_vertex` covers.
+        let blas = unsafe {
+            self.record_build(
+                &mut builder,
+                AccelerationStructureGeometries::Triangles(vec![triangles]),
+                mesh.index_buffer.len() as u32 / 3,
+                AccelerationStructureType::BottomLevel,
+            )
+        };
+        sync::now(self.queue.device().clone())
+            .then_execute(self.queue.clone(), builder.build().unwrap())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        self.blases
+            .insert(Arc::as_ptr(mesh), (mesh.clone(), blas.clone()));
+        blas
+    }
+
+    /// Records a build of a new acceleration structure of type `ty` from `geometries`, which hold
+    /// `primitive_count` triangles or instances.
+    ///
+    /// # Safety
+    ///
+    /// `geometries` must meet the requirements of `build_acceleration_structure`.
+    unsafe fn record_build<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        geometries: AccelerationStructureGeometries,
+        primitive_count: u32,
+        ty: AccelerationStructureType,
+    ) -> Arc<AccelerationStructure> {
+        let device = self.memory_allocator.device();
+        let mut build_info = AccelerationStructureBuildGeometryInfo {
+            flags: BuildAccelerationStructureFlags::PREFER_FAST_TRACE,
+            ..AccelerationStructureBuildGeometryInfo::new(geometries)
+        };
+        let sizes = device
+            .acceleration_structure_build_sizes(
+                AccelerationStructureBuildType::Device,
+                &build_info,
+                &[primitive_count],
+            )
+            .unwrap();
+
+        let buffer = Buffer::new_slice::<u8>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::ACCELERATION_STRUCTURE_STORAGE
+                    | BufferUsage::SHADER_DEVICE_ADDRESS,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            sizes.acceleration_structure_size,
+        )
+        .unwrap();
+        // SAFETY: the buffer was just created, and is only used through the acceleration
+        // structure.
+        let acceleration_structure = unsafe {
+            AccelerationStructure::new(
+                device.clone(),
+                AccelerationStructureCreateInfo {
+                    ty,
+                    ..AccelerationStructureCreateInfo::new(buffer)
+                },
+            )
+        }
+        .unwrap();
+        let name = if ty == AccelerationStructureType::TopLevel {
+            "TLAS"
+        } else {
+            "BLAS"
+        };
+        debug_utils::name(&acceleration_structure, name);
+
+        let scratch_alignment = device
+            .physical_device()
+            .properties()
+            .min_acceleration_structure_scratch_offset_alignment
+            .unwrap();
+        let scratch = Buffer::new(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::SHADER_DEVICE_ADDRESS,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            DeviceLayout::from_size_alignment(
+                sizes.build_scratch_size,
+                u64::from(scratch_alignment),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        build_info.dst_acceleration_structure = Some(acceleration_structure.clone());
+        build_info.scratch_data = Some(Subbuffer::new(scratch));
+        let range_info = AccelerationStructureBuildRangeInfo {
+            primitive_count,
+            ..Default::default()
+        };
+        // SAFETY: the caller upholds the requirements on the geometries.
+        unsafe {
+            builder.build_acceleration_structure(build_info, [range_info].into_iter().collect())
+        }
+        .unwrap();
+
+        acceleration_structure
+    }
+}
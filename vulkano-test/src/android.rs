@@ -0,0 +1,48 @@
+// The entry point on Android, where the app is a library loaded by a NativeActivity instead of a
+// program with a `main`. It shows the demo scene, and the window comes and goes with the activity,
+// which `App` handles in `resumed` and `suspended`.
+//
+// Relative paths, such as that of the settings file, are resolved against the app's internal
+// storage. The log goes to stdout, which Android discards unless the app is run with its output
+// redirected to logcat.
+
+use tracing::{error, warn};
+use winit::{
+    event_loop::EventLoop,
+    platform::android::{activity::AndroidApp, EventLoopBuilderExtAndroid},
+};
+
+use crate::{
+    app::{App, AppEvent, SceneSource},
+    device_requirements::DeviceRequirements,
+    error::AppError,
+    logging,
+};
+
+#[unsafe(no_mangle)]
+fn android_main(android_app: AndroidApp) {
+    logging::init(false);
+
+    if let Some(data_path) = android_app.internal_data_path()
+        && let Err(err) = std::env::set_current_dir(&data_path)
+    {
+        warn!("Failed to change to {}: {err}", data_path.display());
+    }
+
+    if let Err(err) = run(android_app) {
+        error!("{err}");
+    }
+}
+
+fn run(android_app: AndroidApp) -> Result<(), AppError> {
+    let event_loop = EventLoop::<AppEvent>::with_user_event()
+        .with_android_app(android_app)
+        .build()?;
+    let mut app = App::new(&event_loop, SceneSource::Demo, DeviceRequirements::new())?;
+
+    event_loop.run_app(&mut app)?;
+    match app.take_error() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,3383 @@
+// Copyright (c) 2017 The vulkano developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or https://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+// The windowed application. The Vulkan device is created up front, while the window, swapchain
+// and everything that depends on the swapchain format live in a `RenderContext` created on
+// `resumed` and dropped on `suspended`, which is when Android takes the window away. A frame is
+// rendered whenever the window receives `RedrawRequested`, which the app asks for after every
+// frame or only when something changed, depending on the `RedrawPolicy`. The scene is simulated
+// separately at a fixed rate on the clock of `Time`, in `about_to_wait`, and frames show it
+// interpolated between the last two steps.
+//
+// A frame first records the passes that can't be inside a render pass: the particles' update,
+// the ray or path traced main view where the command line asked for one, the TLAS, the light
+// clusters, picking, the selection mask and GPU culling. The rasterized main view follows, with
+// the terrain, skybox, grid and particles, either straight into the swapchain image or offscreen
+// where water, fog, blur or `render_scale` need to work on it before it is blitted there. The
+// material preview and the `stereo` view take its place while they are on. The overlay of the
+// selection outline, the debug draw and the console's line goes on top, after which screenshots,
+// GIF frames and video are copied from the swapchain image.
+//
+// Input from the window is handled as `InputEvent`s, which `input_recording` can record and
+// replay. Keys go to the console while it is open, and otherwise to the actions of `keybindings`
+// and the camera of `CameraController`; the mouse moves the camera, picks entities and drags the
+// gizmo of the selected one, and edits are kept in a `History`. Files dropped onto the window are
+// loaded by their kind: glTF into the scene, PNG as the selected entity's texture and HDR or
+// OpenEXR as the environment.
+//
+// In exclusive fullscreen, the window leaves fullscreen whenever it loses focus, which restores the
+// monitor's video mode for the other windows, and returns to it when focused again.
+//
+// While the window is being resized, the swapchain is only recreated once the size has stopped
+// changing for `RESIZE_DEBOUNCE`, rather than on every step of the drag. Until then, frames keep
+// being drawn at the old size and presented to the old swapchain, which is fitted to the window as
+// the `present_scaling` setting asks where `VK_EXT_swapchain_maintenance1` lets it be chosen,
+// unless the surface insists on the new size by reporting it out of date. The same extension lets
+// the swapchain switch present modes when vsync is turned off or on, without recreating it. The old
+// swapchain is handed to the new one as it is recreated, so that images being presented from it
+// stay on screen until the new ones replace them.
+//
+// Sizes from winit are in physical pixels, which is what the swapchain and viewports use. The
+// window's scale factor, which can be fractional on Wayland, converts them to logical pixels for
+// anything that should keep the same apparent size on high-DPI displays.
+
+use glam::{DVec2, Mat4, Vec2, Vec4};
+use hecs::Entity;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug_span, error, info, info_span, warn};
+use vulkano::{
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BlitImageInfo,
+        CommandBufferUsage, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents, SubpassEndInfo,
+    },
+    descriptor_set::allocator::StandardDescriptorSetAllocator,
+    device::{Device, Queue},
+    format::{ClearColorValue, Format, FormatFeatures},
+    image::{sampler::Filter, view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    instance::Instance,
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::graphics::viewport::Viewport,
+    render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass, Subpass},
+    swapchain::{
+        acquire_next_image, CompositeAlpha, CompositeAlphas, PresentGravity, PresentMode,
+        PresentScaling, Surface, SurfaceCapabilities, SurfaceInfo, Swapchain, SwapchainCreateInfo,
+        SwapchainPresentInfo,
+    },
+    sync::{self, GpuFuture},
+    Validated, VulkanError,
+};
+use winit::{
+    application::ApplicationHandler,
+    event::{DeviceEvent, DeviceId, ElementState, MouseButton, TouchPhase, WindowEvent},
+    event_loop::{ActiveEventLoop, EventLoop},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+    monitor::VideoModeHandle,
+    window::{Fullscreen, Icon, Window, WindowId},
+};
+
+#[cfg(feature = "scripting")]
+use crate::scripting::{self, Scripts};
+#[cfg(feature = "video")]
+use crate::video::VideoRecorder;
+use crate::{
+    acceleration::AccelerationStructures,
+    assets::Assets,
+    blur::BlurFilter,
+    camera::Camera,
+    camera_controller::CameraController,
+    camera_path::{CameraPath, CameraPathPlayback},
+    clipboard::Clipboard,
+    components::{MaterialOverride, MeshHandle, Transform},
+    console::{Command, Console, PathCommand},
+    crash_report,
+    cursor::CursorMode,
+    debug_draw::{DebugDraw, DebugDrawPipeline},
+    debug_utils,
+    denoise::Denoiser,
+    device_requirements::{Capabilities, DeviceRequirements},
+    environment::Environment,
+    error::AppError,
+    fog::{Fog, FogPass},
+    frame_debug::FrameDebugger,
+    frame_limiter::{self, FrameLimiter},
+    gamma,
+    gif::GifCapture,
+    gizmo::Gizmo,
+    gpu::Gpu,
+    gpu_culling::GpuCuller,
+    grid::GridPipeline,
+    hdr,
+    history::{Edit, History, MaterialState},
+    icon, info,
+    input_recording::{InputEvent, InputRecorder, InputReplay, Recorded},
+    keybindings::{Action, Keybindings},
+    light_clusters::LightCuller,
+    low_latency::{self, LowLatency},
+    material::Material,
+    material_editor::MaterialEditor,
+    memory_budget::{self, MemoryBudget},
+    metrics::FrameMetrics,
+    monitor::WindowPlacement,
+    occlusion::OcclusionCuller,
+    offscreen::OffscreenTarget,
+    outline::SelectionOutline,
+    particles::ParticleSystem,
+    path_tracing::PathTracer,
+    picking::{self, Picker},
+    ray_tracing::RayTracer,
+    raycast::{self, Ray},
+    scene::Scene,
+    scene_file,
+    scene_pipeline::{DrawStats, ScenePipeline},
+    screenshot::ScreenshotCapture,
+    settings::{self, PickingMethod, RedrawPolicy, RenderSettings},
+    skybox::SkyboxPipeline,
+    terrain::{Terrain, TerrainPipeline},
+    text::Fonts,
+    texture_streaming,
+    time::{self, Time},
+    timestep::FixedTimestep,
+    watch::FileWatcher,
+    water::WaterPass,
+};
+
+/// Simulation steps per second.
+const TICK_RATE: u32 = 60;
+
+/// How long the window's size has to stay the same before the swapchain is recreated for it.
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// How far the cursor can move between pressing and releasing the left button, in physical
+/// pixels, for it to count as a click rather than a drag.
+const CLICK_DISTANCE: f64 = 4.0;
+
+/// How high an em of the console's line is in the overlay, in logical pixels.
+const CONSOLE_TEXT_SIZE: f32 = 20.0;
+
+/// What the app shows at startup, or loads from the console.
+pub enum SceneSource {
+    Demo,
+    Gltf(PathBuf),
+    /// A scene saved by `scene_file::save`.
+    Ron(PathBuf),
+}
+
+/// Events sent to the event loop from other threads.
+#[derive(Debug)]
+pub enum AppEvent {
+    /// A file registered with the app's `FileWatcher` was created or modified.
+    FileChanged(PathBuf),
+    /// The asset loaders finished decoding something, which `Assets::finish_loads` uploads.
+    AssetsLoaded,
+}
+
+pub struct App {
+    instance: Arc<Instance>,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    assets: Assets,
+    scene: Scene,
+    /// What is shown behind the scene, if an environment map was loaded.
+    environment: Option<Environment>,
+    /// Where the scene is saved to: the file it was loaded from if it is a scene file, or
+    /// `scene.ron` otherwise.
+    scene_file_path: PathBuf,
+    /// The entity that material overrides and edits apply to.
+    selected_object: Option<Entity>,
+    material_editor: MaterialEditor,
+    /// Whether the selected object is shown once per material variant instead of the scene.
+    material_preview: bool,
+    /// Whether the scene is drawn as wireframe, where the device supports it.
+    wireframe: bool,
+    /// Whether the main view is shadowed, where ray-query shadows are supported.
+    shadows: bool,
+    /// Whether the main view is path traced rather than ray traced, where ray tracing is
+    /// supported.
+    path_tracing: bool,
+    /// Whether the path traced view is denoised.
+    denoise: bool,
+    camera: CameraController,
+    /// The keyframes recorded with `path add`, and the path being played back, if any, which
+    /// steers the camera instead while it lasts.
+    camera_path: CameraPath,
+    camera_playback: Option<CameraPathPlayback>,
+    /// The camera of the last frame drawn.
+    drawn_camera: Option<Camera>,
+    /// The finger that orbits the camera, and where it was last.
+    touch: Option<(u64, DVec2)>,
+    /// Where the mouse cursor was last seen in the window.
+    cursor_position: Option<DVec2>,
+    /// Where the mouse cursor was when the left button was last pressed.
+    press_position: Option<DVec2>,
+    /// What the mouse is doing to the camera, if anything.
+    cursor_mode: CursorMode,
+    settings: RenderSettings,
+    settings_path: PathBuf,
+    /// Nothing is reloaded when it changes without one, as when the system can't watch any more
+    /// files.
+    watcher: Option<FileWatcher>,
+    console: Console,
+    clipboard: Clipboard,
+    /// What the console's line is set in.
+    fonts: Fonts,
+    debug_draw: DebugDraw,
+    frame_debugger: FrameDebugger,
+    /// The last few seconds of frames, saved as a GIF with F10.
+    gif_capture: GifCapture,
+    screenshot_capture: ScreenshotCapture,
+    /// Selects what is clicked on.
+    picker: Picker,
+    /// Moves, turns and resizes the selected entity.
+    gizmo: Gizmo,
+    /// The edits of the scene, to undo and redo.
+    history: History,
+    /// The modifier keys held, for shortcuts such as Ctrl+Z.
+    modifiers: ModifiersState,
+    timestep: FixedTimestep,
+    time: Time,
+    /// How many frames have been drawn, which recorded input is stamped with.
+    frames_drawn: u64,
+    input_recorder: Option<InputRecorder>,
+    input_replay: Option<InputReplay>,
+    /// Whether the time-scale slider of the debug overlay is being dragged.
+    dragging_time_slider: bool,
+    frame_limiter: Option<FrameLimiter>,
+    /// Waits for each frame to be presented before the next, if `--low-latency` asked for it.
+    low_latency: Option<LowLatency>,
+    /// The budget of the device's memory, queried every second, where the device can tell it.
+    memory_budget: Option<MemoryBudget>,
+    /// The swapchain image count asked for on the command line, in place of the setting.
+    swapchain_images: Option<u32>,
+    /// Where per-frame metrics are written, if anywhere.
+    metrics: Option<FrameMetrics>,
+    /// The recording of the window in progress, started and stopped with F9.
+    #[cfg(feature = "video")]
+    video_recorder: Option<VideoRecorder>,
+    /// The scripts in `assets/scripts`, which are ticked every frame.
+    #[cfg(feature = "scripting")]
+    scripts: Scripts,
+    window_placement: WindowPlacement,
+    /// The error that made the app quit.
+    error: Option<AppError>,
+    rcx: Option<RenderContext>,
+}
+
+struct RenderContext {
+    window: Arc<Window>,
+    /// The video mode of exclusive fullscreen, if the window uses it.
+    fullscreen_mode: Option<VideoModeHandle>,
+    swapchain: Arc<Swapchain>,
+    render_pass: Arc<RenderPass>,
+    /// A render pass compatible with `render_pass` that keeps the contents of the swapchain
+    /// image, for drawing the overlay as a separate pass when debugging frames.
+    overlay_render_pass: Arc<RenderPass>,
+    framebuffers: Vec<Arc<Framebuffer>>,
+    scene_pipeline: ScenePipeline,
+    terrain_pipeline: TerrainPipeline,
+    debug_draw_pipeline: DebugDrawPipeline,
+    grid_pipeline: GridPipeline,
+    skybox_pipeline: SkyboxPipeline,
+    selection_outline: SelectionOutline,
+    occlusion_culler: OcclusionCuller,
+    /// Culls the scene on the GPU, where the scene pipeline can draw what it culled.
+    gpu_culler: Option<GpuCuller>,
+    light_culler: LightCuller,
+    particle_system: ParticleSystem,
+    /// Blurs the scene when the settings ask for it, or `None` if the swapchain images can't be
+    /// blitted to.
+    blur_filter: Option<BlurFilter>,
+    /// Fogs the scene when the settings ask for it, or `None` if the swapchain images can't be
+    /// blitted to.
+    fog_pass: Option<FogPass>,
+    /// Ray traces the main view where the device supports ray tracing and the swapchain images
+    /// can be blitted to.
+    ray_tracer: Option<RayTracer>,
+    /// Path traces the main view in place of `ray_tracer`, when asked to.
+    path_tracer: Option<PathTracer>,
+    /// Denoises what `path_tracer` traced, if there is a path tracer.
+    denoiser: Option<Denoiser>,
+    /// The acceleration structures that shadows are traced through, where the scene pipeline
+    /// supports ray-query shadows.
+    shadow_acceleration_structures: Option<AccelerationStructures>,
+    /// A render pass compatible with `render_pass`, for drawing the scene into `scene_target`.
+    scene_target_render_pass: Arc<RenderPass>,
+    /// What the scene is drawn into to be blurred or to have its water drawn over it, created
+    /// when first needed and recreated with the swapchain.
+    scene_target: Option<OffscreenTarget>,
+    /// A render pass for drawing into `scene_target` again, keeping the scene drawn into it.
+    water_render_pass: Arc<RenderPass>,
+    water_pass: WaterPass,
+    viewport: Viewport,
+    /// The views of the main view's eyes in the last frame, which clicks cast rays through.
+    views: Vec<(Mat4, Viewport)>,
+    /// How the surface can be composited with what is behind the window.
+    supported_composite_alpha: CompositeAlphas,
+    /// The image count that the swapchain was asked for, before it was clamped.
+    requested_images: u32,
+    /// The `present_scaling` setting that the swapchain was created for.
+    present_scaling: settings::PresentScaling,
+    /// The present mode to switch to with the next present, which the swapchain was created to
+    /// allow.
+    next_present_mode: Option<PresentMode>,
+    /// Physical pixels per logical pixel of the window.
+    scale_factor: f64,
+    /// What the last frame drew, shown in the window title.
+    draw_stats: DrawStats,
+    /// How many asset loads were pending when the title was last set.
+    pending_loads: usize,
+    /// Whether the window takes input from input methods, which it does while the console is
+    /// open.
+    ime_allowed: bool,
+    recreate_swapchain: bool,
+    /// When the window was last resized, while the swapchain waits for it to settle.
+    resized_at: Option<Instant>,
+    previous_frame_end: Option<Box<dyn GpuFuture>>,
+}
+
+impl App {
+    /// Sets up the Vulkan device and loads the scene to show. The device meets `requirements` in
+    /// addition to what the app itself needs.
+    pub fn new(
+        event_loop: &EventLoop<AppEvent>,
+        scene_source: SceneSource,
+        mut requirements: DeviceRequirements,
+    ) -> Result<Self, AppError> {
+        let _span = info_span!("init").entered();
+        ScenePipeline::register_requirements(&mut requirements);
+        DebugDrawPipeline::register_requirements(&mut requirements);
+        TerrainPipeline::register_requirements(&mut requirements);
+        frame_limiter::register_requirements(&mut requirements);
+        low_latency::register_requirements(&mut requirements);
+        memory_budget::register_requirements(&mut requirements);
+        texture_streaming::register_requirements(&mut requirements);
+        let Gpu {
+            instance,
+            device,
+            queue,
+            memory_allocator,
+            command_buffer_allocator,
+            descriptor_set_allocator,
+        } = Gpu::windowed(event_loop, requirements)?;
+
+        let proxy = event_loop.create_proxy();
+        let mut assets = Assets::new(
+            memory_allocator.clone(),
+            command_buffer_allocator.clone(),
+            queue.clone(),
+            move || {
+                // This only fails once the event loop has exited.
+                let _ = proxy.send_event(AppEvent::AssetsLoaded);
+            },
+        );
+        let (scene, scene_file_path) = load_scene(&mut assets, scene_source)?;
+
+        // Edits to the settings file and to the files of loaded assets arrive as
+        // `AppEvent::FileChanged` on the event loop.
+        let proxy = event_loop.create_proxy();
+        let mut watcher = FileWatcher::new(move |path| {
+            // This only fails once the event loop has exited.
+            let _ = proxy.send_event(AppEvent::FileChanged(path));
+        })
+        .inspect_err(|err| warn!("Failed to watch files, they won't be reloaded: {err}"))
+        .ok();
+        let settings_path = std::path::absolute(RenderSettings::PATH)
+            .unwrap_or_else(|_| RenderSettings::PATH.into());
+        if let Some(watcher) = &mut watcher {
+            if let Err(err) = watcher.watch(&settings_path) {
+                warn!("Failed to watch {}: {err}", settings_path.display());
+            }
+            for path in assets.files() {
+                if let Err(err) = watcher.watch(&path) {
+                    warn!("Failed to watch {}: {err}", path.display());
+                }
+            }
+        }
+        #[cfg(feature = "scripting")]
+        let mut scene = scene;
+        #[cfg(feature = "scripting")]
+        let scripts = Scripts::load(&mut assets, &mut scene);
+        #[cfg(feature = "scripting")]
+        if let Some(watcher) = &mut watcher
+            && scripts.directory().is_dir()
+            && let Err(err) = watcher.watch_directory(scripts.directory())
+        {
+            warn!("Failed to watch {}: {err}", scripting::DIRECTORY);
+        }
+        let settings = RenderSettings::load(&settings_path).unwrap_or_else(|err| {
+            warn!(
+                "Failed to load {}, using defaults: {err}",
+                settings_path.display()
+            );
+            RenderSettings::default()
+        });
+        let fonts = Fonts::load(&settings.fonts);
+
+        let frame_debugger = FrameDebugger::new(memory_allocator.clone(), "frame-debug".into());
+        let gif_capture = GifCapture::new(memory_allocator.clone());
+        let screenshot_capture = ScreenshotCapture::new(memory_allocator.clone());
+        let picker = Picker::new(memory_allocator.clone())?;
+        let frame_limiter = Some(settings.max_fps)
+            .filter(|&max_fps| max_fps > 0)
+            .map(FrameLimiter::new);
+        let memory_budget = MemoryBudget::new(&device);
+        warn_keybinding_conflicts(&settings.keybindings);
+
+        Ok(App {
+            instance,
+            device,
+            queue,
+            memory_allocator,
+            command_buffer_allocator,
+            descriptor_set_allocator,
+            assets,
+            scene,
+            environment: None,
+            scene_file_path,
+            selected_object: None,
+            material_editor: MaterialEditor::default(),
+            material_preview: false,
+            wireframe: false,
+            shadows: true,
+            path_tracing: false,
+            denoise: true,
+            camera: CameraController::default(),
+            camera_path: CameraPath::default(),
+            camera_playback: None,
+            drawn_camera: None,
+            touch: None,
+            cursor_position: None,
+            press_position: None,
+            cursor_mode: CursorMode::Arrow,
+            settings,
+            settings_path,
+            watcher,
+            console: Console::default(),
+            clipboard: Clipboard::default(),
+            fonts,
+            debug_draw: DebugDraw::new(),
+            frame_debugger,
+            gif_capture,
+            screenshot_capture,
+            picker,
+            gizmo: Gizmo::default(),
+            history: History::default(),
+            modifiers: ModifiersState::empty(),
+            timestep: FixedTimestep::new(TICK_RATE),
+            time: Time::default(),
+            frames_drawn: 0,
+            input_recorder: None,
+            input_replay: None,
+            dragging_time_slider: false,
+            frame_limiter,
+            low_latency: None,
+            memory_budget,
+            swapchain_images: None,
+            metrics: None,
+            #[cfg(feature = "video")]
+            video_recorder: None,
+            #[cfg(feature = "scripting")]
+            scripts,
+            window_placement: WindowPlacement::default(),
+            error: None,
+            rcx: None,
+        })
+    }
+
+    /// Sets where the window is created. This only takes effect before the event loop starts.
+    pub fn set_window_placement(&mut self, window_placement: WindowPlacement) {
+        self.window_placement = window_placement;
+    }
+
+    /// Switches the main view to the path tracer where ray tracing is supported. This only takes
+    /// effect before the event loop starts.
+    pub fn set_path_tracing(&mut self, path_tracing: bool) {
+        self.path_tracing = path_tracing;
+    }
+
+    /// Adds `count` point lights orbiting above the scene, as `Scene::add_orbiting_lights` does.
+    pub fn add_orbiting_lights(&mut self, count: u32) {
+        self.scene.add_orbiting_lights(count);
+    }
+
+    /// Caps the frame rate at `max_fps`, or lifts the cap for `None`.
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        self.frame_limiter = max_fps.map(|max_fps| {
+            let mut frame_limiter = FrameLimiter::new(max_fps);
+            if let Some(rcx) = &self.rcx {
+                frame_limiter.set_refresh_period(frame_limiter::refresh_period(
+                    &self.device,
+                    &rcx.swapchain,
+                ));
+            }
+            frame_limiter
+        });
+    }
+
+    /// Waits for each frame to be presented before starting the next, where the device can.
+    pub fn enable_low_latency(&mut self) {
+        self.low_latency = LowLatency::new(&self.device);
+        if self.low_latency.is_none() {
+            warn!("Low latency needs VK_KHR_present_wait, which the device doesn't support");
+        }
+    }
+
+    /// Streams the detail of the textures in and out as the camera moves, rather than keeping
+    /// them whole. Only textures that load from now on are streamed.
+    pub fn enable_texture_streaming(&mut self) {
+        self.assets.enable_texture_streaming();
+    }
+
+    /// Shows the equirectangular HDR image at `path` behind the scene, converting it into a
+    /// cubemap along with its irradiance, and waits for the conversion to finish.
+    pub fn set_environment(&mut self, path: &Path) -> Result<(), AppError> {
+        self.environment = Some(Environment::load(
+            self.memory_allocator.clone(),
+            self.descriptor_set_allocator.clone(),
+            self.command_buffer_allocator.clone(),
+            &self.queue,
+            path,
+            true,
+        )?);
+        Ok(())
+    }
+
+    /// Asks for at least `count` swapchain images in place of the `swapchain_images` setting, or
+    /// goes by the setting for `None`.
+    pub fn set_swapchain_images(&mut self, count: Option<u32>) {
+        self.swapchain_images = count;
+        if let Some(rcx) = &mut self.rcx {
+            rcx.recreate_swapchain = true;
+        }
+    }
+
+    /// The swapchain image count being asked for, with zero for the fewest the surface supports.
+    fn requested_images(&self) -> u32 {
+        self.swapchain_images
+            .unwrap_or(self.settings.swapchain_images)
+    }
+
+    /// Records the input of the window to `path`, for `set_input_replay` to replay.
+    pub fn set_input_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.input_recorder = Some(InputRecorder::create(path)?);
+        Ok(())
+    }
+
+    /// Replays the input recorded to `path`, in place of the window's, until it runs out.
+    pub fn set_input_replay(&mut self, path: &Path) -> io::Result<()> {
+        self.input_replay = Some(InputReplay::load(path)?);
+        Ok(())
+    }
+
+    /// Writes the metrics of every frame to the CSV file at `path`, which is flushed on exit.
+    pub fn set_metrics_output(&mut self, path: &Path) -> io::Result<()> {
+        self.metrics = Some(FrameMetrics::create(path, &self.queue)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "video")]
+    fn toggle_recording(&mut self) {
+        if self.video_recorder.is_some() {
+            self.stop_recording();
+            return;
+        }
+        let Some(rcx) = &self.rcx else {
+            return;
+        };
+
+        let path = timestamped_path("recording", "mp4");
+        let image = rcx.framebuffers[0].attachments()[0].image();
+        match VideoRecorder::start(self.memory_allocator.clone(), image, &path) {
+            Ok(recorder) => self.video_recorder = Some(recorder),
+            Err(err) => error!("Failed to start recording: {err}"),
+        }
+    }
+
+    #[cfg(not(feature = "video"))]
+    fn toggle_recording(&mut self) {
+        warn!("Recording needs vulkano-test to be built with the `video` feature");
+    }
+
+    #[cfg(feature = "video")]
+    fn is_recording(&self) -> bool {
+        self.video_recorder.is_some()
+    }
+
+    #[cfg(not(feature = "video"))]
+    fn is_recording(&self) -> bool {
+        false
+    }
+
+    /// Waits for the frames in flight, whose copies the recording still needs, and finishes it.
+    #[cfg(feature = "video")]
+    fn stop_recording(&mut self) {
+        let Some(recorder) = self.video_recorder.take() else {
+            return;
+        };
+        if let Some(rcx) = &mut self.rcx {
+            // Dropping the future of the last frame waits for it.
+            rcx.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+        }
+        recorder.finish();
+    }
+
+    /// Selects `entity` for material overrides and edits, or clears the selection.
+    fn select(&mut self, entity: Option<Entity>) {
+        self.end_gizmo_drag();
+        self.selected_object = entity;
+        match entity {
+            Some(entity) => {
+                let mut query = self
+                    .scene
+                    .world
+                    .query_one::<(&Material, Option<&MaterialOverride>)>(entity);
+                let name = match query.get() {
+                    Ok((_, Some(material_override))) => &material_override.0.name,
+                    Ok((material, None)) => &material.name,
+                    Err(_) => "no material",
+                };
+                info!("Selected entity {} ({name})", entity.id());
+            }
+            None => info!("Cleared selection"),
+        }
+    }
+
+    /// Selects the entity under `position`, in physical pixels, as the `picking` setting asks: in a
+    /// frame or two with an ID buffer, or straight away with a ray.
+    fn pick(&mut self, position: DVec2) {
+        let Some(rcx) = &self.rcx else {
+            return;
+        };
+        match self.settings.picking {
+            PickingMethod::IdBuffer => {
+                self.picker.request([position.x as u32, position.y as u32]);
+                rcx.window.request_redraw();
+            }
+            PickingMethod::Raycast => {
+                let position = position.as_vec2();
+                let Some((view_proj, viewport)) = picking::view_at(&rcx.views, position) else {
+                    return;
+                };
+                let ray = Ray::through_pixel(*view_proj, viewport, position);
+                let hit = raycast::raycast(&self.scene, &ray);
+                if let Some(hit) = &hit {
+                    info!("Hit at {} at a distance of {}", hit.position, hit.distance);
+                }
+                self.select(hit.map(|hit| hit.entity));
+            }
+        }
+    }
+
+    /// Starts dragging a handle of the gizmo of the selected entity, if the cursor is on one of
+    /// the handles drawn for it. Returns whether it did.
+    fn begin_gizmo_drag(&mut self) -> bool {
+        let (Some(rcx), Some(entity), Some(position)) =
+            (&self.rcx, self.selected_object, self.cursor_position)
+        else {
+            return false;
+        };
+        // The gizmo is drawn through the debug-draw layer, so there is nothing to hold without it.
+        if !self.settings.debug_draw {
+            return false;
+        }
+        let Ok(transform) = self.scene.world.get::<&Transform>(entity).map(|t| t.0) else {
+            return false;
+        };
+        self.gizmo
+            .begin_drag(transform, &rcx.views, position.as_vec2())
+    }
+
+    /// Moves the selected entity as the gizmo drag asks with the cursor at `position`.
+    fn drag_gizmo(&mut self, position: DVec2) {
+        let (Some(rcx), Some(entity)) = (&self.rcx, self.selected_object) else {
+            return;
+        };
+        if let Some(transform) = self.gizmo.drag(position.as_vec2()) {
+            self.scene.set_world_transform(entity, transform);
+            rcx.window.request_redraw();
+        }
+    }
+
+    /// Ends the gizmo drag, if there is one, recording the move that it made.
+    fn end_gizmo_drag(&mut self) {
+        let (Some(before), Some(entity)) = (self.gizmo.end_drag(), self.selected_object) else {
+            return;
+        };
+        // A node's move only reaches the entity's `Transform` once the transforms are updated.
+        self.scene.update_transforms();
+        let Ok(after) = self.scene.world.get::<&Transform>(entity).map(|t| t.0) else {
+            return;
+        };
+        if after != before {
+            self.history.record(Edit::Transform {
+                entity,
+                before,
+                after,
+            });
+        }
+    }
+
+    /// Records the change of the materials of `entity` since they were `before`, if they changed.
+    fn record_material_edit(&mut self, entity: Entity, before: MaterialState) {
+        let after = MaterialState::of(&self.scene, entity);
+        if after != before {
+            self.history.record(Edit::Material {
+                entity,
+                before: Box::new(before),
+                after: Box::new(after),
+            });
+        }
+    }
+
+    /// Takes back the last edit of the scene, or makes the last one taken back again if `redo`,
+    /// and selects the entity it was of, if it is still there.
+    fn undo(&mut self, redo: bool) {
+        self.end_gizmo_drag();
+        let edit = if redo {
+            self.history.redo(&mut self.scene)
+        } else {
+            self.history.undo(&mut self.scene)
+        };
+        let Some(edit) = edit else {
+            info!("Nothing to {}", if redo { "redo" } else { "undo" });
+            return;
+        };
+        info!(
+            "{} the {}",
+            if redo { "Redid" } else { "Undid" },
+            edit.describe()
+        );
+        let entity = edit.entity();
+        let entity = self.scene.world.contains(entity).then_some(entity);
+        self.select(entity);
+    }
+
+    /// Turns the camera for a drag of `delta` physical pixels, or pans it with Shift held.
+    fn orbit_by(&mut self, delta: DVec2) {
+        let Some(rcx) = &self.rcx else {
+            return;
+        };
+        let height = rcx.window.inner_size().height.max(1) as f64;
+        let delta = (delta / height).as_vec2();
+        if self.modifiers.shift_key() {
+            // The scene follows the cursor.
+            self.camera.pan(-delta.x, delta.y);
+        } else {
+            // Dragging across the whole height of the window turns the camera half way around,
+            // at the default speed.
+            let turn = -delta * std::f32::consts::PI * self.settings.orbit_speed;
+            self.camera.rotate(turn.x, turn.y);
+        }
+        rcx.window.request_redraw();
+    }
+
+    /// Logs how the clock runs now, and shows it in the window title.
+    fn show_time(&mut self) {
+        if self.time.is_paused() {
+            info!("Paused");
+        } else {
+            info!("Time scale: {}x", self.time.scale());
+        }
+        if let Some(rcx) = &mut self.rcx {
+            // The title is set again on the next frame.
+            rcx.draw_stats = DrawStats::default();
+        }
+    }
+
+    /// Sets the time scale from the slider of the debug overlay, with the cursor at `position`.
+    fn drag_time_slider(&mut self, position: DVec2) {
+        let Some(rcx) = &self.rcx else {
+            return;
+        };
+        let scale = time::slider_scale(&rcx.viewport, position.as_vec2());
+        if scale != self.time.scale() {
+            self.time.set_scale(scale);
+            self.show_time();
+        }
+    }
+
+    /// Glides the camera to orbiting the selected entity, or the whole scene if none is.
+    fn frame_selected(&mut self) {
+        let selected = self.selected_object.and_then(|entity| {
+            let mut query = self
+                .scene
+                .world
+                .query_one::<(&MeshHandle, &Transform)>(entity);
+            let (mesh, transform) = query.get().ok()?;
+            mesh.aabb(transform)
+        });
+        self.camera
+            .frame(&selected.unwrap_or_else(|| self.scene.bounds()));
+    }
+
+    fn set_cursor_mode(&mut self, cursor_mode: CursorMode) {
+        if cursor_mode == self.cursor_mode {
+            return;
+        }
+        self.cursor_mode = cursor_mode;
+        if let Some(rcx) = &self.rcx {
+            cursor_mode.apply(&rcx.window);
+        }
+    }
+
+    fn handle_mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        match (button, state, self.cursor_mode) {
+            // The time-scale slider of the debug overlay is dragged rather than orbiting.
+            (MouseButton::Left, ElementState::Pressed, CursorMode::Arrow)
+                if self.settings.debug_draw
+                    && let (Some(rcx), Some(position)) = (&self.rcx, self.cursor_position)
+                    && time::is_on_slider(&rcx.viewport, position.as_vec2()) =>
+            {
+                self.dragging_time_slider = true;
+                self.drag_time_slider(position);
+            }
+            (MouseButton::Left, ElementState::Released, _) if self.dragging_time_slider => {
+                self.dragging_time_slider = false;
+            }
+            // A press on a handle of the gizmo drags it instead of orbiting.
+            (MouseButton::Left, ElementState::Pressed, CursorMode::Arrow)
+                if self.begin_gizmo_drag() => {}
+            (MouseButton::Left, ElementState::Released, _) if self.gizmo.is_dragging() => {
+                self.end_gizmo_drag();
+            }
+            (MouseButton::Left, ElementState::Pressed, CursorMode::Arrow) => {
+                self.press_position = self.cursor_position;
+                self.set_cursor_mode(CursorMode::Grab);
+            }
+            // A press and release that barely moved the cursor is a click, which picks what is
+            // under it rather than orbiting.
+            (MouseButton::Left, ElementState::Released, CursorMode::Grab)
+                if self.press_position.zip(self.cursor_position).is_some_and(
+                    |(pressed, released)| pressed.distance(released) <= CLICK_DISTANCE,
+                ) =>
+            {
+                self.set_cursor_mode(CursorMode::Arrow);
+                self.pick(self.cursor_position.unwrap());
+            }
+            (MouseButton::Right, ElementState::Pressed, _) => {
+                self.set_cursor_mode(CursorMode::Hidden);
+            }
+            (MouseButton::Left, ElementState::Released, CursorMode::Grab)
+            | (MouseButton::Right, ElementState::Released, CursorMode::Hidden) => {
+                self.set_cursor_mode(CursorMode::Arrow);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_touch(&mut self, id: u64, phase: TouchPhase, position: DVec2) {
+        match (phase, self.touch) {
+            (TouchPhase::Started, None) => self.touch = Some((id, position)),
+            (TouchPhase::Moved, Some((touching, last))) if touching == id => {
+                self.orbit_by(position - last);
+                self.touch = Some((id, position));
+            }
+            (TouchPhase::Ended | TouchPhase::Cancelled, Some((touching, _))) if touching == id => {
+                self.touch = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles input from the window, unless a replay is feeding in its own, and records it.
+    fn receive_input(&mut self, input: InputEvent) {
+        if self.input_replay.is_some() {
+            return;
+        }
+        if let Some(recorder) = &mut self.input_recorder {
+            recorder.record(&Recorded::Input {
+                frame: self.frames_drawn,
+                event: input.clone(),
+            });
+        }
+        self.handle_input(input);
+    }
+
+    /// Feeds in what the replay recorded before the frame about to be drawn, ending the replay
+    /// once it runs out.
+    fn replay_input(&mut self) {
+        let Some(replay) = &mut self.input_replay else {
+            return;
+        };
+        let mut inputs = Vec::new();
+        while let Some(recorded) = replay.next(self.frames_drawn) {
+            inputs.push(recorded);
+        }
+        if replay.is_finished() {
+            info!(
+                "Finished replaying input after {} frames",
+                self.frames_drawn
+            );
+            self.input_replay = None;
+        }
+        for recorded in inputs {
+            match recorded {
+                Recorded::Input { event, .. } => self.handle_input(event),
+                Recorded::Clock { elapsed, .. } => {
+                    self.time.replay(elapsed);
+                    self.advance_simulation(elapsed);
+                }
+            }
+        }
+        if let Some(rcx) = &self.rcx {
+            // The next frame may have more to replay, whatever the redraw policy.
+            rcx.window.request_redraw();
+        }
+    }
+
+    fn handle_input(&mut self, input: InputEvent) {
+        match input {
+            InputEvent::Key {
+                key,
+                pressed: true,
+                repeat,
+                text,
+            } if self.console.is_open() => {
+                let action = match key {
+                    PhysicalKey::Code(code) => self
+                        .settings
+                        .keybindings
+                        .console_action(code, self.modifiers),
+                    PhysicalKey::Unidentified(_) => None,
+                };
+                if action == Some(Action::ToggleConsole) && !repeat {
+                    self.console.toggle();
+                } else if action == Some(Action::ConsoleCopy) {
+                    if let Err(err) = self.clipboard.copy(self.console.line()) {
+                        warn!("Failed to copy the console line: {err}");
+                    }
+                } else if action == Some(Action::ConsolePaste) {
+                    match self.clipboard.paste() {
+                        Ok(text) => self.console.paste(&text),
+                        Err(err) => warn!("Failed to paste into the console: {err}"),
+                    }
+                } else if let Some(line) = self.console.handle_key(key, text.as_deref()) {
+                    self.run_command(&line);
+                }
+                self.update_console_title();
+            }
+            InputEvent::Key {
+                key: PhysicalKey::Code(key),
+                pressed,
+                repeat: false,
+                ..
+            } if !self.console.is_open() => {
+                // The movement keys of the fly camera are held rather than pressed, and don't
+                // move it while Ctrl makes them shortcuts.
+                let movement = (!pressed || !self.modifiers.control_key())
+                    && self.camera.set_key(key, pressed);
+                if pressed && !movement {
+                    self.handle_key(key);
+                }
+            }
+            InputEvent::Key { .. } => return,
+            InputEvent::ImePreedit { text } if self.console.is_open() => {
+                self.console.set_preedit(&text);
+                self.update_console_title();
+            }
+            InputEvent::ImeCommit { text } if self.console.is_open() => {
+                self.console.commit(&text);
+                self.update_console_title();
+            }
+            InputEvent::ImePreedit { .. } | InputEvent::ImeCommit { .. } => return,
+            InputEvent::DroppedFile { path } => self.open_dropped(&path),
+            InputEvent::Modifiers(modifiers) => self.modifiers = modifiers,
+            InputEvent::MouseButton { button, pressed } => {
+                let state = if pressed {
+                    ElementState::Pressed
+                } else {
+                    ElementState::Released
+                };
+                self.handle_mouse_button(button, state);
+            }
+            InputEvent::CursorMoved { position } => {
+                let position = DVec2::from(position);
+                if self.dragging_time_slider {
+                    self.drag_time_slider(position);
+                } else if self.gizmo.is_dragging() {
+                    self.drag_gizmo(position);
+                } else if let Some(last) = self.cursor_position
+                    && self.cursor_mode == CursorMode::Grab
+                {
+                    self.orbit_by(position - last);
+                }
+                self.cursor_position = Some(position);
+            }
+            InputEvent::MouseWheel { lines } => self.camera.zoom(lines),
+            InputEvent::MouseMotion { delta } => {
+                if self.cursor_mode == CursorMode::Hidden {
+                    self.orbit_by(DVec2::from(delta));
+                }
+            }
+            InputEvent::Touch {
+                id,
+                phase,
+                position,
+            } => {
+                self.handle_touch(id, phase, DVec2::from(position));
+            }
+            InputEvent::Focused(focused) => {
+                if !focused {
+                    self.set_cursor_mode(CursorMode::Arrow);
+                    self.end_gizmo_drag();
+                    // Keys let go of elsewhere never come back up here.
+                    self.camera.release_keys();
+                }
+            }
+        }
+        if let Some(rcx) = &self.rcx {
+            rcx.window.request_redraw();
+        }
+    }
+
+    /// Runs the simulation steps that `elapsed` on the clock of `Time` adds up to.
+    fn advance_simulation(&mut self, elapsed: Duration) {
+        let steps = self.timestep.advance(elapsed);
+        for _ in 0..steps {
+            self.scene.tick(self.timestep.step());
+        }
+        #[cfg(feature = "scripting")]
+        self.scripts.tick(&mut self.scene, elapsed.as_secs_f32());
+    }
+
+    fn reload_settings(&mut self) {
+        let settings = match RenderSettings::load(&self.settings_path) {
+            Ok(settings) => settings,
+            Err(err) => {
+                // Keep the previous settings so a half-typed edit doesn't reset everything.
+                warn!("Failed to reload {}: {err}", self.settings_path.display());
+                return;
+            }
+        };
+        if settings == self.settings {
+            return;
+        }
+
+        info!("Reloaded {}", self.settings_path.display());
+        self.apply_settings(settings);
+    }
+
+    /// Switches to `settings`, updating whatever depends on those that changed.
+    fn apply_settings(&mut self, settings: RenderSettings) {
+        if settings.max_fps != self.settings.max_fps {
+            self.set_max_fps(Some(settings.max_fps).filter(|&max_fps| max_fps > 0));
+        }
+        if settings.swapchain_images != self.settings.swapchain_images {
+            // Changing the setting takes over from the command line.
+            self.swapchain_images = None;
+        }
+        if settings.fonts != self.settings.fonts {
+            self.fonts = Fonts::load(&settings.fonts);
+        }
+        if let Some(rcx) = &mut self.rcx {
+            if settings.vsync != self.settings.vsync {
+                let present_mode =
+                    present_mode(&self.device, settings.vsync, rcx.swapchain.surface());
+                if rcx.swapchain.present_modes().contains(&present_mode) {
+                    rcx.next_present_mode = Some(present_mode);
+                } else {
+                    rcx.recreate_swapchain = true;
+                }
+            }
+            if settings.swapchain_images != self.settings.swapchain_images
+                || settings.present_scaling != self.settings.present_scaling
+            {
+                rcx.recreate_swapchain = true;
+            }
+            if settings.transparent != self.settings.transparent {
+                rcx.window.set_transparent(settings.transparent);
+                rcx.recreate_swapchain = true;
+            }
+            if settings.ten_bit_output != self.settings.ten_bit_output {
+                // The render passes and pipelines are made for the swapchain's format, so it is
+                // only picked again along with them.
+                warn!("ten_bit_output takes effect the next time the app starts");
+            }
+            if settings.window_icon != self.settings.window_icon {
+                rcx.window.set_window_icon(Some(window_icon(&settings)));
+            }
+            if settings.blur_radius > 0
+                && self.settings.blur_radius == 0
+                && rcx.blur_filter.is_none()
+            {
+                warn!("The window's images can't be blitted to, which blurring needs");
+            }
+            if settings.fog_density > 0.0
+                && self.settings.fog_density <= 0.0
+                && rcx.fog_pass.is_none()
+            {
+                warn!("The window's images can't be blitted to, which fog needs");
+            }
+            if settings.render_scale != 1.0
+                && self.settings.render_scale == 1.0
+                && rcx.blur_filter.is_none()
+            {
+                warn!("The window's images can't be blitted to, which render_scale needs");
+            }
+            rcx.window.request_redraw();
+        }
+        if settings.keybindings != self.settings.keybindings {
+            warn_keybinding_conflicts(&settings.keybindings);
+        }
+        self.settings = settings;
+    }
+
+    /// Runs a line typed into the console.
+    fn run_command(&mut self, line: &str) {
+        let command = match Command::parse(line) {
+            Ok(Some(command)) => command,
+            Ok(None) => return,
+            Err(err) => {
+                warn!("{err}");
+                return;
+            }
+        };
+
+        match command {
+            Command::Help => {
+                for usage in Command::usages() {
+                    info!("{usage}");
+                }
+            }
+            Command::Info => self.copy_gpu_info(),
+            Command::Keys => {
+                for line in self.settings.keybindings.describe() {
+                    info!("{line}");
+                }
+            }
+            Command::Load(path) => {
+                let source = if path.extension().is_some_and(|extension| extension == "ron") {
+                    SceneSource::Ron(path)
+                } else {
+                    SceneSource::Gltf(path)
+                };
+                self.open_scene(source);
+            }
+            Command::CameraPath(command) => self.run_path_command(command),
+            Command::Screenshot { path, supersampled } => {
+                let path = path.unwrap_or_else(|| timestamped_path("screenshot", "png"));
+                if supersampled {
+                    self.screenshot_capture
+                        .request_supersampled(path, self.settings.supersampling);
+                } else {
+                    self.screenshot_capture.request(path);
+                }
+            }
+            Command::Set { name, value } => match self.settings.with(&name, &value) {
+                Ok(settings) => self.apply_settings(settings),
+                Err(err) => warn!("Failed to set {name}: {err}"),
+            },
+            Command::Vsync(vsync) => self.apply_settings(RenderSettings {
+                vsync,
+                ..self.settings.clone()
+            }),
+        }
+    }
+
+    fn run_path_command(&mut self, command: PathCommand) {
+        match command {
+            PathCommand::Add => {
+                let Some(camera) = self.drawn_camera else {
+                    return;
+                };
+                self.camera_path.push(camera);
+                info!(
+                    "Added keyframe {} to the camera path",
+                    self.camera_path.len()
+                );
+            }
+            PathCommand::Clear => {
+                self.camera_path.clear();
+                info!("Cleared the camera path");
+            }
+            PathCommand::Play { seconds, record } => {
+                if self.camera_path.is_empty() {
+                    warn!("The camera path has no keyframes, see `path add`");
+                    return;
+                }
+                // Only a recording started for the playback is stopped at its end.
+                let recording = record && !self.is_recording();
+                if recording {
+                    self.toggle_recording();
+                }
+                info!(
+                    "Playing {} keyframes over {seconds} s",
+                    self.camera_path.len()
+                );
+                self.camera_playback = Some(CameraPathPlayback::new(
+                    self.camera_path.clone(),
+                    Duration::from_secs_f32(seconds),
+                    recording && self.is_recording(),
+                ));
+            }
+            PathCommand::Save(path) => match self.camera_path.save(&path) {
+                Ok(()) => info!("Saved {}", path.display()),
+                Err(err) => error!("Failed to save {}: {err}", path.display()),
+            },
+            PathCommand::Load(path) => match CameraPath::load(&path) {
+                Ok(camera_path) => {
+                    info!(
+                        "Loaded {} keyframes from {}",
+                        camera_path.len(),
+                        path.display()
+                    );
+                    self.camera_path = camera_path;
+                }
+                Err(err) => error!("Failed to load {}: {err}", path.display()),
+            },
+        }
+    }
+
+    /// Ends the playback of the camera path, and the recording it started.
+    fn finish_playback(&mut self) {
+        let Some(playback) = self.camera_playback.take() else {
+            return;
+        };
+        info!("Finished playing the camera path");
+        if playback.recording && self.is_recording() {
+            self.toggle_recording();
+        }
+    }
+
+    /// Replaces the scene with the one loaded from `source`, keeping the current one if that
+    /// fails.
+    fn open_scene(&mut self, source: SceneSource) {
+        let (scene, scene_file_path) = match load_scene(&mut self.assets, source) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                error!("{err}");
+                return;
+            }
+        };
+        info!("Loaded {}", scene_file_path.display());
+
+        self.scene = scene;
+        #[cfg(feature = "scripting")]
+        self.scripts.restart(&mut self.scene);
+        self.scene_file_path = scene_file_path;
+        self.selected_object = None;
+        self.gizmo.end_drag();
+        self.history.clear();
+        self.camera.reset();
+        self.camera_playback = None;
+        self.watch_assets();
+    }
+
+    /// Watches the files of every asset, so that they are reloaded when they change.
+    fn watch_assets(&mut self) {
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        for path in self.assets.files() {
+            if let Err(err) = watcher.watch(&path) {
+                warn!("Failed to watch {}: {err}", path.display());
+            }
+        }
+    }
+
+    /// Loads a file dropped onto the window, going by its extension: a glTF file is added to the
+    /// scene, a PNG image becomes the base color texture of the selected entity, and a Radiance
+    /// or OpenEXR image becomes the environment.
+    fn open_dropped(&mut self, path: &Path) {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("gltf" | "glb") => match self.scene.add_gltf(&mut self.assets, path) {
+                Ok(_) => {
+                    info!("Adding {} to the scene", path.display());
+                    self.watch_assets();
+                }
+                Err(err) => error!("Failed to load {}: {err}", path.display()),
+            },
+            Some("png") => {
+                let Some(entity) = self.selected_object else {
+                    warn!("Select an entity to texture with {}", path.display());
+                    return;
+                };
+                let before = MaterialState::of(&self.scene, entity);
+                let texture = self.assets.texture(path);
+                let Ok(mut material) = self.scene.world.get::<&mut Material>(entity) else {
+                    return;
+                };
+                material.base_color_texture = Some(texture);
+                drop(material);
+                info!("Texturing entity {} with {}", entity.id(), path.display());
+                self.record_material_edit(entity, before);
+                self.watch_assets();
+            }
+            _ if hdr::is_hdr(path) => match self.set_environment(path) {
+                Ok(()) => info!("Set the environment to {}", path.display()),
+                Err(err) => error!("{err}"),
+            },
+            _ => warn!(
+                "Can't open {}: only glTF, PNG, Radiance and OpenEXR files can be dropped",
+                path.display()
+            ),
+        }
+    }
+
+    /// Shows the console in the window title while it is open, taking input from input methods
+    /// for it, and the frame statistics again once it closes.
+    fn update_console_title(&mut self) {
+        let Some(rcx) = &mut self.rcx else {
+            return;
+        };
+        if rcx.ime_allowed != self.console.is_open() {
+            rcx.ime_allowed = self.console.is_open();
+            rcx.window.set_ime_allowed(rcx.ime_allowed);
+        }
+        if self.console.is_open() {
+            rcx.window.set_title(&self.console.title());
+            // The line in the overlay too.
+            rcx.window.request_redraw();
+        } else {
+            // The title is set again on the next frame.
+            rcx.draw_stats = DrawStats::default();
+        }
+    }
+
+    /// Copies the report of `vulkano-test info` on the device in use to the clipboard, or logs it
+    /// where it can't be copied.
+    fn copy_gpu_info(&mut self) {
+        let Some(rcx) = &self.rcx else {
+            return;
+        };
+        let report =
+            match info::Report::of_device(self.device.physical_device(), rcx.swapchain.surface()) {
+                Ok(report) => report.to_text(),
+                Err(err) => {
+                    warn!("Failed to gather the GPU report: {err}");
+                    return;
+                }
+            };
+        match self.clipboard.copy(&report) {
+            Ok(()) => info!("Copied the GPU report to the clipboard"),
+            Err(err) => {
+                // It can still be copied out of the log.
+                warn!("Failed to copy the GPU report: {err}");
+                info!("{report}");
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyCode) {
+        let Some(action) = self.settings.keybindings.action(key, self.modifiers) else {
+            return;
+        };
+        match action {
+            Action::Undo => self.undo(false),
+            Action::Redo => self.undo(true),
+            Action::DuplicateSelected => {
+                let Some(entity) = self.selected_object else {
+                    return;
+                };
+                self.end_gizmo_drag();
+                if let Some(edit) = Edit::duplicate(&mut self.scene, entity) {
+                    let copy = edit.entity();
+                    info!(
+                        "Added entity {}, a copy of entity {}",
+                        copy.id(),
+                        entity.id()
+                    );
+                    self.history.record(edit);
+                    self.select(Some(copy));
+                }
+            }
+            // These are only done while the console is open.
+            Action::ConsoleCopy | Action::ConsolePaste => {}
+            Action::CopyGpuInfo => self.copy_gpu_info(),
+            Action::ToggleConsole => {
+                self.console.toggle();
+                if self.console.is_open() {
+                    // The console takes the keys from here on, so none of them come back up for the
+                    // camera.
+                    self.camera.release_keys();
+                }
+                self.update_console_title();
+            }
+            Action::SelectNext => {
+                // Cycles through the drawn entities, with a step where nothing is selected.
+                let entities: Vec<Entity> = self
+                    .scene
+                    .world
+                    .query::<(Entity, &MeshHandle)>()
+                    .iter()
+                    .map(|(entity, _)| entity)
+                    .collect();
+                let selected = match self.selected_object {
+                    None => entities.first().copied(),
+                    Some(selected) => entities
+                        .iter()
+                        .position(|&entity| entity == selected)
+                        .and_then(|i| entities.get(i + 1).copied()),
+                };
+                self.select(selected);
+            }
+            Action::CycleMaterialOverride => {
+                let Some(entity) = self.selected_object else {
+                    return;
+                };
+
+                // Steps through every material variant, then back to no override.
+                let before = MaterialState::of(&self.scene, entity);
+                let materials = &self.scene.materials;
+                let next = match self.scene.world.get::<&MaterialOverride>(entity) {
+                    Err(_) => 0,
+                    Ok(current) => materials
+                        .iter()
+                        .position(|material| *material == current.0)
+                        .map_or(materials.len(), |i| i + 1),
+                };
+                let material_override = materials.get(next).cloned();
+                info!(
+                    "Material override: {}",
+                    material_override
+                        .as_ref()
+                        .map_or("none", |material| &material.name),
+                );
+                // The selected entity may have been despawned since, in which case there is
+                // nothing left to override.
+                match material_override {
+                    Some(material) => {
+                        let _ = self
+                            .scene
+                            .world
+                            .insert_one(entity, MaterialOverride(material));
+                    }
+                    None => {
+                        let _ = self.scene.world.remove_one::<MaterialOverride>(entity);
+                    }
+                }
+                self.record_material_edit(entity, before);
+            }
+            Action::ToggleCameraMode => {
+                self.camera.toggle_mode();
+                info!("Camera: {}", self.camera.mode().name());
+            }
+            Action::FrameSelected => self.frame_selected(),
+            Action::TogglePause => {
+                self.time.toggle_pause();
+                self.show_time();
+            }
+            Action::StepTime => self.time.step(),
+            Action::SlowDown | Action::SpeedUp => {
+                let factor = if action == Action::SlowDown { 0.5 } else { 2.0 };
+                self.time.set_scale(self.time.scale() * factor);
+                self.show_time();
+            }
+            Action::NextGizmoMode => {
+                self.end_gizmo_drag();
+                self.gizmo.mode = self.gizmo.mode.next();
+                info!("Gizmo: {}", self.gizmo.mode.name());
+            }
+            Action::NextMaterialParameter => {
+                let parameter = self.material_editor.next_parameter();
+                info!("Editing {}", parameter.name());
+            }
+            Action::DecreaseMaterialParameter | Action::IncreaseMaterialParameter => {
+                let Some(entity) = self.selected_object else {
+                    return;
+                };
+                let steps = if action == Action::DecreaseMaterialParameter {
+                    -1.0
+                } else {
+                    1.0
+                };
+                let before = MaterialState::of(&self.scene, entity);
+                if let Some(edited) = self.material_editor.step(&mut self.scene, entity, steps) {
+                    info!("{edited}");
+                }
+                self.record_material_edit(entity, before);
+            }
+            Action::DeleteSelected => {
+                let Some(entity) = self.selected_object else {
+                    return;
+                };
+                self.end_gizmo_drag();
+                if let Some(edit) = Edit::despawn(&mut self.scene, entity) {
+                    info!("Removed entity {}", entity.id());
+                    self.history.record(edit);
+                }
+                self.select(None);
+            }
+            Action::SaveScene => match scene_file::save(&self.scene, &self.scene_file_path) {
+                Ok(()) => info!("Saved {}", self.scene_file_path.display()),
+                Err(err) => error!("Failed to save {}: {err}", self.scene_file_path.display()),
+            },
+            Action::ToggleMaterialPreview => {
+                self.material_preview = !self.material_preview;
+            }
+            Action::Screenshot => self
+                .screenshot_capture
+                .request(timestamped_path("screenshot", "png")),
+            Action::SupersampledScreenshot => self.screenshot_capture.request_supersampled(
+                timestamped_path("screenshot", "png"),
+                self.settings.supersampling,
+            ),
+            Action::ToggleRecording => self.toggle_recording(),
+            Action::SaveGif => self.gif_capture.save(timestamped_path("capture", "gif")),
+            Action::ToggleFullscreen => {
+                let Some(rcx) = &mut self.rcx else {
+                    return;
+                };
+                // Leaving exclusive fullscreen this way is for good, rather than until the window
+                // is focused again.
+                rcx.fullscreen_mode = None;
+                if rcx.window.fullscreen().is_some() {
+                    rcx.window.set_fullscreen(None);
+                } else {
+                    let monitor = rcx.window.current_monitor();
+                    rcx.window
+                        .set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+                }
+            }
+            Action::ToggleWireframe => {
+                let Some(rcx) = &mut self.rcx else {
+                    return;
+                };
+                if !rcx.scene_pipeline.supports_wireframe() {
+                    warn!("Wireframe rendering needs the fillModeNonSolid device feature");
+                    return;
+                }
+
+                self.wireframe = !self.wireframe;
+                rcx.scene_pipeline.set_wireframe(self.wireframe);
+                rcx.terrain_pipeline.set_wireframe(self.wireframe);
+            }
+            Action::ToggleShadows => {
+                let Some(rcx) = &self.rcx else {
+                    return;
+                };
+                if rcx.shadow_acceleration_structures.is_none() {
+                    warn!("Shadows need --ray-query-shadows, a GPU with ray queries and glslc");
+                    return;
+                }
+
+                self.shadows = !self.shadows;
+                info!("Shadows {}", if self.shadows { "on" } else { "off" });
+            }
+            Action::ToggleDenoise => {
+                let Some(rcx) = &mut self.rcx else {
+                    return;
+                };
+                let Some(denoiser) = &mut rcx.denoiser else {
+                    warn!("Denoising needs --pathtrace and a GPU with ray tracing");
+                    return;
+                };
+
+                // The history is of frames that weren't denoised in the meantime.
+                denoiser.reset();
+                self.denoise = !self.denoise;
+                info!("Denoising {}", if self.denoise { "on" } else { "off" });
+            }
+        }
+    }
+
+    fn create_render_context(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+    ) -> Result<RenderContext, AppError> {
+        let fullscreen_mode = self.window_placement.fullscreen_mode(event_loop);
+        let window = Arc::new(
+            event_loop.create_window(
+                self.window_placement.apply(
+                    event_loop,
+                    Window::default_attributes()
+                        .with_title("vulkano-test")
+                        .with_window_icon(Some(window_icon(&self.settings)))
+                        .with_transparent(self.settings.transparent),
+                    fullscreen_mode.clone(),
+                ),
+            )?,
+        );
+        let surface = Surface::from_window(self.instance.clone(), window.clone())?;
+        let window_size = window.inner_size();
+
+        let supported_composite_alpha;
+        let requested_images = self.requested_images();
+        let (swapchain, images) = {
+            let surface_capabilities = self
+                .device
+                .physical_device()
+                .surface_capabilities(&surface, Default::default())
+                .map_err(AppError::Swapchain)?;
+            // The ray and path tracers blit linear colors, which a 10-bit swapchain wouldn't
+            // encode.
+            let ten_bit =
+                self.settings.ten_bit_output && !Capabilities::of(&self.device).ray_tracing;
+            let (image_format, image_color_space) = gamma::swapchain_format(
+                &self
+                    .device
+                    .physical_device()
+                    .surface_formats(&surface, Default::default())
+                    .map_err(AppError::Swapchain)?,
+                ten_bit,
+            );
+            gamma::log_color_pipeline(image_format);
+            let present_mode = present_mode(&self.device, self.settings.vsync, &surface);
+            supported_composite_alpha = surface_capabilities.supported_composite_alpha;
+            if self.settings.transparent
+                && composite_alpha(supported_composite_alpha, true) == CompositeAlpha::Opaque
+            {
+                warn!("The window surface can't be transparent");
+            }
+
+            let create_info = with_maintenance(
+                &self.device,
+                &surface,
+                self.settings.present_scaling,
+                SwapchainCreateInfo {
+                    min_image_count: image_count(requested_images, &surface_capabilities),
+                    image_format,
+                    image_color_space,
+                    image_extent: window_size.into(),
+                    // Transfers are only needed to save the image when debugging frames, to record
+                    // it, and to blit the blurred scene into it.
+                    image_usage: ImageUsage::COLOR_ATTACHMENT
+                        | (surface_capabilities.supported_usage_flags
+                            & (ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST)),
+                    composite_alpha: composite_alpha(
+                        supported_composite_alpha,
+                        self.settings.transparent,
+                    ),
+                    present_mode,
+                    ..Default::default()
+                },
+            );
+            warn_unscaled(&self.device, &create_info, self.settings.present_scaling);
+            Swapchain::new(self.device.clone(), surface, create_info)
+                .map_err(AppError::Swapchain)?
+        };
+        crash_report::set_swapchain(&swapchain);
+        debug_utils::name(&swapchain, "swapchain");
+
+        if let Some(frame_limiter) = &mut self.frame_limiter {
+            frame_limiter
+                .set_refresh_period(frame_limiter::refresh_period(&self.device, &swapchain));
+        }
+
+        let render_pass = vulkano::single_pass_renderpass!(
+            self.device.clone(),
+            attachments: {
+                color: {
+                    format: swapchain.image_format(),
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: Store,
+                },
+                depth_stencil: {
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                    load_op: Clear,
+                    store_op: DontCare,
+                },
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth_stencil},
+            }
+        )
+        .map_err(AppError::Pipeline)?;
+
+        let overlay_render_pass = vulkano::single_pass_renderpass!(
+            self.device.clone(),
+            attachments: {
+                color: {
+                    format: swapchain.image_format(),
+                    samples: 1,
+                    load_op: Load,
+                    store_op: Store,
+                },
+                depth_stencil: {
+                    format: Format::D16_UNORM,
+                    samples: 1,
+                    load_op: DontCare,
+                    store_op: DontCare,
+                },
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {depth_stencil},
+            }
+        )
+        .map_err(AppError::Pipeline)?;
+
+        // Every swapchain image can have a frame in flight.
+        self.assets.set_frames_in_flight(images.len());
+        let framebuffers =
+            window_size_dependent_setup(&self.memory_allocator, &images, &render_pass)?;
+
+        let mut scene_pipeline = ScenePipeline::new(
+            self.memory_allocator.clone(),
+            self.descriptor_set_allocator.clone(),
+            self.assets.white_texture().clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+        scene_pipeline.set_wireframe(self.wireframe);
+        let mut terrain_pipeline = TerrainPipeline::new(
+            self.memory_allocator.clone(),
+            self.descriptor_set_allocator.clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+        terrain_pipeline.set_wireframe(self.wireframe);
+        let debug_draw_pipeline = DebugDrawPipeline::new(
+            self.memory_allocator.clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+        let grid_pipeline = GridPipeline::new(
+            self.memory_allocator.clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+        let skybox_pipeline = SkyboxPipeline::new(
+            self.memory_allocator.clone(),
+            self.descriptor_set_allocator.clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+        let selection_outline = SelectionOutline::new(
+            self.memory_allocator.clone(),
+            self.descriptor_set_allocator.clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+
+        let occlusion_culler = OcclusionCuller::new(
+            self.memory_allocator.clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+        let gpu_culler = scene_pipeline
+            .supports_gpu_culling()
+            .then(|| {
+                GpuCuller::new(
+                    self.memory_allocator.clone(),
+                    self.descriptor_set_allocator.clone(),
+                )
+            })
+            .transpose()?;
+
+        let light_culler = LightCuller::new(
+            self.memory_allocator.clone(),
+            self.descriptor_set_allocator.clone(),
+        )?;
+
+        let particle_system = ParticleSystem::new(
+            self.memory_allocator.clone(),
+            self.descriptor_set_allocator.clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+
+        let blit_dst = swapchain.image_usage().intersects(ImageUsage::TRANSFER_DST)
+            && self
+                .device
+                .physical_device()
+                .format_properties(swapchain.image_format())
+                .map_err(|source| AppError::FormatProperties {
+                    format: swapchain.image_format(),
+                    source,
+                })?
+                .optimal_tiling_features
+                .intersects(FormatFeatures::BLIT_DST);
+        // Offscreen targets of the main view share the swapchain's format, so where colors are
+        // encoded by hand, the post-effects get them encoded and have to leave them so.
+        let encoded = gamma::Encoding::of(swapchain.image_format()) == gamma::Encoding::Manual;
+        let blur_filter = blit_dst
+            .then(|| {
+                BlurFilter::new(
+                    self.memory_allocator.clone(),
+                    self.descriptor_set_allocator.clone(),
+                    encoded,
+                )
+            })
+            .transpose()?;
+        if self.settings.blur_radius > 0 && blur_filter.is_none() {
+            warn!("The window's images can't be blitted to, which blurring needs");
+        }
+        if self.settings.render_scale != 1.0 && blur_filter.is_none() {
+            warn!("The window's images can't be blitted to, which render_scale needs");
+        }
+        let fog_pass = blit_dst
+            .then(|| {
+                FogPass::new(
+                    self.memory_allocator.clone(),
+                    self.descriptor_set_allocator.clone(),
+                    encoded,
+                )
+            })
+            .transpose()?;
+        if self.settings.fog_density > 0.0 && fog_pass.is_none() {
+            warn!("The window's images can't be blitted to, which fog needs");
+        }
+        let ray_tracing = Capabilities::of(&self.device).ray_tracing && blit_dst;
+        let path_tracer = if ray_tracing && self.path_tracing {
+            PathTracer::new(
+                self.memory_allocator.clone(),
+                self.command_buffer_allocator.clone(),
+                self.descriptor_set_allocator.clone(),
+                self.queue.clone(),
+            )
+            .inspect_err(|err| warn!("Path tracing is unavailable: {err}"))
+            .ok()
+        } else {
+            None
+        };
+        let denoiser = path_tracer
+            .is_some()
+            .then(|| {
+                Denoiser::new(
+                    self.memory_allocator.clone(),
+                    self.descriptor_set_allocator.clone(),
+                )
+                .inspect_err(|err| warn!("Denoising is unavailable: {err}"))
+                .ok()
+            })
+            .flatten();
+        let ray_tracer = if ray_tracing && !self.path_tracing {
+            // Without glslc, the scene is rasterized as it is elsewhere.
+            RayTracer::new(
+                self.memory_allocator.clone(),
+                self.command_buffer_allocator.clone(),
+                self.descriptor_set_allocator.clone(),
+                self.queue.clone(),
+            )
+            .inspect_err(|err| warn!("Ray tracing is unavailable: {err}"))
+            .ok()
+        } else {
+            None
+        };
+        let shadow_acceleration_structures = scene_pipeline.supports_shadows().then(|| {
+            AccelerationStructures::new(
+                self.memory_allocator.clone(),
+                self.command_buffer_allocator.clone(),
+                self.queue.clone(),
+            )
+        });
+        let scene_target_render_pass = OffscreenTarget::create_render_pass(
+            self.device.clone(),
+            &[swapchain.image_format()],
+            Some(Format::D16_UNORM),
+        )?;
+        let water_render_pass = OffscreenTarget::create_continuing_render_pass(
+            self.device.clone(),
+            &[swapchain.image_format()],
+            Some(Format::D16_UNORM),
+        )?;
+        let water_pass = WaterPass::new(
+            self.memory_allocator.clone(),
+            self.descriptor_set_allocator.clone(),
+            Subpass::from(water_render_pass.clone(), 0).unwrap(),
+        )?;
+        if self.scene.water.is_some() && !blit_dst {
+            warn!("The window's images can't be blitted to, which drawing water needs");
+        }
+
+        let viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: window_size.into(),
+            depth_range: 0.0..=1.0,
+        };
+
+        let scale_factor = window.scale_factor();
+        let previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+
+        Ok(RenderContext {
+            window,
+            fullscreen_mode,
+            swapchain,
+            render_pass,
+            overlay_render_pass,
+            framebuffers,
+            scene_pipeline,
+            terrain_pipeline,
+            debug_draw_pipeline,
+            grid_pipeline,
+            skybox_pipeline,
+            selection_outline,
+            occlusion_culler,
+            gpu_culler,
+            light_culler,
+            particle_system,
+            blur_filter,
+            fog_pass,
+            ray_tracer,
+            path_tracer,
+            denoiser,
+            shadow_acceleration_structures,
+            scene_target_render_pass,
+            scene_target: None,
+            water_render_pass,
+            water_pass,
+            viewport,
+            views: Vec::new(),
+            supported_composite_alpha,
+            requested_images,
+            present_scaling: self.settings.present_scaling,
+            next_present_mode: None,
+            scale_factor,
+            draw_stats: DrawStats::default(),
+            pending_loads: 0,
+            ime_allowed: false,
+            recreate_swapchain: false,
+            resized_at: None,
+            previous_frame_end,
+        })
+    }
+
+    fn redraw(&mut self) -> Result<(), AppError> {
+        let requested_images = self.requested_images();
+        // A pick is read back once the future of its frame has been cleaned up, by an earlier
+        // redraw.
+        if let Some(pick) = self.picker.poll() {
+            self.select(pick.entity);
+        }
+        let Some(rcx) = self.rcx.as_mut() else {
+            return Ok(());
+        };
+
+        let window_size = rcx.window.inner_size();
+
+        // Do not draw the frame when the screen size is zero. On Windows, this can occur
+        // when minimizing the application.
+        if window_size.width == 0 || window_size.height == 0 {
+            return Ok(());
+        }
+
+        rcx.previous_frame_end.as_mut().unwrap().cleanup_finished();
+
+        if let Some(resized_at) = rcx.resized_at {
+            if resized_at.elapsed() >= RESIZE_DEBOUNCE {
+                rcx.resized_at = None;
+                rcx.recreate_swapchain = true;
+            } else {
+                // Come back to recreate the swapchain, even if nothing else asks for a frame.
+                rcx.window.request_redraw();
+            }
+        }
+
+        if rcx.recreate_swapchain {
+            // A video can't change size halfway through.
+            #[cfg(feature = "video")]
+            if let Some(recorder) = self
+                .video_recorder
+                .take_if(|recorder| recorder.extent() != <[u32; 2]>::from(window_size))
+            {
+                warn!("Stopped recording, as the window was resized");
+                rcx.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                recorder.finish();
+            }
+
+            let present_mode =
+                present_mode(&self.device, self.settings.vsync, rcx.swapchain.surface());
+            let min_image_count = if requested_images == rcx.requested_images {
+                rcx.swapchain.create_info().min_image_count
+            } else {
+                let surface_capabilities = self
+                    .device
+                    .physical_device()
+                    .surface_capabilities(rcx.swapchain.surface(), Default::default())
+                    .map_err(AppError::Swapchain)?;
+                rcx.requested_images = requested_images;
+                image_count(requested_images, &surface_capabilities)
+            };
+            let create_info = SwapchainCreateInfo {
+                min_image_count,
+                image_extent: window_size.into(),
+                present_mode,
+                composite_alpha: composite_alpha(
+                    rcx.supported_composite_alpha,
+                    self.settings.transparent,
+                ),
+                // These are chosen again for the new present mode and size.
+                present_modes: Default::default(),
+                scaling_behavior: None,
+                present_gravity: None,
+                ..rcx.swapchain.create_info()
+            };
+            let create_info = with_maintenance(
+                &self.device,
+                rcx.swapchain.surface(),
+                self.settings.present_scaling,
+                create_info,
+            );
+            if self.settings.present_scaling != rcx.present_scaling {
+                warn_unscaled(&self.device, &create_info, self.settings.present_scaling);
+                rcx.present_scaling = self.settings.present_scaling;
+            }
+            let (new_swapchain, new_images) = rcx
+                .swapchain
+                .recreate(create_info)
+                .map_err(AppError::Swapchain)?;
+            rcx.next_present_mode = None;
+            crash_report::set_swapchain(&new_swapchain);
+            debug_utils::name(&new_swapchain, "swapchain");
+
+            rcx.swapchain = new_swapchain;
+            rcx.framebuffers =
+                window_size_dependent_setup(&self.memory_allocator, &new_images, &rcx.render_pass)?;
+            rcx.scene_target = None;
+            rcx.viewport.extent = window_size.into();
+            rcx.recreate_swapchain = false;
+            rcx.resized_at = None;
+            if let Some(low_latency) = &mut self.low_latency {
+                // The old swapchain is retired, and can't be waited on.
+                low_latency.forget_present();
+            }
+            if let Some(metrics) = &mut self.metrics {
+                metrics.swapchain_recreated();
+            }
+            // The window may have moved to a display with a different refresh rate.
+            if let Some(frame_limiter) = &mut self.frame_limiter {
+                frame_limiter.set_refresh_period(frame_limiter::refresh_period(
+                    &self.device,
+                    &rcx.swapchain,
+                ));
+            }
+        }
+
+        // Until the swapchain is recreated for a resize, frames are drawn at the size of its
+        // images.
+        let extent = rcx.swapchain.image_extent();
+
+        if let Some(frame_limiter) = &mut self.frame_limiter {
+            frame_limiter.wait();
+        }
+        let latency_changed = self
+            .low_latency
+            .as_mut()
+            .is_some_and(|low_latency| low_latency.wait(&rcx.swapchain));
+        let budget_changed = self
+            .memory_budget
+            .as_mut()
+            .is_some_and(MemoryBudget::update);
+        if latency_changed || budget_changed {
+            // Show the new latency and memory usage, even if the frame draws the same as the last.
+            rcx.draw_stats = DrawStats::default();
+        }
+        if let Some(metrics) = &mut self.metrics {
+            metrics.begin_frame();
+        }
+
+        let acquire_span = debug_span!("acquire").entered();
+        let acquire_start = Instant::now();
+        let (image_index, suboptimal, acquire_future) =
+            match acquire_next_image(rcx.swapchain.clone(), None).map_err(Validated::unwrap) {
+                Ok(r) => r,
+                Err(VulkanError::OutOfDate) => {
+                    rcx.recreate_swapchain = true;
+                    return Ok(());
+                }
+                Err(VulkanError::DeviceLost) => return Err(AppError::DeviceLost),
+                Err(e) => return Err(AppError::Swapchain(e.into())),
+            };
+        drop(acquire_span);
+        if let Some(metrics) = &mut self.metrics {
+            metrics.set_acquire_time(acquire_start.elapsed());
+        }
+
+        // A swapchain that no longer fits the window is expected while it is being resized.
+        if suboptimal && rcx.resized_at.is_none() {
+            rcx.recreate_swapchain = true;
+        }
+
+        let framebuffer = rcx.framebuffers[image_index as usize].clone();
+        let swapchain_image = framebuffer.attachments()[0].image().clone();
+        let frame_debug = self.settings.frame_debug;
+        let output = gamma::Output::new(
+            self.settings.gamma_audit,
+            self.settings.dither,
+            rcx.swapchain.image_format(),
+        );
+        rcx.scene_pipeline.set_output(output);
+        rcx.terrain_pipeline.set_output(output);
+        rcx.water_pass.set_output(output);
+        let mut after_passes = rcx
+            .previous_frame_end
+            .take()
+            .unwrap()
+            .join(acquire_future)
+            .boxed();
+        if frame_debug {
+            self.frame_debugger.next_frame();
+        }
+
+        let record_span = debug_span!("record").entered();
+
+        self.scene.interpolate(self.timestep.alpha());
+        self.scene.update_transforms();
+        let preview_object = self
+            .selected_object
+            .filter(|_| self.material_preview && !self.scene.materials.is_empty())
+            .and_then(|entity| {
+                let mut query = self
+                    .scene
+                    .world
+                    .query_one::<(&Transform, &MeshHandle)>(entity);
+                let (transform, mesh) = query.get().ok()?;
+                // Nothing to preview until the mesh has loaded.
+                let aabb = mesh.aabb(transform)?;
+                Some((*transform, mesh.clone(), aabb))
+            });
+        let mut builder = AutoCommandBufferBuilder::primary(
+            self.command_buffer_allocator.clone(),
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        if let Some(metrics) = &mut self.metrics {
+            metrics.begin_commands(&mut builder);
+        }
+        debug_utils::labeled(&mut builder, "particle update", |builder| {
+            rcx.particle_system
+                .update(builder, self.settings.particles, self.time.elapsed());
+        });
+
+        // Premultiplied composition expects the color to be scaled by the alpha already.
+        let mut clear_color = self.settings.clear_color;
+        if rcx.swapchain.create_info().composite_alpha == CompositeAlpha::PreMultiplied {
+            let [r, g, b, a] = clear_color;
+            clear_color = [r * a, g * a, b * a, a];
+        }
+
+        // The camera of the main view.
+        let [width, height] = rcx.viewport.extent;
+        let bounds = self.scene.bounds();
+        let controlled = self.camera.camera(
+            self.scene
+                .camera
+                .unwrap_or_else(|| Camera::framing(&bounds)),
+        );
+        let camera = self
+            .camera_playback
+            .as_ref()
+            .and_then(CameraPathPlayback::camera)
+            .unwrap_or(controlled);
+        self.drawn_camera = Some(camera);
+        let main_view_proj = camera.view_proj(width / height, &bounds);
+        if self.assets.is_streaming_textures() {
+            let needs =
+                texture_streaming::texture_needs(&self.scene, main_view_proj, [width, height]);
+            self.assets.stream_textures(&needs);
+        }
+        let ray_traced = match (&mut rcx.path_tracer, &mut rcx.ray_tracer) {
+            _ if preview_object.is_some() => None,
+            (Some(path_tracer), _) => {
+                debug_utils::labeled(&mut builder, "path tracing", |builder| {
+                    path_tracer.trace(
+                        builder,
+                        &self.scene,
+                        main_view_proj,
+                        camera.eye,
+                        extent,
+                        clear_color,
+                    )
+                })
+                .map(|traced| match &mut rcx.denoiser {
+                    Some(denoiser) if self.denoise => {
+                        debug_utils::labeled(&mut builder, "denoise", |builder| {
+                            denoiser.apply(
+                                builder,
+                                &traced.color,
+                                &traced.guide,
+                                path_tracer.sample_count(),
+                                main_view_proj,
+                                camera.eye,
+                            )
+                        })
+                    }
+                    _ => traced.color.image().clone(),
+                })
+            }
+            (None, Some(ray_tracer)) => {
+                debug_utils::labeled(&mut builder, "ray tracing", |builder| {
+                    ray_tracer.trace(
+                        builder,
+                        &self.scene,
+                        main_view_proj,
+                        camera.eye,
+                        extent,
+                        clear_color,
+                    )
+                })
+            }
+            (None, None) => None,
+        };
+
+        // The rasterized scene is drawn at the render scale of the window's size, offscreen unless
+        // that is the window's size, and blitted to the swapchain image once it is done. The
+        // overlay is drawn at the window's size either way.
+        let render_scale = if ray_traced.is_none() && rcx.blur_filter.is_some() {
+            self.settings.render_scale.clamp(
+                RenderSettings::MIN_RENDER_SCALE,
+                RenderSettings::MAX_RENDER_SCALE,
+            )
+        } else {
+            1.0
+        };
+        let scene_extent = extent.map(|side| ((side as f32 * render_scale).round() as u32).max(1));
+        let scene_viewport = scale_viewport(&rcx.viewport, extent, scene_extent);
+
+        // The rasterized main view is drawn once for each eye: the camera, its view and
+        // projection, and the half of the window it is drawn into, in stereo.
+        let stereo = self.settings.stereo && preview_object.is_none() && ray_traced.is_none();
+        let eyes = if stereo {
+            let viewports = grid_viewports(&rcx.viewport, 2);
+            let [width, height] = viewports[0].extent;
+            camera
+                .stereo_pair(self.settings.eye_separation, width / height, &bounds)
+                .into_iter()
+                .zip(viewports)
+                .map(|((camera, view_proj), viewport)| (camera, view_proj, viewport))
+                .collect()
+        } else {
+            vec![(camera, main_view_proj, rcx.viewport.clone())]
+        };
+
+        // The TLAS is built here, as it can't be inside the render pass. The material preview
+        // isn't shadowed, as its cells frame the entity away from the rest of the scene.
+        let shadow_tlas = match &mut rcx.shadow_acceleration_structures {
+            Some(acceleration_structures)
+                if self.shadows && preview_object.is_none() && ray_traced.is_none() =>
+            {
+                debug_utils::labeled(&mut builder, "TLAS build", |builder| {
+                    acceleration_structures.build_tlas(builder, &self.scene)
+                })
+                .map(|tlas| tlas.acceleration_structure)
+            }
+            _ => None,
+        };
+        rcx.scene_pipeline.set_shadow_tlas(shadow_tlas);
+        // The lights are binned here for the same reason, and only light the main view, when it
+        // isn't stereo.
+        let light_clusters = if preview_object.is_none() && ray_traced.is_none() && !stereo {
+            debug_utils::labeled(&mut builder, "light culling", |builder| {
+                rcx.light_culler.cull(
+                    builder,
+                    &self.scene,
+                    &camera,
+                    &bounds,
+                    scene_viewport.extent,
+                )
+            })
+        } else {
+            None
+        };
+        rcx.scene_pipeline.set_light_clusters(light_clusters);
+
+        let occlusion_culling = self.settings.occlusion_culling
+            && preview_object.is_none()
+            && ray_traced.is_none()
+            && !stereo;
+        if occlusion_culling {
+            debug_utils::labeled(&mut builder, "occlusion results", |builder| {
+                rcx.occlusion_culler.begin_frame(builder, &self.scene);
+            });
+        }
+        // The views of the main view's eyes, which clicks pick from and the overlay, with the
+        // outline of the selection, is drawn for. The material preview has none, so clicks on it
+        // are dropped.
+        let views: Vec<_> = eyes
+            .iter()
+            .filter(|_| preview_object.is_none())
+            .map(|(_, view_proj, viewport)| (*view_proj, viewport.clone()))
+            .collect();
+        debug_utils::labeled(&mut builder, "picking", |builder| {
+            self.picker.record(builder, &self.scene, &views)
+        })?;
+        debug_utils::labeled(&mut builder, "selection mask", |builder| {
+            rcx.selection_outline.record(
+                builder,
+                &self.scene,
+                self.selected_object,
+                &views,
+                extent,
+                self.settings.outline_width * rcx.scale_factor as f32,
+            )
+        })?;
+        rcx.views.clone_from(&views);
+        // The compute passes of GPU culling can't be inside the render pass either. Levels of
+        // detail are only colored when culled on the CPU.
+        let culled = match &mut rcx.gpu_culler {
+            Some(gpu_culler)
+                if self.settings.gpu_culling
+                    && !self.settings.lod_debug
+                    && preview_object.is_none()
+                    && ray_traced.is_none()
+                    && !stereo =>
+            {
+                debug_utils::labeled(&mut builder, "GPU culling", |builder| {
+                    gpu_culler.cull(
+                        builder,
+                        &self.scene,
+                        main_view_proj,
+                        occlusion_culling.then_some(&rcx.occlusion_culler),
+                    )
+                })
+            }
+            _ => None,
+        };
+        // A blurred or fogged scene, or one with water, is drawn offscreen, and ends up in the
+        // swapchain image once its water and fog are drawn and it is blurred. Only the rasterized
+        // main view has water and fog, and only when it isn't stereo.
+        let blur_radius = self.settings.blur_radius;
+        let main_view = preview_object.is_none() && ray_traced.is_none() && !stereo;
+        let water = self.scene.water.filter(|_| main_view);
+        let fog = (main_view && self.settings.fog_density > 0.0).then_some(Fog {
+            density: self.settings.fog_density,
+            anisotropy: self.settings.fog_anisotropy,
+            height_falloff: self.settings.fog_height_falloff,
+        });
+        let scene_target =
+            if (blur_radius > 0 || water.is_some() || fog.is_some() || scene_extent != extent)
+                && rcx.blur_filter.is_some()
+                && ray_traced.is_none()
+            {
+                if rcx
+                    .scene_target
+                    .as_ref()
+                    .is_none_or(|target| target.extent() != scene_extent)
+                {
+                    rcx.scene_target = Some(OffscreenTarget::new(
+                        self.memory_allocator.clone(),
+                        rcx.scene_target_render_pass.clone(),
+                        scene_extent,
+                    )?);
+                }
+                rcx.scene_target.as_mut()
+            } else {
+                None
+            };
+        // Only clearing takes the clear color encoded as the swapchain stores it; the tracers and
+        // the water take it linear.
+        let clear_value = gamma::clear_color(clear_color, rcx.swapchain.image_format());
+        match (&ray_traced, &scene_target) {
+            (Some(image), _) => {
+                builder
+                    .blit_image(BlitImageInfo::images(
+                        image.clone(),
+                        swapchain_image.clone(),
+                    ))
+                    .unwrap();
+                begin_overlay_pass(
+                    &mut builder,
+                    rcx.overlay_render_pass.clone(),
+                    framebuffer.clone(),
+                );
+            }
+            (None, Some(target)) => {
+                target.begin_render_pass(&mut builder, &[ClearColorValue::Float(clear_value)]);
+            }
+            (None, None) => {
+                builder
+                    .begin_render_pass(
+                        RenderPassBeginInfo {
+                            clear_values: vec![Some(clear_value.into()), Some(1.0.into())],
+                            ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+                        },
+                        SubpassBeginInfo {
+                            contents: SubpassContents::Inline,
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap();
+            }
+        }
+
+        // The camera of the main view, which the overlay is drawn with. There is none
+        // while previewing materials.
+        let view_proj = if let Some((transform, mesh, aabb)) = &preview_object {
+            // One cell per material variant, each framing the selected entity.
+            let cells = grid_viewports(&scene_viewport, self.scene.materials.len());
+            let light = self.scene.light();
+            for (material, viewport) in self.scene.materials.iter().zip(cells) {
+                let [width, height] = viewport.extent;
+                let view_proj = Camera::framing(aabb).view_proj(width / height, aabb);
+
+                debug_utils::labeled(&mut builder, "material preview", |builder| {
+                    rcx.scene_pipeline
+                        .bind(builder, view_proj, &light, viewport);
+                    rcx.scene_pipeline
+                        .draw_object(builder, transform, mesh, material);
+                });
+            }
+            None
+        } else if ray_traced.is_some() {
+            if let Some(path_tracer) = &rcx.path_tracer {
+                rcx.window.set_title(&format!(
+                    "vulkano-test - path tracing - {} samples",
+                    path_tracer.sample_count(),
+                ));
+                // The title of the rasterized view is set again once that is back.
+                rcx.draw_stats = DrawStats::default();
+            }
+            Some(main_view_proj)
+        } else {
+            rcx.scene_pipeline.set_lod_debug(self.settings.lod_debug);
+            rcx.terrain_pipeline
+                .set_tessellation(self.settings.tessellation);
+            let mut draw_stats = None;
+            for (eye_camera, view_proj, viewport) in &eyes {
+                let viewport = &scale_viewport(viewport, extent, scene_extent);
+                let eye_stats =
+                    debug_utils::labeled(&mut builder, "scene", |builder| match &culled {
+                        Some(culled) => rcx.scene_pipeline.draw_culled(
+                            builder,
+                            culled,
+                            &self.scene,
+                            *view_proj,
+                            viewport.clone(),
+                        ),
+                        None => rcx.scene_pipeline.draw(
+                            builder,
+                            &self.scene,
+                            *view_proj,
+                            viewport.clone(),
+                            occlusion_culling.then_some(&rcx.occlusion_culler),
+                        ),
+                    });
+                // Both eyes see about the same, so the title counts what the first one drew.
+                draw_stats.get_or_insert(eye_stats);
+                if let Some(terrain) = &self.scene.terrain {
+                    debug_utils::labeled(&mut builder, "terrain", |builder| {
+                        rcx.terrain_pipeline.draw(
+                            builder,
+                            terrain,
+                            *view_proj,
+                            eye_camera.eye,
+                            &self.scene.light(),
+                            viewport.clone(),
+                        );
+                    });
+                }
+                if let Some(environment) = &self.environment {
+                    debug_utils::labeled(&mut builder, "skybox", |builder| {
+                        rcx.skybox_pipeline.draw(
+                            builder,
+                            &environment.cubemap.view,
+                            *view_proj,
+                            eye_camera.eye,
+                            viewport.clone(),
+                        );
+                    });
+                }
+                if self.settings.grid {
+                    let (_, far) = eye_camera.clip_planes(&bounds);
+                    debug_utils::labeled(&mut builder, "grid", |builder| {
+                        rcx.grid_pipeline.draw(
+                            builder,
+                            *view_proj,
+                            eye_camera.eye,
+                            far,
+                            viewport.clone(),
+                        );
+                    });
+                }
+                debug_utils::labeled(&mut builder, "particles", |builder| {
+                    rcx.particle_system
+                        .draw(builder, *view_proj, viewport.clone());
+                });
+            }
+            let draw_stats = draw_stats.unwrap_or_default();
+            let view_proj = main_view_proj;
+            if occlusion_culling {
+                let (near, _) = camera.clip_planes(&bounds);
+                debug_utils::labeled(&mut builder, "occlusion queries", |builder| {
+                    rcx.occlusion_culler.query(
+                        builder,
+                        &self.scene,
+                        view_proj,
+                        scene_viewport.clone(),
+                        camera.eye,
+                        near,
+                    );
+                });
+            }
+
+            let pending_loads = self.assets.pending_loads();
+            if (draw_stats != rcx.draw_stats || pending_loads != rcx.pending_loads)
+                && !self.console.is_open()
+            {
+                let title = if draw_stats.gpu_culled {
+                    format!("vulkano-test - {} culled on the GPU", draw_stats.drawn)
+                } else {
+                    let meshlets = if draw_stats.meshlets > 0 {
+                        format!(" in {} meshlets", draw_stats.meshlets)
+                    } else {
+                        String::new()
+                    };
+                    format!(
+                        "vulkano-test - {} drawn, {} culled, {} occluded - {} triangles, {} \
+                         vertices{}",
+                        draw_stats.drawn,
+                        draw_stats.culled,
+                        draw_stats.occluded,
+                        draw_stats.triangles,
+                        draw_stats.vertices,
+                        meshlets,
+                    )
+                };
+                let latency = match self
+                    .low_latency
+                    .as_ref()
+                    .and_then(LowLatency::average_latency)
+                {
+                    Some(latency) => format!(" - {:.1} ms latency", latency.as_secs_f64() * 1000.0),
+                    None => String::new(),
+                };
+                let memory = match self.memory_budget.as_ref().and_then(MemoryBudget::describe) {
+                    Some(memory) => format!(" - {memory}"),
+                    None => String::new(),
+                };
+                let time = match self.time.describe() {
+                    Some(time) => format!(" - {time}"),
+                    None => String::new(),
+                };
+                let loading = match pending_loads {
+                    0 => String::new(),
+                    1 => " - loading 1 file".to_owned(),
+                    count => format!(" - loading {count} files"),
+                };
+                rcx.window
+                    .set_title(&format!("{title}{latency}{memory}{time}{loading}"));
+                rcx.draw_stats = draw_stats;
+                rcx.pending_loads = pending_loads;
+            }
+            Some(view_proj)
+        };
+
+        if scene_target.is_some() || frame_debug {
+            // Finish the scene pass on its own, then continue on top of its result.
+            builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+            if let Some(target) = scene_target {
+                if let (Some(water), Some(view_proj)) = (&water, view_proj) {
+                    debug_utils::labeled(&mut builder, "water", |builder| {
+                        rcx.water_pass.capture(builder, target);
+                        target.continue_render_pass(builder, rcx.water_render_pass.clone());
+                        rcx.water_pass.draw(
+                            builder,
+                            water,
+                            view_proj,
+                            camera.eye,
+                            self.time.elapsed().as_secs_f32(),
+                            &self.scene.light(),
+                            clear_color,
+                            scene_viewport.clone(),
+                        );
+                        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+                    });
+                }
+                let mut image = target.color().clone();
+                if let (Some(fog), Some(view_proj)) = (&fog, view_proj) {
+                    image = debug_utils::labeled(&mut builder, "fog", |builder| {
+                        rcx.fog_pass.as_mut().unwrap().apply(
+                            builder,
+                            &image,
+                            target.depth().unwrap(),
+                            view_proj,
+                            camera.eye,
+                            &self.scene.light(),
+                            fog,
+                        )
+                    });
+                }
+                let image = if blur_radius > 0 {
+                    debug_utils::labeled(&mut builder, "blur", |builder| {
+                        rcx.blur_filter
+                            .as_mut()
+                            .unwrap()
+                            .apply(builder, &image, blur_radius)
+                    })
+                } else {
+                    image.image().clone()
+                };
+                // Filtered, in case the scene was drawn at another size.
+                builder
+                    .blit_image(BlitImageInfo {
+                        filter: blit_filter(&self.device, image.format())?,
+                        ..BlitImageInfo::images(image, swapchain_image.clone())
+                    })
+                    .unwrap();
+            }
+            if frame_debug {
+                after_passes = self.frame_debugger.submit_pass(
+                    &self.queue,
+                    builder,
+                    after_passes,
+                    &swapchain_image,
+                    "scene",
+                );
+                builder = AutoCommandBufferBuilder::primary(
+                    self.command_buffer_allocator.clone(),
+                    self.queue.queue_family_index(),
+                    CommandBufferUsage::OneTimeSubmit,
+                )
+                .unwrap();
+            }
+            begin_overlay_pass(&mut builder, rcx.overlay_render_pass.clone(), framebuffer);
+        }
+
+        debug_utils::labeled(&mut builder, "selection outline", |builder| {
+            rcx.selection_outline.draw(builder, rcx.viewport.clone());
+        });
+        match view_proj.filter(|_| self.settings.debug_draw) {
+            // Whenever there is a main view, its eyes are seen through its view and projection, or
+            // the stereo pair's.
+            Some(view_proj) => {
+                if self.settings.show_bounds {
+                    let mut query = self.scene.world.query::<(&Transform, &MeshHandle)>();
+                    for (transform, mesh) in query.iter() {
+                        let Some(aabb) = mesh.aabb(transform) else {
+                            continue;
+                        };
+                        self.debug_draw
+                            .wire_box(aabb.min, aabb.max, Vec4::new(0.0, 1.0, 0.0, 1.0));
+                    }
+                    let chunks = self.scene.terrain.iter().flat_map(Terrain::chunk_bounds);
+                    for aabb in chunks {
+                        self.debug_draw
+                            .wire_box(aabb.min, aabb.max, Vec4::new(0.0, 0.6, 1.0, 1.0));
+                    }
+                }
+                if let Some(aabb) = self.selected_object.and_then(|e| self.scene.aabb(e)) {
+                    self.debug_draw
+                        .wire_box(aabb.min, aabb.max, Vec4::new(1.0, 1.0, 0.0, 1.0));
+                }
+                if let Some(entity) = self.selected_object
+                    && let Ok(transform) = self.scene.world.get::<&Transform>(entity)
+                {
+                    self.gizmo
+                        .draw(&mut self.debug_draw, transform.0, view_proj);
+                }
+                self.debug_draw.axes(Mat4::IDENTITY, 1.0);
+                debug_utils::labeled(&mut builder, "debug draw", |builder| {
+                    rcx.debug_draw_pipeline.draw(
+                        builder,
+                        &mut self.debug_draw,
+                        &views,
+                        rcx.scale_factor as f32,
+                    );
+                    // The slider is in normalized device coordinates of the whole window.
+                    self.time.draw_slider(&mut self.debug_draw);
+                    rcx.debug_draw_pipeline.draw(
+                        builder,
+                        &mut self.debug_draw,
+                        &[(Mat4::IDENTITY, rcx.viewport.clone())],
+                        rcx.scale_factor as f32,
+                    );
+                });
+            }
+            None => self.debug_draw.clear(),
+        }
+        if self.console.is_open() && !self.fonts.is_empty() {
+            // The console's line in the top left corner, in normalized device coordinates.
+            let size = CONSOLE_TEXT_SIZE * rcx.scale_factor as f32;
+            let em = 2.0 * size / Vec2::from(rcx.viewport.extent);
+            let origin = Vec2::new(-1.0, -1.0) + em * Vec2::new(0.5, 1.25);
+            let text = self.fonts.shape(&self.console.title());
+            self.fonts
+                .draw(&mut self.debug_draw, &text, origin, em, Vec4::ONE);
+            debug_utils::labeled(&mut builder, "console", |builder| {
+                rcx.debug_draw_pipeline.draw(
+                    builder,
+                    &mut self.debug_draw,
+                    &[(Mat4::IDENTITY, rcx.viewport.clone())],
+                    rcx.scale_factor as f32,
+                );
+            });
+        }
+
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+        if let Some((path, scale)) = self.screenshot_capture.take_supersampled() {
+            // The rasterized main view alone is drawn again, at the screenshot's size times the
+            // scale, with its lights binned anew for that size.
+            let size = self.settings.supersampled_size.unwrap_or(extent);
+            let max_size = self
+                .device
+                .physical_device()
+                .properties()
+                .max_image_dimension2_d;
+            let scale = scale.min(max_size / size[0].max(size[1]).max(1));
+            if scale == 0 {
+                warn!("Screenshots can be at most {max_size} pixels across on this GPU");
+            } else if let Some((target, scale)) = supersampled_target(
+                &self.memory_allocator,
+                &rcx.scene_target_render_pass,
+                size,
+                scale,
+            ) {
+                let viewport = target.viewport();
+                let view_proj = camera.view_proj(size[0] as f32 / size[1] as f32, &bounds);
+                debug_utils::labeled(&mut builder, "supersampled screenshot", |builder| {
+                    let light_clusters = rcx.light_culler.cull(
+                        builder,
+                        &self.scene,
+                        &camera,
+                        &bounds,
+                        viewport.extent,
+                    );
+                    rcx.scene_pipeline.set_light_clusters(light_clusters);
+                    target.begin_render_pass(builder, &[ClearColorValue::Float(clear_value)]);
+                    rcx.scene_pipeline.draw(
+                        builder,
+                        &self.scene,
+                        view_proj,
+                        viewport.clone(),
+                        None,
+                    );
+                    if let Some(terrain) = &self.scene.terrain {
+                        rcx.terrain_pipeline.draw(
+                            builder,
+                            terrain,
+                            view_proj,
+                            camera.eye,
+                            &self.scene.light(),
+                            viewport.clone(),
+                        );
+                    }
+                    if let Some(environment) = &self.environment {
+                        rcx.skybox_pipeline.draw(
+                            builder,
+                            &environment.cubemap.view,
+                            view_proj,
+                            camera.eye,
+                            viewport.clone(),
+                        );
+                    }
+                    if self.settings.grid {
+                        let (_, far) = camera.clip_planes(&bounds);
+                        rcx.grid_pipeline.draw(
+                            builder,
+                            view_proj,
+                            camera.eye,
+                            far,
+                            viewport.clone(),
+                        );
+                    }
+                    rcx.particle_system
+                        .draw(builder, view_proj, viewport.clone());
+                    builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+                });
+                self.screenshot_capture.record_supersampled(
+                    &mut builder,
+                    target.color().image(),
+                    path,
+                    scale,
+                );
+            }
+        }
+        self.gif_capture
+            .record(&mut builder, &swapchain_image, self.settings.gif_seconds);
+        self.screenshot_capture
+            .record(&mut builder, &swapchain_image);
+        if self.screenshot_capture.is_pending() || self.picker.is_pending() {
+            // Another frame is needed to find that the GPU finished the copy.
+            rcx.window.request_redraw();
+        }
+        #[cfg(feature = "video")]
+        if let Some(recorder) = &mut self.video_recorder {
+            recorder.record(&mut builder, &swapchain_image);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.end_commands(&mut builder);
+        }
+        drop(record_span);
+
+        let _submit_span = debug_span!("submit").entered();
+        let after_passes = if frame_debug {
+            self.frame_debugger.submit_pass(
+                &self.queue,
+                builder,
+                after_passes,
+                &swapchain_image,
+                "debug-draw",
+            )
+        } else {
+            after_passes
+                .then_execute(self.queue.clone(), builder.build().unwrap())
+                .unwrap()
+                .boxed()
+        };
+
+        let future = after_passes
+            .then_swapchain_present(
+                self.queue.clone(),
+                SwapchainPresentInfo {
+                    present_id: self.low_latency.as_mut().map(LowLatency::present_id),
+                    present_mode: rcx.next_present_mode.take(),
+                    ..SwapchainPresentInfo::swapchain_image_index(
+                        rcx.swapchain.clone(),
+                        image_index,
+                    )
+                },
+            )
+            .then_signal_fence_and_flush();
+
+        match future.map_err(Validated::unwrap) {
+            Ok(future) => {
+                rcx.previous_frame_end = Some(future.boxed());
+            }
+            Err(VulkanError::OutOfDate) => {
+                rcx.recreate_swapchain = true;
+                rcx.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+            }
+            Err(VulkanError::DeviceLost) => return Err(AppError::DeviceLost),
+            Err(e) => {
+                error!("failed to flush future: {e}");
+                rcx.previous_frame_end = Some(sync::now(self.device.clone()).boxed());
+                if let Some(low_latency) = &mut self.low_latency {
+                    low_latency.forget_present();
+                }
+            }
+        }
+        self.assets.end_frame();
+        if let Some(metrics) = &mut self.metrics {
+            metrics.end_frame();
+        }
+
+        Ok(())
+    }
+
+    /// Quits because of an error that the app can't continue after, which `take_error` returns
+    /// once the event loop has exited.
+    fn fail(&mut self, event_loop: &ActiveEventLoop, err: AppError) {
+        self.error.get_or_insert(err);
+        event_loop.exit();
+    }
+
+    /// The error that made the app quit, if any.
+    pub fn take_error(&mut self) -> Option<AppError> {
+        self.error.take()
+    }
+}
+
+impl ApplicationHandler<AppEvent> for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        match self.create_render_context(event_loop) {
+            Ok(rcx) => self.rcx = Some(rcx),
+            Err(err) => self.fail(event_loop, err),
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        #[cfg(feature = "video")]
+        self.stop_recording();
+        if let Some(metrics) = &mut self.metrics {
+            // SAFETY: nothing else submits to the device while the app exits.
+            if let Err(err) = unsafe { self.device.wait_idle() } {
+                error!("Failed to wait for the device: {err}");
+            }
+            metrics.finish();
+        }
+
+        // Dropping the window should restore the video mode too, but not every platform does.
+        if let Some(rcx) = &self.rcx
+            && rcx.fullscreen_mode.is_some()
+        {
+            rcx.window.set_fullscreen(None);
+        }
+    }
+
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        // The surface can't outlive the window, so everything depending on it goes, after the
+        // frames in flight finish.
+        #[cfg(feature = "video")]
+        self.stop_recording();
+        self.rcx = None;
+        self.touch = None;
+    }
+
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: AppEvent) {
+        match event {
+            AppEvent::FileChanged(path) if path == self.settings_path => self.reload_settings(),
+            #[cfg(feature = "scripting")]
+            AppEvent::FileChanged(path) if self.scripts.is_script(&path) => {
+                self.scripts.reload(&path, &mut self.scene)
+            }
+            AppEvent::FileChanged(path) => self.assets.reload(&path),
+            AppEvent::AssetsLoaded => {
+                for err in self.assets.finish_loads() {
+                    error!("{err}");
+                }
+                if let Some(rcx) = &self.rcx {
+                    rcx.window.request_redraw();
+                }
+            }
+        }
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        _window_id: WindowId,
+        event: WindowEvent,
+    ) {
+        let Some(rcx) = self.rcx.as_mut() else {
+            return;
+        };
+
+        match event {
+            WindowEvent::CloseRequested => {
+                event_loop.exit();
+            }
+            WindowEvent::Resized(size) => {
+                rcx.resized_at =
+                    (<[u32; 2]>::from(size) != rcx.swapchain.image_extent()).then(Instant::now);
+                rcx.window.request_redraw();
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                // The new physical size arrives as a `Resized` of its own, unless the size
+                // winit suggests is kept as it is.
+                info!("Scale factor changed to {scale_factor}");
+                rcx.scale_factor = scale_factor;
+                rcx.recreate_swapchain = true;
+                rcx.window.request_redraw();
+            }
+            WindowEvent::Focused(focused) => {
+                if let Some(mode) = &rcx.fullscreen_mode {
+                    if focused {
+                        rcx.window
+                            .set_fullscreen(Some(Fullscreen::Exclusive(mode.clone())));
+                    } else {
+                        rcx.window.set_fullscreen(None);
+                        rcx.window.set_minimized(true);
+                    }
+                }
+                self.receive_input(InputEvent::Focused(focused));
+            }
+            WindowEvent::RedrawRequested => {
+                self.replay_input();
+                if let Err(err) = self.redraw() {
+                    self.fail(event_loop, err);
+                }
+                self.frames_drawn += 1;
+            }
+            event => {
+                if let Some(input) = InputEvent::from_window_event(&event) {
+                    self.receive_input(input);
+                }
+            }
+        }
+    }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        // A locked cursor doesn't move, so only the raw motion tells how far the mouse went.
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = event
+            && self.cursor_mode == CursorMode::Hidden
+        {
+            self.receive_input(InputEvent::MouseMotion { delta: [x, y] });
+        }
+    }
+
+    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+        // A replay moves the clock as it was recorded to, frame by frame, instead.
+        if self.input_replay.is_none() {
+            let elapsed = self
+                .time
+                .update(Instant::now(), self.timestep.step_duration());
+            if let Some(recorder) = &mut self.input_recorder
+                && !elapsed.is_zero()
+            {
+                recorder.record(&Recorded::Clock {
+                    frame: self.frames_drawn,
+                    elapsed,
+                });
+            }
+            self.advance_simulation(elapsed);
+        }
+
+        if self
+            .camera_playback
+            .as_ref()
+            .is_some_and(CameraPathPlayback::is_finished)
+        {
+            self.finish_playback();
+        }
+
+        // A camera that is gliding, flying or following a path needs frames to be seen moving.
+        if let Some(rcx) = &self.rcx
+            && (self.settings.redraw == RedrawPolicy::Continuous
+                || self.camera.is_moving()
+                || self.camera_playback.is_some())
+        {
+            rcx.window.request_redraw();
+        }
+    }
+}
+
+/// Begins `overlay_render_pass` on `framebuffer`, keeping what the swapchain image holds.
+fn begin_overlay_pass<L>(
+    builder: &mut AutoCommandBufferBuilder<L>,
+    overlay_render_pass: Arc<RenderPass>,
+    framebuffer: Arc<Framebuffer>,
+) {
+    builder
+        .begin_render_pass(
+            RenderPassBeginInfo {
+                render_pass: overlay_render_pass,
+                clear_values: vec![None, None],
+                ..RenderPassBeginInfo::framebuffer(framebuffer)
+            },
+            SubpassBeginInfo {
+                contents: SubpassContents::Inline,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+}
+
+/// Loads the scene of `source` into `assets`, along with the path that it is saved to.
+fn load_scene(assets: &mut Assets, source: SceneSource) -> Result<(Scene, PathBuf), AppError> {
+    match source {
+        SceneSource::Demo => Ok((Scene::demo(assets), "scene.ron".into())),
+        SceneSource::Gltf(path) => {
+            let scene = Scene::load_gltf(assets, &path).map_err(|err| AppError::Scene {
+                path: path.clone(),
+                source: err.into(),
+            })?;
+            Ok((scene, "scene.ron".into()))
+        }
+        SceneSource::Ron(path) => {
+            let scene = scene_file::load(assets, &path).map_err(|err| AppError::Scene {
+                path: path.clone(),
+                source: err.into(),
+            })?;
+            Ok((scene, path))
+        }
+    }
+}
+
+/// A path in the working directory named after the current time, such as `recording-1712345678.mp4`
+/// for `prefix` "recording" and `extension` "mp4".
+pub(crate) fn timestamped_path(prefix: &str, extension: &str) -> PathBuf {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    PathBuf::from(format!("{prefix}-{seconds}.{extension}"))
+}
+
+/// `viewport`, a part of an image of `extent`, as the same part of an image of `scaled_extent`.
+fn scale_viewport(viewport: &Viewport, extent: [u32; 2], scaled_extent: [u32; 2]) -> Viewport {
+    let scale = [0, 1].map(|axis| scaled_extent[axis] as f32 / extent[axis] as f32);
+    Viewport {
+        offset: [0, 1].map(|axis| viewport.offset[axis] * scale[axis]),
+        extent: [0, 1].map(|axis| viewport.extent[axis] * scale[axis]),
+        depth_range: viewport.depth_range.clone(),
+    }
+}
+
+/// Linear filtering for blits from images of `format` where the device supports it, so that
+/// scaled blits are smooth.
+fn blit_filter(device: &Device, format: Format) -> Result<Filter, AppError> {
+    let features = device
+        .physical_device()
+        .format_properties(format)
+        .map_err(|source| AppError::FormatProperties { format, source })?
+        .optimal_tiling_features;
+    if features.intersects(FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR) {
+        Ok(Filter::Linear)
+    } else {
+        Ok(Filter::Nearest)
+    }
+}
+
+/// Warns about the keys of `keybindings` that don't do what they are bound to.
+fn warn_keybinding_conflicts(keybindings: &Keybindings) {
+    for conflict in keybindings.conflicts() {
+        warn!("Keybinding conflict: {conflict}");
+    }
+}
+
+/// The icon named by `settings`, or the built-in one if there is none or it can't be read.
+fn window_icon(settings: &RenderSettings) -> Icon {
+    let Some(path) = &settings.window_icon else {
+        return icon::default_icon();
+    };
+    icon::load(path).unwrap_or_else(|err| {
+        warn!("Failed to load {}: {err}", path.display());
+        icon::default_icon()
+    })
+}
+
+/// Picks how the swapchain's images are composited with what is behind the window. A transparent
+/// window needs the alpha channel to be taken into account, which not every surface supports.
+fn composite_alpha(supported: CompositeAlphas, transparent: bool) -> CompositeAlpha {
+    let preferred: &[CompositeAlpha] = if transparent {
+        &[
+            CompositeAlpha::PreMultiplied,
+            CompositeAlpha::PostMultiplied,
+            CompositeAlpha::Inherit,
+        ]
+    } else {
+        &[CompositeAlpha::Opaque]
+    };
+
+    preferred
+        .iter()
+        .copied()
+        .find(|&composite_alpha| supported.contains_enum(composite_alpha))
+        .or_else(|| supported.into_iter().next())
+        // Every surface supports one, so this only stands in for creating the swapchain to fail.
+        .unwrap_or(CompositeAlpha::Opaque)
+}
+
+/// Clamps the `requested` swapchain image count to what the surface supports, explaining why in
+/// the log where it has to. Zero asks for the fewest images the surface supports, but at least two,
+/// so that one can be drawn into while the other is shown.
+fn image_count(requested: u32, capabilities: &SurfaceCapabilities) -> u32 {
+    let min = capabilities.min_image_count;
+    let max = capabilities.max_image_count.unwrap_or(u32::MAX);
+    if requested == 0 {
+        return min.max(2).min(max);
+    }
+
+    let count = requested.clamp(min, max);
+    if count < requested {
+        warn!(
+            "Asked for {requested} swapchain images, but the surface supports at most {max}, \
+             so {count} are used"
+        );
+    } else if count > requested {
+        warn!(
+            "Asked for {requested} swapchain images, but the surface needs at least {min}, \
+             so {count} are used"
+        );
+    }
+    count
+}
+
+/// Lets `create_info` switch between the present modes of both vsync settings without being
+/// recreated, where they are compatible, and scales its images to a window of another size as
+/// `scaling` asks. Without `VK_EXT_swapchain_maintenance1`, `create_info` is left as it is.
+fn with_maintenance(
+    device: &Device,
+    surface: &Surface,
+    scaling: settings::PresentScaling,
+    mut create_info: SwapchainCreateInfo,
+) -> SwapchainCreateInfo {
+    if !Capabilities::of(device).swapchain_maintenance {
+        return create_info;
+    }
+    let physical_device = device.physical_device();
+    let capabilities = |present_mode| {
+        physical_device.surface_capabilities(
+            surface,
+            SurfaceInfo {
+                present_mode: Some(present_mode),
+                ..Default::default()
+            },
+        )
+    };
+    let Ok(surface_capabilities) = capabilities(create_info.present_mode) else {
+        return create_info;
+    };
+
+    // The present mode of the other vsync setting.
+    let other_mode = present_mode(
+        device,
+        create_info.present_mode != PresentMode::Fifo,
+        surface,
+    );
+    if other_mode != create_info.present_mode
+        && surface_capabilities
+            .compatible_present_modes
+            .contains(&other_mode)
+    {
+        create_info.present_modes = [create_info.present_mode, other_mode].into_iter().collect();
+    }
+
+    // The scaling has to be supported with every present mode that the swapchain can switch to.
+    let mut supported_scaling = surface_capabilities.supported_present_scaling;
+    let mut supported_gravity = surface_capabilities.supported_present_gravity;
+    for &mode in create_info.present_modes.iter().skip(1) {
+        let Ok(mode_capabilities) = capabilities(mode) else {
+            create_info.present_modes.clear();
+            break;
+        };
+        supported_scaling &= mode_capabilities.supported_present_scaling;
+        supported_gravity[0] &= mode_capabilities.supported_present_gravity[0];
+        supported_gravity[1] &= mode_capabilities.supported_present_gravity[1];
+    }
+
+    let scaling_behavior = match scaling {
+        settings::PresentScaling::Stretch => PresentScaling::Stretch,
+        settings::PresentScaling::AspectRatio => PresentScaling::AspectRatioStretch,
+        settings::PresentScaling::OneToOne => PresentScaling::OneToOne,
+    };
+    if !supported_scaling.contains_enum(scaling_behavior) {
+        return create_info;
+    }
+    let fits = |extent: Option<[u32; 2]>, compare: fn(u32, u32) -> bool| {
+        extent.is_none_or(|extent| (0..2).all(|i| compare(create_info.image_extent[i], extent[i])))
+    };
+    if !fits(surface_capabilities.min_scaled_image_extent, |a, b| a >= b)
+        || !fits(surface_capabilities.max_scaled_image_extent, |a, b| a <= b)
+    {
+        return create_info;
+    }
+    create_info.scaling_behavior = Some(scaling_behavior);
+    if supported_gravity
+        .iter()
+        .all(|gravity| gravity.contains_enum(PresentGravity::Centered))
+    {
+        create_info.present_gravity = Some([PresentGravity::Centered; 2]);
+    }
+    create_info
+}
+
+/// Warns if the swapchain of `create_info` can't be scaled as `scaling` asks, although the device
+/// lets scaling be chosen.
+fn warn_unscaled(
+    device: &Device,
+    create_info: &SwapchainCreateInfo,
+    scaling: settings::PresentScaling,
+) {
+    if Capabilities::of(device).swapchain_maintenance && create_info.scaling_behavior.is_none() {
+        warn!("The window surface doesn't support {scaling:?} scaling at this size");
+    }
+}
+
+/// Picks the present mode for the `vsync` setting among those the surface supports.
+fn present_mode(device: &Device, vsync: bool, surface: &Surface) -> PresentMode {
+    if vsync {
+        // FIFO is the only mode that is guaranteed to be supported.
+        return PresentMode::Fifo;
+    }
+
+    let supported = match device
+        .physical_device()
+        .surface_present_modes(surface, Default::default())
+    {
+        Ok(supported) => supported,
+        Err(err) => {
+            warn!("Failed to query the present modes of the window surface, using vsync: {err}");
+            return PresentMode::Fifo;
+        }
+    };
+    [PresentMode::Mailbox, PresentMode::Immediate]
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Splits `viewport` into a roughly square grid of `count` cells, filled row by row.
+fn grid_viewports(viewport: &Viewport, count: usize) -> Vec<Viewport> {
+    let columns = (count as f32).sqrt().ceil() as usize;
+    let rows = count.div_ceil(columns);
+    let [width, height] = viewport.extent;
+    let cell_extent = [width / columns as f32, height / rows as f32];
+
+    (0..count)
+        .map(|i| Viewport {
+            offset: [
+                viewport.offset[0] + (i % columns) as f32 * cell_extent[0],
+                viewport.offset[1] + (i / columns) as f32 * cell_extent[1],
+            ],
+            extent: cell_extent,
+            depth_range: viewport.depth_range.clone(),
+        })
+        .collect()
+}
+
+/// Creates the target of a supersampled screenshot of `size` at `scale`, or at half the scale
+/// each time the last one is too large for the device's memory, with the scale it is at. There is
+/// none if even the screenshot's own size is too large.
+fn supersampled_target(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    render_pass: &Arc<RenderPass>,
+    size: [u32; 2],
+    mut scale: u32,
+) -> Option<(OffscreenTarget, u32)> {
+    loop {
+        match OffscreenTarget::new(
+            memory_allocator.clone(),
+            render_pass.clone(),
+            size.map(|side| side * scale),
+        ) {
+            Ok(target) => return Some((target, scale)),
+            Err(err) if scale > 1 => {
+                warn!(
+                    "Failed to render the screenshot at {scale}x, trying {}x: {err}",
+                    scale / 2
+                );
+                scale /= 2;
+            }
+            Err(err) => {
+                warn!("Failed to render the screenshot: {err}");
+                return None;
+            }
+        }
+    }
+}
+
+/// This function is called once during initialization, then again whenever the window is resized.
+fn window_size_dependent_setup(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    images: &[Arc<Image>],
+    render_pass: &Arc<RenderPass>,
+) -> Result<Vec<Arc<Framebuffer>>, AppError> {
+    let depth_buffer = ImageView::new_default(
+        Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::D16_UNORM,
+                extent: images[0].extent(),
+                usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .map_err(|err| AppError::Image {
+            name: "depth buffer",
+            source: err,
+        })?,
+    )
+    .map_err(|source| AppError::ImageView {
+        name: "depth buffer",
+        source,
+    })?;
+    debug_utils::name(depth_buffer.image(), "depth buffer");
+
+    images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            debug_utils::name(image, &format!("swapchain image {i}"));
+            let view =
+                ImageView::new_default(image.clone()).map_err(|source| AppError::ImageView {
+                    name: "swapchain image",
+                    source,
+                })?;
+
+            let framebuffer = Framebuffer::new(
+                render_pass.clone(),
+                FramebufferCreateInfo {
+                    attachments: vec![view, depth_buffer.clone()],
+                    ..Default::default()
+                },
+            )
+            .map_err(|source| AppError::Framebuffer {
+                name: "swapchain image",
+                source,
+            })?;
+            debug_utils::name(&framebuffer, &format!("framebuffer {i}"));
+            Ok(framebuffer)
+        })
+        .collect()
+}
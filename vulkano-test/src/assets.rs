@@ -0,0 +1,668 @@
+// Shared GPU assets. Every mesh and texture is loaded once per source, however many entities use
+// it, and handed out as a reference-counted `Handle`. Once the last handle to an asset is dropped,
+// the asset is retired rather than destroyed: it stays alive until every frame that might still be
+// rendering with it has finished, then it is freed at the end of a later frame.
+//
+// Submitted command buffers keep the buffers and images they use alive as well, so retiring is
+// mainly about freeing memory at a predictable point instead of wherever the last handle happened
+// to be dropped.
+//
+// Files are read and decoded on a pool of worker threads, so that opening a large scene doesn't
+// block the event loop. Handles are returned right away: meshes are missing until they are
+// loaded, and textures show a checkerboard. The decoded data is uploaded on the thread that owns
+// the `Assets` once it calls `finish_loads`. Files that change on disk can be loaded again with
+// `reload`, which swaps the new data in behind the existing handles and retires the old.
+//
+// With texture streaming enabled, textures are handed to a `TextureStreamer` instead, mip chain
+// and all, which decodes the chain on the worker as well. Only their coarsest levels are uploaded
+// at first, and `stream_textures` swaps more or less detailed ones in as the view needs them.
+//
+// Textures in `.ktx2` and `.dds` files are uploaded in the format they are stored in, which is
+// usually block-compressed, along with the mip levels they come with, if the device can sample
+// that format. HDR images in `.hdr` and `.exr` files become half float textures, or tonemapped
+// sRGB ones where the device can't sample those. Neither kind is streamed.
+
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    hash::Hash,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, RwLock},
+    thread,
+};
+use tracing::warn;
+use vulkano::{
+    command_buffer::allocator::StandardCommandBufferAllocator, device::Queue, format::Format,
+    memory::allocator::StandardMemoryAllocator,
+};
+
+use crate::{
+    dds::{self, DdsError},
+    hdr::{self, HdrError},
+    ktx2::{self, Ktx2Error, TranscodeTarget},
+    mesh::{Mesh, MeshData},
+    scene,
+    texture::{self, Texture, TextureData},
+    texture_streaming::TextureStreamer,
+};
+
+/// Something `Assets` can hold, along with what identifies where it was loaded from.
+pub trait Asset: Send + Sync + 'static {
+    type Key: Clone + Eq + Hash + fmt::Debug + Send + Sync;
+}
+
+impl Asset for Mesh {
+    type Key = MeshSource;
+}
+
+impl Asset for Texture {
+    type Key = PathBuf;
+}
+
+/// Where a mesh comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MeshSource {
+    /// The unit cube of `Mesh::cube`.
+    Cube,
+    /// A triangle primitive of a mesh in a glTF file.
+    Gltf {
+        path: PathBuf,
+        mesh: usize,
+        primitive: usize,
+    },
+}
+
+struct Slot<T: Asset> {
+    key: T::Key,
+    /// `None` while the asset is loading, for assets without a placeholder.
+    asset: RwLock<Option<Arc<T>>>,
+}
+
+/// A reference to a loaded asset. Cloning a handle is cheap, and two handles are equal if they
+/// refer to the same asset.
+pub struct Handle<T: Asset> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T: Asset> Handle<T> {
+    /// What the asset was loaded from.
+    pub fn key(&self) -> &T::Key {
+        &self.slot.key
+    }
+
+    /// The asset itself, or `None` if it hasn't been loaded yet.
+    pub fn get(&self) -> Option<Arc<T>> {
+        self.slot.asset.read().unwrap().clone()
+    }
+}
+
+impl<T: Asset> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T: Asset> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.slot, &other.slot)
+    }
+}
+
+impl<T: Asset> Eq for Handle<T> {}
+
+impl<T: Asset> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Handle").field(&self.slot.key).finish()
+    }
+}
+
+/// The loaded assets of one type, by key.
+struct AssetStore<T: Asset> {
+    slots: HashMap<T::Key, Arc<Slot<T>>>,
+}
+
+impl<T: Asset> AssetStore<T> {
+    fn new() -> Self {
+        AssetStore {
+            slots: HashMap::new(),
+        }
+    }
+
+    fn get(&self, key: &T::Key) -> Option<Handle<T>> {
+        self.slots
+            .get(key)
+            .map(|slot| Handle { slot: slot.clone() })
+    }
+
+    fn insert(&mut self, key: T::Key, asset: Option<Arc<T>>) -> Handle<T> {
+        let slot = Arc::new(Slot {
+            key: key.clone(),
+            asset: RwLock::new(asset),
+        });
+        self.slots.insert(key, slot.clone());
+
+        Handle { slot }
+    }
+
+    /// Removes the assets that no handle refers to anymore, adding them to `retired`.
+    fn retire_unused(&mut self, retired: &mut Vec<Arc<dyn Any + Send + Sync>>) {
+        self.slots.retain(|_, slot| {
+            // The store's own reference is the only one left.
+            if Arc::strong_count(slot) > 1 {
+                return true;
+            }
+            if let Some(asset) = slot.asset.write().unwrap().take() {
+                retired.push(asset);
+            }
+            false
+        });
+    }
+}
+
+#[derive(Debug)]
+pub enum AssetError {
+    Image(PathBuf, image::ImageError),
+    Ktx2(PathBuf, Ktx2Error),
+    Dds(PathBuf, DdsError),
+    Hdr(PathBuf, HdrError),
+    /// A texture is in a format the device can't sample.
+    UnsupportedFormat(PathBuf, Format),
+    /// A material refers to a cubemap, which only environment maps can be.
+    Cubemap(PathBuf),
+    Gltf(PathBuf, gltf::Error),
+    /// A glTF file doesn't have the triangle primitive a `MeshSource` refers to.
+    MissingMesh(MeshSource),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::Image(path, err) => write!(f, "failed to load {}: {err}", path.display()),
+            AssetError::Ktx2(path, err) => write!(f, "failed to load {}: {err}", path.display()),
+            AssetError::Dds(path, err) => write!(f, "failed to load {}: {err}", path.display()),
+            AssetError::Hdr(path, err) => write!(f, "failed to load {}: {err}", path.display()),
+            AssetError::UnsupportedFormat(path, format) => write!(
+                f,
+                "failed to load {}: the device can't sample {format:?} textures",
+                path.display(),
+            ),
+            AssetError::Cubemap(path) => write!(
+                f,
+                "failed to load {}: cubemaps can't be material textures",
+                path.display(),
+            ),
+            AssetError::Gltf(path, err) => write!(f, "failed to load {}: {err}", path.display()),
+            AssetError::MissingMesh(MeshSource::Gltf {
+                path,
+                mesh,
+                primitive,
+            }) => write!(
+                f,
+                "{} has no triangle primitive {primitive} in mesh {mesh}",
+                path.display(),
+            ),
+            AssetError::MissingMesh(source) => write!(f, "mesh {source:?} is missing"),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+/// A file decoded by a worker thread, ready to be uploaded.
+enum Loaded {
+    /// The image of a texture, followed by the rest of its mip chain if textures are streamed.
+    Texture(PathBuf, Result<Vec<RgbaImage>, image::ImageError>),
+    /// A texture from a KTX2 or DDS file, in the format it is stored in, or from an HDR image.
+    TextureData(PathBuf, Result<TextureData, AssetError>),
+    Gltf(PathBuf, Result<Vec<(MeshSource, MeshData)>, gltf::Error>),
+}
+
+type LoadJob = Box<dyn FnOnce() -> Loaded + Send>;
+
+/// Threads that run load jobs, sending back what they decoded.
+struct WorkerPool {
+    jobs: mpsc::Sender<LoadJob>,
+    results: mpsc::Receiver<Loaded>,
+}
+
+impl WorkerPool {
+    fn new(on_loaded: Arc<dyn Fn() + Send + Sync>) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<LoadJob>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        // Leave a core for the thread that renders.
+        let worker_count = thread::available_parallelism().map_or(1, |n| n.get().max(2) - 1);
+        for i in 0..worker_count {
+            let job_receiver = job_receiver.clone();
+            let result_sender = result_sender.clone();
+            let on_loaded = on_loaded.clone();
+            thread::Builder::new()
+                .name(format!("asset loader {i}"))
+                .spawn(move || {
+                    // Workers exit once the pool, and with it the job sender, is dropped.
+                    loop {
+                        let job = job_receiver.lock().unwrap().recv();
+                        let Ok(job) = job else {
+                            break;
+                        };
+                        if result_sender.send(job()).is_err() {
+                            break;
+                        }
+                        on_loaded();
+                    }
+                })
+                .unwrap();
+        }
+
+        WorkerPool { jobs, results }
+    }
+
+    fn spawn(&self, job: impl FnOnce() -> Loaded + Send + 'static) {
+        self.jobs.send(Box::new(job)).unwrap();
+    }
+}
+
+pub struct Assets {
+    pub memory_allocator: Arc<StandardMemoryAllocator>,
+    pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    pub queue: Arc<Queue>,
+    meshes: AssetStore<Mesh>,
+    textures: AssetStore<Texture>,
+    /// Shown in place of textures that are still loading, or failed to.
+    placeholder_texture: Arc<Texture>,
+    /// Bound for materials without a texture.
+    white_texture: Arc<Texture>,
+    /// Where textures are streamed, what streams them.
+    streamer: Option<TextureStreamer>,
+    workers: WorkerPool,
+    /// The number of jobs sent to the workers whose results haven't been handled yet.
+    pending_loads: usize,
+    /// The glTF files being decoded.
+    loading_gltf: HashSet<PathBuf>,
+    /// How many frames may still be using an asset after the one it was released in.
+    frames_in_flight: u64,
+    frame: u64,
+    /// Assets replaced by a reload during this frame, retired along with the released ones.
+    replaced: Vec<Arc<dyn Any + Send + Sync>>,
+    /// Released assets waiting to be destroyed, with the frame they were released in.
+    retired: VecDeque<(u64, Vec<Arc<dyn Any + Send + Sync>>)>,
+}
+
+impl Assets {
+    /// Creates an empty set of assets. `on_loaded` is called from a worker thread whenever a load
+    /// finishes, to have `finish_loads` called soon after. Released assets are destroyed at the
+    /// next `end_frame` until `set_frames_in_flight` says otherwise.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: Arc<Queue>,
+        on_loaded: impl Fn() + Send + Sync + 'static,
+    ) -> Self {
+        let placeholder_texture = Texture::from_rgba(
+            memory_allocator.clone(),
+            command_buffer_allocator.clone(),
+            &queue,
+            &texture::checkerboard(64, 8),
+        );
+        let white_texture = Texture::white(
+            memory_allocator.clone(),
+            command_buffer_allocator.clone(),
+            &queue,
+        );
+
+        Assets {
+            memory_allocator,
+            command_buffer_allocator,
+            queue,
+            meshes: AssetStore::new(),
+            textures: AssetStore::new(),
+            placeholder_texture,
+            white_texture,
+            streamer: None,
+            workers: WorkerPool::new(Arc::new(on_loaded)),
+            pending_loads: 0,
+            loading_gltf: HashSet::new(),
+            frames_in_flight: 0,
+            frame: 0,
+            replaced: Vec::new(),
+            retired: VecDeque::new(),
+        }
+    }
+
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: usize) {
+        self.frames_in_flight = frames_in_flight as u64;
+    }
+
+    /// Streams the textures loaded from now on, rather than uploading them whole. See
+    /// `texture_streaming.rs`.
+    pub fn enable_texture_streaming(&mut self) {
+        self.streamer = Some(TextureStreamer::new(
+            self.memory_allocator.clone(),
+            self.command_buffer_allocator.clone(),
+            self.queue.clone(),
+        ));
+    }
+
+    pub fn is_streaming_textures(&self) -> bool {
+        self.streamer.is_some()
+    }
+
+    /// Streams the detail of the textures in and out as `needs` says, as `texture_needs` tells
+    /// them, swapping the new textures in behind their handles. Does nothing unless textures are
+    /// streamed.
+    pub fn stream_textures(&mut self, needs: &HashMap<PathBuf, f32>) {
+        let Some(streamer) = &mut self.streamer else {
+            return;
+        };
+        for (path, texture) in streamer.update(needs, self.frame) {
+            if let Some(slot) = self.textures.slots.get(&path)
+                && let Some(old) = slot.asset.write().unwrap().replace(texture)
+            {
+                self.replaced.push(old);
+            }
+        }
+    }
+
+    /// The texture bound for materials without one of their own.
+    pub fn white_texture(&self) -> &Arc<Texture> {
+        &self.white_texture
+    }
+
+    /// The mesh loaded from `source`, starting to load it if it isn't already. Loading one
+    /// primitive of a glTF file decodes the whole file, so primitives requested after the file
+    /// finished loading decode it again.
+    pub fn mesh(&mut self, source: &MeshSource) -> Handle<Mesh> {
+        let source = mesh_key(source);
+        if let Some(handle) = self.meshes.get(&source) {
+            return handle;
+        }
+
+        match &source {
+            MeshSource::Cube => {
+                let mesh = Mesh::cube(self.memory_allocator.clone());
+                self.meshes.insert(source, Some(mesh))
+            }
+            MeshSource::Gltf { path, .. } => {
+                if !self.loading_gltf.contains(path) {
+                    self.load_gltf(path.clone());
+                }
+                self.meshes.insert(source, None)
+            }
+        }
+    }
+
+    fn load_gltf(&mut self, path: PathBuf) {
+        self.loading_gltf.insert(path.clone());
+        self.pending_loads += 1;
+        self.workers.spawn(move || {
+            let result = scene::decode_gltf_meshes(&path);
+            Loaded::Gltf(path, result)
+        });
+    }
+
+    /// The texture loaded from the image at `path`, starting to load it if it isn't already.
+    pub fn texture(&mut self, path: &Path) -> Handle<Texture> {
+        let path = asset_path(path);
+        if let Some(handle) = self.textures.get(&path) {
+            return handle;
+        }
+
+        self.load_texture(path.clone());
+        self.textures
+            .insert(path, Some(self.placeholder_texture.clone()))
+    }
+
+    fn load_texture(&mut self, path: PathBuf) {
+        self.pending_loads += 1;
+        if ktx2::is_ktx2(&path) {
+            let transcode_target = TranscodeTarget::for_device(self.queue.device());
+            self.workers.spawn(move || {
+                let result = ktx2::open(&path, transcode_target)
+                    .map_err(|err| AssetError::Ktx2(path.clone(), err));
+                Loaded::TextureData(path, result)
+            });
+            return;
+        }
+        if hdr::is_hdr(&path) {
+            let float = hdr::can_sample_float(self.queue.device());
+            if !float {
+                warn!(
+                    "{} is tonemapped to 8 bits, as the device can't sample half float textures",
+                    path.display(),
+                );
+            }
+            self.workers.spawn(move || {
+                let result = hdr::open(&path)
+                    .map(|image| hdr::texture_data(image, float))
+                    .map_err(|err| AssetError::Hdr(path.clone(), err));
+                Loaded::TextureData(path, result)
+            });
+            return;
+        }
+        if dds::is_dds(&path) {
+            self.workers.spawn(move || {
+                let result = dds::open(&path).map_err(|err| AssetError::Dds(path.clone(), err));
+                Loaded::TextureData(path, result)
+            });
+            return;
+        }
+        let streamed = self.streamer.is_some();
+        self.workers.spawn(move || {
+            let result = image::open(&path).map(|image| {
+                let pixels = image.into_rgba8();
+                if streamed {
+                    texture::mip_chain(pixels)
+                } else {
+                    vec![pixels]
+                }
+            });
+            Loaded::Texture(path, result)
+        });
+    }
+
+    /// The files that the loaded assets came from.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<_> = self.textures.slots.keys().cloned().collect();
+        for source in self.meshes.slots.keys() {
+            if let MeshSource::Gltf { path, .. } = source
+                && !files.contains(path)
+            {
+                files.push(path.clone());
+            }
+        }
+
+        files
+    }
+
+    /// Loads the assets that came from the file at `path` again, after it changed on disk. The
+    /// assets keep their current data until the new one is uploaded by `finish_loads`, and keep
+    /// it if the file fails to load. Only the meshes of a glTF file are reloaded, not its
+    /// materials or node tree.
+    pub fn reload(&mut self, path: &Path) {
+        let path = asset_path(path);
+        if self.textures.slots.contains_key(&path) {
+            self.load_texture(path.clone());
+        }
+        if self
+            .meshes
+            .slots
+            .keys()
+            .any(|source| matches!(source, MeshSource::Gltf { path: p, .. } if *p == path))
+        {
+            self.load_gltf(path);
+        }
+    }
+
+    /// How many of the loads that were started haven't finished.
+    pub fn pending_loads(&self) -> usize {
+        self.pending_loads
+    }
+
+    /// Uploads whatever the workers finished decoding, without waiting for the rest. Returns the
+    /// loads that failed.
+    pub fn finish_loads(&mut self) -> Vec<AssetError> {
+        let mut errors = Vec::new();
+        while let Ok(loaded) = self.workers.results.try_recv() {
+            self.upload(loaded, &mut errors);
+        }
+
+        errors
+    }
+
+    /// Waits for every load that was started, and uploads the results. Returns the loads that
+    /// failed.
+    pub fn wait_for_loads(&mut self) -> Vec<AssetError> {
+        let mut errors = Vec::new();
+        while self.pending_loads > 0 {
+            let loaded = self.workers.results.recv().unwrap();
+            self.upload(loaded, &mut errors);
+        }
+
+        errors
+    }
+
+    fn upload(&mut self, loaded: Loaded, errors: &mut Vec<AssetError>) {
+        self.pending_loads -= 1;
+
+        match loaded {
+            Loaded::Texture(path, Ok(mut mips)) => {
+                // Nothing is waiting for the texture anymore if it was retired in the meantime.
+                if let Some(slot) = self.textures.slots.get(&path) {
+                    let texture = match &mut self.streamer {
+                        Some(streamer) => {
+                            // Loads started before streaming was enabled come without a chain.
+                            if mips.len() == 1 {
+                                mips = texture::mip_chain(mips.swap_remove(0));
+                            }
+                            streamer.insert(path.clone(), mips, self.frame)
+                        }
+                        None => Texture::from_rgba(
+                            self.memory_allocator.clone(),
+                            self.command_buffer_allocator.clone(),
+                            &self.queue,
+                            &mips.swap_remove(0),
+                        ),
+                    };
+                    if texture.downscaled > 0 {
+                        let [width, height, _] = texture.image.extent();
+                        warn!(
+                            "{} only fit in memory at {width}x{height}, halved {} times",
+                            path.display(),
+                            texture.downscaled,
+                        );
+                    }
+                    if let Some(old) = slot.asset.write().unwrap().replace(texture) {
+                        self.replaced.push(old);
+                    }
+                }
+            }
+            Loaded::Texture(path, Err(err)) => errors.push(AssetError::Image(path, err)),
+            Loaded::TextureData(path, Ok(data)) => {
+                let Some(slot) = self.textures.slots.get(&path) else {
+                    return;
+                };
+                if !texture::can_sample(self.queue.device(), data.format) {
+                    errors.push(AssetError::UnsupportedFormat(path, data.format));
+                    return;
+                }
+                if data.cube {
+                    errors.push(AssetError::Cubemap(path));
+                    return;
+                }
+                let texture = Texture::from_data(
+                    self.memory_allocator.clone(),
+                    self.command_buffer_allocator.clone(),
+                    &self.queue,
+                    &data,
+                );
+                if let Some(old) = slot.asset.write().unwrap().replace(texture) {
+                    self.replaced.push(old);
+                }
+            }
+            Loaded::TextureData(_, Err(err)) => errors.push(err),
+            Loaded::Gltf(path, result) => {
+                self.loading_gltf.remove(&path);
+                let primitives = match result {
+                    Ok(primitives) => primitives,
+                    Err(err) => {
+                        errors.push(AssetError::Gltf(path, err));
+                        return;
+                    }
+                };
+
+                for (source, data) in primitives {
+                    if let Some(slot) = self.meshes.slots.get(&source) {
+                        let mesh = Mesh::upload(self.memory_allocator.clone(), data);
+                        if let Some(old) = slot.asset.write().unwrap().replace(mesh) {
+                            self.replaced.push(old);
+                        }
+                    }
+                }
+
+                // Meshes requested from the file that it turned out not to have.
+                errors.extend(
+                    self.meshes
+                        .slots
+                        .iter()
+                        .filter(|(source, slot)| {
+                            matches!(source, MeshSource::Gltf { path: p, .. } if *p == path)
+                                && slot.asset.read().unwrap().is_none()
+                        })
+                        .map(|(source, _)| AssetError::MissingMesh(source.clone())),
+                );
+            }
+        }
+    }
+
+    /// Retires the assets that were released during this frame, and destroys those that no frame
+    /// in flight can be using anymore. Call this once the frame has been submitted.
+    pub fn end_frame(&mut self) {
+        let mut retired = std::mem::take(&mut self.replaced);
+        self.meshes.retire_unused(&mut retired);
+        self.textures.retire_unused(&mut retired);
+        if let Some(streamer) = &mut self.streamer {
+            let slots = &self.textures.slots;
+            streamer.end_frame(self.frame, self.frames_in_flight, |path| {
+                slots.contains_key(path)
+            });
+        }
+        if !retired.is_empty() {
+            self.retired.push_back((self.frame, retired));
+        }
+
+        while let Some(&(released, _)) = self.retired.front() {
+            if released + self.frames_in_flight > self.frame {
+                break;
+            }
+            self.retired.pop_front();
+        }
+        self.frame += 1;
+    }
+}
+
+fn mesh_key(source: &MeshSource) -> MeshSource {
+    match source {
+        MeshSource::Cube => MeshSource::Cube,
+        MeshSource::Gltf {
+            path,
+            mesh,
+            primitive,
+        } => MeshSource::Gltf {
+            path: asset_path(path),
+            mesh: *mesh,
+            primitive: *primitive,
+        },
+    }
+}
+
+/// The path that identifies the asset at `path`, so that different spellings of the same path
+/// share one asset.
+pub(crate) fn asset_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
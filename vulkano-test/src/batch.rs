@@ -0,0 +1,199 @@
+// Batch rendering for `vulkano-test render-batch jobs.toml`. The jobs file lists scenes to render
+// headlessly, each with its own camera, resolution and output image:
+//
+//     [[job]]
+//     scene = "models/box.gltf"
+//     output = "out/box.png"
+//     resolution = [800, 600]
+//     camera = { eye = [3.0, 2.0, 4.0], target = [0.0, 0.0, 0.0], fov_y = 45.0 }
+//
+// Everything but `output` is optional. Relative paths are resolved against the directory of the
+// jobs file. Jobs run one after another and fail independently, so one broken scene doesn't stop
+// the rest of a run.
+
+use glam::Vec3;
+use serde::Deserialize;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tracing::{error, info};
+
+use crate::{
+    assets::{AssetError, Assets},
+    camera::Camera,
+    error::AppError,
+    headless::HeadlessRenderer,
+    scene::Scene,
+    settings::RenderSettings,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// The glTF file to render. The demo scene is rendered if this is left out.
+    pub scene: Option<PathBuf>,
+    /// Where the rendered image is written. The format is picked from the extension.
+    pub output: PathBuf,
+    #[serde(default = "default_resolution")]
+    pub resolution: [u32; 2],
+    /// The camera to render from. Defaults to one framing the whole scene.
+    pub camera: Option<JobCamera>,
+    #[serde(default = "default_clear_color")]
+    pub clear_color: [f32; 4],
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobCamera {
+    pub eye: [f32; 3],
+    /// The point the camera looks at. Defaults to the center of the scene.
+    pub target: Option<[f32; 3]>,
+    /// The vertical field of view, in degrees.
+    #[serde(default = "default_fov_y")]
+    pub fov_y: f32,
+}
+
+fn default_resolution() -> [u32; 2] {
+    [1024, 768]
+}
+
+fn default_clear_color() -> [f32; 4] {
+    RenderSettings::default().clear_color
+}
+
+fn default_fov_y() -> f32 {
+    60.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobsFile {
+    #[serde(default)]
+    job: Vec<Job>,
+}
+
+/// Reads the jobs listed in `path`.
+pub fn load(path: &Path) -> io::Result<Vec<Job>> {
+    let source = fs::read_to_string(path)?;
+    let file: JobsFile =
+        toml::from_str(&source).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let jobs = file
+        .job
+        .into_iter()
+        .map(|job| Job {
+            scene: job.scene.map(|scene| base.join(scene)),
+            output: base.join(&job.output),
+            ..job
+        })
+        .collect();
+
+    Ok(jobs)
+}
+
+/// Why a single job failed.
+#[derive(Debug)]
+pub enum JobError {
+    /// The resolution is zero or larger than the device supports.
+    Resolution([u32; 2]),
+    Scene(gltf::Error),
+    /// A mesh or texture of the scene failed to load.
+    Asset(AssetError),
+    Output(image::ImageError),
+    /// The image to render into couldn't be created.
+    Render(AppError),
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Resolution([width, height]) => {
+                write!(f, "unsupported resolution {width}x{height}")
+            }
+            JobError::Scene(err) => write!(f, "failed to load scene: {err}"),
+            JobError::Asset(err) => write!(f, "{err}"),
+            JobError::Output(err) => write!(f, "failed to write image: {err}"),
+            JobError::Render(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+/// Renders every job in order, printing progress as it goes. Returns the number of jobs that
+/// failed.
+pub fn run(renderer: &HeadlessRenderer, jobs: &[Job]) -> usize {
+    let gpu = renderer.gpu();
+    // Shared between jobs, so that jobs rendering the same scene only load it once.
+    let mut assets = Assets::new(
+        gpu.memory_allocator.clone(),
+        gpu.command_buffer_allocator.clone(),
+        gpu.queue.clone(),
+        || {},
+    );
+    let mut failed = 0;
+
+    for (i, job) in jobs.iter().enumerate() {
+        let start = Instant::now();
+        let progress = format!("[{}/{}] {}", i + 1, jobs.len(), job.output.display());
+
+        match render_job(renderer, &mut assets, job) {
+            Ok(()) => info!("{progress} ({:.0?})", start.elapsed()),
+            Err(err) => {
+                error!("{progress} failed: {err}");
+                failed += 1;
+            }
+        }
+        assets.end_frame();
+    }
+
+    info!("{} of {} jobs succeeded", jobs.len() - failed, jobs.len());
+    failed
+}
+
+fn render_job(renderer: &HeadlessRenderer, assets: &mut Assets, job: &Job) -> Result<(), JobError> {
+    let gpu = renderer.gpu();
+
+    let [width, height] = job.resolution;
+    let max_dimension = gpu
+        .device
+        .physical_device()
+        .properties()
+        .max_image_dimension2_d;
+    if width == 0 || height == 0 || width > max_dimension || height > max_dimension {
+        return Err(JobError::Resolution(job.resolution));
+    }
+
+    let scene = match &job.scene {
+        Some(path) => Scene::load_gltf(assets, path).map_err(JobError::Scene)?,
+        None => Scene::demo(assets),
+    };
+    if let Some(err) = assets.wait_for_loads().into_iter().next() {
+        return Err(JobError::Asset(err));
+    }
+
+    let bounds = scene.bounds();
+    let framing = Camera::framing(&bounds);
+    let camera = match job.camera {
+        Some(camera) => Camera {
+            eye: Vec3::from(camera.eye),
+            target: camera.target.map_or(framing.target, Vec3::from),
+            fov_y: camera.fov_y.to_radians(),
+        },
+        None => scene.camera.unwrap_or(framing),
+    };
+    let view_proj = camera.view_proj(width as f32 / height as f32, &bounds);
+
+    let image = renderer
+        .render(&scene, view_proj, job.resolution, job.clear_color)
+        .map_err(JobError::Render)?;
+
+    if let Some(parent) = job.output.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| JobError::Output(image::ImageError::IoError(err)))?;
+    }
+    image.save(&job.output).map_err(JobError::Output)
+}
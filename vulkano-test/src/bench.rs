@@ -0,0 +1,192 @@
+// Benchmarking for `vulkano-test --bench N`. The scene is rendered offscreen N times, as fast as
+// the GPU goes, waiting for each frame before starting the next, and the frame times are summed up
+// in a JSON report:
+//
+//     {
+//       "gpu": "AMD Radeon RX 7800 XT",
+//       "resolution": [1280, 720],
+//       "frames": 1000,
+//       "mean_ms": 0.84,
+//       "median_ms": 0.81,
+//       "p95_ms": 1.02,
+//       "p99_ms": 1.37,
+//       "min_ms": 0.74,
+//       "max_ms": 2.9,
+//       "mesh_shading": false,
+//       "triangles": 1534,
+//       "vertices": 1089,
+//       "meshlets": 0
+//     }
+//
+// With a camera path, the frames follow it from its first keyframe to its last, and every run
+// sees the same views in the same order; otherwise every frame is of the scene's camera.
+//
+// Frame times are measured on the CPU from recording to the fence signalling, so they include the
+// submission overhead but not presentation. The geometry counts are those of the last frame, for
+// comparing the work of the mesh shading path, under `--mesh-shading`, with the vertex path.
+
+use serde::Serialize;
+use std::{fmt, time::Instant};
+use tracing::info;
+
+use crate::{
+    app::SceneSource,
+    assets::{AssetError, Assets},
+    camera::Camera,
+    camera_path::CameraPath,
+    error::AppError,
+    headless::HeadlessRenderer,
+    scene::Scene,
+    scene_file,
+    scene_pipeline::DrawStats,
+    settings::RenderSettings,
+};
+
+/// Frames rendered before timing starts, so that pipeline and driver warm-up isn't measured.
+const WARMUP_FRAMES: u32 = 10;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchReport {
+    pub gpu: String,
+    pub resolution: [u32; 2],
+    pub frames: u32,
+    pub mean_ms: f64,
+    pub median_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    /// Whether meshes were drawn from their meshlets with task and mesh shaders.
+    pub mesh_shading: bool,
+    /// The triangles and transformed vertices of the drawn meshes, as in `DrawStats`.
+    pub triangles: usize,
+    pub vertices: usize,
+    pub meshlets: usize,
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    /// The resolution is zero or larger than the device supports.
+    Resolution([u32; 2]),
+    Scene(Box<dyn std::error::Error + Send + Sync>),
+    /// A mesh or texture of the scene failed to load.
+    Asset(AssetError),
+    /// The images to render into couldn't be created.
+    Render(AppError),
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BenchError::Resolution([width, height]) => {
+                write!(f, "unsupported resolution {width}x{height}")
+            }
+            BenchError::Scene(err) => write!(f, "failed to load scene: {err}"),
+            BenchError::Asset(err) => write!(f, "{err}"),
+            BenchError::Render(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for BenchError {}
+
+/// Renders `frames` frames of the scene at `resolution`, along `camera_path` if there is one, and
+/// reports how long they took.
+pub fn run(
+    renderer: &HeadlessRenderer,
+    scene_source: &SceneSource,
+    resolution: [u32; 2],
+    frames: u32,
+    camera_path: Option<&CameraPath>,
+) -> Result<BenchReport, BenchError> {
+    let gpu = renderer.gpu();
+
+    let [width, height] = resolution;
+    let max_dimension = gpu
+        .device
+        .physical_device()
+        .properties()
+        .max_image_dimension2_d;
+    if width == 0 || height == 0 || width > max_dimension || height > max_dimension {
+        return Err(BenchError::Resolution(resolution));
+    }
+
+    let mut assets = Assets::new(
+        gpu.memory_allocator.clone(),
+        gpu.command_buffer_allocator.clone(),
+        gpu.queue.clone(),
+        || {},
+    );
+    let scene = match scene_source {
+        SceneSource::Demo => Scene::demo(&mut assets),
+        SceneSource::Gltf(path) => {
+            Scene::load_gltf(&mut assets, path).map_err(|err| BenchError::Scene(err.into()))?
+        }
+        SceneSource::Ron(path) => {
+            scene_file::load(&mut assets, path).map_err(|err| BenchError::Scene(err.into()))?
+        }
+    };
+    if let Some(err) = assets.wait_for_loads().into_iter().next() {
+        return Err(BenchError::Asset(err));
+    }
+
+    let bounds = scene.bounds();
+    let camera = scene.camera.unwrap_or_else(|| Camera::framing(&bounds));
+    // The view of frame `i`, before the timing starts so that sampling the path isn't timed.
+    let view_projs: Vec<_> = (0..frames)
+        .map(|i| {
+            let t = i as f32 / (frames - 1).max(1) as f32;
+            camera_path
+                .and_then(|path| path.sample(t))
+                .unwrap_or(camera)
+                .view_proj(width as f32 / height as f32, &bounds)
+        })
+        .collect();
+    let clear_color = RenderSettings::default().clear_color;
+    let target = renderer
+        .create_target(resolution)
+        .map_err(BenchError::Render)?;
+
+    for _ in 0..WARMUP_FRAMES {
+        renderer.render_to(&target, &scene, view_projs[0], clear_color);
+    }
+
+    info!("Rendering {frames} frames at {width}x{height}");
+    let mut stats = DrawStats::default();
+    let mut frame_times_ms: Vec<f64> = view_projs
+        .iter()
+        .map(|&view_proj| {
+            let start = Instant::now();
+            stats = renderer.render_to(&target, &scene, view_proj, clear_color);
+            start.elapsed().as_secs_f64() * 1000.0
+        })
+        .collect();
+    frame_times_ms.sort_by(f64::total_cmp);
+
+    Ok(BenchReport {
+        gpu: gpu
+            .device
+            .physical_device()
+            .properties()
+            .device_name
+            .clone(),
+        resolution,
+        frames,
+        mean_ms: frame_times_ms.iter().sum::<f64>() / frame_times_ms.len() as f64,
+        median_ms: percentile(&frame_times_ms, 50.0),
+        p95_ms: percentile(&frame_times_ms, 95.0),
+        p99_ms: percentile(&frame_times_ms, 99.0),
+        min_ms: frame_times_ms[0],
+        max_ms: frame_times_ms[frame_times_ms.len() - 1],
+        mesh_shading: renderer.scene_pipeline().mesh_shading(),
+        triangles: stats.triangles,
+        vertices: stats.vertices,
+        meshlets: stats.meshlets,
+    })
+}
+
+/// The nearest-rank percentile of `sorted`, which must not be empty.
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
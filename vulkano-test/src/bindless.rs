@@ -0,0 +1,266 @@
+// Bindless textures, through descriptor indexing. Rather than binding a descriptor set for the
+// texture of every draw, all textures live in one array in a single descriptor set, which is bound
+// once per frame, and each draw pushes the index of its texture along with its other push
+// constants.
+//
+// The array is sized for as many textures as the device allows, up to `MAX_TEXTURES`, with a
+// variable descriptor count, and its descriptors are only written as textures are first drawn
+// with. That happens after the set has been bound, possibly while earlier frames using it are still
+// in flight, which the update-after-bind flags allow since those never read the new descriptors.
+// Once full, textures that don't have a slot are drawn with the one in slot 0.
+//
+// The array only refers to its textures weakly. Once a texture is dropped, which `Assets` only
+// does after every frame that drew with it has finished, its slot is written over with the one in
+// slot 0, letting go of its image, and given to the next new texture. Textures that are replaced
+// often, as streamed ones are, then neither fill up the array nor keep their memory in use.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{Arc, Weak},
+};
+use vulkano::{
+    descriptor_set::{
+        allocator::{StandardDescriptorSetAllocator, StandardDescriptorSetAllocatorCreateInfo},
+        layout::{
+            DescriptorBindingFlags, DescriptorSetLayout, DescriptorSetLayoutBinding,
+            DescriptorSetLayoutCreateFlags, DescriptorSetLayoutCreateInfo, DescriptorType,
+        },
+        DescriptorSet, WriteDescriptorSet,
+    },
+    device::{Device, DeviceExtensions, DeviceFeatures},
+    image::sampler::Sampler,
+    shader::ShaderStages,
+    Version,
+};
+
+use crate::{device_requirements::DeviceRequirements, error::AppError, texture::Texture};
+
+/// The most textures the array holds.
+pub const MAX_TEXTURES: u32 = 4096;
+
+/// The binding of the sampler that every texture is sampled with.
+const SAMPLER_BINDING: u32 = 0;
+
+/// The binding of the texture array. A variable descriptor count is only allowed on the last
+/// binding of a set.
+const TEXTURES_BINDING: u32 = 1;
+
+/// The features bindless textures need.
+pub const FEATURES: DeviceFeatures = DeviceFeatures {
+    shader_sampled_image_array_dynamic_indexing: true,
+    runtime_descriptor_array: true,
+    descriptor_binding_partially_bound: true,
+    descriptor_binding_variable_descriptor_count: true,
+    descriptor_binding_sampled_image_update_after_bind: true,
+    descriptor_binding_update_unused_while_pending: true,
+    ..DeviceFeatures::empty()
+};
+
+/// Asks for descriptor indexing where supported.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_promoted_features(
+        Version::V1_2,
+        FEATURES,
+        DeviceExtensions {
+            ext_descriptor_indexing: true,
+            khr_maintenance3: true,
+            ..DeviceExtensions::empty()
+        },
+    );
+}
+
+pub struct BindlessTextures {
+    layout: Arc<DescriptorSetLayout>,
+    set: Arc<DescriptorSet>,
+    capacity: u32,
+    /// The slot of every texture in the array, by its address. An address can be that of a
+    /// dropped texture until `release_dropped` is called.
+    slots: RefCell<HashMap<*const Texture, u32>>,
+    /// The texture in every slot, or `None` for slots that were released.
+    textures: RefCell<Vec<Option<Weak<Texture>>>>,
+    /// The slots that were released, for new textures to take.
+    free: RefCell<Vec<u32>>,
+    /// The texture in slot 0, which released slots are written over with.
+    default_texture: Arc<Texture>,
+}
+
+impl BindlessTextures {
+    /// Creates an array in which every texture is sampled with `sampler`, with `default_texture`
+    /// in slot 0. The device must support bindless textures, as `Capabilities` tells.
+    pub fn new(
+        device: Arc<Device>,
+        sampler: Arc<Sampler>,
+        default_texture: Arc<Texture>,
+    ) -> Result<Self, AppError> {
+        let properties = device.physical_device().properties();
+        let capacity = MAX_TEXTURES
+            .min(
+                properties
+                    .max_descriptor_set_update_after_bind_sampled_images
+                    .unwrap_or(0),
+            )
+            .min(
+                properties
+                    .max_per_stage_descriptor_update_after_bind_sampled_images
+                    .unwrap_or(0),
+            )
+            .max(1);
+
+        let layout = DescriptorSetLayout::new(
+            device.clone(),
+            DescriptorSetLayoutCreateInfo {
+                flags: DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+                bindings: [
+                    (
+                        SAMPLER_BINDING,
+                        DescriptorSetLayoutBinding {
+                            stages: ShaderStages::FRAGMENT,
+                            ..DescriptorSetLayoutBinding::descriptor_type(DescriptorType::Sampler)
+                        },
+                    ),
+                    (
+                        TEXTURES_BINDING,
+                        DescriptorSetLayoutBinding {
+                            binding_flags: DescriptorBindingFlags::UPDATE_AFTER_BIND
+                                | DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING
+                                | DescriptorBindingFlags::PARTIALLY_BOUND
+                                | DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+                            descriptor_count: capacity,
+                            stages: ShaderStages::FRAGMENT,
+                            ..DescriptorSetLayoutBinding::descriptor_type(
+                                DescriptorType::SampledImage,
+                            )
+                        },
+                    ),
+                ]
+                .into(),
+                ..Default::default()
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+
+        // The set lives as long as the array, so it gets a pool of its own.
+        let allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            device,
+            StandardDescriptorSetAllocatorCreateInfo {
+                set_count: 1,
+                update_after_bind: true,
+                ..Default::default()
+            },
+        ));
+        let set = DescriptorSet::new_variable(
+            allocator,
+            layout.clone(),
+            capacity,
+            [WriteDescriptorSet::sampler(SAMPLER_BINDING, sampler)],
+            [],
+        )
+        .map_err(AppError::Pipeline)?;
+
+        let textures = BindlessTextures {
+            layout,
+            set,
+            capacity,
+            slots: RefCell::default(),
+            textures: RefCell::default(),
+            free: RefCell::default(),
+            default_texture,
+        };
+        textures.slot(&textures.default_texture);
+        Ok(textures)
+    }
+
+    /// The layout of the set, for pipeline layouts to use in place of the one their shaders
+    /// declare, which can't tell that the array is bindless.
+    pub fn layout(&self) -> &Arc<DescriptorSetLayout> {
+        &self.layout
+    }
+
+    /// The set to bind, which holds every texture that has a slot.
+    pub fn set(&self) -> &Arc<DescriptorSet> {
+        &self.set
+    }
+
+    /// The index of `texture` in the array, writing it into a free slot the first time, or 0 if
+    /// there are none left.
+    pub fn slot(&self, texture: &Arc<Texture>) -> u32 {
+        let mut slots = self.slots.borrow_mut();
+        let mut textures = self.textures.borrow_mut();
+        let slot = match slots.get(&Arc::as_ptr(texture)) {
+            // A slot whose texture is alive at the same address holds this very texture.
+            Some(&slot)
+                if textures[slot as usize]
+                    .as_ref()
+                    .is_some_and(|weak| weak.strong_count() > 0) =>
+            {
+                return slot;
+            }
+            // The texture that had the slot was dropped, and this one took its address.
+            Some(&slot) => slot,
+            None => match self.free.borrow_mut().pop() {
+                Some(slot) => slot,
+                None if textures.len() as u32 == self.capacity => return 0,
+                None => {
+                    textures.push(None);
+                    textures.len() as u32 - 1
+                }
+            },
+        };
+        // SAFETY: the set is only ever updated here and in `release_dropped`, and `slots` being
+        // borrowed keeps that from happening twice at once. The slot is either new or was last
+        // written with a texture that has been dropped, which no pending frame draws with.
+        unsafe { self.write(slot, texture) };
+        textures[slot as usize] = Some(Arc::downgrade(texture));
+        slots.insert(Arc::as_ptr(texture), slot);
+        slot
+    }
+
+    /// Writes the texture in slot 0 over the slots of textures that were dropped, releasing their
+    /// images, and frees the slots for new textures. Call this before recording a frame.
+    pub fn release_dropped(&self) {
+        let mut slots = self.slots.borrow_mut();
+        let mut textures = self.textures.borrow_mut();
+        for (slot, texture) in textures.iter_mut().enumerate().skip(1) {
+            let Some(weak) = texture.take_if(|weak| weak.strong_count() == 0) else {
+                continue;
+            };
+            // SAFETY: as in `slot`, which `slots` being borrowed keeps from running at the same
+            // time. Nothing pending draws with a dropped texture.
+            unsafe { self.write(slot as u32, &self.default_texture) };
+            if slots.get(&weak.as_ptr()) == Some(&(slot as u32)) {
+                slots.remove(&weak.as_ptr());
+            }
+            self.free.borrow_mut().push(slot as u32);
+        }
+    }
+
+    /// Writes `texture` into `slot`.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may update the set at the same time, and no pending command buffer may read
+    /// the slot.
+    unsafe fn write(&self, slot: u32, texture: &Arc<Texture>) {
+        unsafe {
+            self.set.update_by_ref(
+                [WriteDescriptorSet::image_view_array(
+                    TEXTURES_BINDING,
+                    slot,
+                    [texture.view.clone()],
+                )],
+                [],
+            )
+        }
+        .unwrap();
+    }
+
+    /// How many textures have a slot.
+    pub fn len(&self) -> usize {
+        self.textures.borrow().iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,249 @@
+// A Gaussian blur of a rendered image, as a compute post-effect. The blur is separable, so it runs
+// as two passes of `shaders/blur.comp`: along the rows into an intermediate storage image, then
+// along the columns of that into the output. Each workgroup loads its segment of a row or column,
+// plus the texels around it that the kernel reaches, into shared memory once, and every invocation
+// then reads its neighbours from there instead of from the image.
+//
+// The workgroup size is the largest that the device allows, up to `MAX_WORKGROUP_SIZE`. naga
+// can't write specialization constants, so it is defined into the shader before it is compiled,
+// along with `MAX_RADIUS`, which sizes the shared memory.
+//
+// vulkano inserts the barriers between the passes: from the render pass writing the input to the
+// first pass sampling it, from the first pass's writes to the second's reads, and from the second
+// to whatever uses the output next, such as a blit or a fragment shader.
+
+use std::sync::Arc;
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::DeviceOwned,
+    format::Format,
+    image::{
+        sampler::{Sampler, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, ComputePipeline, Pipeline, PipelineBindPoint,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    debug_utils,
+    error::AppError,
+    shader::{self, ShaderStage},
+};
+
+/// The largest blur radius, in pixels.
+pub const MAX_RADIUS: u32 = 32;
+
+/// The largest workgroup, in invocations, that the blur is dispatched with.
+const MAX_WORKGROUP_SIZE: u32 = 256;
+
+/// The format of the blurred images, which keeps the precision of the intermediate result.
+const FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    direction: [i32; 2],
+    radius: i32,
+    sigma: f32,
+}
+
+pub struct BlurFilter {
+    pipeline: Arc<ComputePipeline>,
+    workgroup_size: u32,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    sampler: Arc<Sampler>,
+    /// The result of the horizontal pass and of the vertical one, recreated when the size of the
+    /// input changes.
+    images: Option<[Arc<ImageView>; 2]>,
+}
+
+impl BlurFilter {
+    /// A blur of images that hold sRGB-encoded colors if `encoded`, as targets encoded by hand do,
+    /// which are blurred linear and written out encoded again.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        encoded: bool,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+        let workgroup_size = workgroup_size(&memory_allocator);
+
+        let mut defines = vec![
+            ("WORKGROUP_SIZE", workgroup_size.to_string()),
+            ("MAX_RADIUS", MAX_RADIUS.to_string()),
+        ];
+        if encoded {
+            defines.push(("ENCODED", "1".to_owned()));
+        }
+        let cs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/blur.comp"),
+            ShaderStage::Compute,
+            &defines,
+        )?
+        .entry_point("main")
+        .unwrap();
+
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout =
+            shader::reflect_layout::<PushConstants>(device.clone(), std::slice::from_ref(&stage))?;
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "blur");
+
+        // Texels are fetched without filtering, so the sampler's settings don't matter.
+        let sampler =
+            Sampler::new(device, SamplerCreateInfo::default()).map_err(AppError::Pipeline)?;
+
+        Ok(BlurFilter {
+            pipeline,
+            workgroup_size,
+            memory_allocator,
+            descriptor_set_allocator,
+            sampler,
+            images: None,
+        })
+    }
+
+    /// Records a blur of `input`, which must be sampleable, with a kernel reaching `radius` pixels
+    /// to either side, clamped to `MAX_RADIUS`. Returns the image holding the result, which is
+    /// only valid until the next call.
+    pub fn apply<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        input: &Arc<ImageView>,
+        radius: u32,
+    ) -> Arc<Image> {
+        let [width, height, _] = input.image().extent();
+        if self
+            .images
+            .as_ref()
+            .is_none_or(|[image, _]| image.image().extent() != input.image().extent())
+        {
+            self.images = Some([
+                self.create_image([width, height]),
+                self.create_image([width, height]),
+            ]);
+        }
+        let [intermediate, output] = self.images.clone().unwrap();
+
+        let radius = radius.min(MAX_RADIUS);
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap();
+        self.dispatch(
+            builder,
+            input,
+            &intermediate,
+            [1, 0],
+            radius,
+            [width, height],
+        );
+        self.dispatch(
+            builder,
+            &intermediate,
+            &output,
+            [0, 1],
+            radius,
+            [height, width],
+        );
+
+        output.image().clone()
+    }
+
+    /// Records one pass, with a workgroup for every segment of `extent[0]` pixels along
+    /// `direction` and for each of the `extent[1]` rows or columns.
+    fn dispatch<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+        direction: [i32; 2],
+        radius: u32,
+        extent: [u32; 2],
+    ) {
+        let layout = &self.pipeline.layout().set_layouts()[0];
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::image_view(0, input.clone()),
+                WriteDescriptorSet::sampler(1, self.sampler.clone()),
+                WriteDescriptorSet::image_view(2, output.clone()),
+            ],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                PushConstants {
+                    direction,
+                    radius: radius as i32,
+                    // Most of the kernel's weight falls within two standard deviations.
+                    sigma: (radius as f32 / 2.0).max(0.5),
+                },
+            )
+            .unwrap();
+
+        // SAFETY: the shader only writes inside the output image, which is the size of the input.
+        unsafe { builder.dispatch([extent[0].div_ceil(self.workgroup_size), extent[1], 1]) }
+            .unwrap();
+    }
+
+    fn create_image(&self, extent: [u32; 2]) -> Arc<ImageView> {
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: FORMAT,
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+        ImageView::new_default(image).unwrap()
+    }
+}
+
+/// The largest power of two, up to `MAX_WORKGROUP_SIZE`, that the device can dispatch along one
+/// dimension and that leaves the tile fitting in shared memory.
+fn workgroup_size(memory_allocator: &StandardMemoryAllocator) -> u32 {
+    let properties = memory_allocator.device().physical_device().properties();
+    let tile_size = |size: u32| (size + 2 * MAX_RADIUS) * 16;
+
+    let mut size = MAX_WORKGROUP_SIZE;
+    while size > 1
+        && (size > properties.max_compute_work_group_size[0]
+            || size > properties.max_compute_work_group_invocations
+            || tile_size(size) > properties.max_compute_shared_memory_size)
+    {
+        size /= 2;
+    }
+    size
+}
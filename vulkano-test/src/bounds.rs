@@ -0,0 +1,109 @@
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+
+/// An axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// A box containing nothing. It is the identity for `union`.
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    /// The smallest box containing all of `points`, or `EMPTY` if there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |aabb, point| Aabb {
+            min: aabb.min.min(point),
+            max: aabb.max.max(point),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Half the size of the box along each axis.
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// The box enclosing this one after it has been transformed by `transform`.
+    pub fn transformed(&self, transform: Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        // Transforming the center and projecting the half extents onto each axis gives the same
+        // result as transforming all eight corners, with less work.
+        let center = transform.transform_point3(self.center());
+        let half_extents = self.half_extents();
+        let radius = transform.x_axis.truncate().abs() * half_extents.x
+            + transform.y_axis.truncate().abs() * half_extents.y
+            + transform.z_axis.truncate().abs() * half_extents.z;
+
+        Aabb {
+            min: center - radius,
+            max: center + radius,
+        }
+    }
+}
+
+/// The volume visible through a camera, as six planes facing inwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    /// Each plane is `(normal, distance)`, with points on the inside satisfying
+    /// `normal.dot(point) + distance >= 0`.
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a view-projection matrix with Vulkan's `0..1` depth range.
+    pub fn from_view_proj(view_proj: Mat4) -> Self {
+        let [row0, row1, row2, row3] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+
+        Frustum {
+            planes: [
+                row3 + row0,
+                row3 - row0,
+                row3 + row1,
+                row3 - row1,
+                row2,
+                row3 - row2,
+            ],
+        }
+    }
+
+    /// Whether any part of `aabb` may be visible. Boxes close to a corner of the frustum can be
+    /// reported as visible when they aren't, but visible boxes are never rejected.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal is the last to leave the inside.
+            let normal = plane.xyz();
+            let corner = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
@@ -0,0 +1,101 @@
+// Perspective cameras. The clip planes are derived from the bounds of whatever is being looked at,
+// so the same camera works for scenes of any scale.
+//
+// With the `stereo` setting, the window draws the rasterized main view twice, side by side, for
+// the two cameras of `Camera::stereo_pair`. Culling, the light clusters, water and fog are all made
+// for a single camera, so the stereo view goes without occlusion and GPU culling, point and spot
+// lights, water and fog. The debug overlay is drawn for both eyes.
+
+use glam::{
+    camera::rh::{proj, view},
+    Mat4, Vec3,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::bounds::Aabb;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Camera {
+    pub eye: Vec3,
+    pub target: Vec3,
+    /// The vertical field of view, in radians.
+    pub fov_y: f32,
+}
+
+impl Camera {
+    /// A camera looking at the whole of `bounds` from above and to the side.
+    pub fn framing(bounds: &Aabb) -> Self {
+        let (center, radius) = bounding_sphere(bounds);
+
+        Camera {
+            eye: center + Vec3::new(3.0, 2.0, 4.0).normalize() * radius * 2.5,
+            target: center,
+            fov_y: 60f32.to_radians(),
+        }
+    }
+
+    /// The combined view and projection matrix, with the clip planes from `clip_planes`.
+    pub fn view_proj(&self, aspect_ratio: f32, bounds: &Aabb) -> Mat4 {
+        self.proj(aspect_ratio, bounds) * self.view()
+    }
+
+    /// From world space to view space, in which the camera looks down -Z.
+    pub fn view(&self) -> Mat4 {
+        view::look_at_mat4(self.eye, self.target, Vec3::Y)
+    }
+
+    /// From view space to clip space, with the clip planes from `clip_planes`.
+    pub fn proj(&self, aspect_ratio: f32, bounds: &Aabb) -> Mat4 {
+        let (near, far) = self.clip_planes(bounds);
+        proj::vulkan::perspective(self.fov_y, aspect_ratio, near, far)
+    }
+
+    /// The cameras of the left and right eye of a stereo pair `separation` apart, each with its
+    /// combined view and projection matrix. The eyes look the same way as this camera, and their
+    /// frustums are shifted towards each other so that whatever is as far away as the target
+    /// shows in the same place to both, in front of the screen when nearer and behind it when
+    /// further.
+    pub fn stereo_pair(
+        &self,
+        separation: f32,
+        aspect_ratio: f32,
+        bounds: &Aabb,
+    ) -> [(Camera, Mat4); 2] {
+        let forward = self.target - self.eye;
+        let convergence = forward.length().max(f32::EPSILON);
+        let right = forward.cross(Vec3::Y).normalize_or_zero();
+        let focal_length = 1.0 / (self.fov_y / 2.0).tan();
+
+        [-0.5, 0.5].map(|side| {
+            let offset = side * separation;
+            let eye = Camera {
+                eye: self.eye + right * offset,
+                target: self.target + right * offset,
+                ..*self
+            };
+            // Moves clip space sideways by the disparity that the offset gives at the target's
+            // distance, in normalized device coordinates, which is the same at every depth once
+            // it is scaled by w.
+            let shift = focal_length / aspect_ratio * offset / convergence;
+            let proj =
+                Mat4::from_translation(Vec3::new(shift, 0.0, 0.0)) * eye.proj(aspect_ratio, bounds);
+            (eye, proj * eye.view())
+        })
+    }
+
+    /// The distances to the near and far clip planes, chosen so that `bounds` is enclosed.
+    pub fn clip_planes(&self, bounds: &Aabb) -> (f32, f32) {
+        let (center, radius) = bounding_sphere(bounds);
+
+        (radius * 0.05, (self.eye.distance(center) + radius) * 2.0)
+    }
+}
+
+/// The center and radius of a sphere enclosing `bounds`, or a unit sphere if they are empty.
+fn bounding_sphere(bounds: &Aabb) -> (Vec3, f32) {
+    if bounds.is_empty() {
+        (Vec3::ZERO, 1.0)
+    } else {
+        (bounds.center(), bounds.half_extents().length().max(0.01))
+    }
+}
@@ -0,0 +1,333 @@
+// The camera of the window, moved by the mouse and keyboard in one of two modes. The orbit camera
+// turns around a focus point, pans it sideways and zooms towards it; the fly camera turns where it
+// stands and moves with WASD, Q and E. Both look the same way for the same yaw and pitch, so
+// switching from orbiting to flying carries on from where the camera is, while switching back
+// returns to the focus it was orbiting, gliding there over `TRANSITION`. Framing an entity glides
+// the same way, into orbiting around it.
+//
+// In the window, dragging with the left mouse button, or a finger across a touch screen, turns the
+// camera, and holding the right button does the same with the cursor hidden, so the mouse can keep
+// moving past the edge of the screen. A drag with Shift held pans the orbit camera and the mouse
+// wheel zooms it. C switches between the modes, and F frames the selected entity, or the whole
+// scene.
+//
+// Until the camera is first moved, it is whatever the scene asks for, which for a scene without a
+// camera of its own is one framing the bounds loaded so far.
+
+use glam::{Vec3, Vec4Swizzles};
+use std::time::{Duration, Instant};
+use winit::keyboard::KeyCode;
+
+use crate::{bounds::Aabb, camera::Camera};
+
+/// How long the camera takes to glide to where it is sent.
+const TRANSITION: Duration = Duration::from_millis(400);
+
+/// How far the camera stops short of looking straight up or down.
+const PITCH_LIMIT: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+/// How much one line of the mouse wheel zooms in the orbit camera, or speeds up the fly camera.
+const WHEEL_STEP: f32 = 0.85;
+
+/// How much further than its bounding sphere a framed entity is seen from.
+const FRAMING_MARGIN: f32 = 1.2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    #[default]
+    Orbit,
+    Fly,
+}
+
+impl CameraMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            CameraMode::Orbit => "orbit",
+            CameraMode::Fly => "fly",
+        }
+    }
+}
+
+/// Where a camera is and which way it looks: from `eye`, towards a focus `distance` away in the
+/// direction given by `yaw` about the Y axis and `pitch` above the horizon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Pose {
+    eye: Vec3,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+}
+
+impl Pose {
+    fn from_camera(camera: &Camera) -> Self {
+        let offset = camera.eye - camera.target;
+        let distance = offset.length().max(f32::EPSILON);
+        Pose {
+            eye: camera.eye,
+            yaw: offset.x.atan2(offset.z),
+            pitch: (offset.y / distance).clamp(-1.0, 1.0).asin(),
+            distance,
+        }
+    }
+
+    /// The unit vector from the focus back to the eye.
+    fn backward(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vec3::new(cos_pitch * sin_yaw, sin_pitch, cos_pitch * cos_yaw)
+    }
+
+    fn focus(&self) -> Vec3 {
+        self.eye - self.backward() * self.distance
+    }
+
+    fn camera(&self, fov_y: f32) -> Camera {
+        Camera {
+            eye: self.eye,
+            target: self.focus(),
+            fov_y,
+        }
+    }
+
+    /// The pose `t` of the way from this one to `other`, turning the short way around.
+    fn lerp(&self, other: &Pose, t: f32) -> Pose {
+        let tau = std::f32::consts::TAU;
+        let yaw_change =
+            (other.yaw - self.yaw + std::f32::consts::PI).rem_euclid(tau) - std::f32::consts::PI;
+        Pose {
+            eye: self.eye.lerp(other.eye, t),
+            yaw: self.yaw + yaw_change * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+            distance: self.distance + (other.distance - self.distance) * t,
+        }
+    }
+}
+
+/// A glide from one pose to wherever the camera is meant to be.
+struct Transition {
+    from: Pose,
+    started: Instant,
+}
+
+#[derive(Default)]
+pub struct CameraController {
+    mode: CameraMode,
+    /// The orbit camera's pose, and the fly camera's, once the camera has been moved.
+    orbit: Option<Pose>,
+    fly: Option<Pose>,
+    /// The camera that the scene asks for, as of the last frame.
+    default: Option<Camera>,
+    transition: Option<Transition>,
+    /// The movement keys held, as right, up and backward from -1 to 1.
+    movement: [i8; 3],
+    /// When the fly camera last moved with the keys.
+    last_update: Option<Instant>,
+}
+
+impl CameraController {
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// The camera to draw with, `default` being what the scene asks for.
+    pub fn camera(&mut self, default: Camera) -> Camera {
+        self.default = Some(default);
+        self.move_with_keys();
+
+        let pose = self.pose();
+        let pose = match &self.transition {
+            Some(transition) => {
+                let t = transition.started.elapsed().as_secs_f32() / TRANSITION.as_secs_f32();
+                if t >= 1.0 {
+                    self.transition = None;
+                    pose
+                } else {
+                    // Eases in and out.
+                    transition.from.lerp(&pose, t * t * (3.0 - 2.0 * t))
+                }
+            }
+            None => pose,
+        };
+        pose.camera(default.fov_y)
+    }
+
+    /// Whether the camera moves by itself, gliding or flying while a key is held, so that frames
+    /// need to keep being drawn.
+    pub fn is_moving(&self) -> bool {
+        self.transition.is_some() || (self.mode == CameraMode::Fly && self.movement != [0; 3])
+    }
+
+    /// Goes back to whatever the scene asks for, as for a newly loaded scene.
+    pub fn reset(&mut self) {
+        *self = CameraController::default();
+    }
+
+    /// Switches between orbiting and flying.
+    pub fn toggle_mode(&mut self) {
+        let from = self.pose();
+        self.mode = match self.mode {
+            CameraMode::Orbit => {
+                self.fly = Some(from);
+                CameraMode::Fly
+            }
+            CameraMode::Fly => {
+                self.orbit.get_or_insert(from);
+                self.glide_from(from);
+                CameraMode::Orbit
+            }
+        };
+        self.last_update = None;
+    }
+
+    /// Turns the camera by `yaw` radians about the Y axis and `pitch` radians up or down: around
+    /// its focus when orbiting, or where it stands when flying.
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        let mode = self.mode;
+        let pose = self.pose_mut();
+        let focus = pose.focus();
+        pose.yaw += yaw;
+        pose.pitch = (pose.pitch + pitch).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+        if mode == CameraMode::Orbit {
+            pose.eye = focus + pose.backward() * pose.distance;
+        }
+    }
+
+    /// Moves the orbit camera and its focus sideways, by `right` and `up` as fractions of the
+    /// height of the view at the focus.
+    pub fn pan(&mut self, right: f32, up: f32) {
+        let Some(fov_y) = self.default.map(|camera| camera.fov_y) else {
+            return;
+        };
+        let pose = self.pose_mut();
+        let camera = pose.camera(fov_y);
+        let height = 2.0 * pose.distance * (fov_y / 2.0).tan();
+        let inverse_view = camera.view().inverse();
+        let right_axis = inverse_view.x_axis.xyz();
+        let up_axis = inverse_view.y_axis.xyz();
+        pose.eye += (right_axis * right + up_axis * up) * height;
+    }
+
+    /// Zooms the orbit camera towards its focus, or speeds up the fly camera, by `lines` of the
+    /// mouse wheel.
+    pub fn zoom(&mut self, lines: f32) {
+        let mode = self.mode;
+        let pose = self.pose_mut();
+        let focus = pose.focus();
+        pose.distance *= WHEEL_STEP.powf(lines);
+        if mode == CameraMode::Orbit {
+            pose.eye = focus + pose.backward() * pose.distance;
+        }
+    }
+
+    /// Glides into orbiting around `bounds`, seen from the way the camera looks now and from far
+    /// enough away to fit them.
+    pub fn frame(&mut self, bounds: &Aabb) {
+        if bounds.is_empty() {
+            return;
+        }
+        let Some(fov_y) = self.default.map(|camera| camera.fov_y) else {
+            return;
+        };
+        let from = self.pose();
+        let radius = bounds.half_extents().length().max(0.01);
+        let mut pose = Pose {
+            distance: radius / (fov_y / 2.0).sin() * FRAMING_MARGIN,
+            ..from
+        };
+        pose.eye = bounds.center() + pose.backward() * pose.distance;
+        self.mode = CameraMode::Orbit;
+        self.orbit = Some(pose);
+        self.glide_from(from);
+    }
+
+    /// Notes a movement key going down or up, returning whether `key` is one.
+    pub fn set_key(&mut self, key: KeyCode, pressed: bool) -> bool {
+        let (axis, direction) = match key {
+            KeyCode::KeyD => (0, 1),
+            KeyCode::KeyA => (0, -1),
+            KeyCode::KeyE => (1, 1),
+            KeyCode::KeyQ => (1, -1),
+            KeyCode::KeyS => (2, 1),
+            KeyCode::KeyW => (2, -1),
+            _ => return false,
+        };
+        if pressed {
+            self.movement[axis] = direction;
+        } else if self.movement[axis] == direction {
+            self.movement[axis] = 0;
+        }
+        true
+    }
+
+    /// Lets go of every movement key.
+    pub fn release_keys(&mut self) {
+        self.movement = [0; 3];
+    }
+
+    /// Moves the fly camera by the keys held since it last moved, at its distance every second.
+    fn move_with_keys(&mut self) {
+        let now = Instant::now();
+        let last_update = self.last_update.replace(now);
+        if self.mode != CameraMode::Fly || self.movement == [0; 3] {
+            return;
+        }
+        let Some(last_update) = last_update else {
+            return;
+        };
+        let Some(fov_y) = self.default.map(|camera| camera.fov_y) else {
+            return;
+        };
+        let dt = now.duration_since(last_update).as_secs_f32();
+        let inverse_view = self.pose().camera(fov_y).view().inverse();
+        let direction = inverse_view.x_axis.xyz() * f32::from(self.movement[0])
+            + Vec3::Y * f32::from(self.movement[1])
+            + inverse_view.z_axis.xyz() * f32::from(self.movement[2]);
+        let pose = self.pose_mut();
+        pose.eye += direction.normalize_or_zero() * pose.distance * dt;
+    }
+
+    fn glide_from(&mut self, from: Pose) {
+        self.transition = Some(Transition {
+            from,
+            started: Instant::now(),
+        });
+    }
+
+    /// The pose that the current mode has, or the scene's camera's while it hasn't been moved.
+    fn pose(&self) -> Pose {
+        let pose = match self.mode {
+            CameraMode::Orbit => self.orbit,
+            CameraMode::Fly => self.fly,
+        };
+        pose.unwrap_or_else(|| Pose::from_camera(&self.default.unwrap_or_else(default_camera)))
+    }
+
+    /// The pose of the current mode, taken from the scene's camera if it hasn't been moved yet.
+    fn pose_mut(&mut self) -> &mut Pose {
+        let pose = self.pose();
+        let slot = match self.mode {
+            CameraMode::Orbit => &mut self.orbit,
+            CameraMode::Fly => &mut self.fly,
+        };
+        slot.get_or_insert(pose)
+    }
+}
+
+/// The camera before the scene has asked for one, which only lasts until the first frame.
+fn default_camera() -> Camera {
+    Camera::framing(&Aabb::EMPTY)
+}
+
+/// Whether `key` is one of the keys that move the fly camera, which it takes before any
+/// keybinding.
+pub fn is_movement_key(key: KeyCode) -> bool {
+    matches!(
+        key,
+        KeyCode::KeyW
+            | KeyCode::KeyA
+            | KeyCode::KeyS
+            | KeyCode::KeyD
+            | KeyCode::KeyQ
+            | KeyCode::KeyE
+    )
+}
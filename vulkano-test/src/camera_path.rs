@@ -0,0 +1,203 @@
+// Camera paths: keyframes of the camera, recorded one at a time from the console, and played back
+// along a Catmull-Rom spline through them over a chosen duration. The eye and target each follow
+// their own spline, which passes through every keyframe and eases through the first and last, and
+// the field of view changes linearly between keyframes. Every segment between two keyframes takes
+// as long as the others, however far apart they are.
+//
+// Paths are saved as RON, a list of cameras as scene files store them:
+//
+//     [
+//         (eye: (3.0, 2.0, 4.0), target: (0.0, 0.0, 0.0), fov_y: 1.047),
+//         (eye: (-3.0, 1.0, 4.0), target: (0.0, 0.5, 0.0), fov_y: 1.047),
+//     ]
+//
+// `vulkano-test --bench N --camera-path path.ron` flies along a path over the N frames, so that
+// benchmarks see the same views every run, and `path play` can start a video recording that stops
+// when the path ends. While a path plays in the window, it steers the camera instead of the
+// mouse.
+
+use glam::Vec3;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::camera::Camera;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CameraPath {
+    keyframes: Vec<Camera>,
+}
+
+#[derive(Debug)]
+pub enum CameraPathError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+}
+
+impl fmt::Display for CameraPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraPathError::Io(err) => write!(f, "{err}"),
+            CameraPathError::Parse(err) => write!(f, "invalid camera path: {err}"),
+            CameraPathError::Serialize(err) => write!(f, "failed to serialize camera path: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CameraPathError {}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<Self, CameraPathError> {
+        let source = fs::read_to_string(path).map_err(CameraPathError::Io)?;
+        ron::from_str(&source).map_err(CameraPathError::Parse)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CameraPathError> {
+        let source = ron::ser::to_string_pretty(self, PrettyConfig::default())
+            .map_err(CameraPathError::Serialize)?;
+        fs::write(path, source).map_err(CameraPathError::Io)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keyframes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Adds `camera` as the last keyframe.
+    pub fn push(&mut self, camera: Camera) {
+        self.keyframes.push(camera);
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    /// The camera `t` of the way along the path, from 0 at the first keyframe to 1 at the last,
+    /// or `None` if there are no keyframes.
+    pub fn sample(&self, t: f32) -> Option<Camera> {
+        let last = self.keyframes.len().checked_sub(1)?;
+        let position = t.clamp(0.0, 1.0) * last as f32;
+        let segment = (position.floor() as usize).min(last.saturating_sub(1));
+        let t = position - segment as f32;
+        // The keyframes around the segment, with the ends repeated.
+        let keyframe =
+            |i: isize| self.keyframes[(segment as isize + i).clamp(0, last as isize) as usize];
+        let [k0, k1, k2, k3] = [-1, 0, 1, 2].map(keyframe);
+        Some(Camera {
+            eye: catmull_rom([k0.eye, k1.eye, k2.eye, k3.eye], t),
+            target: catmull_rom([k0.target, k1.target, k2.target, k3.target], t),
+            fov_y: k1.fov_y + (k2.fov_y - k1.fov_y) * t,
+        })
+    }
+}
+
+/// The point `t` of the way from `p1` to `p2` on the uniform Catmull-Rom spline through the four
+/// points.
+fn catmull_rom([p0, p1, p2, p3]: [Vec3; 4], t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+/// A path being played back in the window, from when it started.
+pub struct CameraPathPlayback {
+    path: CameraPath,
+    duration: Duration,
+    started: Instant,
+    /// Whether the playback started a video recording, to be stopped when it ends.
+    pub recording: bool,
+}
+
+impl CameraPathPlayback {
+    pub fn new(path: CameraPath, duration: Duration, recording: bool) -> Self {
+        CameraPathPlayback {
+            path,
+            duration,
+            started: Instant::now(),
+            recording,
+        }
+    }
+
+    /// The camera along the path by now, which stays at the last keyframe once it has ended.
+    pub fn camera(&self) -> Option<Camera> {
+        self.path.sample(
+            self.started.elapsed().as_secs_f32() / self.duration.as_secs_f32().max(f32::EPSILON),
+        )
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.started.elapsed() >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(x: f32, fov_y: f32) -> Camera {
+        Camera {
+            eye: Vec3::new(x, 2.0, 4.0),
+            target: Vec3::new(x, 0.0, 0.0),
+            fov_y,
+        }
+    }
+
+    /// Keyframes with coordinates that the spline's arithmetic is exact for.
+    fn path() -> CameraPath {
+        CameraPath {
+            keyframes: vec![camera(0.0, 1.0), camera(4.0, 0.5), camera(-8.0, 1.5)],
+        }
+    }
+
+    #[test]
+    fn samples_nothing_without_keyframes() {
+        assert_eq!(CameraPath::default().sample(0.5), None);
+    }
+
+    #[test]
+    fn samples_a_single_keyframe_everywhere() {
+        let path = CameraPath {
+            keyframes: vec![camera(3.0, 1.0)],
+        };
+        for t in [-1.0, 0.0, 0.5, 1.0, 2.0] {
+            assert_eq!(path.sample(t), Some(camera(3.0, 1.0)));
+        }
+    }
+
+    #[test]
+    fn starts_and_ends_at_the_first_and_last_keyframes() {
+        let path = path();
+        assert_eq!(path.sample(0.0), Some(camera(0.0, 1.0)));
+        assert_eq!(path.sample(0.5), Some(camera(4.0, 0.5)));
+        assert_eq!(path.sample(1.0), Some(camera(-8.0, 1.5)));
+    }
+
+    #[test]
+    fn clamps_outside_the_path() {
+        let path = path();
+        assert_eq!(path.sample(-0.5), path.sample(0.0));
+        assert_eq!(path.sample(1.5), path.sample(1.0));
+    }
+
+    #[test]
+    fn catmull_rom_runs_between_the_middle_points() {
+        let points = [-1.0, 0.0, 2.0, 3.0].map(|x| Vec3::new(x, 0.0, 0.0));
+        assert_eq!(catmull_rom(points, 0.0), points[1]);
+        assert_eq!(catmull_rom(points, 1.0), points[2]);
+        // Halfway between evenly spaced points on a line is halfway along.
+        let even = [0.0, 1.0, 2.0, 3.0].map(|x| Vec3::new(x, 0.0, 0.0));
+        assert_eq!(catmull_rom(even, 0.5), Vec3::new(1.5, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,36 @@
+// The system clipboard, for pasting into the console and copying reports out of the app, through
+// `arboard`, which speaks the clipboard protocol of each platform: X11 selections, which Wayland
+// sessions also answer through XWayland, the macOS pasteboard and the Windows clipboard.
+//
+// The connection is made the first time the clipboard is used and kept for as long as the app
+// runs. Under X11 what is copied is only on the clipboard while the app that copied it serves
+// it, so the text stays pasteable elsewhere until the app closes, when `arboard` hands it to a
+// clipboard manager where there is one.
+
+/// What copying or pasting fails with, such as `ContentNotAvailable` for an empty clipboard.
+pub type ClipboardError = arboard::Error;
+
+#[derive(Default)]
+pub struct Clipboard {
+    /// Made on first use, as opening it connects to the display.
+    inner: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    /// Puts `text` on the clipboard.
+    pub fn copy(&mut self, text: &str) -> Result<(), ClipboardError> {
+        self.inner()?.set_text(text)
+    }
+
+    /// The text on the clipboard.
+    pub fn paste(&mut self) -> Result<String, ClipboardError> {
+        self.inner()?.get_text()
+    }
+
+    fn inner(&mut self) -> Result<&mut arboard::Clipboard, ClipboardError> {
+        if self.inner.is_none() {
+            self.inner = Some(arboard::Clipboard::new()?);
+        }
+        Ok(self.inner.as_mut().unwrap())
+    }
+}
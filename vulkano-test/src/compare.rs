@@ -0,0 +1,225 @@
+// Image comparison for `vulkano-test compare`, which checks a rendered image against a golden one.
+// The images are compared pixel by pixel, both by the largest difference between their channels
+// and by how different the colors look: the distance between them in CIELAB, where a distance of
+// about 2.3 is the smallest that can be told apart. A pixel counts as different when its colors
+// are further apart than the tolerance, and the comparison fails when more of the pixels than the
+// threshold allows are.
+//
+// The heatmap shows the perceptual difference of each pixel, from black where the images match
+// through red to yellow, and white at `HEATMAP_SCALE` and beyond. Alpha is only part of the
+// per-channel difference.
+
+use glam::Vec3;
+use image::{ImageError, Rgb, RgbImage, RgbaImage};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// The perceptual difference that the heatmap shows as white.
+const HEATMAP_SCALE: f32 = 20.0;
+
+/// How two images differ.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    /// The largest difference between the same channel of the same pixel, from 0 to 1.
+    pub max_channel_difference: f32,
+    /// The average perceptual difference over all pixels.
+    pub mean_difference: f32,
+    /// The largest perceptual difference.
+    pub max_difference: f32,
+    /// How many pixels differ by more than the tolerance.
+    pub differing_pixels: usize,
+    pub pixel_count: usize,
+    pub heatmap: RgbImage,
+}
+
+impl Comparison {
+    /// The fraction of the pixels that differ by more than the tolerance.
+    pub fn differing_fraction(&self) -> f32 {
+        self.differing_pixels as f32 / self.pixel_count.max(1) as f32
+    }
+}
+
+#[derive(Debug)]
+pub enum CompareError {
+    Image(PathBuf, ImageError),
+    /// The images have different sizes, which are given.
+    SizeMismatch([u32; 2], [u32; 2]),
+}
+
+impl fmt::Display for CompareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompareError::Image(path, err) => write!(f, "failed to load {}: {err}", path.display()),
+            CompareError::SizeMismatch([w0, h0], [w1, h1]) => {
+                write!(f, "the images are {w0}x{h0} and {w1}x{h1}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompareError {}
+
+/// Loads the images at `path` and `reference` and compares them, counting the pixels whose colors
+/// are further apart than `tolerance` in CIELAB.
+pub fn compare_files(
+    path: &Path,
+    reference: &Path,
+    tolerance: f32,
+) -> Result<Comparison, CompareError> {
+    let load = |path: &Path| {
+        image::open(path)
+            .map(|image| image.into_rgba8())
+            .map_err(|err| CompareError::Image(path.to_owned(), err))
+    };
+    compare(&load(path)?, &load(reference)?, tolerance)
+}
+
+/// Compares `image` with `reference`, as `compare_files` does.
+pub fn compare(
+    image: &RgbaImage,
+    reference: &RgbaImage,
+    tolerance: f32,
+) -> Result<Comparison, CompareError> {
+    if image.dimensions() != reference.dimensions() {
+        let (width, height) = image.dimensions();
+        let (reference_width, reference_height) = reference.dimensions();
+        return Err(CompareError::SizeMismatch(
+            [width, height],
+            [reference_width, reference_height],
+        ));
+    }
+
+    let (width, height) = image.dimensions();
+    let mut heatmap = RgbImage::new(width, height);
+    let mut max_channel_difference = 0u8;
+    let mut total_difference = 0.0;
+    let mut max_difference = 0.0f32;
+    let mut differing_pixels = 0;
+    for ((pixel, reference_pixel), heat) in image
+        .pixels()
+        .zip(reference.pixels())
+        .zip(heatmap.pixels_mut())
+    {
+        for (a, b) in pixel.0.iter().zip(reference_pixel.0) {
+            max_channel_difference = max_channel_difference.max(a.abs_diff(b));
+        }
+
+        let difference = lab(pixel.0).distance(lab(reference_pixel.0));
+        total_difference += f64::from(difference);
+        max_difference = max_difference.max(difference);
+        if difference > tolerance {
+            differing_pixels += 1;
+        }
+        *heat = heat_color(difference / HEATMAP_SCALE);
+    }
+
+    let pixel_count = width as usize * height as usize;
+    Ok(Comparison {
+        max_channel_difference: f32::from(max_channel_difference) / 255.0,
+        mean_difference: (total_difference / pixel_count.max(1) as f64) as f32,
+        max_difference,
+        differing_pixels,
+        pixel_count,
+        heatmap,
+    })
+}
+
+/// The CIELAB color of an sRGB pixel, under the D65 white point.
+fn lab([r, g, b, _]: [u8; 4]) -> Vec3 {
+    let linear = Vec3::new(r.into(), g.into(), b.into()).map(|channel: f32| {
+        let channel = channel / 255.0;
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let xyz = Vec3::new(
+        linear.dot(Vec3::new(0.4124, 0.3576, 0.1805)) / 0.95047,
+        linear.dot(Vec3::new(0.2126, 0.7152, 0.0722)),
+        linear.dot(Vec3::new(0.0193, 0.1192, 0.9505)) / 1.08883,
+    );
+    let f = xyz.map(|t| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    });
+    Vec3::new(116.0 * f.y - 16.0, 500.0 * (f.x - f.y), 200.0 * (f.y - f.z))
+}
+
+/// Black at 0, then red, yellow and white at 1 and beyond.
+fn heat_color(heat: f32) -> Rgb<u8> {
+    let heat = heat.clamp(0.0, 1.0) * 3.0;
+    let channel = |start: f32| ((heat - start).clamp(0.0, 1.0) * 255.0) as u8;
+    Rgb([channel(0.0), channel(1.0), channel(2.0)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const TOLERANCE: f32 = 2.3;
+
+    fn gray(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = gray(4, 3, 128);
+        let comparison = compare(&image, &image, TOLERANCE).unwrap();
+
+        assert_eq!(comparison.max_channel_difference, 0.0);
+        assert_eq!(comparison.max_difference, 0.0);
+        assert_eq!(comparison.mean_difference, 0.0);
+        assert_eq!(comparison.differing_pixels, 0);
+        assert_eq!(comparison.pixel_count, 12);
+        assert!(comparison
+            .heatmap
+            .pixels()
+            .all(|pixel| pixel.0 == [0, 0, 0]));
+    }
+
+    #[test]
+    fn one_differing_pixel() {
+        let reference = gray(2, 2, 0);
+        let mut image = reference.clone();
+        image.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+        let comparison = compare(&image, &reference, TOLERANCE).unwrap();
+
+        // Black and white are the whole range of lightness apart, and nothing else.
+        assert_eq!(comparison.max_channel_difference, 1.0);
+        assert!((comparison.max_difference - 100.0).abs() < 0.1);
+        assert!((comparison.mean_difference - 25.0).abs() < 0.1);
+        assert_eq!(comparison.differing_pixels, 1);
+        assert_eq!(comparison.differing_fraction(), 0.25);
+        assert_eq!(comparison.heatmap.get_pixel(1, 0).0, [255, 255, 255]);
+        assert_eq!(comparison.heatmap.get_pixel(0, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn small_differences_are_within_tolerance() {
+        let reference = gray(2, 2, 100);
+        let image = gray(2, 2, 101);
+        let comparison = compare(&image, &reference, TOLERANCE).unwrap();
+
+        assert_eq!(comparison.max_channel_difference, 1.0 / 255.0);
+        assert!(comparison.max_difference > 0.0 && comparison.max_difference < TOLERANCE);
+        assert_eq!(comparison.differing_pixels, 0);
+    }
+
+    #[test]
+    fn sizes_must_match() {
+        let result = compare(&gray(2, 2, 0), &gray(3, 2, 0), TOLERANCE);
+
+        assert!(matches!(
+            result,
+            Err(CompareError::SizeMismatch([2, 2], [3, 2]))
+        ));
+    }
+}
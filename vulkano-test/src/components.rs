@@ -0,0 +1,108 @@
+// The components that scene entities are made of. An entity is drawn if it has a `Transform`, a
+// `MeshHandle` and a `Material`; anything else can be attached next to them without the renderer
+// having to know about it.
+
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{assets::Handle, bounds::Aabb, material::Material, mesh::Mesh, scene::NodeId};
+
+/// The world transform of an entity. It is kept up to date by `Scene::update_transforms` for
+/// entities that have a `SceneNode`, and can be set directly on those that don't.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform(pub Mat4);
+
+/// Places an entity at a node of the scene's transform hierarchy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SceneNode(pub NodeId);
+
+/// The mesh an entity is drawn with, shared between every entity that instances it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshHandle(pub Handle<Mesh>);
+
+impl MeshHandle {
+    /// The bounds of the mesh once placed with `transform`, or `None` while it is loading.
+    pub fn aabb(&self, transform: &Transform) -> Option<Aabb> {
+        Some(self.0.get()?.aabb.transformed(transform.0))
+    }
+}
+
+/// A material drawn instead of the entity's own `Material`, for as long as the component is
+/// attached.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialOverride(pub Material);
+
+/// Turns the node of an entity around an axis through its origin, as part of the simulation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spin {
+    pub axis: Vec3,
+    /// Radians per second.
+    pub speed: f32,
+    /// The local transform of the node before any rotation.
+    pub rest_transform: Mat4,
+    /// The angle after the last simulation step, and the one before it.
+    pub angle: f32,
+    pub previous_angle: f32,
+}
+
+impl Spin {
+    pub fn new(axis: Vec3, speed: f32, rest_transform: Mat4) -> Self {
+        Spin {
+            axis: axis.normalize(),
+            speed,
+            rest_transform,
+            angle: 0.0,
+            previous_angle: 0.0,
+        }
+    }
+}
+
+/// A light of the scene, of any kind.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+/// Where a `Light` shines from, and in which directions.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+    /// Shining everywhere in the same direction, in world space, like the sun.
+    Directional { direction: Vec3 },
+    /// Shining in every direction from the origin of the entity's `Transform`, like a bulb. The
+    /// light fades out smoothly before `range`, and lights nothing beyond.
+    Point { range: f32 },
+    /// Shining in a cone from the origin of the entity's `Transform`, along its -Z axis as in glTF,
+    /// like a torch. The light is at full intensity up to `inner_cone_angle` from the axis, in
+    /// radians, and has faded out at `outer_cone_angle`. It reaches as far as a point light does.
+    Spot {
+        range: f32,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+impl Light {
+    /// The direction that a directional light shines in, or `None` for other kinds of light.
+    pub fn direction(&self) -> Option<Vec3> {
+        match self.kind {
+            LightKind::Directional { direction } => Some(direction),
+            LightKind::Point { .. } | LightKind::Spot { .. } => None,
+        }
+    }
+}
+
+impl Default for Light {
+    /// A white sun, shining down at an angle.
+    fn default() -> Self {
+        Light {
+            kind: LightKind::Directional {
+                direction: -Vec3::new(0.4, 1.0, 0.6).normalize(),
+            },
+            color: Vec3::ONE,
+            intensity: 1.0,
+        }
+    }
+}
@@ -0,0 +1,318 @@
+// The developer console, which drops down with the key left of 1, `~` on US layouts, unless the
+// `toggle-console` keybinding moves it, and takes the keyboard for as long as it is open. The line
+// being typed is drawn in the top left corner of the overlay, shaped and set in the fallback chain
+// of fonts of `text.rs` so that any script or emoji that one of them covers can be typed, and shown
+// in the window title in place of the frame statistics too. Whatever the commands report goes to
+// the log. The line is edited by grapheme cluster, as the user sees characters, so that Backspace
+// takes a whole emoji or accented letter rather than leaving half of it behind.
+//
+// Input methods, for CJK and other composed text, are enabled while the console is open. What is
+// being composed shows in brackets after the line until it is committed, and the keys pressed in
+// the meantime are the input method's to handle, so Enter doesn't run the line half typed.
+//
+// Enter runs the line, Up and Down step back and forth through the lines that ran before, and Tab
+// completes the name of a command, or of a setting after `set`. Where several names start the same
+// way, Tab completes what they have in common and logs them all. Ctrl+C copies the line to the
+// clipboard and Ctrl+V pastes the first line of what is on it, unless the `console-copy` and
+// `console-paste` keybindings move them. Escape or the console key closes it again.
+
+use std::path::PathBuf;
+use tracing::info;
+use unicode_segmentation::UnicodeSegmentation;
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+use crate::settings::RenderSettings;
+
+/// The commands of the console, with how each of them is used.
+const COMMANDS: &[(&str, &str)] = &[
+    ("help", "help - lists the commands"),
+    (
+        "info",
+        "info - copies what the GPU supports to the clipboard, for bug reports",
+    ),
+    (
+        "keys",
+        "keys - lists the keybindings, as the settings remap them",
+    ),
+    ("load", "load <model.gltf | scene.ron> - replaces the scene"),
+    (
+        "path",
+        "path <add | clear | play <seconds> [record] | save <file.ron> | load <file.ron>> - \
+         records a camera path, one keyframe at a time, and plays it back",
+    ),
+    (
+        "screenshot",
+        "screenshot [hires] [file.png] - saves the next frame, or renders it supersampled with \
+         hires",
+    ),
+    (
+        "set",
+        "set <setting> <value> - sets a setting as settings.toml would",
+    ),
+    ("vsync", "vsync <on | off> - sets the vsync setting"),
+];
+
+/// A line of the console, parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Help,
+    /// Copies the report of `vulkano-test info` on the device in use to the clipboard.
+    Info,
+    /// Logs the keys of every hotkey.
+    Keys,
+    /// Loads a scene file if the path ends in `.ron`, and a glTF file otherwise.
+    Load(PathBuf),
+    CameraPath(PathCommand),
+    /// Saves a screenshot to `path`, or to a timestamped file in the working directory, rendered
+    /// supersampled for itself if `supersampled` is set.
+    Screenshot {
+        path: Option<PathBuf>,
+        supersampled: bool,
+    },
+    /// Sets the setting called `name` to `value`, which is TOML.
+    Set {
+        name: String,
+        value: String,
+    },
+    Vsync(bool),
+}
+
+/// What the `path` command does with the camera path.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathCommand {
+    /// Adds the current camera as the last keyframe.
+    Add,
+    Clear,
+    /// Plays the path back over `seconds`, recording a video of it if `record` is set.
+    Play {
+        seconds: f32,
+        record: bool,
+    },
+    Save(PathBuf),
+    Load(PathBuf),
+}
+
+impl Command {
+    /// Parses `line`, returning `None` for a blank one and a description of what is wrong with it
+    /// otherwise.
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let line = line.trim();
+        let (name, arguments) = line.split_once(' ').unwrap_or((line, ""));
+        let arguments = arguments.trim();
+        let usage = || {
+            let (_, usage) = COMMANDS
+                .iter()
+                .find(|(command, _)| *command == name)
+                .unwrap();
+            format!("usage: {usage}")
+        };
+
+        let command = match name {
+            "" => return Ok(None),
+            "help" => Command::Help,
+            "info" => Command::Info,
+            "keys" => Command::Keys,
+            "load" if !arguments.is_empty() => Command::Load(arguments.into()),
+            "path" => {
+                let (action, rest) = arguments.split_once(' ').unwrap_or((arguments, ""));
+                let rest = rest.trim();
+                Command::CameraPath(match (action, rest) {
+                    ("add", "") => PathCommand::Add,
+                    ("clear", "") => PathCommand::Clear,
+                    ("play", _) => {
+                        let (seconds, record) = match rest.split_once(' ') {
+                            Some((seconds, "record")) => (seconds, true),
+                            Some(_) => return Err(usage()),
+                            None => (rest, false),
+                        };
+                        match seconds.parse() {
+                            Ok(seconds) if seconds > 0.0 => PathCommand::Play { seconds, record },
+                            _ => return Err(usage()),
+                        }
+                    }
+                    ("save", file) if !file.is_empty() => PathCommand::Save(file.into()),
+                    ("load", file) if !file.is_empty() => PathCommand::Load(file.into()),
+                    _ => return Err(usage()),
+                })
+            }
+            "screenshot" => {
+                let (supersampled, file) = match arguments.split_once(' ') {
+                    Some(("hires", file)) => (true, file.trim()),
+                    _ if arguments == "hires" => (true, ""),
+                    _ => (false, arguments),
+                };
+                Command::Screenshot {
+                    path: Some(file).filter(|f| !f.is_empty()).map(PathBuf::from),
+                    supersampled,
+                }
+            }
+            "set" => match arguments.split_once(' ') {
+                Some((name, value)) => Command::Set {
+                    name: name.to_owned(),
+                    value: value.trim().to_owned(),
+                },
+                None => return Err(usage()),
+            },
+            "vsync" => match arguments {
+                "on" => Command::Vsync(true),
+                "off" => Command::Vsync(false),
+                _ => return Err(usage()),
+            },
+            "load" => return Err(usage()),
+            _ => return Err(format!("unknown command {name:?}, see `help`")),
+        };
+
+        Ok(Some(command))
+    }
+
+    /// How to use every command.
+    pub fn usages() -> impl Iterator<Item = &'static str> {
+        COMMANDS.iter().map(|(_, usage)| *usage)
+    }
+}
+
+pub struct Console {
+    open: bool,
+    line: String,
+    /// The text that an input method is composing, to go after the line once it is committed.
+    preedit: String,
+    /// The lines that ran, oldest first.
+    history: Vec<String>,
+    /// The line of the history being shown, while stepping through it.
+    history_index: Option<usize>,
+    setting_names: Vec<String>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Console {
+            open: false,
+            line: String::new(),
+            preedit: String::new(),
+            history: Vec::new(),
+            history_index: None,
+            setting_names: RenderSettings::names(),
+        }
+    }
+}
+
+impl Console {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.preedit.clear();
+    }
+
+    /// The window title while the console is open.
+    pub fn title(&self) -> String {
+        if self.preedit.is_empty() {
+            format!("vulkano-test console> {}_", self.line)
+        } else {
+            format!("vulkano-test console> {}[{}]_", self.line, self.preedit)
+        }
+    }
+
+    /// Shows `text` as what an input method is composing, or nothing if it is empty.
+    pub fn set_preedit(&mut self, text: &str) {
+        self.preedit = text.to_owned();
+    }
+
+    /// Adds `text`, which an input method typed, to the line.
+    pub fn commit(&mut self, text: &str) {
+        self.preedit.clear();
+        self.line.extend(text.chars().filter(|c| !c.is_control()));
+    }
+
+    /// The line being typed.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Adds the first line of `text`, pasted from the clipboard, to the line.
+    pub fn paste(&mut self, text: &str) {
+        let first = text.lines().next().unwrap_or_default();
+        self.line.extend(first.chars().filter(|c| !c.is_control()));
+    }
+
+    /// Edits the line with `key`, pressed while the console is open and typing `text`. Returns
+    /// the line if the key ran it.
+    pub fn handle_key(&mut self, key: PhysicalKey, text: Option<&str>) -> Option<String> {
+        if !self.preedit.is_empty() {
+            return None;
+        }
+        match key {
+            PhysicalKey::Code(KeyCode::Escape) => self.open = false,
+            PhysicalKey::Code(KeyCode::Enter | KeyCode::NumpadEnter) => {
+                let line = std::mem::take(&mut self.line);
+                self.history_index = None;
+                if !line.trim().is_empty() && self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                }
+                return Some(line);
+            }
+            PhysicalKey::Code(KeyCode::Backspace) => {
+                let last = self.line.grapheme_indices(true).next_back();
+                if let Some((start, _)) = last {
+                    self.line.truncate(start);
+                }
+            }
+            PhysicalKey::Code(KeyCode::ArrowUp) => {
+                let index = match self.history_index {
+                    Some(index) => index.saturating_sub(1),
+                    None => self.history.len().checked_sub(1)?,
+                };
+                self.history_index = Some(index);
+                self.line = self.history[index].clone();
+            }
+            PhysicalKey::Code(KeyCode::ArrowDown) => {
+                let index = self.history_index? + 1;
+                self.history_index = (index < self.history.len()).then_some(index);
+                self.line = self.history.get(index).cloned().unwrap_or_default();
+            }
+            PhysicalKey::Code(KeyCode::Tab) => self.complete(),
+            _ => {
+                let text = text.unwrap_or_default();
+                self.line.extend(text.chars().filter(|c| !c.is_control()));
+            }
+        }
+        None
+    }
+
+    /// Completes the word being typed, if it is the name of a command or of a setting to set.
+    fn complete(&mut self) {
+        let (start, candidates): (usize, Vec<&str>) = match self.line.split_once(' ') {
+            None => (0, COMMANDS.iter().map(|(name, _)| *name).collect()),
+            Some(("set", name)) if !name.contains(' ') => (
+                "set ".len(),
+                self.setting_names.iter().map(String::as_str).collect(),
+            ),
+            Some(_) => return,
+        };
+
+        let word = &self.line[start..];
+        let matches: Vec<&str> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .collect();
+        let completed = match matches.as_slice() {
+            [] => return,
+            [only] => format!("{only} "),
+            [first, rest @ ..] => {
+                info!("{}", matches.join(" "));
+                let common = rest.iter().fold(first.len(), |common, candidate| {
+                    first
+                        .bytes()
+                        .zip(candidate.bytes())
+                        .take(common)
+                        .take_while(|(a, b)| a == b)
+                        .count()
+                });
+                first[..common].to_owned()
+            }
+        };
+        self.line.replace_range(start.., &completed);
+    }
+}
@@ -0,0 +1,208 @@
+// Crash reports. A panic anywhere writes `crash-<seconds since the epoch>.txt` to the working
+// directory before the process goes down, with what a GPU-specific bug report needs: the panic
+// message and a backtrace, the GPU and its driver, the instance and device extensions that were
+// enabled, the last validation messages and the state of the swapchain. The panic is then
+// reported as usual.
+//
+// What the report says about Vulkan is noted as it is set up, since a panic can come at any point
+// and the objects themselves may be out of reach. Validation messages come in through a debug
+// messenger on the instance, which also logs them, and only arrive with the validation layers
+// enabled, for example with `VK_INSTANCE_LAYERS=VK_LAYER_KHRONOS_validation`.
+
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    fmt::Write as _,
+    fs, io,
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tracing::{error, warn};
+use vulkano::{
+    device::Device,
+    instance::{
+        debug::{
+            DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessengerCallback,
+            DebugUtilsMessengerCreateInfo,
+        },
+        Instance,
+    },
+    swapchain::Swapchain,
+};
+
+use crate::app::timestamped_path;
+
+/// How many of the latest validation messages a report keeps.
+const VALIDATION_MESSAGES: usize = 32;
+
+/// What is known about Vulkan so far, as it goes in a report.
+struct Context {
+    instance: Option<String>,
+    device: Option<String>,
+    swapchain: Option<String>,
+    validation_messages: VecDeque<String>,
+}
+
+static CONTEXT: Mutex<Context> = Mutex::new(Context {
+    instance: None,
+    device: None,
+    swapchain: None,
+    validation_messages: VecDeque::new(),
+});
+
+fn context() -> std::sync::MutexGuard<'static, Context> {
+    // Nothing panics while holding the lock, and a report is worth writing either way.
+    CONTEXT.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Installs the panic hook that writes crash reports, ahead of the one already installed.
+pub fn install() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+        match write_report(info) {
+            Ok(path) => error!("Wrote a crash report to {}", path.display()),
+            Err(err) => error!("Failed to write a crash report: {err}"),
+        }
+    }));
+}
+
+fn write_report(info: &PanicHookInfo<'_>) -> io::Result<PathBuf> {
+    let backtrace = Backtrace::force_capture();
+    let context = context();
+    let unknown = || "unknown\n".to_owned();
+
+    let mut report = String::new();
+    let thread = std::thread::current();
+    let _ = writeln!(
+        report,
+        "vulkano-test {} panicked on thread {}: {info}\n",
+        env!("CARGO_PKG_VERSION"),
+        thread.name().unwrap_or("<unnamed>"),
+    );
+    let _ = writeln!(report, "Backtrace:\n{backtrace}");
+    let _ = writeln!(
+        report,
+        "OS: {} {}\n",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+    );
+    let _ = writeln!(
+        report,
+        "Instance:\n{}",
+        context.instance.clone().unwrap_or_else(unknown),
+    );
+    let _ = writeln!(
+        report,
+        "Device:\n{}",
+        context.device.clone().unwrap_or_else(unknown),
+    );
+    let _ = writeln!(
+        report,
+        "Swapchain:\n{}",
+        context
+            .swapchain
+            .clone()
+            .unwrap_or_else(|| "none\n".to_owned()),
+    );
+    let _ = writeln!(report, "Last validation messages:");
+    if context.validation_messages.is_empty() {
+        let _ = writeln!(report, "none");
+    }
+    for message in &context.validation_messages {
+        let _ = writeln!(report, "{message}");
+    }
+
+    let path = timestamped_path("crash", "txt");
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// The debug messenger to create the instance with, which logs what the driver and layers report
+/// and keeps the latest messages for crash reports.
+pub(crate) fn debug_messenger() -> DebugUtilsMessengerCreateInfo {
+    // SAFETY: the callback only logs and locks the context, without calling into Vulkan.
+    let callback = unsafe {
+        DebugUtilsMessengerCallback::new(|severity, ty, data| {
+            let message = match data.message_id_name {
+                Some(name) => format!("[{name}] {}", data.message),
+                None => data.message.to_owned(),
+            };
+            if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+                error!("Vulkan {ty:?}: {message}");
+            } else {
+                warn!("Vulkan {ty:?}: {message}");
+            }
+            let mut context = context();
+            if context.validation_messages.len() == VALIDATION_MESSAGES {
+                context.validation_messages.pop_front();
+            }
+            context
+                .validation_messages
+                .push_back(format!("{severity:?} {ty:?}: {message}"));
+        })
+    };
+    DebugUtilsMessengerCreateInfo {
+        message_type: DebugUtilsMessageType::GENERAL
+            | DebugUtilsMessageType::VALIDATION
+            | DebugUtilsMessageType::PERFORMANCE,
+        ..DebugUtilsMessengerCreateInfo::user_callback(callback)
+    }
+}
+
+/// Notes the instance that devices are created from.
+pub(crate) fn set_instance(instance: &Arc<Instance>) {
+    let mut description = format!("Vulkan {}\nExtensions:\n", instance.api_version());
+    write_extensions(&mut description, *instance.enabled_extensions());
+    context().instance = Some(description);
+}
+
+/// Notes the device that everything renders with.
+pub(crate) fn set_device(device: &Arc<Device>) {
+    let properties = device.physical_device().properties();
+    let mut description = format!(
+        "{} ({:?}, vendor {:#06x}, device {:#06x})\nVulkan {}\n",
+        properties.device_name,
+        properties.device_type,
+        properties.vendor_id,
+        properties.device_id,
+        properties.api_version,
+    );
+    let _ = match (&properties.driver_name, &properties.driver_info) {
+        (Some(name), Some(info)) => writeln!(description, "Driver: {name} {info}"),
+        (Some(name), None) => writeln!(description, "Driver: {name}"),
+        // The encoding is vendor specific without the driver properties.
+        _ => writeln!(description, "Driver: {:#x}", properties.driver_version),
+    };
+    description.push_str("Extensions:\n");
+    write_extensions(&mut description, *device.enabled_extensions());
+    context().device = Some(description);
+}
+
+/// Notes the swapchain that the window presents with, whenever it is created or recreated.
+pub(crate) fn set_swapchain(swapchain: &Swapchain) {
+    let [width, height] = swapchain.image_extent();
+    let mut description = format!(
+        "{width}x{height}, {} images of {:?} in {:?}\n",
+        swapchain.image_count(),
+        swapchain.image_format(),
+        swapchain.image_color_space(),
+    );
+    let _ = writeln!(description, "Present mode: {:?}", swapchain.present_mode());
+    let _ = writeln!(
+        description,
+        "Composite alpha: {:?}",
+        swapchain.composite_alpha()
+    );
+    context().swapchain = Some(description);
+}
+
+fn write_extensions(
+    description: &mut String,
+    extensions: impl IntoIterator<Item = (&'static str, bool)>,
+) {
+    for (name, _) in extensions.into_iter().filter(|(_, enabled)| *enabled) {
+        let _ = writeln!(description, "    {name}");
+    }
+}
@@ -0,0 +1,42 @@
+// The mouse cursor, which shows what the mouse is doing to the camera: an arrow normally, a
+// grabbing hand while dragging the camera around, and nothing while the mouse steers the camera
+// directly. For the latter the cursor is also locked in place, or failing that confined to the
+// window, so that it doesn't run into the edge of the screen.
+
+use tracing::warn;
+use winit::window::{CursorGrabMode, CursorIcon, Window};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorMode {
+    #[default]
+    Arrow,
+    Grab,
+    Hidden,
+}
+
+impl CursorMode {
+    pub fn apply(self, window: &Window) {
+        match self {
+            CursorMode::Arrow | CursorMode::Grab => {
+                window.set_cursor_visible(true);
+                window.set_cursor(if self == CursorMode::Grab {
+                    CursorIcon::Grabbing
+                } else {
+                    CursorIcon::Default
+                });
+                if let Err(err) = window.set_cursor_grab(CursorGrabMode::None) {
+                    warn!("Failed to release the cursor: {err}");
+                }
+            }
+            CursorMode::Hidden => {
+                window.set_cursor_visible(false);
+                let grabbed = window
+                    .set_cursor_grab(CursorGrabMode::Locked)
+                    .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+                if let Err(err) = grabbed {
+                    warn!("Failed to grab the cursor: {err}");
+                }
+            }
+        }
+    }
+}
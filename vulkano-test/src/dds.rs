@@ -0,0 +1,375 @@
+// DDS textures. A `.dds` file holds a texture block-compressed in one of the BC formats, BC1 to
+// BC7, or as plain 8-bit RGBA or BGRA, with its mip chain. It can also be a cubemap, of six faces
+// with a chain each, for environment maps rather than materials. The format comes from the DX10
+// header where there is one, which also tells sRGB from linear data, and from the FourCC code of
+// older files otherwise, whose color data is taken to be sRGB as the textures of image files are.
+//
+// Volume textures, arrays and the formats of other DXGI codes aren't read. Whether the device can
+// sample the format is up to whoever uploads the texture, through `texture::can_sample`.
+
+use std::{fmt, fs, io, path::Path};
+use vulkano::format::Format;
+
+use crate::texture::TextureData;
+
+/// What a DDS file starts with.
+const MAGIC: &[u8; 4] = b"DDS ";
+
+/// Where the header ends and what follows starts, after the magic.
+const HEADER_END: usize = 128;
+
+/// The size of the DX10 header that follows the header of newer files.
+const DX10_HEADER_SIZE: usize = 20;
+
+/// The header flag saying that the mip map count is set.
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+
+/// The pixel format flags of FourCC and of uncompressed RGB data.
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+
+/// The caps2 flags of cubemaps, of all six of their faces, and of volume textures.
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0xfc00;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+/// The resource dimension of 2D textures in the DX10 header, and the flag of cubemaps.
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const D3D10_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+#[derive(Debug)]
+pub enum DdsError {
+    Io(io::Error),
+    /// The file doesn't start like a DDS file.
+    NotDds,
+    /// The file ends before the data its header says it has.
+    Malformed,
+    /// The texture isn't a 2D image or a cubemap with all six faces.
+    UnsupportedShape,
+    UnsupportedFourCc([u8; 4]),
+    UnsupportedDxgiFormat(u32),
+    /// Uncompressed data whose channels aren't 8-bit RGBA or BGRA.
+    UnsupportedPixelFormat,
+}
+
+impl fmt::Display for DdsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DdsError::Io(err) => err.fmt(f),
+            DdsError::NotDds => write!(f, "not a DDS file"),
+            DdsError::Malformed => write!(f, "malformed DDS file"),
+            DdsError::UnsupportedShape => {
+                write!(f, "only 2D textures and complete cubemaps are supported")
+            }
+            DdsError::UnsupportedFourCc(code) => write!(
+                f,
+                "unsupported FourCC code {:?}",
+                String::from_utf8_lossy(code),
+            ),
+            DdsError::UnsupportedDxgiFormat(format) => {
+                write!(f, "unsupported DXGI format {format}")
+            }
+            DdsError::UnsupportedPixelFormat => {
+                write!(f, "only 8-bit RGBA and BGRA uncompressed data is supported")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DdsError {}
+
+impl From<io::Error> for DdsError {
+    fn from(err: io::Error) -> Self {
+        DdsError::Io(err)
+    }
+}
+
+/// Whether `path` names a DDS file, going by its extension.
+pub fn is_dds(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("dds"))
+}
+
+/// Reads the texture in the DDS file at `path`.
+pub fn open(path: &Path) -> Result<TextureData, DdsError> {
+    decode(&fs::read(path)?)
+}
+
+/// Reads the texture in `bytes`, the contents of a DDS file.
+pub fn decode(bytes: &[u8]) -> Result<TextureData, DdsError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(DdsError::NotDds);
+    }
+    let u32_at = |offset: usize| -> Result<u32, DdsError> {
+        let bytes = bytes.get(offset..offset + 4).ok_or(DdsError::Malformed)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let flags = u32_at(8)?;
+    let [height, width] = [u32_at(12)?, u32_at(16)?];
+    let mip_count = if flags & DDSD_MIPMAPCOUNT != 0 {
+        u32_at(28)?.max(1)
+    } else {
+        1
+    };
+    let pixel_flags = u32_at(80)?;
+    let four_cc: [u8; 4] = bytes
+        .get(84..88)
+        .ok_or(DdsError::Malformed)?
+        .try_into()
+        .unwrap();
+    let caps2 = u32_at(112)?;
+
+    let (format, cube, data_start) = if pixel_flags & DDPF_FOURCC != 0 && &four_cc == b"DX10" {
+        let dxgi_format = u32_at(HEADER_END)?;
+        let dimension = u32_at(HEADER_END + 4)?;
+        let misc_flags = u32_at(HEADER_END + 8)?;
+        let array_size = u32_at(HEADER_END + 12)?;
+        if dimension != D3D10_RESOURCE_DIMENSION_TEXTURE2D || array_size > 1 {
+            return Err(DdsError::UnsupportedShape);
+        }
+        (
+            dxgi_format_of(dxgi_format)?,
+            misc_flags & D3D10_RESOURCE_MISC_TEXTURECUBE != 0,
+            HEADER_END + DX10_HEADER_SIZE,
+        )
+    } else {
+        if caps2 & DDSCAPS2_VOLUME != 0
+            || (caps2 & DDSCAPS2_CUBEMAP != 0
+                && caps2 & DDSCAPS2_CUBEMAP_ALL_FACES != DDSCAPS2_CUBEMAP_ALL_FACES)
+        {
+            return Err(DdsError::UnsupportedShape);
+        }
+        let format = if pixel_flags & DDPF_FOURCC != 0 {
+            four_cc_format(four_cc)?
+        } else if pixel_flags & DDPF_RGB != 0 {
+            let masks = [u32_at(92)?, u32_at(96)?, u32_at(100)?, u32_at(104)?];
+            match (u32_at(88)?, masks) {
+                (32, [0xff, 0xff00, 0xff_0000, 0xff00_0000]) => Format::R8G8B8A8_SRGB,
+                (32, [0xff_0000, 0xff00, 0xff, 0xff00_0000]) => Format::B8G8R8A8_SRGB,
+                _ => return Err(DdsError::UnsupportedPixelFormat),
+            }
+        } else {
+            return Err(DdsError::UnsupportedPixelFormat);
+        };
+        (format, caps2 & DDSCAPS2_CUBEMAP != 0, HEADER_END)
+    };
+    if width == 0 || height == 0 || (cube && width != height) {
+        return Err(DdsError::UnsupportedShape);
+    }
+    if mip_count > u32::BITS - width.max(height).leading_zeros() {
+        return Err(DdsError::Malformed);
+    }
+
+    // The file holds each face with its whole chain, one after the other, where `TextureData`
+    // holds each level with all of its faces.
+    let [block_width, block_height, _] = format.block_extent();
+    let level_sizes: Vec<usize> = (0..mip_count)
+        .map(|level| {
+            let [level_width, level_height] = [width, height].map(|side| (side >> level).max(1));
+            let size = u64::from(level_width.div_ceil(block_width))
+                * u64::from(level_height.div_ceil(block_height))
+                * format.block_size();
+            usize::try_from(size).map_err(|_| DdsError::Malformed)
+        })
+        .collect::<Result<_, _>>()?;
+    let face_count = if cube { 6 } else { 1 };
+    let mut levels = vec![Vec::new(); level_sizes.len()];
+    let mut offset = data_start;
+    for _ in 0..face_count {
+        for (level, &size) in levels.iter_mut().zip(&level_sizes) {
+            let end = offset.checked_add(size).ok_or(DdsError::Malformed)?;
+            level.extend_from_slice(bytes.get(offset..end).ok_or(DdsError::Malformed)?);
+            offset = end;
+        }
+    }
+
+    Ok(TextureData {
+        format,
+        extent: [width, height],
+        cube,
+        levels,
+    })
+}
+
+/// The format of the FourCC code of a file without a DX10 header.
+fn four_cc_format(four_cc: [u8; 4]) -> Result<Format, DdsError> {
+    Ok(match &four_cc {
+        b"DXT1" => Format::BC1_RGBA_SRGB_BLOCK,
+        b"DXT2" | b"DXT3" => Format::BC2_SRGB_BLOCK,
+        b"DXT4" | b"DXT5" => Format::BC3_SRGB_BLOCK,
+        b"ATI1" | b"BC4U" => Format::BC4_UNORM_BLOCK,
+        b"BC4S" => Format::BC4_SNORM_BLOCK,
+        b"ATI2" | b"BC5U" => Format::BC5_UNORM_BLOCK,
+        b"BC5S" => Format::BC5_SNORM_BLOCK,
+        _ => return Err(DdsError::UnsupportedFourCc(four_cc)),
+    })
+}
+
+/// The format of a `DXGI_FORMAT` from the DX10 header.
+fn dxgi_format_of(dxgi_format: u32) -> Result<Format, DdsError> {
+    Ok(match dxgi_format {
+        28 => Format::R8G8B8A8_UNORM,
+        29 => Format::R8G8B8A8_SRGB,
+        71 => Format::BC1_RGBA_UNORM_BLOCK,
+        72 => Format::BC1_RGBA_SRGB_BLOCK,
+        74 => Format::BC2_UNORM_BLOCK,
+        75 => Format::BC2_SRGB_BLOCK,
+        77 => Format::BC3_UNORM_BLOCK,
+        78 => Format::BC3_SRGB_BLOCK,
+        80 => Format::BC4_UNORM_BLOCK,
+        81 => Format::BC4_SNORM_BLOCK,
+        83 => Format::BC5_UNORM_BLOCK,
+        84 => Format::BC5_SNORM_BLOCK,
+        87 => Format::B8G8R8A8_UNORM,
+        91 => Format::B8G8R8A8_SRGB,
+        95 => Format::BC6H_UFLOAT_BLOCK,
+        96 => Format::BC6H_SFLOAT_BLOCK,
+        98 => Format::BC7_UNORM_BLOCK,
+        99 => Format::BC7_SRGB_BLOCK,
+        _ => return Err(DdsError::UnsupportedDxgiFormat(dxgi_format)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The header of a file of the given size and mip levels, with the pixel format flags,
+    /// FourCC code and caps2 flags given, and in `masks` the bit count and masks of uncompressed
+    /// data.
+    fn header(
+        [width, height]: [u32; 2],
+        mip_count: u32,
+        pixel_flags: u32,
+        four_cc: &[u8; 4],
+        caps2: u32,
+        masks: [u32; 5],
+    ) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_END];
+        let mut put = |offset: usize, value: u32| {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        put(4, 124);
+        put(8, DDSD_MIPMAPCOUNT);
+        put(12, height);
+        put(16, width);
+        put(28, mip_count);
+        put(76, 32);
+        put(80, pixel_flags);
+        for (i, mask) in masks.into_iter().enumerate() {
+            put(88 + i * 4, mask);
+        }
+        put(112, caps2);
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes
+    }
+
+    /// A file with a DX10 header of `dxgi_format`, and of a cubemap if `cube`.
+    fn dx10_header(extent: [u32; 2], mip_count: u32, dxgi_format: u32, cube: bool) -> Vec<u8> {
+        let mut bytes = header(extent, mip_count, DDPF_FOURCC, b"DX10", 0, [0; 5]);
+        let misc_flags = if cube {
+            D3D10_RESOURCE_MISC_TEXTURECUBE
+        } else {
+            0
+        };
+        for value in [
+            dxgi_format,
+            D3D10_RESOURCE_DIMENSION_TEXTURE2D,
+            misc_flags,
+            1,
+            0,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_dx10_files() {
+        // BC7 in sRGB, 8x8 and 4x4, of 16 bytes a block.
+        let mut bytes = dx10_header([8, 8], 2, 99, false);
+        bytes.extend([1; 64]);
+        bytes.extend([2; 16]);
+        let texture = decode(&bytes).unwrap();
+
+        assert_eq!(texture.format, Format::BC7_SRGB_BLOCK);
+        assert_eq!(texture.extent, [8, 8]);
+        assert!(!texture.cube);
+        assert_eq!(texture.levels, [vec![1; 64], vec![2; 16]]);
+    }
+
+    #[test]
+    fn reads_the_faces_of_cubemaps_into_levels() {
+        // Each face has a 4x4 level and a 2x2 one, of 64 and 16 bytes of RGBA.
+        let mut bytes = dx10_header([4, 4], 2, 29, true);
+        for face in 0..6 {
+            bytes.extend([face; 64]);
+            bytes.extend([face + 10; 16]);
+        }
+        let texture = decode(&bytes).unwrap();
+
+        assert!(texture.cube);
+        assert_eq!(texture.levels[0].len(), 6 * 64);
+        assert_eq!(texture.levels[1].len(), 6 * 16);
+        assert_eq!(texture.levels[0][64], 1);
+        assert_eq!(texture.levels[1][5 * 16], 15);
+    }
+
+    #[test]
+    fn reads_legacy_files() {
+        // DXT5 of 6x6, whose levels are 2x2, 1x1 and 1x1 blocks of 16 bytes.
+        let mut bytes = header([6, 6], 3, DDPF_FOURCC, b"DXT5", 0, [0; 5]);
+        bytes.extend([0; 64 + 16 + 16]);
+        let texture = decode(&bytes).unwrap();
+        assert_eq!(texture.format, Format::BC3_SRGB_BLOCK);
+        let sizes: Vec<_> = texture.levels.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [64, 16, 16]);
+
+        // DXT1 of 8 bytes a block, down to 1x1.
+        let mut bytes = header([4, 4], 3, DDPF_FOURCC, b"DXT1", 0, [0; 5]);
+        bytes.extend([0; 3 * 8]);
+        let texture = decode(&bytes).unwrap();
+        assert_eq!(texture.format, Format::BC1_RGBA_SRGB_BLOCK);
+        let sizes: Vec<_> = texture.levels.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [8, 8, 8]);
+
+        let bgra = [32, 0xff_0000, 0xff00, 0xff, 0xff00_0000];
+        let mut bytes = header([2, 2], 1, DDPF_RGB, &[0; 4], 0, bgra);
+        bytes.extend([0; 16]);
+        assert_eq!(decode(&bytes).unwrap().format, Format::B8G8R8A8_SRGB);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let mut bytes = dx10_header([8, 8], 2, 99, false);
+        bytes.extend([0; 64 + 15]);
+        assert!(matches!(decode(&bytes), Err(DdsError::Malformed)));
+
+        // Files that end anywhere in the headers.
+        for len in [8, 82, 86, HEADER_END + 2] {
+            assert!(matches!(decode(&bytes[..len]), Err(DdsError::Malformed)));
+        }
+        assert!(matches!(decode(b"PNG"), Err(DdsError::NotDds)));
+    }
+
+    #[test]
+    fn rejects_what_it_cant_read() {
+        let bytes = header([4, 4], 1, DDPF_FOURCC, b"ETC1", 0, [0; 5]);
+        assert!(matches!(
+            decode(&bytes),
+            Err(DdsError::UnsupportedFourCc(code)) if &code == b"ETC1"
+        ));
+        let bytes = header([4, 4], 1, 0, &[0; 4], DDSCAPS2_VOLUME, [0; 5]);
+        assert!(matches!(decode(&bytes), Err(DdsError::UnsupportedShape)));
+        let bytes = dx10_header([4, 4], 1, 2, false);
+        assert!(matches!(
+            decode(&bytes),
+            Err(DdsError::UnsupportedDxgiFormat(2))
+        ));
+        // A 4x4 texture has 3 levels at most.
+        let bytes = dx10_header([4, 4], 4, 29, false);
+        assert!(matches!(decode(&bytes), Err(DdsError::Malformed)));
+    }
+}
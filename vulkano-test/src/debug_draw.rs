@@ -0,0 +1,326 @@
+// Immediate-mode debug drawing. Shapes are pushed into a `DebugDraw` from anywhere during the
+// frame, then `DebugDrawPipeline::draw` uploads the whole batch into a transient vertex buffer,
+// records a single line-list draw and clears the batch for the next frame. Lines are a logical
+// pixel wide where the device supports wide lines, so that they stay as visible on high-DPI
+// displays, and a physical pixel wide otherwise.
+
+use glam::{Mat4, Vec3, Vec4};
+use std::{f32::consts::TAU, sync::Arc};
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        BufferContents, BufferUsage,
+    },
+    command_buffer::AutoCommandBufferBuilder,
+    device::{DeviceFeatures, DeviceOwned},
+    memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState},
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        DynamicState, GraphicsPipeline, Pipeline, PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+};
+
+use crate::{
+    debug_utils,
+    device_requirements::{Capabilities, DeviceRequirements},
+    error::AppError,
+    gamma, offscreen,
+    shader::{self, ShaderStage},
+};
+
+/// Number of segments used for each circle of a wire sphere.
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[derive(BufferContents, Vertex, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct DebugVertex {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+    #[format(R32G32B32A32_SFLOAT)]
+    pub color: [f32; 4],
+}
+
+/// A batch of debug lines, rebuilt every frame.
+#[derive(Default)]
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self, from: Vec3, to: Vec3, color: Vec4) {
+        let color = color.to_array();
+        self.vertices.push(DebugVertex {
+            position: from.to_array(),
+            color,
+        });
+        self.vertices.push(DebugVertex {
+            position: to.to_array(),
+            color,
+        });
+    }
+
+    /// Draws the edges of the axis-aligned box spanning `min` to `max`.
+    pub fn wire_box(&mut self, min: Vec3, max: Vec3, color: Vec4) {
+        let corners: [Vec3; 8] =
+            std::array::from_fn(|i| Vec3::select(box_corner_mask(i), max, min));
+        self.box_edges(&corners, color);
+    }
+
+    /// Draws three great circles of the sphere, one per axis plane.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec4) {
+        for (u, v) in [(Vec3::X, Vec3::Y), (Vec3::Y, Vec3::Z), (Vec3::Z, Vec3::X)] {
+            self.ellipse(center, u * radius, v * radius, color);
+        }
+    }
+
+    /// Draws the circle around `axis`, which must have unit length, through the points `radius`
+    /// away from `center` in the plane the axis is normal to.
+    pub fn circle(&mut self, center: Vec3, axis: Vec3, radius: f32, color: Vec4) {
+        let (u, v) = axis.any_orthonormal_pair();
+        self.ellipse(center, u * radius, v * radius, color);
+    }
+
+    /// Draws the ellipse through `center + u` and `center + v`, with those as its half axes.
+    fn ellipse(&mut self, center: Vec3, u: Vec3, v: Vec3, color: Vec4) {
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+            center + u * angle.cos() + v * angle.sin()
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    /// Draws the X, Y and Z axes of `transform` in red, green and blue.
+    pub fn axes(&mut self, transform: Mat4, size: f32) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        for (axis, color) in [
+            (Vec3::X, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            (Vec3::Y, Vec4::new(0.0, 1.0, 0.0, 1.0)),
+            (Vec3::Z, Vec4::new(0.0, 0.0, 1.0, 1.0)),
+        ] {
+            self.line(origin, transform.transform_point3(axis * size), color);
+        }
+    }
+
+    /// Draws the frustum described by a view-projection matrix, e.g. another camera's.
+    pub fn frustum(&mut self, view_proj: Mat4, color: Vec4) {
+        let inverse = view_proj.inverse();
+        // Vulkan clip space: X and Y in [-1, 1], depth in [0, 1].
+        let corners: [Vec3; 8] = std::array::from_fn(|i| {
+            let ndc = Vec3::select(box_corner_mask(i), Vec3::ONE, Vec3::new(-1.0, -1.0, 0.0));
+            inverse.project_point3(ndc)
+        });
+        self.box_edges(&corners, color);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Draws the 12 edges between `corners`, indexed as in `box_corner_mask`.
+    fn box_edges(&mut self, corners: &[Vec3; 8], color: Vec4) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+}
+
+/// Bit 0 of `i` selects the max X of a box corner, bit 1 the max Y and bit 2 the max Z.
+fn box_corner_mask(i: usize) -> glam::BVec3 {
+    glam::BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0)
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    view_proj: [[f32; 4]; 4],
+}
+
+/// The line-list pipeline and transient vertex memory used to render a `DebugDraw` batch.
+pub struct DebugDrawPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+    vertex_buffer_allocator: SubbufferAllocator,
+    /// The range of line widths the device supports, if it supports more than one.
+    line_width_range: Option<[f32; 2]>,
+}
+
+impl DebugDrawPipeline {
+    /// Asks for wide lines where supported.
+    pub fn register_requirements(requirements: &mut DeviceRequirements) {
+        requirements.request_features(DeviceFeatures {
+            wide_lines: true,
+            ..DeviceFeatures::empty()
+        });
+    }
+
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/debug_line.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/debug_line.frag"),
+            ShaderStage::Fragment,
+            &gamma::encoding_defines(&subpass),
+        )?
+        .entry_point("main")
+        .unwrap();
+
+        let vertex_input_state = DebugVertex::per_vertex().definition(&vs).unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+
+        // Lines are drawn on top of everything, so the depth test always passes when the subpass
+        // has a depth attachment at all.
+        let depth_stencil_state = subpass
+            .subpass_desc()
+            .depth_stencil_attachment
+            .is_some()
+            .then(|| DepthStencilState {
+                depth: Some(DepthState::default()),
+                ..Default::default()
+            });
+
+        let line_width_range = Capabilities::of(&device)
+            .wide_lines
+            .then(|| device.physical_device().properties().line_width_range);
+        let mut dynamic_state = vec![DynamicState::Viewport];
+        if line_width_range.is_some() {
+            dynamic_state.push(DynamicState::LineWidth);
+        }
+
+        let pipeline = GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState {
+                    topology: PrimitiveTopology::LineList,
+                    ..Default::default()
+                }),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state,
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(offscreen::color_blend_state(
+                    &subpass,
+                    &[ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    }],
+                )),
+                dynamic_state: dynamic_state.into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "debug draw");
+
+        let vertex_buffer_allocator = SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::VERTEX_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        Ok(DebugDrawPipeline {
+            pipeline,
+            vertex_buffer_allocator,
+            line_width_range,
+        })
+    }
+
+    /// Records the batched lines into the current subpass once for each of `views`, seen through
+    /// its view and projection in its viewport, and empties `debug_draw`. `scale_factor` is the
+    /// number of physical pixels per logical pixel of the target.
+    pub fn draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        debug_draw: &mut DebugDraw,
+        views: &[(Mat4, Viewport)],
+        scale_factor: f32,
+    ) {
+        if debug_draw.is_empty() {
+            return;
+        }
+
+        let vertex_buffer = self
+            .vertex_buffer_allocator
+            .allocate_slice(debug_draw.vertices.len() as _)
+            .unwrap();
+        vertex_buffer
+            .write()
+            .unwrap()
+            .copy_from_slice(&debug_draw.vertices);
+
+        builder
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_vertex_buffers(0, vertex_buffer.clone())
+            .unwrap();
+        if let Some([min, max]) = self.line_width_range {
+            builder
+                .set_line_width(scale_factor.clamp(min, max))
+                .unwrap();
+        }
+
+        for (view_proj, viewport) in views {
+            builder
+                .set_viewport(0, [viewport.clone()].into_iter().collect())
+                .unwrap()
+                .push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    PushConstants {
+                        view_proj: view_proj.to_cols_array_2d(),
+                    },
+                )
+                .unwrap();
+            // SAFETY: the vertex buffer holds exactly `vertex_buffer.len()` vertices and the
+            // shaders don't access any other resources.
+            unsafe { builder.draw(vertex_buffer.len() as u32, 1, 0, 0) }.unwrap();
+        }
+
+        debug_draw.clear();
+    }
+}
@@ -0,0 +1,53 @@
+// Names for validation messages and captures. Where the instance has `VK_EXT_debug_utils`, the
+// long-lived Vulkan objects are named after what they are for: the swapchain, its images and
+// framebuffers, every pipeline, acceleration structures and the buffers of meshes and
+// particles. Each pass of a frame in the window is recorded in a labeled region of the command
+// buffer. Validation messages then say which object or pass they are about, and tools such as
+// RenderDoc show the frame as a tree of passes.
+//
+// Without the extension, which the loader only lacks on unusual systems, nothing is named or
+// labeled.
+
+use tracing::debug;
+use vulkano::{
+    command_buffer::AutoCommandBufferBuilder, device::DeviceOwned,
+    instance::debug::DebugUtilsLabel, VulkanObject,
+};
+
+/// Names `object`, for validation messages and captures.
+pub fn name<T: VulkanObject + DeviceOwned>(object: &T, name: &str) {
+    let device = object.device();
+    if !device.instance().enabled_extensions().ext_debug_utils {
+        return;
+    }
+    // The names only help debugging, so failing to set one is no reason to stop.
+    if let Err(err) = device.set_debug_utils_object_name(object, Some(name)) {
+        debug!("Failed to name {name}: {err}");
+    }
+}
+
+/// Records `record` into `builder` in a region labeled `label`.
+pub fn labeled<L, R>(
+    builder: &mut AutoCommandBufferBuilder<L>,
+    label: &str,
+    record: impl FnOnce(&mut AutoCommandBufferBuilder<L>) -> R,
+) -> R {
+    if !builder
+        .device()
+        .instance()
+        .enabled_extensions()
+        .ext_debug_utils
+    {
+        return record(builder);
+    }
+    builder
+        .begin_debug_utils_label(DebugUtilsLabel {
+            label_name: label.to_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+    let result = record(builder);
+    // SAFETY: the region was begun above, in the same command buffer.
+    unsafe { builder.end_debug_utils_label() }.unwrap();
+    result
+}
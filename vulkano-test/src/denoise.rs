@@ -0,0 +1,298 @@
+// A denoiser for traced images of few samples, after SVGF: a temporal pass followed by a few
+// spatial ones, all compute shaders. The window runs it over the path traced view until there are
+// many samples, and `N` turns it off and on again.
+//
+// The temporal pass (`shaders/denoise_temporal.comp`) finds where each pixel's surface was in the
+// last frame, using the guide image of normals and distances that the tracer wrote for both
+// frames, and blends the pixel with the last result there. Where the surface wasn't seen before,
+// the history starts over. Along with the color, it keeps the mean and mean square of the
+// luminance over time, and from them the variance of every pixel, which is estimated from the
+// pixels around it instead while the history is short.
+//
+// The spatial passes (`shaders/denoise_atrous.comp`) then filter the result with an à-trous
+// wavelet, `ATROUS_ITERATIONS` times with the taps twice as far apart each time. The weights stop
+// at edges in the guide and at differences in luminance beyond what the variance explains, so
+// noisy pixels are smoothed a lot and converged ones hardly at all.
+//
+// There is no render graph to make these nodes of, so the passes are recorded in turn, like those
+// of `BlurFilter`, and vulkano inserts the barriers between them.
+
+use glam::{Mat4, Vec3};
+use std::sync::Arc;
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::{AutoCommandBufferBuilder, CopyImageInfo},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::{Device, DeviceOwned},
+    format::Format,
+    image::{view::ImageView, Image, ImageUsage},
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        compute::ComputePipelineCreateInfo, ComputePipeline, Pipeline, PipelineBindPoint,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    debug_utils,
+    error::AppError,
+    ray_tracing,
+    shader::{self, ShaderStage},
+};
+
+/// How many spatial passes filter the result of the temporal one.
+pub const ATROUS_ITERATIONS: u32 = 4;
+
+/// The size of the workgroups along both dimensions, which the shaders declare too.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// The format of every image the denoiser writes.
+const FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct TemporalPushConstants {
+    inverse_view_proj: [[f32; 4]; 4],
+    previous_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    previous_eye: [f32; 4],
+    input_samples: u32,
+    has_history: u32,
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct AtrousPushConstants {
+    step: i32,
+    last: u32,
+}
+
+/// The images the denoiser keeps, of one size.
+struct Images {
+    /// The results of the temporal pass, of the last frame and of this one by turns.
+    integrated: [Arc<ImageView>; 2],
+    /// The moments of the luminance, by turns as well.
+    moments: [Arc<ImageView>; 2],
+    /// The guide of the last frame.
+    previous_guide: Arc<ImageView>,
+    /// The spatial passes write into these in turn.
+    filtered: [Arc<ImageView>; 2],
+}
+
+pub struct Denoiser {
+    temporal_pipeline: Arc<ComputePipeline>,
+    atrous_pipeline: Arc<ComputePipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    /// Recreated when the size of the input changes.
+    images: Option<Images>,
+    /// The camera of the last frame, or `None` if there is no history to blend with.
+    previous_view: Option<(Mat4, Vec3)>,
+    /// Which of the integrated and moments images the last frame wrote.
+    current: usize,
+}
+
+impl Denoiser {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        Ok(Denoiser {
+            temporal_pipeline: compute_pipeline::<TemporalPushConstants>(
+                device.clone(),
+                include_str!("shaders/denoise_temporal.comp"),
+                "denoise temporal",
+            )?,
+            atrous_pipeline: compute_pipeline::<AtrousPushConstants>(
+                device,
+                include_str!("shaders/denoise_atrous.comp"),
+                "denoise a-trous",
+            )?,
+            memory_allocator,
+            descriptor_set_allocator,
+            images: None,
+            previous_view: None,
+            current: 0,
+        })
+    }
+
+    /// Drops the history, so that the next frame is denoised as if it were the first. This is
+    /// for when the frames in between weren't denoised.
+    pub fn reset(&mut self) {
+        self.previous_view = None;
+    }
+
+    /// Records the denoising of `color`, which averages `input_samples` samples of every pixel,
+    /// seen through `view_proj` from `eye`. `guide` holds the normal of the surface that each
+    /// pixel sees, with its distance from the eye in alpha, which is negative for the sky. Both
+    /// must be storage images of the same size, and the guide must be transferable from. Returns
+    /// the image holding the result, which is only valid until the next call.
+    pub fn apply<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        color: &Arc<ImageView>,
+        guide: &Arc<ImageView>,
+        input_samples: u32,
+        view_proj: Mat4,
+        eye: Vec3,
+    ) -> Arc<Image> {
+        let [width, height, _] = color.image().extent();
+        if self
+            .images
+            .as_ref()
+            .is_none_or(|images| images.previous_guide.image().extent() != color.image().extent())
+        {
+            let image = |usage| {
+                ray_tracing::storage_image(
+                    self.memory_allocator.clone(),
+                    FORMAT,
+                    [width, height],
+                    usage,
+                )
+            };
+            self.images = Some(Images {
+                integrated: [image(ImageUsage::empty()), image(ImageUsage::empty())],
+                moments: [image(ImageUsage::empty()), image(ImageUsage::empty())],
+                previous_guide: image(ImageUsage::TRANSFER_DST),
+                filtered: [
+                    image(ImageUsage::TRANSFER_SRC),
+                    image(ImageUsage::TRANSFER_SRC),
+                ],
+            });
+            self.previous_view = None;
+        }
+        let images = self.images.as_ref().unwrap();
+        let extent = [width, height];
+
+        let (previous, current) = (self.current, 1 - self.current);
+        let (previous_view_proj, previous_eye) = self.previous_view.unwrap_or((view_proj, eye));
+        builder
+            .bind_pipeline_compute(self.temporal_pipeline.clone())
+            .unwrap();
+        dispatch(
+            builder,
+            &self.temporal_pipeline,
+            &self.descriptor_set_allocator,
+            [
+                color,
+                guide,
+                &images.previous_guide,
+                &images.integrated[previous],
+                &images.moments[previous],
+                &images.integrated[current],
+                &images.moments[current],
+            ],
+            TemporalPushConstants {
+                inverse_view_proj: view_proj.inverse().to_cols_array_2d(),
+                previous_view_proj: previous_view_proj.to_cols_array_2d(),
+                eye: eye.extend(1.0).to_array(),
+                previous_eye: previous_eye.extend(1.0).to_array(),
+                input_samples,
+                has_history: self.previous_view.is_some() as u32,
+            },
+            extent,
+        );
+        // The next frame reprojects onto this one's surfaces.
+        builder
+            .copy_image(CopyImageInfo::images(
+                guide.image().clone(),
+                images.previous_guide.image().clone(),
+            ))
+            .unwrap();
+
+        builder
+            .bind_pipeline_compute(self.atrous_pipeline.clone())
+            .unwrap();
+        let mut input = &images.integrated[current];
+        for iteration in 0..ATROUS_ITERATIONS {
+            let output = &images.filtered[iteration as usize % 2];
+            dispatch(
+                builder,
+                &self.atrous_pipeline,
+                &self.descriptor_set_allocator,
+                [input, guide, output],
+                AtrousPushConstants {
+                    step: 1 << iteration,
+                    last: (iteration + 1 == ATROUS_ITERATIONS) as u32,
+                },
+                extent,
+            );
+            input = output;
+        }
+
+        self.current = current;
+        self.previous_view = Some((view_proj, eye));
+        input.image().clone()
+    }
+}
+
+/// Records one pass of `pipeline`, which must be bound, with `images` at bindings in order and a
+/// workgroup for every tile of `extent`.
+fn dispatch<L, P: BufferContents, const N: usize>(
+    builder: &mut AutoCommandBufferBuilder<L>,
+    pipeline: &Arc<ComputePipeline>,
+    descriptor_set_allocator: &Arc<StandardDescriptorSetAllocator>,
+    images: [&Arc<ImageView>; N],
+    push_constants: P,
+    extent: [u32; 2],
+) {
+    let layout = &pipeline.layout().set_layouts()[0];
+    let descriptor_set = DescriptorSet::new(
+        descriptor_set_allocator.clone(),
+        layout.clone(),
+        images
+            .into_iter()
+            .enumerate()
+            .map(|(binding, image)| WriteDescriptorSet::image_view(binding as u32, image.clone())),
+        [],
+    )
+    .unwrap();
+
+    builder
+        .bind_descriptor_sets(
+            PipelineBindPoint::Compute,
+            pipeline.layout().clone(),
+            0,
+            descriptor_set,
+        )
+        .unwrap()
+        .push_constants(pipeline.layout().clone(), 0, push_constants)
+        .unwrap();
+
+    // SAFETY: the shaders only load and store inside the images, which are all the size of the
+    // input, and skip the invocations of the last workgroups that fall outside of it.
+    unsafe {
+        builder.dispatch([
+            extent[0].div_ceil(WORKGROUP_SIZE),
+            extent[1].div_ceil(WORKGROUP_SIZE),
+            1,
+        ])
+    }
+    .unwrap();
+}
+
+fn compute_pipeline<P>(
+    device: Arc<Device>,
+    source: &str,
+    name: &str,
+) -> Result<Arc<ComputePipeline>, AppError>
+where
+    P: BufferContents,
+{
+    let cs = shader::load(device.clone(), source, ShaderStage::Compute)?
+        .entry_point("main")
+        .unwrap();
+    let stage = PipelineShaderStageCreateInfo::new(cs);
+    let layout = shader::reflect_layout::<P>(device.clone(), std::slice::from_ref(&stage))?;
+    ComputePipeline::new(
+        device,
+        None,
+        ComputePipelineCreateInfo::stage_layout(stage, layout),
+    )
+    .map_err(AppError::Pipeline)
+    .inspect(|pipeline| debug_utils::name(pipeline, name))
+}
@@ -0,0 +1,254 @@
+// What the device has to support and what it should enable if it can. Each subsystem registers its
+// own needs before the device is created: required extensions and features rule out devices that
+// lack them, while optional ones are enabled wherever they are supported. What ended up enabled is
+// summed up by `Capabilities`, which is what the renderers branch on.
+//
+// Requirements can also ask for a software implementation of Vulkan, such as lavapipe or
+// SwiftShader, which is what lets rendering run on machines without a GPU.
+
+use vulkano::{
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        Device, DeviceExtensions, DeviceFeatures,
+    },
+    Version,
+};
+
+use crate::{bindless, gpu_culling, ray_tracing, shadows};
+
+#[derive(Clone, Debug, Default)]
+pub struct DeviceRequirements {
+    required_extensions: DeviceExtensions,
+    required_features: DeviceFeatures,
+    optional_extensions: DeviceExtensions,
+    optional_features: DeviceFeatures,
+    optional_promoted: Vec<Promoted>,
+    software: Software,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum Software {
+    #[default]
+    Either,
+    Preferred,
+    Required,
+}
+
+/// Optional features that are core since `version`, and need `extensions` before it, or always if
+/// they were never promoted.
+#[derive(Clone, Debug)]
+struct Promoted {
+    version: Option<Version>,
+    features: DeviceFeatures,
+    extensions: DeviceExtensions,
+}
+
+impl DeviceRequirements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only considers devices that support all of `extensions`.
+    pub fn require_extensions(&mut self, extensions: DeviceExtensions) -> &mut Self {
+        self.required_extensions = self.required_extensions.union(&extensions);
+        self
+    }
+
+    /// Only considers devices that support all of `features`, which must be core features of
+    /// Vulkan 1.0.
+    pub fn require_features(&mut self, features: DeviceFeatures) -> &mut Self {
+        self.required_features = self.required_features.union(&features);
+        self
+    }
+
+    /// Enables whichever of `extensions` the device supports.
+    pub fn request_extensions(&mut self, extensions: DeviceExtensions) -> &mut Self {
+        self.optional_extensions = self.optional_extensions.union(&extensions);
+        self
+    }
+
+    /// Enables whichever of `features` the device supports. These must be core features of
+    /// Vulkan 1.0 or portability subset features; see `request_promoted_features` for later ones
+    /// and `request_extension_features` for those of extensions.
+    pub fn request_features(&mut self, features: DeviceFeatures) -> &mut Self {
+        self.optional_features = self.optional_features.union(&features);
+        self
+    }
+
+    /// Enables `features` if the device supports them, either as part of Vulkan `version` or
+    /// through `extensions` on older versions, which are then enabled as well.
+    pub fn request_promoted_features(
+        &mut self,
+        version: Version,
+        features: DeviceFeatures,
+        extensions: DeviceExtensions,
+    ) -> &mut Self {
+        self.optional_promoted.push(Promoted {
+            version: Some(version),
+            features,
+            extensions,
+        });
+        self
+    }
+
+    /// Enables `features` along with `extensions`, which they belong to, if the device supports
+    /// all of them.
+    pub fn request_extension_features(
+        &mut self,
+        features: DeviceFeatures,
+        extensions: DeviceExtensions,
+    ) -> &mut Self {
+        self.optional_promoted.push(Promoted {
+            version: None,
+            features,
+            extensions,
+        });
+        self
+    }
+
+    /// Picks a software implementation over hardware ones where there is one.
+    pub fn prefer_software(&mut self) -> &mut Self {
+        self.software = self.software.max(Software::Preferred);
+        self
+    }
+
+    /// Only considers software implementations.
+    pub fn require_software(&mut self) -> &mut Self {
+        self.software = Software::Required;
+        self
+    }
+
+    /// Whether a software implementation should be picked where there is one.
+    pub fn prefers_software(&self) -> bool {
+        self.software != Software::Either
+    }
+
+    /// Whether `physical_device` has everything that is required.
+    pub fn is_supported_by(&self, physical_device: &PhysicalDevice) -> bool {
+        (self.software != Software::Required || is_software(physical_device))
+            && physical_device
+                .supported_extensions()
+                .contains(&self.required_extensions)
+            && physical_device
+                .supported_features()
+                .contains(&self.required_features)
+    }
+
+    /// The extensions and features to enable on `physical_device`: the required ones, and the
+    /// optional ones it supports.
+    pub fn enabled_for(
+        &self,
+        physical_device: &PhysicalDevice,
+    ) -> (DeviceExtensions, DeviceFeatures) {
+        let supported_extensions = physical_device.supported_extensions();
+        let supported_features = physical_device.supported_features();
+
+        let mut extensions = self
+            .required_extensions
+            .union(&supported_extensions.intersection(&self.optional_extensions));
+        let mut features = self
+            .required_features
+            .union(&supported_features.intersection(&self.optional_features));
+
+        for promoted in &self.optional_promoted {
+            if !supported_features.contains(&promoted.features) {
+                continue;
+            }
+            if promoted
+                .version
+                .is_some_and(|version| physical_device.api_version() >= version)
+            {
+                features = features.union(&promoted.features);
+            } else if supported_extensions.contains(&promoted.extensions) {
+                features = features.union(&promoted.features);
+                extensions = extensions.union(&promoted.extensions);
+            }
+        }
+
+        // Portability subset devices only work with the extension enabled, and only report the
+        // portability subset features as supported.
+        if supported_extensions.khr_portability_subset {
+            extensions.khr_portability_subset = true;
+        }
+
+        (extensions, features)
+    }
+}
+
+/// Whether `physical_device` runs on the CPU.
+pub fn is_software(physical_device: &PhysicalDevice) -> bool {
+    physical_device.properties().device_type == PhysicalDeviceType::Cpu
+}
+
+/// The optional functionality that is enabled on a device.
+#[derive(Clone, Copy, Debug)]
+pub struct Capabilities {
+    pub dynamic_rendering: bool,
+    pub timeline_semaphores: bool,
+    pub sampler_anisotropy: bool,
+    /// Whether polygons can be drawn as lines, for the wireframe view.
+    pub wireframe: bool,
+    /// Whether lines can be drawn wider than a pixel.
+    pub wide_lines: bool,
+    /// Whether buffers can be referred to by their address in device memory, as `Mesh` buffers
+    /// then are.
+    pub buffer_device_address: bool,
+    /// Whether meshes can be drawn from their meshlets with task and mesh shaders.
+    pub mesh_shading: bool,
+    /// Whether the scene can be ray traced, through acceleration structures of its meshes.
+    pub ray_tracing: bool,
+    /// Whether fragment shaders can trace shadow rays through acceleration structures of the
+    /// scene.
+    pub ray_query_shadows: bool,
+    /// Whether textures can be indexed from one global array, rather than bound for each draw.
+    pub bindless: bool,
+    /// Whether the scene can be culled on the GPU and drawn with indirect draws of many
+    /// instances.
+    pub gpu_culling: bool,
+    /// Whether indirect draws can read how many there are from a buffer.
+    pub draw_indirect_count: bool,
+    /// Whether pipelines can have tessellation shaders, which subdivide the terrain.
+    pub tessellation: bool,
+    /// Whether `VK_GOOGLE_display_timing` can tell the refresh rate of the display.
+    pub display_timing: bool,
+    /// Whether `VK_EXT_swapchain_maintenance1` lets swapchains switch present modes and choose how
+    /// their images are scaled.
+    pub swapchain_maintenance: bool,
+    /// Whether presents can be given IDs that `VK_KHR_present_wait` waits for.
+    pub present_wait: bool,
+    /// Whether `VK_EXT_memory_budget` tells how much memory the process can use from each heap.
+    pub memory_budget: bool,
+    /// Whether 2D images can be sparse, with memory bound to only some of their mip levels, as
+    /// streamed textures then are.
+    pub sparse_residency: bool,
+    /// Whether the device is a portability subset device, missing parts of Vulkan that aren't
+    /// enabled through features.
+    pub portability_subset: bool,
+}
+
+impl Capabilities {
+    pub fn of(device: &Device) -> Self {
+        let features = device.enabled_features();
+        Capabilities {
+            dynamic_rendering: features.dynamic_rendering,
+            timeline_semaphores: features.timeline_semaphore,
+            sampler_anisotropy: features.sampler_anisotropy,
+            wireframe: features.fill_mode_non_solid,
+            wide_lines: features.wide_lines,
+            buffer_device_address: features.buffer_device_address,
+            mesh_shading: features.mesh_shader && features.task_shader,
+            ray_tracing: features.contains(&ray_tracing::FEATURES),
+            ray_query_shadows: features.contains(&shadows::FEATURES),
+            bindless: features.contains(&bindless::FEATURES),
+            gpu_culling: features.contains(&gpu_culling::FEATURES),
+            draw_indirect_count: features.draw_indirect_count,
+            tessellation: features.tessellation_shader,
+            display_timing: device.enabled_extensions().google_display_timing,
+            swapchain_maintenance: features.swapchain_maintenance1,
+            present_wait: features.present_id && features.present_wait,
+            memory_budget: device.enabled_extensions().ext_memory_budget,
+            sparse_residency: features.sparse_binding && features.sparse_residency_image2_d,
+            portability_subset: device.enabled_extensions().khr_portability_subset,
+        }
+    }
+}
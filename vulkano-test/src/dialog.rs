@@ -0,0 +1,53 @@
+// A native message box for errors that stop the windowed app, so that someone who started it from
+// a file manager learns what went wrong instead of seeing the window vanish. On Linux the dialog
+// is shown by running zenity; where that isn't installed, the log remains the only report. There
+// is no dialog on Android, where the activity just finishes.
+
+#[cfg(not(target_os = "android"))]
+use rfd::{MessageButtons, MessageDialog, MessageLevel};
+
+use crate::error::AppError;
+
+/// Shows `err` along with what might fix it, and waits for the dialog to be closed.
+#[cfg(not(target_os = "android"))]
+pub fn show_fatal_error(err: &AppError) {
+    let mut description = err.to_string();
+    if let Some(hint) = hint(err) {
+        description.push_str("\n\n");
+        description.push_str(hint);
+    }
+
+    MessageDialog::new()
+        .set_level(MessageLevel::Error)
+        .set_title("vulkano-test")
+        .set_description(description)
+        .set_buttons(MessageButtons::Ok)
+        .show();
+}
+
+#[cfg(target_os = "android")]
+pub fn show_fatal_error(_err: &AppError) {}
+
+/// Suggestions for the errors that are usually down to the system rather than the app.
+#[cfg_attr(target_os = "android", allow(dead_code))]
+fn hint(err: &AppError) -> Option<&'static str> {
+    match err {
+        AppError::Library(_) => Some(
+            "No Vulkan driver was found. Install the Vulkan driver for your graphics card (on \
+             Linux, Mesa or the vendor driver along with the Vulkan loader; on macOS, MoltenVK).",
+        ),
+        AppError::Instance(_) => {
+            Some("The Vulkan driver refused to start. Updating the graphics driver usually helps.")
+        }
+        AppError::NoDevice => Some(
+            "No graphics card supports what the app needs, which is Vulkan rendering to a \
+             window. Update the graphics driver, or install a software renderer such as \
+             lavapipe on machines without a GPU.",
+        ),
+        AppError::DeviceLost => Some(
+            "The graphics driver stopped responding. Updating it, or closing other programs \
+             that use the GPU heavily, may help.",
+        ),
+        _ => None,
+    }
+}
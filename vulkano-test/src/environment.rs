@@ -0,0 +1,318 @@
+// Environment maps, for the skybox and image-based lighting. An equirectangular HDR image, as
+// `hdr` loads one, is converted into a cubemap on the GPU when it is loaded: one compute pass of
+// `shaders/equirect_to_cube.comp` resamples it into the six faces, a quarter of the image wide
+// each, so that a face has about as many texels across as the image has over the same angle.
+//
+// An irradiance cubemap can be made along with it, by `shaders/irradiance.comp`: each texel holds
+// the light that a white diffuse surface facing that way reflects, convolved over the hemisphere
+// around it. The light barely changes between neighbouring directions, so the faces are small,
+// and each sample reads a mip level of the image about as large as the patch of the sky it
+// stands for instead of the texels in between.
+//
+// Both passes are recorded into one command buffer, which is waited on before the environment is
+// returned.
+
+use std::{path::Path, sync::Arc};
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
+    },
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::{Device, DeviceOwned, Queue},
+    image::{
+        sampler::{
+            Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode,
+            LOD_CLAMP_NONE,
+        },
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        Image, ImageCreateFlags, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, ComputePipeline, Pipeline, PipelineBindPoint,
+        PipelineShaderStageCreateInfo,
+    },
+    sync::{self, GpuFuture},
+};
+
+use crate::{
+    debug_utils,
+    error::AppError,
+    hdr,
+    shader::{self, ShaderStage},
+    texture::Texture,
+};
+
+/// The sides of the faces of environment cubemaps, which are a quarter of the width of the
+/// equirectangular image within these.
+const MIN_FACE_SIZE: u32 = 16;
+const MAX_FACE_SIZE: u32 = 2048;
+
+/// The side of the faces of irradiance cubemaps.
+const IRRADIANCE_FACE_SIZE: u32 = 32;
+
+/// How many directions around the normal the irradiance of each texel is sampled in.
+const AZIMUTH_STEPS: u32 = 64;
+
+/// The side of the square workgroups of both passes.
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct IrradiancePushConstants {
+    lod: f32,
+}
+
+pub struct Environment {
+    /// The environment, for the skybox.
+    pub cubemap: Arc<Texture>,
+    /// The light that diffuse surfaces facing each way reflect, for image-based lighting, if it
+    /// was asked for.
+    pub irradiance: Option<Arc<Texture>>,
+}
+
+impl Environment {
+    /// Loads the equirectangular Radiance or OpenEXR image at `path` and converts it, along with
+    /// its irradiance if `irradiance`.
+    pub fn load(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+        path: &Path,
+        irradiance: bool,
+    ) -> Result<Self, AppError> {
+        let image = hdr::open(path).map_err(|err| AppError::Environment {
+            path: path.to_owned(),
+            source: err,
+        })?;
+        let data = hdr::texture_data(image, hdr::can_sample_float(queue.device()));
+        let equirect = Texture::from_data(
+            memory_allocator.clone(),
+            command_buffer_allocator.clone(),
+            queue,
+            &data,
+        );
+        Self::from_equirect(
+            memory_allocator,
+            descriptor_set_allocator,
+            command_buffer_allocator,
+            queue,
+            &equirect,
+            irradiance,
+        )
+    }
+
+    /// Converts `equirect`, an equirectangular texture with a mip chain, into a cubemap, along
+    /// with its irradiance if `irradiance`, and waits for the conversion to finish.
+    pub fn from_equirect(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+        equirect: &Texture,
+        irradiance: bool,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+        let [width, _, _] = equirect.image.extent();
+
+        // The longitude wraps around, and the latitude stops at the poles.
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                mipmap_mode: SamplerMipmapMode::Linear,
+                address_mode: [
+                    SamplerAddressMode::Repeat,
+                    SamplerAddressMode::ClampToEdge,
+                    SamplerAddressMode::ClampToEdge,
+                ],
+                lod: 0.0..=LOD_CLAMP_NONE,
+                ..Default::default()
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        let cubemap = cubemap(
+            &memory_allocator,
+            (width / 4).clamp(MIN_FACE_SIZE, MAX_FACE_SIZE),
+            "environment",
+        );
+        let pipeline = compute_pipeline::<()>(
+            device.clone(),
+            include_str!("shaders/equirect_to_cube.comp"),
+            &[],
+            "equirect to cube",
+        )?;
+        dispatch(
+            &mut builder,
+            &pipeline,
+            &descriptor_set_allocator,
+            equirect,
+            &sampler,
+            &cubemap,
+        );
+
+        let irradiance = if irradiance {
+            let irradiance = self::cubemap(&memory_allocator, IRRADIANCE_FACE_SIZE, "irradiance");
+            let pipeline = compute_pipeline::<IrradiancePushConstants>(
+                device,
+                include_str!("shaders/irradiance.comp"),
+                &[("AZIMUTH_STEPS", AZIMUTH_STEPS.to_string())],
+                "irradiance",
+            )?;
+            builder
+                .push_constants(
+                    pipeline.layout().clone(),
+                    0,
+                    IrradiancePushConstants {
+                        // The level whose texels are as wide as a step of azimuth at the equator.
+                        lod: (width as f32 / AZIMUTH_STEPS as f32).log2().max(0.0),
+                    },
+                )
+                .unwrap();
+            dispatch(
+                &mut builder,
+                &pipeline,
+                &descriptor_set_allocator,
+                equirect,
+                &sampler,
+                &irradiance,
+            );
+            Some(irradiance)
+        } else {
+            None
+        };
+
+        let command_buffer = builder.build().unwrap();
+        sync::now(queue.device().clone())
+            .then_execute(queue.clone(), command_buffer)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        Ok(Environment {
+            cubemap,
+            irradiance,
+        })
+    }
+}
+
+/// Creates a cubemap named `name` with faces `size` texels wide that compute shaders can write,
+/// as a texture whose view samples it as a cube.
+fn cubemap(memory_allocator: &Arc<StandardMemoryAllocator>, size: u32, name: &str) -> Arc<Texture> {
+    let image = Image::new(
+        memory_allocator.clone(),
+        ImageCreateInfo {
+            flags: ImageCreateFlags::CUBE_COMPATIBLE,
+            image_type: ImageType::Dim2d,
+            format: hdr::FLOAT_FORMAT,
+            extent: [size, size, 1],
+            array_layers: 6,
+            usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )
+    .unwrap();
+    debug_utils::name(&image, name);
+    let view = ImageView::new(
+        image.clone(),
+        ImageViewCreateInfo {
+            view_type: ImageViewType::Cube,
+            ..ImageViewCreateInfo::from_image(&image)
+        },
+    )
+    .unwrap();
+    Arc::new(Texture {
+        image,
+        view,
+        downscaled: 0,
+    })
+}
+
+/// Records a pass of `pipeline` from `equirect` into every texel of every face of `output`.
+fn dispatch<L>(
+    builder: &mut AutoCommandBufferBuilder<L>,
+    pipeline: &Arc<ComputePipeline>,
+    descriptor_set_allocator: &Arc<StandardDescriptorSetAllocator>,
+    equirect: &Texture,
+    sampler: &Arc<Sampler>,
+    output: &Texture,
+) {
+    // The shaders write the faces as the layers of an array.
+    let faces = ImageView::new(
+        output.image.clone(),
+        ImageViewCreateInfo {
+            view_type: ImageViewType::Dim2dArray,
+            ..ImageViewCreateInfo::from_image(&output.image)
+        },
+    )
+    .unwrap();
+    let descriptor_set = DescriptorSet::new(
+        descriptor_set_allocator.clone(),
+        pipeline.layout().set_layouts()[0].clone(),
+        [
+            WriteDescriptorSet::image_view(0, equirect.view.clone()),
+            WriteDescriptorSet::sampler(1, sampler.clone()),
+            WriteDescriptorSet::image_view(2, faces),
+        ],
+        [],
+    )
+    .unwrap();
+
+    let [size, _, _] = output.image.extent();
+    builder
+        .bind_pipeline_compute(pipeline.clone())
+        .unwrap()
+        .bind_descriptor_sets(
+            PipelineBindPoint::Compute,
+            pipeline.layout().clone(),
+            0,
+            descriptor_set,
+        )
+        .unwrap();
+    // SAFETY: the shaders only write inside the faces, and read the equirectangular texture
+    // through a sampler.
+    unsafe {
+        builder.dispatch([
+            size.div_ceil(WORKGROUP_SIZE),
+            size.div_ceil(WORKGROUP_SIZE),
+            6,
+        ])
+    }
+    .unwrap();
+}
+
+fn compute_pipeline<P>(
+    device: Arc<Device>,
+    source: &str,
+    defines: &[(&str, String)],
+    name: &str,
+) -> Result<Arc<ComputePipeline>, AppError> {
+    let cs = shader::load_with_defines(device.clone(), source, ShaderStage::Compute, defines)?
+        .entry_point("main")
+        .unwrap();
+    let stage = PipelineShaderStageCreateInfo::new(cs);
+    let layout = shader::reflect_layout::<P>(device.clone(), std::slice::from_ref(&stage))?;
+    ComputePipeline::new(
+        device,
+        None,
+        ComputePipelineCreateInfo::stage_layout(stage, layout),
+    )
+    .map_err(AppError::Pipeline)
+    .inspect(|pipeline| debug_utils::name(pipeline, name))
+}
@@ -0,0 +1,77 @@
+// The errors that keep the app from starting or from rendering any further: failing to set up
+// Vulkan, the window or the pipelines, or to load the scene. They are reported once, after which
+// the app exits, rather than panicking wherever they happen.
+
+use std::path::PathBuf;
+use thiserror::Error;
+use vulkano::{
+    format::Format, image::AllocateImageError, library::LoadingError, swapchain::FromWindowError,
+    Validated, ValidationError, VulkanError,
+};
+
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("failed to load the Vulkan library: {0}")]
+    Library(#[from] LoadingError),
+    #[error("failed to create the Vulkan instance: {0}")]
+    Instance(Validated<VulkanError>),
+    #[error("no suitable physical device found")]
+    NoDevice,
+    #[error("failed to create the device: {0}")]
+    Device(Validated<VulkanError>),
+    #[error("the device was lost, most likely because the driver crashed or was reset")]
+    DeviceLost,
+    #[error("event loop error: {0}")]
+    EventLoop(#[from] winit::error::EventLoopError),
+    #[error("failed to create the window: {0}")]
+    Window(#[from] winit::error::OsError),
+    #[error("failed to create a surface for the window: {0}")]
+    Surface(#[from] FromWindowError),
+    #[error("swapchain error: {0}")]
+    Swapchain(Validated<VulkanError>),
+    /// A shader failed to compile, with the compiler diagnostics.
+    #[error("invalid shader: {0}")]
+    Shader(String),
+    #[error("failed to create the {name}: {source}")]
+    Image {
+        name: &'static str,
+        source: Validated<AllocateImageError>,
+    },
+    #[error("failed to query what the device supports for {format:?}: {source}")]
+    FormatProperties {
+        format: Format,
+        source: Box<ValidationError>,
+    },
+    #[error("failed to create a view of the {name}: {source}")]
+    ImageView {
+        name: &'static str,
+        source: Validated<VulkanError>,
+    },
+    #[error("failed to create the framebuffer of the {name}: {source}")]
+    Framebuffer {
+        name: &'static str,
+        source: Validated<VulkanError>,
+    },
+    #[error("failed to create a pipeline: {0}")]
+    Pipeline(Validated<VulkanError>),
+    #[error("failed to create {}: {source}", .path.display())]
+    Output {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to read {}: {source}", .path.display())]
+    Input {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to load the environment map {}: {source}", .path.display())]
+    Environment {
+        path: PathBuf,
+        source: crate::hdr::HdrError,
+    },
+    #[error("failed to load {}: {source}", .path.display())]
+    Scene {
+        path: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
@@ -0,0 +1,222 @@
+// Volumetric fog, as a compute post-effect over the rasterized main view. `shaders/fog.comp`
+// marches the view ray of every pixel through the fog up to the depth that the scene left in its
+// depth buffer, and writes the scene as seen through the fog into an image of its own, along with
+// the light of the directional light that the fog scatters towards the eye on the way. How the
+// light scatters is the Henyey-Greenstein phase function, whose anisotropy makes the fog glow
+// around the light when looking towards it.
+//
+// The renderer has no shadow map for the fog to look up, so nothing shadows it: it is lit
+// everywhere, and there are no shafts of light between occluders. The density and anisotropy are
+// render settings, which are reloaded while the app runs, so they can be tuned by eye.
+
+use glam::{Mat4, Vec3};
+use std::sync::Arc;
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::DeviceOwned,
+    format::Format,
+    image::{
+        sampler::{Sampler, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, ComputePipeline, Pipeline, PipelineBindPoint,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    components::Light,
+    debug_utils,
+    error::AppError,
+    shader::{self, ShaderStage},
+};
+
+/// The width and height of a workgroup, in invocations.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// How many samples of the fog each view ray takes.
+const STEPS: u32 = 32;
+
+/// The format of the fogged image, like that of the blurred ones.
+const FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// How thick the fog is and how it scatters light.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    /// How much of the light going through a unit of fog at y = 0 it takes out.
+    pub density: f32,
+    /// From -1 to 1, how much more of the light is scattered on ahead than back the way it came,
+    /// with 0 scattering it evenly.
+    pub anisotropy: f32,
+    /// How quickly the fog thins out above y = 0.
+    pub height_falloff: f32,
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    inverse_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    light_direction: [f32; 4],
+    light_color: [f32; 4],
+    fog: [f32; 4],
+}
+
+pub struct FogPass {
+    pipeline: Arc<ComputePipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    sampler: Arc<Sampler>,
+    /// Recreated when the size of the input changes.
+    output: Option<Arc<ImageView>>,
+}
+
+impl FogPass {
+    /// Fog over images that hold sRGB-encoded colors if `encoded`, as targets encoded by hand do,
+    /// which are fogged linear and written out encoded again.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        encoded: bool,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let mut defines = vec![
+            ("WORKGROUP_SIZE", WORKGROUP_SIZE.to_string()),
+            ("STEPS", STEPS.to_string()),
+        ];
+        if encoded {
+            defines.push(("ENCODED", "1".to_owned()));
+        }
+        let cs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/fog.comp"),
+            ShaderStage::Compute,
+            &defines,
+        )?
+        .entry_point("main")
+        .unwrap();
+
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout =
+            shader::reflect_layout::<PushConstants>(device.clone(), std::slice::from_ref(&stage))?;
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "fog");
+
+        // Texels are fetched without filtering, so the sampler's settings don't matter.
+        let sampler =
+            Sampler::new(device, SamplerCreateInfo::default()).map_err(AppError::Pipeline)?;
+
+        Ok(FogPass {
+            pipeline,
+            memory_allocator,
+            descriptor_set_allocator,
+            sampler,
+            output: None,
+        })
+    }
+
+    /// Records the fogging of `color`, whose depth buffer is `depth`, both of which must be
+    /// sampleable. The scene in them was seen through `view_proj` from `eye` and is lit by
+    /// `light`. Returns the image holding the result, which is only valid until the next call.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        color: &Arc<ImageView>,
+        depth: &Arc<ImageView>,
+        view_proj: Mat4,
+        eye: Vec3,
+        light: &Light,
+        fog: &Fog,
+    ) -> Arc<ImageView> {
+        let [width, height, _] = color.image().extent();
+        if self
+            .output
+            .as_ref()
+            .is_none_or(|output| output.image().extent() != color.image().extent())
+        {
+            self.output = Some(self.create_image([width, height]));
+        }
+        let output = self.output.clone().unwrap();
+
+        let layout = self.pipeline.layout();
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view(0, color.clone()),
+                WriteDescriptorSet::image_view(1, depth.clone()),
+                WriteDescriptorSet::sampler(2, self.sampler.clone()),
+                WriteDescriptorSet::image_view(3, output.clone()),
+            ],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                layout.clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                layout.clone(),
+                0,
+                PushConstants {
+                    inverse_view_proj: view_proj.inverse().to_cols_array_2d(),
+                    eye: eye.extend(1.0).to_array(),
+                    light_direction: (-light.direction().unwrap_or_default().normalize_or_zero())
+                        .extend(0.0)
+                        .to_array(),
+                    light_color: (light.color * light.intensity).extend(1.0).to_array(),
+                    fog: [fog.density, fog.anisotropy, fog.height_falloff, 0.0],
+                },
+            )
+            .unwrap();
+
+        // SAFETY: the shader only writes inside the output image, which is the size of the input.
+        unsafe {
+            builder.dispatch([
+                width.div_ceil(WORKGROUP_SIZE),
+                height.div_ceil(WORKGROUP_SIZE),
+                1,
+            ])
+        }
+        .unwrap();
+
+        output
+    }
+
+    fn create_image(&self, extent: [u32; 2]) -> Arc<ImageView> {
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: FORMAT,
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+        ImageView::new_default(image).unwrap()
+    }
+}
@@ -0,0 +1,130 @@
+// Single-step frame debugging. Instead of recording a whole frame into one command buffer, each
+// pass is submitted on its own and waited on, and the image it rendered to is copied back and
+// saved. This is slow, but shows exactly which pass corrupts a frame on hardware where no capture
+// tool is available.
+
+use std::{fs, path::PathBuf, sync::Arc};
+use tracing::{error, info, warn};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
+    command_buffer::{AutoCommandBufferBuilder, CopyImageToBufferInfo, PrimaryAutoCommandBuffer},
+    device::{DeviceOwned, Queue},
+    format::Format,
+    image::{Image, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    sync::{self, GpuFuture},
+};
+
+pub struct FrameDebugger {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    directory: PathBuf,
+    frame: u64,
+    pass: u32,
+}
+
+impl FrameDebugger {
+    /// Creates a debugger that saves the images of every pass into `directory`, which is created
+    /// when the first image is saved.
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>, directory: PathBuf) -> Self {
+        FrameDebugger {
+            memory_allocator,
+            directory,
+            frame: 0,
+            pass: 0,
+        }
+    }
+
+    /// Starts numbering the passes of a new frame.
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+        self.pass = 0;
+    }
+
+    /// Submits the commands in `builder` once `after` has completed and waits for them to finish,
+    /// then saves the contents of `target` as `frame-NNNN-P-{name}.png`. Returns a future for
+    /// whatever comes next.
+    pub fn submit_pass(
+        &mut self,
+        queue: &Arc<Queue>,
+        mut builder: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        after: Box<dyn GpuFuture>,
+        target: &Arc<Image>,
+        name: &str,
+    ) -> Box<dyn GpuFuture> {
+        self.pass += 1;
+        let path = self
+            .directory
+            .join(format!("frame-{:04}-{}-{name}.png", self.frame, self.pass));
+
+        // Only 8-bit RGBA and BGRA images can be saved as they are.
+        let swizzle = match target.format() {
+            Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => Some(false),
+            Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => Some(true),
+            _ => None,
+        };
+        let readback_buffer = swizzle
+            .filter(|_| target.usage().intersects(ImageUsage::TRANSFER_SRC))
+            .map(|_| {
+                let [width, height, _] = target.extent();
+                let buffer = Buffer::new_slice::<u8>(
+                    self.memory_allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::TRANSFER_DST,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                            | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                        ..Default::default()
+                    },
+                    u64::from(width) * u64::from(height) * 4,
+                )
+                .unwrap();
+                builder
+                    .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                        target.clone(),
+                        buffer.clone(),
+                    ))
+                    .unwrap();
+
+                buffer
+            });
+
+        let command_buffer = builder.build().unwrap();
+        after
+            .then_execute(queue.clone(), command_buffer)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        match readback_buffer {
+            Some(buffer) => {
+                let mut pixels = buffer.read().unwrap().to_vec();
+                if swizzle == Some(true) {
+                    for pixel in pixels.chunks_exact_mut(4) {
+                        pixel.swap(0, 2);
+                    }
+                }
+
+                let [width, height, _] = target.extent();
+                let result = fs::create_dir_all(&self.directory)
+                    .map_err(image::ImageError::IoError)
+                    .and_then(|()| {
+                        image::save_buffer(&path, &pixels, width, height, image::ColorType::Rgba8)
+                    });
+                match result {
+                    Ok(()) => info!("Saved {}", path.display()),
+                    Err(err) => error!("Failed to save {}: {err}", path.display()),
+                }
+            }
+            None => warn!(
+                "Pass {name} finished, but its {:?} image can't be saved",
+                target.format(),
+            ),
+        }
+
+        sync::now(queue.device().clone()).boxed()
+    }
+}
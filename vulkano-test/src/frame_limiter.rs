@@ -0,0 +1,116 @@
+// Caps the frame rate by sleeping before each frame until it is due. Sleeping alone wakes up too
+// late by up to a scheduler tick, so the last stretch is spent spinning instead, which is what
+// keeps the frames evenly spaced.
+//
+// Where the device supports `VK_GOOGLE_display_timing`, the interval between frames is rounded up
+// to a whole number of display refreshes, so that every frame stays on screen for the same number
+// of refreshes instead of the odd one being shown twice.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use vulkano::{
+    device::{Device, DeviceExtensions},
+    swapchain::Swapchain,
+    VulkanObject,
+};
+
+use crate::device_requirements::{Capabilities, DeviceRequirements};
+
+/// How long before a frame is due to stop sleeping and start spinning.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+pub struct FrameLimiter {
+    /// The time between frames for the requested rate.
+    max_fps_interval: Duration,
+    /// `max_fps_interval` matched to the display's refresh rate, if it is known.
+    interval: Duration,
+    next_frame: Option<Instant>,
+}
+
+impl FrameLimiter {
+    /// A limiter for at most `max_fps` frames per second.
+    pub fn new(max_fps: u32) -> Self {
+        let interval = Duration::from_secs(1) / max_fps.max(1);
+
+        FrameLimiter {
+            max_fps_interval: interval,
+            interval,
+            next_frame: None,
+        }
+    }
+
+    /// Aligns the interval between frames with a display that refreshes every `refresh_period`.
+    pub fn set_refresh_period(&mut self, refresh_period: Option<Duration>) {
+        self.interval = match refresh_period {
+            Some(period) if !period.is_zero() => {
+                // A little slack, so that a limit of 60 doesn't halve the rate on a 59.94 Hz
+                // display.
+                let refreshes = (self.max_fps_interval.as_secs_f64() / period.as_secs_f64() - 0.05)
+                    .ceil()
+                    .max(1.0);
+                period.mul_f64(refreshes)
+            }
+            _ => self.max_fps_interval,
+        };
+    }
+
+    /// Blocks until the next frame is due.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        let Some(next_frame) = self.next_frame else {
+            self.next_frame = Some(now + self.interval);
+            return;
+        };
+
+        if let Some(remaining) = next_frame.checked_duration_since(now) {
+            if remaining > SPIN_MARGIN {
+                thread::sleep(remaining - SPIN_MARGIN);
+            }
+            while Instant::now() < next_frame {
+                std::hint::spin_loop();
+            }
+        }
+
+        // A frame that ran late starts a new schedule rather than making the next ones hurry to
+        // catch up.
+        let now = Instant::now();
+        self.next_frame = Some(if now > next_frame + self.interval {
+            now + self.interval
+        } else {
+            next_frame + self.interval
+        });
+    }
+}
+
+/// Asks for the extension that tells the refresh rate of the display.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_extensions(DeviceExtensions {
+        google_display_timing: true,
+        ..DeviceExtensions::empty()
+    });
+}
+
+/// The time between refreshes of the display that `swapchain` presents to, if the device can
+/// tell.
+pub fn refresh_period(device: &Device, swapchain: &Swapchain) -> Option<Duration> {
+    if !Capabilities::of(device).display_timing {
+        return None;
+    }
+
+    let mut properties = ash::vk::RefreshCycleDurationGOOGLE::default();
+    // SAFETY: the extension is enabled, and the swapchain was created from the device.
+    let result = unsafe {
+        (device
+            .fns()
+            .google_display_timing
+            .get_refresh_cycle_duration_google)(
+            device.handle(),
+            swapchain.handle(),
+            &mut properties,
+        )
+    };
+
+    (result == ash::vk::Result::SUCCESS).then(|| Duration::from_nanos(properties.refresh_duration))
+}
@@ -0,0 +1,207 @@
+// The linear workflow. Colors are linear from the moment they are read until they are written
+// out, so that lighting can add them up as they are:
+//
+// - Image files store sRGB colors, so their textures have an sRGB format, `Texture::FORMAT`, which
+//   the sampler decodes to linear.
+// - Material, vertex and light colors, and the clear color, are linear already, as glTF has them.
+// - The swapchain has an sRGB format where the surface offers one, whose images encode what the
+//   shaders write to them. Offscreen targets of the main view share the swapchain's format, so
+//   passes that read them back, such as blur, fog and water, get linear colors as well.
+//
+// With the `ten_bit_output` setting, the swapchain has a 10-bit format instead where the surface
+// offers one in the sRGB color space, with four times the steps of an 8-bit one for gradients to
+// go through. There are no 10-bit sRGB formats, so the colors are encoded by hand: `output_color`,
+// the debug lines and the selection outline encode what they write, the clear color is encoded
+// before it is cleared with, and blur and fog decode what they read and encode what they write,
+// so that their results can be blitted to the swapchain as they are. The ray and path tracers blit
+// linear colors, so the swapchain keeps an sRGB format where the device can ray trace.
+//
+// Other than that, nothing is encoded or decoded by hand in between, which is what would go wrong
+// twice or not at all. The `gamma_audit` setting shows what either mistake looks like, with the
+// encoding of the output skipped or done a second time, through `output_color` in
+// `shaders/include/gamma.glsl`. Anything that looks wrong without it, the way the whole frame does
+// with it, has been decoded too little or too often on the way.
+//
+// With the `dither` setting, `output_color` also adds an 8x8 Bayer pattern of less than one step
+// of the target to every color it writes, after encoding it as the target will, so that smooth
+// gradients on 8-bit swapchains dither between neighbouring steps rather than showing bands. The
+// clear color isn't drawn by a shader, so it isn't dithered.
+
+use tracing::{info, warn};
+use vulkano::{
+    format::{Format, NumericFormat},
+    render_pass::Subpass,
+    swapchain::ColorSpace,
+};
+
+use crate::{settings::GammaAudit, texture::Texture};
+
+/// How the shaders of the main view write colors out, through `output_color` in
+/// `shaders/include/gamma.glsl`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Output {
+    pub gamma_audit: GammaAudit,
+    /// How many steps there are between black and white in each channel of the target, to dither
+    /// between, or 0 not to dither.
+    pub dither_steps: u32,
+    pub encoding: Encoding,
+}
+
+/// How a target stores the colors written to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// The target stores linear colors as they are, as float formats do.
+    #[default]
+    Linear,
+    /// The target encodes linear colors to sRGB itself, as sRGB formats do.
+    Srgb,
+    /// The target stores what is written to it, but is shown as sRGB, so colors are encoded to
+    /// sRGB by hand before they are written.
+    Manual,
+}
+
+impl Encoding {
+    pub fn of(format: Format) -> Self {
+        match format.numeric_format_color() {
+            Some(NumericFormat::SRGB) => Encoding::Srgb,
+            Some(NumericFormat::SFLOAT | NumericFormat::UFLOAT) => Encoding::Linear,
+            _ => Encoding::Manual,
+        }
+    }
+}
+
+impl Output {
+    /// Writes into images of `format` as the `gamma_audit` and `dither` settings ask. Float
+    /// formats have no steps to dither between.
+    pub fn new(gamma_audit: GammaAudit, dither: bool, format: Format) -> Self {
+        let encoding = Encoding::of(format);
+        let bits = u32::from(format.components()[0]);
+        Output {
+            gamma_audit,
+            dither_steps: if dither && encoding != Encoding::Linear && bits > 0 {
+                (1 << bits) - 1
+            } else {
+                0
+            },
+            encoding,
+        }
+    }
+
+    /// The `color_output` of the `Frame` block of `shaders/include/frame.glsl`: the gamma audit in
+    /// `x`, 0 for off, 1 for uncorrected and 2 for double corrected, the steps to dither between
+    /// in `y`, and in `z` 0 for linear targets, 1 for sRGB ones and 2 for those encoded by hand.
+    pub(crate) fn to_uniform(self) -> [u32; 4] {
+        let gamma_audit = match self.gamma_audit {
+            GammaAudit::Off => 0,
+            GammaAudit::Uncorrected => 1,
+            GammaAudit::DoubleCorrected => 2,
+        };
+        let encoding = match self.encoding {
+            Encoding::Linear => 0,
+            Encoding::Srgb => 1,
+            Encoding::Manual => 2,
+        };
+        [gamma_audit, self.dither_steps, encoding, 0]
+    }
+}
+
+/// The 10-bit formats that `ten_bit_output` picks from, in order of preference.
+const TEN_BIT_FORMATS: [Format; 2] = [
+    Format::A2B10G10R10_UNORM_PACK32,
+    Format::A2R10G10B10_UNORM_PACK32,
+];
+
+/// Picks the format of the swapchain among those the surface offers, preferring sRGB formats in
+/// the sRGB color space, which encode the linear colors that are written to them. With
+/// `ten_bit`, 10-bit formats in the sRGB color space come first.
+pub fn swapchain_format(formats: &[(Format, ColorSpace)], ten_bit: bool) -> (Format, ColorSpace) {
+    if ten_bit {
+        let ten_bit_format = TEN_BIT_FORMATS.iter().find_map(|&wanted| {
+            formats.iter().copied().find(|&(format, color_space)| {
+                format == wanted && color_space == ColorSpace::SrgbNonLinear
+            })
+        });
+        match ten_bit_format {
+            Some(format) => return format,
+            None => warn!("The window surface has no 10-bit format, so the output has 8 bits"),
+        }
+    }
+
+    let srgb = formats
+        .iter()
+        .copied()
+        .find(|&(format, color_space)| color_space == ColorSpace::SrgbNonLinear && is_srgb(format));
+    match srgb {
+        Some(format) => format,
+        None => {
+            warn!(
+                "The window surface has no sRGB format, so the frame is shown without encoding \
+                 its linear colors, too dark"
+            );
+            formats[0]
+        }
+    }
+}
+
+/// `color` as it is cleared into images of `format`: encoded to sRGB where they store what is
+/// written to them as it is.
+pub fn clear_color(color: [f32; 4], format: Format) -> [f32; 4] {
+    if Encoding::of(format) != Encoding::Manual {
+        return color;
+    }
+    let [r, g, b, a] = color;
+    [linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a]
+}
+
+/// The defines of shaders that write colors out without `output_color`, such as
+/// `shaders/debug_line.frag`, for drawing into the first color attachment of `subpass`, which
+/// encode the colors by hand where the attachment is encoded by hand.
+pub fn encoding_defines(subpass: &Subpass) -> Vec<(&'static str, String)> {
+    let format = subpass
+        .subpass_desc()
+        .color_attachments
+        .first()
+        .and_then(Option::as_ref)
+        .map(|reference| subpass.render_pass().attachments()[reference.attachment as usize].format);
+    match format.map(Encoding::of) {
+        Some(Encoding::Manual) => vec![("ENCODE_SRGB", "1".to_owned())],
+        _ => Vec::new(),
+    }
+}
+
+/// Logs how colors are decoded and encoded on their way through the frame.
+pub fn log_color_pipeline(swapchain_format: Format) {
+    let output = match Encoding::of(swapchain_format) {
+        Encoding::Srgb => "encoded by the swapchain",
+        Encoding::Manual => "encoded by the shaders",
+        Encoding::Linear => "not encoded",
+    };
+    info!(
+        "Linear workflow: textures {:?}, decoded by the sampler; lighting in linear; output \
+         {swapchain_format:?}, {output}",
+        Texture::FORMAT,
+    );
+}
+
+fn is_srgb(format: Format) -> bool {
+    Encoding::of(format) == Encoding::Srgb
+}
+
+/// The linear value of an sRGB-encoded channel, which `linear_to_srgb` encodes again.
+pub(crate) fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The sRGB encoding of a linear channel, as `linear_to_srgb` in `shaders/include/srgb.glsl`.
+pub(crate) fn linear_to_srgb(channel: f32) -> f32 {
+    let channel = channel.max(0.0);
+    if channel < 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    }
+}
@@ -0,0 +1,557 @@
+// Animated GIFs of the last few seconds, saved with F10, for sharing rendering glitches after they
+// happened. While `gif_seconds` in the settings isn't zero, a downscaled copy of the swapchain
+// image is blitted and read back a few times a second, and the copies of the last `gif_seconds`
+// are kept in host memory for when the GIF is saved.
+//
+// GIFs are limited to 256 colors. Frames are mapped to a fixed palette of 6 levels of red and blue
+// and 7 of green, with ordered dithering to hide the banding, which keeps encoding fast enough
+// to not need a palette per frame. Nothing is transparent.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, BlitImageInfo, CopyImageToBufferInfo},
+    device::DeviceOwned,
+    format::{Format, FormatFeatures, NumericFormat},
+    image::{sampler::Filter, Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+/// How many frames a second are kept.
+const FRAME_RATE: u32 = 15;
+
+/// The largest width and height of the GIF, which smaller windows keep their size below.
+const MAX_SIZE: u32 = 480;
+
+/// How many copies can be waiting for the GPU at once.
+const READBACK_BUFFERS: usize = 4;
+
+pub struct GifCapture {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    /// What copies are made from and into, for the current size of the swapchain.
+    target: Option<Target>,
+    /// Whether the swapchain images can't be captured, which is only warned about once.
+    unsupported: bool,
+    /// Readback buffers that were copied into, oldest first, waiting for the GPU.
+    pending: VecDeque<(Instant, Subbuffer<[u8]>)>,
+    /// The frames that are kept, oldest first.
+    frames: VecDeque<Frame>,
+    last_capture: Option<Instant>,
+}
+
+struct Target {
+    source_extent: [u32; 3],
+    image: Arc<Image>,
+    free: Vec<Subbuffer<[u8]>>,
+}
+
+#[derive(Clone)]
+struct Frame {
+    time: Instant,
+    pixels: Vec<u8>,
+}
+
+impl GifCapture {
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>) -> Self {
+        GifCapture {
+            memory_allocator,
+            target: None,
+            unsupported: false,
+            pending: VecDeque::new(),
+            frames: VecDeque::new(),
+            last_capture: None,
+        }
+    }
+
+    /// Records a downscaled copy of `image` into `builder` if one is due, after whatever the frame
+    /// drew into it, and forgets frames older than `seconds`.
+    pub fn record<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        image: &Arc<Image>,
+        seconds: f32,
+    ) {
+        self.poll();
+        let now = Instant::now();
+        let keep = Duration::from_secs_f32(seconds.max(0.0));
+        while self
+            .frames
+            .front()
+            .is_some_and(|frame| now - frame.time > keep)
+        {
+            self.frames.pop_front();
+        }
+
+        if keep.is_zero() || self.unsupported {
+            return;
+        }
+        if self
+            .last_capture
+            .is_some_and(|last| now - last < Duration::from_secs(1) / FRAME_RATE)
+        {
+            return;
+        }
+
+        if self
+            .target
+            .as_ref()
+            .is_none_or(|target| target.source_extent != image.extent())
+        {
+            // Frames of different sizes can't be in the same GIF.
+            self.frames.clear();
+            self.pending.clear();
+            self.target = self.create_target(image);
+            if self.target.is_none() {
+                self.unsupported = true;
+                return;
+            }
+        }
+        let target = self.target.as_mut().unwrap();
+        let Some(buffer) = target.free.pop() else {
+            return;
+        };
+
+        builder
+            .blit_image(BlitImageInfo {
+                filter: Filter::Linear,
+                ..BlitImageInfo::images(image.clone(), target.image.clone())
+            })
+            .unwrap()
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                target.image.clone(),
+                buffer.clone(),
+            ))
+            .unwrap();
+        self.pending.push_back((now, buffer));
+        self.last_capture = Some(now);
+    }
+
+    /// Saves the frames that are kept as a GIF, on a thread of its own.
+    pub fn save(&self, path: PathBuf) {
+        let Some(target) = self.target.as_ref().filter(|_| !self.frames.is_empty()) else {
+            warn!("There are no frames to save as a GIF");
+            return;
+        };
+
+        let [width, height, _] = target.image.extent();
+        let frames: Vec<Frame> = self.frames.iter().cloned().collect();
+        thread::spawn(move || match write_gif(&path, width, height, &frames) {
+            Ok(()) => info!("Saved {} ({} frames)", path.display(), frames.len()),
+            Err(err) => error!("Failed to save {}: {err}", path.display()),
+        });
+    }
+
+    /// Keeps the frames that the GPU has finished copying.
+    fn poll(&mut self) {
+        while let Some((time, buffer)) = self.pending.front() {
+            // The buffer stays locked until the future of its frame has been cleaned up.
+            let Ok(pixels) = buffer.read().map(|pixels| pixels.to_vec()) else {
+                break;
+            };
+            self.frames.push_back(Frame {
+                time: *time,
+                pixels,
+            });
+            let (_, buffer) = self.pending.pop_front().unwrap();
+            if let Some(target) = &mut self.target {
+                target.free.push(buffer);
+            }
+        }
+    }
+
+    /// Creates the image to blit copies of `source` into, and the buffers to read them back
+    /// into, or warns why not and returns `None` if the device can't blit from it or they can't
+    /// be made.
+    fn create_target(&self, source: &Image) -> Option<Target> {
+        let unsupported = || {
+            warn!(
+                "GIF capture isn't supported for {:?} windows",
+                source.format()
+            )
+        };
+        if !source.usage().intersects(ImageUsage::TRANSFER_SRC) {
+            unsupported();
+            return None;
+        }
+        let source_features = match self
+            .memory_allocator
+            .device()
+            .physical_device()
+            .format_properties(source.format())
+        {
+            Ok(properties) => properties.optimal_tiling_features,
+            Err(err) => {
+                warn!("Failed to query the window's format for GIF capture: {err}");
+                return None;
+            }
+        };
+        if !source_features
+            .contains(FormatFeatures::BLIT_SRC | FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR)
+        {
+            unsupported();
+            return None;
+        }
+
+        // Blitting between sRGB formats keeps the colors as they are, whereas blitting from sRGB
+        // to UNORM would linearize them.
+        let format = if source.format().numeric_format_color() == Some(NumericFormat::SRGB) {
+            Format::R8G8B8A8_SRGB
+        } else {
+            Format::R8G8B8A8_UNORM
+        };
+        let [source_width, source_height, _] = source.extent();
+        let scale = (MAX_SIZE as f32 / source_width.max(source_height) as f32).min(1.0);
+        let extent = [
+            ((source_width as f32 * scale) as u32).max(1),
+            ((source_height as f32 * scale) as u32).max(1),
+            1,
+        ];
+
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format,
+                extent,
+                usage: ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .inspect_err(|err| warn!("Failed to create the image for GIF capture: {err}"))
+        .ok()?;
+        let free = (0..READBACK_BUFFERS)
+            .map(|_| {
+                Buffer::new_slice::<u8>(
+                    self.memory_allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::TRANSFER_DST,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                            | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                        ..Default::default()
+                    },
+                    u64::from(extent[0]) * u64::from(extent[1]) * 4,
+                )
+            })
+            .collect::<Result<_, _>>()
+            .inspect_err(|err| warn!("Failed to create the buffers for GIF capture: {err}"))
+            .ok()?;
+
+        Some(Target {
+            source_extent: source.extent(),
+            image,
+            free,
+        })
+    }
+}
+
+fn write_gif(path: &Path, width: u32, height: u32, frames: &[Frame]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    // The header and logical screen descriptor, with a global color table of 256 colors.
+    writer.write_all(b"GIF89a")?;
+    writer.write_all(&(width as u16).to_le_bytes())?;
+    writer.write_all(&(height as u16).to_le_bytes())?;
+    writer.write_all(&[0xF7, 0, 0])?;
+    for index in 0..256u32 {
+        let color = if index < 6 * 7 * 6 {
+            let (r, g, b) = (index / 42, index / 6 % 7, index % 6);
+            [r * 255 / 5, g * 255 / 6, b * 255 / 5].map(|level| level as u8)
+        } else {
+            [0; 3]
+        };
+        writer.write_all(&color)?;
+    }
+    // Loops forever.
+    writer.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+    for (i, frame) in frames.iter().enumerate() {
+        // Each frame shows until the next was captured, in hundredths of a second. Browsers
+        // slow down delays below 2 to 10.
+        let delay = match frames.get(i + 1) {
+            Some(next) => (next.time - frame.time).as_millis() / 10,
+            None => u128::from(100 / FRAME_RATE),
+        };
+        let delay = delay.clamp(2, u128::from(u16::MAX)) as u16;
+        writer.write_all(&[0x21, 0xF9, 0x04, 0x04])?;
+        writer.write_all(&delay.to_le_bytes())?;
+        writer.write_all(&[0, 0])?;
+
+        // The image descriptor, covering the whole screen.
+        writer.write_all(&[0x2C, 0, 0, 0, 0])?;
+        writer.write_all(&(width as u16).to_le_bytes())?;
+        writer.write_all(&(height as u16).to_le_bytes())?;
+        writer.write_all(&[0])?;
+
+        let indices = palette_indices(&frame.pixels, width as usize);
+        writer.write_all(&[8])?;
+        for block in lzw_compress(&indices).chunks(255) {
+            writer.write_all(&[block.len() as u8])?;
+            writer.write_all(block)?;
+        }
+        writer.write_all(&[0])?;
+    }
+
+    writer.write_all(&[0x3B])?;
+    writer.flush()
+}
+
+/// Maps RGBA pixels to the fixed palette, dithering with a 4x4 Bayer matrix.
+fn palette_indices(pixels: &[u8], width: usize) -> Vec<u8> {
+    const BAYER: [[f32; 4]; 4] = [
+        [0.0, 8.0, 2.0, 10.0],
+        [12.0, 4.0, 14.0, 6.0],
+        [3.0, 11.0, 1.0, 9.0],
+        [15.0, 7.0, 13.0, 5.0],
+    ];
+
+    pixels
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(i, pixel)| {
+            let threshold = BAYER[i / width % 4][i % width % 4] / 16.0;
+            let level = |channel: u8, levels: u32| {
+                let steps = (levels - 1) as f32;
+                ((channel as f32 / 255.0 * steps + threshold).floor() as u32).min(levels - 1)
+            };
+            let (r, g, b) = (level(pixel[0], 6), level(pixel[1], 7), level(pixel[2], 6));
+            (r * 42 + g * 6 + b) as u8
+        })
+        .collect()
+}
+
+/// Compresses 8-bit palette indices the way GIF image data is, with variable-length codes of up
+/// to 12 bits.
+fn lzw_compress(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    const MAX_CODES: u16 = 4096;
+
+    let mut writer = BitWriter::default();
+    let mut code_size = 9;
+    let mut table = HashMap::<(u16, u8), u16>::new();
+    let mut next_code = END + 1;
+    writer.write(CLEAR, code_size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.write(END, code_size);
+        return writer.finish();
+    };
+    let mut prefix = u16::from(first);
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        writer.write(prefix, code_size);
+        // The decoder adds each entry one code later than the encoder, so codes widen once the
+        // entry that needs the extra bit has been added.
+        if next_code >= 1 << code_size && code_size < 12 {
+            code_size += 1;
+        }
+        if next_code < MAX_CODES {
+            table.insert((prefix, index), next_code);
+            next_code += 1;
+        } else {
+            writer.write(CLEAR, code_size);
+            table.clear();
+            next_code = END + 1;
+            code_size = 9;
+        }
+        prefix = u16::from(index);
+    }
+    writer.write(prefix, code_size);
+    if next_code >= 1 << code_size && code_size < 12 {
+        code_size += 1;
+    }
+    writer.write(END, code_size);
+    writer.finish()
+}
+
+/// Packs codes into bytes, least significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.bits |= u32::from(code) << self.bit_count;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Saves `frames` with `write_gif` and reads the file back as its size and the delay and
+    /// palette indices of each frame, with weezl's LZW decoder standing in for a GIF decoder.
+    fn round_trip(name: &str, width: u32, height: u32, frames: &[Frame]) -> Decoded {
+        let path =
+            std::env::temp_dir().join(format!("vulkano-test-{name}-{}.gif", std::process::id()));
+        write_gif(&path, width, height, frames).unwrap();
+        let bytes = std::fs::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        decode(&bytes.unwrap())
+    }
+
+    struct Decoded {
+        size: [u16; 2],
+        frames: Vec<(u16, Vec<u8>)>,
+    }
+
+    fn decode(bytes: &[u8]) -> Decoded {
+        assert_eq!(&bytes[..6], b"GIF89a");
+        let size = [
+            u16::from_le_bytes([bytes[6], bytes[7]]),
+            u16::from_le_bytes([bytes[8], bytes[9]]),
+        ];
+        // Past the screen descriptor and the global color table.
+        let mut at = 13 + 256 * 3;
+        let mut delay = 0;
+        let mut frames = Vec::new();
+        loop {
+            match bytes[at] {
+                0x21 => {
+                    if bytes[at + 1] == 0xF9 {
+                        delay = u16::from_le_bytes([bytes[at + 4], bytes[at + 5]]);
+                    }
+                    at = sub_blocks(bytes, at + 2).1;
+                }
+                0x2C => {
+                    let min_code_size = bytes[at + 10];
+                    let (data, end) = sub_blocks(bytes, at + 11);
+                    let indices = weezl::decode::Decoder::new(weezl::BitOrder::Lsb, min_code_size)
+                        .decode(&data)
+                        .unwrap();
+                    frames.push((delay, indices));
+                    at = end;
+                }
+                0x3B => return Decoded { size, frames },
+                block => panic!("unexpected block {block:#x} at {at}"),
+            }
+        }
+    }
+
+    /// The data of the sub-blocks starting at `at`, and where they end.
+    fn sub_blocks(bytes: &[u8], mut at: usize) -> (Vec<u8>, usize) {
+        let mut data = Vec::new();
+        loop {
+            let len = usize::from(bytes[at]);
+            at += 1;
+            if len == 0 {
+                return (data, at);
+            }
+            data.extend_from_slice(&bytes[at..at + len]);
+            at += len;
+        }
+    }
+
+    /// `len` bytes of noise, which LZW can't find runs in.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn maps_black_and_white_to_the_palette_ends() {
+        let pixels = [0, 0, 0, 255, 255, 255, 255, 255];
+        assert_eq!(palette_indices(&pixels, 2), [0, 5 * 42 + 6 * 6 + 5]);
+    }
+
+    #[test]
+    fn round_trips_a_small_frame() {
+        let pixels: Vec<u8> = (0..8 * 4)
+            .flat_map(|i| [i as u8 * 8, 255 - i as u8 * 8, 128, 255])
+            .collect();
+        let start = Instant::now();
+        let frames = [
+            Frame {
+                time: start,
+                pixels: pixels.clone(),
+            },
+            Frame {
+                time: start + Duration::from_millis(500),
+                pixels: pixels.clone(),
+            },
+        ];
+
+        let decoded = round_trip("small", 8, 4, &frames);
+        assert_eq!(decoded.size, [8, 4]);
+        let indices = palette_indices(&pixels, 8);
+        // The last frame shows for as long as frames are apart when capturing.
+        let last_delay = 100 / FRAME_RATE as u16;
+        assert_eq!(
+            decoded.frames,
+            [(50, indices.clone()), (last_delay, indices)]
+        );
+    }
+
+    #[test]
+    fn round_trips_frames_past_the_largest_code() {
+        let (width, height) = (128, 64);
+        let pixels = noise(width * height * 4);
+        let frames = [Frame {
+            time: Instant::now(),
+            pixels: pixels.clone(),
+        }];
+
+        let decoded = round_trip("long", width as u32, height as u32, &frames);
+        assert_eq!(decoded.frames.len(), 1);
+        assert_eq!(decoded.frames[0].1, palette_indices(&pixels, width));
+
+        // Noise needs about a code a byte, so the table fills and is cleared several times.
+        let indices = noise(20_000);
+        let decoded = weezl::decode::Decoder::new(weezl::BitOrder::Lsb, 8)
+            .decode(&lzw_compress(&indices))
+            .unwrap();
+        assert_eq!(decoded, indices);
+    }
+
+    #[test]
+    fn round_trips_empty_input() {
+        let decoded = weezl::decode::Decoder::new(weezl::BitOrder::Lsb, 8)
+            .decode(&lzw_compress(&[]))
+            .unwrap();
+        assert!(decoded.is_empty());
+
+        let decoded = round_trip("empty", 4, 4, &[]);
+        assert_eq!(decoded.size, [4, 4]);
+        assert!(decoded.frames.is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+// Transform gizmos for moving, turning and resizing the selected entity with the mouse. The gizmo
+// is drawn through the debug-draw layer at the entity's origin, as three handles, one per axis:
+// arrows for translating along the world axes, rings for rotating about them, and boxed lines for
+// scaling along the entity's own axes. It is as large on screen wherever the entity is. It shows
+// with the debug overlay, and G switches between the three kinds of handle.
+//
+// A press on a handle starts a drag, found by casting the picking ray through the cursor at the
+// handles. While it lasts, the cursor's ray is brought back onto the handle's axis, or onto the
+// plane of its ring, and how far it moved along it since the press moves the entity by as much
+// from where it was then. The result is written back through `Scene::set_world_transform`, which
+// places the entity's node relative to its parent.
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use vulkano::pipeline::graphics::viewport::Viewport;
+
+use crate::{debug_draw::DebugDraw, picking, raycast::Ray};
+
+/// How long the handles are for every unit that their origin is away from the camera.
+const SCREEN_SIZE: f32 = 0.15;
+
+/// How close the ray has to pass by a handle to hit it, as a fraction of the handles' length.
+const HIT_DISTANCE: f32 = 0.08;
+
+/// The smallest that a drag can scale an axis to, of what it was.
+const MIN_SCALE: f32 = 0.01;
+
+const AXIS_COLORS: [Vec4; 3] = [
+    Vec4::new(1.0, 0.0, 0.0, 1.0),
+    Vec4::new(0.0, 1.0, 0.0, 1.0),
+    Vec4::new(0.0, 0.0, 1.0, 1.0),
+];
+
+/// The color of the handle being dragged.
+const ACTIVE_COLOR: Vec4 = Vec4::new(1.0, 1.0, 0.0, 1.0);
+
+/// What dragging a handle of the gizmo does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    /// The mode after this one, wrapping around.
+    pub fn next(self) -> Self {
+        match self {
+            GizmoMode::Translate => GizmoMode::Rotate,
+            GizmoMode::Rotate => GizmoMode::Scale,
+            GizmoMode::Scale => GizmoMode::Translate,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GizmoMode::Translate => "translate",
+            GizmoMode::Rotate => "rotate",
+            GizmoMode::Scale => "scale",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    drag: Option<Drag>,
+}
+
+/// A drag of a handle, from the press that started it.
+struct Drag {
+    axis: usize,
+    /// The view that the drag started in, which the cursor keeps being seen through.
+    view_proj: Mat4,
+    viewport: Viewport,
+    /// The entity's world transform when the drag started.
+    transform: Mat4,
+    /// How far along the axis, or at what angle around it, the press was.
+    start: f32,
+}
+
+/// Where the handles of a gizmo are: from its origin along each of its axes, as long as each
+/// other.
+struct Handles {
+    origin: Vec3,
+    axes: [Vec3; 3],
+    length: f32,
+}
+
+impl Gizmo {
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    /// Starts dragging the handle under `pixel`, in physical pixels from the top left of the
+    /// window, for an entity at `transform`, seen through the first of `views` whose viewport
+    /// contains the pixel. Returns whether there was a handle there.
+    pub fn begin_drag(&mut self, transform: Mat4, views: &[(Mat4, Viewport)], pixel: Vec2) -> bool {
+        let Some((view_proj, viewport)) = picking::view_at(views, pixel) else {
+            return false;
+        };
+        let ray = Ray::through_pixel(*view_proj, viewport, pixel);
+        let handles = self.handles(transform, *view_proj);
+        let Some(axis) = (0..3)
+            .filter_map(|axis| Some((axis, self.hit(&handles, axis, &ray)?)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(axis, _)| axis)
+        else {
+            return false;
+        };
+        let Some(start) = self.measure(&handles, axis, &ray) else {
+            return false;
+        };
+        self.drag = Some(Drag {
+            axis,
+            view_proj: *view_proj,
+            viewport: viewport.clone(),
+            transform,
+            start,
+        });
+        true
+    }
+
+    /// The world transform that the drag moves the entity to with the cursor at `pixel`, if the
+    /// cursor's ray can be brought onto the handle.
+    pub fn drag(&self, pixel: Vec2) -> Option<Mat4> {
+        let drag = self.drag.as_ref()?;
+        let ray = Ray::through_pixel(drag.view_proj, &drag.viewport, pixel);
+        let handles = self.handles(drag.transform, drag.view_proj);
+        let axis = handles.axes[drag.axis];
+        let current = self.measure(&handles, drag.axis, &ray)?;
+        let about_origin = |change: Mat4| {
+            Mat4::from_translation(handles.origin)
+                * change
+                * Mat4::from_translation(-handles.origin)
+        };
+        Some(match self.mode {
+            GizmoMode::Translate => {
+                Mat4::from_translation(axis * (current - drag.start)) * drag.transform
+            }
+            GizmoMode::Rotate => {
+                about_origin(Mat4::from_axis_angle(axis, current - drag.start)) * drag.transform
+            }
+            GizmoMode::Scale => {
+                let mut scale = Vec3::ONE;
+                scale[drag.axis] = (current / drag.start).max(MIN_SCALE);
+                drag.transform * Mat4::from_scale(scale)
+            }
+        })
+    }
+
+    /// Ends the drag, returning the world transform that the entity had when it started, if there
+    /// was a drag.
+    pub fn end_drag(&mut self) -> Option<Mat4> {
+        self.drag.take().map(|drag| drag.transform)
+    }
+
+    /// Queues the handles of the gizmo for an entity at `transform`, sized for the view seen
+    /// through `view_proj`, with the one being dragged highlighted.
+    pub fn draw(&self, debug_draw: &mut DebugDraw, transform: Mat4, view_proj: Mat4) {
+        let handles = self.handles(transform, view_proj);
+        let active = self.drag.as_ref().map(|drag| drag.axis);
+        for (i, axis) in handles.axes.into_iter().enumerate() {
+            let color = if active == Some(i) {
+                ACTIVE_COLOR
+            } else {
+                AXIS_COLORS[i]
+            };
+            let tip = handles.origin + axis * handles.length;
+            match self.mode {
+                GizmoMode::Translate => {
+                    debug_draw.line(handles.origin, tip, color);
+                    // An arrowhead of four lines back from the tip.
+                    let (u, v) = axis.any_orthonormal_pair();
+                    let base = tip - axis * handles.length * 0.15;
+                    for side in [u, -u, v, -v] {
+                        debug_draw.line(tip, base + side * handles.length * 0.05, color);
+                    }
+                }
+                GizmoMode::Rotate => {
+                    debug_draw.circle(handles.origin, axis, handles.length, color);
+                }
+                GizmoMode::Scale => {
+                    debug_draw.line(handles.origin, tip, color);
+                    let half = Vec3::splat(handles.length * 0.04);
+                    debug_draw.wire_box(tip - half, tip + half, color);
+                }
+            }
+        }
+    }
+
+    /// The handles for an entity at `transform`, seen through `view_proj`.
+    fn handles(&self, transform: Mat4, view_proj: Mat4) -> Handles {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        let axes = match self.mode {
+            GizmoMode::Translate | GizmoMode::Rotate => [Vec3::X, Vec3::Y, Vec3::Z],
+            GizmoMode::Scale => [
+                transform.x_axis.truncate().normalize_or(Vec3::X),
+                transform.y_axis.truncate().normalize_or(Vec3::Y),
+                transform.z_axis.truncate().normalize_or(Vec3::Z),
+            ],
+        };
+        // The clip-space w of a perspective projection is the distance in front of the camera.
+        let depth = (view_proj * origin.extend(1.0)).w.abs().max(f32::EPSILON);
+        Handles {
+            origin,
+            axes,
+            length: depth * SCREEN_SIZE,
+        }
+    }
+
+    /// How far from the handle of `axis` the ray passes, if it passes close enough to hit it.
+    fn hit(&self, handles: &Handles, axis: usize, ray: &Ray) -> Option<f32> {
+        let tolerance = handles.length * HIT_DISTANCE;
+        let distance = match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                let (along, distance) = closest_on_axis(handles.origin, handles.axes[axis], ray)?;
+                // A scale is how much further out the cursor is than it was at the press, which
+                // jumps about close to the origin, so scale handles are only held by their outer
+                // half.
+                let start = match self.mode {
+                    GizmoMode::Scale => handles.length * 0.5,
+                    _ => 0.0,
+                };
+                if !(start..=handles.length + tolerance).contains(&along) {
+                    return None;
+                }
+                distance
+            }
+            GizmoMode::Rotate => {
+                let point = intersect_plane(handles.origin, handles.axes[axis], ray)?;
+                (point.distance(handles.origin) - handles.length).abs()
+            }
+        };
+        (distance <= tolerance).then_some(distance)
+    }
+
+    /// How far along the axis the ray comes closest to it, or for rings, at what angle around
+    /// the axis it goes through the ring's plane.
+    fn measure(&self, handles: &Handles, axis: usize, ray: &Ray) -> Option<f32> {
+        let direction = handles.axes[axis];
+        match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                closest_on_axis(handles.origin, direction, ray).map(|(along, _)| along)
+            }
+            GizmoMode::Rotate => {
+                let offset = intersect_plane(handles.origin, direction, ray)? - handles.origin;
+                let (u, v) = direction.any_orthonormal_pair();
+                Some(offset.dot(v).atan2(offset.dot(u)))
+            }
+        }
+    }
+}
+
+/// How far along the line through `origin` in the unit `direction` the point nearest to `ray` is,
+/// and how far it is from the ray, unless the two are parallel.
+fn closest_on_axis(origin: Vec3, direction: Vec3, ray: &Ray) -> Option<(f32, f32)> {
+    let to_ray = ray.origin - origin;
+    let cosine = ray.direction.dot(direction);
+    let denominator = 1.0 - cosine * cosine;
+    if denominator < 1e-6 {
+        return None;
+    }
+    let along = (direction.dot(to_ray) - cosine * ray.direction.dot(to_ray)) / denominator;
+    let along_ray = (cosine * direction.dot(to_ray) - ray.direction.dot(to_ray)) / denominator;
+    let distance = (ray.at(along_ray.max(0.0)) - (origin + direction * along)).length();
+    Some((along, distance))
+}
+
+/// Where `ray` goes through the plane through `origin` with the unit `normal`, unless it runs
+/// along the plane or away from it.
+fn intersect_plane(origin: Vec3, normal: Vec3, ray: &Ray) -> Option<Vec3> {
+    let facing = ray.direction.dot(normal);
+    if facing.abs() < 1e-6 {
+        return None;
+    }
+    let distance = (origin - ray.origin).dot(normal) / facing;
+    (distance >= 0.0).then(|| ray.at(distance))
+}
@@ -0,0 +1,418 @@
+// The Vulkan objects shared by everything that renders: the device, its graphics queue and the
+// allocators. Windowed and headless rendering only differ in the extensions they enable and in
+// whether the queue has to be able to present. Everything else the device needs comes from the
+// `DeviceRequirements` the caller gathered from its subsystems.
+//
+// Portability subset devices, such as MoltenVK on macOS, are listed along with conformant ones.
+// They leave out parts of Vulkan unless the features bringing them back are enabled, so all of
+// those are asked for, and the ones a device still lacks are reported.
+//
+// Which physical device is used can be chosen with `VKTEST_GPU`, set to either the index of the
+// device in the order Vulkan lists them or a part of its name. Otherwise a software implementation
+// is picked if the requirements prefer one, or the device that was used last time, so that
+// multi-GPU systems don't switch between runs, and failing that the one most likely to be fastest.
+
+use std::{fmt::Write, fs, sync::Arc};
+use tracing::{info, info_span, warn};
+use vulkano::{
+    command_buffer::allocator::StandardCommandBufferAllocator,
+    descriptor_set::allocator::StandardDescriptorSetAllocator,
+    device::{
+        physical::{PhysicalDevice, PhysicalDeviceType},
+        Device, DeviceCreateInfo, DeviceExtensions, DeviceFeatures, Queue, QueueCreateInfo,
+        QueueFlags,
+    },
+    instance::{Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions},
+    library::VulkanLibrary,
+    memory::allocator::StandardMemoryAllocator,
+    swapchain::{FromWindowError, Surface},
+    Version,
+};
+use winit::raw_window_handle::HasDisplayHandle;
+
+use crate::{
+    crash_report,
+    device_requirements::{self, Capabilities, DeviceRequirements},
+    error::AppError,
+};
+
+/// The environment variable that selects a device.
+pub const GPU_VARIABLE: &str = "VKTEST_GPU";
+
+/// Where the UUID of the last device that was created successfully is kept.
+const LAST_GPU_PATH: &str = "last-gpu.txt";
+
+/// The features that portability subset devices only have when they are enabled.
+const PORTABILITY_FEATURES: DeviceFeatures = DeviceFeatures {
+    constant_alpha_color_blend_factors: true,
+    events: true,
+    image_view_format_reinterpretation: true,
+    image_view_format_swizzle: true,
+    image_view2_d_on3_d_image: true,
+    multisample_array_image: true,
+    mutable_comparison_samplers: true,
+    point_polygons: true,
+    sampler_mip_lod_bias: true,
+    separate_stencil_mask_ref: true,
+    shader_sample_rate_interpolation_functions: true,
+    tessellation_isolines: true,
+    tessellation_point_mode: true,
+    triangle_fans: true,
+    vertex_attribute_access_beyond_stride: true,
+    ..DeviceFeatures::empty()
+};
+
+pub struct Gpu {
+    pub instance: Arc<Instance>,
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    pub memory_allocator: Arc<StandardMemoryAllocator>,
+    pub command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+}
+
+impl Gpu {
+    /// Creates a device whose queue can present to windows of `event_loop`.
+    pub fn windowed(
+        event_loop: &impl HasDisplayHandle,
+        mut requirements: DeviceRequirements,
+    ) -> Result<Self, AppError> {
+        let instance_extensions = Surface::required_extensions(event_loop)
+            .map_err(|err| AppError::Surface(FromWindowError::RetrieveHandle(err)))?;
+        requirements.require_extensions(DeviceExtensions {
+            khr_swapchain: true,
+            ..Default::default()
+        });
+
+        Self::new(instance_extensions, requirements, |p, i| {
+            p.presentation_support(i, event_loop).unwrap()
+        })
+    }
+
+    /// Creates a device that only renders to images, without touching the windowing system.
+    pub fn headless(requirements: DeviceRequirements) -> Result<Self, AppError> {
+        Self::new(InstanceExtensions::empty(), requirements, |_, _| true)
+    }
+
+    /// Creates a device for rendering to the headset of `runtime`, on the GPU it is connected to
+    /// and with the extensions it needs.
+    #[cfg(feature = "xr")]
+    pub fn xr(
+        runtime: &crate::xr::XrRuntime,
+        mut requirements: DeviceRequirements,
+    ) -> Result<Self, AppError> {
+        requirements.require_extensions(runtime.device_extensions());
+        Self::new(runtime.instance_extensions(), requirements, |p, _| {
+            runtime.is_graphics_device(p)
+        })
+    }
+
+    /// Creates a headless device on a software implementation of Vulkan, for rendering tests
+    /// that should give the same results on every machine. Fails with `AppError::NoDevice` where
+    /// none is installed, which tests can take as a reason to skip.
+    pub fn software(mut requirements: DeviceRequirements) -> Result<Self, AppError> {
+        requirements.require_software();
+        Self::headless(requirements)
+    }
+
+    fn new(
+        instance_extensions: InstanceExtensions,
+        mut requirements: DeviceRequirements,
+        can_present: impl Fn(&PhysicalDevice, u32) -> bool,
+    ) -> Result<Self, AppError> {
+        let _span = info_span!("create_device").entered();
+        let instance = create_instance(instance_extensions)?;
+        crash_report::set_instance(&instance);
+
+        // Each suitable device along with its index among all devices and its graphics queue
+        // family.
+        let candidates: Vec<(usize, Arc<PhysicalDevice>, u32)> = instance
+            .enumerate_physical_devices()
+            .map_err(|err| AppError::Instance(err.into()))?
+            .enumerate()
+            .filter(|(_, p)| requirements.is_supported_by(p))
+            .filter_map(|(index, p)| {
+                p.queue_family_properties()
+                    .iter()
+                    .enumerate()
+                    .position(|(i, q)| {
+                        q.queue_flags.contains(QueueFlags::GRAPHICS) && can_present(&p, i as u32)
+                    })
+                    .map(|i| (index, p.clone(), i as u32))
+            })
+            .collect();
+        let (_, physical_device, queue_family_index) =
+            select_device(candidates, requirements.prefers_software()).ok_or(AppError::NoDevice)?;
+
+        info!(
+            "Using device: {} (type: {:?})",
+            physical_device.properties().device_name,
+            physical_device.properties().device_type,
+        );
+
+        // Little uses these yet. They are asked for on every device so that renderers moving to
+        // them can rely on `Capabilities` to tell whether they are there.
+        requirements
+            .request_promoted_features(
+                Version::V1_3,
+                DeviceFeatures {
+                    dynamic_rendering: true,
+                    ..DeviceFeatures::empty()
+                },
+                DeviceExtensions {
+                    khr_dynamic_rendering: true,
+                    ..DeviceExtensions::empty()
+                },
+            )
+            .request_promoted_features(
+                Version::V1_2,
+                DeviceFeatures {
+                    timeline_semaphore: true,
+                    ..DeviceFeatures::empty()
+                },
+                DeviceExtensions {
+                    khr_timeline_semaphore: true,
+                    ..DeviceExtensions::empty()
+                },
+            )
+            .request_promoted_features(
+                Version::V1_2,
+                DeviceFeatures {
+                    buffer_device_address: true,
+                    ..DeviceFeatures::empty()
+                },
+                DeviceExtensions {
+                    khr_buffer_device_address: true,
+                    ..DeviceExtensions::empty()
+                },
+            )
+            .request_features(PORTABILITY_FEATURES);
+        // Only the instance extension tells what the device extension allows for a surface.
+        if instance.enabled_extensions().ext_surface_maintenance1 {
+            requirements.request_extension_features(
+                DeviceFeatures {
+                    swapchain_maintenance1: true,
+                    ..DeviceFeatures::empty()
+                },
+                DeviceExtensions {
+                    ext_swapchain_maintenance1: true,
+                    ..DeviceExtensions::empty()
+                },
+            );
+        }
+        let (enabled_extensions, enabled_features) = requirements.enabled_for(&physical_device);
+        if enabled_extensions.khr_portability_subset
+            && !enabled_features.contains(&PORTABILITY_FEATURES)
+        {
+            warn!(
+                "This is a portability subset device, which lacks {:?}",
+                PORTABILITY_FEATURES.difference(&enabled_features),
+            );
+        }
+
+        let (device, mut queues) = Device::new(
+            physical_device,
+            DeviceCreateInfo {
+                enabled_extensions,
+                enabled_features,
+                queue_create_infos: vec![QueueCreateInfo {
+                    queue_family_index,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        )
+        .map_err(AppError::Device)?;
+        crash_report::set_device(&device);
+
+        // Software devices are only a preference for the run that asked for them.
+        if let Some(uuid) = device.physical_device().properties().device_uuid
+            && !device_requirements::is_software(device.physical_device())
+        {
+            // Only a preference, so failing to store it is no reason to stop.
+            if let Err(err) = fs::write(LAST_GPU_PATH, uuid_string(&uuid)) {
+                warn!("Failed to write {LAST_GPU_PATH}: {err}");
+            }
+        }
+
+        info!("Device capabilities: {:?}", Capabilities::of(&device));
+
+        let queue = queues.next().unwrap();
+        let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let command_buffer_allocator = Arc::new(StandardCommandBufferAllocator::new(
+            device.clone(),
+            Default::default(),
+        ));
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            device.clone(),
+            Default::default(),
+        ));
+
+        Ok(Gpu {
+            instance,
+            device,
+            queue,
+            memory_allocator,
+            command_buffer_allocator,
+            descriptor_set_allocator,
+        })
+    }
+}
+
+pub(crate) fn create_instance(
+    enabled_extensions: InstanceExtensions,
+) -> Result<Arc<Instance>, AppError> {
+    let library = VulkanLibrary::new()?;
+    // These let the swapchain tell how it can be scaled, where the loader has them.
+    let enabled_extensions = if enabled_extensions.khr_surface {
+        enabled_extensions.union(&library.supported_extensions().intersection(
+            &InstanceExtensions {
+                khr_get_surface_capabilities2: true,
+                ext_surface_maintenance1: true,
+                ..InstanceExtensions::empty()
+            },
+        ))
+    } else {
+        enabled_extensions
+    };
+    // Where the loader has it, what the driver and layers report is logged and kept for crash
+    // reports.
+    let debug_utils = library.supported_extensions().ext_debug_utils;
+    let enabled_extensions = InstanceExtensions {
+        ext_debug_utils: debug_utils,
+        ..enabled_extensions
+    };
+    Instance::new(
+        library,
+        InstanceCreateInfo {
+            // vulkano also enables `khr_portability_enumeration` for this, if the loader has it.
+            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+            enabled_extensions,
+            debug_utils_messengers: if debug_utils {
+                vec![crash_report::debug_messenger()]
+            } else {
+                Vec::new()
+            },
+            ..Default::default()
+        },
+    )
+    .map_err(AppError::Instance)
+}
+
+/// Describes every physical device, for `vulkano-test --list-gpus`. The indices are the ones
+/// `GPU_VARIABLE` takes.
+///
+/// Whether a queue family can present depends on the surface, so without a window this can only
+/// tell whether the device supports swapchains at all.
+pub fn describe_devices() -> Result<String, AppError> {
+    let instance = create_instance(InstanceExtensions::empty())?;
+    let last = fs::read_to_string(LAST_GPU_PATH).unwrap_or_default();
+    let mut report = format!("Vulkan {}\n", instance.api_version());
+
+    let physical_devices = instance
+        .enumerate_physical_devices()
+        .map_err(|err| AppError::Instance(err.into()))?;
+    for (index, p) in physical_devices.enumerate() {
+        let properties = p.properties();
+        let uuid = properties.device_uuid.map(|uuid| uuid_string(&uuid));
+        let last_used = if uuid.as_deref() == Some(last.trim()) {
+            " (used last time)"
+        } else {
+            ""
+        };
+        let driver = match (&properties.driver_name, &properties.driver_info) {
+            (Some(name), Some(info)) => format!("{name} {info}"),
+            (Some(name), None) => name.clone(),
+            // The encoding is vendor specific without the driver properties.
+            _ => format!("{:#x}", properties.driver_version),
+        };
+
+        // Writing to a `String` can't fail.
+        let _ = writeln!(report, "\n{index}: {}{last_used}", properties.device_name);
+        let _ = writeln!(report, "    type: {:?}", properties.device_type);
+        let _ = writeln!(report, "    API version: {}", properties.api_version);
+        let _ = writeln!(report, "    driver: {driver}");
+        let _ = writeln!(report, "    UUID: {}", uuid.as_deref().unwrap_or("unknown"));
+        let _ = writeln!(
+            report,
+            "    swapchains: {}",
+            if p.supported_extensions().khr_swapchain {
+                "supported"
+            } else {
+                "not supported"
+            }
+        );
+        for (i, family) in p.queue_family_properties().iter().enumerate() {
+            let _ = writeln!(
+                report,
+                "    queue family {i}: {} x {:?}",
+                family.queue_count, family.queue_flags
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+/// Picks the device requested through `GPU_VARIABLE`, or else a software one if
+/// `prefer_software`, or else the one used last time, or else the best guess among `candidates`.
+fn select_device(
+    mut candidates: Vec<(usize, Arc<PhysicalDevice>, u32)>,
+    prefer_software: bool,
+) -> Option<(usize, Arc<PhysicalDevice>, u32)> {
+    if let Ok(requested) = std::env::var(GPU_VARIABLE) {
+        let position = match requested.trim().parse::<usize>() {
+            Ok(index) => candidates.iter().position(|(i, _, _)| *i == index),
+            Err(_) => {
+                let requested = requested.to_lowercase();
+                candidates.iter().position(|(_, p, _)| {
+                    p.properties()
+                        .device_name
+                        .to_lowercase()
+                        .contains(&requested)
+                })
+            }
+        };
+        match position {
+            Some(position) => return Some(candidates.swap_remove(position)),
+            None => warn!("{GPU_VARIABLE}={requested} matches no suitable device, ignoring it"),
+        }
+    }
+
+    if prefer_software {
+        match candidates
+            .iter()
+            .position(|(_, p, _)| device_requirements::is_software(p))
+        {
+            Some(position) => return Some(candidates.swap_remove(position)),
+            None => warn!("There is no software Vulkan implementation, using hardware"),
+        }
+    }
+
+    if let Ok(last) = fs::read_to_string(LAST_GPU_PATH) {
+        let last = last.trim();
+        if let Some(position) = candidates.iter().position(|(_, p, _)| {
+            p.properties()
+                .device_uuid
+                .is_some_and(|uuid| uuid_string(&uuid) == last)
+        }) {
+            return Some(candidates.swap_remove(position));
+        }
+    }
+
+    candidates.into_iter().min_by_key(|(_, p, _)| {
+        // We assign a lower score to device types that are likely to be faster/better.
+        match p.properties().device_type {
+            PhysicalDeviceType::DiscreteGpu => 0,
+            PhysicalDeviceType::IntegratedGpu => 1,
+            PhysicalDeviceType::VirtualGpu => 2,
+            PhysicalDeviceType::Cpu => 3,
+            PhysicalDeviceType::Other => 4,
+            _ => 5,
+        }
+    })
+}
+
+fn uuid_string(uuid: &[u8; 16]) -> String {
+    uuid.iter().map(|byte| format!("{byte:02x}")).collect()
+}
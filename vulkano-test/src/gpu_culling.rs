@@ -0,0 +1,433 @@
+// Culling on the GPU, so that large scenes don't pay for testing and recording every entity on the
+// CPU. The entities are gathered into batches of those with the same mesh and texture, and
+// uploaded with their transforms and bounds. Two compute passes then run ahead of the render pass:
+// `shaders/cull.comp` tests every instance against the frustum and, with occlusion culling, the
+// results of the last frame's occlusion queries, which `OcclusionCuller` copies into a buffer
+// without them going through the CPU, and picks the level of detail of those that are visible.
+// `shaders/cull_draws.comp` then writes the indirect draws of each level of each batch.
+//
+// Where the device supports `draw_indirect_count`, the draws of each level are compacted and
+// counted, and drawn with `draw_indexed_indirect_count`. Elsewhere every instance has a draw at
+// every level, with no instances where it isn't drawn at that level, and all of them are drawn
+// with `draw_indexed_indirect`. Either way the CPU records one indirect draw per level of a batch
+// instead of one per entity, in `ScenePipeline::draw_culled`, whose vertex shader reads the
+// transform and base color of each instance back from the instance buffer.
+//
+// The levels of detail are only told apart in color on the CPU, so `lod_debug` culls there.
+
+use glam::Mat4;
+use hecs::Entity;
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        BufferContents, BufferUsage, Subbuffer,
+    },
+    command_buffer::{AutoCommandBufferBuilder, DrawIndexedIndirectCommand},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::{Device, DeviceExtensions, DeviceFeatures, DeviceOwned},
+    memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, ComputePipeline, Pipeline, PipelineBindPoint,
+        PipelineShaderStageCreateInfo,
+    },
+    Version,
+};
+
+use crate::{
+    components::{MaterialOverride, MeshHandle, Transform},
+    debug_utils,
+    device_requirements::{Capabilities, DeviceRequirements},
+    error::AppError,
+    material::Material,
+    mesh::Mesh,
+    occlusion::OcclusionCuller,
+    scene::Scene,
+    shader::{self, ShaderStage},
+    texture::Texture,
+};
+
+/// The features indirect draws of culled instances need: more than one draw per call, and draws
+/// that start at the index of their instance.
+pub const FEATURES: DeviceFeatures = DeviceFeatures {
+    multi_draw_indirect: true,
+    draw_indirect_first_instance: true,
+    ..DeviceFeatures::empty()
+};
+
+/// The local size of both culling shaders.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The `CullInstance` of `shaders/include/culling.glsl`.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct CullInstance {
+    model: [[f32; 4]; 4],
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    /// The bounds of the mesh in model space.
+    aabb_min: [f32; 4],
+    aabb_max: [f32; 4],
+    /// The id of the entity, which its occlusion query is indexed by.
+    entity: u32,
+    /// The region of each level of detail of the mesh, from the most detailed.
+    first_region: u32,
+    level_count: u32,
+    metallic: f32,
+    roughness: f32,
+    padding: [u32; 3],
+}
+
+/// The `CullRegion` of `shaders/include/culling.glsl`.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct CullRegion {
+    first_command: u32,
+    index_count: u32,
+    max_screen_size: f32,
+    level: u32,
+    first_instance: u32,
+    instance_count: u32,
+    padding: [u32; 2],
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct CullPushConstants {
+    view_proj: [[f32; 4]; 4],
+    instance_count: u32,
+    occlusion_count: u32,
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct DrawsPushConstants {
+    compact: u32,
+}
+
+/// Asks for indirect draws of many instances where supported, and for counting them on the GPU,
+/// which is core since Vulkan 1.2.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements
+        .request_features(FEATURES)
+        .request_promoted_features(
+            Version::V1_2,
+            DeviceFeatures {
+                draw_indirect_count: true,
+                ..DeviceFeatures::empty()
+            },
+            DeviceExtensions {
+                khr_draw_indirect_count: true,
+                ..DeviceExtensions::empty()
+            },
+        );
+}
+
+/// The instances of one mesh with one texture, which are next to each other in the instance
+/// buffer.
+pub(crate) struct CulledBatch {
+    pub(crate) mesh: Arc<Mesh>,
+    pub(crate) texture: Option<Arc<Texture>>,
+    /// The region of the first level of detail. Those of the others follow it.
+    pub(crate) first_region: u32,
+    /// The draws of the first level. Those of each of the others follow the previous level's.
+    pub(crate) first_command: u32,
+    pub(crate) instance_count: u32,
+}
+
+/// What `GpuCuller::cull` left for `ScenePipeline::draw_culled` to draw, which is only valid in
+/// the command buffer it was recorded into.
+pub struct CulledDraws {
+    pub(crate) instances: Subbuffer<[CullInstance]>,
+    pub(crate) commands: Subbuffer<[DrawIndexedIndirectCommand]>,
+    /// How many draws of each region there are, if they are compacted.
+    pub(crate) counts: Option<Subbuffer<[u32]>>,
+    pub(crate) batches: Vec<CulledBatch>,
+}
+
+pub struct GpuCuller {
+    cull_pipeline: Arc<ComputePipeline>,
+    draws_pipeline: Arc<ComputePipeline>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    buffer_allocator: SubbufferAllocator,
+    /// Whether the draws are compacted and counted.
+    compact: bool,
+}
+
+impl GpuCuller {
+    /// Creates the culling pipelines. The device must support `FEATURES`, as `Capabilities`
+    /// tells.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let buffer_allocator = SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::STORAGE_BUFFER | BufferUsage::INDIRECT_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        Ok(GpuCuller {
+            cull_pipeline: compute_pipeline::<CullPushConstants>(
+                device.clone(),
+                include_str!("shaders/cull.comp"),
+                "culling",
+            )?,
+            draws_pipeline: compute_pipeline::<DrawsPushConstants>(
+                device.clone(),
+                include_str!("shaders/cull_draws.comp"),
+                "culled draws",
+            )?,
+            descriptor_set_allocator,
+            buffer_allocator,
+            compact: Capabilities::of(&device).draw_indirect_count,
+        })
+    }
+
+    /// Records the culling of every loaded entity of `scene` against the frustum of `view_proj`,
+    /// and against the last frame's queries of `occlusion` if there are any. This must be outside
+    /// of a render pass, after `OcclusionCuller::begin_frame`. Returns the draws of the visible
+    /// entities, or `None` if nothing in the scene has loaded yet.
+    pub fn cull<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+        view_proj: Mat4,
+        occlusion: Option<&OcclusionCuller>,
+    ) -> Option<CulledDraws> {
+        let mut batches = Vec::<(Arc<Mesh>, Option<Arc<Texture>>, Vec<CullInstance>)>::new();
+        let mut batch_indices = HashMap::<(*const Mesh, Option<*const Texture>), usize>::new();
+        let mut query = scene.world.query::<(
+            Entity,
+            &Transform,
+            &MeshHandle,
+            &Material,
+            Option<&MaterialOverride>,
+        )>();
+        for (entity, transform, mesh, material, material_override) in query.iter() {
+            let Some(mesh) = mesh.0.get() else {
+                continue;
+            };
+            let material = material_override.map_or(material, |o| &o.0);
+            let texture = material
+                .base_color_texture
+                .as_ref()
+                .and_then(|texture| texture.get());
+
+            let key = (Arc::as_ptr(&mesh), texture.as_ref().map(Arc::as_ptr));
+            let index = *batch_indices.entry(key).or_insert_with(|| {
+                batches.push((mesh.clone(), texture, Vec::new()));
+                batches.len() - 1
+            });
+            batches[index].2.push(CullInstance {
+                model: transform.0.to_cols_array_2d(),
+                base_color: material.base_color.to_array(),
+                emissive: material.emissive.extend(0.0).to_array(),
+                aabb_min: mesh.aabb.min.extend(0.0).to_array(),
+                aabb_max: mesh.aabb.max.extend(0.0).to_array(),
+                entity: entity.id(),
+                first_region: 0,
+                level_count: 0,
+                metallic: material.metallic,
+                roughness: material.roughness,
+                padding: [0; 3],
+            });
+        }
+        if batches.is_empty() {
+            return None;
+        }
+
+        // Each level of each batch has a region with a draw for every instance of the batch,
+        // which compacted draws use the start of.
+        let mut instances = Vec::new();
+        let mut regions = Vec::new();
+        let mut culled_batches = Vec::with_capacity(batches.len());
+        let mut command_count = 0;
+        for (mesh, texture, batch_instances) in batches {
+            let instance_count = batch_instances.len() as u32;
+            let first_instance = instances.len() as u32;
+            let first_region = regions.len() as u32;
+            let first_command = command_count;
+
+            let levels = [(&mesh, f32::INFINITY)]
+                .into_iter()
+                .chain(mesh.lods.iter().map(|lod| (&lod.mesh, lod.max_screen_size)));
+            for (level, (level_mesh, max_screen_size)) in levels.enumerate() {
+                regions.push(CullRegion {
+                    first_command: command_count,
+                    index_count: level_mesh.index_buffer.len() as u32,
+                    max_screen_size,
+                    level: level as u32,
+                    first_instance,
+                    instance_count,
+                    padding: [0; 2],
+                });
+                command_count += instance_count;
+            }
+
+            let level_count = regions.len() as u32 - first_region;
+            instances.extend(batch_instances.into_iter().map(|instance| CullInstance {
+                first_region,
+                level_count,
+                ..instance
+            }));
+            culled_batches.push(CulledBatch {
+                mesh,
+                texture,
+                first_region,
+                first_command,
+                instance_count,
+            });
+        }
+
+        let instance_count = instances.len() as u32;
+        let instance_buffer = self.upload(instances);
+        let region_buffer = self.upload(regions);
+        let levels = self
+            .buffer_allocator
+            .allocate_slice::<u32>(instance_count.into())
+            .unwrap();
+        let commands = self
+            .buffer_allocator
+            .allocate_slice::<DrawIndexedIndirectCommand>(command_count.into())
+            .unwrap();
+        let counts = self
+            .buffer_allocator
+            .allocate_slice::<u32>(region_buffer.len())
+            .unwrap();
+
+        // Without results, the shader reads none, but the binding needs a buffer all the same.
+        let (occlusion_results, occlusion_count) =
+            match occlusion.and_then(|occlusion| occlusion.results()) {
+                Some(results) => (results.clone(), results.len() as u32 / 2),
+                None => (self.upload(vec![0u32; 2]), 0),
+            };
+
+        builder
+            .bind_pipeline_compute(self.cull_pipeline.clone())
+            .unwrap();
+        self.bind(
+            builder,
+            &self.cull_pipeline,
+            [
+                WriteDescriptorSet::buffer(0, instance_buffer.clone()),
+                WriteDescriptorSet::buffer(1, region_buffer.clone()),
+                WriteDescriptorSet::buffer(2, occlusion_results),
+                WriteDescriptorSet::buffer(3, levels.clone()),
+            ],
+        );
+        builder
+            .push_constants(
+                self.cull_pipeline.layout().clone(),
+                0,
+                CullPushConstants {
+                    view_proj: view_proj.to_cols_array_2d(),
+                    instance_count,
+                    occlusion_count,
+                },
+            )
+            .unwrap();
+        // SAFETY: the shader only reads the instances below `instance_count`, the regions that
+        // they refer to and the occlusion results below `occlusion_count`, and writes the level of
+        // each instance it reads.
+        unsafe { builder.dispatch([instance_count.div_ceil(WORKGROUP_SIZE), 1, 1]) }.unwrap();
+
+        builder
+            .bind_pipeline_compute(self.draws_pipeline.clone())
+            .unwrap();
+        self.bind(
+            builder,
+            &self.draws_pipeline,
+            [
+                WriteDescriptorSet::buffer(0, region_buffer.clone()),
+                WriteDescriptorSet::buffer(1, levels),
+                WriteDescriptorSet::buffer(2, counts.clone()),
+                WriteDescriptorSet::buffer(3, commands.clone()),
+            ],
+        );
+        builder
+            .push_constants(
+                self.draws_pipeline.layout().clone(),
+                0,
+                DrawsPushConstants {
+                    compact: self.compact as u32,
+                },
+            )
+            .unwrap();
+        // SAFETY: each workgroup reads its own region and the levels of the instances in it, and
+        // writes the count of the region and at most one draw per instance into the region's
+        // draws, which follow those of the regions before it.
+        unsafe { builder.dispatch([region_buffer.len() as u32, 1, 1]) }.unwrap();
+
+        Some(CulledDraws {
+            instances: instance_buffer,
+            commands,
+            counts: self.compact.then_some(counts),
+            batches: culled_batches,
+        })
+    }
+
+    /// Binds the descriptor set of `pipeline` with `writes`.
+    fn bind<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        pipeline: &Arc<ComputePipeline>,
+        writes: impl IntoIterator<Item = WriteDescriptorSet>,
+    ) {
+        let layout = &pipeline.layout().set_layouts()[0];
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.clone(),
+            writes,
+            [],
+        )
+        .unwrap();
+        builder
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap();
+    }
+
+    fn upload<T: BufferContents + Copy>(&self, data: Vec<T>) -> Subbuffer<[T]> {
+        let buffer = self
+            .buffer_allocator
+            .allocate_slice::<T>(data.len() as u64)
+            .unwrap();
+        buffer.write().unwrap().copy_from_slice(&data);
+        buffer
+    }
+}
+
+fn compute_pipeline<P>(
+    device: Arc<Device>,
+    source: &str,
+    name: &str,
+) -> Result<Arc<ComputePipeline>, AppError>
+where
+    P: BufferContents,
+{
+    let cs = shader::load(device.clone(), source, ShaderStage::Compute)?
+        .entry_point("main")
+        .unwrap();
+    let stage = PipelineShaderStageCreateInfo::new(cs);
+    let layout = shader::reflect_layout::<P>(device.clone(), std::slice::from_ref(&stage))?;
+    ComputePipeline::new(
+        device,
+        None,
+        ComputePipelineCreateInfo::stage_layout(stage, layout),
+    )
+    .map_err(AppError::Pipeline)
+    .inspect(|pipeline| debug_utils::name(pipeline, name))
+}
@@ -0,0 +1,146 @@
+// A grid on the ground plane, for a sense of where things are and how big they are. It is drawn in
+// a single pass over a square of the plane y = 0 around the eye, made by the vertex shader, with
+// the lines worked out per pixel in the fragment shader from the world position: one every unit,
+// a stronger one every ten, and the X and Z axes in red and blue. Lines are a pixel wide wherever
+// they are seen from, as their width comes from the screen-space derivatives of the position.
+//
+// The square reaches as far as the grid fades out, which is where the main view's far plane is,
+// so it looks endless without ever being clipped. It is tested against the depth of the scene
+// drawn before it, without writing its own, and blended over it.
+
+use glam::{Mat4, Vec3};
+use std::sync::Arc;
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::AutoCommandBufferBuilder,
+    device::DeviceOwned,
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState},
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        DynamicState, GraphicsPipeline, Pipeline, PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+};
+
+use crate::{
+    debug_utils,
+    error::AppError,
+    gamma, offscreen,
+    shader::{self, ShaderStage},
+};
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    view_proj: [[f32; 4]; 4],
+    /// The eye, and the distance at which the grid has faded out.
+    eye: [f32; 4],
+}
+
+pub struct GridPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+}
+
+impl GridPipeline {
+    /// Creates the pipeline of the grid, for drawing into `subpass` of a target with a depth
+    /// buffer.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/grid.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/grid.frag"),
+            ShaderStage::Fragment,
+            &gamma::encoding_defines(&subpass),
+        )?
+        .entry_point("main")
+        .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+        let pipeline = GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                // The square is made from the vertex index.
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                // The plane is seen from below as well.
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: CompareOp::Less,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(offscreen::color_blend_state(
+                    &subpass,
+                    &[ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    }],
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "grid");
+
+        Ok(GridPipeline { pipeline })
+    }
+
+    /// Records the grid into the current subpass, seen through `view_proj` from `eye` in
+    /// `viewport`, fading out by `far`, the distance to the far plane.
+    pub fn draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        view_proj: Mat4,
+        eye: Vec3,
+        far: f32,
+        viewport: Viewport,
+    ) {
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .unwrap()
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                PushConstants {
+                    view_proj: view_proj.to_cols_array_2d(),
+                    eye: eye.extend(far).to_array(),
+                },
+            )
+            .unwrap();
+        // SAFETY: the shader makes the square from the vertex index alone.
+        unsafe { builder.draw(6, 1, 0, 0) }.unwrap();
+    }
+}
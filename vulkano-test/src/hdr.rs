@@ -0,0 +1,170 @@
+// HDR images, for environment maps and image-based lighting. Radiance `.hdr` and OpenEXR `.exr`
+// files are both decoded by the `image` crate, which reads the R, G, B and A channels of the first
+// layer of scanline or tiled OpenEXR files in any compression scheme but DWAA and DWAB, within
+// the limits on allocations it sets by default.
+//
+// The linear colors are kept as they are, beyond 1 and all, in a half float texture with a mip
+// chain. Where the device can't sample and filter half floats, which leaves out hardly any
+// desktop GPUs but some mobile ones, the image is tonemapped into an sRGB texture instead, as a
+// preview that loses the range lighting would need but still shows what the environment looks
+// like.
+
+use half::f16;
+use image::{imageops::FilterType, Rgba, Rgba32FImage, RgbaImage};
+use std::path::Path;
+use vulkano::{
+    device::Device,
+    format::{Format, FormatFeatures},
+};
+
+use crate::{
+    gamma,
+    texture::{self, Texture, TextureData},
+};
+
+/// The format of the textures of HDR images, where the device can sample it.
+pub const FLOAT_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// What reading an HDR image fails with.
+pub type HdrError = image::ImageError;
+
+/// Whether `path` names a Radiance or OpenEXR file, going by its extension.
+pub fn is_hdr(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("hdr") || extension.eq_ignore_ascii_case("exr")
+    })
+}
+
+/// Reads the linear colors of the Radiance or OpenEXR file at `path`.
+pub fn open(path: &Path) -> Result<Rgba32FImage, HdrError> {
+    image::open(path).map(|image| image.into_rgba32f())
+}
+
+/// Whether `device` can sample and filter textures of `FLOAT_FORMAT`.
+pub fn can_sample_float(device: &Device) -> bool {
+    texture::can_sample(device, FLOAT_FORMAT)
+        && device
+            .physical_device()
+            .format_properties(FLOAT_FORMAT)
+            .is_ok_and(|properties| {
+                properties
+                    .optimal_tiling_features
+                    .intersects(FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR)
+            })
+}
+
+/// The texels of `image` with its mip chain, as half floats if `float`, which
+/// `can_sample_float` should tell, and tonemapped to sRGB otherwise.
+pub fn texture_data(image: Rgba32FImage, float: bool) -> TextureData {
+    let extent = [image.width(), image.height()];
+    let mips = mip_chain(image);
+    let (format, levels) = if float {
+        let levels = mips
+            .iter()
+            .map(|mip| {
+                mip.as_raw()
+                    .iter()
+                    .flat_map(|&channel| f16::from_f32(channel).to_le_bytes())
+                    .collect()
+            })
+            .collect();
+        (FLOAT_FORMAT, levels)
+    } else {
+        let levels = mips.iter().map(|mip| tonemap(mip).into_raw()).collect();
+        (Texture::FORMAT, levels)
+    };
+    TextureData {
+        format,
+        extent,
+        cube: false,
+        levels,
+    }
+}
+
+/// `image` tonemapped by the ACES filmic curve at an exposure of 1, and encoded as sRGB.
+pub fn tonemap(image: &Rgba32FImage) -> RgbaImage {
+    let encode = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+        let [r, g, b] = [r, g, b].map(|channel| encode(gamma::linear_to_srgb(aces(channel))));
+        Rgba([r, g, b, encode(a)])
+    })
+}
+
+/// Krzysztof Narkowicz's fit of the ACES filmic curve, from linear to between 0 and 1.
+fn aces(channel: f32) -> f32 {
+    let x = channel.max(0.0);
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+/// `image` followed by each of its mip levels, down to 1x1, as `texture::mip_chain` computes
+/// them.
+fn mip_chain(image: Rgba32FImage) -> Vec<Rgba32FImage> {
+    let mut mips = vec![image];
+    loop {
+        let (width, height) = mips.last().unwrap().dimensions();
+        if (width, height) == (1, 1) {
+            return mips;
+        }
+        let smaller = image::imageops::resize(
+            mips.last().unwrap(),
+            (width / 2).max(1),
+            (height / 2).max(1),
+            FilterType::Triangle,
+        );
+        mips.push(smaller);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageFormat;
+
+    /// A 4x2 image whose colors go beyond 1.
+    fn image() -> Rgba32FImage {
+        Rgba32FImage::from_fn(4, 2, |x, y| Rgba([x as f32 * 2.5, y as f32, 0.25, 1.0]))
+    }
+
+    #[test]
+    fn reads_openexr_files() {
+        let path = std::env::temp_dir().join(format!("vulkano-test-{}.exr", std::process::id()));
+        image()
+            .save_with_format(&path, ImageFormat::OpenExr)
+            .unwrap();
+        let read = open(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.unwrap(), image());
+    }
+
+    #[test]
+    fn keeps_the_range_of_float_textures() {
+        let data = texture_data(image(), true);
+
+        assert_eq!(data.format, FLOAT_FORMAT);
+        assert_eq!(data.extent, [4, 2]);
+        // 4x2, 2x1 and 1x1, of 8 bytes a texel.
+        let sizes: Vec<_> = data.levels.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [64, 16, 8]);
+        // Red of the last texel of the first row.
+        let red = f16::from_le_bytes(data.levels[0][24..26].try_into().unwrap());
+        assert_eq!(red.to_f32(), 7.5);
+    }
+
+    #[test]
+    fn tonemaps_where_floats_cant_be_sampled() {
+        let data = texture_data(image(), false);
+
+        assert_eq!(data.format, Texture::FORMAT);
+        assert_eq!(data.levels[0].len(), 4 * 2 * 4);
+        // Black stays black, bright colors stay brighter but below white, and alpha is kept.
+        let [r, g, b, a] = data.levels[0][..4] else {
+            unreachable!()
+        };
+        assert_eq!([r, g, a], [0, 0, 255]);
+        assert!(b > 0 && b < 255);
+        // The red of 2.5 of the second texel.
+        assert!(data.levels[0][4] > b && data.levels[0][4] < 255);
+    }
+}
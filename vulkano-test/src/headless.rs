@@ -0,0 +1,189 @@
+// Renders scenes into images without a window. The render pass mirrors the one the app uses for its
+// swapchain, but targets an sRGB `OffscreenTarget` that is copied back to host memory once the
+// frame is done.
+
+use glam::Mat4;
+use image::RgbaImage;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        AutoCommandBufferBuilder, CommandBufferUsage, CopyImageToBufferInfo, SubpassEndInfo,
+    },
+    format::{ClearColorValue, Format},
+    image::view::ImageView,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    render_pass::{RenderPass, Subpass},
+    sync::{self, GpuFuture},
+};
+
+use crate::{
+    device_requirements::DeviceRequirements,
+    error::AppError,
+    gpu::Gpu,
+    offscreen::OffscreenTarget,
+    scene::Scene,
+    scene_pipeline::{DrawStats, ScenePipeline},
+    texture::Texture,
+};
+
+pub struct HeadlessRenderer {
+    gpu: Gpu,
+    render_pass: Arc<RenderPass>,
+    scene_pipeline: ScenePipeline,
+}
+
+impl HeadlessRenderer {
+    /// The format of the rendered images, matching what `render` returns.
+    pub const FORMAT: Format = Format::R8G8B8A8_SRGB;
+
+    /// Asks for what the device passed to `new` should support.
+    pub fn register_requirements(requirements: &mut DeviceRequirements) {
+        ScenePipeline::register_requirements(requirements);
+    }
+
+    pub fn new(gpu: Gpu) -> Result<Self, AppError> {
+        let render_pass = OffscreenTarget::create_render_pass(
+            gpu.device.clone(),
+            &[Self::FORMAT],
+            Some(Format::D16_UNORM),
+        )?;
+
+        let scene_pipeline = ScenePipeline::new(
+            gpu.memory_allocator.clone(),
+            gpu.descriptor_set_allocator.clone(),
+            Texture::white(
+                gpu.memory_allocator.clone(),
+                gpu.command_buffer_allocator.clone(),
+                &gpu.queue,
+            ),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+        )?;
+
+        Ok(HeadlessRenderer {
+            gpu,
+            render_pass,
+            scene_pipeline,
+        })
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    pub fn scene_pipeline(&self) -> &ScenePipeline {
+        &self.scene_pipeline
+    }
+
+    /// Creates an image of the given size to render into with `render_to`.
+    pub fn create_target(&self, extent: [u32; 2]) -> Result<OffscreenTarget, AppError> {
+        OffscreenTarget::new(
+            self.gpu.memory_allocator.clone(),
+            self.render_pass.clone(),
+            extent,
+        )
+    }
+
+    /// Creates a target that draws into `color`, an image of `FORMAT` made elsewhere, such as by
+    /// an OpenXR runtime, for `render_to`.
+    pub fn create_target_for(&self, color: Arc<ImageView>) -> Result<OffscreenTarget, AppError> {
+        OffscreenTarget::with_colors(
+            self.gpu.memory_allocator.clone(),
+            self.render_pass.clone(),
+            vec![color],
+        )
+    }
+
+    /// Renders `scene` into a new image of the given size and waits for the result.
+    pub fn render(
+        &self,
+        scene: &Scene,
+        view_proj: Mat4,
+        extent: [u32; 2],
+        clear_color: [f32; 4],
+    ) -> Result<RgbaImage, AppError> {
+        let target = self.create_target(extent)?;
+        let readback_buffer = Buffer::new_slice::<u8>(
+            self.gpu.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            u64::from(extent[0]) * u64::from(extent[1]) * 4,
+        )
+        .unwrap();
+
+        self.render_frame(
+            &target,
+            scene,
+            view_proj,
+            clear_color,
+            Some(readback_buffer.clone()),
+        );
+
+        let pixels = readback_buffer.read().unwrap().to_vec();
+        Ok(RgbaImage::from_raw(extent[0], extent[1], pixels).unwrap())
+    }
+
+    /// Renders `scene` into `target` and waits for the GPU to finish, without reading the image
+    /// back.
+    pub fn render_to(
+        &self,
+        target: &OffscreenTarget,
+        scene: &Scene,
+        view_proj: Mat4,
+        clear_color: [f32; 4],
+    ) -> DrawStats {
+        self.render_frame(target, scene, view_proj, clear_color, None)
+    }
+
+    fn render_frame(
+        &self,
+        target: &OffscreenTarget,
+        scene: &Scene,
+        view_proj: Mat4,
+        clear_color: [f32; 4],
+        readback_buffer: Option<Subbuffer<[u8]>>,
+    ) -> DrawStats {
+        let gpu = &self.gpu;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            gpu.command_buffer_allocator.clone(),
+            gpu.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        target.begin_render_pass(&mut builder, &[ClearColorValue::Float(clear_color)]);
+        let stats =
+            self.scene_pipeline
+                .draw(&mut builder, scene, view_proj, target.viewport(), None);
+
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+        if let Some(readback_buffer) = readback_buffer {
+            builder
+                .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                    target.color().image().clone(),
+                    readback_buffer,
+                ))
+                .unwrap();
+        }
+
+        let command_buffer = builder.build().unwrap();
+
+        sync::now(gpu.device.clone())
+            .then_execute(gpu.queue.clone(), command_buffer)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        stats
+    }
+}
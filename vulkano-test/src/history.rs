@@ -0,0 +1,357 @@
+// Undo and redo for edits of the scene. Every edit made from the window is an `Edit`, which knows
+// how to make itself and how to take itself back: moving an entity, changing its material or its
+// material override, and adding or removing an entity. `History` keeps the edits made so far, to
+// be taken back in turn with Ctrl+Z, and those taken back, to be made again with Ctrl+Y, until a
+// new edit is made. Only the last `History::CAPACITY` edits are kept, as removals hold on to whole
+// entities.
+//
+// Edits that happen gradually, such as dragging a gizmo, change the scene as they go and are only
+// recorded once they are done, as a single edit from where they started to where they ended.
+//
+// Removed entities keep their components in the edit, to be spawned again under the same handle,
+// so that the edits around them still find them. Only the components of `components` are kept,
+// which are all that entities of the scene are made of.
+
+use glam::Mat4;
+use hecs::{BuiltEntityClone, Component, Entity, EntityBuilderClone, EntityRef};
+use std::collections::VecDeque;
+
+use crate::{
+    components::{Light, MaterialOverride, MeshHandle, SceneNode, Spin, Transform},
+    material::Material,
+    scene::Scene,
+};
+
+/// An edit of the scene, with what it changed from and to.
+pub enum Edit {
+    /// `entity` moved, from one world transform to another.
+    Transform {
+        entity: Entity,
+        before: Mat4,
+        after: Mat4,
+    },
+    /// The material of `entity`, its override or the scene's material variants changed. The
+    /// states are boxed, as they are much larger than the other edits.
+    Material {
+        entity: Entity,
+        before: Box<MaterialState>,
+        after: Box<MaterialState>,
+    },
+    /// `entity` was added, with `components`.
+    Spawn {
+        entity: Entity,
+        components: BuiltEntityClone,
+    },
+    /// `entity` was removed, and had `components`.
+    Despawn {
+        entity: Entity,
+        components: BuiltEntityClone,
+    },
+}
+
+/// The materials that an entity is drawn with: its own, its override and the variants that the
+/// override may be one of, which the material editor edits along with it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialState {
+    pub material: Option<Material>,
+    pub material_override: Option<MaterialOverride>,
+    pub variants: Vec<Material>,
+}
+
+impl MaterialState {
+    pub fn of(scene: &Scene, entity: Entity) -> Self {
+        MaterialState {
+            material: scene
+                .world
+                .get::<&Material>(entity)
+                .ok()
+                .map(|material| (*material).clone()),
+            material_override: scene
+                .world
+                .get::<&MaterialOverride>(entity)
+                .ok()
+                .map(|material_override| (*material_override).clone()),
+            variants: scene.materials.clone(),
+        }
+    }
+
+    fn restore(&self, scene: &mut Scene, entity: Entity) {
+        scene.materials.clone_from(&self.variants);
+        if let Some(material) = &self.material {
+            let _ = scene.world.insert_one(entity, material.clone());
+        }
+        match &self.material_override {
+            Some(material_override) => {
+                let _ = scene.world.insert_one(entity, material_override.clone());
+            }
+            None => {
+                let _ = scene.world.remove_one::<MaterialOverride>(entity);
+            }
+        }
+    }
+}
+
+impl Edit {
+    /// Removes `entity` from `scene`, returning the edit that did it, or `None` if it no longer
+    /// exists.
+    pub fn despawn(scene: &mut Scene, entity: Entity) -> Option<Self> {
+        let components = snapshot(scene.world.entity(entity).ok()?).build();
+        scene.world.despawn(entity).ok()?;
+        Some(Edit::Despawn { entity, components })
+    }
+
+    /// Adds a copy of `entity` to `scene`, returning the edit that did it with the copy as its
+    /// entity, or `None` if `entity` no longer exists. A copy of an entity placed by a node is
+    /// placed by a node of its own, under the same parent and at the same place.
+    pub fn duplicate(scene: &mut Scene, entity: Entity) -> Option<Self> {
+        let entity_ref = scene.world.entity(entity).ok()?;
+        let node = entity_ref.get::<&SceneNode>().map(|node| node.0);
+        let mut builder = snapshot(entity_ref);
+        if let Some(node) = node {
+            let node = scene.node(node);
+            let copy = scene.add_node(node.parent(), node.local_transform());
+            builder.add(SceneNode(copy));
+        }
+        let components = builder.build();
+        let copy = scene.world.spawn(&components);
+        Some(Edit::Spawn {
+            entity: copy,
+            components,
+        })
+    }
+
+    /// The entity that the edit is of.
+    pub fn entity(&self) -> Entity {
+        match self {
+            Edit::Transform { entity, .. }
+            | Edit::Material { entity, .. }
+            | Edit::Spawn { entity, .. }
+            | Edit::Despawn { entity, .. } => *entity,
+        }
+    }
+
+    /// What the edit did, for the log.
+    pub fn describe(&self) -> String {
+        match self {
+            Edit::Transform { entity, .. } => format!("move of entity {}", entity.id()),
+            Edit::Material { entity, .. } => format!("material edit of entity {}", entity.id()),
+            Edit::Spawn { entity, .. } => format!("addition of entity {}", entity.id()),
+            Edit::Despawn { entity, .. } => format!("removal of entity {}", entity.id()),
+        }
+    }
+
+    /// Makes the edit again, after it was taken back.
+    fn redo(&self, scene: &mut Scene) {
+        match self {
+            Edit::Transform { entity, after, .. } => scene.set_world_transform(*entity, *after),
+            Edit::Material { entity, after, .. } => after.restore(scene, *entity),
+            Edit::Spawn { entity, components } => scene.world.spawn_at(*entity, components),
+            Edit::Despawn { entity, .. } => {
+                let _ = scene.world.despawn(*entity);
+            }
+        }
+    }
+
+    /// Takes the edit back.
+    fn undo(&self, scene: &mut Scene) {
+        match self {
+            Edit::Transform { entity, before, .. } => scene.set_world_transform(*entity, *before),
+            Edit::Material { entity, before, .. } => before.restore(scene, *entity),
+            Edit::Spawn { entity, .. } => {
+                let _ = scene.world.despawn(*entity);
+            }
+            Edit::Despawn { entity, components } => scene.world.spawn_at(*entity, components),
+        }
+    }
+}
+
+/// A copy of the components of an entity, to spawn it again with.
+fn snapshot(entity: EntityRef<'_>) -> EntityBuilderClone {
+    fn add<T: Component + Clone>(builder: &mut EntityBuilderClone, entity: EntityRef<'_>) {
+        if let Some(component) = entity.get::<&T>() {
+            builder.add((*component).clone());
+        }
+    }
+
+    let mut builder = EntityBuilderClone::new();
+    add::<SceneNode>(&mut builder, entity);
+    add::<Transform>(&mut builder, entity);
+    add::<MeshHandle>(&mut builder, entity);
+    add::<Material>(&mut builder, entity);
+    add::<MaterialOverride>(&mut builder, entity);
+    add::<Spin>(&mut builder, entity);
+    add::<Light>(&mut builder, entity);
+    builder
+}
+
+#[derive(Default)]
+pub struct History {
+    /// The edits made, the last one last.
+    done: VecDeque<Edit>,
+    /// The edits taken back, the last one taken back last.
+    undone: Vec<Edit>,
+}
+
+impl History {
+    /// How many edits can be taken back.
+    pub const CAPACITY: usize = 256;
+
+    /// Records `edit`, which has already been made to the scene, forgetting the oldest edit if
+    /// there are `CAPACITY` already. The edits taken back can't be made again after it.
+    pub fn record(&mut self, edit: Edit) {
+        if self.done.len() == Self::CAPACITY {
+            self.done.pop_front();
+        }
+        self.done.push_back(edit);
+        self.undone.clear();
+    }
+
+    /// Takes back the last edit made to `scene`, returning it, or `None` if there is none.
+    pub fn undo(&mut self, scene: &mut Scene) -> Option<&Edit> {
+        let edit = self.done.pop_back()?;
+        edit.undo(scene);
+        self.undone.push(edit);
+        self.undone.last()
+    }
+
+    /// Makes the last edit taken back again, returning it, or `None` if there is none.
+    pub fn redo(&mut self, scene: &mut Scene) -> Option<&Edit> {
+        let edit = self.undone.pop()?;
+        edit.redo(scene);
+        self.done.push_back(edit);
+        self.done.back()
+    }
+
+    /// Forgets every edit, as those of a scene that was replaced.
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Vec3, Vec4};
+
+    fn translation(x: f32) -> Mat4 {
+        Mat4::from_translation(Vec3::new(x, 0.0, 0.0))
+    }
+
+    fn x_of(scene: &Scene, entity: Entity) -> f32 {
+        scene.world.get::<&Transform>(entity).unwrap().0.w_axis.x
+    }
+
+    /// Moves `entity` to `x` and records it.
+    fn move_to(history: &mut History, scene: &mut Scene, entity: Entity, x: f32) {
+        let before = scene.world.get::<&Transform>(entity).unwrap().0;
+        scene.set_world_transform(entity, translation(x));
+        history.record(Edit::Transform {
+            entity,
+            before,
+            after: translation(x),
+        });
+    }
+
+    #[test]
+    fn undo_and_redo_moves() {
+        let mut scene = Scene::default();
+        let entity = scene.world.spawn((Transform(translation(0.0)),));
+        let mut history = History::default();
+        move_to(&mut history, &mut scene, entity, 1.0);
+        move_to(&mut history, &mut scene, entity, 2.0);
+
+        assert_eq!(history.undo(&mut scene).map(Edit::entity), Some(entity));
+        assert_eq!(x_of(&scene, entity), 1.0);
+        history.undo(&mut scene);
+        assert_eq!(x_of(&scene, entity), 0.0);
+        assert!(history.undo(&mut scene).is_none());
+
+        history.redo(&mut scene);
+        history.redo(&mut scene);
+        assert_eq!(x_of(&scene, entity), 2.0);
+        assert!(history.redo(&mut scene).is_none());
+    }
+
+    #[test]
+    fn new_edit_forgets_the_undone_ones() {
+        let mut scene = Scene::default();
+        let entity = scene.world.spawn((Transform(translation(0.0)),));
+        let mut history = History::default();
+        move_to(&mut history, &mut scene, entity, 1.0);
+        move_to(&mut history, &mut scene, entity, 2.0);
+        history.undo(&mut scene);
+
+        move_to(&mut history, &mut scene, entity, 3.0);
+        assert!(history.redo(&mut scene).is_none());
+        assert_eq!(x_of(&scene, entity), 3.0);
+
+        history.undo(&mut scene);
+        assert_eq!(x_of(&scene, entity), 1.0);
+        history.undo(&mut scene);
+        assert_eq!(x_of(&scene, entity), 0.0);
+        assert!(history.undo(&mut scene).is_none());
+    }
+
+    #[test]
+    fn only_the_last_edits_are_kept() {
+        let mut scene = Scene::default();
+        let entity = scene.world.spawn((Transform(translation(0.0)),));
+        let mut history = History::default();
+        for i in 1..=History::CAPACITY + 1 {
+            move_to(&mut history, &mut scene, entity, i as f32);
+        }
+
+        for _ in 0..History::CAPACITY {
+            assert!(history.undo(&mut scene).is_some());
+        }
+        assert!(history.undo(&mut scene).is_none());
+        // The first move was forgotten, so its result is as far back as it goes.
+        assert_eq!(x_of(&scene, entity), 1.0);
+    }
+
+    #[test]
+    fn removed_entities_come_back_as_they_were() {
+        let mut scene = Scene::default();
+        let material = Material {
+            base_color: Vec4::new(1.0, 0.5, 0.25, 1.0),
+            ..Default::default()
+        };
+        let entity = scene
+            .world
+            .spawn((Transform(translation(4.0)), material.clone()));
+        let mut history = History::default();
+        history.record(Edit::despawn(&mut scene, entity).unwrap());
+        assert!(!scene.world.contains(entity));
+
+        history.undo(&mut scene);
+        assert_eq!(x_of(&scene, entity), 4.0);
+        assert_eq!(*scene.world.get::<&Material>(entity).unwrap(), material);
+
+        history.redo(&mut scene);
+        assert!(!scene.world.contains(entity));
+    }
+
+    #[test]
+    fn undo_restores_materials() {
+        let mut scene = Scene::default();
+        let entity = scene.world.spawn((Material::default(),));
+        let before = MaterialState::of(&scene, entity);
+        scene.world.get::<&mut Material>(entity).unwrap().roughness = 0.25;
+        let after = MaterialState::of(&scene, entity);
+        let mut history = History::default();
+        history.record(Edit::Material {
+            entity,
+            before: Box::new(before),
+            after: Box::new(after),
+        });
+
+        history.undo(&mut scene);
+        assert_eq!(scene.world.get::<&Material>(entity).unwrap().roughness, 1.0);
+        history.redo(&mut scene);
+        assert_eq!(
+            scene.world.get::<&Material>(entity).unwrap().roughness,
+            0.25
+        );
+    }
+}
@@ -0,0 +1,49 @@
+// Window icons. The app's own icon is embedded in the binary, and `settings.toml` can point to a
+// PNG file to show instead. Some platforms, such as Wayland and macOS, ignore window icons and
+// take the icon from the desktop entry or app bundle instead.
+
+use image::{DynamicImage, ImageError, ImageFormat};
+use std::{fmt, path::Path};
+use winit::window::{BadIcon, Icon};
+
+#[derive(Debug)]
+pub enum IconError {
+    Image(ImageError),
+    Icon(BadIcon),
+}
+
+impl fmt::Display for IconError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IconError::Image(err) => write!(f, "failed to read the icon: {err}"),
+            IconError::Icon(err) => write!(f, "unusable icon: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for IconError {}
+
+/// The icon the window has unless the settings name another.
+pub fn default_icon() -> Icon {
+    from_png(include_bytes!("icon.png")).expect("the embedded icon is a valid PNG")
+}
+
+/// Decodes a PNG image into an icon.
+pub fn from_png(bytes: &[u8]) -> Result<Icon, IconError> {
+    image::load_from_memory_with_format(bytes, ImageFormat::Png)
+        .map_err(IconError::Image)
+        .and_then(from_image)
+}
+
+/// Reads an icon from an image file.
+pub fn load(path: &Path) -> Result<Icon, IconError> {
+    image::open(path)
+        .map_err(IconError::Image)
+        .and_then(from_image)
+}
+
+fn from_image(image: DynamicImage) -> Result<Icon, IconError> {
+    let image = image.into_rgba8();
+    let (width, height) = image.dimensions();
+    Icon::from_rgba(image.into_raw(), width, height).map_err(IconError::Icon)
+}
@@ -0,0 +1,363 @@
+// The report of `vulkano-test info`: what the Vulkan instance and every physical device support,
+// for attaching to bug reports. It lists the instance layers and extensions, and for each device
+// its properties, the limits that the renderer runs into, its memory heaps and types, its queue
+// families and its extensions. The same report is printed as text or, with `--json`, as JSON.
+//
+// Surface formats and present modes depend on the surface, so an invisible window is created to
+// ask about. Where there is no display to create it on, those are left out.
+//
+// The console's `info` command and the `copy-gpu-info` key, Ctrl+I by default, copy the same
+// report to the clipboard from the running app, with only the device it runs on and the surface
+// of its window.
+
+use serde::Serialize;
+use std::{fmt::Write, sync::Arc};
+use vulkano::{
+    device::physical::PhysicalDevice,
+    instance::{Instance, InstanceExtensions},
+    swapchain::{FromWindowError, Surface, SurfaceInfo},
+};
+use winit::{
+    application::ApplicationHandler,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, EventLoop},
+    window::{Window, WindowId},
+};
+
+use crate::{error::AppError, gpu::create_instance};
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub api_version: String,
+    pub layers: Vec<LayerReport>,
+    pub instance_extensions: Vec<&'static str>,
+    pub devices: Vec<DeviceReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LayerReport {
+    pub name: String,
+    pub description: String,
+    pub vulkan_version: String,
+    pub implementation_version: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceReport {
+    pub name: String,
+    pub device_type: String,
+    pub api_version: String,
+    pub driver: String,
+    /// The name and value of the limits, in the order they are printed.
+    pub limits: Vec<(&'static str, String)>,
+    pub memory_heaps: Vec<MemoryHeapReport>,
+    pub memory_types: Vec<MemoryTypeReport>,
+    pub queue_families: Vec<QueueFamilyReport>,
+    pub extensions: Vec<&'static str>,
+    /// What the device supports for the window's surface, if there was one.
+    pub surface: Option<SurfaceReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemoryHeapReport {
+    pub size: u64,
+    pub flags: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemoryTypeReport {
+    pub heap: u32,
+    pub flags: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueFamilyReport {
+    pub count: u32,
+    pub flags: String,
+    /// Whether the family can present to the window's surface, if there was one.
+    pub present: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SurfaceReport {
+    /// The format and color space of each supported surface format.
+    pub formats: Vec<(String, String)>,
+    pub present_modes: Vec<String>,
+}
+
+/// The name and debug representation of each of `fields` of `properties`.
+macro_rules! limits {
+    ($properties:expr, $($field:ident),* $(,)?) => {
+        vec![$((stringify!($field), format!("{:?}", $properties.$field))),*]
+    };
+}
+
+impl Report {
+    /// Gathers the report, with the window's surface if one can be created.
+    pub fn gather() -> Result<Self, AppError> {
+        let Ok(event_loop) = EventLoop::new() else {
+            return Self::without_surface();
+        };
+        let mut gather = GatherWithSurface(None);
+        event_loop.run_app(&mut gather)?;
+        match gather.0 {
+            Some(Ok(report)) => Ok(report),
+            _ => Self::without_surface(),
+        }
+    }
+
+    fn without_surface() -> Result<Self, AppError> {
+        let instance = create_instance(InstanceExtensions::empty())?;
+        Self::new(&instance, None)
+    }
+
+    /// The report on `physical_device` alone, the one the app runs on, and the surface of its
+    /// window, as the console's `info` command copies it.
+    pub fn of_device(
+        physical_device: &Arc<PhysicalDevice>,
+        surface: &Surface,
+    ) -> Result<Self, AppError> {
+        let device = DeviceReport::new(physical_device, Some(surface));
+        Self::with_devices(physical_device.instance(), vec![device])
+    }
+
+    fn new(instance: &Arc<Instance>, surface: Option<&Surface>) -> Result<Self, AppError> {
+        let devices = instance
+            .enumerate_physical_devices()
+            .map_err(|err| AppError::Instance(err.into()))?
+            .map(|p| DeviceReport::new(&p, surface))
+            .collect();
+        Self::with_devices(instance, devices)
+    }
+
+    fn with_devices(
+        instance: &Arc<Instance>,
+        devices: Vec<DeviceReport>,
+    ) -> Result<Self, AppError> {
+        let library = instance.library();
+        let layers = library
+            .layer_properties()
+            .map_err(|err| AppError::Instance(err.into()))?
+            .map(|layer| LayerReport {
+                name: layer.name().to_owned(),
+                description: layer.description().to_owned(),
+                vulkan_version: layer.vulkan_version().to_string(),
+                implementation_version: layer.implementation_version(),
+            })
+            .collect();
+
+        Ok(Report {
+            api_version: instance.api_version().to_string(),
+            layers,
+            instance_extensions: supported(*library.supported_extensions()),
+            devices,
+        })
+    }
+
+    /// The report as text, with a line for each item.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        // Writing to a `String` can't fail.
+        let _ = writeln!(text, "Vulkan {}", self.api_version);
+        let _ = writeln!(text, "\nlayers:");
+        for layer in &self.layers {
+            let _ = writeln!(
+                text,
+                "    {} (Vulkan {}, version {}): {}",
+                layer.name, layer.vulkan_version, layer.implementation_version, layer.description,
+            );
+        }
+        let _ = writeln!(text, "\ninstance extensions:");
+        for extension in &self.instance_extensions {
+            let _ = writeln!(text, "    {extension}");
+        }
+
+        for (index, device) in self.devices.iter().enumerate() {
+            let _ = writeln!(text, "\n{index}: {}", device.name);
+            let _ = writeln!(text, "    type: {}", device.device_type);
+            let _ = writeln!(text, "    API version: {}", device.api_version);
+            let _ = writeln!(text, "    driver: {}", device.driver);
+            let _ = writeln!(text, "    limits:");
+            for (name, value) in &device.limits {
+                let _ = writeln!(text, "        {name}: {value}");
+            }
+            for (i, heap) in device.memory_heaps.iter().enumerate() {
+                let _ = writeln!(
+                    text,
+                    "    memory heap {i}: {} MiB {}",
+                    heap.size >> 20,
+                    heap.flags
+                );
+            }
+            for (i, memory_type) in device.memory_types.iter().enumerate() {
+                let _ = writeln!(
+                    text,
+                    "    memory type {i}: heap {} {}",
+                    memory_type.heap, memory_type.flags
+                );
+            }
+            for (i, family) in device.queue_families.iter().enumerate() {
+                let present = match family.present {
+                    Some(true) => ", can present",
+                    Some(false) => ", can't present",
+                    None => "",
+                };
+                let _ = writeln!(
+                    text,
+                    "    queue family {i}: {} x {}{present}",
+                    family.count, family.flags
+                );
+            }
+            match &device.surface {
+                Some(surface) => {
+                    let _ = writeln!(text, "    surface formats:");
+                    for (format, color_space) in &surface.formats {
+                        let _ = writeln!(text, "        {format} {color_space}");
+                    }
+                    let _ = writeln!(
+                        text,
+                        "    present modes: {}",
+                        surface.present_modes.join(", ")
+                    );
+                }
+                None => {
+                    let _ = writeln!(text, "    surface: no window to ask about");
+                }
+            }
+            let _ = writeln!(text, "    extensions:");
+            for extension in &device.extensions {
+                let _ = writeln!(text, "        {extension}");
+            }
+        }
+
+        text
+    }
+}
+
+impl DeviceReport {
+    fn new(p: &PhysicalDevice, surface: Option<&Surface>) -> Self {
+        let properties = p.properties();
+        let driver = match (&properties.driver_name, &properties.driver_info) {
+            (Some(name), Some(info)) => format!("{name} {info}"),
+            (Some(name), None) => name.clone(),
+            // The encoding is vendor specific without the driver properties.
+            _ => format!("{:#x}", properties.driver_version),
+        };
+        let memory = p.memory_properties();
+        let surface_report = surface.and_then(|surface| {
+            let formats = p.surface_formats(surface, SurfaceInfo::default()).ok()?;
+            let present_modes = p
+                .surface_present_modes(surface, SurfaceInfo::default())
+                .ok()?;
+            Some(SurfaceReport {
+                formats: formats
+                    .into_iter()
+                    .map(|(format, color_space)| {
+                        (format!("{format:?}"), format!("{color_space:?}"))
+                    })
+                    .collect(),
+                present_modes: present_modes
+                    .into_iter()
+                    .map(|mode| format!("{mode:?}"))
+                    .collect(),
+            })
+        });
+
+        DeviceReport {
+            name: properties.device_name.clone(),
+            device_type: format!("{:?}", properties.device_type),
+            api_version: properties.api_version.to_string(),
+            driver,
+            limits: limits!(
+                properties,
+                max_image_dimension2_d,
+                max_image_array_layers,
+                max_uniform_buffer_range,
+                max_storage_buffer_range,
+                max_push_constants_size,
+                max_memory_allocation_count,
+                max_bound_descriptor_sets,
+                max_per_stage_descriptor_samplers,
+                max_per_stage_descriptor_sampled_images,
+                max_per_stage_descriptor_storage_images,
+                max_descriptor_set_update_after_bind_sampled_images,
+                max_compute_shared_memory_size,
+                max_compute_work_group_count,
+                max_compute_work_group_invocations,
+                max_compute_work_group_size,
+                max_sampler_anisotropy,
+                max_viewports,
+                max_framebuffer_width,
+                max_framebuffer_height,
+                framebuffer_color_sample_counts,
+                line_width_range,
+                timestamp_period,
+                min_uniform_buffer_offset_alignment,
+                min_storage_buffer_offset_alignment,
+                non_coherent_atom_size,
+            ),
+            memory_heaps: memory
+                .memory_heaps
+                .iter()
+                .map(|heap| MemoryHeapReport {
+                    size: heap.size,
+                    flags: format!("{:?}", heap.flags),
+                })
+                .collect(),
+            memory_types: memory
+                .memory_types
+                .iter()
+                .map(|memory_type| MemoryTypeReport {
+                    heap: memory_type.heap_index,
+                    flags: format!("{:?}", memory_type.property_flags),
+                })
+                .collect(),
+            queue_families: p
+                .queue_family_properties()
+                .iter()
+                .enumerate()
+                .map(|(i, family)| QueueFamilyReport {
+                    count: family.queue_count,
+                    flags: format!("{:?}", family.queue_flags),
+                    present: surface.and_then(|surface| p.surface_support(i as u32, surface).ok()),
+                })
+                .collect(),
+            extensions: supported(*p.supported_extensions()),
+            surface: surface_report,
+        }
+    }
+}
+
+/// The names of the extensions in `extensions`.
+fn supported<E: IntoIterator<Item = (&'static str, bool)>>(extensions: E) -> Vec<&'static str> {
+    extensions
+        .into_iter()
+        .filter_map(|(name, supported)| supported.then_some(name))
+        .collect()
+}
+
+/// Gathers the report once the event loop can create a window, then exits.
+struct GatherWithSurface(Option<Result<Report, AppError>>);
+
+impl GatherWithSurface {
+    fn gather(event_loop: &ActiveEventLoop) -> Result<Report, AppError> {
+        let extensions = Surface::required_extensions(event_loop)
+            .map_err(|err| AppError::Surface(FromWindowError::RetrieveHandle(err)))?;
+        let instance = create_instance(extensions)?;
+        let window =
+            Arc::new(event_loop.create_window(Window::default_attributes().with_visible(false))?);
+        let surface = Surface::from_window(instance.clone(), window)?;
+        Report::new(&instance, Some(&surface))
+    }
+}
+
+impl ApplicationHandler for GatherWithSurface {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.0.is_none() {
+            self.0 = Some(Self::gather(event_loop));
+        }
+        event_loop.exit();
+    }
+
+    fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, _: WindowEvent) {}
+}
@@ -0,0 +1,197 @@
+// Recording the input of the window, to replay it later. `--record-input file` writes every input
+// event that the app handles to a file, one JSON object per line, stamped with the number of
+// frames drawn before it arrived, along with how far the clock of `Time` moved between frames.
+// `--replay file` feeds them back in the same order: the events and clock steps stamped with a
+// frame are handled just before that frame is drawn, and the real input is ignored until the
+// replay runs out. The simulation, particles and water then go through the same states as when
+// they were recorded, whatever the frame rate.
+//
+// Only input goes in the file, so a replay should start from the same scene, settings and window
+// size as the recording did. The camera's glides and the fly camera's movement follow the wall
+// clock, and come out the same only as far as the frames come as quickly.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::error;
+use winit::{
+    event::{ElementState, Ime, KeyEvent, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
+    keyboard::{ModifiersState, PhysicalKey},
+};
+
+/// An input event, as the app handles it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Key {
+        key: PhysicalKey,
+        pressed: bool,
+        repeat: bool,
+        /// The text that the key typed, if any.
+        text: Option<String>,
+    },
+    Modifiers(ModifiersState),
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+    /// The cursor moved to `position`, in physical pixels from the top left of the window.
+    CursorMoved {
+        position: [f64; 2],
+    },
+    /// The mouse wheel turned by `lines`, up being positive.
+    MouseWheel {
+        lines: f32,
+    },
+    /// The mouse moved by `delta` while the cursor was hidden, in unspecified units.
+    MouseMotion {
+        delta: [f64; 2],
+    },
+    Touch {
+        id: u64,
+        phase: TouchPhase,
+        position: [f64; 2],
+    },
+    Focused(bool),
+    /// The text being composed by an input method changed to `text`, which is empty once the
+    /// composition ends.
+    ImePreedit {
+        text: String,
+    },
+    /// An input method typed `text`, ending its composition.
+    ImeCommit {
+        text: String,
+    },
+    /// The file at `path` was dropped onto the window.
+    DroppedFile {
+        path: PathBuf,
+    },
+}
+
+impl InputEvent {
+    /// The input in `event`, if it is input.
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        Some(match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key,
+                        state,
+                        repeat,
+                        text,
+                        ..
+                    },
+                ..
+            } => InputEvent::Key {
+                key: *physical_key,
+                pressed: *state == ElementState::Pressed,
+                repeat: *repeat,
+                text: text.as_ref().map(ToString::to_string),
+            },
+            WindowEvent::ModifiersChanged(modifiers) => InputEvent::Modifiers(modifiers.state()),
+            WindowEvent::MouseInput { state, button, .. } => InputEvent::MouseButton {
+                button: *button,
+                pressed: *state == ElementState::Pressed,
+            },
+            WindowEvent::CursorMoved { position, .. } => InputEvent::CursorMoved {
+                position: [position.x, position.y],
+            },
+            WindowEvent::MouseWheel { delta, .. } => InputEvent::MouseWheel {
+                lines: match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // Touchpads scroll in pixels, of which a line is about this many.
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                },
+            },
+            WindowEvent::Touch(touch) => InputEvent::Touch {
+                id: touch.id,
+                phase: touch.phase,
+                position: [touch.location.x, touch.location.y],
+            },
+            WindowEvent::Focused(focused) => InputEvent::Focused(*focused),
+            WindowEvent::Ime(Ime::Preedit(text, _)) => {
+                InputEvent::ImePreedit { text: text.clone() }
+            }
+            WindowEvent::Ime(Ime::Disabled) => InputEvent::ImePreedit {
+                text: String::new(),
+            },
+            WindowEvent::Ime(Ime::Commit(text)) => InputEvent::ImeCommit { text: text.clone() },
+            WindowEvent::DroppedFile(path) => InputEvent::DroppedFile { path: path.clone() },
+            _ => return None,
+        })
+    }
+}
+
+/// A line of a recording.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Recorded {
+    /// `event` arrived after `frame` frames were drawn.
+    Input { frame: u64, event: InputEvent },
+    /// The clock moved by `elapsed` after `frame` frames were drawn.
+    Clock { frame: u64, elapsed: Duration },
+}
+
+impl Recorded {
+    fn frame(&self) -> u64 {
+        match self {
+            Recorded::Input { frame, .. } | Recorded::Clock { frame, .. } => *frame,
+        }
+    }
+}
+
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    /// Whether writing already failed, which is only logged once.
+    failed: bool,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(InputRecorder {
+            writer: BufWriter::new(File::create(path)?),
+            failed: false,
+        })
+    }
+
+    pub fn record(&mut self, recorded: &Recorded) {
+        if self.failed {
+            return;
+        }
+        // Serializing the events can't fail.
+        let line = serde_json::to_string(recorded).unwrap();
+        if let Err(err) = writeln!(self.writer, "{line}") {
+            error!("Failed to record input: {err}");
+            self.failed = true;
+        }
+    }
+}
+
+/// A recording being replayed, from the next line on.
+pub struct InputReplay {
+    lines: VecDeque<Recorded>,
+}
+
+impl InputReplay {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let lines = BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect::<io::Result<_>>()?;
+        Ok(InputReplay { lines })
+    }
+
+    /// The next line to replay before drawing a frame with `frame` frames drawn before it, if
+    /// there is one left for it.
+    pub fn next(&mut self, frame: u64) -> Option<Recorded> {
+        self.lines.pop_front_if(|line| line.frame() <= frame)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
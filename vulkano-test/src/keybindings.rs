@@ -0,0 +1,476 @@
+// The hotkeys of the window, which the `[keybindings]` table of `settings.toml` can remap. Each
+// action is bound to a list of keys, written as the names that winit gives the physical keys,
+// after any of `Ctrl+`, `Shift+` and `Alt+`, such as `"Ctrl+Shift+KeyZ"`. Only the actions that
+// are listed in the table change; the rest keep their default keys, and an empty list unbinds an
+// action. Keys are physical, so `KeyZ` is where Z is on a US layout whatever the layout.
+//
+// A key pressed with Shift that isn't bound with Shift does what it does without, so that holding
+// Shift to pan doesn't get in the way. The keys that move the fly camera are taken before any
+// binding, so bindings to them without Ctrl never fire. The console's actions, copying and pasting
+// its line, only fire while it is open, and the others only while it is closed, but for opening
+// and closing it, which fires either way. Keys bound to several actions that fire at the same
+// time, and bindings that can't fire, are reported as conflicts, and the console's `keys` command
+// lists every binding.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+use winit::keyboard::{KeyCode, ModifiersState};
+
+use crate::camera_controller;
+
+/// Something that a hotkey does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+    ToggleConsole,
+    ConsoleCopy,
+    ConsolePaste,
+    CopyGpuInfo,
+    SelectNext,
+    CycleMaterialOverride,
+    ToggleCameraMode,
+    FrameSelected,
+    TogglePause,
+    StepTime,
+    SlowDown,
+    SpeedUp,
+    NextGizmoMode,
+    NextMaterialParameter,
+    DecreaseMaterialParameter,
+    IncreaseMaterialParameter,
+    DeleteSelected,
+    DuplicateSelected,
+    Undo,
+    Redo,
+    SaveScene,
+    Screenshot,
+    SupersampledScreenshot,
+    ToggleRecording,
+    SaveGif,
+    ToggleFullscreen,
+    ToggleMaterialPreview,
+    ToggleWireframe,
+    ToggleShadows,
+    ToggleDenoise,
+}
+
+/// Every action, with its name in the settings and the keys it is bound to by default.
+const ACTIONS: &[(Action, &str, &[&str])] = &[
+    (Action::ToggleConsole, "toggle-console", &["Backquote"]),
+    (Action::ConsoleCopy, "console-copy", &["Ctrl+KeyC"]),
+    (Action::ConsolePaste, "console-paste", &["Ctrl+KeyV"]),
+    (Action::CopyGpuInfo, "copy-gpu-info", &["Ctrl+KeyI"]),
+    (Action::SelectNext, "select-next", &["Tab"]),
+    (
+        Action::CycleMaterialOverride,
+        "cycle-material-override",
+        &["KeyM"],
+    ),
+    (Action::ToggleCameraMode, "toggle-camera-mode", &["KeyC"]),
+    (Action::FrameSelected, "frame-selected", &["KeyF"]),
+    (Action::TogglePause, "toggle-pause", &["Space"]),
+    (Action::StepTime, "step-time", &["Period"]),
+    (Action::SlowDown, "slow-down", &["Minus"]),
+    (Action::SpeedUp, "speed-up", &["Equal"]),
+    (Action::NextGizmoMode, "next-gizmo-mode", &["KeyG"]),
+    (
+        Action::NextMaterialParameter,
+        "next-material-parameter",
+        &["Backslash"],
+    ),
+    (
+        Action::DecreaseMaterialParameter,
+        "decrease-material-parameter",
+        &["BracketLeft"],
+    ),
+    (
+        Action::IncreaseMaterialParameter,
+        "increase-material-parameter",
+        &["BracketRight"],
+    ),
+    (Action::DeleteSelected, "delete-selected", &["Delete"]),
+    (
+        Action::DuplicateSelected,
+        "duplicate-selected",
+        &["Ctrl+KeyD"],
+    ),
+    (Action::Undo, "undo", &["Ctrl+KeyZ"]),
+    (Action::Redo, "redo", &["Ctrl+KeyY", "Ctrl+Shift+KeyZ"]),
+    (Action::SaveScene, "save-scene", &["F5"]),
+    (Action::Screenshot, "screenshot", &["F12"]),
+    (
+        Action::SupersampledScreenshot,
+        "supersampled-screenshot",
+        &["Ctrl+F12"],
+    ),
+    (Action::ToggleRecording, "toggle-recording", &["F9"]),
+    (Action::SaveGif, "save-gif", &["F10"]),
+    (Action::ToggleFullscreen, "toggle-fullscreen", &["F11"]),
+    (
+        Action::ToggleMaterialPreview,
+        "toggle-material-preview",
+        &["KeyP"],
+    ),
+    (Action::ToggleWireframe, "toggle-wireframe", &["KeyZ"]),
+    (Action::ToggleShadows, "toggle-shadows", &["KeyH"]),
+    (Action::ToggleDenoise, "toggle-denoise", &["KeyN"]),
+];
+
+impl Action {
+    /// The name of the action in the settings.
+    pub fn name(self) -> &'static str {
+        let (_, name, _) = ACTIONS.iter().find(|(action, ..)| *action == self).unwrap();
+        name
+    }
+
+    /// Whether the action fires while the console is open.
+    fn in_console(self) -> bool {
+        matches!(
+            self,
+            Action::ToggleConsole | Action::ConsoleCopy | Action::ConsolePaste
+        )
+    }
+
+    /// Whether the action fires while the console is closed.
+    fn in_window(self) -> bool {
+        !matches!(self, Action::ConsoleCopy | Action::ConsolePaste)
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(_, action_name, _)| *action_name == name)
+            .map(|(action, ..)| *action)
+    }
+}
+
+/// A key along with the modifiers held with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyBinding {
+    pub key: KeyCode,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl KeyBinding {
+    /// `key` pressed with `modifiers` held.
+    pub fn pressed(key: KeyCode, modifiers: ModifiersState) -> Self {
+        KeyBinding {
+            key,
+            ctrl: modifiers.control_key(),
+            shift: modifiers.shift_key(),
+            alt: modifiers.alt_key(),
+        }
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, modifier) in [
+            (self.ctrl, "Ctrl"),
+            (self.shift, "Shift"),
+            (self.alt, "Alt"),
+        ] {
+            if held {
+                write!(f, "{modifier}+")?;
+            }
+        }
+        write!(f, "{:?}", self.key)
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        let [mut ctrl, mut shift, mut alt] = [false; 3];
+        for modifier in parts {
+            match modifier {
+                "Ctrl" => ctrl = true,
+                "Shift" => shift = true,
+                "Alt" => alt = true,
+                _ => return Err(format!("unknown modifier {modifier:?} in {s:?}")),
+            }
+        }
+        // The names are those that winit's keys are serialized with.
+        let deserializer = serde::de::value::StrDeserializer::<serde::de::value::Error>::new(key);
+        let key = KeyCode::deserialize(deserializer)
+            .map_err(|_| format!("unknown key {key:?} in {s:?}"))?;
+        Ok(KeyBinding {
+            key,
+            ctrl,
+            shift,
+            alt,
+        })
+    }
+}
+
+impl TryFrom<String> for KeyBinding {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<KeyBinding> for String {
+    fn from(binding: KeyBinding) -> Self {
+        binding.to_string()
+    }
+}
+
+/// The keys that every action is bound to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(
+    try_from = "BTreeMap<String, Vec<KeyBinding>>",
+    into = "BTreeMap<String, Vec<KeyBinding>>"
+)]
+pub struct Keybindings {
+    bindings: BTreeMap<Action, Vec<KeyBinding>>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        let bindings = ACTIONS
+            .iter()
+            .map(|(action, _, keys)| {
+                let keys = keys.iter().map(|key| key.parse().unwrap()).collect();
+                (*action, keys)
+            })
+            .collect();
+        Keybindings { bindings }
+    }
+}
+
+impl TryFrom<BTreeMap<String, Vec<KeyBinding>>> for Keybindings {
+    type Error = String;
+
+    /// The default bindings, with those of the actions named in `table` replaced.
+    fn try_from(table: BTreeMap<String, Vec<KeyBinding>>) -> Result<Self, Self::Error> {
+        let mut keybindings = Keybindings::default();
+        for (name, keys) in table {
+            let action =
+                Action::from_name(&name).ok_or_else(|| format!("unknown action {name:?}"))?;
+            keybindings.bindings.insert(action, keys);
+        }
+        Ok(keybindings)
+    }
+}
+
+impl From<Keybindings> for BTreeMap<String, Vec<KeyBinding>> {
+    fn from(keybindings: Keybindings) -> Self {
+        keybindings
+            .bindings
+            .into_iter()
+            .map(|(action, keys)| (action.name().to_owned(), keys))
+            .collect()
+    }
+}
+
+impl Keybindings {
+    /// The action that `key` does when pressed with `modifiers` held while the console is
+    /// closed, if any.
+    pub fn action(&self, key: KeyCode, modifiers: ModifiersState) -> Option<Action> {
+        self.action_where(key, modifiers, Action::in_window)
+    }
+
+    /// The action that `key` does when pressed with `modifiers` held while the console is open,
+    /// if any.
+    pub fn console_action(&self, key: KeyCode, modifiers: ModifiersState) -> Option<Action> {
+        self.action_where(key, modifiers, Action::in_console)
+    }
+
+    fn action_where(
+        &self,
+        key: KeyCode,
+        modifiers: ModifiersState,
+        fires: fn(Action) -> bool,
+    ) -> Option<Action> {
+        let pressed = KeyBinding::pressed(key, modifiers);
+        self.find(pressed, fires).or_else(|| {
+            pressed
+                .shift
+                .then(|| {
+                    let unshifted = KeyBinding {
+                        shift: false,
+                        ..pressed
+                    };
+                    self.find(unshifted, fires)
+                })
+                .flatten()
+        })
+    }
+
+    fn find(&self, pressed: KeyBinding, fires: fn(Action) -> bool) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(action, keys)| fires(**action) && keys.contains(&pressed))
+            .map(|(action, _)| *action)
+    }
+
+    /// Descriptions of the keys bound to more than one action that fire at the same time, of
+    /// which only the first fires, and of the bindings that the fly camera's movement keys take
+    /// while the console is closed.
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut actions: BTreeMap<KeyBinding, Vec<Action>> = BTreeMap::new();
+        for (action, keys) in &self.bindings {
+            for key in keys {
+                actions.entry(*key).or_default().push(*action);
+            }
+        }
+
+        let names = |actions: &[Action], fires: fn(Action) -> bool| -> Vec<&str> {
+            actions
+                .iter()
+                .filter(|action| fires(**action))
+                .map(|action| action.name())
+                .collect()
+        };
+        let mut conflicts = Vec::new();
+        for (key, actions) in actions {
+            let window = names(&actions, Action::in_window);
+            let console = names(&actions, Action::in_console);
+            for names in [&window, &console] {
+                if names.len() > 1 {
+                    conflicts.push(format!("{key} is bound to {}", names.join(" and ")));
+                }
+            }
+            if !key.ctrl && !window.is_empty() && camera_controller::is_movement_key(key.key) {
+                conflicts.push(format!(
+                    "{key}, bound to {}, moves the fly camera instead",
+                    window.join(" and ")
+                ));
+            }
+        }
+        conflicts
+    }
+
+    /// A line for each action, with the keys it is bound to.
+    pub fn describe(&self) -> impl Iterator<Item = String> + '_ {
+        self.bindings.iter().map(|(action, keys)| {
+            let keys: Vec<String> = keys.iter().map(ToString::to_string).collect();
+            let keys = if keys.is_empty() {
+                "unbound".to_owned()
+            } else {
+                keys.join(", ")
+            };
+            format!("{}: {keys}", action.name())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The default bindings, with those of the actions in `table` replaced.
+    fn with(table: &[(&str, &[&str])]) -> Keybindings {
+        let table = table
+            .iter()
+            .map(|(name, keys)| {
+                let keys = keys.iter().map(|key| key.parse().unwrap()).collect();
+                (name.to_string(), keys)
+            })
+            .collect::<BTreeMap<_, _>>();
+        table.try_into().unwrap()
+    }
+
+    #[test]
+    fn the_defaults_dont_conflict() {
+        assert_eq!(Keybindings::default().conflicts(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn settings_file_lists_every_action_with_its_defaults() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/settings.toml");
+        let settings: toml::Table =
+            toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        let table = settings["keybindings"].as_table().unwrap();
+
+        for (_, name, _) in ACTIONS {
+            assert!(
+                table.contains_key(*name),
+                "settings.toml doesn't list {name}"
+            );
+        }
+        let keybindings: Keybindings = table.clone().try_into().unwrap();
+        assert_eq!(keybindings, Keybindings::default());
+    }
+
+    #[test]
+    fn reports_keys_bound_to_two_actions() {
+        let keybindings = with(&[("screenshot", &["F11"])]);
+        assert_eq!(
+            keybindings.conflicts(),
+            ["F11 is bound to screenshot and toggle-fullscreen"]
+        );
+        // Only the first of them fires.
+        let action = keybindings.action(KeyCode::F11, ModifiersState::empty());
+        assert_eq!(action, Some(Action::Screenshot));
+    }
+
+    #[test]
+    fn reports_bindings_taken_by_the_fly_camera() {
+        let keybindings = with(&[("screenshot", &["KeyW", "Ctrl+KeyW"])]);
+        assert_eq!(
+            keybindings.conflicts(),
+            ["KeyW, bound to screenshot, moves the fly camera instead"]
+        );
+    }
+
+    #[test]
+    fn console_actions_only_conflict_with_each_other() {
+        // Copying the console line and undoing never fire at the same time.
+        let keybindings = with(&[("console-copy", &["Ctrl+KeyZ"])]);
+        assert_eq!(keybindings.conflicts(), Vec::<String>::new());
+        let ctrl = ModifiersState::CONTROL;
+        assert_eq!(keybindings.action(KeyCode::KeyZ, ctrl), Some(Action::Undo));
+        assert_eq!(
+            keybindings.console_action(KeyCode::KeyZ, ctrl),
+            Some(Action::ConsoleCopy)
+        );
+
+        let keybindings = with(&[("console-paste", &["Ctrl+KeyC"])]);
+        assert_eq!(
+            keybindings.conflicts(),
+            ["Ctrl+KeyC is bound to console-copy and console-paste"]
+        );
+        // Toggling the console fires along with both kinds of actions.
+        let keybindings = with(&[("console-paste", &["Backquote"])]);
+        assert_eq!(
+            keybindings.conflicts(),
+            ["Backquote is bound to toggle-console and console-paste"]
+        );
+    }
+
+    #[test]
+    fn shift_falls_back_to_the_unshifted_binding() {
+        let keybindings = Keybindings::default();
+        let shift = ModifiersState::SHIFT;
+        assert_eq!(
+            keybindings.action(KeyCode::KeyF, shift),
+            Some(Action::FrameSelected)
+        );
+        // Unless there is a binding with Shift.
+        let ctrl_shift = ModifiersState::CONTROL | ModifiersState::SHIFT;
+        assert_eq!(
+            keybindings.action(KeyCode::KeyZ, ctrl_shift),
+            Some(Action::Redo)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_actions_and_keys() {
+        let table = BTreeMap::from([("fly".to_owned(), Vec::new())]);
+        assert!(Keybindings::try_from(table).is_err());
+        assert!("Ctrl+Nope".parse::<KeyBinding>().is_err());
+        assert!("Super+KeyA".parse::<KeyBinding>().is_err());
+        assert_eq!(
+            "Ctrl+Shift+KeyZ".parse::<KeyBinding>().unwrap().to_string(),
+            "Ctrl+Shift+KeyZ"
+        );
+    }
+}
@@ -0,0 +1,468 @@
+// KTX2 textures. A `.ktx2` file holds a texture in a Vulkan format, block-compressed more often
+// than not, with its mip levels ready for upload as they are: BC7 or BC1-5 for desktop GPUs, ETC2
+// or ASTC for mobile ones. Such a texture takes a quarter to an eighth of the VRAM of the same
+// image as RGBA, and uploads without decoding anything on the CPU.
+//
+// Only 2D textures of a single layer, uncompressed by a supercompression scheme, are read, and
+// their format has to be one the device can sample. Files holding Basis Universal data in UASTC
+// are transcoded to the first of BC7, ASTC 4x4 and ETC2 that the device can sample, level by
+// level, with the Basis Universal transcoder; that is only built with the `basisu` feature, and
+// without it they fail to load with an error that says so. Basis Universal's other kind of data,
+// ETC1S, can't be transcoded, as its codebooks are out of reach of the transcoder's Rust
+// bindings, and neither can files supercompressed with Zstandard. `toktx` or `basisu` can write
+// files in UASTC or in a GPU format instead.
+
+use std::{fmt, fs, io, path::Path};
+use vulkano::{device::Device, format::Format};
+
+use crate::texture::{self, TextureData};
+
+/// What a KTX2 file starts with.
+const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+
+/// The size of the header, the index and a level of the level index, in bytes.
+const HEADER_SIZE: usize = 48;
+const INDEX_SIZE: usize = 32;
+const LEVEL_SIZE: usize = 24;
+
+/// The supercompression scheme of Basis Universal's ETC1S data.
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+
+/// The color model that the data format descriptor gives Basis Universal's UASTC data.
+const COLOR_MODEL_UASTC: u8 = 166;
+
+/// The transfer function of the data format descriptor for sRGB colors.
+const TRANSFER_SRGB: u8 = 2;
+
+/// The channels of UASTC data with alpha, as the data format descriptor gives them: RGBA, and
+/// red and green as RRRG.
+const UASTC_CHANNELS_WITH_ALPHA: [u8; 2] = [3, 5];
+
+/// A format that UASTC data is transcoded to, all of which have 4x4 blocks of 16 bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranscodeTarget {
+    Bc7,
+    Astc4x4,
+    Etc2,
+}
+
+impl TranscodeTarget {
+    /// The first of BC7, ASTC 4x4 and ETC2, from the best looking down, that `device` can sample,
+    /// or `None` if it can sample none of them or this build can't transcode.
+    pub fn for_device(device: &Device) -> Option<Self> {
+        if !cfg!(feature = "basisu") {
+            return None;
+        }
+        [
+            TranscodeTarget::Bc7,
+            TranscodeTarget::Astc4x4,
+            TranscodeTarget::Etc2,
+        ]
+        .into_iter()
+        .find(|target| {
+            texture::can_sample(device, target.format(true))
+                && texture::can_sample(device, target.format(false))
+        })
+    }
+
+    /// The format to transcode to, for sRGB colors or linear values.
+    pub fn format(self, srgb: bool) -> Format {
+        match (self, srgb) {
+            (TranscodeTarget::Bc7, true) => Format::BC7_SRGB_BLOCK,
+            (TranscodeTarget::Bc7, false) => Format::BC7_UNORM_BLOCK,
+            (TranscodeTarget::Astc4x4, true) => Format::ASTC_4x4_SRGB_BLOCK,
+            (TranscodeTarget::Astc4x4, false) => Format::ASTC_4x4_UNORM_BLOCK,
+            (TranscodeTarget::Etc2, true) => Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+            (TranscodeTarget::Etc2, false) => Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Ktx2Error {
+    Io(io::Error),
+    /// The file doesn't start like a KTX2 file.
+    NotKtx2,
+    /// The file ends before the data its header says it has, or has a level of the wrong size.
+    Malformed,
+    /// The file holds UASTC data, which this build can't transcode, or the device can sample
+    /// none of the formats it is transcoded to.
+    BasisUniversal,
+    /// The file holds ETC1S data, which can't be transcoded.
+    Etc1s,
+    Supercompression(u32),
+    /// The texture isn't a single 2D image with mip levels.
+    UnsupportedShape,
+    UnsupportedFormat(i32),
+}
+
+impl fmt::Display for Ktx2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ktx2Error::Io(err) => err.fmt(f),
+            Ktx2Error::NotKtx2 => write!(f, "not a KTX2 file"),
+            Ktx2Error::Malformed => write!(f, "malformed KTX2 file"),
+            Ktx2Error::BasisUniversal if cfg!(feature = "basisu") => write!(
+                f,
+                "UASTC textures are transcoded to BC7, ASTC or ETC2, none of which the device can \
+                 sample",
+            ),
+            Ktx2Error::BasisUniversal => {
+                write!(
+                    f,
+                    "UASTC textures need vulkano-test built with the basisu feature"
+                )
+            }
+            Ktx2Error::Etc1s => write!(
+                f,
+                "ETC1S textures can't be transcoded, only UASTC ones, such as those of \
+                 `toktx --encode uastc`",
+            ),
+            Ktx2Error::Supercompression(scheme) => {
+                write!(f, "unsupported supercompression scheme {scheme}")
+            }
+            Ktx2Error::UnsupportedShape => {
+                write!(f, "only 2D textures of a single layer are supported")
+            }
+            Ktx2Error::UnsupportedFormat(format) => write!(f, "unsupported Vulkan format {format}"),
+        }
+    }
+}
+
+impl std::error::Error for Ktx2Error {}
+
+impl From<io::Error> for Ktx2Error {
+    fn from(err: io::Error) -> Self {
+        Ktx2Error::Io(err)
+    }
+}
+
+/// Whether `path` names a KTX2 file, going by its extension.
+pub fn is_ktx2(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2"))
+}
+
+/// Reads the texture in the KTX2 file at `path`, transcoding UASTC data to `transcode_target`.
+pub fn open(
+    path: &Path,
+    transcode_target: Option<TranscodeTarget>,
+) -> Result<TextureData, Ktx2Error> {
+    decode(&fs::read(path)?, transcode_target)
+}
+
+/// Reads the texture in `bytes`, the contents of a KTX2 file, transcoding UASTC data to
+/// `transcode_target`. Fails with `Ktx2Error::BasisUniversal` for UASTC data without one.
+pub fn decode(
+    bytes: &[u8],
+    transcode_target: Option<TranscodeTarget>,
+) -> Result<TextureData, Ktx2Error> {
+    if !bytes.starts_with(&IDENTIFIER) {
+        return Err(Ktx2Error::NotKtx2);
+    }
+    let u32_at = |offset: usize| -> Result<u32, Ktx2Error> {
+        let bytes = bytes.get(offset..offset + 4).ok_or(Ktx2Error::Malformed)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let u64_at = |offset: usize| -> Result<usize, Ktx2Error> {
+        let bytes = bytes.get(offset..offset + 8).ok_or(Ktx2Error::Malformed)?;
+        usize::try_from(u64::from_le_bytes(bytes.try_into().unwrap()))
+            .map_err(|_| Ktx2Error::Malformed)
+    };
+
+    let vk_format = u32_at(12)? as i32;
+    let [width, height, depth] = [u32_at(20)?, u32_at(24)?, u32_at(28)?];
+    let [layer_count, face_count, level_count] = [u32_at(32)?, u32_at(36)?, u32_at(40)?];
+    let supercompression = u32_at(44)?;
+
+    if width == 0 || height == 0 || depth != 0 || layer_count > 1 || face_count != 1 {
+        return Err(Ktx2Error::UnsupportedShape);
+    }
+    // The UASTC data to transcode, whether it has alpha, and what to transcode it to.
+    let mut uastc = None;
+    let format = if vk_format == 0 {
+        let dfd_offset = u32_at(HEADER_SIZE)? as usize;
+        // The first block is after the total size of the data format descriptor. Its color
+        // model, primaries, transfer function and flags follow its vendor, type, version and
+        // size, and its first sample follows those and the texel block's dimensions and sizes.
+        let dfd_byte = |offset: usize| {
+            bytes
+                .get(dfd_offset + 4 + offset)
+                .copied()
+                .ok_or(Ktx2Error::Malformed)
+        };
+        if supercompression == SUPERCOMPRESSION_BASIS_LZ {
+            return Err(Ktx2Error::Etc1s);
+        }
+        if dfd_byte(8)? != COLOR_MODEL_UASTC {
+            return Err(Ktx2Error::UnsupportedFormat(vk_format));
+        }
+        if supercompression != 0 {
+            return Err(Ktx2Error::Supercompression(supercompression));
+        }
+        let target = transcode_target.ok_or(Ktx2Error::BasisUniversal)?;
+        // The channel is in the low bits of the sample's channel type.
+        let has_alpha = UASTC_CHANNELS_WITH_ALPHA.contains(&(dfd_byte(27)? & 0xf));
+        uastc = Some((has_alpha, target));
+        target.format(dfd_byte(10)? == TRANSFER_SRGB)
+    } else {
+        if supercompression != 0 {
+            return Err(Ktx2Error::Supercompression(supercompression));
+        }
+        let format = Format::try_from(ash::vk::Format::from_raw(vk_format))
+            .map_err(|()| Ktx2Error::UnsupportedFormat(vk_format))?;
+        if format.planes().len() > 1 || format.block_extent()[2] != 1 {
+            return Err(Ktx2Error::UnsupportedFormat(vk_format));
+        }
+        format
+    };
+
+    // No levels at all asks for them to be generated, which leaves only the largest.
+    let level_count = level_count.max(1);
+    if level_count > u32::BITS - width.max(height).leading_zeros() {
+        return Err(Ktx2Error::Malformed);
+    }
+    // The blocks of UASTC data are the size of those of the formats it is transcoded to.
+    let [block_width, block_height, _] = format.block_extent();
+    let levels = (0..level_count)
+        .map(|level| {
+            let entry = HEADER_SIZE + INDEX_SIZE + level as usize * LEVEL_SIZE;
+            let (offset, length) = (u64_at(entry)?, u64_at(entry + 8)?);
+            let [level_width, level_height] = [width, height].map(|side| (side >> level).max(1));
+            let expected = u64::from(level_width.div_ceil(block_width))
+                * u64::from(level_height.div_ceil(block_height))
+                * format.block_size();
+            if length as u64 != expected {
+                return Err(Ktx2Error::Malformed);
+            }
+            let data = offset
+                .checked_add(length)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or(Ktx2Error::Malformed)?;
+            match uastc {
+                Some((has_alpha, target)) => {
+                    transcode_uastc(data, [level_width, level_height], has_alpha, target)
+                }
+                None => Ok(data.to_vec()),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(TextureData {
+        format,
+        extent: [width, height],
+        cube: false,
+        levels,
+    })
+}
+
+/// Transcodes `data`, a level of UASTC data of the given size, to `target`.
+#[cfg(feature = "basisu")]
+fn transcode_uastc(
+    data: &[u8],
+    extent: [u32; 2],
+    has_alpha: bool,
+    target: TranscodeTarget,
+) -> Result<Vec<u8>, Ktx2Error> {
+    use basis_universal::{
+        DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat,
+    };
+
+    let block_format = match target {
+        TranscodeTarget::Bc7 => TranscoderBlockFormat::BC7,
+        TranscodeTarget::Astc4x4 => TranscoderBlockFormat::ASTC_4x4,
+        TranscodeTarget::Etc2 => TranscoderBlockFormat::ETC2_RGBA,
+    };
+    LowLevelUastcTranscoder::new()
+        .transcode_slice(
+            data,
+            SliceParametersUastc {
+                num_blocks_x: extent[0].div_ceil(4),
+                num_blocks_y: extent[1].div_ceil(4),
+                has_alpha,
+                original_width: extent[0],
+                original_height: extent[1],
+            },
+            DecodeFlags::HIGH_QUALITY,
+            block_format,
+        )
+        // The transcoder only fails on blocks that aren't UASTC.
+        .map_err(|_| Ktx2Error::Malformed)
+}
+
+/// Without the transcoder, there is never a target to transcode to.
+#[cfg(not(feature = "basisu"))]
+fn transcode_uastc(
+    _data: &[u8],
+    _extent: [u32; 2],
+    _has_alpha: bool,
+    _target: TranscodeTarget,
+) -> Result<Vec<u8>, Ktx2Error> {
+    Err(Ktx2Error::BasisUniversal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const R8G8B8A8_SRGB: u32 = 43;
+    const BC1_RGB_UNORM_BLOCK: u32 = 131;
+
+    /// A KTX2 file of a 2D texture of `vk_format`, with `levels` from the largest down and a data
+    /// format descriptor of `color_model`, `transfer` and the channel of its first sample.
+    fn file(
+        vk_format: u32,
+        extent: [u32; 2],
+        supercompression: u32,
+        [color_model, transfer, channel]: [u8; 3],
+        levels: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let dfd_offset = HEADER_SIZE + INDEX_SIZE + levels.len() * LEVEL_SIZE;
+        let mut dfd = vec![0; 44];
+        dfd[..4].copy_from_slice(&44u32.to_le_bytes());
+        dfd[4 + 8] = color_model;
+        dfd[4 + 10] = transfer;
+        dfd[4 + 27] = channel;
+
+        let mut bytes = IDENTIFIER.to_vec();
+        for value in [
+            vk_format,
+            1,
+            extent[0],
+            extent[1],
+            0,
+            0,
+            1,
+            levels.len() as u32,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(supercompression.to_le_bytes());
+        for value in [dfd_offset as u32, dfd.len() as u32, 0, 0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend([0; 16]);
+        let mut offset = dfd_offset + dfd.len();
+        for level in levels {
+            for value in [offset, level.len(), level.len()] {
+                bytes.extend((value as u64).to_le_bytes());
+            }
+            offset += level.len();
+        }
+        bytes.extend(dfd);
+        for level in levels {
+            bytes.extend(level);
+        }
+        bytes
+    }
+
+    fn rgba_file(levels: &[Vec<u8>]) -> Vec<u8> {
+        file(R8G8B8A8_SRGB, [4, 2], 0, [1, TRANSFER_SRGB, 0], levels)
+    }
+
+    #[test]
+    fn reads_the_header_and_every_level() {
+        let levels = [vec![1; 4 * 2 * 4], vec![2; 2 * 4], vec![3; 4]];
+        let texture = decode(&rgba_file(&levels), None).unwrap();
+
+        assert_eq!(texture.format, Format::R8G8B8A8_SRGB);
+        assert_eq!(texture.extent, [4, 2]);
+        assert!(!texture.cube);
+        assert_eq!(texture.levels, levels);
+    }
+
+    #[test]
+    fn levels_of_block_formats_are_whole_blocks() {
+        // 8x8, 4x4, and then a single block for 2x2 and 1x1, of 8 bytes each.
+        let levels = [vec![0; 4 * 8], vec![0; 8], vec![0; 8], vec![0; 8]];
+        let bytes = file(BC1_RGB_UNORM_BLOCK, [8, 8], 0, [1, 1, 0], &levels);
+        let texture = decode(&bytes, None).unwrap();
+        assert_eq!(texture.format, Format::BC1_RGB_UNORM_BLOCK);
+        assert_eq!(texture.levels.len(), 4);
+
+        let short = [vec![0; 4 * 8], vec![0; 8], vec![0; 4]];
+        let bytes = file(BC1_RGB_UNORM_BLOCK, [8, 8], 0, [1, 1, 0], &short);
+        assert!(matches!(decode(&bytes, None), Err(Ktx2Error::Malformed)));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(matches!(
+            decode(b"not a texture", None),
+            Err(Ktx2Error::NotKtx2)
+        ));
+
+        let bytes = rgba_file(&[vec![0; 4 * 2 * 4]]);
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(decode(truncated, None), Err(Ktx2Error::Malformed)));
+        assert!(matches!(
+            decode(&bytes[..HEADER_SIZE - 4], None),
+            Err(Ktx2Error::Malformed)
+        ));
+
+        // A 4x2 texture has 3 levels at most.
+        let levels = [vec![0; 32], vec![0; 8], vec![0; 4], vec![0; 4]];
+        assert!(matches!(
+            decode(&rgba_file(&levels), None),
+            Err(Ktx2Error::Malformed)
+        ));
+    }
+
+    #[test]
+    fn rejects_what_it_cant_read() {
+        let level = [vec![0; 4 * 2 * 4]];
+        let zstd = file(R8G8B8A8_SRGB, [4, 2], 2, [1, TRANSFER_SRGB, 0], &level);
+        assert!(matches!(
+            decode(&zstd, None),
+            Err(Ktx2Error::Supercompression(2))
+        ));
+        let unknown = file(1_000_000, [4, 2], 0, [1, TRANSFER_SRGB, 0], &level);
+        assert!(matches!(
+            decode(&unknown, None),
+            Err(Ktx2Error::UnsupportedFormat(1_000_000))
+        ));
+
+        let etc1s = file(
+            0,
+            [4, 4],
+            SUPERCOMPRESSION_BASIS_LZ,
+            [163, 2, 0],
+            &[vec![0; 16]],
+        );
+        assert!(matches!(decode(&etc1s, None), Err(Ktx2Error::Etc1s)));
+        let uastc = file(0, [4, 4], 0, [COLOR_MODEL_UASTC, 2, 0], &[vec![0; 16]]);
+        assert!(matches!(
+            decode(&uastc, None),
+            Err(Ktx2Error::BasisUniversal)
+        ));
+    }
+
+    #[cfg(feature = "basisu")]
+    #[test]
+    fn transcodes_uastc() {
+        // An 8x8 UASTC texture of a solid color, in blocks of mode 8, with its mip levels.
+        let mut block = [0u8; 16];
+        block[0] = 8;
+        block[1..5].copy_from_slice(&[255, 128, 0, 255]);
+        let levels = [
+            block.repeat(4),
+            block.to_vec(),
+            block.to_vec(),
+            block.to_vec(),
+        ];
+        let bytes = file(0, [8, 8], 0, [COLOR_MODEL_UASTC, TRANSFER_SRGB, 3], &levels);
+
+        for target in [
+            TranscodeTarget::Bc7,
+            TranscodeTarget::Astc4x4,
+            TranscodeTarget::Etc2,
+        ] {
+            let texture = decode(&bytes, Some(target)).unwrap();
+            assert_eq!(texture.format, target.format(true));
+            let sizes: Vec<_> = texture.levels.iter().map(Vec::len).collect();
+            assert_eq!(sizes, [64, 16, 16, 16]);
+        }
+    }
+}
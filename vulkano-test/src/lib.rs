@@ -0,0 +1,87 @@
+pub mod acceleration;
+#[cfg(target_os = "android")]
+mod android;
+pub mod app;
+pub mod assets;
+pub mod batch;
+pub mod bench;
+pub mod bindless;
+pub mod blur;
+pub mod bounds;
+pub mod camera;
+pub mod camera_controller;
+pub mod camera_path;
+pub mod clipboard;
+pub mod compare;
+pub mod components;
+pub mod console;
+pub mod crash_report;
+pub mod cursor;
+pub mod dds;
+pub mod debug_draw;
+pub mod debug_utils;
+pub mod denoise;
+pub mod device_requirements;
+pub mod dialog;
+pub mod environment;
+pub mod error;
+pub mod fog;
+pub mod frame_debug;
+pub mod frame_limiter;
+pub mod gamma;
+pub mod gif;
+pub mod gizmo;
+pub mod gpu;
+pub mod gpu_culling;
+pub mod grid;
+pub mod hdr;
+pub mod headless;
+pub mod history;
+pub mod icon;
+pub mod info;
+pub mod input_recording;
+pub mod keybindings;
+pub mod ktx2;
+pub mod light_clusters;
+pub mod lod;
+pub mod logging;
+pub mod low_latency;
+pub mod material;
+pub mod material_editor;
+pub mod memory_budget;
+pub mod mesh;
+pub mod meshlet;
+pub mod metrics;
+pub mod monitor;
+pub mod occlusion;
+pub mod offscreen;
+pub mod outline;
+pub mod particles;
+pub mod path_tracing;
+pub mod picking;
+pub mod ray_tracing;
+pub mod raycast;
+pub mod scene;
+pub mod scene_file;
+pub mod scene_pipeline;
+pub mod screenshot;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod settings;
+pub mod shader;
+pub mod shadows;
+pub mod skybox;
+pub mod storage;
+pub mod terrain;
+pub mod text;
+pub mod texture;
+pub mod texture_streaming;
+pub mod time;
+pub mod timestep;
+pub mod variants;
+#[cfg(feature = "video")]
+pub mod video;
+pub mod watch;
+pub mod water;
+#[cfg(feature = "xr")]
+pub mod xr;
@@ -0,0 +1,322 @@
+// Clustered culling of point and spot lights, so that a scene can have hundreds of them while
+// each fragment only pays for the few that reach it. The view frustum is split into a grid of
+// clusters: tiles of the screen, each cut into slices of depth that get thicker further away.
+// Every frame, the lights of the scene are uploaded, and `shaders/cluster_lights.comp` lists the
+// lights whose range reaches into each cluster. The fragment shader of the scene then finds the
+// cluster it is in from its position on screen and its depth, and adds up the lights in its list.
+//
+// Each invocation of the pass bins one cluster by testing every light against it, which needs no
+// atomics, and finishes quickly enough for a few thousand lights. A cluster lists at most
+// `MAX_LIGHTS_PER_CLUSTER` of them, and leaves out the rest. Spot lights are binned as the sphere
+// of their range, so clusters off to the side of the cone list them for nothing.
+//
+// Directional lights reach everywhere, so they aren't binned: the main one, which `Scene::light`
+// finds, is part of the frame uniforms as before, and casts the shadows. Any others go at the start
+// of the uploaded lights, and light every fragment without shadows.
+//
+// Only the rasterized main view is lit by these lights. The ray tracer, path tracer, terrain and
+// material preview are lit by the main directional light alone.
+
+use glam::{Mat4, Vec3};
+use std::sync::Arc;
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer,
+    },
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::DeviceOwned,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo, ComputePipeline, Pipeline, PipelineBindPoint,
+        PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    bounds::Aabb,
+    camera::Camera,
+    components::{Light, LightKind, Transform},
+    debug_utils,
+    error::AppError,
+    scene::Scene,
+    shader::{self, ShaderStage},
+};
+
+/// How many clusters the view is split into across, down and in depth.
+const GRID_SIZE: [u32; 3] = [16, 9, 24];
+
+/// The most lights that a cluster lists.
+const MAX_LIGHTS_PER_CLUSTER: u32 = 64;
+
+/// The local size of `shaders/cluster_lights.comp`.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The definition of `MAX_LIGHTS_PER_CLUSTER` that the shaders including
+/// `shaders/include/light_clusters.glsl` are compiled with.
+pub(crate) fn defines() -> (&'static str, String) {
+    (
+        "MAX_LIGHTS_PER_CLUSTER",
+        format!("{MAX_LIGHTS_PER_CLUSTER}u"),
+    )
+}
+
+/// The `ClusterLight` of `shaders/include/light_clusters.glsl`.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct ClusterLight {
+    position_range: [f32; 4],
+    color: [f32; 4],
+    direction_inner: [f32; 4],
+    outer: [f32; 4],
+}
+
+impl ClusterLight {
+    /// The light on the GPU, or `None` for point and spot lights without a `Transform` to place
+    /// them.
+    fn new(transform: Option<&Transform>, light: &Light) -> Option<Self> {
+        let color = (light.color * light.intensity).extend(1.0).to_array();
+        let (position_range, direction_inner, outer) = match light.kind {
+            LightKind::Directional { direction } => (
+                [0.0; 4],
+                (-direction.normalize_or_zero()).extend(0.0).to_array(),
+                [0.0; 4],
+            ),
+            LightKind::Point { range } => (
+                transform?.0.w_axis.truncate().extend(range).to_array(),
+                [0.0, 0.0, -1.0, -1.0],
+                [-2.0, 0.0, 0.0, 0.0],
+            ),
+            LightKind::Spot {
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                let transform = transform?;
+                let direction = transform
+                    .0
+                    .transform_vector3(Vec3::NEG_Z)
+                    .normalize_or_zero();
+                (
+                    transform.0.w_axis.truncate().extend(range).to_array(),
+                    direction.extend(inner_cone_angle.cos()).to_array(),
+                    [outer_cone_angle.cos(), 0.0, 0.0, 0.0],
+                )
+            }
+        };
+        Some(ClusterLight {
+            position_range,
+            color,
+            direction_inner,
+            outer,
+        })
+    }
+}
+
+/// The `ClusterGrid` of `shaders/include/light_clusters.glsl`.
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct ClusterGrid {
+    view: [[f32; 4]; 4],
+    inverse_proj: [[f32; 4]; 4],
+    size: [u32; 4],
+    planes: [f32; 4],
+    light_counts: [u32; 4],
+}
+
+/// What `LightCuller::cull` binned, for `ScenePipeline` to light the scene with, which is only
+/// valid in the command buffer it was recorded into.
+#[derive(Clone)]
+pub struct LightClusters {
+    pub(crate) grid: Subbuffer<ClusterGrid>,
+    pub(crate) lights: Subbuffer<[ClusterLight]>,
+    pub(crate) clusters: Subbuffer<[u32]>,
+}
+
+impl LightClusters {
+    /// Clusters without any lights, for drawing without them. These stay valid.
+    pub(crate) fn empty(memory_allocator: Arc<StandardMemoryAllocator>) -> Self {
+        let buffer = |usage| BufferCreateInfo {
+            usage,
+            ..Default::default()
+        };
+        let allocation = AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        };
+        let zeroed_light = ClusterLight {
+            position_range: [0.0; 4],
+            color: [0.0; 4],
+            direction_inner: [0.0; 4],
+            outer: [0.0; 4],
+        };
+
+        LightClusters {
+            grid: Buffer::from_data(
+                memory_allocator.clone(),
+                buffer(BufferUsage::UNIFORM_BUFFER),
+                allocation.clone(),
+                ClusterGrid {
+                    view: Mat4::IDENTITY.to_cols_array_2d(),
+                    inverse_proj: Mat4::IDENTITY.to_cols_array_2d(),
+                    size: [1, 1, 1, 0],
+                    planes: [1.0; 4],
+                    light_counts: [0; 4],
+                },
+            )
+            .unwrap(),
+            lights: Buffer::from_iter(
+                memory_allocator.clone(),
+                buffer(BufferUsage::STORAGE_BUFFER),
+                allocation.clone(),
+                [zeroed_light],
+            )
+            .unwrap(),
+            clusters: Buffer::from_iter(
+                memory_allocator,
+                buffer(BufferUsage::STORAGE_BUFFER),
+                allocation,
+                [0u32],
+            )
+            .unwrap(),
+        }
+    }
+}
+
+pub struct LightCuller {
+    pipeline: Arc<ComputePipeline>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    buffer_allocator: SubbufferAllocator,
+}
+
+impl LightCuller {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let cs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/cluster_lights.comp"),
+            ShaderStage::Compute,
+            &[defines()],
+        )?
+        .entry_point("main")
+        .unwrap();
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout = shader::reflect_layout::<()>(device.clone(), std::slice::from_ref(&stage))?;
+        let pipeline = ComputePipeline::new(
+            device,
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "light culling");
+
+        let buffer_allocator = SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::UNIFORM_BUFFER | BufferUsage::STORAGE_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        Ok(LightCuller {
+            pipeline,
+            descriptor_set_allocator,
+            buffer_allocator,
+        })
+    }
+
+    /// Records the upload of the lights of `scene` other than its main directional light, and the
+    /// binning of the point and spot lights among them into the clusters of the view of `camera`,
+    /// with the clip planes it has for `bounds`, over a viewport of `extent` pixels. This must be
+    /// outside of a render pass. Returns `None` if the scene has no such lights.
+    pub fn cull<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+        camera: &Camera,
+        bounds: &Aabb,
+        extent: [f32; 2],
+    ) -> Option<LightClusters> {
+        let mut query = scene.world.query::<(Option<&Transform>, &Light)>();
+        let (directional, positional): (Vec<_>, Vec<_>) = query
+            .iter()
+            .partition(|(_, light)| light.direction().is_some());
+        // The first directional light is the one that `Scene::light` finds, which is already lit.
+        let mut lights: Vec<_> = directional
+            .into_iter()
+            .skip(1)
+            .filter_map(|(transform, light)| ClusterLight::new(transform, light))
+            .collect();
+        let directional_count = lights.len() as u32;
+        lights.extend(
+            positional
+                .into_iter()
+                .filter_map(|(transform, light)| ClusterLight::new(transform, light)),
+        );
+        if lights.is_empty() {
+            return None;
+        }
+
+        let (near, far) = camera.clip_planes(bounds);
+        let proj = camera.proj(extent[0] / extent[1], bounds);
+        let grid = self.buffer_allocator.allocate_sized().unwrap();
+        *grid.write().unwrap() = ClusterGrid {
+            view: camera.view().to_cols_array_2d(),
+            inverse_proj: proj.inverse().to_cols_array_2d(),
+            size: [GRID_SIZE[0], GRID_SIZE[1], GRID_SIZE[2], 0],
+            planes: [near, far, extent[0], extent[1]],
+            light_counts: [directional_count, lights.len() as u32, 0, 0],
+        };
+        let light_count = lights.len() as u64;
+        let light_buffer = self.buffer_allocator.allocate_slice(light_count).unwrap();
+        light_buffer.write().unwrap().copy_from_slice(&lights);
+        let cluster_count = GRID_SIZE.iter().product::<u32>();
+        let clusters = self
+            .buffer_allocator
+            .allocate_slice(u64::from(cluster_count * (MAX_LIGHTS_PER_CLUSTER + 1)))
+            .unwrap();
+
+        let layout = self.pipeline.layout();
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::buffer(0, grid.clone()),
+                WriteDescriptorSet::buffer(1, light_buffer.clone()),
+                WriteDescriptorSet::buffer(2, clusters.clone()),
+            ],
+            [],
+        )
+        .unwrap();
+        builder
+            .bind_pipeline_compute(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                layout.clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap();
+        // SAFETY: each invocation reads the lights below the total count in the grid, and writes
+        // the count and light indices of its own cluster, which has room for
+        // `MAX_LIGHTS_PER_CLUSTER`.
+        unsafe { builder.dispatch([cluster_count.div_ceil(WORKGROUP_SIZE), 1, 1]) }.unwrap();
+
+        Some(LightClusters {
+            grid,
+            lights: light_buffer,
+            clusters,
+        })
+    }
+}
@@ -0,0 +1,141 @@
+// Level of detail. Meshes with enough triangles get simplified versions generated when they are
+// loaded, by merging all vertices that fall into the same cell of a coarse grid. Each frame the
+// renderer picks the level to draw from how large the object appears on screen.
+
+use glam::{BVec3, IVec3, Mat4, Vec2, Vec3, Vec4};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    bounds::Aabb,
+    mesh::{Mesh, MeshData, MeshVertex},
+};
+
+/// Meshes with fewer triangles than this are always drawn at full detail.
+const MIN_TRIANGLES: usize = 256;
+
+/// For each generated level, the number of grid cells along the longest side of the mesh, and the
+/// screen size below which the level is used.
+const LEVELS: [(f32, f32); 3] = [(32.0, 0.5), (16.0, 0.25), (8.0, 0.125)];
+
+/// The colors objects are tinted with in LOD debug mode, indexed by level.
+pub const DEBUG_COLORS: [Vec4; 4] = [
+    Vec4::new(0.1, 0.8, 0.1, 1.0),
+    Vec4::new(0.9, 0.9, 0.1, 1.0),
+    Vec4::new(0.9, 0.5, 0.1, 1.0),
+    Vec4::new(0.9, 0.1, 0.1, 1.0),
+];
+
+/// A simplified version of a mesh.
+pub struct Lod {
+    pub mesh: Arc<Mesh>,
+    /// The level is used once the mesh covers less than this fraction of the screen.
+    pub max_screen_size: f32,
+}
+
+/// Generates the simplified levels for a mesh along with their maximum screen sizes, coarsest
+/// last. Levels that barely remove any triangles are skipped.
+pub fn generate(vertices: &[MeshVertex], indices: &[u32]) -> Vec<(MeshData, f32)> {
+    if indices.len() / 3 < MIN_TRIANGLES {
+        return Vec::new();
+    }
+
+    let aabb = Aabb::from_points(vertices.iter().map(|v| Vec3::from(v.position)));
+    let longest_side = (aabb.max - aabb.min).max_element();
+    let mut triangle_count = indices.len() / 3;
+    let mut lods = Vec::new();
+
+    for (cells, max_screen_size) in LEVELS {
+        let (lod_vertices, lod_indices) = decimate(vertices, indices, longest_side / cells);
+        if lod_indices.is_empty() || lod_indices.len() / 3 > triangle_count * 7 / 10 {
+            continue;
+        }
+
+        triangle_count = lod_indices.len() / 3;
+        lods.push((
+            MeshData {
+                vertices: lod_vertices,
+                indices: lod_indices,
+                lods: Vec::new(),
+            },
+            max_screen_size,
+        ));
+    }
+
+    lods
+}
+
+/// Simplifies a triangle mesh by merging the vertices in each cell of a grid with cells of
+/// `cell_size`, and dropping the triangles that collapse as a result. Merged vertices take the
+/// average position, normal and texture coordinates of the originals, which smears textures
+/// across seams.
+pub fn decimate(
+    vertices: &[MeshVertex],
+    indices: &[u32],
+    cell_size: f32,
+) -> (Vec<MeshVertex>, Vec<u32>) {
+    let mut cells = HashMap::<IVec3, u32>::new();
+    let mut sums = Vec::<(Vec3, Vec3, Vec2, f32)>::new();
+
+    let remap: Vec<u32> = vertices
+        .iter()
+        .map(|vertex| {
+            let position = Vec3::from(vertex.position);
+            let cell = (position / cell_size).floor().as_ivec3();
+            let index = *cells.entry(cell).or_insert_with(|| {
+                sums.push((Vec3::ZERO, Vec3::ZERO, Vec2::ZERO, 0.0));
+                sums.len() as u32 - 1
+            });
+
+            let (position_sum, normal_sum, uv_sum, count) = &mut sums[index as usize];
+            *position_sum += position;
+            *normal_sum += Vec3::from(vertex.normal);
+            *uv_sum += Vec2::from(vertex.uv);
+            *count += 1.0;
+
+            index
+        })
+        .collect();
+
+    let vertices = sums
+        .into_iter()
+        .map(|(position_sum, normal_sum, uv_sum, count)| MeshVertex {
+            position: (position_sum / count).to_array(),
+            normal: normal_sum.normalize_or(Vec3::Y).to_array(),
+            uv: (uv_sum / count).to_array(),
+        })
+        .collect();
+    let indices = indices
+        .chunks_exact(3)
+        .map(|triangle| [0, 1, 2].map(|i| remap[triangle[i] as usize]))
+        .filter(|[a, b, c]| a != b && b != c && a != c)
+        .flatten()
+        .collect();
+
+    (vertices, indices)
+}
+
+/// The fraction of the screen covered by `aabb`, along whichever axis it is larger. Boxes that
+/// reach behind the camera count as covering everything.
+pub fn screen_size(aabb: &Aabb, view_proj: Mat4) -> f32 {
+    let mut min = Vec3::INFINITY;
+    let mut max = Vec3::NEG_INFINITY;
+
+    for i in 0..8 {
+        let corner = Vec3::select(
+            BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0),
+            aabb.max,
+            aabb.min,
+        );
+        let clip = view_proj * corner.extend(1.0);
+        if clip.w <= 0.0 {
+            return f32::INFINITY;
+        }
+
+        let ndc = clip.truncate() / clip.w;
+        min = min.min(ndc);
+        max = max.max(ndc);
+    }
+
+    // Normalized device coordinates span 2 units across the screen.
+    ((max.x - min.x).max(max.y - min.y)) * 0.5
+}
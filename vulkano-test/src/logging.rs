@@ -0,0 +1,17 @@
+// Log output. Everything is logged through `tracing`, as human-readable lines or as one JSON
+// object per line. Which levels and modules show up is controlled by `RUST_LOG`, for example
+// `RUST_LOG=vulkano_test=debug` to also see the per-frame spans, and defaults to `info`.
+
+use tracing_subscriber::EnvFilter;
+
+/// Installs the global subscriber. Only the first call has an effect.
+pub fn init(json: bool) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let _ = if json {
+        builder.json().try_init()
+    } else {
+        builder.try_init()
+    };
+}
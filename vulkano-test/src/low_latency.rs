@@ -0,0 +1,115 @@
+// Low-latency presentation through `VK_KHR_present_wait`. Every frame is presented with an ID, and
+// before the next frame starts, the CPU waits until the previous one is on screen. Frames then
+// can't queue up behind the display: the input a frame is drawn from is read as late as it can be,
+// at the cost of the GPU sitting idle between frames.
+//
+// The latency measured is from the start of a frame, after the wait, to the moment it was
+// presented, which is the part of the input-to-photon latency that the app has a say in. It is
+// averaged over `REPORT_INTERVAL` for the window title.
+
+use std::{
+    num::NonZeroU64,
+    time::{Duration, Instant},
+};
+use vulkano::{
+    device::{Device, DeviceExtensions, DeviceFeatures},
+    swapchain::Swapchain,
+};
+
+use crate::device_requirements::{Capabilities, DeviceRequirements};
+
+/// How long the latency is averaged over before it is shown.
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long to wait for a frame to be presented before giving up on it.
+const PRESENT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Asks for the extensions that let the app wait for presentation.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_extension_features(
+        DeviceFeatures {
+            present_id: true,
+            present_wait: true,
+            ..DeviceFeatures::empty()
+        },
+        DeviceExtensions {
+            khr_present_id: true,
+            khr_present_wait: true,
+            ..DeviceExtensions::empty()
+        },
+    );
+}
+
+pub struct LowLatency {
+    /// The ID of the last frame presented, which increases for as long as the app runs.
+    last_present_id: u64,
+    /// The ID of the frame waiting to be presented, and when that frame started.
+    in_flight: Option<(NonZeroU64, Instant)>,
+    /// When the frame being drawn started.
+    frame_start: Instant,
+    total_latency: Duration,
+    samples: u32,
+    report_start: Instant,
+    average_latency: Option<Duration>,
+}
+
+impl LowLatency {
+    /// Low-latency presentation on `device`, or `None` if it can't wait for presentation.
+    pub fn new(device: &Device) -> Option<Self> {
+        let now = Instant::now();
+        Capabilities::of(device).present_wait.then_some(LowLatency {
+            last_present_id: 0,
+            in_flight: None,
+            frame_start: now,
+            total_latency: Duration::ZERO,
+            samples: 0,
+            report_start: now,
+            average_latency: None,
+        })
+    }
+
+    /// Waits until the frame presented last is on screen, then starts the next one. Returns
+    /// whether the average latency changed.
+    pub fn wait(&mut self, swapchain: &Swapchain) -> bool {
+        if let Some((present_id, frame_start)) = self.in_flight.take() {
+            // A frame that isn't presented in time, or at all, is left out of the average.
+            if swapchain
+                .wait_for_present(present_id, Some(PRESENT_TIMEOUT))
+                .is_ok()
+            {
+                self.total_latency += frame_start.elapsed();
+                self.samples += 1;
+            }
+        }
+        self.frame_start = Instant::now();
+
+        if self.report_start.elapsed() < REPORT_INTERVAL || self.samples == 0 {
+            return false;
+        }
+        self.average_latency = Some(self.total_latency / self.samples);
+        self.total_latency = Duration::ZERO;
+        self.samples = 0;
+        self.report_start = self.frame_start;
+        true
+    }
+
+    /// The ID to present the frame being drawn with.
+    pub fn present_id(&mut self) -> NonZeroU64 {
+        self.last_present_id += 1;
+        let present_id = NonZeroU64::new(self.last_present_id).unwrap();
+        self.in_flight = Some((present_id, self.frame_start));
+        present_id
+    }
+
+    /// Stops waiting for the frame presented last, as its swapchain is being replaced or the frame
+    /// failed to be presented.
+    pub fn forget_present(&mut self) {
+        self.in_flight = None;
+    }
+
+    /// The average time from the start of a frame until it was presented, over the last
+    /// `REPORT_INTERVAL` in which frames were presented.
+    pub fn average_latency(&self) -> Option<Duration> {
+        self.average_latency
+    }
+}
@@ -7,167 +7,608 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
-// This example is a copy of the "Clear screen" example from the vulkano-examples repository,
-// with minor modifications to make it work in this environment.
-
-use std::sync::Arc;
-use vulkano::{
-    command_buffer::{
-        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, CommandBufferUsage,
-    },
-    device::{
-        physical::{PhysicalDeviceType},
-        Device, DeviceCreateInfo, DeviceExtensions, QueueCreateInfo, QueueFlags,
-    },
-    image::{Image, ImageUsage},
-    instance::{Instance, InstanceCreateInfo},
-    library::VulkanLibrary,
-    format::Format,
-    image::ImageCreateInfo,
-    memory::allocator::{StandardMemoryAllocator, AllocationCreateInfo, MemoryTypeFilter},
-    sync::GpuFuture,
+// This started as a copy of the "Clear screen" example from the vulkano-examples repository and
+// has grown into a small windowed test bed. See `app.rs` for the renderer itself.
+//
+// Usage:
+//     vulkano-test [window options] [scene.gltf]
+//     vulkano-test [window options] --scene scene.ron
+//     vulkano-test render-batch jobs.toml
+//     vulkano-test compare image.png golden.png [--heatmap diff.png] [--tolerance DE]
+//                          [--threshold PERCENT]
+//     vulkano-test --bench N [--bench-output report.json] [--resolution WxH]
+//                  [--camera-path path.ron] [scene]
+//     vulkano-test --xr [scene]
+//     vulkano-test info [--json]
+//     vulkano-test --list-gpus
+//     vulkano-test --list-monitors
+//
+// Window options:
+//     --max-fps N          caps the frame rate, in place of the `max_fps` setting
+//     --swapchain-images N asks for N swapchain images, 3 for triple buffering, in place of the
+//                          `swapchain_images` setting
+//     --monitor N          starts on the monitor numbered N by `--list-monitors`
+//     --resolution WxH     sets the size of the window, in physical pixels
+//     --fullscreen         switches the monitor to the video mode closest to `--resolution` and
+//                          `--refresh-rate`, or its largest and fastest one
+//     --refresh-rate HZ    sets the refresh rate of the fullscreen video mode
+//     --metrics-out FILE   writes the CPU and GPU time of every frame to a CSV file
+//     --low-latency        waits for each frame to be presented before starting the next, and
+//                          shows the latency in the window title, where `VK_KHR_present_wait`
+//                          is supported
+//     --point-lights N     adds N colored point lights circling above the scene
+//     --stream-textures    keeps only the mip levels of textures that the view needs on the
+//                          GPU, for scenes with more texture data than fits in VRAM
+//     --environment FILE   shows the equirectangular `.hdr` or `.exr` image FILE behind the
+//                          scene, converted into a cubemap along with its irradiance
+//     --record-input FILE  records the input of the window to FILE
+//     --replay FILE        replays the input recorded to FILE instead of taking the window's
+//
+// `--bench N` renders N frames of the scene offscreen, as fast as possible, and writes a JSON
+// report of the frame times to stdout, or to the `--bench-output` file. `--resolution` sets the
+// size of the frames, which defaults to 1280x720; the scene can be a glTF file or `--scene` file.
+// `--camera-path` moves the camera along a path saved with the console's `path save` over the
+// frames, rather than keeping it still.
+//
+// `--xr` shows the scene, a glTF file or `--scene` file, on a headset through the OpenXR runtime,
+// rendering on the GPU the headset is connected to, until the runtime ends the session. It needs
+// the `xr` feature. See `xr.rs`.
+//
+// `compare` compares an image with a golden one, and exits with an error if more than
+// `--threshold` percent of the pixels, 0 by default, look different: further apart than
+// `--tolerance` in CIELAB, 2.3 by default. `--heatmap` writes an image of where they differ. See
+// `compare.rs`.
+//
+// `info` prints what Vulkan and every GPU support, from layers and extensions to limits, memory,
+// queue families and the formats and present modes of a window's surface, as text or as JSON.
+//
+// `--log-json` switches the log output to JSON lines, and `RUST_LOG` filters it.
+//
+// `--prefer-software` renders with a software implementation of Vulkan, such as lavapipe, where
+// one is installed.
+//
+// `--mesh-shading` draws the scene with task and mesh shaders, from meshlets built as meshes load,
+// where the GPU supports `VK_EXT_mesh_shader`. The window title and the bench report then show how
+// many meshlets were drawn, next to the vertex and triangle counts that the classic vertex path
+// shows, for comparing the two. It needs the `wgsl` feature, as the shaders are written in WGSL.
+//
+// `--ray-tracing` ray traces the main view of the window, where the GPU supports
+// `VK_KHR_ray_tracing_pipeline`. Its shaders are compiled with `glslc` from the Vulkan SDK, which
+// has to be on the `PATH`; without it, the scene is rasterized as usual.
+//
+// `--pathtrace` path traces the main view progressively instead, on the same GPUs and also with
+// `glslc`. The samples add up for as long as nothing in view changes, and the window title shows
+// how many there are. The noise of the first few is filtered out, which `N` turns off and on.
+//
+// `--ray-query-shadows` shades the scene with shadows traced by ray queries where the GPU supports
+// `VK_KHR_ray_query`, which also needs `glslc`. `H` turns them off and on again.
+//
+// A panic writes a crash report to `crash-*.txt`, with a backtrace and what the GPU, the
+// extensions, the swapchain and the validation layers had to say, for bug reports. See
+// `crash_report.rs`.
+//
+// `VKTEST_GPU` picks the GPU, by index (as printed by `--list-gpus`) or by a part of its name.
+// Without it, the GPU used last time is picked again.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+use tracing::error;
+use vulkano_test::{
+    app::{App, AppEvent, SceneSource},
+    batch, bench,
+    camera_path::CameraPath,
+    compare, crash_report,
+    device_requirements::DeviceRequirements,
+    dialog,
+    error::AppError,
+    gpu::{self, Gpu},
+    headless::HeadlessRenderer,
+    info::Report,
+    logging, meshlet,
+    monitor::{self, WindowPlacement},
+    ray_tracing, shadows,
 };
-fn main() {
-    // Create the Vulkan instance
-    let library = VulkanLibrary::new().unwrap();
-    let instance = Instance::new(
-        library,
-        InstanceCreateInfo {
-            enabled_extensions: vulkano::instance::InstanceExtensions {
-                khr_surface: true,
-                ..Default::default()
-            },
-            ..Default::default()
+use winit::event_loop::EventLoop;
+
+fn main() -> ExitCode {
+    let mut args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    logging::init(take_flag(&mut args, "--log-json"));
+    crash_report::install();
+
+    let mut requirements = DeviceRequirements::new();
+    if take_flag(&mut args, "--prefer-software") {
+        requirements.prefer_software();
+    }
+    if take_flag(&mut args, "--ray-tracing") {
+        ray_tracing::register_requirements(&mut requirements);
+    }
+    if take_flag(&mut args, "--ray-query-shadows") {
+        shadows::register_requirements(&mut requirements);
+    }
+    if take_flag(&mut args, "--mesh-shading") {
+        if cfg!(feature = "wgsl") {
+            meshlet::register_requirements(&mut requirements);
+        } else {
+            eprintln!("--mesh-shading needs vulkano-test to be built with the wgsl feature");
+            return ExitCode::FAILURE;
+        }
+    }
+    let xr = take_flag(&mut args, "--xr");
+    if xr && !cfg!(feature = "xr") {
+        eprintln!("--xr needs vulkano-test to be built with the xr feature");
+        return ExitCode::FAILURE;
+    }
+    let options = match window_options(&mut args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if options.path_tracing {
+        ray_tracing::register_requirements(&mut requirements);
+    }
+
+    let bench_frames = match parse_option(&mut args, "--bench", |value| {
+        value.parse().ok().filter(|&frames| frames > 0)
+    }) {
+        Ok(frames) => frames,
+        Err(()) => {
+            eprintln!("--bench needs a positive number of frames");
+            return ExitCode::FAILURE;
+        }
+    };
+    let bench_output = match take_option(&mut args, "--bench-output") {
+        Ok(path) => path.map(PathBuf::from),
+        Err(()) => {
+            eprintln!("--bench-output needs the path of the report");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let camera_path = match take_option(&mut args, "--camera-path") {
+        Ok(path) => path.map(PathBuf::from),
+        Err(()) => {
+            eprintln!("--camera-path needs the path of a camera path file");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut args = args.into_iter();
+    if let Some(frames) = bench_frames {
+        let Some(scene_source) = scene_argument(&mut args) else {
+            eprintln!("usage: vulkano-test --bench <frames> --scene <scene.ron>");
+            return ExitCode::FAILURE;
+        };
+        let resolution = options.placement.size.unwrap_or([1280, 720]);
+        return bench(
+            &scene_source,
+            requirements,
+            resolution,
+            frames,
+            camera_path.as_deref(),
+            bench_output.as_deref(),
+        );
+    }
+
+    #[cfg(feature = "xr")]
+    if xr {
+        let Some(scene_source) = scene_argument(&mut args) else {
+            eprintln!("usage: vulkano-test --xr --scene <scene.ron>");
+            return ExitCode::FAILURE;
+        };
+        return run_xr(&scene_source, requirements);
+    }
+
+    match args.next() {
+        Some(command) if command == "render-batch" => {
+            let Some(jobs_path) = args.next() else {
+                eprintln!("usage: vulkano-test render-batch <jobs.toml>");
+                return ExitCode::FAILURE;
+            };
+            render_batch(Path::new(&jobs_path), requirements)
+        }
+        Some(command) if command == "compare" => compare_images(args.collect()),
+        Some(command) if command == "info" => match args.next() {
+            None => print_info(false),
+            Some(flag) if flag == "--json" => print_info(true),
+            Some(_) => {
+                eprintln!("usage: vulkano-test info [--json]");
+                ExitCode::FAILURE
+            }
         },
-    )
-    .unwrap();
+        Some(flag) if flag == "--list-gpus" => list_gpus(),
+        Some(flag) if flag == "--list-monitors" => list_monitors(),
+        Some(flag) if flag == "--scene" => {
+            let Some(scene_path) = args.next() else {
+                eprintln!("usage: vulkano-test --scene <scene.ron>");
+                return ExitCode::FAILURE;
+            };
+            run_windowed(SceneSource::Ron(scene_path.into()), requirements, options)
+        }
+        Some(scene_path) => run_windowed(
+            SceneSource::Gltf(PathBuf::from(scene_path)),
+            requirements,
+            options,
+        ),
+        None => run_windowed(SceneSource::Demo, requirements, options),
+    }
+}
+
+/// The scene named by what is left of the arguments: a glTF file, `--scene` and a scene file, or
+/// nothing for the demo scene. `None` if `--scene` isn't followed by a path.
+fn scene_argument(args: &mut impl Iterator<Item = OsString>) -> Option<SceneSource> {
+    match args.next() {
+        Some(flag) if flag == "--scene" => args.next().map(|path| SceneSource::Ron(path.into())),
+        Some(scene_path) => Some(SceneSource::Gltf(scene_path.into())),
+        None => Some(SceneSource::Demo),
+    }
+}
+
+/// How the windowed app is set up, from the command line.
+struct WindowOptions {
+    max_fps: Option<u32>,
+    swapchain_images: Option<u32>,
+    metrics_path: Option<PathBuf>,
+    record_input_path: Option<PathBuf>,
+    replay_path: Option<PathBuf>,
+    placement: WindowPlacement,
+    path_tracing: bool,
+    low_latency: bool,
+    point_lights: u32,
+    stream_textures: bool,
+    environment_path: Option<PathBuf>,
+}
+
+/// Takes the window options out of `args`, or describes what is wrong with them.
+fn window_options(args: &mut Vec<OsString>) -> Result<WindowOptions, String> {
+    let max_fps = parse_option(args, "--max-fps", |value| {
+        value.parse().ok().filter(|&max_fps| max_fps > 0)
+    })
+    .map_err(|()| "--max-fps needs a positive whole number of frames per second")?;
+    let swapchain_images = parse_option(args, "--swapchain-images", |value| {
+        value.parse().ok().filter(|&count| count > 0)
+    })
+    .map_err(|()| "--swapchain-images needs a positive whole number of images")?;
+    let monitor = parse_option(args, "--monitor", |value| value.parse().ok())
+        .map_err(|()| "--monitor needs the number of a monitor, as listed by --list-monitors")?;
+    let size = parse_option(args, "--resolution", parse_size)
+        .map_err(|()| "--resolution needs a size such as 1920x1080")?;
+    let refresh_rate = parse_option(args, "--refresh-rate", |value| {
+        value.parse::<f64>().ok().filter(|&rate| rate > 0.0)
+    })
+    .map_err(|()| "--refresh-rate needs a rate in hertz, such as 59.94")?;
+    let fullscreen = take_flag(args, "--fullscreen");
+    let metrics_path = take_option(args, "--metrics-out")
+        .map_err(|()| "--metrics-out needs the path of the CSV file")?
+        .map(PathBuf::from);
+    let record_input_path = take_option(args, "--record-input")
+        .map_err(|()| "--record-input needs the path of the recording")?
+        .map(PathBuf::from);
+    let replay_path = take_option(args, "--replay")
+        .map_err(|()| "--replay needs the path of a recording")?
+        .map(PathBuf::from);
+    let path_tracing = take_flag(args, "--pathtrace");
+    let low_latency = take_flag(args, "--low-latency");
+    let point_lights = parse_option(args, "--point-lights", |value| value.parse().ok())
+        .map_err(|()| "--point-lights needs a whole number of lights")?
+        .unwrap_or(0);
+    let stream_textures = take_flag(args, "--stream-textures");
+    let environment_path = take_option(args, "--environment")
+        .map_err(|()| "--environment needs the path of an .hdr or .exr image")?
+        .map(PathBuf::from);
+
+    Ok(WindowOptions {
+        max_fps,
+        swapchain_images,
+        metrics_path,
+        record_input_path,
+        replay_path,
+        path_tracing,
+        low_latency,
+        point_lights,
+        stream_textures,
+        environment_path,
+        placement: WindowPlacement {
+            monitor,
+            size,
+            fullscreen,
+            refresh_rate_millihertz: refresh_rate.map(|rate| (rate * 1000.0).round() as u32),
+        },
+    })
+}
+
+/// Parses a size written as `WIDTHxHEIGHT`, neither of which may be zero.
+fn parse_size(value: &str) -> Option<[u32; 2]> {
+    let (width, height) = value.split_once('x')?;
+    let size = [width.parse().ok()?, height.parse().ok()?];
+    size.iter().all(|&n| n > 0).then_some(size)
+}
+
+/// Removes `name` and the value following it from `args`, parsing the value. Fails if the value
+/// is missing or `parse` rejects it.
+fn parse_option<T>(
+    args: &mut Vec<OsString>,
+    name: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<Option<T>, ()> {
+    match take_option(args, name)? {
+        None => Ok(None),
+        Some(value) => value.to_str().and_then(parse).map(Some).ok_or(()),
+    }
+}
+
+/// Removes every `name` from `args`, returning whether there was one.
+fn take_flag(args: &mut Vec<OsString>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != len
+}
+
+/// Removes `name` and the value following it from `args`, wherever they are. Fails if `name` is
+/// the last argument.
+fn take_option(args: &mut Vec<OsString>, name: &str) -> Result<Option<OsString>, ()> {
+    let Some(i) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err(());
+    }
+
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(value))
+}
+
+fn run_windowed(
+    scene_source: SceneSource,
+    requirements: DeviceRequirements,
+    options: WindowOptions,
+) -> ExitCode {
+    match try_run_windowed(scene_source, requirements, options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{err}");
+            dialog::show_fatal_error(&err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn try_run_windowed(
+    scene_source: SceneSource,
+    requirements: DeviceRequirements,
+    options: WindowOptions,
+) -> Result<(), AppError> {
+    let event_loop = EventLoop::<AppEvent>::with_user_event().build()?;
+    let mut app = App::new(&event_loop, scene_source, requirements)?;
+    if options.max_fps.is_some() {
+        app.set_max_fps(options.max_fps);
+    }
+    app.set_swapchain_images(options.swapchain_images);
+    app.set_window_placement(options.placement);
+    app.set_path_tracing(options.path_tracing);
+    if options.low_latency {
+        app.enable_low_latency();
+    }
+    app.add_orbiting_lights(options.point_lights);
+    if options.stream_textures {
+        app.enable_texture_streaming();
+    }
+    if let Some(path) = &options.environment_path {
+        app.set_environment(path)?;
+    }
+    if let Some(path) = &options.metrics_path {
+        app.set_metrics_output(path)
+            .map_err(|err| AppError::Output {
+                path: path.clone(),
+                source: err,
+            })?;
+    }
+    if let Some(path) = &options.record_input_path {
+        app.set_input_recording(path)
+            .map_err(|err| AppError::Output {
+                path: path.clone(),
+                source: err,
+            })?;
+    }
+    if let Some(path) = &options.replay_path {
+        app.set_input_replay(path).map_err(|err| AppError::Input {
+            path: path.clone(),
+            source: err,
+        })?;
+    }
 
-    let device_extensions = DeviceExtensions {
-        khr_swapchain: true,
-        ..Default::default()
+    event_loop.run_app(&mut app)?;
+    match app.take_error() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn compare_images(mut args: Vec<OsString>) -> ExitCode {
+    const USAGE: &str =
+        "usage: vulkano-test compare <image.png> <golden.png> [--heatmap <diff.png>] \
+                         [--tolerance <delta E>] [--threshold <percent>]";
+    let parse_number = |value: &str| value.parse().ok().filter(|&value: &f32| value >= 0.0);
+    let (Ok(heatmap), Ok(tolerance), Ok(threshold)) = (
+        take_option(&mut args, "--heatmap"),
+        parse_option(&mut args, "--tolerance", parse_number),
+        parse_option(&mut args, "--threshold", parse_number),
+    ) else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let [image, golden] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
     };
 
-    let (physical_device, queue_family_index) = instance
-        .enumerate_physical_devices()
-        .unwrap()
-        .filter(|p| p.supported_extensions().contains(&device_extensions))
-        .filter_map(|p| {
-            p.queue_family_properties()
-                .iter()
-                .enumerate()
-                .find(|(_i, q)| {
-                    q.queue_flags.contains(QueueFlags::GRAPHICS)
-                })
-                .map(|(i, _)| (p.clone(), i as u32))
-        })
-        .min_by_key(|(p, _)| {
-            // We assign a lower score to device types that are likely to be faster/better.
-            match p.properties().device_type {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                PhysicalDeviceType::Other => 4,
-                _ => 5,
+    let comparison =
+        match compare::compare_files(image.as_ref(), golden.as_ref(), tolerance.unwrap_or(2.3)) {
+            Ok(comparison) => comparison,
+            Err(err) => {
+                eprintln!("{err}");
+                return ExitCode::FAILURE;
             }
-        })
-        .expect("no suitable physical device found");
+        };
+    if let Some(path) = heatmap
+        && let Err(err) = comparison.heatmap.save(&path)
+    {
+        eprintln!("failed to write {}: {err}", Path::new(&path).display());
+        return ExitCode::FAILURE;
+    }
 
+    let differing = comparison.differing_fraction() * 100.0;
     println!(
-        "Using device: {} (type: {:?})",
-        physical_device.properties().device_name,
-        physical_device.properties().device_type,
+        "{} of {} pixels differ ({differing:.3}%), by {:.2} on average and {:.2} at most; the \
+         largest channel difference is {:.3}",
+        comparison.differing_pixels,
+        comparison.pixel_count,
+        comparison.mean_difference,
+        comparison.max_difference,
+        comparison.max_channel_difference,
     );
+    if differing > threshold.unwrap_or(0.0) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
 
-    let (device, mut queues) = Device::new(
-        physical_device.clone(),
-        DeviceCreateInfo {
-            enabled_extensions: device_extensions,
-            queue_create_infos: vec![QueueCreateInfo {
-                queue_family_index,
-                ..Default::default()
-            }],
-            ..Default::default()
-        },
-    )
-    .unwrap();
-
-    let queue = queues.next().unwrap();
-    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
-
-    let image = Image::new(
-        memory_allocator.clone(),
-        ImageCreateInfo {
-            format: Format::R8G8B8A8_UNORM,
-            extent: [1024, 768, 1],
-            usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
-            ..Default::default()
-        },
-        AllocationCreateInfo {
-            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
-            ..Default::default()
-        }
-    ).unwrap();
-
-    let command_buffer_allocator =
-        Arc::new(StandardCommandBufferAllocator::new(device.clone(), Default::default()));
-
-    let mut builder = AutoCommandBufferBuilder::primary(
-        command_buffer_allocator.clone(),
-        queue.queue_family_index(),
-        CommandBufferUsage::OneTimeSubmit,
-    )
-    .unwrap();
-
-    let render_pass = vulkano::single_pass_renderpass!(
-        device.clone(),
-        attachments: {
-            color: {
-                format: image.format(),
-                samples: 1,
-                load_op: Clear,
-                store_op: Store,
+fn print_info(json: bool) -> ExitCode {
+    let report = match Report::gather() {
+        Ok(report) => report,
+        Err(err) => {
+            error!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print!("{}", report.to_text());
+    }
+    ExitCode::SUCCESS
+}
+
+fn list_gpus() -> ExitCode {
+    match gpu::describe_devices() {
+        Ok(report) => {
+            print!("{report}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            error!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list_monitors() -> ExitCode {
+    match monitor::print_monitors() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn bench(
+    scene_source: &SceneSource,
+    mut requirements: DeviceRequirements,
+    resolution: [u32; 2],
+    frames: u32,
+    camera_path: Option<&Path>,
+    output: Option<&Path>,
+) -> ExitCode {
+    let camera_path = match camera_path.map(CameraPath::load).transpose() {
+        Ok(camera_path) => camera_path,
+        Err(err) => {
+            error!("Failed to load {}: {err}", camera_path.unwrap().display());
+            return ExitCode::FAILURE;
+        }
+    };
+    HeadlessRenderer::register_requirements(&mut requirements);
+    let renderer = match Gpu::headless(requirements).and_then(HeadlessRenderer::new) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            error!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match bench::run(
+        &renderer,
+        scene_source,
+        resolution,
+        frames,
+        camera_path.as_ref(),
+    ) {
+        Ok(report) => report,
+        Err(err) => {
+            error!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Serializing the report can't fail.
+    let json = serde_json::to_string_pretty(&report).unwrap();
+    match output {
+        Some(path) => {
+            if let Err(err) = std::fs::write(path, json + "\n") {
+                eprintln!("failed to write {}: {err}", path.display());
+                return ExitCode::FAILURE;
             }
-        },
-        pass: {
-            color: [color],
-            depth_stencil: {}
-        }
-    )
-    .unwrap();
-
-    let view = vulkano::image::view::ImageView::new_default(image.clone()).unwrap();
-    let framebuffer = vulkano::render_pass::Framebuffer::new(
-        render_pass.clone(),
-        vulkano::render_pass::FramebufferCreateInfo {
-            attachments: vec![view],
-            ..Default::default()
-        },
-    )
-    .unwrap();
-
-    builder
-        .begin_render_pass(
-            vulkano::command_buffer::RenderPassBeginInfo {
-                clear_values: vec![Some([0.0, 0.0, 1.0, 1.0].into())],
-                ..vulkano::command_buffer::RenderPassBeginInfo::framebuffer(framebuffer.clone())
-            },
-            vulkano::command_buffer::SubpassBeginInfo {
-                contents: vulkano::command_buffer::SubpassContents::Inline,
-                ..Default::default()
-            },
-        )
-        .unwrap()
-        .end_render_pass(vulkano::command_buffer::SubpassEndInfo::default())
-        .unwrap();
-
-    let command_buffer = builder.build().unwrap();
-
-    let future = vulkano::sync::now(device.clone())
-        .then_execute(queue.clone(), command_buffer)
-        .unwrap()
-        .then_signal_fence_and_flush()
-        .unwrap();
-
-    future.wait(None).unwrap();
+        }
+        None => println!("{json}"),
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(feature = "xr")]
+fn run_xr(scene_source: &SceneSource, mut requirements: DeviceRequirements) -> ExitCode {
+    use vulkano_test::xr::{self, XrError, XrRuntime};
+
+    let result = XrRuntime::new().and_then(|runtime| {
+        HeadlessRenderer::register_requirements(&mut requirements);
+        let renderer = Gpu::xr(&runtime, requirements)
+            .and_then(HeadlessRenderer::new)
+            .map_err(XrError::Gpu)?;
+        xr::run(&runtime, &renderer, scene_source)
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn render_batch(jobs_path: &Path, mut requirements: DeviceRequirements) -> ExitCode {
+    let jobs = match batch::load(jobs_path) {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            eprintln!("failed to read {}: {err}", jobs_path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    HeadlessRenderer::register_requirements(&mut requirements);
+    let renderer = match Gpu::headless(requirements).and_then(HeadlessRenderer::new) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            error!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if batch::run(&renderer, &jobs) == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
 }
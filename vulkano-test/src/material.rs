@@ -0,0 +1,80 @@
+use glam::{Vec3, Vec4};
+use std::path::Path;
+
+use crate::{
+    assets::{Assets, Handle},
+    texture::Texture,
+};
+
+/// Surface parameters of a scene object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name: String,
+    /// Linear RGBA multiplied with the lighting.
+    pub base_color: Vec4,
+    /// A texture multiplied with `base_color`.
+    pub base_color_texture: Option<Handle<Texture>>,
+    /// How metallic the surface is, from 0 for a dielectric to 1, where the base color tints the
+    /// reflections instead of being lit.
+    pub metallic: f32,
+    /// From 0 for a mirror to 1, where the highlights are too spread out to see.
+    pub roughness: f32,
+    /// Linear RGB light given off by the surface, added to the lit color.
+    pub emissive: Vec3,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: "default".to_owned(),
+            base_color: Vec4::new(0.8, 0.8, 0.8, 1.0),
+            base_color_texture: None,
+            metallic: 0.0,
+            roughness: 1.0,
+            emissive: Vec3::ZERO,
+        }
+    }
+}
+
+impl Material {
+    pub fn new(name: impl Into<String>, base_color: Vec4) -> Self {
+        Material {
+            name: name.into(),
+            base_color,
+            ..Default::default()
+        }
+    }
+
+    /// Converts a glTF material, loading its base color texture into `assets` if it has one.
+    /// Textures are only supported as image files next to the glTF file, not embedded into it.
+    pub(crate) fn from_gltf(
+        material: &gltf::Material<'_>,
+        assets: &mut Assets,
+        directory: &Path,
+    ) -> Self {
+        let name = match (material.name(), material.index()) {
+            (Some(name), _) => name.to_owned(),
+            (None, Some(index)) => format!("material {index}"),
+            (None, None) => return Self::default(),
+        };
+
+        let pbr = material.pbr_metallic_roughness();
+        let base_color_texture =
+            pbr.base_color_texture()
+                .and_then(|info| match info.texture().source().source() {
+                    gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                        Some(assets.texture(&directory.join(uri)))
+                    }
+                    _ => None,
+                });
+
+        Material {
+            name,
+            base_color: Vec4::from(pbr.base_color_factor()),
+            base_color_texture,
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: Vec3::from(material.emissive_factor()),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// Editing the material of the selected entity while the app runs. `\` picks the parameter to edit
+// and `[` and `]` step it down and up, with the new value logged after every step. Since every
+// draw pushes the parameters of its material as it is at the time, the change shows from the next
+// frame on, without anything to upload or rebuild.
+//
+// The parameters are those that materials have: each channel of the base color, how metallic and
+// how rough the surface is, and each channel of the light it gives off. An edited material
+// variant is edited in the scene's list of variants as well, so that it is still the same variant,
+// and is saved along with the scene.
+
+use hecs::Entity;
+
+use crate::{components::MaterialOverride, material::Material, scene::Scene};
+
+/// How much a step changes a parameter by.
+const STEP: f32 = 0.05;
+
+/// A parameter of a `Material` that can be edited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MaterialParameter {
+    #[default]
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    Metallic,
+    Roughness,
+    EmissiveRed,
+    EmissiveGreen,
+    EmissiveBlue,
+}
+
+impl MaterialParameter {
+    fn next(self) -> Self {
+        match self {
+            MaterialParameter::Red => MaterialParameter::Green,
+            MaterialParameter::Green => MaterialParameter::Blue,
+            MaterialParameter::Blue => MaterialParameter::Alpha,
+            MaterialParameter::Alpha => MaterialParameter::Metallic,
+            MaterialParameter::Metallic => MaterialParameter::Roughness,
+            MaterialParameter::Roughness => MaterialParameter::EmissiveRed,
+            MaterialParameter::EmissiveRed => MaterialParameter::EmissiveGreen,
+            MaterialParameter::EmissiveGreen => MaterialParameter::EmissiveBlue,
+            MaterialParameter::EmissiveBlue => MaterialParameter::Red,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MaterialParameter::Red => "base color red",
+            MaterialParameter::Green => "base color green",
+            MaterialParameter::Blue => "base color blue",
+            MaterialParameter::Alpha => "base color alpha",
+            MaterialParameter::Metallic => "metallic",
+            MaterialParameter::Roughness => "roughness",
+            MaterialParameter::EmissiveRed => "emissive red",
+            MaterialParameter::EmissiveGreen => "emissive green",
+            MaterialParameter::EmissiveBlue => "emissive blue",
+        }
+    }
+
+    fn value_mut(self, material: &mut Material) -> &mut f32 {
+        match self {
+            MaterialParameter::Red => &mut material.base_color.x,
+            MaterialParameter::Green => &mut material.base_color.y,
+            MaterialParameter::Blue => &mut material.base_color.z,
+            MaterialParameter::Alpha => &mut material.base_color.w,
+            MaterialParameter::Metallic => &mut material.metallic,
+            MaterialParameter::Roughness => &mut material.roughness,
+            MaterialParameter::EmissiveRed => &mut material.emissive.x,
+            MaterialParameter::EmissiveGreen => &mut material.emissive.y,
+            MaterialParameter::EmissiveBlue => &mut material.emissive.z,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MaterialEditor {
+    parameter: MaterialParameter,
+}
+
+impl MaterialEditor {
+    /// Moves on to editing the next parameter, and returns it.
+    pub fn next_parameter(&mut self) -> MaterialParameter {
+        self.parameter = self.parameter.next();
+        self.parameter
+    }
+
+    /// Changes the parameter being edited of the material that `entity` is drawn with by `steps`
+    /// steps, clamped between 0 and 1. Returns a description of the new value, or `None` if the
+    /// entity has no material.
+    pub fn step(&self, scene: &mut Scene, entity: Entity, steps: f32) -> Option<String> {
+        let parameter = self.parameter;
+        let edit = |material: &mut Material| {
+            let value = parameter.value_mut(material);
+            *value = (*value + steps * STEP).clamp(0.0, 1.0);
+            let value = *value;
+            format!("{} {}: {value:.2}", material.name, parameter.name())
+        };
+
+        if let Ok(mut material_override) = scene.world.get::<&mut MaterialOverride>(entity) {
+            if let Some(variant) = scene
+                .materials
+                .iter_mut()
+                .find(|variant| **variant == material_override.0)
+            {
+                edit(variant);
+            }
+            return Some(edit(&mut material_override.0));
+        }
+        let mut material = scene.world.get::<&mut Material>(entity).ok()?;
+        Some(edit(&mut material))
+    }
+}
@@ -0,0 +1,143 @@
+// GPU memory budget monitoring through `VK_EXT_memory_budget`. Once a second, the budget of every
+// memory heap is queried along with how much of it the process uses: the budget being how much
+// the driver reckons the process can allocate from the heap without trouble, which takes the
+// other processes on the GPU into account. The usage and budget of the device-local heaps are
+// shown in the window title, and a warning is logged when a heap's usage comes close to its
+// budget, before texture-heavy scenes start failing allocations or slowing down as the driver
+// pages memory out.
+//
+// Without the extension, or on devices older than Vulkan 1.1, nothing is shown.
+
+use std::{
+    fmt::Write as _,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+use vulkano::{
+    device::{Device, DeviceExtensions},
+    memory::MemoryHeapFlags,
+    Version, VulkanObject,
+};
+
+use crate::device_requirements::{Capabilities, DeviceRequirements};
+
+/// How often the budget is queried.
+const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The fraction of a heap's budget that its usage is warned about from.
+const WARNING_THRESHOLD: f64 = 0.9;
+
+/// The fraction of a heap's budget that its usage has to come back under to be warned about
+/// again.
+const WARNING_RESET: f64 = 0.8;
+
+const GIB: f64 = (1u64 << 30) as f64;
+
+/// Asks for the extension that tells the budget of each memory heap.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_extensions(DeviceExtensions {
+        ext_memory_budget: true,
+        ..DeviceExtensions::empty()
+    });
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct HeapBudget {
+    /// How many bytes the process can allocate from the heap without trouble.
+    budget: u64,
+    /// How many bytes the process has allocated from the heap.
+    usage: u64,
+    device_local: bool,
+    /// Whether the usage was warned about, and hasn't since come back under `WARNING_RESET`.
+    warned: bool,
+}
+
+pub struct MemoryBudget {
+    device: Arc<Device>,
+    heaps: Vec<HeapBudget>,
+    last_query: Option<Instant>,
+}
+
+impl MemoryBudget {
+    /// Monitoring of the memory of `device`, or `None` if it can't tell the budget.
+    pub fn new(device: &Arc<Device>) -> Option<Self> {
+        (Capabilities::of(device).memory_budget
+            && device.api_version() >= Version::V1_1
+            && device.instance().api_version() >= Version::V1_1)
+            .then(|| MemoryBudget {
+                device: device.clone(),
+                heaps: Vec::new(),
+                last_query: None,
+            })
+    }
+
+    /// Queries the budget if `QUERY_INTERVAL` has passed since it last was, warning about heaps
+    /// close to their budget. Returns whether it was queried.
+    pub fn update(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last_query
+            .is_some_and(|last_query| now - last_query < QUERY_INTERVAL)
+        {
+            return false;
+        }
+        self.last_query = Some(now);
+
+        let physical_device = self.device.physical_device();
+        let mut budget_properties = ash::vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties =
+            ash::vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_properties);
+        // SAFETY: both the instance and the device are at least Vulkan 1.1, and the budget
+        // properties can be chained as the device has the extension.
+        unsafe {
+            (physical_device
+                .instance()
+                .fns()
+                .v1_1
+                .get_physical_device_memory_properties2)(
+                physical_device.handle(), &mut properties
+            )
+        };
+
+        let memory_heaps = &physical_device.memory_properties().memory_heaps;
+        self.heaps.resize(memory_heaps.len(), HeapBudget::default());
+        for (index, (heap, memory_heap)) in self.heaps.iter_mut().zip(memory_heaps).enumerate() {
+            heap.budget = budget_properties.heap_budget[index];
+            heap.usage = budget_properties.heap_usage[index];
+            heap.device_local = memory_heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL);
+
+            let fraction = heap.usage as f64 / heap.budget.max(1) as f64;
+            if !heap.warned && fraction >= WARNING_THRESHOLD {
+                warn!(
+                    "Memory heap {index} is {:.0}% used: {:.2} of its {:.2} GiB budget",
+                    fraction * 100.0,
+                    heap.usage as f64 / GIB,
+                    heap.budget as f64 / GIB,
+                );
+                heap.warned = true;
+            } else if fraction < WARNING_RESET {
+                heap.warned = false;
+            }
+        }
+        true
+    }
+
+    /// The usage and budget of the device-local heaps, for the window title, or `None` before
+    /// the first query.
+    pub fn describe(&self) -> Option<String> {
+        let mut description = String::new();
+        for heap in self.heaps.iter().filter(|heap| heap.device_local) {
+            if !description.is_empty() {
+                description.push_str(", ");
+            }
+            let _ = write!(
+                description,
+                "{:.2}/{:.2} GiB",
+                heap.usage as f64 / GIB,
+                heap.budget as f64 / GIB,
+            );
+        }
+        (!description.is_empty()).then(|| format!("{description} VRAM"))
+    }
+}
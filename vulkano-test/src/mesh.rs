@@ -0,0 +1,231 @@
+use glam::Vec3;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    device::DeviceOwned,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::graphics::vertex_input::Vertex,
+};
+
+use crate::{
+    bounds::Aabb,
+    debug_utils,
+    device_requirements::Capabilities,
+    lod::{self, Lod},
+    meshlet::{self, MeshletBuffers},
+};
+
+#[derive(BufferContents, Vertex, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct MeshVertex {
+    #[format(R32G32B32_SFLOAT)]
+    pub position: [f32; 3],
+    #[format(R32G32B32_SFLOAT)]
+    pub normal: [f32; 3],
+    #[format(R32G32_SFLOAT)]
+    pub uv: [f32; 2],
+}
+
+/// The geometry of a mesh in host memory, so that it can be prepared away from the thread that
+/// uploads it.
+pub struct MeshData {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+    /// Simplified versions of the mesh with the screen size below which each is used, from the
+    /// most to the least detailed.
+    pub lods: Vec<(MeshData, f32)>,
+}
+
+impl MeshData {
+    /// Geometry along with simplified levels of detail generated from it.
+    pub fn with_generated_lods(vertices: Vec<MeshVertex>, indices: Vec<u32>) -> Self {
+        let lods = lod::generate(&vertices, &indices);
+        MeshData {
+            vertices,
+            indices,
+            lods,
+        }
+    }
+}
+
+/// The addresses of a mesh's buffers in device memory, laid out to be pushed as push constants or
+/// written into shader tables, through which shaders reach the geometry without descriptors. The
+/// scene shaders still pull vertices through vertex buffers, as naga can't yet compile GLSL that
+/// reads through buffer references.
+#[derive(BufferContents, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct MeshAddresses {
+    /// The address of the first `MeshVertex`.
+    pub vertices: u64,
+    /// The address of the first index, each of which is a `u32`.
+    pub indices: u64,
+}
+
+/// Indexed triangle geometry uploaded to the GPU, along with its bounds in model space.
+pub struct Mesh {
+    pub vertex_buffer: Subbuffer<[MeshVertex]>,
+    pub index_buffer: Subbuffer<[u32]>,
+    pub aabb: Aabb,
+    /// Simplified versions of the mesh, from the most to the least detailed.
+    pub lods: Vec<Lod>,
+    /// The mesh split into meshlets, where mesh shading is enabled.
+    pub meshlets: Option<MeshletBuffers>,
+    /// The positions of the vertices and the indices, kept in host memory as well, so that rays
+    /// can be cast against the triangles without reading them back.
+    pub positions: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        vertices: Vec<MeshVertex>,
+        indices: Vec<u32>,
+    ) -> Arc<Mesh> {
+        Self::with_lods(memory_allocator, vertices, indices, Vec::new())
+    }
+
+    /// Uploads `data` along with its levels of detail.
+    pub fn upload(memory_allocator: Arc<StandardMemoryAllocator>, data: MeshData) -> Arc<Mesh> {
+        let lods = data
+            .lods
+            .into_iter()
+            .map(|(lod, max_screen_size)| Lod {
+                mesh: Self::upload(memory_allocator.clone(), lod),
+                max_screen_size,
+            })
+            .collect();
+        Self::with_lods(memory_allocator, data.vertices, data.indices, lods)
+    }
+
+    fn with_lods(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        vertices: Vec<MeshVertex>,
+        indices: Vec<u32>,
+        lods: Vec<Lod>,
+    ) -> Arc<Mesh> {
+        let positions: Vec<Vec3> = vertices.iter().map(|v| Vec3::from(v.position)).collect();
+        let aabb = Aabb::from_points(positions.iter().copied());
+        let capabilities = Capabilities::of(memory_allocator.device());
+        // Where the device can tell buffer addresses, both buffers have one to be read through.
+        let address_usage = if capabilities.buffer_device_address {
+            BufferUsage::SHADER_DEVICE_ADDRESS
+        } else {
+            BufferUsage::empty()
+        };
+        // Mesh shaders read the vertices from a storage buffer.
+        let meshlets = capabilities.mesh_shading.then(|| {
+            let data = meshlet::build(&vertices, &indices);
+            MeshletBuffers::upload(memory_allocator.clone(), data)
+        });
+        let storage_usage = if capabilities.mesh_shading {
+            BufferUsage::STORAGE_BUFFER
+        } else {
+            BufferUsage::empty()
+        };
+        // Acceleration structures are built from both buffers where meshes are ray traced.
+        let ray_tracing_usage = if capabilities.ray_tracing || capabilities.ray_query_shadows {
+            BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY
+        } else {
+            BufferUsage::empty()
+        };
+
+        let vertex_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER
+                    | address_usage
+                    | storage_usage
+                    | ray_tracing_usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            vertices,
+        )
+        .unwrap();
+        let index_buffer = Buffer::from_iter(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::INDEX_BUFFER | address_usage | ray_tracing_usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            indices.iter().copied(),
+        )
+        .unwrap();
+        debug_utils::name(vertex_buffer.buffer(), "mesh vertices");
+        debug_utils::name(index_buffer.buffer(), "mesh indices");
+
+        Arc::new(Mesh {
+            vertex_buffer,
+            index_buffer,
+            aabb,
+            lods,
+            meshlets: meshlets.flatten(),
+            positions,
+            indices,
+        })
+    }
+
+    /// Where the buffers are in device memory, if the device supports buffer device addresses.
+    pub fn addresses(&self) -> Option<MeshAddresses> {
+        let vertices = self.vertex_buffer.device_address().ok()?;
+        let indices = self.index_buffer.device_address().ok()?;
+        Some(MeshAddresses {
+            vertices: vertices.get(),
+            indices: indices.get(),
+        })
+    }
+
+    /// The level of detail to draw when the mesh covers `screen_size` of the screen, along with
+    /// its index. Level 0 is the mesh itself.
+    pub fn lod(&self, screen_size: f32) -> (usize, &Mesh) {
+        self.lods
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, lod)| screen_size < lod.max_screen_size)
+            .map_or((0, self), |(i, lod)| (i + 1, &lod.mesh))
+    }
+
+    /// A unit cube centered on the origin, with flat normals and each face covering the whole
+    /// texture.
+    pub fn cube(memory_allocator: Arc<StandardMemoryAllocator>) -> Arc<Mesh> {
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+
+        for normal in [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ] {
+            // Two axes spanning the face, ordered so that the triangles wind counter-clockwise
+            // when seen from outside.
+            let u = normal.any_orthonormal_vector();
+            let v = normal.cross(u);
+            let base = vertices.len() as u32;
+
+            for (du, dv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                vertices.push(MeshVertex {
+                    position: ((normal + u * du + v * dv) * 0.5).to_array(),
+                    normal: normal.to_array(),
+                    uv: [(du + 1.0) * 0.5, (1.0 - dv) * 0.5],
+                });
+            }
+            indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+
+        Self::new(memory_allocator, vertices, indices)
+    }
+}
@@ -0,0 +1,213 @@
+// Meshlets, for drawing meshes with task and mesh shaders instead of the vertex pipeline. A mesh is
+// split into small clusters of triangles, each with few enough vertices that a mesh shader
+// workgroup can transform all of them and write out their triangles, and with the bounding sphere
+// that the task shader culls them with. See `shaders/scene.wgsl`.
+//
+// Meshlets are built when a mesh is uploaded, on devices where mesh shading is enabled, which only
+// happens when asked for with `--mesh-shading`. The shaders are written in WGSL, as naga's GLSL
+// frontend has no mesh shaders, so this also needs the `wgsl` feature.
+
+use glam::Vec3;
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    device::{DeviceExtensions, DeviceFeatures},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::{debug_utils, device_requirements::DeviceRequirements, mesh::MeshVertex};
+
+/// The most vertices in a meshlet, as `shaders/scene.wgsl` declares its outputs.
+pub const MAX_VERTICES: usize = 64;
+
+/// The most triangles in a meshlet, as `shaders/scene.wgsl` declares its outputs.
+pub const MAX_TRIANGLES: usize = 124;
+
+/// How many meshlets each task shader workgroup culls.
+pub const MESHLETS_PER_TASK: u32 = 32;
+
+/// Asks for mesh and task shaders where supported. They are extension features, which need SPIR-V
+/// 1.4 too.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_extension_features(
+        DeviceFeatures {
+            mesh_shader: true,
+            task_shader: true,
+            ..DeviceFeatures::empty()
+        },
+        DeviceExtensions {
+            ext_mesh_shader: true,
+            khr_spirv_1_4: true,
+            khr_shader_float_controls: true,
+            ..DeviceExtensions::empty()
+        },
+    );
+}
+
+#[derive(BufferContents, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Meshlet {
+    /// The center of the bounding sphere of the meshlet's vertices, in model space.
+    pub center: [f32; 3],
+    pub radius: f32,
+    /// Where the meshlet's vertices start in `MeshletData::vertices`.
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    /// Where the meshlet's triangles start in `MeshletData::triangles`.
+    pub triangle_offset: u32,
+    pub triangle_count: u32,
+}
+
+/// The meshlets of a mesh, in host memory.
+#[derive(Clone, Debug, Default)]
+pub struct MeshletData {
+    pub meshlets: Vec<Meshlet>,
+    /// For each vertex of each meshlet, its index in the mesh's vertices.
+    pub vertices: Vec<u32>,
+    /// For each triangle of each meshlet, the indices of its corners in the meshlet's vertices,
+    /// eight bits each from the lowest.
+    pub triangles: Vec<u32>,
+}
+
+/// `MeshletData` uploaded to the GPU, as the storage buffers that the mesh shaders read.
+pub struct MeshletBuffers {
+    pub meshlets: Subbuffer<[Meshlet]>,
+    pub vertices: Subbuffer<[u32]>,
+    pub triangles: Subbuffer<[u32]>,
+}
+
+impl MeshletBuffers {
+    /// Uploads `data`, unless it has no meshlets.
+    pub fn upload(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        data: MeshletData,
+    ) -> Option<Self> {
+        if data.meshlets.is_empty() {
+            return None;
+        }
+        Some(MeshletBuffers {
+            meshlets: storage_buffer(memory_allocator.clone(), data.meshlets, "meshlets"),
+            vertices: storage_buffer(memory_allocator.clone(), data.vertices, "meshlet vertices"),
+            triangles: storage_buffer(memory_allocator, data.triangles, "meshlet triangles"),
+        })
+    }
+
+    /// The number of meshlets.
+    pub fn len(&self) -> u32 {
+        self.meshlets.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshlets.len() == 0
+    }
+
+    /// The number of vertices that the meshlets transform, counting those that several share once
+    /// for each.
+    pub fn vertex_count(&self) -> u64 {
+        self.vertices.len()
+    }
+}
+
+fn storage_buffer<T: BufferContents>(
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    data: Vec<T>,
+    name: &str,
+) -> Subbuffer<[T]> {
+    let buffer = Buffer::from_iter(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        data,
+    )
+    .unwrap();
+    debug_utils::name(buffer.buffer(), name);
+    buffer
+}
+
+/// Splits the triangles of `indices` into meshlets, in order, starting a new one whenever the next
+/// triangle would take the current one past `MAX_VERTICES` or `MAX_TRIANGLES`. Meshes whose
+/// neighbouring triangles are close in the index buffer, as most exporters leave them, give
+/// meshlets that share few vertices with each other.
+pub fn build(vertices: &[MeshVertex], indices: &[u32]) -> MeshletData {
+    let mut data = MeshletData::default();
+    // The local index of each mesh vertex in the meshlet being built.
+    let mut local: HashMap<u32, u32> = HashMap::new();
+    let mut meshlet_vertices: Vec<u32> = Vec::new();
+    let mut meshlet_triangles: Vec<u32> = Vec::new();
+
+    for triangle in indices.chunks_exact(3) {
+        let new_vertices = triangle
+            .iter()
+            .filter(|index| !local.contains_key(index))
+            .count();
+        if meshlet_vertices.len() + new_vertices > MAX_VERTICES
+            || meshlet_triangles.len() == MAX_TRIANGLES
+        {
+            push_meshlet(
+                &mut data,
+                vertices,
+                &mut meshlet_vertices,
+                &mut meshlet_triangles,
+            );
+            local.clear();
+        }
+
+        let mut packed = 0;
+        for (corner, &index) in triangle.iter().enumerate() {
+            let local_index = *local.entry(index).or_insert_with(|| {
+                meshlet_vertices.push(index);
+                meshlet_vertices.len() as u32 - 1
+            });
+            packed |= local_index << (8 * corner);
+        }
+        meshlet_triangles.push(packed);
+    }
+    if !meshlet_triangles.is_empty() {
+        push_meshlet(
+            &mut data,
+            vertices,
+            &mut meshlet_vertices,
+            &mut meshlet_triangles,
+        );
+    }
+
+    data
+}
+
+/// Appends the meshlet made of `meshlet_vertices` and `meshlet_triangles` to `data`, leaving them
+/// empty for the next one.
+fn push_meshlet(
+    data: &mut MeshletData,
+    vertices: &[MeshVertex],
+    meshlet_vertices: &mut Vec<u32>,
+    meshlet_triangles: &mut Vec<u32>,
+) {
+    let positions = || {
+        meshlet_vertices
+            .iter()
+            .map(|&index| Vec3::from(vertices[index as usize].position))
+    };
+    let (min, max) = positions().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+        (min.min(p), max.max(p))
+    });
+    let center = (min + max) / 2.0;
+    let radius = positions().map(|p| p.distance(center)).fold(0.0, f32::max);
+
+    data.meshlets.push(Meshlet {
+        center: center.to_array(),
+        radius,
+        vertex_offset: data.vertices.len() as u32,
+        vertex_count: meshlet_vertices.len() as u32,
+        triangle_offset: data.triangles.len() as u32,
+        triangle_count: meshlet_triangles.len() as u32,
+    });
+    data.vertices.append(meshlet_vertices);
+    data.triangles.append(meshlet_triangles);
+}
@@ -0,0 +1,248 @@
+// Per-frame metrics for `vulkano-test --metrics-out frames.csv`, for finding stutters after the
+// fact. Every frame becomes a row:
+//
+//     frame,time_s,cpu_ms,gpu_ms,acquire_ms,swapchain_recreations
+//
+// `time_s` is when the frame started, since the app did. `cpu_ms` is how long it took from then
+// until it was submitted, `acquire_ms` how much of that went to waiting for a swapchain image, and
+// `swapchain_recreations` how many times the swapchain was recreated since the previous row.
+// `gpu_ms` is the time between timestamps written at the start and end of the frame's commands,
+// which is left empty if the queue has no timestamps or the results weren't ready.
+//
+// Timestamps are read back a few frames later, once the GPU is surely done with them, so rows are
+// written with a delay. The last ones are written, and the file flushed, by `finish`.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::error;
+use vulkano::{
+    command_buffer::AutoCommandBufferBuilder,
+    device::Queue,
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::PipelineStage,
+};
+
+/// How many frames are timed at once. The timestamps of a frame are read back when its slot is
+/// needed again, which must be long enough after for the frame to have finished.
+const SLOTS: u32 = 8;
+
+pub struct FrameMetrics {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    start: Instant,
+    /// Two timestamps per slot, or `None` if the queue doesn't support them.
+    query_pool: Option<Arc<QueryPool>>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f64,
+    /// The bits of a timestamp that are valid.
+    timestamp_mask: u64,
+    frame: u64,
+    swapchain_recreations: u32,
+    /// The frame being recorded.
+    current: Option<Row>,
+    /// Frames whose timestamps haven't been read yet, oldest first.
+    pending: VecDeque<Row>,
+    /// Whether writing failed, after which nothing more is written.
+    failed: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Row {
+    frame: u64,
+    started: Instant,
+    time: Duration,
+    cpu: Duration,
+    acquire: Duration,
+    swapchain_recreations: u32,
+}
+
+impl FrameMetrics {
+    /// Creates the file at `path` and writes the header, timing the GPU work submitted to `queue`.
+    pub fn create(path: &Path, queue: &Queue) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "frame,time_s,cpu_ms,gpu_ms,acquire_ms,swapchain_recreations"
+        )?;
+
+        let device = queue.device();
+        let valid_bits = device.physical_device().queue_family_properties()
+            [queue.queue_family_index() as usize]
+            .timestamp_valid_bits;
+        let query_pool = valid_bits.map(|_| {
+            QueryPool::new(
+                device.clone(),
+                QueryPoolCreateInfo {
+                    query_count: SLOTS * 2,
+                    ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                },
+            )
+            .unwrap()
+        });
+
+        Ok(FrameMetrics {
+            path: path.to_owned(),
+            writer,
+            start: Instant::now(),
+            query_pool,
+            timestamp_period: f64::from(device.physical_device().properties().timestamp_period),
+            timestamp_mask: match valid_bits {
+                Some(bits) if bits < 64 => (1 << bits) - 1,
+                _ => u64::MAX,
+            },
+            frame: 0,
+            swapchain_recreations: 0,
+            current: None,
+            pending: VecDeque::new(),
+            failed: false,
+        })
+    }
+
+    /// Counts a recreation of the swapchain towards the next row.
+    pub fn swapchain_recreated(&mut self) {
+        self.swapchain_recreations += 1;
+    }
+
+    /// Starts timing a frame. A frame that is started again before `end_frame` is dropped.
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        self.current = Some(Row {
+            frame: self.frame,
+            started: now,
+            time: now - self.start,
+            cpu: Duration::ZERO,
+            acquire: Duration::ZERO,
+            swapchain_recreations: 0,
+        });
+    }
+
+    /// Records how long acquiring the swapchain image took this frame.
+    pub fn set_acquire_time(&mut self, acquire: Duration) {
+        if let Some(row) = &mut self.current {
+            row.acquire = acquire;
+        }
+    }
+
+    /// Writes the timestamp at the start of the frame's commands. This must be recorded outside
+    /// of a render pass, in the first command buffer of the frame.
+    pub fn begin_commands<L>(&mut self, builder: &mut AutoCommandBufferBuilder<L>) {
+        // The slot of the oldest frame is about to be reused, so its result is needed now.
+        if self.pending.len() >= SLOTS as usize {
+            let row = self.pending.pop_front().unwrap();
+            self.write_row(row);
+        }
+
+        let Some(query_pool) = &self.query_pool else {
+            return;
+        };
+        let first = self.slot() * 2;
+        // SAFETY: the frame that used the slot last was `SLOTS` frames ago, and has finished, as
+        // no more frames are in flight than there are swapchain images.
+        unsafe {
+            builder
+                .reset_query_pool(query_pool.clone(), first..first + 2)
+                .unwrap()
+                .write_timestamp(query_pool.clone(), first, PipelineStage::TopOfPipe)
+        }
+        .unwrap();
+    }
+
+    /// Writes the timestamp at the end of the frame's commands, in its last command buffer.
+    pub fn end_commands<L>(&self, builder: &mut AutoCommandBufferBuilder<L>) {
+        let Some(query_pool) = &self.query_pool else {
+            return;
+        };
+        // SAFETY: the query was reset by `begin_commands`.
+        unsafe {
+            builder.write_timestamp(
+                query_pool.clone(),
+                self.slot() * 2 + 1,
+                PipelineStage::BottomOfPipe,
+            )
+        }
+        .unwrap();
+    }
+
+    /// Finishes timing the frame once it has been submitted.
+    pub fn end_frame(&mut self) {
+        let Some(mut row) = self.current.take() else {
+            return;
+        };
+        row.cpu = row.started.elapsed();
+        row.swapchain_recreations = std::mem::take(&mut self.swapchain_recreations);
+        self.pending.push_back(row);
+        self.frame += 1;
+    }
+
+    /// Writes the frames that are left and flushes the file. The device must be idle, so that
+    /// every submitted frame has its timestamps.
+    pub fn finish(&mut self) {
+        while let Some(row) = self.pending.pop_front() {
+            self.write_row(row);
+        }
+        if !self.failed
+            && let Err(err) = self.writer.flush()
+        {
+            error!("Failed to write {}: {err}", self.path.display());
+            self.failed = true;
+        }
+    }
+
+    fn slot(&self) -> u32 {
+        (self.frame % u64::from(SLOTS)) as u32
+    }
+
+    fn write_row(&mut self, row: Row) {
+        if self.failed {
+            return;
+        }
+
+        let gpu_ms = self.gpu_time(row.frame).map_or(String::new(), |gpu| {
+            format!("{:.3}", gpu.as_secs_f64() * 1000.0)
+        });
+        let result = writeln!(
+            self.writer,
+            "{},{:.6},{:.3},{gpu_ms},{:.3},{}",
+            row.frame,
+            row.time.as_secs_f64(),
+            row.cpu.as_secs_f64() * 1000.0,
+            row.acquire.as_secs_f64() * 1000.0,
+            row.swapchain_recreations,
+        );
+        if let Err(err) = result {
+            error!("Failed to write {}: {err}", self.path.display());
+            self.failed = true;
+        }
+    }
+
+    /// The time between the timestamps of `frame`, if they are available.
+    fn gpu_time(&self, frame: u64) -> Option<Duration> {
+        let query_pool = self.query_pool.as_ref()?;
+        let first = (frame % u64::from(SLOTS)) as u32 * 2;
+
+        // Each timestamp is followed by its availability.
+        let mut results = [0u64; 4];
+        query_pool
+            .get_results(
+                first..first + 2,
+                &mut results,
+                QueryResultFlags::WITH_AVAILABILITY,
+            )
+            .ok()?;
+        let [start, start_available, end, end_available] = results;
+        if start_available == 0 || end_available == 0 {
+            return None;
+        }
+
+        let ticks = end.wrapping_sub(start) & self.timestamp_mask;
+        Some(Duration::from_nanos(
+            (ticks as f64 * self.timestamp_period) as u64,
+        ))
+    }
+}
@@ -0,0 +1,189 @@
+// Monitors and where the window goes. Monitors are numbered in the order winit lists them, which
+// `vulkano-test --list-monitors` prints along with the video modes of each.
+//
+// Wayland doesn't let windows choose their position, so there the monitor only matters for
+// fullscreen. Nor does it have exclusive fullscreen, which changes the video mode of the monitor;
+// the window is made borderless fullscreen instead.
+
+use std::{cmp::Reverse, fmt::Write};
+use tracing::warn;
+use winit::{
+    application::ApplicationHandler,
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, EventLoop},
+    monitor::{MonitorHandle, VideoModeHandle},
+    window::{Fullscreen, WindowAttributes, WindowId},
+};
+
+use crate::error::AppError;
+
+/// Where the window is placed, and how large it is.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WindowPlacement {
+    /// The index of the monitor the window starts on. The system decides if this is `None`.
+    pub monitor: Option<usize>,
+    /// The inner size of the window, in physical pixels. In exclusive fullscreen, the resolution
+    /// of the video mode.
+    pub size: Option<[u32; 2]>,
+    /// Whether the window is exclusive fullscreen, switching its monitor to the video mode
+    /// closest to `size` and `refresh_rate_millihertz`.
+    pub fullscreen: bool,
+    pub refresh_rate_millihertz: Option<u32>,
+}
+
+impl WindowPlacement {
+    /// The video mode to switch to for exclusive fullscreen, if that was asked for. This is
+    /// on the chosen monitor, or else the primary one.
+    pub fn fullscreen_mode(&self, event_loop: &ActiveEventLoop) -> Option<VideoModeHandle> {
+        if !self.fullscreen {
+            return None;
+        }
+
+        let monitor = match self.monitor {
+            Some(index) => event_loop.available_monitors().nth(index),
+            None => event_loop
+                .primary_monitor()
+                .or_else(|| event_loop.available_monitors().next()),
+        };
+        let mode = monitor
+            .and_then(|monitor| video_mode(&monitor, self.size, self.refresh_rate_millihertz));
+        if mode.is_none() {
+            warn!("No video mode to go fullscreen with");
+        }
+        mode
+    }
+
+    /// Applies the placement to `attributes`, centering the window on its monitor, or making
+    /// it exclusive fullscreen in `fullscreen_mode`.
+    pub fn apply(
+        &self,
+        event_loop: &ActiveEventLoop,
+        mut attributes: WindowAttributes,
+        fullscreen_mode: Option<VideoModeHandle>,
+    ) -> WindowAttributes {
+        if let Some(mode) = fullscreen_mode {
+            return attributes.with_fullscreen(Some(Fullscreen::Exclusive(mode)));
+        }
+
+        if let Some([width, height]) = self.size {
+            attributes = attributes.with_inner_size(PhysicalSize::new(width, height));
+        }
+
+        if let Some(index) = self.monitor {
+            match event_loop.available_monitors().nth(index) {
+                Some(monitor) => {
+                    // Without a requested size, the window is about as large as winit's default.
+                    let [width, height] = self.size.unwrap_or_else(|| {
+                        let size = PhysicalSize::<u32>::from_logical::<_, f64>(
+                            (800, 600),
+                            monitor.scale_factor(),
+                        );
+                        [size.width, size.height]
+                    });
+                    let position = monitor.position();
+                    let monitor_size = monitor.size();
+                    attributes = attributes.with_position(PhysicalPosition::new(
+                        position.x + (monitor_size.width as i32 - width as i32) / 2,
+                        position.y + (monitor_size.height as i32 - height as i32) / 2,
+                    ));
+                }
+                None => warn!(
+                    "There is no monitor {index}, only {}",
+                    event_loop.available_monitors().count(),
+                ),
+            }
+        }
+
+        attributes
+    }
+}
+
+/// The video mode of `monitor` closest to `size` and `refresh_rate_millihertz`, preferring the
+/// deepest color among equals. For a `None`, the largest size or highest rate is picked.
+pub fn video_mode(
+    monitor: &MonitorHandle,
+    size: Option<[u32; 2]>,
+    refresh_rate_millihertz: Option<u32>,
+) -> Option<VideoModeHandle> {
+    monitor.video_modes().min_by_key(|mode| {
+        let mode_size = mode.size();
+        let size_error = match size {
+            Some([width, height]) => {
+                u64::from(mode_size.width.abs_diff(width) + mode_size.height.abs_diff(height))
+            }
+            // Without a requested size, the largest mode wins.
+            None => u64::MAX - u64::from(mode_size.width) * u64::from(mode_size.height),
+        };
+        let rate = mode.refresh_rate_millihertz();
+        let rate_error = match refresh_rate_millihertz {
+            Some(requested) => rate.abs_diff(requested),
+            None => u32::MAX - rate,
+        };
+        (size_error, rate_error, Reverse(mode.bit_depth()))
+    })
+}
+
+/// Describes every monitor and its video modes.
+pub fn describe_monitors(event_loop: &ActiveEventLoop) -> String {
+    let mut report = String::new();
+    let primary = event_loop.primary_monitor();
+
+    for (index, monitor) in event_loop.available_monitors().enumerate() {
+        let size = monitor.size();
+        let position = monitor.position();
+        // Writing to a `String` can't fail.
+        let _ = writeln!(
+            report,
+            "{index}: {}{}",
+            monitor.name().as_deref().unwrap_or("unnamed monitor"),
+            if primary.as_ref() == Some(&monitor) {
+                " (primary)"
+            } else {
+                ""
+            },
+        );
+        let _ = writeln!(
+            report,
+            "    {}x{} at {},{}, scale factor {}",
+            size.width,
+            size.height,
+            position.x,
+            position.y,
+            monitor.scale_factor(),
+        );
+        for mode in monitor.video_modes() {
+            let mode_size = mode.size();
+            let _ = writeln!(
+                report,
+                "    mode {}x{} @ {:.3} Hz, {}-bit",
+                mode_size.width,
+                mode_size.height,
+                mode.refresh_rate_millihertz() as f64 / 1000.0,
+                mode.bit_depth(),
+            );
+        }
+    }
+
+    if report.is_empty() {
+        report.push_str("No monitors found\n");
+    }
+    report
+}
+
+/// Prints `describe_monitors`, which needs a running event loop.
+pub fn print_monitors() -> Result<(), AppError> {
+    struct PrintMonitors;
+
+    impl ApplicationHandler for PrintMonitors {
+        fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+            print!("{}", describe_monitors(event_loop));
+            event_loop.exit();
+        }
+
+        fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, _: WindowEvent) {}
+    }
+
+    EventLoop::new()?.run_app(&mut PrintMonitors)?;
+    Ok(())
+}
@@ -0,0 +1,303 @@
+// Occlusion culling with occlusion queries. Once the scene has been drawn, the bounding box of
+// every entity in the frustum is drawn against the depth buffer inside a query of its own, without
+// writing color or depth. Entities whose box had no samples pass are skipped the next frame.
+//
+// vulkano doesn't expose conditional rendering, so the results are read back on the CPU before
+// the next frame is recorded. An entity that comes back into view shows up one frame late. They
+// are also copied into a buffer on the GPU, which `GpuCuller` culls with instead.
+
+use glam::{Mat4, Vec3};
+use hecs::Entity;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::AutoCommandBufferBuilder,
+    device::DeviceOwned,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::{ColorBlendAttachmentState, ColorBlendState, ColorComponents},
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        DynamicState, GraphicsPipeline, Pipeline, PipelineShaderStageCreateInfo,
+    },
+    query::{QueryControlFlags, QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    render_pass::Subpass,
+};
+
+use crate::{
+    bounds::Frustum,
+    components::{MeshHandle, Transform},
+    debug_utils,
+    error::AppError,
+    mesh::{Mesh, MeshVertex},
+    scene::Scene,
+    shader::{self, ShaderStage},
+};
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    view_proj: [[f32; 4]; 4],
+    center: [f32; 4],
+    size: [f32; 4],
+}
+
+pub struct OcclusionCuller {
+    pipeline: Arc<GraphicsPipeline>,
+    box_mesh: Arc<Mesh>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    /// One query per entity id in the scene, recreated when the largest id changes.
+    query_pool: Option<Arc<QueryPool>>,
+    /// Whether queries have been recorded since the results were last read.
+    queries_pending: bool,
+    /// For each entity id, whether its box was hidden during the last frame with results.
+    occluded: Vec<bool>,
+    /// The same results on the GPU, each followed by its availability, recreated with the pool.
+    results: Option<Subbuffer<[u32]>>,
+}
+
+impl OcclusionCuller {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/occlusion_box.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load(
+            device.clone(),
+            include_str!("shaders/occlusion_box.frag"),
+            ShaderStage::Fragment,
+        )?
+        .entry_point("main")
+        .unwrap();
+
+        let vertex_input_state = MeshVertex::per_vertex().definition(&vs).unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+
+        // Both sides of the box are rasterized, so a box is still tested when the camera is close
+        // enough for its front faces to be clipped.
+        let pipeline = GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: CompareOp::LessOrEqual,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    ColorBlendAttachmentState {
+                        color_write_mask: ColorComponents::empty(),
+                        ..Default::default()
+                    },
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "occlusion queries");
+
+        Ok(OcclusionCuller {
+            pipeline,
+            box_mesh: Mesh::cube(memory_allocator.clone()),
+            memory_allocator,
+            query_pool: None,
+            queries_pending: false,
+            occluded: Vec::new(),
+            results: None,
+        })
+    }
+
+    /// Whether `entity` was hidden behind other entities during the last frame whose results are
+    /// known.
+    pub fn is_occluded(&self, entity: Entity) -> bool {
+        self.occluded
+            .get(entity.id() as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// The results of the last frame's queries in a buffer on the GPU, indexed by entity id, each
+    /// followed by whether it was available. They are only valid in the command buffer that
+    /// `begin_frame` was last recorded into, and `None` until there is a query pool.
+    pub fn results(&self) -> Option<&Subbuffer<[u32]>> {
+        self.results.as_ref()
+    }
+
+    /// Reads back the results of the previous frame's queries, copies them into `results` too,
+    /// and resets the queries for the entities of `scene` this frame. This must be recorded
+    /// outside of a render pass, before `query`.
+    pub fn begin_frame<L>(&mut self, builder: &mut AutoCommandBufferBuilder<L>, scene: &Scene) {
+        if let Some(query_pool) = self.query_pool.as_ref().filter(|_| self.queries_pending) {
+            builder
+                .copy_query_pool_results(
+                    query_pool.clone(),
+                    0..query_pool.query_count(),
+                    self.results.clone().unwrap(),
+                    QueryResultFlags::WITH_AVAILABILITY,
+                )
+                .unwrap();
+
+            let mut results = vec![0u32; query_pool.query_count() as usize * 2];
+            query_pool
+                .get_results(
+                    0..query_pool.query_count(),
+                    &mut results,
+                    QueryResultFlags::WITH_AVAILABILITY,
+                )
+                .unwrap();
+
+            // Each result is followed by its availability. Queries that haven't finished yet, or
+            // were never begun, count as visible.
+            for (occluded, result) in self.occluded.iter_mut().zip(results.chunks_exact(2)) {
+                *occluded = result[1] != 0 && result[0] == 0;
+            }
+            self.queries_pending = false;
+        }
+
+        // Queries are indexed by entity id, which hecs keeps dense by reusing the ids of despawned
+        // entities.
+        let query_count = scene
+            .world
+            .iter()
+            .map(|entity| entity.entity().id() + 1)
+            .max()
+            .unwrap_or(0);
+        if self.query_pool.as_ref().map(|pool| pool.query_count()) != Some(query_count) {
+            self.query_pool = (query_count > 0).then(|| {
+                QueryPool::new(
+                    self.pipeline.device().clone(),
+                    QueryPoolCreateInfo {
+                        query_count,
+                        ..QueryPoolCreateInfo::query_type(QueryType::Occlusion)
+                    },
+                )
+                .unwrap()
+            });
+            self.occluded = vec![false; query_count as usize];
+
+            // Until the first queries are copied, none of them count as available.
+            self.results = (query_count > 0).then(|| {
+                Buffer::new_slice::<u32>(
+                    self.memory_allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                        ..Default::default()
+                    },
+                    u64::from(query_count) * 2,
+                )
+                .unwrap()
+            });
+            if let Some(results) = &self.results {
+                builder.fill_buffer(results.clone(), 0).unwrap();
+            }
+        }
+
+        if let Some(query_pool) = &self.query_pool {
+            // SAFETY: the previous frame's command buffer is the only other one using the pool,
+            // and its queries ended in that command buffer.
+            unsafe { builder.reset_query_pool(query_pool.clone(), 0..query_count) }.unwrap();
+        }
+    }
+
+    /// Records a query for every drawn entity of `scene` in the frustum, testing its bounding box
+    /// against the depth buffer of the current subpass. `eye` and `near` are those of the camera
+    /// behind `view_proj`.
+    pub fn query<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+        view_proj: Mat4,
+        viewport: Viewport,
+        eye: Vec3,
+        near: f32,
+    ) {
+        let Some(query_pool) = &self.query_pool else {
+            return;
+        };
+        let frustum = Frustum::from_view_proj(view_proj);
+
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .unwrap()
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_vertex_buffers(0, self.box_mesh.vertex_buffer.clone())
+            .unwrap()
+            .bind_index_buffer(self.box_mesh.index_buffer.clone())
+            .unwrap();
+
+        let mut entities = scene.world.query::<(Entity, &Transform, &MeshHandle)>();
+        for (entity, transform, mesh) in entities.iter() {
+            let Some(aabb) = mesh.aabb(transform) else {
+                continue;
+            };
+            if !frustum.intersects(&aabb) {
+                continue;
+            }
+            // The near plane would cut into the box, which can hide it even though the object is
+            // in plain view. Leaving the query out keeps the entity visible.
+            let distance = (eye - aabb.center()).abs() - aabb.half_extents();
+            if distance.max_element() <= near {
+                continue;
+            }
+
+            builder
+                .push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    PushConstants {
+                        view_proj: view_proj.to_cols_array_2d(),
+                        center: aabb.center().extend(0.0).to_array(),
+                        size: (aabb.max - aabb.min).extend(0.0).to_array(),
+                    },
+                )
+                .unwrap();
+
+            // SAFETY: every query of the pool is reset at the start of the frame, by `begin_frame`.
+            unsafe {
+                builder.begin_query(query_pool.clone(), entity.id(), QueryControlFlags::empty())
+            }
+            .unwrap();
+            // SAFETY: the index buffer only refers to vertices of the bound cube.
+            unsafe { builder.draw_indexed(self.box_mesh.index_buffer.len() as u32, 1, 0, 0, 0) }
+                .unwrap();
+            builder.end_query(query_pool.clone(), entity.id()).unwrap();
+        }
+
+        self.queries_pending = true;
+    }
+}
@@ -0,0 +1,336 @@
+// Render-to-texture. An `OffscreenTarget` is one or more color images, and optionally a depth
+// buffer, with a framebuffer of its own. Anything that draws into a subpass can draw into one, and
+// later passes can sample all of its images, which is what post-processing, picking and thumbnails
+// build on. Several color images make a G-buffer for deferred shading, or hold velocities next to
+// the colors.
+//
+// Pipelines are created against a render pass, before any target exists. `create_render_pass`
+// makes one for the formats of a kind of target, and every target created from it, whatever its
+// size, works with the pipelines made for it. Their blend states come from `color_blend_state`,
+// which keeps them from writing to the attachments their fragment shader has no outputs for.
+
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{
+        AutoCommandBufferBuilder, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents,
+    },
+    device::Device,
+    format::{ClearColorValue, Format, NumericFormat},
+    image::{
+        view::ImageView, Image, ImageCreateInfo, ImageLayout, ImageType, ImageUsage, SampleCount,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::graphics::{
+        color_blend::{ColorBlendAttachmentState, ColorBlendState, ColorComponents},
+        viewport::Viewport,
+    },
+    render_pass::{
+        AttachmentDescription, AttachmentLoadOp, AttachmentReference, AttachmentStoreOp,
+        Framebuffer, FramebufferCreateInfo, RenderPass, RenderPassCreateInfo, Subpass,
+        SubpassDescription,
+    },
+};
+
+use crate::error::AppError;
+
+pub struct OffscreenTarget {
+    render_pass: Arc<RenderPass>,
+    framebuffer: Arc<Framebuffer>,
+    colors: Vec<Arc<ImageView>>,
+    depth: Option<Arc<ImageView>>,
+}
+
+impl OffscreenTarget {
+    /// Creates a render pass with a single subpass drawing into a color attachment for each of
+    /// `color_formats`, in that order, and if there is a `depth_format`, testing against a depth
+    /// attachment. All of them are cleared at the start and kept at the end, to be sampled.
+    pub fn create_render_pass(
+        device: Arc<Device>,
+        color_formats: &[Format],
+        depth_format: Option<Format>,
+    ) -> Result<Arc<RenderPass>, AppError> {
+        render_pass(device, color_formats, depth_format, AttachmentLoadOp::Clear)
+    }
+
+    /// Like `create_render_pass`, but keeping what the attachments hold at the start instead of
+    /// clearing them, for drawing into a target again after another pass sampled it. The two are
+    /// compatible, so targets of either work with both.
+    pub fn create_continuing_render_pass(
+        device: Arc<Device>,
+        color_formats: &[Format],
+        depth_format: Option<Format>,
+    ) -> Result<Arc<RenderPass>, AppError> {
+        render_pass(device, color_formats, depth_format, AttachmentLoadOp::Load)
+    }
+
+    /// Creates a target of the given size for `render_pass`, which must have been created by
+    /// `create_render_pass`. It fails where the device is out of memory for its images.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        render_pass: Arc<RenderPass>,
+        extent: [u32; 2],
+    ) -> Result<Self, AppError> {
+        let color_count = render_pass.subpasses()[0].color_attachments.len();
+        // All of the images can also be copied from, to read them back or blit them elsewhere.
+        let colors = render_pass.attachments()[..color_count]
+            .iter()
+            .map(|attachment| {
+                create_view(
+                    &memory_allocator,
+                    "offscreen color image",
+                    attachment.format,
+                    extent,
+                    ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Self::with_colors(memory_allocator, render_pass, colors)
+    }
+
+    /// Creates a target for `render_pass`, which must have been created by `create_render_pass`,
+    /// that draws into `colors`, images made elsewhere, such as by an OpenXR runtime. There has
+    /// to be one for each color attachment, all of the same size, and a depth buffer of that size
+    /// is made for them if the render pass has one.
+    pub fn with_colors(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        render_pass: Arc<RenderPass>,
+        colors: Vec<Arc<ImageView>>,
+    ) -> Result<Self, AppError> {
+        let color_count = render_pass.subpasses()[0].color_attachments.len();
+        assert_eq!(colors.len(), color_count);
+        let [width, height, _] = colors[0].image().extent();
+        let depth = render_pass
+            .attachments()
+            .get(color_count)
+            .map(|attachment| {
+                create_view(
+                    &memory_allocator,
+                    "offscreen depth buffer",
+                    attachment.format,
+                    [width, height],
+                    ImageUsage::DEPTH_STENCIL_ATTACHMENT
+                        | ImageUsage::SAMPLED
+                        | ImageUsage::TRANSFER_SRC,
+                )
+            })
+            .transpose()?;
+
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: colors.iter().chain(&depth).cloned().collect(),
+                ..Default::default()
+            },
+        )
+        .map_err(|source| AppError::Framebuffer {
+            name: "offscreen target",
+            source,
+        })?;
+
+        Ok(OffscreenTarget {
+            render_pass,
+            framebuffer,
+            colors,
+            depth,
+        })
+    }
+
+    /// The subpass that pipelines drawing into the target are created for.
+    pub fn subpass(&self) -> Subpass {
+        Subpass::from(self.render_pass.clone(), 0).unwrap()
+    }
+
+    pub fn framebuffer(&self) -> &Arc<Framebuffer> {
+        &self.framebuffer
+    }
+
+    /// The first color image, for sampling once the render pass has ended.
+    pub fn color(&self) -> &Arc<ImageView> {
+        &self.colors[0]
+    }
+
+    /// Every color image, in the order of the attachments.
+    pub fn colors(&self) -> &[Arc<ImageView>] {
+        &self.colors
+    }
+
+    /// The depth buffer, if the target has one.
+    pub fn depth(&self) -> Option<&Arc<ImageView>> {
+        self.depth.as_ref()
+    }
+
+    pub fn extent(&self) -> [u32; 2] {
+        self.framebuffer.extent()
+    }
+
+    /// A viewport covering the whole target.
+    pub fn viewport(&self) -> Viewport {
+        let [width, height] = self.extent();
+        Viewport {
+            offset: [0.0, 0.0],
+            extent: [width as f32, height as f32],
+            depth_range: 0.0..=1.0,
+        }
+    }
+
+    /// Begins `render_pass`, made by `create_continuing_render_pass` for the formats of the
+    /// target, on the target, keeping what its images hold. The caller draws into the subpass and
+    /// ends the render pass.
+    pub fn continue_render_pass<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        render_pass: Arc<RenderPass>,
+    ) {
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    render_pass,
+                    ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+    }
+
+    /// Begins the render pass on the target, clearing each color image to the value at its index
+    /// in `clear_colors`, or to zero if there is none, and the depth to the far plane. The caller
+    /// draws into `subpass` and ends the render pass.
+    pub fn begin_render_pass<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        clear_colors: &[ClearColorValue],
+    ) {
+        let mut clear_values: Vec<_> = self
+            .colors
+            .iter()
+            .enumerate()
+            .map(|(i, color)| {
+                // Integer formats have to be cleared with integers.
+                let clear_color = clear_colors.get(i).copied().unwrap_or_else(|| {
+                    match color.format().numeric_format_color() {
+                        Some(NumericFormat::UINT) => ClearColorValue::Uint([0; 4]),
+                        Some(NumericFormat::SINT) => ClearColorValue::Int([0; 4]),
+                        _ => ClearColorValue::Float([0.0; 4]),
+                    }
+                });
+                Some(clear_color.into())
+            })
+            .collect();
+        if self.depth.is_some() {
+            clear_values.push(Some(1.0.into()));
+        }
+
+        builder
+            .begin_render_pass(
+                RenderPassBeginInfo {
+                    clear_values,
+                    ..RenderPassBeginInfo::framebuffer(self.framebuffer.clone())
+                },
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+    }
+}
+
+fn create_view(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    name: &'static str,
+    format: Format,
+    extent: [u32; 2],
+    usage: ImageUsage,
+) -> Result<Arc<ImageView>, AppError> {
+    let image = Image::new(
+        memory_allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )
+    .map_err(|source| AppError::Image { name, source })?;
+    ImageView::new_default(image).map_err(|source| AppError::ImageView { name, source })
+}
+
+fn render_pass(
+    device: Arc<Device>,
+    color_formats: &[Format],
+    depth_format: Option<Format>,
+    load_op: AttachmentLoadOp,
+) -> Result<Arc<RenderPass>, AppError> {
+    let attachment = |format, layout| AttachmentDescription {
+        format,
+        samples: SampleCount::Sample1,
+        load_op,
+        store_op: AttachmentStoreOp::Store,
+        initial_layout: layout,
+        final_layout: layout,
+        ..Default::default()
+    };
+    let reference = |attachment, layout| AttachmentReference {
+        attachment,
+        layout,
+        ..Default::default()
+    };
+
+    let mut attachments: Vec<_> = color_formats
+        .iter()
+        .map(|&format| attachment(format, ImageLayout::ColorAttachmentOptimal))
+        .collect();
+    let color_attachments = (0..color_formats.len() as u32)
+        .map(|i| Some(reference(i, ImageLayout::ColorAttachmentOptimal)))
+        .collect();
+    let depth_stencil_attachment = depth_format.map(|format| {
+        attachments.push(attachment(
+            format,
+            ImageLayout::DepthStencilAttachmentOptimal,
+        ));
+        reference(
+            color_formats.len() as u32,
+            ImageLayout::DepthStencilAttachmentOptimal,
+        )
+    });
+
+    RenderPass::new(
+        device,
+        RenderPassCreateInfo {
+            attachments,
+            subpasses: vec![SubpassDescription {
+                color_attachments,
+                depth_stencil_attachment,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    )
+    .map_err(AppError::Pipeline)
+}
+
+/// The blend state of a pipeline drawing into `subpass`, with `attachments` for its first color
+/// attachments. The rest aren't written to, as they would otherwise be left undefined wherever a
+/// fragment shader without outputs for them draws.
+pub fn color_blend_state(
+    subpass: &Subpass,
+    attachments: &[ColorBlendAttachmentState],
+) -> ColorBlendState {
+    let unwritten = ColorBlendAttachmentState {
+        color_write_mask: ColorComponents::empty(),
+        ..Default::default()
+    };
+
+    ColorBlendState {
+        attachments: (0..subpass.num_color_attachments() as usize)
+            .map(|i| attachments.get(i).cloned().unwrap_or(unwritten.clone()))
+            .collect(),
+        ..Default::default()
+    }
+}
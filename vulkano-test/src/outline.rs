@@ -0,0 +1,442 @@
+// The outline around the selected entity, as a jump flood. The entity is drawn once more into a
+// mask of its own, without a depth test so that the parts of it behind other entities are outlined
+// as well, and each pixel it covers is written as the seed of the flood: its own coordinates. Then
+// every step of `shaders/outline_flood.comp` has each pixel keep the nearest of the seeds known to
+// it and to the pixels a step away from it, halving the step each time, which leaves every pixel
+// with about the nearest pixel of the entity in a few passes, however wide the outline is.
+//
+// The flood starts at the power of two past the width of the outline, as seeds further away than
+// that are of no use. The overlay then draws the outline over the frame from the result, where the
+// pixels outside the entity are near enough to one inside it. Integer images can't be filtered,
+// so the seeds are fetched as they are.
+//
+// The mask and flood are recorded outside of the render pass, before the frame is drawn, and
+// vulkano inserts the barriers between them and the overlay that reads their result.
+
+use glam::Mat4;
+use hecs::Entity;
+use std::sync::Arc;
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::{AutoCommandBufferBuilder, SubpassEndInfo},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::DeviceOwned,
+    format::Format,
+    image::{
+        sampler::{Sampler, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, StandardMemoryAllocator},
+    pipeline::{
+        compute::ComputePipelineCreateInfo,
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState},
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition, VertexInputState},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::{RenderPass, Subpass},
+};
+
+use crate::{
+    components::{MeshHandle, Transform},
+    debug_utils,
+    error::AppError,
+    gamma,
+    mesh::MeshVertex,
+    offscreen::{self, OffscreenTarget},
+    scene::Scene,
+    shader::{self, ShaderStage},
+};
+
+/// The widest outline, in physical pixels.
+pub const MAX_WIDTH: f32 = 32.0;
+
+/// The linear color of the outline.
+const COLOR: [f32; 4] = [1.0, 0.5, 0.05, 1.0];
+
+/// The format of the seeds, each a pixel's coordinates plus one, with 0 for none.
+const FORMAT: Format = Format::R16G16B16A16_SINT;
+
+/// The width and height of the workgroups of the flood.
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct MaskPushConstants {
+    model_view_proj: [[f32; 4]; 4],
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct FloodPushConstants {
+    step: i32,
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct OutlinePushConstants {
+    color: [f32; 4],
+    width: f32,
+}
+
+pub struct SelectionOutline {
+    mask_pipeline: Arc<GraphicsPipeline>,
+    flood_pipeline: Arc<ComputePipeline>,
+    outline_pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    sampler: Arc<Sampler>,
+    mask_render_pass: Arc<RenderPass>,
+    /// The mask, and the two images that the flood steps between, recreated when the size of the
+    /// frame changes.
+    images: Option<(OffscreenTarget, [Arc<ImageView>; 2])>,
+    /// The seeds that the flood left, and the width of the outline in physical pixels, if an
+    /// outline was recorded for the frame.
+    flooded: Option<(Arc<ImageView>, f32)>,
+}
+
+impl SelectionOutline {
+    /// The outline of entities drawn into `subpass`, which is where the outline is drawn as well.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+        let mask_render_pass =
+            OffscreenTarget::create_render_pass(device.clone(), &[FORMAT], None)?;
+        let mask_subpass = Subpass::from(mask_render_pass.clone(), 0).unwrap();
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/outline_mask.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load(
+            device.clone(),
+            include_str!("shaders/outline_mask.frag"),
+            ShaderStage::Fragment,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let vertex_input_state = MeshVertex::per_vertex().definition(&vs).unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<MaskPushConstants>(device.clone(), &stages)?;
+        // Every side of the entity is seen through it, so none is culled.
+        let mask_pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(offscreen::color_blend_state(
+                    &mask_subpass,
+                    &[ColorBlendAttachmentState::default()],
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(mask_subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&mask_pipeline, "outline mask");
+
+        let cs = shader::load(
+            device.clone(),
+            include_str!("shaders/outline_flood.comp"),
+            ShaderStage::Compute,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout = shader::reflect_layout::<FloodPushConstants>(
+            device.clone(),
+            std::slice::from_ref(&stage),
+        )?;
+        let flood_pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&flood_pipeline, "outline flood");
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/outline.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/outline.frag"),
+            ShaderStage::Fragment,
+            &gamma::encoding_defines(&subpass),
+        )?
+        .entry_point("main")
+        .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<OutlinePushConstants>(device.clone(), &stages)?;
+        // The outline is drawn on top of everything, as the debug lines are.
+        let depth_stencil_state = subpass
+            .subpass_desc()
+            .depth_stencil_attachment
+            .is_some()
+            .then(|| DepthStencilState {
+                depth: Some(DepthState::default()),
+                ..Default::default()
+            });
+        let outline_pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                // The triangle is made from the vertex index.
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state,
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(offscreen::color_blend_state(
+                    &subpass,
+                    &[ColorBlendAttachmentState {
+                        blend: Some(AttachmentBlend::alpha()),
+                        ..Default::default()
+                    }],
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&outline_pipeline, "outline");
+
+        // Texels are fetched without filtering, so the sampler's settings don't matter.
+        let sampler =
+            Sampler::new(device, SamplerCreateInfo::default()).map_err(AppError::Pipeline)?;
+
+        Ok(SelectionOutline {
+            mask_pipeline,
+            flood_pipeline,
+            outline_pipeline,
+            memory_allocator,
+            descriptor_set_allocator,
+            sampler,
+            mask_render_pass,
+            images: None,
+            flooded: None,
+        })
+    }
+
+    /// Records the mask and the flood for an outline `width` physical pixels wide, clamped to
+    /// `MAX_WIDTH`, around `entity` in a frame of `extent`, where it is drawn once for each of
+    /// `views`, their view and projection and the viewport that they are drawn into. Without an
+    /// entity that has a loaded mesh, or a width, the frame has no outline. This must be recorded
+    /// outside of a render pass.
+    pub fn record<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+        entity: Option<Entity>,
+        views: &[(Mat4, Viewport)],
+        extent: [u32; 2],
+        width: f32,
+    ) -> Result<(), AppError> {
+        self.flooded = None;
+        let width = width.min(MAX_WIDTH);
+        let Some(entity) = entity.filter(|_| width > 0.0 && !views.is_empty()) else {
+            return Ok(());
+        };
+        let mut query = scene.world.query_one::<(&Transform, &MeshHandle)>(entity);
+        let Some((transform, mesh)) = query.get().ok().and_then(|(transform, mesh)| {
+            let mesh = mesh.0.get()?;
+            Some((transform.0, mesh))
+        }) else {
+            return Ok(());
+        };
+
+        if self
+            .images
+            .as_ref()
+            .is_none_or(|(mask, _)| mask.extent() != extent)
+        {
+            let mask = OffscreenTarget::new(
+                self.memory_allocator.clone(),
+                self.mask_render_pass.clone(),
+                extent,
+            )?;
+            self.images = Some((mask, [self.create_image(extent), self.create_image(extent)]));
+        }
+        let (mask, flood_images) = self.images.as_ref().unwrap();
+
+        mask.begin_render_pass(builder, &[]);
+        builder
+            .bind_pipeline_graphics(self.mask_pipeline.clone())
+            .unwrap()
+            .bind_vertex_buffers(0, mesh.vertex_buffer.clone())
+            .unwrap()
+            .bind_index_buffer(mesh.index_buffer.clone())
+            .unwrap();
+        for (view_proj, viewport) in views {
+            builder
+                .set_viewport(0, [viewport.clone()].into_iter().collect())
+                .unwrap()
+                .push_constants(
+                    self.mask_pipeline.layout().clone(),
+                    0,
+                    MaskPushConstants {
+                        model_view_proj: (*view_proj * transform).to_cols_array_2d(),
+                    },
+                )
+                .unwrap();
+            // SAFETY: the index buffer only refers to vertices of the bound vertex buffer.
+            unsafe { builder.draw_indexed(mesh.index_buffer.len() as u32, 1, 0, 0, 0) }.unwrap();
+        }
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+
+        builder
+            .bind_pipeline_compute(self.flood_pipeline.clone())
+            .unwrap();
+        let mut input = mask.color().clone();
+        let mut step = (width.ceil() as u32).next_power_of_two();
+        for output in flood_images.iter().cycle() {
+            self.dispatch(builder, &input, output, step, extent);
+            input = output.clone();
+            if step == 1 {
+                break;
+            }
+            step /= 2;
+        }
+        self.flooded = Some((input, width));
+        Ok(())
+    }
+
+    /// Records the outline that the last `record` flooded, if it flooded one, into the current
+    /// subpass, over the whole of `viewport`, which must be of the frame it was recorded for.
+    pub fn draw<L>(&self, builder: &mut AutoCommandBufferBuilder<L>, viewport: Viewport) {
+        let Some((seeds, width)) = &self.flooded else {
+            return;
+        };
+
+        let layout = self.outline_pipeline.layout();
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view(0, seeds.clone()),
+                WriteDescriptorSet::sampler(1, self.sampler.clone()),
+            ],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .unwrap()
+            .bind_pipeline_graphics(self.outline_pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                layout.clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                layout.clone(),
+                0,
+                OutlinePushConstants {
+                    color: COLOR,
+                    width: *width,
+                },
+            )
+            .unwrap();
+        // SAFETY: the shader makes the triangle from the vertex index alone.
+        unsafe { builder.draw(3, 1, 0, 0) }.unwrap();
+    }
+
+    /// Records one step of the flood, from the seeds in `input` into `output`.
+    fn dispatch<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        input: &Arc<ImageView>,
+        output: &Arc<ImageView>,
+        step: u32,
+        extent: [u32; 2],
+    ) {
+        let layout = self.flood_pipeline.layout();
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view(0, input.clone()),
+                WriteDescriptorSet::sampler(1, self.sampler.clone()),
+                WriteDescriptorSet::image_view(2, output.clone()),
+            ],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                layout.clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(layout.clone(), 0, FloodPushConstants { step: step as i32 })
+            .unwrap();
+        // SAFETY: the shader only writes inside the output image, which is the size of the input.
+        unsafe {
+            builder.dispatch([
+                extent[0].div_ceil(WORKGROUP_SIZE),
+                extent[1].div_ceil(WORKGROUP_SIZE),
+                1,
+            ])
+        }
+        .unwrap();
+    }
+
+    fn create_image(&self, extent: [u32; 2]) -> Arc<ImageView> {
+        let image = Image::new(
+            self.memory_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: FORMAT,
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::STORAGE | ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+        ImageView::new_default(image).unwrap()
+    }
+}
@@ -0,0 +1,299 @@
+// A particle fountain simulated on the GPU. The particles live in a `StorageBuffer` that a compute
+// shader updates every frame, before the scene pass, and that the vertex shader of the point-list
+// draw then reads from directly, so they never go through the CPU after being created.
+//
+// Particles start out waiting, and are spawned one after another over the first `LIFETIME` so that
+// the fountain flows evenly. Whenever one expires it is respawned at the origin.
+
+use glam::Mat4;
+use std::{sync::Arc, time::Duration};
+use vulkano::{
+    buffer::{BufferContents, BufferUsage},
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor_set::{allocator::StandardDescriptorSetAllocator, DescriptorSet},
+    device::DeviceOwned,
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        compute::ComputePipelineCreateInfo,
+        graphics::{
+            color_blend::ColorBlendAttachmentState,
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        ComputePipeline, DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+};
+
+use crate::{
+    debug_utils,
+    error::AppError,
+    gamma, offscreen,
+    shader::{self, ShaderStage},
+    storage::StorageBuffer,
+};
+
+/// How long a particle lives, in seconds.
+const LIFETIME: f32 = 2.0;
+
+/// The local size of `shaders/particles.comp`.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The longest step the simulation takes, so that a stalled frame doesn't scatter the particles.
+const MAX_DELTA_TIME: f32 = 0.1;
+
+#[derive(BufferContents, Clone, Copy)]
+#[repr(C)]
+struct Particle {
+    /// The position, and the time since the particle was spawned in `w`.
+    position: [f32; 4],
+    /// The velocity, and in `w` whether the particle has been spawned yet.
+    velocity: [f32; 4],
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct UpdatePushConstants {
+    delta_time: f32,
+    time: f32,
+    lifetime: f32,
+    count: u32,
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct DrawPushConstants {
+    view_proj: [[f32; 4]; 4],
+    lifetime: f32,
+}
+
+pub struct ParticleSystem {
+    update_pipeline: Arc<ComputePipeline>,
+    draw_pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    /// The particles, created by the first `update` and recreated when their number changes.
+    particles: Option<StorageBuffer<Particle>>,
+    /// The time of the last update.
+    last_update: Option<Duration>,
+}
+
+impl ParticleSystem {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let cs = shader::load(
+            device.clone(),
+            include_str!("shaders/particles.comp"),
+            ShaderStage::Compute,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let stage = PipelineShaderStageCreateInfo::new(cs);
+        let layout = shader::reflect_layout::<UpdatePushConstants>(
+            device.clone(),
+            std::slice::from_ref(&stage),
+        )?;
+        let update_pipeline = ComputePipeline::new(
+            device.clone(),
+            None,
+            ComputePipelineCreateInfo::stage_layout(stage, layout),
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&update_pipeline, "particle update");
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/particle.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/debug_line.frag"),
+            ShaderStage::Fragment,
+            &gamma::encoding_defines(&subpass),
+        )?
+        .entry_point("main")
+        .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<DrawPushConstants>(device.clone(), &stages)?;
+        let draw_pipeline = GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                // The particles are read from the storage buffer rather than vertex buffers.
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState {
+                    topology: PrimitiveTopology::PointList,
+                    ..Default::default()
+                }),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(offscreen::color_blend_state(
+                    &subpass,
+                    &[ColorBlendAttachmentState::default()],
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&draw_pipeline, "particles");
+
+        Ok(ParticleSystem {
+            update_pipeline,
+            draw_pipeline,
+            memory_allocator,
+            descriptor_set_allocator,
+            particles: None,
+            last_update: None,
+        })
+    }
+
+    /// Records the simulation of `count` particles over the time since the last update, up to
+    /// `now` on the clock of `Time`. This must be outside of a render pass, before the `draw` of
+    /// the frame.
+    pub fn update<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        count: u32,
+        now: Duration,
+    ) {
+        if count == 0 {
+            self.particles = None;
+            return;
+        }
+
+        let delta_time = self
+            .last_update
+            .map_or(0.0, |last_update| {
+                now.saturating_sub(last_update).as_secs_f32()
+            })
+            .min(MAX_DELTA_TIME);
+        self.last_update = Some(now);
+
+        if self
+            .particles
+            .as_ref()
+            .is_none_or(|particles| particles.len() != u64::from(count))
+        {
+            // Spawning a particle as soon as its age reaches `LIFETIME` staggers them.
+            let particles = (0..count).map(|i| Particle {
+                position: [0.0, 0.0, 0.0, LIFETIME * i as f32 / count as f32],
+                velocity: [0.0; 4],
+            });
+            let particles = StorageBuffer::from_iter(
+                self.memory_allocator.clone(),
+                builder,
+                BufferUsage::empty(),
+                particles,
+            );
+            debug_utils::name(particles.buffer().buffer(), "particles");
+            self.particles = Some(particles);
+        }
+        let particles = self.particles.as_ref().unwrap();
+
+        let layout = &self.update_pipeline.layout().set_layouts()[0];
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [particles.binding(0)],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .bind_pipeline_compute(self.update_pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.update_pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                self.update_pipeline.layout().clone(),
+                0,
+                UpdatePushConstants {
+                    delta_time,
+                    time: now.as_secs_f32(),
+                    lifetime: LIFETIME,
+                    count,
+                },
+            )
+            .unwrap();
+
+        // SAFETY: the shader only accesses the particles below `count`, which the buffer holds.
+        unsafe { builder.dispatch([count.div_ceil(WORKGROUP_SIZE), 1, 1]) }.unwrap();
+    }
+
+    /// Records a draw of the particles into the current subpass, if there are any.
+    pub fn draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        view_proj: Mat4,
+        viewport: Viewport,
+    ) {
+        let Some(particles) = &self.particles else {
+            return;
+        };
+
+        let layout = &self.draw_pipeline.layout().set_layouts()[0];
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [particles.binding(0)],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .unwrap()
+            .bind_pipeline_graphics(self.draw_pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.draw_pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                self.draw_pipeline.layout().clone(),
+                0,
+                DrawPushConstants {
+                    view_proj: view_proj.to_cols_array_2d(),
+                    lifetime: LIFETIME,
+                },
+            )
+            .unwrap();
+
+        // SAFETY: the shader only reads the particles below the vertex count, which the buffer
+        // holds.
+        unsafe { builder.draw(particles.len() as u32, 1, 0, 0) }.unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+// A progressive path tracer, for `--pathtrace`. Every frame traces one path through each pixel,
+// starting at a random point of it, which bounces off the diffuse surfaces of the scene in random
+// directions until it leaves the scene for the sky or has bounced `MAX_BOUNCES` times. At every
+// bounce, a shadow ray towards the light adds its light where nothing is in the way. The paths are
+// added up in an accumulation image, and their average so far is what is shown, which gets less
+// noisy with every frame. The window title shows how many samples there are so far.
+//
+// The samples are only added up while they are of the same view, so the accumulation starts over
+// whenever the camera, the light, or anything in the scene moves or changes its material. Like
+// `RayTracer`, whose shader includes and acceleration structures it shares, it needs
+// `VK_KHR_ray_tracing_pipeline` and `glslc`, and leaves textures out.
+//
+// Alongside the average, the tracer writes the normal and distance of the surface that each pixel
+// sees first, which `Denoiser` needs to tell which pixels are of the same surface.
+
+use glam::{Mat4, Vec3, Vec4};
+use std::sync::Arc;
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        BufferContents, BufferUsage,
+    },
+    command_buffer::{allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::{DeviceOwned, Queue},
+    format::Format,
+    image::{view::ImageView, ImageUsage},
+    memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        ray_tracing::{
+            RayTracingPipeline, RayTracingPipelineCreateInfo, RayTracingShaderGroupCreateInfo,
+            ShaderBindingTable,
+        },
+        Pipeline, PipelineBindPoint,
+    },
+};
+
+use crate::{
+    acceleration::AccelerationStructures,
+    components::{Light, MaterialOverride, MeshHandle, Transform},
+    debug_utils,
+    error::AppError,
+    material::Material,
+    ray_tracing,
+    scene::Scene,
+    scene_pipeline::FrameUniforms,
+    shader::{self, ShaderStage},
+};
+
+/// How many times a path bounces off surfaces before it is cut off.
+pub const MAX_BOUNCES: u32 = 4;
+
+/// The format of the image that is shown, which is the average of the accumulated samples.
+const OUTPUT_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// The format of the normals and distances of the first surfaces that the paths hit.
+const GUIDE_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// The format of the sums of the samples, which would lose the later ones at half precision.
+const ACCUMULATION_FORMAT: Format = Format::R32G32B32A32_SFLOAT;
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    inverse_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    sky_color: [f32; 4],
+    sample_index: u32,
+    max_bounces: u32,
+}
+
+/// What the accumulated samples were taken of, which they are only added to while it stays the
+/// same.
+#[derive(PartialEq)]
+struct Subject {
+    view_proj: Mat4,
+    light: Light,
+    sky_color: [f32; 4],
+    /// The transform and base color of every loaded entity.
+    instances: Vec<(Mat4, Vec4)>,
+}
+
+impl Subject {
+    fn of(scene: &Scene, view_proj: Mat4, sky_color: [f32; 4]) -> Self {
+        let mut query = scene.world.query::<(
+            &Transform,
+            &MeshHandle,
+            &Material,
+            Option<&MaterialOverride>,
+        )>();
+        let instances = query
+            .iter()
+            .filter(|(_, mesh, _, _)| mesh.0.get().is_some())
+            .map(|(transform, _, material, material_override)| {
+                let material = material_override.map_or(material, |o| &o.0);
+                (transform.0, material.base_color)
+            })
+            .collect();
+        Subject {
+            view_proj,
+            light: scene.light(),
+            sky_color,
+            instances,
+        }
+    }
+}
+
+/// The images the path tracer writes into, of one size.
+struct Images {
+    output: Arc<ImageView>,
+    accumulation: Arc<ImageView>,
+    guide: Arc<ImageView>,
+}
+
+/// What `PathTracer::trace` returns, which is only valid until the next call.
+pub struct PathTraced {
+    /// The average of the samples so far, which can be blitted from.
+    pub color: Arc<ImageView>,
+    /// The normal of the surface that each pixel sees first, in world space, with its distance
+    /// from the eye in alpha, or a negative distance where the pixel sees the sky.
+    pub guide: Arc<ImageView>,
+}
+
+pub struct PathTracer {
+    pipeline: Arc<RayTracingPipeline>,
+    shader_binding_table: ShaderBindingTable,
+    acceleration_structures: AccelerationStructures,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    uniform_buffer_allocator: SubbufferAllocator,
+    /// Recreated when the size changes.
+    images: Option<Images>,
+    /// What the samples in the accumulation image are of, if there are any.
+    subject: Option<Subject>,
+    sample_count: u32,
+}
+
+impl PathTracer {
+    /// Creates the path tracing pipeline, which fails if `glslc` can't compile its shaders. The
+    /// device must support ray tracing, as `Capabilities` tells.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        queue: Arc<Queue>,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let stages = ray_tracing::load_stages(
+            device.clone(),
+            &[
+                (
+                    include_str!("shaders/pathtrace.rgen"),
+                    ShaderStage::RayGeneration,
+                ),
+                (include_str!("shaders/pathtrace.rmiss"), ShaderStage::Miss),
+                (include_str!("shaders/shadow.rmiss"), ShaderStage::Miss),
+                (
+                    include_str!("shaders/pathtrace.rchit"),
+                    ShaderStage::ClosestHit,
+                ),
+            ],
+        )?;
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+
+        // One group for each stage, in the order of `stages`. The miss shaders are indexed in
+        // the order of their groups, so shadow rays miss with the second one.
+        let groups = [
+            RayTracingShaderGroupCreateInfo::General { general_shader: 0 },
+            RayTracingShaderGroupCreateInfo::General { general_shader: 1 },
+            RayTracingShaderGroupCreateInfo::General { general_shader: 2 },
+            RayTracingShaderGroupCreateInfo::TrianglesHit {
+                closest_hit_shader: Some(3),
+                any_hit_shader: None,
+            },
+        ];
+        let pipeline = RayTracingPipeline::new(
+            device,
+            None,
+            RayTracingPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                groups: groups.into_iter().collect(),
+                ..RayTracingPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "path tracing");
+        let shader_binding_table = ShaderBindingTable::new(memory_allocator.clone(), &pipeline)
+            .map_err(AppError::Pipeline)?;
+
+        let uniform_buffer_allocator = SubbufferAllocator::new(
+            memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::UNIFORM_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        Ok(PathTracer {
+            pipeline,
+            shader_binding_table,
+            acceleration_structures: AccelerationStructures::new(
+                memory_allocator.clone(),
+                command_buffer_allocator,
+                queue,
+            ),
+            memory_allocator,
+            descriptor_set_allocator,
+            uniform_buffer_allocator,
+            images: None,
+            subject: None,
+            sample_count: 0,
+        })
+    }
+
+    /// How many samples the last image that `trace` returned is the average of.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Records a trace of one more sample of every pixel of `scene`, seen through `view_proj`
+    /// from `eye`, into an image of the size `extent`, with `sky_color` as the light of the sky.
+    /// The samples so far are dropped first if anything changed since the last call. This must
+    /// be outside of a render pass. Returns the average of the samples and what they hit first,
+    /// or `None` if nothing in the scene has loaded yet.
+    pub fn trace<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+        view_proj: Mat4,
+        eye: Vec3,
+        extent: [u32; 2],
+        sky_color: [f32; 4],
+    ) -> Option<PathTraced> {
+        let tlas = self.acceleration_structures.build_tlas(builder, scene)?;
+
+        if self
+            .images
+            .as_ref()
+            .is_none_or(|images| images.output.image().extent()[..2] != extent)
+        {
+            self.images = Some(Images {
+                output: ray_tracing::storage_image(
+                    self.memory_allocator.clone(),
+                    OUTPUT_FORMAT,
+                    extent,
+                    ImageUsage::TRANSFER_SRC,
+                ),
+                accumulation: ray_tracing::storage_image(
+                    self.memory_allocator.clone(),
+                    ACCUMULATION_FORMAT,
+                    extent,
+                    ImageUsage::empty(),
+                ),
+                guide: ray_tracing::storage_image(
+                    self.memory_allocator.clone(),
+                    GUIDE_FORMAT,
+                    extent,
+                    ImageUsage::TRANSFER_SRC,
+                ),
+            });
+            self.subject = None;
+        }
+        let subject = Subject::of(scene, view_proj, sky_color);
+        if self.subject.as_ref() != Some(&subject) {
+            self.subject = Some(subject);
+            self.sample_count = 0;
+        }
+        let images = self.images.as_ref().unwrap();
+
+        let uniform_buffer = self.uniform_buffer_allocator.allocate_sized().unwrap();
+        *uniform_buffer.write().unwrap() = FrameUniforms::new(view_proj, &scene.light());
+
+        let layout = &self.pipeline.layout().set_layouts()[0];
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, uniform_buffer),
+                WriteDescriptorSet::acceleration_structure(1, tlas.acceleration_structure),
+                WriteDescriptorSet::buffer(2, tlas.instances),
+                WriteDescriptorSet::image_view(3, images.output.clone()),
+                WriteDescriptorSet::image_view(4, images.accumulation.clone()),
+                WriteDescriptorSet::image_view(5, images.guide.clone()),
+            ],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .bind_pipeline_ray_tracing(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::RayTracing,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                PushConstants {
+                    inverse_view_proj: view_proj.inverse().to_cols_array_2d(),
+                    eye: eye.extend(1.0).to_array(),
+                    sky_color,
+                    sample_index: self.sample_count,
+                    max_bounces: MAX_BOUNCES,
+                },
+            )
+            .unwrap();
+
+        // SAFETY: as for `RayTracer::trace`. Each ray also reads and writes its own pixel of the
+        // accumulation image, which is as large as the launch too, and only reads what an earlier
+        // trace wrote there once it has been written. The guide image is as large as well.
+        unsafe {
+            builder.trace_rays(
+                self.shader_binding_table.addresses().clone(),
+                [extent[0], extent[1], 1],
+            )
+        }
+        .unwrap();
+        self.sample_count += 1;
+
+        Some(PathTraced {
+            color: images.output.clone(),
+            guide: images.guide.clone(),
+        })
+    }
+}
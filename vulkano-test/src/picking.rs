@@ -0,0 +1,281 @@
+// Object picking through an ID buffer. When a pixel is to be picked, every drawn entity of the
+// scene is rasterized once more with its number instead of its color, into an `R32_UINT` target
+// with a depth buffer of its own, so that the entity nearest to the camera ends up in the pixel.
+// The number of the pixel is copied into a buffer and read back once the GPU is done with the
+// frame, without the CPU waiting for it, and turned back into the entity it was drawn for.
+//
+// Only the one pixel is ever needed, so the ID buffer is a single pixel: the viewport is moved so
+// that the pixel being picked is the one that lands in it, and everything else is clipped away.
+// Each pick has an ID buffer of its own, so that picks in frames still in flight don't share one.
+//
+// Clicking in the window picks the entity under the cursor this way, unless the `picking` setting
+// has it cast a ray with `raycast` instead.
+
+use glam::{Mat4, Vec2};
+use hecs::Entity;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, CopyImageToBufferInfo, SubpassEndInfo},
+    device::DeviceOwned,
+    format::Format,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendAttachmentState,
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        DynamicState, GraphicsPipeline, Pipeline, PipelineShaderStageCreateInfo,
+    },
+    render_pass::{RenderPass, Subpass},
+};
+
+use crate::{
+    components::{MeshHandle, Transform},
+    debug_utils,
+    error::AppError,
+    mesh::MeshVertex,
+    offscreen::{self, OffscreenTarget},
+    scene::Scene,
+    shader::{self, ShaderStage},
+};
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    model_view_proj: [[f32; 4]; 4],
+    id: u32,
+}
+
+/// What was under a picked pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pick {
+    /// The pixel, in physical pixels from the top left of the window.
+    pub pixel: [u32; 2],
+    /// The entity drawn nearest to the camera at the pixel, or `None` where there was nothing.
+    pub entity: Option<Entity>,
+}
+
+pub struct Picker {
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    render_pass: Arc<RenderPass>,
+    /// The pixel to pick in the next frame, if a pick was requested.
+    requested: Option<[u32; 2]>,
+    /// Picks that were copied into their buffers, waiting for the GPU.
+    pending: Vec<Pending>,
+}
+
+struct Pending {
+    pixel: [u32; 2],
+    buffer: Subbuffer<[u32]>,
+    /// The entity drawn with each number, less one.
+    entities: Vec<Entity>,
+}
+
+impl Picker {
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+        let render_pass = OffscreenTarget::create_render_pass(
+            device.clone(),
+            &[Format::R32_UINT],
+            Some(Format::D16_UNORM),
+        )?;
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/picking.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load(
+            device.clone(),
+            include_str!("shaders/picking.frag"),
+            ShaderStage::Fragment,
+        )?
+        .entry_point("main")
+        .unwrap();
+
+        let vertex_input_state = MeshVertex::per_vertex().definition(&vs).unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+
+        // Integer attachments can't be blended, which the default attachment state doesn't do.
+        let pipeline = GraphicsPipeline::new(
+            device,
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                vertex_input_state: Some(vertex_input_state),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(offscreen::color_blend_state(
+                    &subpass,
+                    &[ColorBlendAttachmentState::default()],
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "picking");
+
+        Ok(Picker {
+            pipeline,
+            memory_allocator,
+            render_pass,
+            requested: None,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Picks `pixel`, in physical pixels from the top left of the window, in the next frame. A
+    /// request made before that frame replaces this one.
+    pub fn request(&mut self, pixel: [u32; 2]) {
+        self.requested = Some(pixel);
+    }
+
+    /// Whether a pick is waiting for a frame to be rendered, or for the GPU to finish one.
+    pub fn is_pending(&self) -> bool {
+        self.requested.is_some() || !self.pending.is_empty()
+    }
+
+    /// The latest of the picks that the GPU has finished, if any has finished since the last call.
+    pub fn poll(&mut self) -> Option<Pick> {
+        let mut latest = None;
+        self.pending.retain(|pending| {
+            // The buffer stays locked until the future of its frame has been cleaned up.
+            let Ok(id) = pending.buffer.read().map(|id| id[0]) else {
+                return true;
+            };
+            let entity = id
+                .checked_sub(1)
+                .and_then(|index| pending.entities.get(index as usize).copied());
+            latest = Some(Pick {
+                pixel: pending.pixel,
+                entity,
+            });
+            false
+        });
+        latest
+    }
+
+    /// Records the ID buffer for the requested pixel, if a pick was requested, and its copy into
+    /// a buffer to read back. The pixel is picked in the first of `views`, their view and
+    /// projection and the viewport that they are drawn into, whose viewport contains it, or not
+    /// at all if none of them does. This must be recorded outside of a render pass.
+    pub fn record<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+        views: &[(Mat4, Viewport)],
+    ) -> Result<(), AppError> {
+        let Some(pixel) = self.requested.take() else {
+            return Ok(());
+        };
+        let center = Vec2::new(pixel[0] as f32, pixel[1] as f32) + 0.5;
+        let Some((view_proj, viewport)) = view_at(views, center) else {
+            return Ok(());
+        };
+
+        let shifted = Viewport {
+            offset: [
+                viewport.offset[0] - pixel[0] as f32,
+                viewport.offset[1] - pixel[1] as f32,
+            ],
+            ..viewport.clone()
+        };
+        let target = OffscreenTarget::new(
+            self.memory_allocator.clone(),
+            self.render_pass.clone(),
+            [1, 1],
+        )?;
+        target.begin_render_pass(builder, &[]);
+        builder
+            .set_viewport(0, [shifted].into_iter().collect())
+            .unwrap()
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap();
+
+        let mut entities = Vec::new();
+        let mut query = scene.world.query::<(Entity, &Transform, &MeshHandle)>();
+        for (entity, transform, mesh) in query.iter() {
+            let Some(mesh) = mesh.0.get() else {
+                continue;
+            };
+            entities.push(entity);
+            builder
+                .push_constants(
+                    self.pipeline.layout().clone(),
+                    0,
+                    PushConstants {
+                        model_view_proj: (*view_proj * transform.0).to_cols_array_2d(),
+                        id: entities.len() as u32,
+                    },
+                )
+                .unwrap()
+                .bind_vertex_buffers(0, mesh.vertex_buffer.clone())
+                .unwrap()
+                .bind_index_buffer(mesh.index_buffer.clone())
+                .unwrap();
+            // SAFETY: the index buffer only refers to vertices of the bound vertex buffer.
+            unsafe { builder.draw_indexed(mesh.index_buffer.len() as u32, 1, 0, 0, 0) }.unwrap();
+        }
+        builder.end_render_pass(SubpassEndInfo::default()).unwrap();
+
+        let buffer = Buffer::new_slice::<u32>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            1,
+        )
+        .unwrap();
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                target.color().image().clone(),
+                buffer.clone(),
+            ))
+            .unwrap();
+        self.pending.push(Pending {
+            pixel,
+            buffer,
+            entities,
+        });
+        Ok(())
+    }
+}
+
+/// The first of `views`, each a view and projection and the viewport it is drawn into, whose
+/// viewport contains `point`, in physical pixels from the top left of the window.
+pub fn view_at(views: &[(Mat4, Viewport)], point: Vec2) -> Option<&(Mat4, Viewport)> {
+    views.iter().find(|(_, viewport)| {
+        let [left, top] = viewport.offset;
+        let [width, height] = viewport.extent;
+        (left..left + width).contains(&point.x) && (top..top + height).contains(&point.y)
+    })
+}
@@ -0,0 +1,289 @@
+// Hardware ray tracing of the scene with `VK_KHR_ray_tracing_pipeline`, in place of rasterizing
+// it, on devices where `--ray-tracing` could enable it. A ray is traced from the eye through the
+// middle of every pixel against the TLAS of the scene (see `acceleration.rs`). The closest-hit
+// shader lights the triangle it hit as `scene.frag` does, though without textures, and the miss
+// shader returns the clear color. The result goes into a storage image, which the app blits to the
+// swapchain image before the overlay is drawn on top. The material preview is still rasterized,
+// and the ray traced view goes without particles and blur.
+//
+// naga translates none of the ray tracing stages, so `shaders/scene.rgen`, `scene.rmiss` and
+// `scene.rchit` are compiled with `glslc` when the pipeline is created. The closest-hit shader
+// reads the hit triangle's normals through the device addresses of the mesh's buffers.
+
+use glam::{Mat4, Vec3};
+use std::sync::Arc;
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        BufferContents, BufferUsage,
+    },
+    command_buffer::{allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::{Device, DeviceExtensions, DeviceFeatures, DeviceOwned, Queue},
+    format::Format,
+    image::{view::ImageView, Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        ray_tracing::{
+            RayTracingPipeline, RayTracingPipelineCreateInfo, RayTracingShaderGroupCreateInfo,
+            ShaderBindingTable,
+        },
+        Pipeline, PipelineBindPoint, PipelineShaderStageCreateInfo,
+    },
+};
+
+use crate::{
+    acceleration::AccelerationStructures,
+    debug_utils,
+    device_requirements::DeviceRequirements,
+    error::AppError,
+    scene::Scene,
+    scene_pipeline::FrameUniforms,
+    shader::{self, ShaderStage},
+};
+
+/// The features ray tracing needs. The closest-hit shader reads vertices through buffer device
+/// addresses.
+pub const FEATURES: DeviceFeatures = DeviceFeatures {
+    ray_tracing_pipeline: true,
+    acceleration_structure: true,
+    buffer_device_address: true,
+    ..DeviceFeatures::empty()
+};
+
+/// The format of the traced image, which keeps the precision of the lighting until it is blitted.
+const FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    inverse_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    clear_color: [f32; 4],
+}
+
+/// Asks for ray tracing where supported. Its extensions need those of the features that they build
+/// on on Vulkan 1.1, where these aren't core yet.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_extension_features(
+        FEATURES,
+        DeviceExtensions {
+            khr_ray_tracing_pipeline: true,
+            khr_acceleration_structure: true,
+            khr_deferred_host_operations: true,
+            khr_buffer_device_address: true,
+            ext_descriptor_indexing: true,
+            khr_maintenance3: true,
+            khr_spirv_1_4: true,
+            khr_shader_float_controls: true,
+            ..DeviceExtensions::empty()
+        },
+    );
+}
+
+pub struct RayTracer {
+    pipeline: Arc<RayTracingPipeline>,
+    shader_binding_table: ShaderBindingTable,
+    acceleration_structures: AccelerationStructures,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    uniform_buffer_allocator: SubbufferAllocator,
+    /// The image rays are traced into, recreated when the size changes.
+    output: Option<Arc<ImageView>>,
+}
+
+impl RayTracer {
+    /// Creates the ray tracing pipeline, which fails if `glslc` can't compile its shaders. The
+    /// device must support ray tracing, as `Capabilities` tells.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        queue: Arc<Queue>,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let stages = load_stages(
+            device.clone(),
+            &[
+                (
+                    include_str!("shaders/scene.rgen"),
+                    ShaderStage::RayGeneration,
+                ),
+                (include_str!("shaders/scene.rmiss"), ShaderStage::Miss),
+                (include_str!("shaders/scene.rchit"), ShaderStage::ClosestHit),
+            ],
+        )?;
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+
+        // One group for each stage, in the order of `stages`.
+        let groups = [
+            RayTracingShaderGroupCreateInfo::General { general_shader: 0 },
+            RayTracingShaderGroupCreateInfo::General { general_shader: 1 },
+            RayTracingShaderGroupCreateInfo::TrianglesHit {
+                closest_hit_shader: Some(2),
+                any_hit_shader: None,
+            },
+        ];
+        let pipeline = RayTracingPipeline::new(
+            device,
+            None,
+            RayTracingPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                groups: groups.into_iter().collect(),
+                ..RayTracingPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "ray tracing");
+        let shader_binding_table = ShaderBindingTable::new(memory_allocator.clone(), &pipeline)
+            .map_err(AppError::Pipeline)?;
+
+        let uniform_buffer_allocator = SubbufferAllocator::new(
+            memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::UNIFORM_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        Ok(RayTracer {
+            pipeline,
+            shader_binding_table,
+            acceleration_structures: AccelerationStructures::new(
+                memory_allocator.clone(),
+                command_buffer_allocator,
+                queue,
+            ),
+            memory_allocator,
+            descriptor_set_allocator,
+            uniform_buffer_allocator,
+            output: None,
+        })
+    }
+
+    /// Records a trace of `scene`, seen through `view_proj` from `eye`, into an image of the size
+    /// `extent`. This must be outside of a render pass. Returns the image, which is only valid
+    /// until the next call and can be blitted from, or `None` if nothing in the scene has loaded
+    /// yet.
+    pub fn trace<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+        view_proj: Mat4,
+        eye: Vec3,
+        extent: [u32; 2],
+        clear_color: [f32; 4],
+    ) -> Option<Arc<Image>> {
+        let tlas = self.acceleration_structures.build_tlas(builder, scene)?;
+
+        if self
+            .output
+            .as_ref()
+            .is_none_or(|output| output.image().extent()[..2] != extent)
+        {
+            self.output = Some(storage_image(
+                self.memory_allocator.clone(),
+                FORMAT,
+                extent,
+                ImageUsage::TRANSFER_SRC,
+            ));
+        }
+        let output = self.output.clone().unwrap();
+
+        let uniform_buffer = self.uniform_buffer_allocator.allocate_sized().unwrap();
+        *uniform_buffer.write().unwrap() = FrameUniforms::new(view_proj, &scene.light());
+
+        let layout = &self.pipeline.layout().set_layouts()[0];
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, uniform_buffer),
+                WriteDescriptorSet::acceleration_structure(1, tlas.acceleration_structure),
+                WriteDescriptorSet::buffer(2, tlas.instances),
+                WriteDescriptorSet::image_view(3, output.clone()),
+            ],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .bind_pipeline_ray_tracing(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::RayTracing,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                PushConstants {
+                    inverse_view_proj: view_proj.inverse().to_cols_array_2d(),
+                    eye: eye.extend(1.0).to_array(),
+                    clear_color,
+                },
+            )
+            .unwrap();
+
+        // SAFETY: each hit only reads the instance data at its own custom index, which
+        // `build_tlas` gave every instance a slot of, and the vertices and indices of the mesh
+        // that the instance was built from, which `AccelerationStructures` keeps alive. Each ray
+        // writes its own pixel of the output image, which is as large as the launch.
+        unsafe {
+            builder.trace_rays(
+                self.shader_binding_table.addresses().clone(),
+                [extent[0], extent[1], 1],
+            )
+        }
+        .unwrap();
+
+        Some(output.image().clone())
+    }
+}
+
+/// Compiles the ray tracing shader of each stage with `glslc`, in order.
+pub(crate) fn load_stages(
+    device: Arc<Device>,
+    sources: &[(&str, ShaderStage)],
+) -> Result<Vec<PipelineShaderStageCreateInfo>, AppError> {
+    sources
+        .iter()
+        .map(|&(source, stage)| {
+            let module = shader::load_with_glslc(device.clone(), source, stage, &[])?;
+            Ok(PipelineShaderStageCreateInfo::new(
+                module.entry_point("main").unwrap(),
+            ))
+        })
+        .collect()
+}
+
+/// A 2D image of `format` and the size `extent` that ray tracing shaders can store into, with
+/// `usage` as well.
+pub(crate) fn storage_image(
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    format: Format,
+    extent: [u32; 2],
+    usage: ImageUsage,
+) -> Arc<ImageView> {
+    let image = Image::new(
+        memory_allocator,
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            usage: ImageUsage::STORAGE | usage,
+            ..Default::default()
+        },
+        AllocationCreateInfo::default(),
+    )
+    .unwrap();
+    ImageView::new_default(image).unwrap()
+}
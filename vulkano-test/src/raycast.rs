@@ -0,0 +1,230 @@
+// Picking on the CPU, by casting a ray from the camera through the cursor against the scene. Each
+// loaded entity's bounding box is tested first, and the triangles of the entities whose box the
+// ray goes through are then tested in their model space, with the ray brought there by the
+// inverse of their transform, which keeps the distances along it the same. The nearest hit wins.
+//
+// Unlike `Picker`, nothing is read back from the GPU, so the hit is known straight away, along
+// with where it is. Meshes keep their triangles in host memory for it. Entities are tested at
+// full detail, whatever level of detail they are drawn with.
+
+use glam::{Mat4, Vec2, Vec3};
+use hecs::Entity;
+use vulkano::pipeline::graphics::viewport::Viewport;
+
+use crate::{
+    bounds::Aabb,
+    components::{MeshHandle, Transform},
+    mesh::Mesh,
+    scene::Scene,
+};
+
+/// A half-line from `origin` along `direction`, whose length is the unit of the distances along
+/// it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+/// Where a ray hit an entity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub entity: Entity,
+    /// The point hit, in world space.
+    pub position: Vec3,
+    /// How far along the ray the point is.
+    pub distance: f32,
+}
+
+impl Ray {
+    /// The ray from the eye through `pixel`, in physical pixels from the top left of the window,
+    /// of a view seen through `view_proj` in `viewport`. It starts on the near plane, and its
+    /// direction has unit length.
+    pub fn through_pixel(view_proj: Mat4, viewport: &Viewport, pixel: Vec2) -> Self {
+        let offset = Vec2::from(viewport.offset);
+        let extent = Vec2::from(viewport.extent);
+        // Vulkan's clip space has Y pointing down, as pixels do.
+        let ndc = (pixel - offset) / extent * 2.0 - 1.0;
+        let inverse = view_proj.inverse();
+        let near = inverse.project_point3(ndc.extend(0.0));
+        let far = inverse.project_point3(ndc.extend(1.0));
+        Ray {
+            origin: near,
+            direction: (far - near).normalize_or_zero(),
+        }
+    }
+
+    /// The ray in the space that `transform` transforms from.
+    pub fn transformed(&self, transform: Mat4) -> Self {
+        Ray {
+            origin: transform.transform_point3(self.origin),
+            direction: transform.transform_vector3(self.direction),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// How far along the ray it enters `aabb`, or 0 if it starts inside it, or `None` if it
+    /// misses it.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        if aabb.is_empty() {
+            return None;
+        }
+        // The slabs between the planes of each pair of faces; axes the ray runs parallel to give
+        // infinities, which the comparisons handle.
+        let inverse = self.direction.recip();
+        let to_min = (aabb.min - self.origin) * inverse;
+        let to_max = (aabb.max - self.origin) * inverse;
+        let enter = to_min.min(to_max).max_element().max(0.0);
+        let exit = to_min.max(to_max).min_element();
+        (enter <= exit).then_some(enter)
+    }
+
+    /// How far along the ray it hits the triangle between `a`, `b` and `c`, from either side, as
+    /// Möller and Trumbore find it.
+    pub fn intersect_triangle(&self, [a, b, c]: [Vec3; 3]) -> Option<f32> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(edge1);
+        let v = self.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge2.dot(q) * inverse;
+        (distance >= 0.0).then_some(distance)
+    }
+
+    /// How far along the ray it first hits a triangle of `mesh`, which the ray must be in the
+    /// model space of.
+    pub fn intersect_mesh(&self, mesh: &Mesh) -> Option<f32> {
+        mesh.indices
+            .chunks_exact(3)
+            .filter_map(|triangle| {
+                let corner = |i: usize| mesh.positions[triangle[i] as usize];
+                self.intersect_triangle([corner(0), corner(1), corner(2)])
+            })
+            .min_by(f32::total_cmp)
+    }
+}
+
+/// The nearest of the loaded entities of `scene` that `ray` hits, if it hits any.
+pub fn raycast(scene: &Scene, ray: &Ray) -> Option<RayHit> {
+    let mut nearest: Option<RayHit> = None;
+    let mut query = scene.world.query::<(Entity, &Transform, &MeshHandle)>();
+    for (entity, transform, mesh) in query.iter() {
+        let Some(mesh) = mesh.0.get() else {
+            continue;
+        };
+        // Entities whose box is no nearer than the nearest hit can't be nearer themselves.
+        let Some(entered) = ray.intersect_aabb(&mesh.aabb.transformed(transform.0)) else {
+            continue;
+        };
+        if nearest.is_some_and(|hit| hit.distance <= entered) {
+            continue;
+        }
+        let model_ray = ray.transformed(transform.0.inverse());
+        if let Some(distance) = model_ray.intersect_mesh(&mesh)
+            && nearest.is_none_or(|hit| distance < hit.distance)
+        {
+            nearest = Some(RayHit {
+                entity,
+                position: ray.at(distance),
+                distance,
+            });
+        }
+    }
+    nearest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+        Ray {
+            origin: Vec3::from(origin),
+            direction: Vec3::from(direction),
+        }
+    }
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::splat(-1.0), Vec3::splat(1.0))
+    }
+
+    const TRIANGLE: [Vec3; 3] = [
+        Vec3::new(-1.0, -1.0, 0.0),
+        Vec3::new(1.0, -1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    ];
+
+    #[test]
+    fn hits_and_misses_boxes() {
+        let hit = ray([-5.0, 0.5, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box());
+        assert_eq!(hit, Some(4.0));
+
+        assert_eq!(
+            ray([-5.0, 3.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box()),
+            None
+        );
+        // The box is behind the ray.
+        assert_eq!(
+            ray([5.0, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box()),
+            None
+        );
+        assert_eq!(
+            ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&Aabb::EMPTY),
+            None
+        );
+    }
+
+    #[test]
+    fn rays_parallel_to_a_slab_hit_only_within_it() {
+        // Running along x, parallel to the slabs of y and z.
+        let inside = ray([-5.0, 0.25, -0.5], [1.0, 0.0, 0.0]);
+        assert_eq!(inside.intersect_aabb(&unit_box()), Some(4.0));
+
+        let outside = ray([-5.0, 0.25, 1.5], [1.0, 0.0, 0.0]);
+        assert_eq!(outside.intersect_aabb(&unit_box()), None);
+    }
+
+    #[test]
+    fn rays_starting_inside_a_box_hit_it_at_once() {
+        let inside = ray([0.25, -0.5, 0.0], [0.0, 0.6, 0.8]);
+        assert_eq!(inside.intersect_aabb(&unit_box()), Some(0.0));
+    }
+
+    #[test]
+    fn hits_and_misses_triangles() {
+        let hit = ray([0.0, 0.0, 3.0], [0.0, 0.0, -1.0]).intersect_triangle(TRIANGLE);
+        assert_eq!(hit, Some(3.0));
+        // From behind.
+        let hit = ray([0.0, 0.0, -2.0], [0.0, 0.0, 1.0]).intersect_triangle(TRIANGLE);
+        assert_eq!(hit, Some(2.0));
+
+        // Past the corner at (1, -1).
+        let miss = ray([0.9, 0.9, 3.0], [0.0, 0.0, -1.0]).intersect_triangle(TRIANGLE);
+        assert_eq!(miss, None);
+        // Pointing away.
+        let miss = ray([0.0, 0.0, 3.0], [0.0, 0.0, 1.0]).intersect_triangle(TRIANGLE);
+        assert_eq!(miss, None);
+    }
+
+    #[test]
+    fn rays_in_the_plane_of_a_triangle_miss_it() {
+        let along = ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        assert_eq!(along.intersect_triangle(TRIANGLE), None);
+    }
+}
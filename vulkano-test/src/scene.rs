@@ -0,0 +1,564 @@
+// The objects being rendered. Everything in the scene is an entity of a `hecs` world, made of the
+// components in `components`. Entities are placed by the nodes of a transform hierarchy: each node
+// has a transform relative to its parent, and the resulting world transforms are cached and only
+// recomputed for the parts of the tree that changed. glTF node trees are imported as they are.
+// The terrain and the water, if the scene has them, aren't entities, as they are drawn by passes of
+// their own.
+
+use glam::{Mat4, Vec3, Vec4};
+use hecs::{Entity, World};
+use std::{
+    f32::consts::{GOLDEN_RATIO, TAU},
+    path::Path,
+};
+
+use crate::{
+    assets::{asset_path, Assets, MeshSource},
+    bounds::Aabb,
+    camera::Camera,
+    components::{Light, LightKind, MeshHandle, SceneNode, Spin, Transform},
+    material::Material,
+    mesh::{MeshData, MeshVertex},
+    terrain::Terrain,
+    water::Water,
+};
+
+/// Identifies a node of a `Scene`. Ids are indices into the scene's nodes, in the order they were
+/// added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(pub(crate) usize);
+
+/// A transform in the scene hierarchy.
+pub struct Node {
+    pub name: Option<String>,
+    local_transform: Mat4,
+    /// `local_transform` combined with the world transform of the parent, valid once
+    /// `Scene::update_transforms` has run.
+    world_transform: Mat4,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// Whether `local_transform` changed since the world transform was last computed.
+    dirty: bool,
+}
+
+impl Node {
+    /// The transform relative to the parent node.
+    pub fn local_transform(&self) -> Mat4 {
+        self.local_transform
+    }
+
+    pub fn world_transform(&self) -> Mat4 {
+        self.world_transform
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+    /// Whether any node is dirty.
+    transforms_dirty: bool,
+    pub world: World,
+    /// The camera the scene is viewed from, if it has one of its own rather than one framing
+    /// everything in it.
+    pub camera: Option<Camera>,
+    /// Every material available in the scene, whether or not an entity currently uses it. These
+    /// are the variants offered for material overrides.
+    pub materials: Vec<Material>,
+    /// The ground, drawn apart from the entities.
+    pub terrain: Option<Terrain>,
+    /// The water surface, drawn over everything else.
+    pub water: Option<Water>,
+}
+
+impl Scene {
+    /// A row of cubes in different materials, shown when no scene file is given. The middle one
+    /// spins.
+    pub fn demo(assets: &mut Assets) -> Scene {
+        let mesh = MeshHandle(assets.mesh(&MeshSource::Cube));
+        let materials: Vec<_> = [
+            ("red", Vec4::new(0.8, 0.1, 0.1, 1.0)),
+            ("green", Vec4::new(0.1, 0.8, 0.1, 1.0)),
+            ("blue", Vec4::new(0.1, 0.1, 0.8, 1.0)),
+        ]
+        .into_iter()
+        .map(|(name, base_color)| Material::new(name, base_color))
+        .collect();
+
+        let mut scene = Scene {
+            materials: materials.clone(),
+            ..Default::default()
+        };
+        for (i, material) in materials.into_iter().enumerate() {
+            let transform = Mat4::from_translation(Vec3::new(i as f32 * 1.5 - 1.5, 0.0, 0.0));
+            let node = scene.add_node(None, transform);
+            let entity = scene.spawn_object(mesh.clone(), node, material);
+            if i == 1 {
+                let spin = Spin::new(Vec3::Y, 1.0, transform);
+                scene.world.insert_one(entity, spin).unwrap();
+            }
+        }
+        scene.world.spawn((Light::default(),));
+        scene.update_transforms();
+
+        scene
+    }
+
+    /// Loads the default scene of a glTF file, or its first scene if no default is set.
+    pub fn load_gltf(assets: &mut Assets, path: &Path) -> Result<Scene, gltf::Error> {
+        let (document, gltf_meshes) = GltfMeshes::open(assets, path)?;
+
+        let mut scene = Scene {
+            materials: gltf_meshes.materials.clone(),
+            ..Default::default()
+        };
+        if let Some(gltf_scene) = document.default_scene().or(document.scenes().next()) {
+            for node in gltf_scene.nodes() {
+                scene.add_gltf_node(&node, None, &gltf_meshes.meshes);
+            }
+        }
+        // glTF lights are an extension that isn't imported, so the scene gets a default sun.
+        scene.world.spawn((Light::default(),));
+        scene.update_transforms();
+
+        Ok(scene)
+    }
+
+    /// Adds the default scene of a glTF file, or its first scene, to what is already in the
+    /// scene, under a root node of its own named after the file. Returns that node.
+    pub fn add_gltf(&mut self, assets: &mut Assets, path: &Path) -> Result<NodeId, gltf::Error> {
+        let (document, gltf_meshes) = GltfMeshes::open(assets, path)?;
+
+        let root = self.add_node(None, Mat4::IDENTITY);
+        self.nodes[root.0].name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+        self.materials.extend(gltf_meshes.materials.iter().cloned());
+        if let Some(gltf_scene) = document.default_scene().or(document.scenes().next()) {
+            for node in gltf_scene.nodes() {
+                self.add_gltf_node(&node, Some(root), &gltf_meshes.meshes);
+            }
+        }
+        self.update_transforms();
+
+        Ok(root)
+    }
+
+    fn add_gltf_node(
+        &mut self,
+        gltf_node: &gltf::Node<'_>,
+        parent: Option<NodeId>,
+        meshes: &[Vec<(MeshHandle, Material)>],
+    ) {
+        let transform = Mat4::from_cols_array_2d(&gltf_node.transform().matrix());
+        let node = self.add_node(parent, transform);
+        self.nodes[node.0].name = gltf_node.name().map(str::to_owned);
+
+        if let Some(mesh) = gltf_node.mesh() {
+            for (mesh, material) in &meshes[mesh.index()] {
+                self.spawn_object(mesh.clone(), node, material.clone());
+            }
+        }
+        for child in gltf_node.children() {
+            self.add_gltf_node(&child, Some(node), meshes);
+        }
+    }
+
+    /// Adds a node under `parent`, or as a root if there is none.
+    pub fn add_node(&mut self, parent: Option<NodeId>, local_transform: Mat4) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            name: None,
+            local_transform,
+            world_transform: Mat4::IDENTITY,
+            parent,
+            children: Vec::new(),
+            dirty: true,
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        self.transforms_dirty = true;
+
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    /// The node for renaming it. Its transform is changed through `set_local_transform`.
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    /// Every node, in the order they were added. Parents always come before their children.
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (NodeId(i), node))
+    }
+
+    /// The nodes without a parent.
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// Moves a node relative to its parent. The world transforms of the node and everything below
+    /// it are refreshed by the next `update_transforms`.
+    pub fn set_local_transform(&mut self, id: NodeId, local_transform: Mat4) {
+        let node = &mut self.nodes[id.0];
+        node.local_transform = local_transform;
+        node.dirty = true;
+        self.transforms_dirty = true;
+    }
+
+    /// Moves `entity` to `world_transform`. An entity placed by a node moves its node relative to
+    /// the parent, taking its descendants along, and one that spins keeps spinning from there;
+    /// others have their `Transform` set directly. Does nothing if the entity no longer exists.
+    pub fn set_world_transform(&mut self, entity: Entity, world_transform: Mat4) {
+        let Ok(node) = self.world.get::<&SceneNode>(entity).map(|node| node.0) else {
+            if let Ok(mut transform) = self.world.get::<&mut Transform>(entity) {
+                transform.0 = world_transform;
+            }
+            return;
+        };
+        let parent_transform = self.nodes[node.0].parent.map_or(Mat4::IDENTITY, |parent| {
+            self.nodes[parent.0].world_transform
+        });
+        let local_transform = parent_transform.inverse() * world_transform;
+        if let Ok(mut spin) = self.world.get::<&mut Spin>(entity) {
+            // `interpolate` places the node by its rest transform, so that moves by as much.
+            spin.rest_transform = local_transform
+                * self.nodes[node.0].local_transform.inverse()
+                * spin.rest_transform;
+        }
+        self.set_local_transform(node, local_transform);
+    }
+
+    /// Spawns an entity that draws `mesh` with `material`, placed by `node`.
+    pub fn spawn_object(&mut self, mesh: MeshHandle, node: NodeId, material: Material) -> Entity {
+        self.world.spawn((
+            SceneNode(node),
+            Transform(self.nodes[node.0].world_transform),
+            mesh,
+            material,
+        ))
+    }
+
+    /// Adds `count` point lights in colors around the hue wheel, spread over a ring above what
+    /// has loaded of the scene, which turns about its vertical axis as part of the simulation.
+    /// This is for trying out many lights at once.
+    pub fn add_orbiting_lights(&mut self, count: u32) {
+        if count == 0 {
+            return;
+        }
+        let bounds = self.bounds();
+        let (center, radius) = if bounds.is_empty() {
+            (Vec3::ZERO, 1.0)
+        } else {
+            (bounds.center(), bounds.half_extents().length().max(0.01))
+        };
+        let rest_transform = Mat4::from_translation(center + Vec3::Y * radius * 0.3);
+        let ring = self.add_node(None, rest_transform);
+        self.world
+            .spawn((SceneNode(ring), Spin::new(Vec3::Y, 0.5, rest_transform)));
+
+        // Each light is a golden angle around from the last, so that however many there are,
+        // they spread out evenly rather than lining up.
+        let golden_angle = TAU * (1.0 - 1.0 / GOLDEN_RATIO);
+        let range = radius * 0.75;
+        for i in 0..count {
+            let fraction = (i as f32 + 0.5) / count as f32;
+            let angle = i as f32 * golden_angle;
+            let distance = radius * 1.2 * fraction.sqrt();
+            let offset = Vec3::new(angle.cos() * distance, 0.0, angle.sin() * distance);
+            let node = self.add_node(Some(ring), Mat4::from_translation(offset));
+            self.world.spawn((
+                SceneNode(node),
+                Transform(Mat4::IDENTITY),
+                Light {
+                    kind: LightKind::Point { range },
+                    color: hue(fraction),
+                    intensity: range * range * 0.5,
+                },
+            ));
+        }
+        self.update_transforms();
+    }
+
+    /// The bounds of a drawn entity in world space, or `None` if it has no mesh, its mesh is still
+    /// loading or the entity no longer exists.
+    pub fn aabb(&self, entity: Entity) -> Option<Aabb> {
+        let mut query = self.world.query_one::<(&Transform, &MeshHandle)>(entity);
+        let (transform, mesh) = query.get().ok()?;
+        mesh.aabb(transform)
+    }
+
+    /// The main directional light of the scene, which is the first one found. The scene pipeline
+    /// is lit by every light, but the other passes only by this one, and without it only by
+    /// ambient light.
+    pub fn light(&self) -> Light {
+        self.world
+            .query::<&Light>()
+            .iter()
+            .find(|light| light.direction().is_some())
+            .copied()
+            .unwrap_or(Light {
+                intensity: 0.0,
+                ..Light::default()
+            })
+    }
+
+    /// Advances the simulation by `dt` seconds. It runs at a fixed rate, independently of the
+    /// frame rate; `interpolate` then places things between the last two steps for rendering.
+    pub fn tick(&mut self, dt: f32) {
+        for spin in self.world.query_mut::<&mut Spin>() {
+            spin.previous_angle = spin.angle;
+            spin.angle += spin.speed * dt;
+            // Wrap both, so that the angle doesn't lose precision and interpolation still works.
+            if spin.angle.abs() >= TAU {
+                let turns = (spin.angle / TAU).trunc() * TAU;
+                spin.angle -= turns;
+                spin.previous_angle -= turns;
+            }
+        }
+    }
+
+    /// Updates the local transforms animated by `tick` to the state `alpha` of the way from the
+    /// previous step to the last one.
+    pub fn interpolate(&mut self, alpha: f32) {
+        let transforms: Vec<(NodeId, Mat4)> = self
+            .world
+            .query::<(&SceneNode, &Spin)>()
+            .iter()
+            .map(|(node, spin)| {
+                let angle = spin.previous_angle + (spin.angle - spin.previous_angle) * alpha;
+                let rotation = Mat4::from_axis_angle(spin.axis, angle);
+                (node.0, spin.rest_transform * rotation)
+            })
+            .collect();
+        for (node, transform) in transforms {
+            self.set_local_transform(node, transform);
+        }
+    }
+
+    /// Recomputes the world transforms of dirty nodes and their descendants, and the `Transform`
+    /// of the entities placed by them. This is cheap when nothing moved, so it can run every frame.
+    pub fn update_transforms(&mut self) {
+        if !self.transforms_dirty {
+            return;
+        }
+
+        // Depth-first, carrying whether an ancestor changed so its whole subtree is refreshed.
+        let mut stack: Vec<(NodeId, Mat4, bool)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY, false))
+            .collect();
+        while let Some((id, parent_transform, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id.0];
+            let changed = parent_changed || node.dirty;
+            if changed {
+                node.world_transform = parent_transform * node.local_transform;
+                node.dirty = false;
+            }
+
+            let world_transform = node.world_transform;
+            stack.extend(
+                node.children
+                    .iter()
+                    .map(|&child| (child, world_transform, changed)),
+            );
+        }
+
+        for (node, transform) in self.world.query_mut::<(&SceneNode, &mut Transform)>() {
+            transform.0 = self.nodes[node.0 .0].world_transform;
+        }
+        self.transforms_dirty = false;
+    }
+
+    /// The bounds of every drawn entity in the scene whose mesh has loaded, and of the terrain and
+    /// the water, in world space.
+    pub fn bounds(&self) -> Aabb {
+        let terrain = self.terrain.as_ref().map_or(Aabb::EMPTY, Terrain::bounds);
+        let water = self.water.as_ref().map_or(Aabb::EMPTY, Water::bounds);
+        self.world
+            .query::<(&Transform, &MeshHandle)>()
+            .iter()
+            .filter_map(|(transform, mesh)| mesh.aabb(transform))
+            .fold(terrain.union(&water), |bounds, aabb| bounds.union(&aabb))
+    }
+}
+
+/// The meshes of a glTF document, as assets that every node instancing them shares.
+pub(crate) struct GltfMeshes {
+    /// The materials of the document, followed by the glTF default material if a primitive uses
+    /// it.
+    pub materials: Vec<Material>,
+    /// For each glTF mesh, its triangle primitives along with their materials. Every primitive
+    /// becomes a separate `Mesh`.
+    pub meshes: Vec<Vec<(MeshHandle, Material)>>,
+}
+
+impl GltfMeshes {
+    /// Reads the structure of a glTF file and starts loading the meshes that `assets` doesn't
+    /// already have. Buffers and images are left to the asset loaders.
+    pub fn open(
+        assets: &mut Assets,
+        path: &Path,
+    ) -> Result<(gltf::Document, GltfMeshes), gltf::Error> {
+        let path = asset_path(path);
+        let document = gltf::Gltf::open(&path)?.document;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let mut materials: Vec<_> = document
+            .materials()
+            .map(|material| Material::from_gltf(&material, assets, directory))
+            .collect();
+        // Primitives without a material use the glTF default material, which is only added to
+        // the list of variants if something refers to it.
+        let default_material = Material::default();
+        if document
+            .meshes()
+            .flat_map(|mesh| mesh.primitives())
+            .any(|primitive| primitive.material().index().is_none())
+        {
+            materials.push(default_material.clone());
+        }
+
+        let mut meshes = Vec::new();
+        for mesh in document.meshes() {
+            let mut primitives = Vec::new();
+            for primitive in mesh.primitives().filter(is_drawable) {
+                let handle = assets.mesh(&MeshSource::Gltf {
+                    path: path.clone(),
+                    mesh: mesh.index(),
+                    primitive: primitive.index(),
+                });
+
+                let material = match primitive.material().index() {
+                    Some(index) => materials[index].clone(),
+                    None => default_material.clone(),
+                };
+                primitives.push((MeshHandle(handle), material));
+            }
+            meshes.push(primitives);
+        }
+
+        Ok((document, GltfMeshes { materials, meshes }))
+    }
+}
+
+/// Whether a glTF primitive is turned into a `Mesh`. Only triangle lists with positions are.
+fn is_drawable(primitive: &gltf::Primitive<'_>) -> bool {
+    primitive.mode() == gltf::mesh::Mode::Triangles
+        && primitive.get(&gltf::Semantic::Positions).is_some()
+}
+
+/// Reads and decodes the geometry of every drawable primitive in the glTF file at `path`, which
+/// is slow enough for large files to be done by the asset loaders. Only the first set of texture
+/// coordinates is used.
+pub(crate) fn decode_gltf_meshes(path: &Path) -> Result<Vec<(MeshSource, MeshData)>, gltf::Error> {
+    let (document, buffers, _images) = gltf::import(path)?;
+
+    let mut primitives = Vec::new();
+    for mesh in document.meshes() {
+        for primitive in mesh.primitives().filter(is_drawable) {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions: Vec<[f32; 3]> = positions.collect();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            // Everything after this indexes the vertices with them, so a file that refers past
+            // its vertices is rejected rather than panicking.
+            if indices
+                .iter()
+                .any(|&index| index as usize >= positions.len())
+            {
+                let path = gltf::json::Path::new()
+                    .field("meshes")
+                    .index(mesh.index())
+                    .field("primitives")
+                    .index(primitive.index())
+                    .field("indices");
+                return Err(gltf::Error::Validation(vec![(
+                    path,
+                    gltf::json::validation::Error::IndexOutOfBounds,
+                )]));
+            }
+            let normals: Vec<[f32; 3]> = match reader.read_normals() {
+                Some(normals) => normals.collect(),
+                None => compute_normals(&positions, &indices),
+            };
+            let uvs: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+                Some(uvs) => uvs.into_f32().collect(),
+                None => vec![[0.0; 2]; positions.len()],
+            };
+            let vertices = positions
+                .into_iter()
+                .zip(normals)
+                .zip(uvs)
+                .map(|((position, normal), uv)| MeshVertex {
+                    position,
+                    normal,
+                    uv,
+                })
+                .collect();
+
+            let source = MeshSource::Gltf {
+                path: path.to_owned(),
+                mesh: mesh.index(),
+                primitive: primitive.index(),
+            };
+            primitives.push((source, MeshData::with_generated_lods(vertices, indices)));
+        }
+    }
+
+    Ok(primitives)
+}
+
+/// Computes smooth vertex normals by averaging the normals of the triangles that share a vertex,
+/// for primitives that don't provide their own. `indices` must all be within `positions`.
+fn compute_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i] as usize]));
+        // Not normalized, so that larger triangles contribute more.
+        let normal = (b - a).cross(c - a);
+        for &index in triangle {
+            normals[index as usize] += normal;
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|normal| normal.normalize_or(Vec3::Y).to_array())
+        .collect()
+}
+
+/// The fully saturated color `fraction` of the way around the hue wheel, from red.
+fn hue(fraction: f32) -> Vec3 {
+    let h = fraction * 6.0;
+    Vec3::new(
+        (h - 3.0).abs() - 1.0,
+        2.0 - (h - 2.0).abs(),
+        2.0 - (h - 4.0).abs(),
+    )
+    .clamp(Vec3::ZERO, Vec3::ONE)
+}
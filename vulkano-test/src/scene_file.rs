@@ -0,0 +1,333 @@
+// Saving and loading scenes as RON. A scene file holds the node hierarchy, the entities along with
+// those of their components that can be stored, the material variants and the scene's camera:
+//
+//     (
+//         camera: (eye: (3.0, 2.0, 4.0), target: (0.0, 0.0, 0.0), fov_y: 1.047),
+//         materials: [(name: "red", base_color: (0.8, 0.1, 0.1, 1.0), base_color_texture: "red.png")],
+//         nodes: [(name: "box", transform: (1.0, 0.0, 0.0, 0.0, ...))],
+//         entities: [
+//             (node: 0, mesh: Gltf(path: "box.gltf", mesh: 0, primitive: 0), material: ...),
+//             (light: (kind: Directional(direction: (-0.3, -0.8, -0.5)), color: (1.0, 1.0, 1.0),
+//                 intensity: 1.0)),
+//             (node: 0, light: (kind: Point(range: 5.0), color: (1.0, 0.5, 0.2), intensity: 2.0)),
+//         ],
+//         terrain: (heightmap: "heights.png", size: (256.0, 256.0), height: 30.0),
+//         water: (level: 4.0, size: (256.0, 256.0)),
+//     )
+//
+// Meshes, textures and heightmaps are stored as references to where they were loaded from rather
+// than as data, so the files they came from have to stay around. Relative paths are resolved against the directory
+// of the scene file.
+
+use glam::{Mat4, Vec3, Vec4};
+use hecs::EntityRef;
+use ron::{extensions::Extensions, ser::PrettyConfig, Options};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    assets::{Assets, MeshSource},
+    camera::Camera,
+    components::{Light, MaterialOverride, MeshHandle, SceneNode, Transform},
+    material::Material,
+    scene::{NodeId, Scene},
+    terrain::{Terrain, TerrainDesc},
+    water::Water,
+};
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SceneFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    camera: Option<Camera>,
+    materials: Vec<MaterialFile>,
+    nodes: Vec<NodeFile>,
+    entities: Vec<EntityFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    terrain: Option<TerrainDesc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    water: Option<Water>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodeFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// The index of the parent node, which must come before this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<usize>,
+    transform: Mat4,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialFile {
+    name: String,
+    base_color: Vec4,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base_color_texture: Option<PathBuf>,
+    #[serde(default)]
+    metallic: f32,
+    #[serde(default = "default_roughness")]
+    roughness: f32,
+    #[serde(default)]
+    emissive: Vec3,
+}
+
+/// The roughness of materials saved without one, as `Material::default` has it.
+fn default_roughness() -> f32 {
+    Material::default().roughness
+}
+
+impl MaterialFile {
+    fn new(material: &Material, base: &Path) -> Self {
+        MaterialFile {
+            name: material.name.clone(),
+            base_color: material.base_color,
+            base_color_texture: material
+                .base_color_texture
+                .as_ref()
+                .map(|texture| relative_path(texture.key(), base)),
+            metallic: material.metallic,
+            roughness: material.roughness,
+            emissive: material.emissive,
+        }
+    }
+
+    fn load(self, assets: &mut Assets, base: &Path) -> Material {
+        Material {
+            name: self.name,
+            base_color: self.base_color,
+            base_color_texture: self
+                .base_color_texture
+                .map(|path| assets.texture(&base.join(path))),
+            metallic: self.metallic,
+            roughness: self.roughness,
+            emissive: self.emissive,
+        }
+    }
+}
+
+/// The components of an entity that are saved. All of them are optional.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct EntityFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node: Option<usize>,
+    /// The world transform of an entity without a node.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transform: Option<Mat4>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mesh: Option<MeshSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    material: Option<MaterialFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    material_override: Option<MaterialFile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    light: Option<Light>,
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    /// A node refers to a parent that doesn't come before it, or an entity to a node that doesn't
+    /// exist.
+    InvalidNode(usize),
+    /// The heightmap of the terrain failed to load.
+    Heightmap(PathBuf, image::ImageError),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(err) => write!(f, "{err}"),
+            SceneFileError::Parse(err) => write!(f, "invalid scene file: {err}"),
+            SceneFileError::Serialize(err) => write!(f, "failed to serialize scene: {err}"),
+            SceneFileError::InvalidNode(index) => write!(f, "invalid node reference {index}"),
+            SceneFileError::Heightmap(path, err) => {
+                write!(f, "failed to load {}: {err}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+/// The RON options shared by reading and writing, which leave out the `Some(...)` around optional
+/// fields.
+fn options() -> Options {
+    Options::default().with_default_extension(Extensions::IMPLICIT_SOME)
+}
+
+/// Writes `scene` to `path`. Components other than those in `components` aren't saved.
+pub fn save(scene: &Scene, path: &Path) -> Result<(), SceneFileError> {
+    let base = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    let nodes = scene
+        .nodes()
+        .map(|(_, node)| NodeFile {
+            name: node.name.clone(),
+            parent: node.parent().map(|parent| parent.0),
+            transform: node.local_transform(),
+        })
+        .collect();
+
+    let entities = scene
+        .world
+        .iter()
+        .map(|entity| entity_file(&entity, base))
+        .collect();
+
+    let file = SceneFile {
+        camera: scene.camera,
+        materials: scene
+            .materials
+            .iter()
+            .map(|material| MaterialFile::new(material, base))
+            .collect(),
+        nodes,
+        entities,
+        terrain: scene.terrain.as_ref().map(|terrain| TerrainDesc {
+            heightmap: relative_path(&terrain.desc.heightmap, base),
+            ..terrain.desc.clone()
+        }),
+        water: scene.water,
+    };
+    let source = options()
+        .to_string_pretty(&file, PrettyConfig::default())
+        .map_err(SceneFileError::Serialize)?;
+
+    fs::write(path, source).map_err(SceneFileError::Io)
+}
+
+fn entity_file(entity: &EntityRef<'_>, base: &Path) -> EntityFile {
+    let node = entity.get::<&SceneNode>().map(|node| node.0);
+
+    EntityFile {
+        node: node.map(|node| node.0),
+        transform: entity
+            .get::<&Transform>()
+            .filter(|_| node.is_none())
+            .map(|transform| transform.0),
+        mesh: entity.get::<&MeshHandle>().map(|mesh| match mesh.0.key() {
+            MeshSource::Gltf {
+                path,
+                mesh,
+                primitive,
+            } => MeshSource::Gltf {
+                path: relative_path(path, base),
+                mesh: *mesh,
+                primitive: *primitive,
+            },
+            source => source.clone(),
+        }),
+        material: entity
+            .get::<&Material>()
+            .map(|material| MaterialFile::new(&material, base)),
+        material_override: entity
+            .get::<&MaterialOverride>()
+            .map(|material_override| MaterialFile::new(&material_override.0, base)),
+        light: entity.get::<&Light>().map(|light| *light),
+    }
+}
+
+/// `path` relative to `base` if it is inside of it, otherwise as an absolute path.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let Ok(path) = path.canonicalize() else {
+        return path.to_owned();
+    };
+    base.canonicalize()
+        .ok()
+        .and_then(|base| path.strip_prefix(base).ok().map(Path::to_owned))
+        .unwrap_or(path)
+}
+
+/// Reads a scene from `path`, starting to load the meshes and textures it refers to into `assets`.
+/// The terrain is loaded right away, which waits for its heightmap to be read and uploaded.
+pub fn load(assets: &mut Assets, path: &Path) -> Result<Scene, SceneFileError> {
+    let source = fs::read_to_string(path).map_err(SceneFileError::Io)?;
+    let file: SceneFile = options().from_str(&source).map_err(SceneFileError::Parse)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut scene = Scene::default();
+    scene.camera = file.camera;
+    scene.materials = file
+        .materials
+        .into_iter()
+        .map(|material| material.load(assets, base))
+        .collect();
+
+    for (i, node) in file.nodes.iter().enumerate() {
+        let parent = match node.parent {
+            Some(parent) if parent < i => Some(NodeId(parent)),
+            Some(parent) => return Err(SceneFileError::InvalidNode(parent)),
+            None => None,
+        };
+        let id = scene.add_node(parent, node.transform);
+        scene.node_mut(id).name = node.name.clone();
+    }
+
+    for entity in file.entities {
+        let mut builder = hecs::EntityBuilder::new();
+
+        if let Some(node) = entity.node {
+            if node >= file.nodes.len() {
+                return Err(SceneFileError::InvalidNode(node));
+            }
+            builder.add(SceneNode(NodeId(node)));
+        }
+        if let Some(transform) = entity.transform {
+            builder.add(Transform(transform));
+        } else if entity.node.is_some() {
+            // Filled in by `update_transforms`.
+            builder.add(Transform(Mat4::IDENTITY));
+        }
+
+        if let Some(mut source) = entity.mesh {
+            if let MeshSource::Gltf { path, .. } = &mut source {
+                *path = base.join(&*path);
+            }
+            builder.add(MeshHandle(assets.mesh(&source)));
+        }
+
+        if let Some(material) = entity.material {
+            builder.add(material.load(assets, base));
+        }
+        if let Some(material) = entity.material_override {
+            builder.add(MaterialOverride(material.load(assets, base)));
+        }
+        if let Some(light) = entity.light {
+            builder.add(light);
+        }
+
+        scene.world.spawn(builder.build());
+    }
+
+    if let Some(mut terrain) = file.terrain {
+        terrain.heightmap = base.join(&terrain.heightmap);
+        let path = terrain.heightmap.clone();
+        scene.terrain = Some(
+            Terrain::load(
+                assets.memory_allocator.clone(),
+                assets.command_buffer_allocator.clone(),
+                &assets.queue,
+                terrain,
+            )
+            .map_err(|err| SceneFileError::Heightmap(path, err))?,
+        );
+    }
+
+    scene.water = file.water;
+    scene.update_transforms();
+
+    Ok(scene)
+}
@@ -0,0 +1,944 @@
+// Draws the entities of a `Scene` with simple directional lighting. The camera matrix and the light
+// are shared by every draw through a uniform buffer, while each entity's transform is a push
+// constant and its base color texture a descriptor set of its own. Where descriptor indexing is
+// supported, the textures are bindless instead: the array of all of them is bound once, and each
+// draw pushes the index of its own. Entities whose mesh is still loading are left out.
+//
+// With mesh shading, meshes are drawn from their meshlets by the task and mesh shaders of
+// `shaders/scene.wgsl`, which read the meshlets and vertices from a descriptor set of each draw's
+// own, in place of the vertex shader and the vertex and index buffers. The fragment shader is the
+// same either way.
+//
+// With ray-query shadows, the frame's descriptor set also holds the TLAS that the fragment shader
+// traces shadow rays through, and lit draws use the variants of the fragment shader that `glslc`
+// compiled with the ray queries in. See `shadows.rs`.
+//
+// With GPU culling, `draw_culled` draws what `GpuCuller` culled instead, with one indirect draw per
+// level of detail of each batch of entities with the same mesh and texture. Those draws use a
+// variant of the vertex shader that reads the transform and base color of each instance from the
+// culled instances in the frame's descriptor set, rather than from the push constants.
+//
+// Besides the main directional light, lit draws add up the other directional lights and the point
+// and spot lights of the cluster that each fragment is in, which the frame's descriptor set holds
+// as `LightCuller` uploaded and binned them. Draws bound without any have none.
+
+use glam::{Mat4, Vec3, Vec4};
+use hecs::Entity;
+use std::{cell::Cell, sync::Arc};
+use tracing::warn;
+use vulkano::{
+    acceleration_structure::AccelerationStructure,
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        BufferContents, BufferUsage, Subbuffer,
+    },
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::{Device, DeviceFeatures, DeviceOwned},
+    image::sampler::{
+        Filter, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode, LOD_CLAMP_NONE,
+    },
+    memory::allocator::{MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendAttachmentState,
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::{CullMode, PolygonMode, RasterizationState},
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        DynamicState, GraphicsPipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+    shader::SpecializationConstant,
+    Validated,
+};
+
+use crate::{
+    bindless::{self, BindlessTextures},
+    bounds::Frustum,
+    components::{Light, MaterialOverride, MeshHandle, Transform},
+    debug_utils,
+    device_requirements::{Capabilities, DeviceRequirements},
+    error::AppError,
+    gamma::Output,
+    gpu_culling::{self, CullInstance, CulledDraws},
+    light_clusters::{self, LightClusters},
+    lod,
+    material::Material,
+    mesh::{Mesh, MeshVertex},
+    meshlet,
+    occlusion::OcclusionCuller,
+    offscreen,
+    scene::Scene,
+    shader::{self, ShaderSource, ShaderStage},
+    texture::{self, Texture},
+    variants::ShaderVariants,
+};
+
+/// The `Frame` block of `shaders/include/frame.glsl`.
+#[derive(BufferContents)]
+#[repr(C)]
+pub(crate) struct FrameUniforms {
+    view_proj: [[f32; 4]; 4],
+    /// The direction towards the light.
+    light_direction: [f32; 4],
+    /// The color of the light premultiplied by its intensity.
+    light_color: [f32; 4],
+    /// How colors are written out, as `Output::to_uniform` has it.
+    color_output: [u32; 4],
+    /// The position of the eye, which highlights are seen from.
+    eye: [f32; 4],
+}
+
+impl FrameUniforms {
+    pub(crate) fn new(view_proj: Mat4, light: &Light) -> Self {
+        FrameUniforms {
+            view_proj: view_proj.to_cols_array_2d(),
+            light_direction: (-light.direction().unwrap_or_default().normalize_or_zero())
+                .extend(0.0)
+                .to_array(),
+            light_color: (light.color * light.intensity).extend(1.0).to_array(),
+            color_output: Output::default().to_uniform(),
+            eye: eye(view_proj).extend(1.0).to_array(),
+        }
+    }
+
+    /// The same uniforms, writing colors out as `output` asks.
+    pub(crate) fn with_output(self, output: Output) -> Self {
+        FrameUniforms {
+            color_output: output.to_uniform(),
+            ..self
+        }
+    }
+}
+
+/// The point that `view_proj`, a perspective projection, takes to the plane at infinity, which
+/// is where the eye is.
+fn eye(view_proj: Mat4) -> Vec3 {
+    let eye = view_proj.inverse() * Vec4::Z;
+    if eye.w == 0.0 {
+        return Vec3::ZERO;
+    }
+    eye.truncate() / eye.w
+}
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    model: [[f32; 4]; 4],
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    /// The slot of the base color texture in the bindless array, if there is one.
+    texture_index: u32,
+}
+
+/// What a draw pushes of the material it is drawn with.
+#[derive(Clone, Copy, Debug)]
+struct Shading {
+    base_color: Vec4,
+    emissive: Vec3,
+    metallic: f32,
+    roughness: f32,
+}
+
+impl Shading {
+    fn of(material: &Material) -> Self {
+        Shading {
+            base_color: material.base_color,
+            emissive: material.emissive,
+            metallic: material.metallic,
+            roughness: material.roughness,
+        }
+    }
+
+    /// A default material of the color `base_color`.
+    fn color(base_color: Vec4) -> Self {
+        Shading {
+            base_color,
+            ..Self::of(&Material::default())
+        }
+    }
+}
+
+/// The optional features of the scene shaders, each combination of which is a pipeline variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SceneFeatures {
+    /// Rasterizing edges only, in their base color, as lighting would make those facing away
+    /// from the light hard to see.
+    pub wireframe: bool,
+    /// Sampling the base color texture, which materials without one can do without.
+    pub textured: bool,
+    /// Tracing shadow rays, with the fragment shader that `glslc` compiled.
+    pub shadowed: bool,
+    /// Reading the transform and base color of the instance that `GpuCuller` culled.
+    pub gpu_culled: bool,
+}
+
+/// How many entities a `ScenePipeline::draw` call submitted, and how much geometry they had.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    pub drawn: usize,
+    /// Entities skipped because they lie entirely outside the camera frustum.
+    pub culled: usize,
+    /// Entities skipped because they were hidden behind others in the last frame.
+    pub occluded: usize,
+    /// The triangles of the drawn meshes, at the level of detail they were drawn with.
+    pub triangles: usize,
+    /// The vertices that were transformed, which for meshlets counts the vertices that several
+    /// share once for each.
+    pub vertices: usize,
+    /// The meshlets that were submitted, before the task shader culls them, with mesh shading.
+    pub meshlets: usize,
+    /// Whether the entities were culled on the GPU, in which case the CPU doesn't learn which of
+    /// them were culled or occluded, nor what geometry was drawn, and `drawn` counts all of
+    /// them.
+    pub gpu_culled: bool,
+}
+
+pub struct ScenePipeline {
+    /// The pipelines of the combinations of features drawn with so far.
+    variants: ShaderVariants<SceneFeatures>,
+    layout: Arc<PipelineLayout>,
+    supports_wireframe: bool,
+    /// Whether meshes are drawn from their meshlets with task and mesh shaders.
+    mesh_shading: bool,
+    /// The variant that draws are currently recorded with, if any has been bound since `bind`.
+    bound: Cell<Option<SceneFeatures>>,
+    wireframe: bool,
+    lod_debug: bool,
+    output: Output,
+    /// Whether the fragment shader with ray-query shadows was compiled.
+    supports_shadows: bool,
+    /// The TLAS that the draws after the next `bind` trace shadows through, if any.
+    shadow_tlas: Option<Arc<AccelerationStructure>>,
+    /// Whether the vertex shader that reads culled instances was compiled.
+    supports_gpu_culling: bool,
+    /// The lights besides the main one that the draws after the next `bind` are lit by, if any.
+    light_clusters: Option<LightClusters>,
+    /// Bound in their place otherwise.
+    no_lights: LightClusters,
+    uniform_buffer_allocator: SubbufferAllocator,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    sampler: Arc<Sampler>,
+    /// Bound for materials without a texture.
+    white_texture: Arc<Texture>,
+    /// Every texture drawn with so far, where textures are bindless.
+    bindless: Option<BindlessTextures>,
+}
+
+/// Textures seen at grazing angles are sampled with up to this much anisotropy where supported.
+const MAX_ANISOTROPY: f32 = 16.0;
+
+impl ScenePipeline {
+    /// Asks for the features the pipelines use where supported.
+    pub fn register_requirements(requirements: &mut DeviceRequirements) {
+        requirements.request_features(DeviceFeatures {
+            fill_mode_non_solid: true,
+            sampler_anisotropy: true,
+            ..DeviceFeatures::empty()
+        });
+        bindless::register_requirements(requirements);
+        gpu_culling::register_requirements(requirements);
+        texture::register_requirements(requirements);
+    }
+
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        white_texture: Arc<Texture>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+        let capabilities = Capabilities::of(&device);
+
+        let max_anisotropy = device.physical_device().properties().max_sampler_anisotropy;
+        let sampler = Sampler::new(
+            device.clone(),
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                // Streamed textures have mip levels, between which the sampler blends.
+                mipmap_mode: SamplerMipmapMode::Linear,
+                lod: 0.0..=LOD_CLAMP_NONE,
+                address_mode: [SamplerAddressMode::Repeat; 3],
+                anisotropy: capabilities
+                    .sampler_anisotropy
+                    .then_some(max_anisotropy.min(MAX_ANISOTROPY)),
+                ..Default::default()
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        let bindless = capabilities
+            .bindless
+            .then(|| BindlessTextures::new(device.clone(), sampler.clone(), white_texture.clone()))
+            .transpose()?;
+
+        let mesh_shading = capabilities.mesh_shading;
+        let (geometry_stages, vertex_input_state) = if mesh_shading {
+            (mesh_shading_stages(device.clone())?, None)
+        } else {
+            let vs = shader::load(
+                device.clone(),
+                include_str!("shaders/scene.vert"),
+                ShaderStage::Vertex,
+            )?
+            .entry_point("main")
+            .unwrap();
+            let vertex_input_state = MeshVertex::per_vertex().definition(&vs).unwrap();
+            (
+                vec![PipelineShaderStageCreateInfo::new(vs)],
+                Some(vertex_input_state),
+            )
+        };
+        // Meshlets are culled by the task shader instead.
+        let culled_vs_stage = (capabilities.gpu_culling && !mesh_shading)
+            .then(|| {
+                shader::load_with_defines(
+                    device.clone(),
+                    include_str!("shaders/scene.vert"),
+                    ShaderStage::Vertex,
+                    &[("GPU_CULLED", "1".to_string())],
+                )
+            })
+            .transpose()?
+            .map(|vs| PipelineShaderStageCreateInfo::new(vs.entry_point("main").unwrap()));
+        let mut defines = vec![light_clusters::defines()];
+        if bindless.is_some() {
+            defines.push(("BINDLESS", "1".to_string()));
+        }
+        let fs_source = ShaderSource::parse(
+            include_str!("shaders/scene.frag"),
+            ShaderStage::Fragment,
+            &defines,
+        )?;
+        // Without glslc, the scene is drawn without shadows.
+        let shadowed_fs = capabilities
+            .ray_query_shadows
+            .then(|| {
+                let defines: Vec<_> = defines
+                    .iter()
+                    .cloned()
+                    .chain([("RAY_QUERY_SHADOWS", "1".to_string())])
+                    .collect();
+                shader::load_with_glslc(
+                    device.clone(),
+                    include_str!("shaders/scene.frag"),
+                    ShaderStage::Fragment,
+                    &defines,
+                )
+                .inspect_err(|err| warn!("Ray-query shadows are unavailable: {err}"))
+                .ok()
+            })
+            .flatten();
+        // The shadowed fragment shader uses all that the other does, and the TLAS as well.
+        let fs = match &shadowed_fs {
+            Some(shadowed_fs) => shadowed_fs.entry_point("main").unwrap(),
+            None => fs_source
+                .specialize(device.clone(), &[])?
+                .entry_point("main")
+                .unwrap(),
+        };
+
+        let stages: Vec<_> = geometry_stages
+            .iter()
+            .cloned()
+            .chain(culled_vs_stage.clone())
+            .chain([PipelineShaderStageCreateInfo::new(fs)])
+            .collect();
+        // The bindless set takes the place of the per-draw one.
+        let set_layouts: Vec<_> = bindless
+            .iter()
+            .map(|bindless| (1, bindless.layout().clone()))
+            .collect();
+        let layout = shader::reflect_layout_with_sets::<PushConstants>(
+            device.clone(),
+            &stages,
+            &set_layouts,
+        )?;
+
+        // Each variant has its own fragment shader, with the features as its specialization
+        // constants, and shares the geometry stages and the layout. Culled variants have their
+        // own vertex shader, which takes the same vertices.
+        let supports_shadows = shadowed_fs.is_some();
+        let supports_gpu_culling = culled_vs_stage.is_some();
+        let variants = ShaderVariants::new({
+            let device = device.clone();
+            let layout = layout.clone();
+            move |features: SceneFeatures| {
+                let fs = match shadowed_fs.as_ref().filter(|_| features.shadowed) {
+                    Some(shadowed_fs) => shadowed_fs
+                        .specialize(
+                            [
+                                (0, SpecializationConstant::Bool(!features.wireframe)),
+                                (1, SpecializationConstant::Bool(features.textured)),
+                            ]
+                            .into_iter()
+                            .collect(),
+                        )
+                        .map_err(|err| AppError::Pipeline(Validated::from(err)))?
+                        .entry_point("main")
+                        .unwrap(),
+                    None => fs_source
+                        .specialize(
+                            device.clone(),
+                            &[
+                                (0, f64::from(u8::from(!features.wireframe))),
+                                (1, f64::from(u8::from(features.textured))),
+                            ],
+                        )?
+                        .entry_point("main")
+                        .unwrap(),
+                };
+                let geometry_stages = match culled_vs_stage.as_ref().filter(|_| features.gpu_culled)
+                {
+                    Some(culled_vs_stage) => std::slice::from_ref(culled_vs_stage),
+                    None => &geometry_stages[..],
+                };
+                let polygon_mode = if features.wireframe {
+                    PolygonMode::Line
+                } else {
+                    PolygonMode::Fill
+                };
+
+                GraphicsPipeline::new(
+                    device.clone(),
+                    None,
+                    GraphicsPipelineCreateInfo {
+                        stages: geometry_stages
+                            .iter()
+                            .cloned()
+                            .chain([PipelineShaderStageCreateInfo::new(fs)])
+                            .collect(),
+                        // Mesh shaders assemble their own primitives.
+                        vertex_input_state: vertex_input_state.clone(),
+                        input_assembly_state: (!mesh_shading).then(InputAssemblyState::default),
+                        viewport_state: Some(ViewportState::default()),
+                        rasterization_state: Some(RasterizationState {
+                            polygon_mode,
+                            cull_mode: CullMode::Back,
+                            ..Default::default()
+                        }),
+                        depth_stencil_state: Some(DepthStencilState {
+                            depth: Some(DepthState::simple()),
+                            ..Default::default()
+                        }),
+                        multisample_state: Some(MultisampleState::default()),
+                        color_blend_state: Some(offscreen::color_blend_state(
+                            &subpass,
+                            &[ColorBlendAttachmentState::default()],
+                        )),
+                        dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                        subpass: Some(subpass.clone().into()),
+                        ..GraphicsPipelineCreateInfo::layout(layout.clone())
+                    },
+                )
+                .map_err(AppError::Pipeline)
+                .inspect(|pipeline| debug_utils::name(pipeline, &format!("scene {features:?}")))
+            }
+        });
+        // The variant that most draws use is created up front, so that errors show at startup.
+        variants.get(SceneFeatures {
+            wireframe: false,
+            textured: true,
+            shadowed: supports_shadows,
+            gpu_culled: false,
+        })?;
+
+        let no_lights = LightClusters::empty(memory_allocator.clone());
+        let uniform_buffer_allocator = SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::UNIFORM_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+
+        Ok(ScenePipeline {
+            variants,
+            layout,
+            supports_wireframe: capabilities.wireframe,
+            mesh_shading,
+            bound: Cell::new(None),
+            wireframe: false,
+            lod_debug: false,
+            output: Output::default(),
+            supports_shadows,
+            shadow_tlas: None,
+            supports_gpu_culling,
+            light_clusters: None,
+            no_lights,
+            uniform_buffer_allocator,
+            descriptor_set_allocator,
+            sampler,
+            white_texture,
+            bindless,
+        })
+    }
+
+    /// Whether the device supports drawing in wireframe.
+    pub fn supports_wireframe(&self) -> bool {
+        self.supports_wireframe
+    }
+
+    /// Whether meshes are drawn from their meshlets with task and mesh shaders, rather than from
+    /// their vertex and index buffers.
+    pub fn mesh_shading(&self) -> bool {
+        self.mesh_shading
+    }
+
+    /// Whether shadows can be traced with ray queries, which takes a device that supports them and
+    /// `glslc` to compile the shader.
+    pub fn supports_shadows(&self) -> bool {
+        self.supports_shadows
+    }
+
+    /// Whether what `GpuCuller` culled can be drawn with `draw_culled`, which takes a device with
+    /// `gpu_culling::FEATURES` and the vertex pipeline rather than mesh shading.
+    pub fn supports_gpu_culling(&self) -> bool {
+        self.supports_gpu_culling
+    }
+
+    /// Sets the TLAS that the draws after the next `bind` trace shadows through, or `None` to
+    /// draw without shadows. Ignored if shadows are unsupported.
+    pub fn set_shadow_tlas(&mut self, tlas: Option<Arc<AccelerationStructure>>) {
+        self.shadow_tlas = tlas.filter(|_| self.supports_shadows);
+    }
+
+    /// Sets the lights besides the main one that the draws after the next `bind` are lit by, or
+    /// `None` to light them by the main directional light alone. The clusters must have been
+    /// binned for the camera and viewport that the draws use.
+    pub fn set_light_clusters(&mut self, light_clusters: Option<LightClusters>) {
+        self.light_clusters = light_clusters;
+    }
+
+    /// Switches between filled and wireframe rendering. Wireframe is ignored if unsupported.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        self.wireframe = wireframe;
+    }
+
+    /// Switches to coloring entities by the level of detail they are drawn with, instead of their
+    /// material.
+    pub fn set_lod_debug(&mut self, lod_debug: bool) {
+        self.lod_debug = lod_debug;
+    }
+
+    /// Writes colors out as `output` asks.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    /// Records a draw of every loaded entity in `scene` that is inside the camera frustum into the
+    /// current subpass, leaving out those that `occlusion` found to be hidden. Each entity is
+    /// drawn at the level of detail that matches its size on screen.
+    pub fn draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        scene: &Scene,
+        view_proj: Mat4,
+        viewport: Viewport,
+        occlusion: Option<&OcclusionCuller>,
+    ) -> DrawStats {
+        let frustum = Frustum::from_view_proj(view_proj);
+        let light = scene.light();
+        let mut stats = DrawStats::default();
+
+        let mut query = scene.world.query::<(
+            Entity,
+            &Transform,
+            &MeshHandle,
+            &Material,
+            Option<&MaterialOverride>,
+        )>();
+        for (entity, transform, mesh, material, material_override) in query.iter() {
+            let Some(mesh) = mesh.0.get() else {
+                continue;
+            };
+            let aabb = mesh.aabb.transformed(transform.0);
+            if !frustum.intersects(&aabb) {
+                stats.culled += 1;
+                continue;
+            }
+            if occlusion.is_some_and(|occlusion| occlusion.is_occluded(entity)) {
+                stats.occluded += 1;
+                continue;
+            }
+
+            if stats.drawn == 0 {
+                self.bind(builder, view_proj, &light, viewport.clone());
+            }
+            let (level, mesh) = mesh.lod(lod::screen_size(&aabb, view_proj));
+            stats.triangles += mesh.index_buffer.len() as usize / 3;
+            match mesh.meshlets.as_ref().filter(|_| self.mesh_shading) {
+                Some(meshlets) => {
+                    stats.vertices += meshlets.vertex_count() as usize;
+                    stats.meshlets += meshlets.len() as usize;
+                }
+                None => stats.vertices += mesh.vertex_buffer.len() as usize,
+            }
+            let material = material_override.map_or(material, |o| &o.0);
+            if self.lod_debug {
+                let base_color = lod::DEBUG_COLORS[level.min(lod::DEBUG_COLORS.len() - 1)];
+                self.draw_mesh(builder, mesh, transform.0, Shading::color(base_color), None);
+            } else {
+                let texture = self.texture(material);
+                self.draw_mesh(
+                    builder,
+                    mesh,
+                    transform.0,
+                    Shading::of(material),
+                    texture.as_ref(),
+                );
+            }
+            stats.drawn += 1;
+        }
+
+        stats
+    }
+
+    /// Records the draws that `GpuCuller` left in `culled` into the current subpass, seen through
+    /// `view_proj`, which must be what they were culled with. This must only be called if
+    /// `supports_gpu_culling`.
+    pub fn draw_culled<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        culled: &CulledDraws,
+        scene: &Scene,
+        view_proj: Mat4,
+        viewport: Viewport,
+    ) -> DrawStats {
+        self.bind_frame(
+            builder,
+            view_proj,
+            &scene.light(),
+            viewport,
+            Some(culled.instances.clone()),
+        );
+        let mut stats = DrawStats {
+            gpu_culled: true,
+            ..Default::default()
+        };
+
+        for batch in &culled.batches {
+            // The transform and material are the instances' own.
+            self.bind_draw(
+                builder,
+                true,
+                Mat4::IDENTITY,
+                Shading::color(Vec4::ONE),
+                batch.texture.as_ref(),
+            );
+            stats.drawn += batch.instance_count as usize;
+
+            let levels = [&batch.mesh]
+                .into_iter()
+                .chain(batch.mesh.lods.iter().map(|lod| &lod.mesh));
+            for (level, mesh) in levels.enumerate() {
+                let first_command =
+                    u64::from(batch.first_command + level as u32 * batch.instance_count);
+                let commands = culled
+                    .commands
+                    .clone()
+                    .slice(first_command..first_command + u64::from(batch.instance_count));
+                builder
+                    .bind_vertex_buffers(0, mesh.vertex_buffer.clone())
+                    .unwrap()
+                    .bind_index_buffer(mesh.index_buffer.clone())
+                    .unwrap();
+
+                // SAFETY: every draw that the culling shaders wrote into the region of this level
+                // draws all of its index buffer, which only refers to vertices of the bound vertex
+                // buffer, and starts at an instance of the bound instance buffer, whose level of
+                // detail this is. Compacted draws are counted, up to the draws of the region.
+                unsafe {
+                    match &culled.counts {
+                        Some(counts) => builder.draw_indexed_indirect_count(
+                            commands,
+                            counts
+                                .clone()
+                                .index(u64::from(batch.first_region) + level as u64),
+                            batch.instance_count,
+                        ),
+                        None => builder.draw_indexed_indirect(commands),
+                    }
+                }
+                .unwrap();
+            }
+        }
+
+        stats
+    }
+
+    /// Binds the camera, light and viewport used by subsequent `draw_object` calls, each of which
+    /// binds the pipeline variant it needs.
+    pub fn bind<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        view_proj: Mat4,
+        light: &Light,
+        viewport: Viewport,
+    ) {
+        self.bind_frame(builder, view_proj, light, viewport, None);
+    }
+
+    /// Like `bind`, with the instances that culled draws read from, if any.
+    fn bind_frame<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        view_proj: Mat4,
+        light: &Light,
+        viewport: Viewport,
+        culled_instances: Option<Subbuffer<[CullInstance]>>,
+    ) {
+        let uniform_buffer = self.uniform_buffer_allocator.allocate_sized().unwrap();
+        *uniform_buffer.write().unwrap() =
+            FrameUniforms::new(view_proj, light).with_output(self.output);
+
+        let layout = &self.layout.set_layouts()[0];
+        let light_clusters = self.light_clusters.as_ref().unwrap_or(&self.no_lights);
+        let writes = [
+            WriteDescriptorSet::buffer(0, uniform_buffer),
+            WriteDescriptorSet::buffer(3, light_clusters.grid.clone()),
+            WriteDescriptorSet::buffer(4, light_clusters.lights.clone()),
+            WriteDescriptorSet::buffer(5, light_clusters.clusters.clone()),
+        ]
+        .into_iter()
+        .chain(
+            self.shadow_tlas
+                .iter()
+                .map(|tlas| WriteDescriptorSet::acceleration_structure(1, tlas.clone())),
+        )
+        .chain(culled_instances.map(|instances| WriteDescriptorSet::buffer(2, instances)));
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.clone(),
+            writes,
+            [],
+        )
+        .unwrap();
+
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.layout.clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap();
+        if let Some(bindless) = &self.bindless {
+            bindless.release_dropped();
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.layout.clone(),
+                    1,
+                    bindless.set().clone(),
+                )
+                .unwrap();
+        }
+        self.bound.set(None);
+    }
+
+    /// Records a draw of `mesh` placed with `transform` at full detail with `material`, unless
+    /// the mesh is still loading. `bind` must have been called first.
+    pub fn draw_object<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        transform: &Transform,
+        mesh: &MeshHandle,
+        material: &Material,
+    ) {
+        if let Some(mesh) = mesh.0.get() {
+            let texture = self.texture(material);
+            self.draw_mesh(
+                builder,
+                &mesh,
+                transform.0,
+                Shading::of(material),
+                texture.as_ref(),
+            );
+        }
+    }
+
+    /// The texture to draw `material` with, if it has one that has loaded.
+    fn texture(&self, material: &Material) -> Option<Arc<Texture>> {
+        material
+            .base_color_texture
+            .as_ref()
+            .and_then(|texture| texture.get())
+    }
+
+    /// Draws `mesh` with the variant for `texture` and the current polygon mode.
+    fn draw_mesh<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        mesh: &Mesh,
+        transform: Mat4,
+        shading: Shading,
+        texture: Option<&Arc<Texture>>,
+    ) {
+        self.bind_draw(builder, false, transform, shading, texture);
+
+        if self.mesh_shading {
+            // Meshes too small for a meshlet have nothing to draw.
+            let Some(meshlets) = &mesh.meshlets else {
+                return;
+            };
+            let layout = &self.layout.set_layouts()[2];
+            let descriptor_set = DescriptorSet::new(
+                self.descriptor_set_allocator.clone(),
+                layout.clone(),
+                [
+                    WriteDescriptorSet::buffer(0, meshlets.meshlets.clone()),
+                    WriteDescriptorSet::buffer(1, meshlets.vertices.clone()),
+                    WriteDescriptorSet::buffer(2, meshlets.triangles.clone()),
+                    WriteDescriptorSet::buffer(3, mesh.vertex_buffer.clone()),
+                ],
+                [],
+            )
+            .unwrap();
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.layout.clone(),
+                    2,
+                    descriptor_set,
+                )
+                .unwrap();
+
+            let group_count = meshlets.len().div_ceil(meshlet::MESHLETS_PER_TASK);
+            // SAFETY: the task shader only reads the meshlets below the length of their buffer,
+            // each of which only refers to the vertices and triangles that `meshlet::build` wrote
+            // for it, and to vertices of the mesh. The other resources are as for `draw_indexed`.
+            unsafe { builder.draw_mesh_tasks([group_count, 1, 1]) }.unwrap();
+        } else {
+            builder
+                .bind_vertex_buffers(0, mesh.vertex_buffer.clone())
+                .unwrap()
+                .bind_index_buffer(mesh.index_buffer.clone())
+                .unwrap();
+
+            // SAFETY: the index buffer only refers to vertices of the bound vertex buffer, and the
+            // shaders only access the bound uniform buffer and texture, which for bindless textures
+            // is in a slot that has been written.
+            unsafe { builder.draw_indexed(mesh.index_buffer.len() as u32, 1, 0, 0, 0) }.unwrap();
+        }
+    }
+
+    /// Binds the variant for `texture`, the current polygon mode and whether the draw is of
+    /// culled instances, unless the previous draw used it too, along with the texture, and pushes
+    /// the constants of the draw.
+    fn bind_draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        gpu_culled: bool,
+        transform: Mat4,
+        shading: Shading,
+        texture: Option<&Arc<Texture>>,
+    ) {
+        let wireframe = self.wireframe && self.supports_wireframe;
+        let features = SceneFeatures {
+            wireframe,
+            textured: texture.is_some(),
+            // Wireframes aren't lit, so they have nothing to shadow.
+            shadowed: self.shadow_tlas.is_some() && !wireframe,
+            gpu_culled,
+        };
+        if self.bound.get() != Some(features) {
+            builder
+                .bind_pipeline_graphics(self.variants.get(features).unwrap())
+                .unwrap();
+            self.bound.set(Some(features));
+        }
+
+        // Untextured variants don't sample the texture, but the set is still bound for the
+        // shared layout.
+        let texture = texture.unwrap_or(&self.white_texture);
+        let texture_index = match &self.bindless {
+            Some(bindless) => bindless.slot(texture),
+            None => {
+                let layout = &self.layout.set_layouts()[1];
+                let descriptor_set = DescriptorSet::new(
+                    self.descriptor_set_allocator.clone(),
+                    layout.clone(),
+                    [
+                        WriteDescriptorSet::image_view(0, texture.view.clone()),
+                        WriteDescriptorSet::sampler(1, self.sampler.clone()),
+                    ],
+                    [],
+                )
+                .unwrap();
+                builder
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        self.layout.clone(),
+                        1,
+                        descriptor_set,
+                    )
+                    .unwrap();
+                0
+            }
+        };
+
+        builder
+            .push_constants(
+                self.layout.clone(),
+                0,
+                PushConstants {
+                    model: transform.to_cols_array_2d(),
+                    base_color: shading.base_color.to_array(),
+                    emissive: shading.emissive.extend(0.0).to_array(),
+                    metallic: shading.metallic,
+                    roughness: shading.roughness,
+                    texture_index,
+                },
+            )
+            .unwrap();
+    }
+}
+
+/// The task and mesh shader stages of `shaders/scene.wgsl`.
+#[cfg(feature = "wgsl")]
+fn mesh_shading_stages(
+    device: Arc<Device>,
+) -> Result<Vec<PipelineShaderStageCreateInfo>, AppError> {
+    [
+        (ShaderStage::Task, "task_main"),
+        (ShaderStage::Mesh, "mesh_main"),
+    ]
+    .into_iter()
+    .map(|(stage, entry_point)| {
+        let module = shader::load_wgsl(
+            device.clone(),
+            include_str!("shaders/scene.wgsl"),
+            stage,
+            entry_point,
+        )?;
+        Ok(PipelineShaderStageCreateInfo::new(
+            module.entry_point("main").unwrap(),
+        ))
+    })
+    .collect()
+}
+
+/// Mesh shading is only ever enabled with the `wgsl` feature, which its shaders are written in.
+#[cfg(not(feature = "wgsl"))]
+fn mesh_shading_stages(
+    _device: Arc<Device>,
+) -> Result<Vec<PipelineShaderStageCreateInfo>, AppError> {
+    Err(AppError::Shader(
+        "mesh shading needs the wgsl feature".to_string(),
+    ))
+}
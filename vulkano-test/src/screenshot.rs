@@ -0,0 +1,246 @@
+// Screenshots of the window, saved as PNG. A requested screenshot is blitted from the swapchain
+// image at the end of the next frame, into an RGBA image of the same size, and copied into a
+// buffer that is read back once the GPU is done with the frame. The PNG is then encoded on a
+// thread of its own, so that the frames after it don't wait for it.
+//
+// Screenshots are opaque, whether or not the window is transparent, and show whatever the frame
+// showed, including the debug-draw overlay.
+//
+// F12 saves a screenshot, and Ctrl+F12 a supersampled one. Supersampled screenshots are of an image
+// that the app renders for them alone, several times as wide and high as the screenshot, of the
+// rasterized main view without its water, fog, blur or overlay. Once it is read back, every square
+// of as many pixels across is averaged into one on the encoding thread, in linear light, for edges
+// smoother than any multisampling the window has.
+
+use std::{path::PathBuf, sync::Arc, thread};
+use tracing::{error, info, warn};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, BlitImageInfo, CopyImageToBufferInfo},
+    device::DeviceOwned,
+    format::{Format, FormatFeatures, NumericFormat},
+    image::{sampler::Filter, Image, ImageCreateInfo, ImageType, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+use crate::gamma;
+
+pub struct ScreenshotCapture {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    /// Where the screenshot of the next frame is saved, if one was requested.
+    requested: Option<PathBuf>,
+    /// Where a supersampled screenshot is saved, and how many times its size it is rendered at,
+    /// if one was requested.
+    supersampled: Option<(PathBuf, u32)>,
+    /// Screenshots that were copied into their buffers, waiting for the GPU.
+    pending: Vec<Pending>,
+}
+
+struct Pending {
+    path: PathBuf,
+    extent: [u32; 2],
+    /// How many pixels across are averaged into each pixel of the screenshot.
+    scale: u32,
+    buffer: Subbuffer<[u8]>,
+}
+
+impl ScreenshotCapture {
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>) -> Self {
+        ScreenshotCapture {
+            memory_allocator,
+            requested: None,
+            supersampled: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Saves the next frame to `path`.
+    pub fn request(&mut self, path: PathBuf) {
+        self.requested = Some(path);
+    }
+
+    /// Saves a screenshot to `path` rendered at `scale` times its size, once the app renders it
+    /// and passes it to `record_supersampled`.
+    pub fn request_supersampled(&mut self, path: PathBuf, scale: u32) {
+        self.supersampled = Some((path, scale.max(1)));
+    }
+
+    /// The supersampled screenshot that was requested, with its scale, for the app to render.
+    pub fn take_supersampled(&mut self) -> Option<(PathBuf, u32)> {
+        self.supersampled.take()
+    }
+
+    /// Whether a screenshot is waiting for a frame to be rendered, or for the GPU to finish one.
+    pub fn is_pending(&self) -> bool {
+        self.requested.is_some() || self.supersampled.is_some() || !self.pending.is_empty()
+    }
+
+    /// Records the copy of `image` into `builder` if a screenshot was requested, after whatever
+    /// the frame drew into it, and saves the screenshots that the GPU has finished copying.
+    pub fn record<L>(&mut self, builder: &mut AutoCommandBufferBuilder<L>, image: &Arc<Image>) {
+        self.poll();
+        let Some(path) = self.requested.take() else {
+            return;
+        };
+        self.copy(builder, image, path, 1);
+    }
+
+    /// Records the copy of `image`, rendered for a supersampled screenshot at `scale` times its
+    /// size, into `builder`, after whatever drew into it.
+    pub fn record_supersampled<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        image: &Arc<Image>,
+        path: PathBuf,
+        scale: u32,
+    ) {
+        self.copy(builder, image, path, scale);
+    }
+
+    fn copy<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        image: &Arc<Image>,
+        path: PathBuf,
+        scale: u32,
+    ) {
+        let Some(target) = self.create_target(image) else {
+            warn!(
+                "Screenshots aren't supported for {:?} windows",
+                image.format()
+            );
+            return;
+        };
+
+        let [width, height, _] = image.extent();
+        let buffer = Buffer::new_slice::<u8>(
+            self.memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            u64::from(width) * u64::from(height) * 4,
+        )
+        .unwrap();
+        builder
+            .blit_image(BlitImageInfo {
+                filter: Filter::Nearest,
+                ..BlitImageInfo::images(image.clone(), target.clone())
+            })
+            .unwrap()
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(target, buffer.clone()))
+            .unwrap();
+        self.pending.push(Pending {
+            path,
+            extent: [width, height],
+            scale,
+            buffer,
+        });
+    }
+
+    /// Saves the screenshots that the GPU has finished copying.
+    fn poll(&mut self) {
+        self.pending.retain(|pending| {
+            // The buffer stays locked until the future of its frame has been cleaned up.
+            let Ok(mut pixels) = pending.buffer.read().map(|pixels| pixels.to_vec()) else {
+                return true;
+            };
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel[3] = u8::MAX;
+            }
+            let path = pending.path.clone();
+            let [mut width, mut height] = pending.extent;
+            let scale = pending.scale;
+            thread::spawn(move || {
+                if scale > 1 {
+                    pixels = downsample(&pixels, [width, height], scale);
+                    [width, height] = [width / scale, height / scale];
+                }
+                match image::save_buffer(&path, &pixels, width, height, image::ColorType::Rgba8) {
+                    Ok(()) => info!("Saved {}", path.display()),
+                    Err(err) => error!("Failed to save {}: {err}", path.display()),
+                }
+            });
+            false
+        });
+    }
+
+    /// Creates the image to blit `source` into, or `None` if the device can't blit from it.
+    fn create_target(&self, source: &Image) -> Option<Arc<Image>> {
+        if !source.usage().intersects(ImageUsage::TRANSFER_SRC) {
+            return None;
+        }
+        let source_features = self
+            .memory_allocator
+            .device()
+            .physical_device()
+            .format_properties(source.format())
+            .unwrap()
+            .optimal_tiling_features;
+        if !source_features.intersects(FormatFeatures::BLIT_SRC) {
+            return None;
+        }
+
+        // As for GIFs, sRGB is blitted to sRGB so that the colors stay as they are.
+        let format = if source.format().numeric_format_color() == Some(NumericFormat::SRGB) {
+            Format::R8G8B8A8_SRGB
+        } else {
+            Format::R8G8B8A8_UNORM
+        };
+        Some(
+            Image::new(
+                self.memory_allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format,
+                    extent: source.extent(),
+                    usage: ImageUsage::TRANSFER_DST | ImageUsage::TRANSFER_SRC,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .unwrap(),
+        )
+    }
+}
+
+/// Averages every `scale` by `scale` square of `pixels`, an RGBA image of `extent`, into a pixel.
+/// The colors are those the window shows, encoded as sRGB, so they are averaged in linear light.
+fn downsample(pixels: &[u8], [width, height]: [u32; 2], scale: u32) -> Vec<u8> {
+    let decoded: Vec<f32> = (0..=u8::MAX)
+        .map(|value| gamma::srgb_to_linear(f32::from(value) / 255.0))
+        .collect();
+    let [width, scale] = [width, scale].map(|n| n as usize);
+    let samples = (scale * scale) as f32;
+
+    let mut downsampled = Vec::with_capacity(pixels.len() / (scale * scale));
+    for y in 0..height as usize / scale {
+        for x in 0..width / scale {
+            let mut sum = [0.0; 4];
+            for row in y * scale..(y + 1) * scale {
+                let start = (row * width + x * scale) * 4;
+                for pixel in pixels[start..start + scale * 4].chunks_exact(4) {
+                    for channel in 0..3 {
+                        sum[channel] += decoded[usize::from(pixel[channel])];
+                    }
+                    sum[3] += f32::from(pixel[3]) / 255.0;
+                }
+            }
+            for (channel, sum) in sum.into_iter().enumerate() {
+                let average = sum / samples;
+                let encoded = if channel < 3 {
+                    gamma::linear_to_srgb(average)
+                } else {
+                    average
+                };
+                downsampled.push((encoded * 255.0).round().clamp(0.0, 255.0) as u8);
+            }
+        }
+    }
+    downsampled
+}
@@ -0,0 +1,344 @@
+// Scripts that drive the scene, written in rhai, for trying ideas out without rebuilding the app.
+// Every `.rhai` file in `assets/scripts` is a script, which can define two functions: `init()`,
+// called when the script is loaded, and `tick(dt)`, called every frame with the seconds of
+// simulated time since the last one, which are 0 while the simulation is paused. Both are called
+// with `this` bound to a map that keeps whatever the script puts in it from one call to the next.
+//
+// Scripts spawn cubes and move, turn, scale, color and remove entities through the functions of
+// `register_api`, which take numbers as floats, so `1.0` rather than `1`. A script that is edited
+// is loaded again, with the entities it spawned removed and a new `this`, while one that no longer
+// compiles keeps running as it was. Opening another scene runs every script's `init` again.
+// Errors are logged, as is whatever scripts `print`. This is only built with the `scripting`
+// feature.
+
+use glam::{EulerRot, Mat4, Quat, Vec3};
+use hecs::Entity;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+use std::{
+    cell::RefCell,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use tracing::{info, warn};
+
+use crate::{
+    assets::{Assets, MeshSource},
+    components::{MeshHandle, Transform},
+    material::Material,
+    scene::Scene,
+};
+
+/// Where the scripts are, relative to the working directory.
+pub const DIRECTORY: &str = "assets/scripts";
+
+const EXTENSION: &str = "rhai";
+
+struct Script {
+    path: PathBuf,
+    ast: AST,
+    /// What `this` is in the script's functions.
+    state: Dynamic,
+    /// The entities the script spawned, which are removed when it is loaded again.
+    spawned: Vec<Entity>,
+}
+
+/// What the functions that scripts call act on. The scene is only moved in here while a script
+/// runs.
+#[derive(Default)]
+struct Context {
+    scene: Scene,
+    /// The entities spawned by the script that is running.
+    spawned: Vec<Entity>,
+}
+
+pub struct Scripts {
+    engine: Engine,
+    context: Rc<RefCell<Context>>,
+    /// The absolute path of `DIRECTORY`.
+    directory: PathBuf,
+    scripts: Vec<Script>,
+}
+
+impl Scripts {
+    /// Loads every script in `DIRECTORY`, in the order of their names, and runs their `init`
+    /// functions on `scene`. There are no scripts if the directory doesn't exist.
+    pub fn load(assets: &mut Assets, scene: &mut Scene) -> Self {
+        let mut engine = Engine::new();
+        engine.on_print(|text| info!("{text}"));
+        engine.on_debug(|text, source, position| {
+            info!("{}:{position}: {text}", source.unwrap_or("script"))
+        });
+        let context = Rc::new(RefCell::new(Context::default()));
+        register_api(
+            &mut engine,
+            &context,
+            MeshHandle(assets.mesh(&MeshSource::Cube)),
+        );
+
+        let directory = std::path::absolute(DIRECTORY).unwrap_or_else(|_| DIRECTORY.into());
+        let mut scripts = Scripts {
+            engine,
+            context,
+            directory,
+            scripts: Vec::new(),
+        };
+        let mut paths: Vec<PathBuf> = match fs::read_dir(&scripts.directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| scripts.is_script(path))
+                .collect(),
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    warn!("Failed to read {}: {err}", scripts.directory.display());
+                }
+                Vec::new()
+            }
+        };
+        paths.sort();
+        for path in paths {
+            scripts.reload(&path, scene);
+        }
+        scripts
+    }
+
+    /// The absolute path of the directory that the scripts are loaded from.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Whether `path`, an absolute path as `FileWatcher` passes, is that of a script.
+    pub fn is_script(&self, path: &Path) -> bool {
+        path.parent() == Some(self.directory.as_path())
+            && path
+                .extension()
+                .is_some_and(|extension| extension == EXTENSION)
+    }
+
+    /// Loads the script at `path`, or loads it again if it already was, removing the entities it
+    /// spawned from `scene`, and runs its `init` function. Keeps the script as it was if it
+    /// fails to compile.
+    pub fn reload(&mut self, path: &Path, scene: &mut Scene) {
+        let ast = match self.engine.compile_file(path.to_owned()) {
+            Ok(ast) => ast,
+            Err(err) => {
+                warn!("Failed to load {}: {err}", path.display());
+                return;
+            }
+        };
+        info!("Loaded {}", path.display());
+
+        let index = match self.scripts.iter().position(|script| script.path == path) {
+            Some(index) => {
+                let script = &mut self.scripts[index];
+                for entity in script.spawned.drain(..) {
+                    // It may have been removed already.
+                    let _ = scene.world.despawn(entity);
+                }
+                script.ast = ast;
+                script.state = Dynamic::from_map(Map::new());
+                index
+            }
+            None => {
+                self.scripts.push(Script {
+                    path: path.to_owned(),
+                    ast,
+                    state: Dynamic::from_map(Map::new()),
+                    spawned: Vec::new(),
+                });
+                self.scripts.len() - 1
+            }
+        };
+        call(
+            &self.engine,
+            &self.context,
+            &mut self.scripts[index],
+            scene,
+            "init",
+            (),
+        );
+    }
+
+    /// Starts every script over on `scene`, which replaced the scene they ran on, with a new
+    /// `this` for each.
+    pub fn restart(&mut self, scene: &mut Scene) {
+        for script in &mut self.scripts {
+            script.state = Dynamic::from_map(Map::new());
+            script.spawned.clear();
+            call(&self.engine, &self.context, script, scene, "init", ());
+        }
+    }
+
+    /// Runs the `tick` function of every script, `dt` seconds after the last frame.
+    pub fn tick(&mut self, scene: &mut Scene, dt: f32) {
+        for script in &mut self.scripts {
+            call(
+                &self.engine,
+                &self.context,
+                script,
+                scene,
+                "tick",
+                (f64::from(dt),),
+            );
+        }
+    }
+}
+
+/// Calls the function `name` of `script` on `scene` with `args`, if the script has one, logging
+/// any error it gives.
+fn call(
+    engine: &Engine,
+    context: &RefCell<Context>,
+    script: &mut Script,
+    scene: &mut Scene,
+    name: &str,
+    args: impl FuncArgs,
+) {
+    if !script
+        .ast
+        .iter_functions()
+        .any(|function| function.name == name)
+    {
+        return;
+    }
+
+    std::mem::swap(&mut context.borrow_mut().scene, scene);
+    let result = engine.call_fn_with_options::<Dynamic>(
+        CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut script.state),
+        &mut Scope::new(),
+        &script.ast,
+        name,
+        args,
+    );
+    let mut context = context.borrow_mut();
+    std::mem::swap(&mut context.scene, scene);
+    script.spawned.append(&mut context.spawned);
+
+    if let Err(err) = result {
+        warn!("{} failed in {name}: {err}", script.path.display());
+    }
+}
+
+/// Adds the functions that scripts call to `engine`, acting on the scene in `context`. Spawned
+/// entities are drawn with `cube`.
+fn register_api(engine: &mut Engine, context: &Rc<RefCell<Context>>, cube: MeshHandle) {
+    engine
+        .register_type_with_name::<Entity>("Entity")
+        .register_fn("to_string", |entity: &mut Entity| {
+            format!("entity {}", entity.id())
+        })
+        .register_fn("==", |a: Entity, b: Entity| a == b);
+
+    let ctx = context.clone();
+    engine.register_fn("spawn_cube", move |x: f64, y: f64, z: f64| {
+        let context = &mut *ctx.borrow_mut();
+        let translation = Mat4::from_translation(vec3(x, y, z));
+        let node = context.scene.add_node(None, translation);
+        let entity = context
+            .scene
+            .spawn_object(cube.clone(), node, Material::default());
+        context.spawned.push(entity);
+        entity
+    });
+
+    let ctx = context.clone();
+    engine.register_fn("despawn", move |entity: Entity| {
+        // Removing an entity twice is harmless.
+        let _ = ctx.borrow_mut().scene.world.despawn(entity);
+    });
+
+    let ctx = context.clone();
+    engine.register_fn(
+        "position",
+        move |entity: Entity| -> Result<Array, Box<EvalAltResult>> {
+            let context = &mut *ctx.borrow_mut();
+            // Moves made earlier in the same call are only in the nodes until this.
+            context.scene.update_transforms();
+            let transform = world_transform(&context.scene, entity)?;
+            let translation = transform.w_axis.truncate();
+            Ok(translation
+                .to_array()
+                .map(|coordinate| Dynamic::from_float(coordinate.into()))
+                .into())
+        },
+    );
+
+    let ctx = context.clone();
+    engine.register_fn(
+        "set_position",
+        move |entity: Entity, x: f64, y: f64, z: f64| {
+            edit_transform(&ctx, entity, |_, _, translation| {
+                *translation = vec3(x, y, z)
+            })
+        },
+    );
+
+    let ctx = context.clone();
+    engine.register_fn(
+        "set_rotation",
+        move |entity: Entity, yaw: f64, pitch: f64, roll: f64| {
+            edit_transform(&ctx, entity, |_, rotation, _| {
+                *rotation = Quat::from_euler(EulerRot::YXZ, yaw as f32, pitch as f32, roll as f32)
+            })
+        },
+    );
+
+    let ctx = context.clone();
+    engine.register_fn("set_scale", move |entity: Entity, factor: f64| {
+        edit_transform(&ctx, entity, |scale, _, _| {
+            *scale = Vec3::splat(factor as f32)
+        })
+    });
+
+    let ctx = context.clone();
+    engine.register_fn(
+        "set_color",
+        move |entity: Entity, r: f64, g: f64, b: f64| -> Result<(), Box<EvalAltResult>> {
+            let context = ctx.borrow_mut();
+            let mut material = context
+                .scene
+                .world
+                .get::<&mut Material>(entity)
+                .map_err(|_| missing(entity))?;
+            let alpha = material.base_color.w;
+            material.base_color = vec3(r, g, b).extend(alpha);
+            Ok(())
+        },
+    );
+}
+
+fn vec3(x: f64, y: f64, z: f64) -> Vec3 {
+    Vec3::new(x as f32, y as f32, z as f32)
+}
+
+fn missing(entity: Entity) -> Box<EvalAltResult> {
+    format!("entity {} doesn't exist or has no transform", entity.id()).into()
+}
+
+fn world_transform(scene: &Scene, entity: Entity) -> Result<Mat4, Box<EvalAltResult>> {
+    scene
+        .world
+        .get::<&Transform>(entity)
+        .map(|transform| transform.0)
+        .map_err(|_| missing(entity))
+}
+
+/// Changes the scale, rotation and translation of the world transform of `entity` with `edit`.
+fn edit_transform(
+    context: &RefCell<Context>,
+    entity: Entity,
+    edit: impl FnOnce(&mut Vec3, &mut Quat, &mut Vec3),
+) -> Result<(), Box<EvalAltResult>> {
+    let context = &mut *context.borrow_mut();
+    context.scene.update_transforms();
+    let transform = world_transform(&context.scene, entity)?;
+    let (mut scale, mut rotation, mut translation) = transform.to_scale_rotation_translation();
+    edit(&mut scale, &mut rotation, &mut translation);
+    context.scene.set_world_transform(
+        entity,
+        Mat4::from_scale_rotation_translation(scale, rotation, translation),
+    );
+    Ok(())
+}
@@ -0,0 +1,305 @@
+// Render settings that can be tweaked while the app is running. They are read from
+// `settings.toml` in the working directory and reloaded whenever that file changes, so the values
+// below can be edited from any text editor without restarting. The developer console sets them one
+// at a time by name, as they are written in the file, until the file changes again.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{keybindings::Keybindings, text};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    /// The color the frame is cleared to before anything is drawn.
+    pub clear_color: [f32; 4],
+    /// Whether presentation waits for vertical blank. Changing it recreates the swapchain, unless
+    /// the swapchain can switch between the present modes as it is.
+    pub vsync: bool,
+    /// How many images the swapchain is asked to have at least, within what the surface
+    /// supports, or zero for the fewest it supports. Changing it recreates the swapchain.
+    pub swapchain_images: u32,
+    /// How the swapchain's images are fitted to a window of another size, where the surface lets
+    /// this be chosen. Changing it recreates the swapchain.
+    pub present_scaling: PresentScaling,
+    /// When frames are rendered.
+    pub redraw: RedrawPolicy,
+    /// The most frames a second that are rendered, or zero for as many as presentation allows.
+    pub max_fps: u32,
+    /// How far the camera turns when dragged across the height of the window, in half turns.
+    pub orbit_speed: f32,
+    /// Whether the main view is drawn twice side by side, once for each eye of a stereo pair.
+    pub stereo: bool,
+    /// How far apart the eyes of the stereo pair are, in scene units.
+    pub eye_separation: f32,
+    /// How a click finds the entity to select.
+    pub picking: PickingMethod,
+    /// How wide the outline around the selected entity is, in logical pixels, or 0 for none.
+    pub outline_width: f32,
+    /// Whether the shapes queued on the `DebugDraw` batch are rendered.
+    pub debug_draw: bool,
+    /// Whether a grid is drawn on the ground plane of the rasterized main view.
+    pub grid: bool,
+    /// Whether the world-space bounding box of every scene object is drawn.
+    pub show_bounds: bool,
+    /// Whether objects hidden behind others in the previous frame are skipped, using occlusion
+    /// queries.
+    pub occlusion_culling: bool,
+    /// Whether objects are culled on the GPU and drawn indirectly, where the device supports it,
+    /// rather than tested and drawn one by one on the CPU.
+    pub gpu_culling: bool,
+    /// Whether objects are colored by the level of detail they are drawn with.
+    pub lod_debug: bool,
+    /// Shows the frame as it would look if its colors weren't encoded on output, or were encoded
+    /// twice.
+    pub gamma_audit: GammaAudit,
+    /// Whether colors are dithered as they are written out, against banding in gradients.
+    pub dither: bool,
+    /// Whether the swapchain has a 10-bit format where the surface offers one, read when the
+    /// window is created.
+    pub ten_bit_output: bool,
+    /// Whether the terrain is tessellated more the closer it is to the camera, where the device
+    /// supports it, rather than drawn at full detail everywhere.
+    pub tessellation: bool,
+    /// Whether every pass is submitted and waited on separately, with the image it rendered to
+    /// saved to `frame-debug/` after it.
+    pub frame_debug: bool,
+    /// How many seconds of the latest frames are kept for saving as a GIF, or zero to keep none.
+    pub gif_seconds: f32,
+    /// The size that the rasterized scene is drawn at, as a fraction of the window's, from
+    /// `MIN_RENDER_SCALE` to `MAX_RENDER_SCALE`. It is scaled to the window's size when shown,
+    /// where the window's images can be blitted to.
+    pub render_scale: f32,
+    /// How many times as wide and high as themselves supersampled screenshots are rendered,
+    /// within what the device's images can be. It must be at least 1.
+    #[serde(deserialize_with = "deserialize_supersampling")]
+    pub supersampling: u32,
+    /// The size of supersampled screenshots, or `None` for the size of the window. Neither side
+    /// can be zero.
+    #[serde(deserialize_with = "deserialize_supersampled_size")]
+    pub supersampled_size: Option<[u32; 2]>,
+    /// How many pixels to either side the scene is blurred across, or zero not to blur it. The
+    /// debug-draw overlay is drawn on top, unblurred.
+    pub blur_radius: u32,
+    /// How thick the fog is at y = 0, or zero for no fog. It thins out above that.
+    pub fog_density: f32,
+    /// From -1 to 1, how much of the light the fog scatters on ahead rather than back, which is
+    /// how much it glows around the light when looking towards it.
+    pub fog_anisotropy: f32,
+    /// How quickly the fog thins out with height.
+    pub fog_height_falloff: f32,
+    /// How many particles the fountain at the origin has, simulated on the GPU, or zero for no
+    /// fountain. Changing it restarts the fountain.
+    pub particles: u32,
+    /// Whether the window shows what is behind it where the clear color is not opaque. This only
+    /// works where the compositor blends windows, and turning it on while running doesn't work
+    /// on X11, where it has to be set when the window is created.
+    pub transparent: bool,
+    /// An image to use as the window icon instead of the built-in one.
+    pub window_icon: Option<PathBuf>,
+    /// The fonts that overlay text is set in, each tried in turn for what the ones before it
+    /// have no glyphs for. Those that aren't installed are skipped.
+    pub fonts: Vec<PathBuf>,
+    /// The keys of the hotkeys, by action.
+    pub keybindings: Keybindings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedrawPolicy {
+    /// A new frame is rendered as soon as the last one is presented, which animations need.
+    Continuous,
+    /// Frames are only rendered when something changes, through input, resizing, the system
+    /// asking for one, or reloaded settings and assets. Animations stand still in between.
+    Reactive,
+}
+
+/// How an image of the swapchain is shown in a window it doesn't match the size of, such as while
+/// the window is being resized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresentScaling {
+    /// The image is stretched to fill the window.
+    Stretch,
+    /// The image is stretched as far as it can be without changing its aspect ratio, and
+    /// centered.
+    AspectRatio,
+    /// Every pixel of the image is one pixel of the window, with the image centered.
+    OneToOne,
+}
+
+/// How clicking finds the entity under the cursor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PickingMethod {
+    /// The entities are drawn into an ID buffer on the GPU, which is read back a frame or two
+    /// later, with `Picker`.
+    IdBuffer,
+    /// A ray is cast against the triangles of the entities on the CPU, with `raycast`.
+    Raycast,
+}
+
+/// A debug view for mistakes in the linear workflow of `gamma.rs`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GammaAudit {
+    /// The frame is drawn as usual.
+    #[default]
+    Off,
+    /// Linear colors are shown as they are, too dark, as they would be if the frame were written
+    /// to a target that doesn't encode them.
+    Uncorrected,
+    /// Colors are encoded twice, washed out, as they would be if a shader encoded them for a
+    /// target that encodes them as well.
+    DoubleCorrected,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            clear_color: [0.0, 0.0, 1.0, 1.0],
+            vsync: true,
+            swapchain_images: 0,
+            present_scaling: PresentScaling::Stretch,
+            redraw: RedrawPolicy::Continuous,
+            max_fps: 0,
+            orbit_speed: 1.0,
+            stereo: false,
+            eye_separation: 0.064,
+            picking: PickingMethod::IdBuffer,
+            outline_width: 2.0,
+            debug_draw: true,
+            grid: false,
+            show_bounds: false,
+            occlusion_culling: false,
+            gpu_culling: true,
+            lod_debug: false,
+            gamma_audit: GammaAudit::Off,
+            dither: false,
+            ten_bit_output: false,
+            tessellation: true,
+            frame_debug: false,
+            gif_seconds: 5.0,
+            render_scale: 1.0,
+            supersampling: 4,
+            supersampled_size: None,
+            blur_radius: 0,
+            fog_density: 0.0,
+            fog_anisotropy: 0.6,
+            fog_height_falloff: 0.2,
+            particles: 0,
+            transparent: false,
+            window_icon: None,
+            fonts: text::DEFAULT_FONTS.iter().map(PathBuf::from).collect(),
+            keybindings: Keybindings::default(),
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Where the settings are read from, relative to the working directory.
+    pub const PATH: &str = "settings.toml";
+
+    /// The range of the render scale.
+    pub const MIN_RENDER_SCALE: f32 = 0.5;
+    pub const MAX_RENDER_SCALE: f32 = 2.0;
+
+    /// Reads the settings from `path`. A missing file yields the defaults, so the file only needs
+    /// to list the values that differ from them.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+
+        toml::from_str(&source).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// The names of the settings, as they are written in the file. Those without a value by
+    /// default are left out.
+    pub fn names() -> Vec<String> {
+        toml::Table::try_from(Self::default())
+            .map(|table| table.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// These settings with the one called `name` set to `value`, which is written as in the file.
+    pub fn with(&self, name: &str, value: &str) -> Result<Self, toml::de::Error> {
+        let value: toml::Table = toml::from_str(&format!("value = {value}"))?;
+        let mut table = toml::Table::try_from(self).expect("settings are serializable");
+        table.insert(name.to_owned(), value["value"].clone());
+        table.try_into()
+    }
+}
+
+fn deserialize_supersampling<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let supersampling = u32::deserialize(deserializer)?;
+    if supersampling == 0 {
+        return Err(serde::de::Error::custom(
+            "supersampling must be at least 1, which renders at the screenshot's size",
+        ));
+    }
+    Ok(supersampling)
+}
+
+fn deserialize_supersampled_size<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<[u32; 2]>, D::Error> {
+    let size = Option::<[u32; 2]>::deserialize(deserializer)?;
+    if size.is_some_and(|size| size.contains(&0)) {
+        return Err(serde::de::Error::custom(
+            "supersampled_size must be at least 1 pixel wide and high",
+        ));
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_supersampling_below_one() {
+        let settings = RenderSettings::default();
+        let err = settings.with("supersampling", "0").unwrap_err();
+        assert!(err.to_string().contains("supersampling must be at least 1"));
+        assert!(toml::from_str::<RenderSettings>("supersampling = 0").is_err());
+
+        assert_eq!(
+            settings.with("supersampling", "1").unwrap().supersampling,
+            1
+        );
+    }
+
+    #[test]
+    fn rejects_empty_supersampled_sizes() {
+        let settings = RenderSettings::default();
+        for size in ["[0, 1080]", "[1920, 0]", "[0, 0]"] {
+            let err = settings.with("supersampled_size", size).unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("supersampled_size must be at least 1"));
+        }
+        assert!(toml::from_str::<RenderSettings>("supersampled_size = [0, 4]").is_err());
+
+        assert_eq!(
+            settings
+                .with("supersampled_size", "[1, 2]")
+                .unwrap()
+                .supersampled_size,
+            Some([1, 2])
+        );
+        assert_eq!(
+            toml::from_str::<RenderSettings>("")
+                .unwrap()
+                .supersampled_size,
+            None
+        );
+    }
+}
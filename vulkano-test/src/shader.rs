@@ -0,0 +1,521 @@
+// Shaders are written in GLSL and translated to SPIR-V at startup with naga, so building the crate
+// does not require shaderc or any other native toolchain. Variants of a shader are made with
+// preprocessor defines, or with specialization constants through a `ShaderSource`. Code shared
+// between shaders, such as the layout of the frame's uniforms, lives in `shaders/include/` and is
+// pulled in with `#include "NAME"`.
+//
+// With the `wgsl` feature, shaders can also be written in WGSL, so that those written for wgpu work
+// with the pipelines here too. Their bind groups are descriptor sets, and their clip space is
+// converted to Vulkan's, whose Y axis points down.
+//
+// naga can't translate ray tracing stages, ray queries or tessellation stages, so those are
+// compiled with `glslc` from the Vulkan SDK instead, which has to be on the `PATH` wherever they
+// are used.
+
+use std::{
+    collections::HashSet,
+    io::Write,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+use vulkano::{
+    descriptor_set::layout::{DescriptorSetLayout, DescriptorSetLayoutCreateInfo},
+    device::Device,
+    pipeline::{
+        layout::PipelineDescriptorSetLayoutCreateInfo, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    shader::{spirv::bytes_to_words, ShaderModule, ShaderModuleCreateInfo},
+};
+
+use crate::error::AppError;
+
+pub use naga::ShaderStage;
+
+/// The files in `shaders/include/`, which GLSL shaders can include.
+const INCLUDES: &[(&str, &str)] = &[
+    ("culling.glsl", include_str!("shaders/include/culling.glsl")),
+    ("draw.glsl", include_str!("shaders/include/draw.glsl")),
+    (
+        "environment.glsl",
+        include_str!("shaders/include/environment.glsl"),
+    ),
+    ("frame.glsl", include_str!("shaders/include/frame.glsl")),
+    ("gamma.glsl", include_str!("shaders/include/gamma.glsl")),
+    (
+        "light_clusters.glsl",
+        include_str!("shaders/include/light_clusters.glsl"),
+    ),
+    (
+        "lighting.glsl",
+        include_str!("shaders/include/lighting.glsl"),
+    ),
+    (
+        "path_tracing.glsl",
+        include_str!("shaders/include/path_tracing.glsl"),
+    ),
+    (
+        "ray_traced_view.glsl",
+        include_str!("shaders/include/ray_traced_view.glsl"),
+    ),
+    (
+        "ray_tracing.glsl",
+        include_str!("shaders/include/ray_tracing.glsl"),
+    ),
+    ("srgb.glsl", include_str!("shaders/include/srgb.glsl")),
+    ("terrain.glsl", include_str!("shaders/include/terrain.glsl")),
+    ("water.glsl", include_str!("shaders/include/water.glsl")),
+];
+
+/// Compiles GLSL `source` for the given `stage` and creates a shader module from it. Fails with
+/// the compiler diagnostics if the source is invalid.
+pub fn load(
+    device: Arc<Device>,
+    source: &str,
+    stage: ShaderStage,
+) -> Result<Arc<ShaderModule>, AppError> {
+    load_with_defines(device, source, stage, &[])
+}
+
+/// Like `load`, but with each of `defines` defined as a preprocessor macro, as if by `#define NAME
+/// VALUE` at the top of the source. naga can't write specialization constants, so this is how
+/// shaders are specialized, such as for the workgroup size that suits the device.
+pub fn load_with_defines(
+    device: Arc<Device>,
+    source: &str,
+    stage: ShaderStage,
+    defines: &[(&str, String)],
+) -> Result<Arc<ShaderModule>, AppError> {
+    ShaderSource::parse(source, stage, defines)?.specialize(device, &[])
+}
+
+/// Translates GLSL `source` into SPIR-V words, with `defines` defined as preprocessor macros.
+pub fn compile_glsl(
+    source: &str,
+    stage: ShaderStage,
+    defines: &[(&str, String)],
+) -> Result<Vec<u32>, AppError> {
+    ShaderSource::parse(source, stage, defines)?.to_spirv(&[])
+}
+
+/// Translates the entry point of `stage` named `entry_point` in WGSL `source` and creates a shader
+/// module from it, in which the entry point is called `main`.
+#[cfg(feature = "wgsl")]
+pub fn load_wgsl(
+    device: Arc<Device>,
+    source: &str,
+    stage: ShaderStage,
+    entry_point: &str,
+) -> Result<Arc<ShaderModule>, AppError> {
+    ShaderSource::parse_wgsl(source, stage, entry_point)?.specialize(device, &[])
+}
+
+/// Compiles GLSL `source` for `stage` with the `glslc` executable and creates a shader module from
+/// it, for the ray tracing stages and ray queries that naga can't translate. Includes are looked
+/// up in `shaders/include/` and `defines` defined as for `load_with_defines`. The SPIR-V is 1.4,
+/// which ray tracing needs, for Vulkan 1.1. Unlike naga's, it keeps the specialization constants,
+/// which `ShaderModule::specialize` sets.
+pub fn load_with_glslc(
+    device: Arc<Device>,
+    source: &str,
+    stage: ShaderStage,
+    defines: &[(&str, String)],
+) -> Result<Arc<ShaderModule>, AppError> {
+    let stage_name = match stage {
+        ShaderStage::Vertex => "vert",
+        ShaderStage::Task => "task",
+        ShaderStage::Mesh => "mesh",
+        ShaderStage::Fragment => "frag",
+        ShaderStage::Compute => "comp",
+        ShaderStage::RayGeneration => "rgen",
+        ShaderStage::Miss => "rmiss",
+        ShaderStage::AnyHit => "rahit",
+        ShaderStage::ClosestHit => "rchit",
+    };
+    glslc(device, source, stage_name, defines)
+}
+
+/// The tessellation stages, which naga has no `ShaderStage` for, as it can't translate them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TessellationStage {
+    Control,
+    Evaluation,
+}
+
+/// Like `load_with_glslc`, for a tessellation stage.
+pub fn load_tessellation_with_glslc(
+    device: Arc<Device>,
+    source: &str,
+    stage: TessellationStage,
+    defines: &[(&str, String)],
+) -> Result<Arc<ShaderModule>, AppError> {
+    let stage_name = match stage {
+        TessellationStage::Control => "tesc",
+        TessellationStage::Evaluation => "tese",
+    };
+    glslc(device, source, stage_name, defines)
+}
+
+fn glslc(
+    device: Arc<Device>,
+    source: &str,
+    stage_name: &str,
+    defines: &[(&str, String)],
+) -> Result<Arc<ShaderModule>, AppError> {
+    let source = expand_includes(source, &builtin_include)?;
+
+    // The source is read from stdin, and the SPIR-V written to stdout.
+    let mut child = Command::new("glslc")
+        .args(["--target-env=vulkan1.1", "--target-spv=spv1.4"])
+        .arg(format!("-fshader-stage={stage_name}"))
+        .args(
+            defines
+                .iter()
+                .map(|(name, value)| format!("-D{name}={value}")),
+        )
+        .args(["-o", "-", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| AppError::Shader(format!("failed to start glslc: {err}")))?;
+    let written = child.stdin.take().unwrap().write_all(source.as_bytes());
+    let output = child
+        .wait_with_output()
+        .map_err(|err| AppError::Shader(format!("failed to run glslc: {err}")))?;
+    if !output.status.success() {
+        return Err(AppError::Shader(format!(
+            "failed to compile:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    written.map_err(|err| AppError::Shader(format!("failed to run glslc: {err}")))?;
+
+    let words = bytes_to_words(&output.stdout).map_err(|err| AppError::Shader(err.to_string()))?;
+    // SAFETY: the SPIR-V was produced by glslc, which validates what it writes.
+    unsafe { ShaderModule::new(device, ShaderModuleCreateInfo::new(&words)) }
+        .map_err(|err| AppError::Shader(err.to_string()))
+}
+
+/// The source of the file in `shaders/include/` named `name`, if there is one.
+pub fn builtin_include(name: &str) -> Option<String> {
+    INCLUDES
+        .iter()
+        .find(|(include, _)| *include == name)
+        .map(|(_, source)| source.to_string())
+}
+
+/// Creates the layout of a pipeline made of `stages` from what their shaders declare: a descriptor
+/// set layout for every set that any of them uses, and the push constant range of every stage with
+/// push constants. Those are pushed as a `P`, or not at all for `()`. Its size is checked against
+/// the shaders' push constant block, so that a struct that no longer matches its GLSL fails here
+/// rather than on the first push.
+pub fn reflect_layout<P>(
+    device: Arc<Device>,
+    stages: &[PipelineShaderStageCreateInfo],
+) -> Result<Arc<PipelineLayout>, AppError> {
+    reflect_layout_with_sets::<P>(device, stages, &[])
+}
+
+/// Like `reflect_layout`, but with the layout of each set number in `set_layouts` given rather
+/// than reflected, for sets whose binding flags the shaders can't declare, such as bindless ones.
+pub fn reflect_layout_with_sets<P>(
+    device: Arc<Device>,
+    stages: &[PipelineShaderStageCreateInfo],
+    set_layouts: &[(u32, Arc<DescriptorSetLayout>)],
+) -> Result<Arc<PipelineLayout>, AppError> {
+    let mut create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(stages);
+    for &(set, _) in set_layouts {
+        if let Some(set_layout) = create_info.set_layouts.get_mut(set as usize) {
+            *set_layout = DescriptorSetLayoutCreateInfo::default();
+        }
+    }
+
+    let push_constants_size = create_info
+        .push_constant_ranges
+        .iter()
+        .map(|range| range.offset + range.size)
+        .max()
+        .unwrap_or(0);
+    if push_constants_size as usize != size_of::<P>() {
+        return Err(AppError::Shader(format!(
+            "the shaders take {push_constants_size} bytes of push constants, but {} are pushed",
+            size_of::<P>(),
+        )));
+    }
+
+    let mut create_info = create_info
+        .into_pipeline_layout_create_info(device.clone())
+        .map_err(|err| AppError::Pipeline(err.error))?;
+    for (set, set_layout) in set_layouts {
+        let set = *set as usize;
+        if create_info.set_layouts.len() <= set {
+            let empty = DescriptorSetLayout::new(device.clone(), Default::default())
+                .map_err(AppError::Pipeline)?;
+            create_info.set_layouts.resize(set + 1, empty);
+        }
+        create_info.set_layouts[set] = set_layout.clone();
+    }
+    PipelineLayout::new(device, create_info).map_err(AppError::Pipeline)
+}
+
+/// A GLSL shader that has been parsed and validated, from which any number of variants can be
+/// created with different values for its specialization constants. These are declared as
+/// `layout(constant_id = N) const TYPE NAME = DEFAULT;` and set by their ID `N`. naga can't write
+/// specialization constants into SPIR-V, so each variant gets a module of its own with the values
+/// filled in, but only the SPIR-V is generated again.
+pub struct ShaderSource {
+    module: naga::Module,
+    info: naga::valid::ModuleInfo,
+    stage: ShaderStage,
+    /// Whether the Y axis of clip space points up, as it does in WGSL, and has to be flipped.
+    y_up: bool,
+}
+
+impl ShaderSource {
+    /// Parses and validates GLSL `source` for `stage`, with `defines` defined as preprocessor
+    /// macros and includes looked up in `shaders/include/`.
+    pub fn parse(
+        source: &str,
+        stage: ShaderStage,
+        defines: &[(&str, String)],
+    ) -> Result<Self, AppError> {
+        Self::parse_with_includes(source, stage, defines, builtin_include)
+    }
+
+    /// Like `parse`, but with the source of every `#include "NAME"` found by `resolve`, which
+    /// returns `None` for names it doesn't know.
+    pub fn parse_with_includes(
+        source: &str,
+        stage: ShaderStage,
+        defines: &[(&str, String)],
+        resolve: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, AppError> {
+        let source = &expand_includes(source, &resolve)?;
+        let mut options = naga::front::glsl::Options::from(stage);
+        options.defines.extend(
+            defines
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone())),
+        );
+        let mut module = naga::front::glsl::Frontend::default()
+            .parse(&options, source)
+            .map_err(|err| {
+                AppError::Shader(format!("failed to parse:\n{}", err.emit_to_string(source)))
+            })?;
+        descriptor_arrays_to_binding_arrays(&mut module);
+
+        Self::validate(module, source, stage, false)
+    }
+
+    /// Parses and validates the entry point of `stage` named `entry_point` in WGSL `source`,
+    /// leaving out the module's other entry points. WGSL has no preprocessor, and its
+    /// specialization constants are `override` declarations, set by their `@id`.
+    #[cfg(feature = "wgsl")]
+    pub fn parse_wgsl(
+        source: &str,
+        stage: ShaderStage,
+        entry_point: &str,
+    ) -> Result<Self, AppError> {
+        let mut module = naga::front::wgsl::parse_str(source).map_err(|err| {
+            AppError::Shader(format!("failed to parse:\n{}", err.emit_to_string(source)))
+        })?;
+
+        // The pipelines look every entry point up as `main`, as GLSL calls it.
+        module
+            .entry_points
+            .retain(|ep| ep.stage == stage && ep.name == entry_point);
+        let Some(ep) = module.entry_points.first_mut() else {
+            return Err(AppError::Shader(format!(
+                "there is no {stage:?} entry point named {entry_point}"
+            )));
+        };
+        ep.name = "main".into();
+
+        Self::validate(module, source, stage, true)
+    }
+
+    fn validate(
+        module: naga::Module,
+        source: &str,
+        stage: ShaderStage,
+        y_up: bool,
+    ) -> Result<Self, AppError> {
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|err| {
+            AppError::Shader(format!(
+                "failed to validate:\n{}",
+                err.emit_to_string(source)
+            ))
+        })?;
+
+        Ok(ShaderSource {
+            module,
+            info,
+            stage,
+            y_up,
+        })
+    }
+
+    /// Creates a shader module with the specialization constants of each ID in `constants` set
+    /// to its value, and the others left at their defaults. Booleans are set with 0 or 1.
+    pub fn specialize(
+        &self,
+        device: Arc<Device>,
+        constants: &[(u32, f64)],
+    ) -> Result<Arc<ShaderModule>, AppError> {
+        let words = self.to_spirv(constants)?;
+
+        // SAFETY: the SPIR-V was produced by naga from a module that passed validation.
+        unsafe { ShaderModule::new(device, ShaderModuleCreateInfo::new(&words)) }
+            .map_err(|err| AppError::Shader(err.to_string()))
+    }
+
+    /// Translates the shader into SPIR-V words, with `constants` as for `specialize`.
+    pub fn to_spirv(&self, constants: &[(u32, f64)]) -> Result<Vec<u32>, AppError> {
+        let pipeline_constants = constants
+            .iter()
+            .map(|&(id, value)| (id.to_string(), value))
+            .collect();
+        let (module, info) = naga::back::pipeline_constants::process_overrides(
+            &self.module,
+            &self.info,
+            Some((self.stage, "main")),
+            &pipeline_constants,
+        )
+        .map_err(|err| AppError::Shader(format!("failed to specialize: {err}")))?;
+
+        // Vertex inputs are matched to `Vertex` struct members by name, so the variable names
+        // must survive into the SPIR-V. The Y coordinate is left alone unless the shader was
+        // written against a clip space other than Vulkan's.
+        let mut flags = naga::back::spv::WriterFlags::DEBUG
+            | naga::back::spv::WriterFlags::LABEL_VARYINGS
+            | naga::back::spv::WriterFlags::CLAMP_FRAG_DEPTH;
+        flags.set(
+            naga::back::spv::WriterFlags::ADJUST_COORDINATE_SPACE,
+            self.y_up,
+        );
+        // Mesh shading was added to SPIR-V 1.4, which other shaders don't need.
+        let lang_version = match self.stage {
+            ShaderStage::Task | ShaderStage::Mesh => (1, 4),
+            _ => (1, 0),
+        };
+        let options = naga::back::spv::Options {
+            lang_version,
+            flags,
+            ..Default::default()
+        };
+        let pipeline_options = naga::back::spv::PipelineOptions {
+            shader_stage: self.stage,
+            entry_point: "main".into(),
+        };
+
+        naga::back::spv::write_vec(&module, &info, &options, Some(&pipeline_options))
+            .map_err(|err| AppError::Shader(format!("failed to generate SPIR-V: {err}")))
+    }
+}
+
+/// Turns the arrays of textures and samplers in `module` into binding arrays. naga's GLSL frontend
+/// parses `uniform texture2D textures[]` as a plain array in the uniform address space, which can't
+/// hold them, whereas it declares an array of descriptors, which is what naga calls a binding array.
+/// Indexing one then gives the descriptor itself rather than a pointer to load it through.
+fn descriptor_arrays_to_binding_arrays(module: &mut naga::Module) {
+    let mut arrays = Vec::new();
+    for (handle, global) in module.global_variables.iter_mut() {
+        let naga::TypeInner::Array { base, size, .. } = module.types[global.ty].inner else {
+            continue;
+        };
+        if !matches!(
+            module.types[base].inner,
+            naga::TypeInner::Image { .. } | naga::TypeInner::Sampler { .. }
+        ) {
+            continue;
+        }
+        let name = module.types[global.ty].name.clone();
+        module.types.replace(
+            global.ty,
+            naga::Type {
+                name,
+                inner: naga::TypeInner::BindingArray { base, size },
+            },
+        );
+        global.space = naga::AddressSpace::Handle;
+        arrays.push(handle);
+    }
+    if arrays.is_empty() {
+        return;
+    }
+
+    let functions = module.functions.iter_mut().map(|(_, function)| function);
+    let entry_points = module
+        .entry_points
+        .iter_mut()
+        .map(|entry_point| &mut entry_point.function);
+    for function in functions.chain(entry_points) {
+        let expressions = &mut function.expressions;
+        let loads: Vec<_> = expressions
+            .iter()
+            .filter_map(|(handle, expression)| match *expression {
+                naga::Expression::Load { pointer } => Some((handle, pointer)),
+                _ => None,
+            })
+            .collect();
+        for (load, pointer) in loads {
+            let indexed = match expressions[pointer] {
+                naga::Expression::Access { base, .. }
+                | naga::Expression::AccessIndex { base, .. } => base,
+                _ => continue,
+            };
+            if let naga::Expression::GlobalVariable(global) = expressions[indexed]
+                && arrays.contains(&global)
+            {
+                expressions[load] = expressions[pointer].clone();
+            }
+        }
+    }
+}
+
+/// Replaces every `#include "NAME"` line in `source` with the source `resolve` finds for `NAME`,
+/// with its own includes expanded. A file is only included the first time, so shared files can
+/// include each other without guards.
+fn expand_includes(
+    source: &str,
+    resolve: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, AppError> {
+    fn expand(
+        source: &str,
+        resolve: &dyn Fn(&str) -> Option<String>,
+        included: &mut HashSet<String>,
+        output: &mut String,
+    ) -> Result<(), AppError> {
+        for line in source.lines() {
+            let Some(name) = line.trim_start().strip_prefix("#include") else {
+                output.push_str(line);
+                output.push('\n');
+                continue;
+            };
+            let name = name.trim();
+            let name = name
+                .strip_prefix('"')
+                .and_then(|name| name.strip_suffix('"'))
+                .or_else(|| name.strip_prefix('<')?.strip_suffix('>'))
+                .ok_or_else(|| AppError::Shader(format!("malformed include: {line}")))?;
+            if !included.insert(name.to_owned()) {
+                continue;
+            }
+
+            let included_source = resolve(name)
+                .ok_or_else(|| AppError::Shader(format!("there is no {name} to include")))?;
+            expand(&included_source, resolve, included, output)?;
+        }
+        Ok(())
+    }
+
+    let mut output = String::with_capacity(source.len());
+    expand(source, resolve, &mut HashSet::new(), &mut output)?;
+    Ok(output)
+}
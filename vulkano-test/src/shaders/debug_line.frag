@@ -0,0 +1,18 @@
+#version 450
+
+// ENCODE_SRGB is defined by `gamma::encoding_defines` where the target is encoded by hand.
+#ifdef ENCODE_SRGB
+#include "srgb.glsl"
+#endif
+
+layout(location = 0) in vec4 v_color;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+#ifdef ENCODE_SRGB
+    f_color = vec4(linear_to_srgb(v_color.rgb), v_color.a);
+#else
+    f_color = v_color;
+#endif
+}
@@ -0,0 +1,54 @@
+#version 450
+
+// ENCODE_SRGB is defined by `gamma::encoding_defines` where the target is encoded by hand.
+#ifdef ENCODE_SRGB
+#include "srgb.glsl"
+#endif
+
+// Lines every unit and every ten units of the ground plane, a pixel wide wherever they are seen
+// from, with the X axis in red and the Z axis in blue, fading out with the distance from the eye.
+layout(location = 0) in vec3 v_world_position;
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_proj;
+    vec4 eye;
+} pc;
+
+const vec3 LINE_COLOR = vec3(0.5);
+const vec3 X_AXIS_COLOR = vec3(0.9, 0.15, 0.15);
+const vec3 Z_AXIS_COLOR = vec3(0.15, 0.3, 0.9);
+
+// How much of the pixel at `coord` is covered by the lines through every multiple of `cell`.
+float lines(vec2 coord, float cell) {
+    vec2 scaled = coord / cell;
+    // How far the nearest line is, in pixels.
+    vec2 distance = abs(fract(scaled - 0.5) - 0.5) / fwidth(scaled);
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+// How much of the pixel at `coord` is covered by the line through 0.
+float axis(float coord) {
+    return 1.0 - min(abs(coord) / fwidth(coord), 1.0);
+}
+
+void main() {
+    vec2 coord = v_world_position.xz;
+    vec4 color = vec4(LINE_COLOR, max(lines(coord, 1.0) * 0.3, lines(coord, 10.0) * 0.6));
+    // The X axis runs where z is 0, and the Z axis where x is 0.
+    float x_axis = axis(coord.y);
+    float z_axis = axis(coord.x);
+    color = mix(color, vec4(X_AXIS_COLOR, 1.0), x_axis);
+    color = mix(color, vec4(Z_AXIS_COLOR, 1.0), z_axis);
+
+    float fade = 1.0 - clamp(distance(v_world_position, pc.eye.xyz) / pc.eye.w, 0.0, 1.0);
+    color.a *= fade * fade;
+    if (color.a <= 0.0) {
+        discard;
+    }
+#ifdef ENCODE_SRGB
+    color.rgb = linear_to_srgb(color.rgb);
+#endif
+    f_color = color;
+}
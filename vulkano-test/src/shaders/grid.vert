@@ -0,0 +1,26 @@
+#version 450
+
+// A square of the ground plane, y = 0, centered under the eye and reaching the fade distance in
+// every direction, made from the vertex index alone. See `grid.rs`.
+layout(location = 0) out vec3 v_world_position;
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_proj;
+    // The eye in xyz, and how far from it the grid has faded out in w.
+    vec4 eye;
+} pc;
+
+void main() {
+    // Two triangles, winding counter-clockwise when seen from above.
+    const vec2 CORNERS[6] = vec2[6](
+        vec2(-1.0, -1.0),
+        vec2(-1.0, 1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(1.0, -1.0)
+    );
+    vec2 corner = pc.eye.xz + CORNERS[gl_VertexIndex] * pc.eye.w;
+    v_world_position = vec3(corner.x, 0.0, corner.y);
+    gl_Position = pc.view_proj * vec4(v_world_position, 1.0);
+}
@@ -0,0 +1,39 @@
+// What `GpuCuller` culls on the GPU. See `gpu_culling.rs`.
+
+// An entity to cull, which the vertex shader of the culled draws also reads back by its instance
+// index.
+struct CullInstance {
+    mat4 model;
+    vec4 base_color;
+    vec4 emissive;
+    // The bounds of the mesh in model space.
+    vec4 aabb_min;
+    vec4 aabb_max;
+    // The id of the entity, which its occlusion query is indexed by.
+    uint entity;
+    // The region of each level of detail of the mesh, from the most detailed.
+    uint first_region;
+    uint level_count;
+    float metallic;
+    float roughness;
+    uint padding0;
+    uint padding1;
+    uint padding2;
+};
+
+// The draws of one level of detail of a batch of instances of the same mesh, which are next to
+// each other in the instance buffer.
+struct CullRegion {
+    uint first_command;
+    uint index_count;
+    // The level is used once the mesh covers less than this fraction of the screen.
+    float max_screen_size;
+    uint level;
+    uint first_instance;
+    uint instance_count;
+    uint padding0;
+    uint padding1;
+};
+
+// The level that culled instances get instead of the one they would be drawn at.
+const uint CULLED = 0xffffffffu;
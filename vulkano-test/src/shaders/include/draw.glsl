@@ -0,0 +1,11 @@
+// The push constants of each draw of the scene, with the parameters of its material. The texture
+// index is only used with bindless textures, where it picks the base color texture out of the
+// global array.
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    vec4 base_color;
+    vec4 emissive;
+    float metallic;
+    float roughness;
+    uint texture_index;
+} pc;
@@ -0,0 +1,32 @@
+// Directions through the texels of cubemaps and of equirectangular environments. The faces of a
+// cubemap are its layers, in the order +X, -X, +Y, -Y, +Z, -Z.
+
+const float PI = 3.14159265359;
+
+// The direction through `uv` of cubemap face `face`, from (0, 0) at the top left of the face to
+// (1, 1) at the bottom right, as samplers pick the face and texel that a direction falls on.
+vec3 cube_direction(int face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    if (face == 0) {
+        return vec3(1.0, -st.y, -st.x);
+    } else if (face == 1) {
+        return vec3(-1.0, -st.y, st.x);
+    } else if (face == 2) {
+        return vec3(st.x, 1.0, st.y);
+    } else if (face == 3) {
+        return vec3(st.x, -1.0, -st.y);
+    } else if (face == 4) {
+        return vec3(st.x, -st.y, 1.0);
+    }
+    return vec3(-st.x, -st.y, -1.0);
+}
+
+// Where `direction` falls on an equirectangular environment, with the longitude across from -X
+// and the latitude down from +Y.
+vec2 equirect_uv(vec3 direction) {
+    direction = normalize(direction);
+    return vec2(
+        atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI
+    );
+}
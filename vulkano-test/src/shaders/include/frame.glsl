@@ -0,0 +1,12 @@
+// The camera and light of the frame, shared by every draw of the scene.
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view_proj;
+    // The direction towards the light.
+    vec4 light_direction;
+    // The color of the light premultiplied by its intensity.
+    vec4 light_color;
+    // How colors are written out, as `output_color` in `gamma.glsl` does.
+    uvec4 color_output;
+    // The position of the eye, which highlights are seen from.
+    vec4 eye;
+} frame;
@@ -0,0 +1,52 @@
+#include "frame.glsl"
+#include "srgb.glsl"
+
+// The threshold of `pixel` in an 8x8 Bayer matrix, from 0 to 1, whose index interleaves the bits
+// of x ^ y and y in reverse.
+float bayer_threshold(uvec2 pixel) {
+    uint x = pixel.x & 7u;
+    uint y = pixel.y & 7u;
+    uint xy = x ^ y;
+    uint index = ((xy & 1u) << 5u) | ((y & 1u) << 4u) | ((xy & 2u) << 2u) | ((y & 2u) << 1u)
+        | ((xy & 4u) >> 1u) | ((y & 4u) >> 2u);
+    return (float(index) + 0.5) / 64.0;
+}
+
+// The color to write out for the linear `color`, encoded to sRGB where the target won't encode it
+// itself (`color_output.z` is 2), as its format isn't sRGB. The gamma audit of the frame shows it
+// as it would look without that encoding, or with it done twice. Where the frame dithers, the
+// color is moved by less than a step of the target, where the target stores it, so that it is
+// rounded up or down depending on the pixel.
+vec3 output_color(vec3 color) {
+    if (frame.color_output.x == 1u) {
+        color = srgb_to_linear(color);
+    } else if (frame.color_output.x == 2u) {
+        color = linear_to_srgb(color);
+    }
+    if (frame.color_output.z == 2u) {
+        color = linear_to_srgb(color);
+    }
+
+    if (frame.color_output.y > 0u) {
+        float threshold = bayer_threshold(uvec2(gl_FragCoord.xy));
+        float offset = (threshold - 0.5) / float(frame.color_output.y);
+        color = frame.color_output.z == 1u
+            ? srgb_to_linear(linear_to_srgb(color) + offset)
+            : max(color + offset, vec3(0.0));
+    }
+    return color;
+}
+
+// The linear color that `output_color` wrote out as `color`, for passes reading the frame back.
+// The dithering is left in, as it is too faint to matter.
+vec3 written_color(vec3 color) {
+    if (frame.color_output.z == 2u) {
+        color = srgb_to_linear(color);
+    }
+    if (frame.color_output.x == 1u) {
+        return linear_to_srgb(color);
+    } else if (frame.color_output.x == 2u) {
+        return srgb_to_linear(color);
+    }
+    return color;
+}
@@ -0,0 +1,46 @@
+// The lights of the frame besides its main directional light, and the clusters of the view that
+// `LightCuller` binned the point and spot lights into. See `light_clusters.rs`.
+
+// A directional, point or spot light, in world space. A directional light only has a color and
+// the direction towards it, in direction_inner.
+struct ClusterLight {
+    // The position, and in w how far the light reaches.
+    vec4 position_range;
+    // The color premultiplied by the intensity.
+    vec4 color;
+    // The direction a spot light shines in, and in w the cosine of its inner cone angle.
+    vec4 direction_inner;
+    // The cosine of the outer cone angle in x, which is -2 for point lights so that they shine
+    // everywhere.
+    vec4 outer;
+};
+
+// How the view is split into clusters, and how many lights there are.
+struct ClusterGrid {
+    // From world space to view space.
+    mat4 view;
+    // From clip space back to view space.
+    mat4 inverse_proj;
+    // The number of clusters across, down and deep.
+    uvec4 size;
+    // The near and far clip planes, and the size of the viewport in pixels.
+    vec4 planes;
+    // How many of the lights at the start are directional in x, and how many there are in all in
+    // y. The point and spot lights come after the directional ones.
+    uvec4 light_counts;
+};
+
+// Each cluster takes this many slots of the cluster buffer: the number of lights it has, then the
+// index of each of them.
+const uint CLUSTER_STRIDE = MAX_LIGHTS_PER_CLUSTER + 1u;
+
+// The depth slice that a view depth falls in. Slices are thinner close up, where they cover less
+// of the scene, each one reaching the same factor further than the last.
+uint cluster_slice(float view_depth, ClusterGrid grid) {
+    float slice = log(view_depth / grid.planes.x) / log(grid.planes.y / grid.planes.x);
+    return uint(clamp(slice * float(grid.size.z), 0.0, float(grid.size.z - 1u)));
+}
+
+uint cluster_index(uvec3 cluster, ClusterGrid grid) {
+    return (cluster.z * grid.size.y + cluster.y) * grid.size.x + cluster.x;
+}
@@ -0,0 +1,42 @@
+#include "frame.glsl"
+
+// How much light reaches every surface, from everywhere.
+const float AMBIENT = 0.15;
+
+// Lights `base_color` on a surface facing `normal` with `light` of the frame's light, from 0 where
+// it is in shadow to 1, and a little ambient light so that the side facing away doesn't go black.
+vec3 lit_in_shadow(vec3 base_color, vec3 normal, float light) {
+    float diffuse = max(dot(normalize(normal), frame.light_direction.xyz), 0.0) * light;
+    return base_color * (AMBIENT + (1.0 - AMBIENT) * diffuse * frame.light_color.rgb);
+}
+
+// Lights a surface with the parameters of its material as `lit_in_shadow` does, seen from the
+// direction `to_eye`, along with the highlight of the frame's light. Metals have no diffuse light,
+// and their base color tints what they reflect, where dielectrics reflect a few percent of it.
+// The highlight is Blinn-Phong's, with an exponent that spreads it out as the roughness goes up.
+vec3 lit_material(
+    vec3 base_color,
+    float metallic,
+    float roughness,
+    vec3 normal,
+    vec3 to_eye,
+    float light
+) {
+    normal = normalize(normal);
+    vec3 diffuse = lit_in_shadow(base_color * (1.0 - metallic), normal, light);
+    vec3 reflectance = mix(vec3(0.04), base_color, metallic);
+
+    float alpha = max(roughness * roughness, 0.01);
+    float exponent = 2.0 / (alpha * alpha) - 2.0;
+    vec3 half_vector = normalize(frame.light_direction.xyz + to_eye);
+    // Kept above 0, as 0 to the power of 0 is undefined.
+    float highlight = pow(max(dot(normal, half_vector), 0.0001), exponent) * (exponent + 8.0) / 8.0;
+    float facing = max(dot(normal, frame.light_direction.xyz), 0.0) * light;
+    return diffuse
+        + reflectance * (AMBIENT + (1.0 - AMBIENT) * highlight * facing * frame.light_color.rgb);
+}
+
+// Lights `base_color` as `lit_in_shadow` does, with all of the light.
+vec3 lit(vec3 base_color, vec3 normal) {
+    return lit_in_shadow(base_color, normal, 1.0);
+}
@@ -0,0 +1,21 @@
+// What the path tracing stages share: the push constants and the payloads of the rays. See
+// `PathTracer`.
+
+layout(push_constant) uniform PushConstants {
+    mat4 inverse_view_proj;
+    vec4 eye;
+    // The radiance of the sky, which every ray that misses the scene sees.
+    vec4 sky_color;
+    // How many samples have been accumulated before this one.
+    uint sample_index;
+    // How many times a path bounces off surfaces before it is cut off.
+    uint max_bounces;
+} pc;
+
+// What a path ray found, which is nothing if `distance` is negative.
+struct Hit {
+    vec3 albedo;
+    float distance;
+    // The normal of the surface that was hit, turned towards the ray, in world space.
+    vec3 normal;
+};
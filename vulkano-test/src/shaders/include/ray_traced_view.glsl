@@ -0,0 +1,6 @@
+// The push constants of the ray traced view. See `RayTracer`.
+layout(push_constant) uniform PushConstants {
+    mat4 inverse_view_proj;
+    vec4 eye;
+    vec4 clear_color;
+} pc;
@@ -0,0 +1,46 @@
+// What the ray tracing stages share: the TLAS of the scene and the data of its instances. See
+// `AccelerationStructures`.
+#extension GL_EXT_ray_tracing : require
+#extension GL_EXT_buffer_reference : require
+
+#include "frame.glsl"
+
+// A mesh's vertex buffer, as eight floats per vertex: the position, the normal and the texture
+// coordinates.
+layout(buffer_reference, std430) readonly buffer Vertices {
+    float vertices[];
+};
+
+layout(buffer_reference, std430) readonly buffer Indices {
+    uint indices[];
+};
+
+// `InstanceData`, indexed by the instance's custom index.
+struct Instance {
+    Vertices vertices;
+    Indices indices;
+    vec4 base_color;
+};
+
+layout(set = 0, binding = 1) uniform accelerationStructureEXT tlas;
+layout(set = 0, binding = 2, std430) readonly buffer Instances {
+    Instance instances[];
+};
+
+// The normal of `instance`'s mesh at the point of `primitive` with `barycentrics`, interpolated
+// between its corners, in model space.
+vec3 interpolated_normal(Instance instance, uint primitive, vec2 barycentrics) {
+    uint first = primitive * 3u;
+    vec3 weights = vec3(1.0 - barycentrics.x - barycentrics.y, barycentrics);
+    vec3 normal = vec3(0.0);
+    for (uint corner = 0u; corner < 3u; corner++) {
+        // Eight floats per vertex, of which the normal is the second three.
+        uint base = instance.indices.indices[first + corner] * 8u;
+        normal += weights[corner] * vec3(
+            instance.vertices.vertices[base + 3u],
+            instance.vertices.vertices[base + 4u],
+            instance.vertices.vertices[base + 5u]
+        );
+    }
+    return normal;
+}
@@ -0,0 +1,14 @@
+// The sRGB transfer function, which sRGB formats apply when they are read from and written to.
+vec3 srgb_to_linear(vec3 color) {
+    color = max(color, vec3(0.0));
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(low, high, step(vec3(0.04045), color));
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    color = max(color, vec3(0.0));
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055;
+    return mix(low, high, step(vec3(0.0031308), color));
+}
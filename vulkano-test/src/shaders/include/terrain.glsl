@@ -0,0 +1,11 @@
+// The push constants of the terrain, which every chunk shares. See `terrain.rs`.
+layout(push_constant) uniform PushConstants {
+    // Where the camera is, which patches are subdivided more the closer they are to.
+    vec4 eye;
+    // The size of a pixel of the heightmap in world space along x and z in xy, the height of
+    // white pixels in z, and in w the distance within which patches are subdivided the most.
+    vec4 scale;
+    // The size of a pixel of the heightmap in texture coordinates in xy, and in z the most times
+    // that the edges of a patch are subdivided.
+    vec4 texel;
+} pc;
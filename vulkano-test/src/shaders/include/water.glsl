@@ -0,0 +1,11 @@
+// The push constants of the water surface. See `water.rs`.
+layout(push_constant) uniform PushConstants {
+    // From clip space back to world space, for finding what the refracted rays hit.
+    mat4 inverse_view_proj;
+    // Where the camera is, and in w the time in seconds that the waves are at.
+    vec4 eye;
+    // What the surface reflects.
+    vec4 sky_color;
+    // The height of the surface at rest in x, and its size along x and z in yz.
+    vec4 surface;
+} pc;
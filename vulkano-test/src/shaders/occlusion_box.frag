@@ -0,0 +1,5 @@
+#version 450
+
+// Occlusion queries only count the samples that pass the depth test, so nothing is written here.
+void main() {
+}
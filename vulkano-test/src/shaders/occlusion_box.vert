@@ -0,0 +1,14 @@
+#version 450
+
+// Places the unit cube over an object's bounding box.
+layout(location = 0) in vec3 position;
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_proj;
+    vec4 center;
+    vec4 size;
+} pc;
+
+void main() {
+    gl_Position = pc.view_proj * vec4(pc.center.xyz + position * pc.size.xyz, 1.0);
+}
@@ -0,0 +1,36 @@
+#version 450
+
+// ENCODE_SRGB is defined by `gamma::encoding_defines` where the target is encoded by hand.
+#ifdef ENCODE_SRGB
+#include "srgb.glsl"
+#endif
+
+// Draws the outline around the selected entity from the jump flood's result: the pixels outside
+// the entity within `width` of the nearest pixel inside it, fading out over the last pixel.
+layout(set = 0, binding = 0) uniform itexture2D seed_texture;
+layout(set = 0, binding = 1) uniform sampler seed_sampler;
+
+layout(push_constant) uniform PushConstants {
+    vec4 color;
+    float width;
+} pc;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    ivec2 seed = texelFetch(isampler2D(seed_texture, seed_sampler), pixel, 0).xy;
+    // Pixels inside the entity are their own seed.
+    if (seed.x == 0 || seed - 1 == pixel) {
+        discard;
+    }
+    float alpha = clamp(pc.width + 0.5 - distance(vec2(seed - 1), vec2(pixel)), 0.0, 1.0);
+    if (alpha == 0.0) {
+        discard;
+    }
+#ifdef ENCODE_SRGB
+    f_color = vec4(linear_to_srgb(pc.color.rgb), pc.color.a * alpha);
+#else
+    f_color = vec4(pc.color.rgb, pc.color.a * alpha);
+#endif
+}
@@ -0,0 +1,9 @@
+#version 450
+
+// Seeds the jump flood with every pixel that the selected entity covers, as its own coordinates
+// plus one, so that 0, the value the mask is cleared to, means no seed.
+layout(location = 0) out ivec4 f_seed;
+
+void main() {
+    f_seed = ivec4(ivec2(gl_FragCoord.xy) + 1, 0, 0);
+}
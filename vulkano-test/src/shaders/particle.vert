@@ -0,0 +1,32 @@
+#version 450
+
+// Draws each particle as a point, read straight from the buffer the simulation writes.
+struct Particle {
+    vec4 position;
+    vec4 velocity;
+};
+
+layout(set = 0, binding = 0) readonly buffer Particles {
+    Particle particles[];
+};
+
+layout(location = 0) out vec4 v_color;
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_proj;
+    float lifetime;
+} pc;
+
+void main() {
+    Particle particle = particles[gl_VertexIndex];
+    // Particles fade from yellow to red as they age.
+    float age = clamp(particle.position.w / pc.lifetime, 0.0, 1.0);
+    v_color = vec4(1.0, 1.0 - age, 0.2 * (1.0 - age), 1.0);
+    gl_PointSize = 1.0;
+    if (particle.velocity.w == 0.0) {
+        // Not spawned yet, so outside the clip volume.
+        gl_Position = vec4(0.0, 0.0, -1.0, 1.0);
+    } else {
+        gl_Position = pc.view_proj * vec4(particle.position.xyz, 1.0);
+    }
+}
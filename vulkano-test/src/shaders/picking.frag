@@ -0,0 +1,14 @@
+#version 450
+
+// Writes the ID of the entity being drawn, which `Picker` numbers from 1 so that 0, the value the
+// ID buffer is cleared to, means nothing.
+layout(location = 0) out uint f_id;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model_view_proj;
+    uint id;
+} pc;
+
+void main() {
+    f_id = pc.id;
+}
@@ -0,0 +1,13 @@
+#version 450
+
+// Places an entity for the ID buffer, which only needs its position.
+layout(location = 0) in vec3 position;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model_view_proj;
+    uint id;
+} pc;
+
+void main() {
+    gl_Position = pc.model_view_proj * vec4(position, 1.0);
+}
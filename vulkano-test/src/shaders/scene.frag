@@ -0,0 +1,136 @@
+#version 460
+
+#ifdef RAY_QUERY_SHADOWS
+// Only glslc compiles this variant, as naga has no ray queries. glslc only indexes the bindless
+// textures with the second extension.
+#extension GL_EXT_ray_query : require
+#extension GL_EXT_nonuniform_qualifier : require
+#endif
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec4 v_base_color;
+layout(location = 2) in vec2 v_uv;
+layout(location = 3) in vec3 v_world_position;
+layout(location = 4) flat in vec4 v_emissive;
+layout(location = 5) flat in vec2 v_metallic_roughness;
+
+layout(location = 0) out vec4 f_color;
+
+#include "gamma.glsl"
+#include "lighting.glsl"
+
+// The lights besides the main directional one, with the point and spot lights binned into the
+// clusters of the view. MAX_LIGHTS_PER_CLUSTER is
+// defined by `ScenePipeline`.
+#include "light_clusters.glsl"
+layout(set = 0, binding = 3) uniform Grid {
+    ClusterGrid grid;
+};
+layout(set = 0, binding = 4) readonly buffer Lights {
+    ClusterLight lights[];
+};
+layout(set = 0, binding = 5) readonly buffer Clusters {
+    uint clusters[];
+};
+
+#ifdef BINDLESS
+// Every texture, of which the draw picks its own. See `BindlessTextures`.
+#include "draw.glsl"
+layout(set = 1, binding = 0) uniform sampler base_color_sampler;
+layout(set = 1, binding = 1) uniform texture2D textures[];
+#define base_color_texture textures[pc.texture_index]
+#else
+layout(set = 1, binding = 0) uniform texture2D base_color_texture;
+layout(set = 1, binding = 1) uniform sampler base_color_sampler;
+#endif
+
+#ifdef RAY_QUERY_SHADOWS
+// The TLAS of the scene, which shadow rays are traced through towards the light.
+layout(set = 0, binding = 1) uniform accelerationStructureEXT tlas;
+#endif
+
+// Whether the color is lit, rather than the base color as it is.
+layout(constant_id = 0) const bool SHADED = true;
+// Whether the base color is multiplied with the texture.
+layout(constant_id = 1) const bool TEXTURED = true;
+
+// How much of the light reaches the fragment, which is 0 if anything in the scene lies between
+// them. The ray starts a little off the surface so that it doesn't hit the triangle it starts on.
+float light_visibility() {
+#ifdef RAY_QUERY_SHADOWS
+    vec3 origin = v_world_position + normalize(v_normal) * 0.001;
+    rayQueryEXT query;
+    rayQueryInitializeEXT(
+        query,
+        tlas,
+        gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT,
+        0xff,
+        origin,
+        0.001,
+        frame.light_direction.xyz,
+        10000.0
+    );
+    while (rayQueryProceedEXT(query)) {}
+    if (rayQueryGetIntersectionTypeEXT(query, true) != gl_RayQueryCommittedIntersectionNoneEXT) {
+        return 0.0;
+    }
+#endif
+    return 1.0;
+}
+
+// The light that falls on the fragment from the other directional lights, and from the point and
+// spot lights of its cluster. Those fade out with the square of the distance, and smoothly to
+// nothing at the edge of their range.
+vec3 cluster_light(vec3 normal) {
+    vec3 light = vec3(0.0);
+    for (uint i = 0u; i < grid.light_counts.x; i++) {
+        light += lights[i].color.rgb * max(dot(normal, lights[i].direction_inner.xyz), 0.0);
+    }
+    if (grid.light_counts.y == grid.light_counts.x) {
+        return light;
+    }
+    uvec2 tile = uvec2(gl_FragCoord.xy / grid.planes.zw * vec2(grid.size.xy));
+    tile = min(tile, grid.size.xy - 1u);
+    // The w of the clip-space position is the view depth, of which gl_FragCoord holds the inverse.
+    uint slice = cluster_slice(1.0 / gl_FragCoord.w, grid);
+    uint first = cluster_index(uvec3(tile, slice), grid) * CLUSTER_STRIDE;
+
+    for (uint i = 0u; i < clusters[first]; i++) {
+        ClusterLight cluster_light = lights[clusters[first + 1u + i]];
+        vec3 to_light = cluster_light.position_range.xyz - v_world_position;
+        float distance = length(to_light);
+        vec3 direction = to_light / distance;
+        float window = clamp(1.0 - pow(distance / cluster_light.position_range.w, 4.0), 0.0, 1.0);
+        float attenuation = window * window / (distance * distance + 1.0);
+        float cone = smoothstep(
+            cluster_light.outer.x,
+            cluster_light.direction_inner.w,
+            dot(-direction, cluster_light.direction_inner.xyz)
+        );
+        light += cluster_light.color.rgb * max(dot(normal, direction), 0.0) * attenuation * cone;
+    }
+    return light;
+}
+
+void main() {
+    vec4 base_color = v_base_color;
+    if (TEXTURED) {
+        base_color *= texture(sampler2D(base_color_texture, base_color_sampler), v_uv);
+    }
+    // The other lights only light the surface diffusely, whatever its material.
+    vec3 to_eye = normalize(frame.eye.xyz - v_world_position);
+    vec3 color = SHADED
+        ? lit_material(
+            base_color.rgb,
+            v_metallic_roughness.x,
+            v_metallic_roughness.y,
+            v_normal,
+            to_eye,
+            light_visibility()
+        )
+            + base_color.rgb * cluster_light(normalize(v_normal))
+            + v_emissive.rgb
+        : base_color.rgb;
+
+    f_color = vec4(output_color(color), base_color.a);
+}
@@ -0,0 +1,47 @@
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec4 v_base_color;
+layout(location = 2) out vec2 v_uv;
+layout(location = 3) out vec3 v_world_position;
+layout(location = 4) flat out vec4 v_emissive;
+layout(location = 5) flat out vec2 v_metallic_roughness;
+
+#include "frame.glsl"
+
+#include "draw.glsl"
+
+#ifdef GPU_CULLED
+// The instances that `GpuCuller` culled, of which each draw is of the one at its instance index.
+// The transform and material are read from there rather than pushed.
+#include "culling.glsl"
+layout(set = 0, binding = 2) readonly buffer Instances {
+    CullInstance instances[];
+};
+#endif
+
+void main() {
+#ifdef GPU_CULLED
+    CullInstance instance = instances[gl_InstanceIndex];
+    mat4 model = instance.model;
+    vec4 base_color = instance.base_color;
+    v_emissive = instance.emissive;
+    v_metallic_roughness = vec2(instance.metallic, instance.roughness);
+#else
+    mat4 model = pc.model;
+    vec4 base_color = pc.base_color;
+    v_emissive = pc.emissive;
+    v_metallic_roughness = vec2(pc.metallic, pc.roughness);
+#endif
+    // Ignores non-uniform scaling, which is good enough for shading.
+    v_normal = mat3(model) * normal;
+    v_base_color = base_color;
+    v_uv = uv;
+    vec4 world_position = model * vec4(position, 1.0);
+    v_world_position = world_position.xyz;
+    gl_Position = frame.view_proj * world_position;
+}
@@ -0,0 +1,164 @@
+// The mesh shading path of the scene shaders, drawing a mesh from its meshlets rather than its
+// vertex and index buffers. One task workgroup culls up to MESHLETS_PER_TASK meshlets against the
+// frustum and launches a mesh workgroup for each survivor, which transforms its vertices and
+// writes its triangles. The outputs are those of `scene.vert`, for `scene.frag` to shade.
+enable wgpu_mesh_shader;
+
+const MESHLETS_PER_TASK: u32 = 32u;
+const WORKGROUP_SIZE: u32 = 32u;
+// The limits that `meshlet::build` keeps meshlets within.
+const MAX_VERTICES: u32 = 64u;
+const MAX_TRIANGLES: u32 = 124u;
+
+struct Frame {
+    view_proj: mat4x4<f32>,
+    light_direction: vec4<f32>,
+    light_color: vec4<f32>,
+    color_output: vec4<u32>,
+    eye: vec4<f32>,
+}
+
+struct PushConstants {
+    model: mat4x4<f32>,
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    texture_index: u32,
+}
+
+struct Meshlet {
+    // The bounding sphere of the meshlet's vertices.
+    center: vec3<f32>,
+    radius: f32,
+    vertex_offset: u32,
+    vertex_count: u32,
+    triangle_offset: u32,
+    triangle_count: u32,
+}
+
+@group(0) @binding(0) var<uniform> frame: Frame;
+var<immediate> pc: PushConstants;
+
+@group(2) @binding(0) var<storage, read> meshlets: array<Meshlet>;
+// The index of each meshlet vertex in `vertices`.
+@group(2) @binding(1) var<storage, read> meshlet_vertices: array<u32>;
+// Three 8-bit indices into the meshlet's vertices per triangle.
+@group(2) @binding(2) var<storage, read> meshlet_triangles: array<u32>;
+// The mesh's vertex buffer: a position, a normal and texture coordinates per vertex.
+@group(2) @binding(3) var<storage, read> vertices: array<f32>;
+
+struct Payload {
+    meshlets: array<u32, MESHLETS_PER_TASK>,
+}
+
+var<task_payload> payload: Payload;
+var<workgroup> visible_count: atomic<u32>;
+
+// Whether a sphere in model space is at least partly inside the frustum of `mvp`. Each plane comes
+// out of the matrix unnormalized, which the radius is scaled by instead.
+fn in_frustum(mvp: mat4x4<f32>, center: vec3<f32>, radius: f32) -> bool {
+    let rows = transpose(mvp);
+    let planes = array(
+        rows[3] + rows[0],
+        rows[3] - rows[0],
+        rows[3] + rows[1],
+        rows[3] - rows[1],
+        rows[2],
+        rows[3] - rows[2],
+    );
+    for (var i = 0; i < 6; i++) {
+        let plane = planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius * length(plane.xyz) {
+            return false;
+        }
+    }
+    return true;
+}
+
+@task
+@payload(payload)
+@workgroup_size(WORKGROUP_SIZE)
+fn task_main(
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) -> @builtin(mesh_task_size) vec3<u32> {
+    if local == 0u {
+        atomicStore(&visible_count, 0u);
+    }
+    workgroupBarrier();
+
+    let index = workgroup_id.x * MESHLETS_PER_TASK + local;
+    if index < arrayLength(&meshlets) {
+        let meshlet = meshlets[index];
+        if in_frustum(frame.view_proj * pc.model, meshlet.center, meshlet.radius) {
+            payload.meshlets[atomicAdd(&visible_count, 1u)] = index;
+        }
+    }
+    workgroupBarrier();
+
+    return vec3(atomicLoad(&visible_count), 1u, 1u);
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) base_color: vec4<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) @interpolate(flat) emissive: vec4<f32>,
+    @location(5) @interpolate(flat) metallic_roughness: vec2<f32>,
+}
+
+struct PrimitiveOutput {
+    @builtin(triangle_indices) indices: vec3<u32>,
+}
+
+struct MeshOutput {
+    @builtin(vertices) vertices: array<VertexOutput, MAX_VERTICES>,
+    @builtin(primitives) primitives: array<PrimitiveOutput, MAX_TRIANGLES>,
+    @builtin(vertex_count) vertex_count: u32,
+    @builtin(primitive_count) primitive_count: u32,
+}
+
+var<workgroup> mesh_output: MeshOutput;
+
+@mesh(mesh_output)
+@payload(payload)
+@workgroup_size(WORKGROUP_SIZE)
+fn mesh_main(
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let meshlet = meshlets[payload.meshlets[workgroup_id.x]];
+    mesh_output.vertex_count = meshlet.vertex_count;
+    mesh_output.primitive_count = meshlet.triangle_count;
+
+    let model = pc.model;
+    let normal_matrix = mat3x3(model[0].xyz, model[1].xyz, model[2].xyz);
+    for (var i = local; i < meshlet.vertex_count; i += WORKGROUP_SIZE) {
+        let base = meshlet_vertices[meshlet.vertex_offset + i] * 8u;
+        let position = vec3(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+        let normal = vec3(vertices[base + 3u], vertices[base + 4u], vertices[base + 5u]);
+
+        let world_position = model * vec4(position, 1.0);
+        var clip_position = frame.view_proj * world_position;
+        // The camera's projection is Vulkan's, whose Y axis points down, but naga flips WGSL's
+        // clip space as if it pointed up.
+        clip_position.y = -clip_position.y;
+        mesh_output.vertices[i].position = clip_position;
+        // Ignores non-uniform scaling, which is good enough for shading.
+        mesh_output.vertices[i].normal = normal_matrix * normal;
+        mesh_output.vertices[i].base_color = pc.base_color;
+        mesh_output.vertices[i].uv = vec2(vertices[base + 6u], vertices[base + 7u]);
+        mesh_output.vertices[i].world_position = world_position.xyz;
+        mesh_output.vertices[i].emissive = pc.emissive;
+        mesh_output.vertices[i].metallic_roughness = vec2(pc.metallic, pc.roughness);
+    }
+
+    for (var i = local; i < meshlet.triangle_count; i += WORKGROUP_SIZE) {
+        let packed = meshlet_triangles[meshlet.triangle_offset + i];
+        mesh_output.primitives[i].indices =
+            vec3(packed & 0xffu, (packed >> 8u) & 0xffu, (packed >> 16u) & 0xffu);
+    }
+}
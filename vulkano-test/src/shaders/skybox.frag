@@ -0,0 +1,22 @@
+#version 450
+
+// ENCODE_SRGB is defined by `gamma::encoding_defines` where the target is encoded by hand.
+#ifdef ENCODE_SRGB
+#include "srgb.glsl"
+#endif
+
+// The environment in the direction of the pixel, behind everything else.
+layout(location = 0) in vec3 v_direction;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform textureCube environment_texture;
+layout(set = 0, binding = 1) uniform sampler environment_sampler;
+
+void main() {
+    vec3 color = texture(samplerCube(environment_texture, environment_sampler), v_direction).rgb;
+#ifdef ENCODE_SRGB
+    color = linear_to_srgb(color);
+#endif
+    f_color = vec4(color, 1.0);
+}
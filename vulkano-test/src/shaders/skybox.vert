@@ -0,0 +1,17 @@
+#version 450
+
+// A triangle covering the viewport on the far plane, made from the vertex index alone, with the
+// direction from the eye to each of its corners. See `skybox.rs`.
+layout(location = 0) out vec3 v_direction;
+
+layout(push_constant) uniform PushConstants {
+    mat4 inverse_view_proj;
+    vec4 eye;
+} pc;
+
+void main() {
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    vec4 far = pc.inverse_view_proj * vec4(position, 1.0, 1.0);
+    v_direction = far.xyz / far.w - pc.eye.xyz;
+    gl_Position = vec4(position, 1.0, 1.0);
+}
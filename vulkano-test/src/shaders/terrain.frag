@@ -0,0 +1,35 @@
+#version 450
+
+// Colors the terrain by its height and slope: sand at the bottom, grass above it, snow at the top,
+// and bare rock wherever it is too steep for any of them.
+layout(location = 0) in vec3 v_normal;
+layout(location = 2) in vec3 v_world_position;
+
+layout(location = 0) out vec4 f_color;
+
+#include "gamma.glsl"
+#include "lighting.glsl"
+
+#include "terrain.glsl"
+
+// Whether the color is lit, rather than shown as it is for the wireframe.
+layout(constant_id = 0) const bool SHADED = true;
+
+const vec3 SAND = vec3(0.76, 0.7, 0.5);
+const vec3 GRASS = vec3(0.2, 0.45, 0.12);
+const vec3 ROCK = vec3(0.4, 0.37, 0.35);
+const vec3 SNOW = vec3(0.95, 0.95, 0.97);
+
+void main() {
+    vec3 normal = normalize(v_normal);
+    // Both from 0 to 1: the height from the lowest pixels to white ones, and the slope from flat
+    // to vertical.
+    float height = clamp(v_world_position.y / max(pc.scale.z, 0.001), 0.0, 1.0);
+    float slope = 1.0 - normal.y;
+
+    vec3 color = mix(SAND, GRASS, smoothstep(0.05, 0.1, height));
+    color = mix(color, SNOW, smoothstep(0.7, 0.8, height));
+    color = mix(color, ROCK, smoothstep(0.2, 0.35, slope));
+
+    f_color = vec4(output_color(SHADED ? lit(color, normal) : color), 1.0);
+}
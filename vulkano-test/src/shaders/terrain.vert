@@ -0,0 +1,20 @@
+#version 450
+
+// The terrain is made in world space, so its vertices are only projected. With tessellation, the
+// control shader takes the vertices as the corners of its patches instead.
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec2 v_uv;
+layout(location = 2) out vec3 v_world_position;
+
+#include "frame.glsl"
+
+void main() {
+    v_normal = normal;
+    v_uv = uv;
+    v_world_position = position;
+    gl_Position = frame.view_proj * vec4(position, 1.0);
+}
@@ -0,0 +1,63 @@
+#version 450
+
+// Shades the water surface from the scene behind it, which was drawn first and copied aside. The
+// scene is refracted by offsetting where it is read by the normal of the waves, and tinted by
+// how much water the light went through, so shallow water is clear and deep water takes its own
+// color. The surface reflects the sky, more so at grazing angles as the Fresnel term has it, and
+// shows a highlight of the light.
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec3 v_world_position;
+
+layout(location = 0) out vec4 f_color;
+
+#include "frame.glsl"
+#include "gamma.glsl"
+
+#include "water.glsl"
+
+layout(set = 0, binding = 1) uniform texture2D scene_color;
+layout(set = 0, binding = 2) uniform texture2D scene_depth;
+layout(set = 0, binding = 3) uniform sampler scene_sampler;
+
+// How far the waves offset what is seen through them, in texture coordinates.
+const float REFRACTION_STRENGTH = 0.03;
+// How much of each color is absorbed over a unit of distance through the water.
+const vec3 ABSORPTION = vec3(0.45, 0.12, 0.08);
+const vec3 DEEP_COLOR = vec3(0.01, 0.06, 0.1);
+// The reflectance of water seen head-on.
+const float BASE_REFLECTANCE = 0.02;
+
+float depth_at(vec2 uv) {
+    ivec2 size = textureSize(sampler2D(scene_depth, scene_sampler), 0);
+    ivec2 texel = clamp(ivec2(uv * vec2(size)), ivec2(0), size - 1);
+    return texelFetch(sampler2D(scene_depth, scene_sampler), texel, 0).r;
+}
+
+void main() {
+    vec3 normal = normalize(v_normal);
+    vec2 size = vec2(textureSize(sampler2D(scene_color, scene_sampler), 0));
+    vec2 uv = gl_FragCoord.xy / size;
+
+    // What is in front of the surface isn't refracted, so where the offset lands on it the
+    // scene straight behind is used instead.
+    vec2 refracted_uv = uv + normal.xz * REFRACTION_STRENGTH;
+    float depth = depth_at(refracted_uv);
+    if (depth < gl_FragCoord.z) {
+        refracted_uv = uv;
+        depth = depth_at(uv);
+    }
+    vec3 behind = written_color(texture(sampler2D(scene_color, scene_sampler), refracted_uv).rgb);
+
+    vec4 scene_position = pc.inverse_view_proj * vec4(refracted_uv * 2.0 - 1.0, depth, 1.0);
+    float thickness = distance(scene_position.xyz / scene_position.w, v_world_position);
+    vec3 transmittance = exp(-ABSORPTION * thickness);
+    vec3 refracted = mix(DEEP_COLOR, behind, transmittance);
+
+    vec3 view = normalize(pc.eye.xyz - v_world_position);
+    float facing = max(dot(normal, view), 0.0);
+    float fresnel = BASE_REFLECTANCE + (1.0 - BASE_REFLECTANCE) * pow(1.0 - facing, 5.0);
+    vec3 halfway = normalize(frame.light_direction.xyz + view);
+    vec3 highlight = pow(max(dot(normal, halfway), 0.0), 256.0) * frame.light_color.rgb;
+
+    f_color = vec4(output_color(mix(refracted, pc.sky_color.rgb, fresnel) + highlight), 1.0);
+}
@@ -0,0 +1,50 @@
+#version 450
+
+// A grid of `GRID_QUADS` by `GRID_QUADS` quads made from the vertex index alone, raised by a sum of
+// sine waves. See `water.rs`.
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec3 v_world_position;
+
+#include "frame.glsl"
+
+#include "water.glsl"
+
+// The waves, each as its direction in xy, its amplitude in z and its wavelength in w.
+const vec4 WAVES[4] = vec4[4](
+    vec4(0.8, 0.6, 0.06, 9.0),
+    vec4(-0.6, 0.8, 0.04, 5.0),
+    vec4(0.3, -0.95, 0.025, 3.1),
+    vec4(-0.9, -0.45, 0.015, 1.7)
+);
+
+const float GRAVITY = 9.81;
+const float TAU = 6.283185307;
+
+void main() {
+    uint quad = uint(gl_VertexIndex) / 6u;
+    uint corner = uint(gl_VertexIndex) % 6u;
+    // Two triangles a quad, winding counter-clockwise when seen from above.
+    uvec2 offset = uvec2(
+        uint(corner == 2u || corner == 3u || corner == 5u),
+        uint(corner == 1u || corner == 4u || corner == 5u)
+    );
+    vec2 cell = vec2(uvec2(quad % uint(GRID_QUADS), quad / uint(GRID_QUADS)) + offset);
+    vec2 xz = (cell / float(GRID_QUADS) - 0.5) * pc.surface.yz;
+
+    float height = 0.0;
+    vec2 slope = vec2(0.0);
+    for (int i = 0; i < 4; i++) {
+        vec2 direction = normalize(WAVES[i].xy);
+        float wavenumber = TAU / WAVES[i].w;
+        // Deep water waves, whose speed grows with their wavelength.
+        float frequency = sqrt(GRAVITY * wavenumber);
+        float phase = wavenumber * dot(direction, xz) - frequency * pc.eye.w;
+        height += WAVES[i].z * sin(phase);
+        slope += WAVES[i].z * wavenumber * cos(phase) * direction;
+    }
+
+    vec3 position = vec3(xz.x, pc.surface.x + height, xz.y);
+    v_normal = normalize(vec3(-slope.x, 1.0, -slope.y));
+    v_world_position = position;
+    gl_Position = frame.view_proj * vec4(position, 1.0);
+}
@@ -0,0 +1,40 @@
+// Shadows traced with ray queries, on devices where `--ray-query-shadows` could enable
+// `VK_KHR_ray_query`. The fragment shader of the scene traces a ray from every fragment towards the
+// light through the TLAS of the scene (see `acceleration.rs`), and leaves out the light wherever
+// the ray hits something. There is no other kind of shadow, so without ray queries the scene is
+// lit as if nothing cast any. `H` turns them off and on again in the window.
+//
+// naga can't translate ray queries, so the shadowed variants of `shaders/scene.frag` are compiled
+// with `glslc`, with `RAY_QUERY_SHADOWS` defined. The unshadowed variants are still naga's.
+
+use vulkano::device::{DeviceExtensions, DeviceFeatures};
+
+use crate::device_requirements::DeviceRequirements;
+
+/// The features ray-query shadows need. Acceleration structures are built from the addresses of
+/// the mesh buffers.
+pub const FEATURES: DeviceFeatures = DeviceFeatures {
+    ray_query: true,
+    acceleration_structure: true,
+    buffer_device_address: true,
+    ..DeviceFeatures::empty()
+};
+
+/// Asks for ray queries where supported. Like ray tracing pipelines, they need the extensions of
+/// the features that they build on on Vulkan 1.1.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_extension_features(
+        FEATURES,
+        DeviceExtensions {
+            khr_ray_query: true,
+            khr_acceleration_structure: true,
+            khr_deferred_host_operations: true,
+            khr_buffer_device_address: true,
+            ext_descriptor_indexing: true,
+            khr_maintenance3: true,
+            khr_spirv_1_4: true,
+            khr_shader_float_controls: true,
+            ..DeviceExtensions::empty()
+        },
+    );
+}
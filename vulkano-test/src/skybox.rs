@@ -0,0 +1,192 @@
+// The environment map behind the scene. A single triangle covering the viewport is drawn on the
+// far plane, made by the vertex shader along with the direction from the eye through each of its
+// corners, and the fragment shader looks the environment's cubemap up in the direction of each
+// pixel. It is drawn after the scene, tested against its depth without writing any, so that it
+// only fills the pixels that nothing else was drawn on.
+
+use glam::{Mat4, Vec3};
+use std::sync::Arc;
+use vulkano::{
+    buffer::BufferContents,
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::DeviceOwned,
+    image::{
+        sampler::{Filter, Sampler, SamplerCreateInfo},
+        view::ImageView,
+    },
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendAttachmentState,
+            depth_stencil::{CompareOp, DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+};
+
+use crate::{
+    debug_utils,
+    error::AppError,
+    gamma, offscreen,
+    shader::{self, ShaderStage},
+};
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    inverse_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+}
+
+pub struct SkyboxPipeline {
+    pipeline: Arc<GraphicsPipeline>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    sampler: Arc<Sampler>,
+    /// The cubemap last drawn, and the descriptor set that binds it.
+    descriptor_set: Option<(Arc<ImageView>, Arc<DescriptorSet>)>,
+}
+
+impl SkyboxPipeline {
+    /// Creates the pipeline of the skybox, for drawing into `subpass` of a target with a depth
+    /// buffer.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/skybox.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/skybox.frag"),
+            ShaderStage::Fragment,
+            &gamma::encoding_defines(&subpass),
+        )?
+        .entry_point("main")
+        .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+        let pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                // The triangle is made from the vertex index.
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                rasterization_state: Some(RasterizationState::default()),
+                // The triangle lies on the far plane, which is what the depth buffer is cleared
+                // to, so it passes where nothing nearer was drawn.
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState {
+                        write_enable: false,
+                        compare_op: CompareOp::LessOrEqual,
+                    }),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(offscreen::color_blend_state(
+                    &subpass,
+                    &[ColorBlendAttachmentState::default()],
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "skybox");
+
+        let sampler = Sampler::new(
+            device,
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                ..Default::default()
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+
+        Ok(SkyboxPipeline {
+            pipeline,
+            descriptor_set_allocator,
+            sampler,
+            descriptor_set: None,
+        })
+    }
+
+    /// Records `cubemap`, the view of an environment's cubemap, into the current subpass, seen
+    /// through `view_proj` from `eye` in `viewport`.
+    pub fn draw<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        cubemap: &Arc<ImageView>,
+        view_proj: Mat4,
+        eye: Vec3,
+        viewport: Viewport,
+    ) {
+        if self
+            .descriptor_set
+            .as_ref()
+            .is_none_or(|(drawn, _)| !Arc::ptr_eq(drawn, cubemap))
+        {
+            let descriptor_set = DescriptorSet::new(
+                self.descriptor_set_allocator.clone(),
+                self.pipeline.layout().set_layouts()[0].clone(),
+                [
+                    WriteDescriptorSet::image_view(0, cubemap.clone()),
+                    WriteDescriptorSet::sampler(1, self.sampler.clone()),
+                ],
+                [],
+            )
+            .unwrap();
+            self.descriptor_set = Some((cubemap.clone(), descriptor_set));
+        }
+        let (_, descriptor_set) = self.descriptor_set.clone().unwrap();
+
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .unwrap()
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.pipeline.layout().clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                self.pipeline.layout().clone(),
+                0,
+                PushConstants {
+                    inverse_view_proj: view_proj.inverse().to_cols_array_2d(),
+                    eye: eye.extend(1.0).to_array(),
+                },
+            )
+            .unwrap();
+        // SAFETY: the shader makes the triangle from the vertex index alone.
+        unsafe { builder.draw(3, 1, 0, 0) }.unwrap();
+    }
+}
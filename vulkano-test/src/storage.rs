@@ -0,0 +1,89 @@
+// Storage buffers, for data that lives on the GPU and that shaders both read and write, such as the
+// state of a simulation. A `StorageBuffer` is device-local: the CPU only fills it once, through a
+// staging copy, and from then on compute shaders update it and later passes, compute or graphics,
+// read the result through a `binding` of it.
+//
+// Nothing has to be synchronized by hand. vulkano tracks how every command in a command buffer
+// accesses the buffer, and inserts a buffer barrier wherever one depends on another: between the
+// staging copy and the first dispatch, between a dispatch writing the buffer and a vertex shader
+// reading it, and between that draw and the dispatch of the next frame.
+
+use std::sync::Arc;
+use vulkano::{
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, CopyBufferInfo},
+    descriptor_set::WriteDescriptorSet,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+pub struct StorageBuffer<T> {
+    buffer: Subbuffer<[T]>,
+}
+
+impl<T: BufferContents> StorageBuffer<T> {
+    /// Creates a buffer holding `data`, which must not be empty, recording the copy into it into
+    /// `builder`. It can also be used as described by `usage`, such as to be drawn from.
+    pub fn from_iter<L, I>(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        usage: BufferUsage,
+        data: I,
+    ) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let staging_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            data,
+        )
+        .unwrap();
+        let buffer = Buffer::new_slice::<T>(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST | usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..Default::default()
+            },
+            staging_buffer.len(),
+        )
+        .unwrap();
+
+        builder
+            .copy_buffer(CopyBufferInfo::buffers(staging_buffer, buffer.clone()))
+            .unwrap();
+
+        StorageBuffer { buffer }
+    }
+
+    /// The number of elements in the buffer.
+    pub fn len(&self) -> u64 {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.len() == 0
+    }
+
+    pub fn buffer(&self) -> &Subbuffer<[T]> {
+        &self.buffer
+    }
+
+    /// A descriptor set write binding the whole buffer at `binding`. Whether shaders may write to
+    /// it through that binding is up to how they declare it.
+    pub fn binding(&self, binding: u32) -> WriteDescriptorSet {
+        WriteDescriptorSet::buffer(binding, self.buffer.clone())
+    }
+}
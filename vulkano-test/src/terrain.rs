@@ -0,0 +1,623 @@
+// Heightmap terrain. A grayscale image becomes a grid with a vertex at every pixel, raised by the
+// brightness of the pixel, which is split into chunks of `CHUNK_QUADS` by `CHUNK_QUADS` quads.
+// Every chunk has its own bounds, and only those inside the camera frustum are drawn. The terrain
+// has no material: it is colored by its height and slope instead, with sand at the bottom, grass
+// above it and snow at the top, and rock wherever it is steep.
+//
+// Where the device supports tessellation shaders, the chunks are drawn as coarser patches of
+// `PATCH_QUADS` by `PATCH_QUADS` quads instead, which the tessellation shaders subdivide more the
+// closer they are to the camera, up to a vertex per pixel again, taking the heights from the
+// heightmap as a texture. naga can't translate tessellation stages, so those are compiled with
+// `glslc`, and without it the full grid is drawn everywhere.
+//
+// The terrain is drawn by a pipeline of its own next to the scene's, which GPU and occlusion
+// culling, shadows and the ray traced views leave out.
+
+use glam::{Mat4, Vec2, Vec3};
+use image::ImageError;
+use serde::{Deserialize, Serialize};
+use std::{iter, path::PathBuf, sync::Arc};
+use tracing::warn;
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer,
+    },
+    command_buffer::{allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::{DeviceFeatures, DeviceOwned, Queue},
+    format::Format,
+    image::sampler::{Sampler, SamplerCreateInfo},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendAttachmentState,
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            multisample::MultisampleState,
+            rasterization::{CullMode, PolygonMode, RasterizationState},
+            tessellation::TessellationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        DynamicState, GraphicsPipeline, PipelineBindPoint, PipelineLayout,
+        PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+};
+
+use crate::{
+    bounds::{Aabb, Frustum},
+    components::Light,
+    debug_utils,
+    device_requirements::{Capabilities, DeviceRequirements},
+    error::AppError,
+    gamma::Output,
+    mesh::{Mesh, MeshVertex},
+    offscreen,
+    scene_pipeline::FrameUniforms,
+    shader::{self, ShaderSource, ShaderStage, TessellationStage},
+    texture::Texture,
+    variants::ShaderVariants,
+};
+
+/// How many quads wide and deep a chunk is, at most.
+pub const CHUNK_QUADS: u32 = 64;
+
+/// How many quads wide and deep a patch is, at most, which is also the most that tessellation
+/// subdivides its edges. Chunks are made of whole patches.
+pub const PATCH_QUADS: u32 = 8;
+
+/// Patches are subdivided the most up to this many times their own size away from the camera,
+/// and less and less further away.
+const FULL_DETAIL_PATCHES: f32 = 4.0;
+
+/// The format of the heightmap texture, which the tessellation evaluation shader filters itself.
+const HEIGHTMAP_FORMAT: Format = Format::R32_SFLOAT;
+
+/// How a scene file describes its terrain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TerrainDesc {
+    /// The heightmap image, whose brightness is the height. 16-bit grayscale images keep the most
+    /// detail.
+    pub heightmap: PathBuf,
+    /// How large the terrain is along x and z. It is centered on the origin.
+    pub size: Vec2,
+    /// The height of white pixels. Black ones are at 0.
+    pub height: f32,
+}
+
+struct Chunk {
+    /// The full grid of the chunk, with its bounds in world space.
+    mesh: Arc<Mesh>,
+    /// The triangles of the coarse patches, between vertices of `mesh`, if the terrain is
+    /// tessellated.
+    patch_indices: Option<Subbuffer<[u32]>>,
+}
+
+/// A terrain built from a heightmap and uploaded to the GPU.
+pub struct Terrain {
+    pub desc: TerrainDesc,
+    chunks: Vec<Chunk>,
+    /// The heights, from 0 to 1, where tessellation can sample them.
+    heightmap: Option<Arc<Texture>>,
+    /// The size of a pixel of the heightmap, along x and z in world space.
+    spacing: Vec2,
+    /// The size of the heightmap in pixels.
+    extent: [u32; 2],
+    bounds: Aabb,
+}
+
+impl Terrain {
+    /// Reads the heightmap of `desc` and uploads the terrain made from it, waiting for the
+    /// heightmap texture to be uploaded as well where the device can tessellate.
+    pub fn load(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+        desc: TerrainDesc,
+    ) -> Result<Terrain, ImageError> {
+        let pixels = image::open(&desc.heightmap)?.into_luma16();
+        let (width, height) = pixels.dimensions();
+        let heights: Vec<f32> = pixels
+            .pixels()
+            .map(|pixel| f32::from(pixel.0[0]) / f32::from(u16::MAX))
+            .collect();
+
+        let spacing = desc.size / Vec2::new(width.max(2) as f32 - 1.0, height.max(2) as f32 - 1.0);
+        let origin = -desc.size * 0.5;
+        let height_at = |x: u32, z: u32| heights[(z * width + x) as usize] * desc.height;
+        let vertex = |x: u32, z: u32| {
+            // The normal is across the pixels to either side, or the pixel itself at the edges.
+            let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+            let (back, front) = (z.saturating_sub(1), (z + 1).min(height - 1));
+            let slope_x = (height_at(right, z) - height_at(left, z))
+                / ((right - left).max(1) as f32 * spacing.x);
+            let slope_z = (height_at(x, front) - height_at(x, back))
+                / ((front - back).max(1) as f32 * spacing.y);
+            MeshVertex {
+                position: [
+                    origin.x + x as f32 * spacing.x,
+                    height_at(x, z),
+                    origin.y + z as f32 * spacing.y,
+                ],
+                normal: Vec3::new(-slope_x, 1.0, -slope_z).normalize().to_array(),
+                // The centers of the pixels, for sampling the heightmap texture.
+                uv: [
+                    (x as f32 + 0.5) / width as f32,
+                    (z as f32 + 0.5) / height as f32,
+                ],
+            }
+        };
+
+        let tessellated = Capabilities::of(memory_allocator.device()).tessellation;
+        let quads = [width.saturating_sub(1), height.saturating_sub(1)];
+        let mut chunks = Vec::new();
+        for chunk_z in (0..quads[1]).step_by(CHUNK_QUADS as usize) {
+            for chunk_x in (0..quads[0]).step_by(CHUNK_QUADS as usize) {
+                let chunk_quads = [
+                    CHUNK_QUADS.min(quads[0] - chunk_x),
+                    CHUNK_QUADS.min(quads[1] - chunk_z),
+                ];
+                let row_length = chunk_quads[0] + 1;
+                let vertices = (0..=chunk_quads[1])
+                    .flat_map(|z| (0..=chunk_quads[0]).map(move |x| (x, z)))
+                    .map(|(x, z)| vertex(chunk_x + x, chunk_z + z))
+                    .collect();
+                let indices = grid_indices(
+                    &(0..=chunk_quads[0]).collect::<Vec<_>>(),
+                    &(0..=chunk_quads[1]).collect::<Vec<_>>(),
+                    row_length,
+                );
+                let patch_indices = tessellated.then(|| {
+                    let patch_indices = grid_indices(
+                        &patch_lines(chunk_quads[0]),
+                        &patch_lines(chunk_quads[1]),
+                        row_length,
+                    );
+                    Buffer::from_iter(
+                        memory_allocator.clone(),
+                        BufferCreateInfo {
+                            usage: BufferUsage::INDEX_BUFFER,
+                            ..Default::default()
+                        },
+                        AllocationCreateInfo {
+                            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                            ..Default::default()
+                        },
+                        patch_indices,
+                    )
+                    .unwrap()
+                });
+                chunks.push(Chunk {
+                    mesh: Mesh::new(memory_allocator.clone(), vertices, indices),
+                    patch_indices,
+                });
+            }
+        }
+
+        let heightmap = tessellated.then(|| {
+            let bytes: Vec<u8> = heights
+                .iter()
+                .flat_map(|height| height.to_ne_bytes())
+                .collect();
+            Texture::from_bytes(
+                memory_allocator.clone(),
+                command_buffer_allocator,
+                queue,
+                HEIGHTMAP_FORMAT,
+                [width, height],
+                bytes.into_iter(),
+            )
+        });
+        let bounds = chunks
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, chunk| bounds.union(&chunk.mesh.aabb));
+
+        Ok(Terrain {
+            desc,
+            chunks,
+            heightmap,
+            spacing,
+            extent: [width, height],
+            bounds,
+        })
+    }
+
+    /// The bounds of the whole terrain.
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    /// The bounds of every chunk.
+    pub fn chunk_bounds(&self) -> impl Iterator<Item = Aabb> + '_ {
+        self.chunks.iter().map(|chunk| chunk.mesh.aabb)
+    }
+}
+
+/// Where the edges of the patches across `quads` quads are: every `PATCH_QUADS`, and at the end.
+fn patch_lines(quads: u32) -> Vec<u32> {
+    (0..quads)
+        .step_by(PATCH_QUADS as usize)
+        .chain(iter::once(quads))
+        .collect()
+}
+
+/// The triangles of a grid with vertices at `columns` along x and `rows` along z, out of a grid of
+/// vertices `row_length` wide. They wind counter-clockwise when seen from above.
+fn grid_indices(columns: &[u32], rows: &[u32], row_length: u32) -> Vec<u32> {
+    let mut indices = Vec::with_capacity(columns.len() * rows.len() * 6);
+    for z in rows.windows(2) {
+        for x in columns.windows(2) {
+            let corner = |x: u32, z: u32| z * row_length + x;
+            let (back_left, back_right) = (corner(x[0], z[0]), corner(x[1], z[0]));
+            let (front_left, front_right) = (corner(x[0], z[1]), corner(x[1], z[1]));
+            indices.extend([
+                back_left,
+                front_left,
+                back_right,
+                back_right,
+                front_left,
+                front_right,
+            ]);
+        }
+    }
+
+    indices
+}
+
+/// The `PushConstants` block of `shaders/include/terrain.glsl`.
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    eye: [f32; 4],
+    scale: [f32; 4],
+    texel: [f32; 4],
+}
+
+/// The pipeline variants of the terrain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct TerrainFeatures {
+    wireframe: bool,
+    tessellated: bool,
+}
+
+pub struct TerrainPipeline {
+    variants: ShaderVariants<TerrainFeatures>,
+    layout: Arc<PipelineLayout>,
+    supports_wireframe: bool,
+    /// Whether the tessellation shaders were compiled.
+    supports_tessellation: bool,
+    wireframe: bool,
+    tessellation: bool,
+    output: Output,
+    uniform_buffer_allocator: SubbufferAllocator,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    sampler: Arc<Sampler>,
+}
+
+impl TerrainPipeline {
+    /// Asks for tessellation shaders where supported.
+    pub fn register_requirements(requirements: &mut DeviceRequirements) {
+        requirements.request_features(DeviceFeatures {
+            tessellation_shader: true,
+            ..DeviceFeatures::empty()
+        });
+    }
+
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+        let capabilities = Capabilities::of(&device);
+
+        let vs = shader::load(
+            device.clone(),
+            include_str!("shaders/terrain.vert"),
+            ShaderStage::Vertex,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let vertex_input_state = MeshVertex::per_vertex().definition(&vs).unwrap();
+        let fs_source = ShaderSource::parse(
+            include_str!("shaders/terrain.frag"),
+            ShaderStage::Fragment,
+            &[],
+        )?;
+        // Without glslc, the full grid is drawn instead.
+        let tessellation_stages = capabilities
+            .tessellation
+            .then(|| {
+                let load = |source, stage| {
+                    shader::load_tessellation_with_glslc(device.clone(), source, stage, &[]).map(
+                        |module| {
+                            PipelineShaderStageCreateInfo::new(module.entry_point("main").unwrap())
+                        },
+                    )
+                };
+                load(
+                    include_str!("shaders/terrain.tesc"),
+                    TessellationStage::Control,
+                )
+                .and_then(|tcs| {
+                    let tes = load(
+                        include_str!("shaders/terrain.tese"),
+                        TessellationStage::Evaluation,
+                    )?;
+                    Ok([tcs, tes])
+                })
+                .inspect_err(|err| warn!("Terrain tessellation is unavailable: {err}"))
+                .ok()
+            })
+            .flatten();
+
+        // The layout covers the tessellation stages too, whose heightmap set the variants without
+        // them leave unbound.
+        let vs_stage = PipelineShaderStageCreateInfo::new(vs);
+        let fs_stage = PipelineShaderStageCreateInfo::new(
+            fs_source
+                .specialize(device.clone(), &[])?
+                .entry_point("main")
+                .unwrap(),
+        );
+        let stages: Vec<_> = iter::once(vs_stage.clone())
+            .chain(tessellation_stages.iter().flatten().cloned())
+            .chain([fs_stage])
+            .collect();
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+
+        let supports_tessellation = tessellation_stages.is_some();
+        let variants = ShaderVariants::new({
+            let device = device.clone();
+            let layout = layout.clone();
+            move |features: TerrainFeatures| {
+                let fs = fs_source
+                    .specialize(
+                        device.clone(),
+                        &[(0, f64::from(u8::from(!features.wireframe)))],
+                    )?
+                    .entry_point("main")
+                    .unwrap();
+                let tessellation_stages = tessellation_stages
+                    .as_ref()
+                    .filter(|_| features.tessellated);
+                let polygon_mode = if features.wireframe {
+                    PolygonMode::Line
+                } else {
+                    PolygonMode::Fill
+                };
+
+                GraphicsPipeline::new(
+                    device.clone(),
+                    None,
+                    GraphicsPipelineCreateInfo {
+                        stages: iter::once(vs_stage.clone())
+                            .chain(tessellation_stages.into_iter().flatten().cloned())
+                            .chain([PipelineShaderStageCreateInfo::new(fs)])
+                            .collect(),
+                        vertex_input_state: Some(vertex_input_state.clone()),
+                        input_assembly_state: Some(InputAssemblyState {
+                            topology: if tessellation_stages.is_some() {
+                                PrimitiveTopology::PatchList
+                            } else {
+                                PrimitiveTopology::TriangleList
+                            },
+                            ..Default::default()
+                        }),
+                        tessellation_state: tessellation_stages
+                            .map(|_| TessellationState::default()),
+                        viewport_state: Some(ViewportState::default()),
+                        // Which way the triangles that tessellation makes wind depends on the
+                        // origin of its domain, so those aren't culled by facing.
+                        rasterization_state: Some(RasterizationState {
+                            polygon_mode,
+                            cull_mode: if tessellation_stages.is_some() {
+                                CullMode::None
+                            } else {
+                                CullMode::Back
+                            },
+                            ..Default::default()
+                        }),
+                        depth_stencil_state: Some(DepthStencilState {
+                            depth: Some(DepthState::simple()),
+                            ..Default::default()
+                        }),
+                        multisample_state: Some(MultisampleState::default()),
+                        color_blend_state: Some(offscreen::color_blend_state(
+                            &subpass,
+                            &[ColorBlendAttachmentState::default()],
+                        )),
+                        dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                        subpass: Some(subpass.clone().into()),
+                        ..GraphicsPipelineCreateInfo::layout(layout.clone())
+                    },
+                )
+                .map_err(AppError::Pipeline)
+                .inspect(|pipeline| debug_utils::name(pipeline, &format!("terrain {features:?}")))
+            }
+        });
+        // The variant that is drawn with by default is created up front, so that errors show at
+        // startup.
+        variants.get(TerrainFeatures {
+            wireframe: false,
+            tessellated: supports_tessellation,
+        })?;
+
+        let uniform_buffer_allocator = SubbufferAllocator::new(
+            memory_allocator,
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::UNIFORM_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+        let sampler =
+            Sampler::new(device, SamplerCreateInfo::default()).map_err(AppError::Pipeline)?;
+
+        Ok(TerrainPipeline {
+            variants,
+            layout,
+            supports_wireframe: capabilities.wireframe,
+            supports_tessellation,
+            wireframe: false,
+            tessellation: true,
+            output: Output::default(),
+            uniform_buffer_allocator,
+            descriptor_set_allocator,
+            sampler,
+        })
+    }
+
+    /// Whether the terrain can be tessellated, which takes a device with tessellation shaders and
+    /// `glslc` to compile them.
+    pub fn supports_tessellation(&self) -> bool {
+        self.supports_tessellation
+    }
+
+    /// Switches between filled and wireframe rendering. Wireframe is ignored if unsupported.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        self.wireframe = wireframe;
+    }
+
+    /// Switches between tessellated patches and the full grid. Tessellation is ignored if
+    /// unsupported.
+    pub fn set_tessellation(&mut self, tessellation: bool) {
+        self.tessellation = tessellation;
+    }
+
+    /// Writes colors out as `output` asks.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    /// Records a draw of the chunks of `terrain` that are inside the camera frustum into the
+    /// current subpass, lit by `light` and seen through `view_proj` from `eye`. Returns how many
+    /// chunks were drawn.
+    pub fn draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        terrain: &Terrain,
+        view_proj: Mat4,
+        eye: Vec3,
+        light: &Light,
+        viewport: Viewport,
+    ) -> usize {
+        let frustum = Frustum::from_view_proj(view_proj);
+        let visible: Vec<_> = terrain
+            .chunks
+            .iter()
+            .filter(|chunk| frustum.intersects(&chunk.mesh.aabb))
+            .collect();
+        if visible.is_empty() {
+            return 0;
+        }
+
+        // The heightmap is only uploaded where the device can tessellate, along with the patches.
+        let heightmap = terrain
+            .heightmap
+            .as_ref()
+            .filter(|_| self.tessellation && self.supports_tessellation);
+        let features = TerrainFeatures {
+            wireframe: self.wireframe && self.supports_wireframe,
+            tessellated: heightmap.is_some(),
+        };
+        let pipeline = match self.variants.get(features) {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                warn!("Failed to create the terrain pipeline {features:?}: {err}");
+                return 0;
+            }
+        };
+
+        let uniform_buffer = self.uniform_buffer_allocator.allocate_sized().unwrap();
+        *uniform_buffer.write().unwrap() =
+            FrameUniforms::new(view_proj, light).with_output(self.output);
+        let frame_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            self.layout.set_layouts()[0].clone(),
+            [WriteDescriptorSet::buffer(0, uniform_buffer)],
+            [],
+        )
+        .unwrap();
+
+        let patch_size = terrain.spacing * PATCH_QUADS as f32;
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .unwrap()
+            .bind_pipeline_graphics(pipeline)
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.layout.clone(),
+                0,
+                frame_set,
+            )
+            .unwrap()
+            .push_constants(
+                self.layout.clone(),
+                0,
+                PushConstants {
+                    eye: eye.extend(1.0).to_array(),
+                    scale: [
+                        terrain.spacing.x,
+                        terrain.spacing.y,
+                        terrain.desc.height,
+                        patch_size.max_element() * FULL_DETAIL_PATCHES,
+                    ],
+                    texel: [
+                        1.0 / terrain.extent[0] as f32,
+                        1.0 / terrain.extent[1] as f32,
+                        PATCH_QUADS as f32,
+                        0.0,
+                    ],
+                },
+            )
+            .unwrap();
+        if let Some(heightmap) = heightmap {
+            let heightmap_set = DescriptorSet::new(
+                self.descriptor_set_allocator.clone(),
+                self.layout.set_layouts()[1].clone(),
+                [WriteDescriptorSet::image_view_sampler(
+                    0,
+                    heightmap.view.clone(),
+                    self.sampler.clone(),
+                )],
+                [],
+            )
+            .unwrap();
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.layout.clone(),
+                    1,
+                    heightmap_set,
+                )
+                .unwrap();
+        }
+
+        for chunk in &visible {
+            let indices = match chunk.patch_indices.as_ref().filter(|_| heightmap.is_some()) {
+                Some(patch_indices) => patch_indices.clone(),
+                None => chunk.mesh.index_buffer.clone(),
+            };
+            let index_count = indices.len() as u32;
+            builder
+                .bind_vertex_buffers(0, chunk.mesh.vertex_buffer.clone())
+                .unwrap()
+                .bind_index_buffer(indices)
+                .unwrap();
+            // SAFETY: the indices are all of vertices of the chunk, which the vertex buffer holds.
+            unsafe { builder.draw_indexed(index_count, 1, 0, 0, 0) }.unwrap();
+        }
+
+        visible.len()
+    }
+}
@@ -0,0 +1,331 @@
+// Text for the overlay, shaped with rustybuzz and drawn as the outlines of its glyphs through
+// `DebugDraw`. The fonts of the `fonts` setting make up a fallback chain: each grapheme cluster,
+// an emoji sequence or a letter with its accents, is set in the first font that has a glyph for
+// every character of it. Runs of clusters in the same font are shaped together, so ligatures,
+// kerning, marks and the joining of Arabic letters come out as the font means them, and
+// right-to-left runs are laid out right to left, though the runs themselves stay in the order
+// they were typed in rather than being reordered for bidirectional text.
+//
+// Glyphs are drawn as lines along their outlines, which keeps them as sharp as any other debug
+// line at any size, but leaves them hollow. Emoji fonts that only have bitmaps or color layers,
+// rather than outlines, take part in the fallback but draw nothing. A cluster that no font covers
+// is drawn with the first font's missing-glyph box.
+
+use glam::{Vec2, Vec4};
+use rustybuzz::{ttf_parser, Face, UnicodeBuffer};
+use std::{
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::debug_draw::DebugDraw;
+
+/// The fonts tried by default, which are those of the common desktops' system font directories
+/// with Latin and more, CJK and emoji. Those that aren't installed are left out.
+#[cfg(target_os = "windows")]
+pub const DEFAULT_FONTS: &[&str] = &[
+    "C:\\Windows\\Fonts\\segoeui.ttf",
+    "C:\\Windows\\Fonts\\msyh.ttc",
+    "C:\\Windows\\Fonts\\seguiemj.ttf",
+];
+#[cfg(target_os = "macos")]
+pub const DEFAULT_FONTS: &[&str] = &[
+    "/System/Library/Fonts/Helvetica.ttc",
+    "/System/Library/Fonts/PingFang.ttc",
+    "/System/Library/Fonts/Apple Color Emoji.ttc",
+];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub const DEFAULT_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/noto/NotoColorEmoji.ttf",
+];
+
+/// How many lines each curve of an outline is drawn with.
+const CURVE_SEGMENTS: usize = 6;
+
+struct Font {
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+impl Font {
+    fn face(&self) -> Face<'_> {
+        // `Fonts::load` only keeps fonts that parse.
+        Face::from_slice(&self.data, 0).unwrap()
+    }
+}
+
+/// The fallback chain of fonts that text is set in.
+#[derive(Default)]
+pub struct Fonts {
+    fonts: Vec<Font>,
+}
+
+/// A glyph of shaped text, placed in ems from the start of the text's baseline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    /// The font of the chain the glyph is from.
+    pub font: usize,
+    pub id: u16,
+    pub position: Vec2,
+}
+
+/// Text laid out on a line.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShapedText {
+    pub glyphs: Vec<Glyph>,
+    /// How far the glyphs advance, in ems.
+    pub width: f32,
+}
+
+impl Fonts {
+    /// Reads the fonts at `paths`, in the order they are tried. Fonts that don't exist are left
+    /// out quietly, as the defaults list those of every desktop, and others that fail to read or
+    /// parse with a warning.
+    pub fn load(paths: &[PathBuf]) -> Self {
+        let fonts = paths
+            .iter()
+            .filter_map(|path| match fs::read(path) {
+                Ok(data) if Face::from_slice(&data, 0).is_some() => Some(Font {
+                    path: path.clone(),
+                    data,
+                }),
+                Ok(_) => {
+                    warn!("{} isn't a font that can be read", path.display());
+                    None
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => {
+                    warn!("Failed to read {}: {err}", path.display());
+                    None
+                }
+            })
+            .collect();
+        Fonts { fonts }
+    }
+
+    /// Whether none of the fonts could be loaded, in which case nothing is drawn.
+    pub fn is_empty(&self) -> bool {
+        self.fonts.is_empty()
+    }
+
+    /// The paths of the fonts that loaded, in the order they are tried.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.fonts.iter().map(|font| font.path.as_path())
+    }
+
+    /// Lays `text` out on a line, with each run of grapheme clusters in the font that covers it.
+    pub fn shape(&self, text: &str) -> ShapedText {
+        let mut shaped = ShapedText::default();
+        if self.fonts.is_empty() {
+            return shaped;
+        }
+        let faces: Vec<Face> = self.fonts.iter().map(Font::face).collect();
+
+        // The font of each run, and the range of `text` it spans.
+        let mut runs: Vec<(usize, Range<usize>)> = Vec::new();
+        for (start, cluster) in text.grapheme_indices(true) {
+            let font = faces
+                .iter()
+                .position(|face| covers(face, cluster))
+                .unwrap_or(0);
+            let end = start + cluster.len();
+            match runs.last_mut() {
+                Some((run_font, run)) if *run_font == font => run.end = end,
+                _ => runs.push((font, start..end)),
+            }
+        }
+
+        for (font, run) in runs {
+            let face = &faces[font];
+            let units_per_em = face.units_per_em() as f32;
+            let mut buffer = UnicodeBuffer::new();
+            buffer.push_str(&text[run]);
+            buffer.guess_segment_properties();
+            let output = rustybuzz::shape(face, &[], buffer);
+            for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+                let offset = Vec2::new(position.x_offset as f32, position.y_offset as f32);
+                shaped.glyphs.push(Glyph {
+                    font,
+                    id: info.glyph_id as u16,
+                    position: Vec2::new(shaped.width, 0.0) + offset / units_per_em,
+                });
+                shaped.width += position.x_advance as f32 / units_per_em;
+            }
+        }
+        shaped
+    }
+
+    /// Draws the outlines of `text` into `debug_draw`, in normalized device coordinates, with
+    /// the start of its baseline at `origin` and an em `em` across and high.
+    pub fn draw(
+        &self,
+        debug_draw: &mut DebugDraw,
+        text: &ShapedText,
+        origin: Vec2,
+        em: Vec2,
+        color: Vec4,
+    ) {
+        let faces: Vec<Face> = self.fonts.iter().map(Font::face).collect();
+        for glyph in &text.glyphs {
+            let face = &faces[glyph.font];
+            let mut outline = Outline {
+                debug_draw,
+                color,
+                // Font units have y up, and normalized device coordinates y down.
+                origin: origin + glyph.position * em * Vec2::new(1.0, -1.0),
+                scale: em * Vec2::new(1.0, -1.0) / face.units_per_em() as f32,
+                start: Vec2::ZERO,
+                last: Vec2::ZERO,
+            };
+            face.outline_glyph(ttf_parser::GlyphId(glyph.id), &mut outline);
+        }
+    }
+}
+
+/// Whether `face` has a glyph for every character of `cluster` that is drawn at all.
+fn covers(face: &Face, cluster: &str) -> bool {
+    cluster
+        .chars()
+        .filter(|&c| !is_default_ignorable(c))
+        .all(|c| face.glyph_index(c).is_some())
+}
+
+/// Whether `c` is one of the characters that only change how those around it are shaped, such as
+/// joiners and variation selectors, which fonts needn't have glyphs for.
+fn is_default_ignorable(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200F}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{E0000}'..='\u{E0FFF}'
+    )
+}
+
+/// Turns a glyph's outline into lines of `debug_draw`.
+struct Outline<'a> {
+    debug_draw: &'a mut DebugDraw,
+    color: Vec4,
+    origin: Vec2,
+    /// Normalized device coordinates to a font unit.
+    scale: Vec2,
+    /// The start of the contour being drawn, and where it has got to, in font units.
+    start: Vec2,
+    last: Vec2,
+}
+
+impl Outline<'_> {
+    fn line(&mut self, to: Vec2) {
+        let point = |p: Vec2| (self.origin + p * self.scale).extend(0.0);
+        self.debug_draw
+            .line(point(self.last), point(to), self.color);
+        self.last = to;
+    }
+}
+
+impl ttf_parser::OutlineBuilder for Outline<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = Vec2::new(x, y);
+        self.last = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.line(Vec2::new(x, y));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.last, Vec2::new(x1, y1), Vec2::new(x, y));
+        for i in 1..=CURVE_SEGMENTS {
+            let t = i as f32 / CURVE_SEGMENTS as f32;
+            self.line(p0.lerp(p1, t).lerp(p1.lerp(p2, t), t));
+        }
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p3) = (
+            self.last,
+            Vec2::new(x1, y1),
+            Vec2::new(x2, y2),
+            Vec2::new(x, y),
+        );
+        for i in 1..=CURVE_SEGMENTS {
+            let t = i as f32 / CURVE_SEGMENTS as f32;
+            let (a, b, c) = (p0.lerp(p1, t), p1.lerp(p2, t), p2.lerp(p3, t));
+            self.line(a.lerp(b, t).lerp(b.lerp(c, t), t));
+        }
+    }
+
+    fn close(&mut self) {
+        if self.last != self.start {
+            self.line(self.start);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SANS: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+    const EXTRA_LIGHT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans-ExtraLight.ttf";
+
+    /// The fonts at `paths`, or none if any of them isn't installed, to skip the test.
+    fn fonts(paths: &[&str]) -> Option<Fonts> {
+        let fonts = Fonts::load(&paths.iter().map(PathBuf::from).collect::<Vec<_>>());
+        (fonts.fonts.len() == paths.len()).then_some(fonts)
+    }
+
+    #[test]
+    fn kerning_pulls_pairs_together() {
+        let Some(fonts) = fonts(&[SANS]) else {
+            return;
+        };
+        let pair = fonts.shape("AV").width;
+        let apart = fonts.shape("A").width + fonts.shape("V").width;
+        assert!(pair < apart, "{pair} isn't less than {apart}");
+    }
+
+    #[test]
+    fn combining_marks_do_not_advance() {
+        let Some(fonts) = fonts(&[SANS]) else {
+            return;
+        };
+        let text = fonts.shape("e\u{301}");
+        assert_eq!(text.width, fonts.shape("e").width);
+        assert!(text.glyphs.len() <= 2);
+    }
+
+    #[test]
+    fn falls_back_for_clusters_the_first_font_lacks() {
+        let Some(fonts) = fonts(&[EXTRA_LIGHT, SANS]) else {
+            return;
+        };
+        let faces: Vec<Face> = fonts.fonts.iter().map(Font::face).collect();
+        assert!(!covers(&faces[0], "\u{2654}") && covers(&faces[1], "\u{2654}"));
+
+        let text = fonts.shape("a\u{2654}b");
+        let used: Vec<usize> = text.glyphs.iter().map(|glyph| glyph.font).collect();
+        assert_eq!(used, [0, 1, 0]);
+    }
+
+    #[test]
+    fn uncovered_clusters_use_the_first_font() {
+        let Some(fonts) = fonts(&[EXTRA_LIGHT, SANS]) else {
+            return;
+        };
+        let text = fonts.shape("\u{4E2D}");
+        assert_eq!(text.glyphs.len(), 1);
+        assert_eq!(text.glyphs[0].font, 0);
+        assert_eq!(text.glyphs[0].id, 0);
+    }
+
+    #[test]
+    fn nothing_is_shaped_without_fonts() {
+        assert_eq!(Fonts::default().shape("text"), ShapedText::default());
+    }
+}
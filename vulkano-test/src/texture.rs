@@ -0,0 +1,459 @@
+// Sampled 2D textures and cubemaps. Pixels are uploaded through a staging buffer with a command
+// buffer of their own, which is waited on before the texture is returned.
+//
+// A texture that runs out of memory, on the host for its staging buffer or on the device for its
+// image, is halved in size and tried again, for as long as it takes to fit, rather than taking the
+// app down. Scenes with more texture data than a low-VRAM device has room for still load, only
+// blurrier. Each halving is logged, and how many there were is kept with the texture.
+//
+// Textures can also have mip levels, from a chain that `mip_chain` computes on the CPU, which is
+// what `TextureStreamer` uploads part of at a time, or in a block-compressed format as
+// `TextureData` from a KTX2 or DDS file. A chain that doesn't fit loses its largest levels
+// instead, one at a time, which comes to the same as halving. Compressed formats need features of
+// their own, which `register_requirements` asks for, and `can_sample` tells whether the device can
+// use one.
+
+use image::{imageops::FilterType, Rgba, RgbaImage};
+use std::{ops::Range, sync::Arc};
+use tracing::warn;
+use vulkano::{
+    buffer::{AllocateBufferError, Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, AutoCommandBufferBuilder, BufferImageCopy,
+        CommandBufferUsage, CopyBufferToImageInfo,
+    },
+    device::{Device, DeviceFeatures, DeviceOwned, Queue},
+    format::{CompressionType, Format, FormatFeatures},
+    image::{
+        view::{ImageView, ImageViewCreateInfo, ImageViewType},
+        AllocateImageError, Image, ImageAspects, ImageCreateFlags, ImageCreateInfo,
+        ImageSubresourceLayers, ImageType, ImageUsage,
+    },
+    memory::allocator::{
+        AllocationCreateInfo, MemoryAllocatorError, MemoryTypeFilter, StandardMemoryAllocator,
+    },
+    sync::{self, GpuFuture},
+    Validated, VulkanError,
+};
+
+use crate::device_requirements::DeviceRequirements;
+
+/// Asks for the features that sample block-compressed formats, where supported.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_features(DeviceFeatures {
+        texture_compression_bc: true,
+        texture_compression_etc2: true,
+        texture_compression_astc_ldr: true,
+        ..DeviceFeatures::empty()
+    });
+}
+
+/// Whether textures of `format` can be created and sampled on `device`.
+pub fn can_sample(device: &Device, format: Format) -> bool {
+    let features = device.enabled_features();
+    let enabled = match format.compression() {
+        None => true,
+        Some(CompressionType::BC) => features.texture_compression_bc,
+        Some(CompressionType::ETC2 | CompressionType::EAC) => features.texture_compression_etc2,
+        Some(CompressionType::ASTC_LDR) => features.texture_compression_astc_ldr,
+        Some(_) => false,
+    };
+    enabled
+        && device
+            .physical_device()
+            .format_properties(format)
+            .is_ok_and(|properties| {
+                properties
+                    .optimal_tiling_features
+                    .intersects(FormatFeatures::SAMPLED_IMAGE)
+            })
+}
+
+/// Texels to upload as a texture, in any format, as a file stores them.
+pub struct TextureData {
+    pub format: Format,
+    /// The size of the largest level.
+    pub extent: [u32; 2],
+    /// Whether the texture is a cubemap, of six square faces, rather than a single image.
+    pub cube: bool,
+    /// The texel blocks of every mip level, from the largest down, each half the size of the one
+    /// before. Each level holds its faces one after the other, in the order +X, -X, +Y, -Y, +Z,
+    /// -Z.
+    pub levels: Vec<Vec<u8>>,
+}
+
+pub struct Texture {
+    pub image: Arc<Image>,
+    pub view: Arc<ImageView>,
+    /// How many times the texture was halved in size to fit in memory.
+    pub downscaled: u32,
+}
+
+impl Texture {
+    /// The format of the textures of image files, which store sRGB colors that the sampler
+    /// converts to linear.
+    pub const FORMAT: Format = Format::R8G8B8A8_SRGB;
+
+    /// Uploads `pixels` and waits for the upload to finish.
+    pub fn from_rgba(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+        pixels: &RgbaImage,
+    ) -> Arc<Texture> {
+        let (width, height) = pixels.dimensions();
+        Self::from_bytes(
+            memory_allocator,
+            command_buffer_allocator,
+            queue,
+            Self::FORMAT,
+            [width, height],
+            pixels.as_raw().iter().copied(),
+        )
+    }
+
+    /// Uploads texels of `format`, which `bytes` holds row by row, and waits for the upload to
+    /// finish. The texture is smaller than `extent` if it had to be halved to fit in memory.
+    pub fn from_bytes(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+        format: Format,
+        mut extent: [u32; 2],
+        bytes: impl ExactSizeIterator<Item = u8>,
+    ) -> Arc<Texture> {
+        let mut texels: Vec<u8> = bytes.collect();
+        let mut downscaled = 0;
+        let (staging_buffer, image) = loop {
+            let create_info = ImageCreateInfo {
+                format,
+                extent: [extent[0], extent[1], 1],
+                ..Default::default()
+            };
+            let err = match allocate(&memory_allocator, create_info, &texels) {
+                Ok(allocated) => break allocated,
+                Err(err) => err,
+            };
+            let [width, height] = extent;
+            if extent == [1, 1] {
+                panic!("out of memory for a {width}x{height} texture: {err}");
+            }
+            let smaller = extent.map(|side| (side / 2).max(1));
+            warn!(
+                "Out of memory for a {width}x{height} texture, retrying at {}x{}: {err}",
+                smaller[0], smaller[1],
+            );
+            texels = halve(&texels, extent, format.block_size() as usize);
+            extent = smaller;
+            downscaled += 1;
+        };
+        upload_levels(
+            command_buffer_allocator,
+            queue,
+            staging_buffer,
+            &image,
+            0..1,
+        );
+
+        Arc::new(Texture {
+            view: ImageView::new_default(image.clone()).unwrap(),
+            image,
+            downscaled,
+        })
+    }
+
+    /// Uploads `mips`, a chain of mip levels each half the size of the one before as `mip_chain`
+    /// computes, as the levels of one texture, and waits for the upload to finish. The largest
+    /// levels are left out if the whole chain doesn't fit in memory.
+    pub fn from_mips(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+        mips: &[RgbaImage],
+    ) -> Arc<Texture> {
+        let (width, height) = mips[0].dimensions();
+        let levels: Vec<&[u8]> = mips.iter().map(|mip| mip.as_raw().as_slice()).collect();
+        Self::from_levels(
+            memory_allocator,
+            command_buffer_allocator,
+            queue,
+            Self::FORMAT,
+            [width, height],
+            false,
+            &levels,
+        )
+    }
+
+    /// Uploads `data`, which `can_sample` must allow the format of, and waits for the upload to
+    /// finish. The largest levels are left out if they don't all fit in memory.
+    pub fn from_data(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+        data: &TextureData,
+    ) -> Arc<Texture> {
+        let levels: Vec<&[u8]> = data.levels.iter().map(Vec::as_slice).collect();
+        Self::from_levels(
+            memory_allocator,
+            command_buffer_allocator,
+            queue,
+            data.format,
+            data.extent,
+            data.cube,
+            &levels,
+        )
+    }
+
+    /// Uploads mip levels of `format` from `extent` down, as `TextureData` holds them.
+    fn from_levels(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+        format: Format,
+        extent: [u32; 2],
+        cube: bool,
+        levels: &[&[u8]],
+    ) -> Arc<Texture> {
+        let mut downscaled = 0;
+        let (staging_buffer, image) = loop {
+            let [width, height] = extent.map(|side| (side >> downscaled).max(1));
+            let remaining = &levels[downscaled as usize..];
+            let texels: Vec<u8> = remaining.concat();
+            let err = match allocate(
+                &memory_allocator,
+                ImageCreateInfo {
+                    flags: if cube {
+                        ImageCreateFlags::CUBE_COMPATIBLE
+                    } else {
+                        ImageCreateFlags::empty()
+                    },
+                    format,
+                    extent: [width, height, 1],
+                    mip_levels: remaining.len() as u32,
+                    array_layers: if cube { 6 } else { 1 },
+                    ..Default::default()
+                },
+                &texels,
+            ) {
+                Ok(allocated) => break allocated,
+                Err(err) => err,
+            };
+            if remaining.len() == 1 {
+                panic!("out of memory for a {width}x{height} texture: {err}");
+            }
+            warn!(
+                "Out of memory for a {width}x{height} texture, retrying at {}x{}: {err}",
+                (width / 2).max(1),
+                (height / 2).max(1),
+            );
+            downscaled += 1;
+        };
+        let mip_levels = image.mip_levels();
+        upload_levels(
+            command_buffer_allocator,
+            queue,
+            staging_buffer,
+            &image,
+            0..mip_levels,
+        );
+
+        let view = if cube {
+            ImageView::new(
+                image.clone(),
+                ImageViewCreateInfo {
+                    view_type: ImageViewType::Cube,
+                    ..ImageViewCreateInfo::from_image(&image)
+                },
+            )
+            .unwrap()
+        } else {
+            ImageView::new_default(image.clone()).unwrap()
+        };
+        Arc::new(Texture {
+            view,
+            image,
+            downscaled,
+        })
+    }
+
+    /// A single white texel, which leaves whatever it is multiplied with unchanged.
+    pub fn white(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: &Arc<Queue>,
+    ) -> Arc<Texture> {
+        let pixels = RgbaImage::from_pixel(1, 1, Rgba([255; 4]));
+        Self::from_rgba(memory_allocator, command_buffer_allocator, queue, &pixels)
+    }
+}
+
+/// Creates the staging buffer holding `texels` and the sampled 2D image of `create_info` they are
+/// copied to, returning the error if either runs out of memory. Other failures are bugs, and panic.
+fn allocate(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    create_info: ImageCreateInfo,
+    texels: &[u8],
+) -> Result<(Subbuffer<[u8]>, Arc<Image>), MemoryAllocatorError> {
+    let staging_buffer = staging_buffer(memory_allocator, texels)?;
+    let image = match Image::new(
+        memory_allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+            ..create_info
+        },
+        AllocationCreateInfo::default(),
+    ) {
+        Ok(image) => image,
+        Err(Validated::Error(AllocateImageError::AllocateMemory(err)))
+            if is_out_of_memory(&err) =>
+        {
+            return Err(err);
+        }
+        Err(err) => panic!("failed to create a texture: {err}"),
+    };
+    Ok((staging_buffer, image))
+}
+
+/// Creates a staging buffer holding `texels`, returning the error if it runs out of memory.
+pub(crate) fn staging_buffer(
+    memory_allocator: &Arc<StandardMemoryAllocator>,
+    texels: &[u8],
+) -> Result<Subbuffer<[u8]>, MemoryAllocatorError> {
+    match Buffer::from_iter(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        texels.iter().copied(),
+    ) {
+        Ok(buffer) => Ok(buffer),
+        Err(Validated::Error(AllocateBufferError::AllocateMemory(err)))
+            if is_out_of_memory(&err) =>
+        {
+            Err(err)
+        }
+        Err(err) => panic!("failed to create a staging buffer: {err}"),
+    }
+}
+
+/// Copies `staging_buffer`, which holds the texel blocks of `levels` one level after the other,
+/// and the array layers of each level one after the other, into those mip levels of `image`, and
+/// waits for the copy to finish.
+pub(crate) fn upload_levels(
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    queue: &Arc<Queue>,
+    staging_buffer: Subbuffer<[u8]>,
+    image: &Arc<Image>,
+    levels: Range<u32>,
+) {
+    let format = image.format();
+    let [block_width, block_height, _] = format.block_extent();
+    let [width, height, _] = image.extent();
+    let mut buffer_offset = 0;
+    let regions = levels
+        .map(|mip_level| {
+            let extent = [width, height].map(|side| (side >> mip_level).max(1));
+            let region = BufferImageCopy {
+                buffer_offset,
+                image_subresource: ImageSubresourceLayers {
+                    aspects: ImageAspects::COLOR,
+                    mip_level,
+                    array_layers: 0..image.array_layers(),
+                },
+                image_extent: [extent[0], extent[1], 1],
+                ..Default::default()
+            };
+            buffer_offset += u64::from(extent[0].div_ceil(block_width))
+                * u64::from(extent[1].div_ceil(block_height))
+                * format.block_size()
+                * u64::from(image.array_layers());
+            region
+        })
+        .collect();
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        command_buffer_allocator,
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    builder
+        .copy_buffer_to_image(CopyBufferToImageInfo {
+            regions,
+            ..CopyBufferToImageInfo::buffer_image(staging_buffer, image.clone())
+        })
+        .unwrap();
+    let command_buffer = builder.build().unwrap();
+
+    sync::now(queue.device().clone())
+        .then_execute(queue.clone(), command_buffer)
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+}
+
+pub(crate) fn is_out_of_memory(err: &MemoryAllocatorError) -> bool {
+    matches!(
+        err,
+        MemoryAllocatorError::AllocateDeviceMemory(Validated::Error(
+            VulkanError::OutOfDeviceMemory | VulkanError::OutOfHostMemory
+        ))
+    )
+}
+
+/// Halves `texels`, of `texel_size` bytes each and `extent` in size, by keeping every other texel
+/// of every other row. Rows and columns of a single texel are kept as they are.
+fn halve(texels: &[u8], [width, height]: [u32; 2], texel_size: usize) -> Vec<u8> {
+    let [width, height] = [width as usize, height as usize];
+    let row_size = width * texel_size;
+    let [new_width, new_height] = [width, height].map(|side| (side / 2).max(1));
+    let step = |side: usize| if side > 1 { 2 } else { 1 };
+    let mut halved = Vec::with_capacity(new_width * new_height * texel_size);
+    for y in 0..new_height {
+        let row = &texels[y * step(height) * row_size..][..row_size];
+        for x in 0..new_width {
+            halved.extend_from_slice(&row[x * step(width) * texel_size..][..texel_size]);
+        }
+    }
+    halved
+}
+
+/// The mip levels of `pixels`, from `pixels` itself down to a single texel, each half the size of
+/// the one before.
+pub fn mip_chain(pixels: RgbaImage) -> Vec<RgbaImage> {
+    let mut mips = vec![pixels];
+    loop {
+        let (width, height) = mips.last().unwrap().dimensions();
+        if (width, height) == (1, 1) {
+            return mips;
+        }
+        let smaller = image::imageops::resize(
+            mips.last().unwrap(),
+            (width / 2).max(1),
+            (height / 2).max(1),
+            FilterType::Triangle,
+        );
+        mips.push(smaller);
+    }
+}
+
+/// A `size` by `size` magenta and black checkerboard with `cell` pixels wide squares, which is
+/// hard to mistake for a real texture.
+pub fn checkerboard(size: u32, cell: u32) -> RgbaImage {
+    RgbaImage::from_fn(size, size, |x, y| {
+        if (x / cell + y / cell).is_multiple_of(2) {
+            Rgba([255, 0, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    })
+}
@@ -0,0 +1,591 @@
+// Texture streaming, for scenes with more texture data than fits in VRAM. With `--stream-textures`,
+// the mip chain of every texture is computed as it loads and kept on the CPU, and only the levels
+// that the view needs are on the GPU. What it needs follows the camera: each texture gets as many
+// texels across as the largest visible entity drawn with it covers pixels on screen, taking the
+// texture to be wrapped once around the entity. Textures load at no more than `RESIDENT_SIZE`
+// texels across, and never drop below that.
+//
+// Detail is streamed in as soon as it is needed, a few textures per frame, those missing the most
+// levels first. It is only dropped once it has gone unneeded for `DROP_DELAY`, so that looking back
+// and forth doesn't upload the same levels over and over.
+//
+// Where the device supports sparse residency for 2D images and its queue can bind sparse memory,
+// each texture is one image of its whole chain, with memory bound to the levels that are resident
+// and a view of only those for the shaders to sample. Streaming detail in binds memory to the new
+// levels and uploads just them; dropping it narrows the view, and unbinds the memory once no frame
+// in flight can be sampling it anymore. Elsewhere, the resident levels are staged into a new image
+// every time they change. Either way, the new texture is swapped in behind the texture's `Handle`,
+// as a reload would be, and the old one is retired.
+//
+// A texture that runs out of memory streaming in stops at the level that didn't fit.
+
+use glam::Mat4;
+use image::RgbaImage;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+use vulkano::{
+    command_buffer::allocator::StandardCommandBufferAllocator,
+    device::{DeviceFeatures, DeviceOwned, Queue, QueueFlags},
+    image::{
+        sys::RawImage,
+        view::{ImageView, ImageViewCreateInfo},
+        Image, ImageAspects, ImageCreateFlags, ImageCreateInfo, ImageSubresourceRange, ImageType,
+        ImageUsage,
+    },
+    memory::{
+        allocator::StandardMemoryAllocator,
+        sparse::{
+            BindSparseInfo, SparseImageMemoryBind, SparseImageMemoryBindInfo,
+            SparseImageOpaqueMemoryBind, SparseImageOpaqueMemoryBindInfo,
+        },
+        DeviceMemory, MemoryAllocateInfo, MemoryPropertyFlags,
+    },
+    sync::fence::{Fence, FenceCreateInfo},
+    DeviceSize, Validated, VulkanError,
+};
+
+use crate::{
+    bounds::Frustum,
+    components::{MaterialOverride, MeshHandle, Transform},
+    device_requirements::{Capabilities, DeviceRequirements},
+    lod,
+    material::Material,
+    scene::Scene,
+    texture::{self, Texture},
+};
+
+/// How many texels across textures are at most when they load, and at least ever after.
+pub const RESIDENT_SIZE: u32 = 128;
+
+/// How long a texture goes without needing detail before the detail is dropped.
+const DROP_DELAY: Duration = Duration::from_secs(2);
+
+/// How many textures stream detail in or out per frame, as each waits for its upload.
+const STREAMED_PER_FRAME: usize = 2;
+
+/// Asks for sparse residency of 2D images where supported.
+pub fn register_requirements(requirements: &mut DeviceRequirements) {
+    requirements.request_features(DeviceFeatures {
+        sparse_binding: true,
+        sparse_residency_image2_d: true,
+        ..DeviceFeatures::empty()
+    });
+}
+
+/// How many pixels across the screen the largest visible entity drawn with each texture of
+/// `scene` covers, seen through `view_proj` in a viewport of `extent`, by the texture's path.
+pub fn texture_needs(scene: &Scene, view_proj: Mat4, extent: [f32; 2]) -> HashMap<PathBuf, f32> {
+    let frustum = Frustum::from_view_proj(view_proj);
+    let mut needs = HashMap::new();
+    let mut query = scene.world.query::<(
+        &Transform,
+        &MeshHandle,
+        &Material,
+        Option<&MaterialOverride>,
+    )>();
+    for (transform, mesh, material, material_override) in query.iter() {
+        let material = material_override.map_or(material, |o| &o.0);
+        let (Some(texture), Some(mesh)) = (&material.base_color_texture, mesh.0.get()) else {
+            continue;
+        };
+        let aabb = mesh.aabb.transformed(transform.0);
+        if !frustum.intersects(&aabb) {
+            continue;
+        }
+        let pixels = lod::screen_size(&aabb, view_proj) * extent[0].max(extent[1]);
+        let need = needs.entry(texture.key().clone()).or_insert(0.0f32);
+        *need = need.max(pixels);
+    }
+
+    needs
+}
+
+/// A texture being streamed.
+struct StreamedTexture {
+    /// Every level of the texture, from the full size down.
+    mips: Vec<RgbaImage>,
+    /// The most detailed level on the GPU.
+    resident: u32,
+    /// The most detailed level that fit in memory so far.
+    finest: u32,
+    /// The least detailed level that is kept resident.
+    coarsest: u32,
+    /// When the texture started to need less detail than it has, if it still does.
+    unneeded_since: Option<Instant>,
+    /// The sparse image the texture lives in, where sparse residency is supported.
+    sparse: Option<SparseTexture>,
+}
+
+impl StreamedTexture {
+    /// The level to have resident to cover `need` pixels across with a texel each.
+    fn wanted_level(&self, need: f32) -> u32 {
+        let (width, height) = self.mips[0].dimensions();
+        let level = if need > 0.0 {
+            (width.max(height) as f32 / need).log2().floor().max(0.0) as u32
+        } else {
+            u32::MAX
+        };
+        level.clamp(self.finest, self.coarsest)
+    }
+}
+
+/// An image of a whole mip chain, with memory bound to its mip tail and to its levels from `bound`
+/// on.
+struct SparseTexture {
+    image: Arc<Image>,
+    /// The memory bound to each level that isn't in the mip tail, if any.
+    levels: Vec<Option<Arc<DeviceMemory>>>,
+    /// The memory of the mip tail, bound for as long as the image lives.
+    _mip_tail: Option<Arc<DeviceMemory>>,
+    /// The most detailed level with memory bound to it and its texels uploaded, or the number of
+    /// levels before any are.
+    bound: u32,
+    /// The frame in which the view was last narrowed.
+    narrowed: u64,
+    /// The size of the texel blocks that memory is bound in.
+    granularity: [u32; 2],
+    /// The size in bytes of the memory of a block.
+    block_size: DeviceSize,
+    memory_type_index: u32,
+}
+
+pub struct TextureStreamer {
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+    queue: Arc<Queue>,
+    /// Whether textures can be sparse images.
+    sparse: bool,
+    textures: HashMap<PathBuf, StreamedTexture>,
+    /// Sparse images that stopped being streamed, with the frame they stopped in, whose memory is
+    /// freed once no frame in flight can be sampling them.
+    released: VecDeque<(u64, SparseTexture)>,
+}
+
+impl TextureStreamer {
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+        queue: Arc<Queue>,
+    ) -> Self {
+        let device = queue.device();
+        let queue_flags = device.physical_device().queue_family_properties()
+            [queue.queue_family_index() as usize]
+            .queue_flags;
+        let sparse = Capabilities::of(device).sparse_residency
+            && queue_flags.intersects(QueueFlags::SPARSE_BINDING);
+        if sparse {
+            info!("Streaming textures with sparse residency");
+        } else {
+            info!("Streaming textures through staged uploads, as sparse residency is unsupported");
+        }
+
+        TextureStreamer {
+            memory_allocator,
+            command_buffer_allocator,
+            queue,
+            sparse,
+            textures: HashMap::new(),
+            released: VecDeque::new(),
+        }
+    }
+
+    /// Starts streaming the texture of `mips` from `path`, as `texture::mip_chain` computes them,
+    /// in place of any that was streamed from there before. Returns the texture with its coarsest
+    /// levels resident.
+    pub fn insert(&mut self, path: PathBuf, mips: Vec<RgbaImage>, frame: u64) -> Arc<Texture> {
+        let coarsest = mips
+            .iter()
+            .position(|mip| mip.width().max(mip.height()) <= RESIDENT_SIZE)
+            .unwrap() as u32;
+        let mut streamed = StreamedTexture {
+            mips,
+            resident: coarsest,
+            finest: 0,
+            coarsest,
+            unneeded_since: None,
+            sparse: None,
+        };
+        if self.sparse {
+            streamed.sparse = SparseTexture::new(&self.queue, &streamed.mips);
+        }
+        let texture = match self.stream(&mut streamed, coarsest, frame) {
+            Some(texture) => texture,
+            // Staging the levels into an image of their own halves it if it has to.
+            None => {
+                streamed.sparse = None;
+                streamed.finest = 0;
+                self.stream(&mut streamed, coarsest, frame).unwrap()
+            }
+        };
+
+        if let Some(old) = self.textures.insert(path, streamed) {
+            self.release(old, frame);
+        }
+        texture
+    }
+
+    /// Streams detail in and out of the textures as `needs` says, as `texture_needs` tells them.
+    /// Returns the textures to swap in, by path.
+    pub fn update(
+        &mut self,
+        needs: &HashMap<PathBuf, f32>,
+        frame: u64,
+    ) -> Vec<(PathBuf, Arc<Texture>)> {
+        let now = Instant::now();
+        // The textures to stream and the level to stream them to, most levels missing first.
+        let mut changes = Vec::new();
+        for (path, streamed) in &mut self.textures {
+            let wanted = streamed.wanted_level(needs.get(path).copied().unwrap_or(0.0));
+            if wanted < streamed.resident {
+                streamed.unneeded_since = None;
+                changes.push((path.clone(), wanted, streamed.resident - wanted));
+            } else if wanted > streamed.resident {
+                let since = *streamed.unneeded_since.get_or_insert(now);
+                if now - since >= DROP_DELAY {
+                    changes.push((path.clone(), wanted, 0));
+                }
+            } else {
+                streamed.unneeded_since = None;
+            }
+        }
+        changes.sort_by_key(|&(_, _, missing)| Reverse(missing));
+
+        let mut streamed_in = Vec::new();
+        for (path, level, _) in changes.into_iter().take(STREAMED_PER_FRAME) {
+            let mut streamed = self.textures.remove(&path).unwrap();
+            if let Some(texture) = self.stream(&mut streamed, level, frame) {
+                streamed_in.push((path.clone(), texture));
+            }
+            self.textures.insert(path, streamed);
+        }
+
+        streamed_in
+    }
+
+    /// Stops streaming the textures whose path `is_used` rejects, unbinds the memory of levels
+    /// that no frame in flight can be sampling anymore and frees that of released images. Call
+    /// this once the frame has been submitted.
+    pub fn end_frame(
+        &mut self,
+        frame: u64,
+        frames_in_flight: u64,
+        is_used: impl Fn(&Path) -> bool,
+    ) {
+        let unused: Vec<_> = self
+            .textures
+            .keys()
+            .filter(|path| !is_used(path))
+            .cloned()
+            .collect();
+        for path in unused {
+            let streamed = self.textures.remove(&path).unwrap();
+            self.release(streamed, frame);
+        }
+
+        for streamed in self.textures.values_mut() {
+            let Some(sparse) = &mut streamed.sparse else {
+                continue;
+            };
+            if sparse.bound < streamed.resident && sparse.narrowed + frames_in_flight <= frame {
+                sparse.unbind(&self.queue, sparse.bound..streamed.resident);
+                sparse.bound = streamed.resident;
+            }
+        }
+
+        while let Some(&(released, _)) = self.released.front() {
+            if released + frames_in_flight > frame {
+                break;
+            }
+            self.released.pop_front();
+        }
+    }
+
+    fn release(&mut self, streamed: StreamedTexture, frame: u64) {
+        if let Some(sparse) = streamed.sparse {
+            self.released.push_back((frame, sparse));
+        }
+    }
+
+    /// Makes the levels of `streamed` from `level` on resident, returning the texture to sample
+    /// them through, or `None` if they don't fit in memory.
+    fn stream(
+        &self,
+        streamed: &mut StreamedTexture,
+        level: u32,
+        frame: u64,
+    ) -> Option<Arc<Texture>> {
+        let Some(sparse) = &mut streamed.sparse else {
+            let texture = Texture::from_mips(
+                self.memory_allocator.clone(),
+                self.command_buffer_allocator.clone(),
+                &self.queue,
+                &streamed.mips[level as usize..],
+            );
+            // Levels that didn't fit were left out.
+            streamed.resident = level + texture.downscaled;
+            if texture.downscaled > 0 {
+                self.note_out_of_memory(streamed, level);
+            }
+            return Some(texture);
+        };
+
+        if level < sparse.bound {
+            let levels = level..sparse.bound;
+            if let Err(err) = sparse.bind(&self.queue, levels.clone()) {
+                debug!("Failed to bind memory to mip levels {levels:?}: {err}");
+                self.note_out_of_memory(streamed, level);
+                return None;
+            }
+            let texels: Vec<u8> = streamed.mips[levels.start as usize..levels.end as usize]
+                .iter()
+                .flat_map(|mip| mip.as_raw())
+                .copied()
+                .collect();
+            let Ok(staging_buffer) = texture::staging_buffer(&self.memory_allocator, &texels)
+            else {
+                self.note_out_of_memory(streamed, level);
+                return None;
+            };
+            texture::upload_levels(
+                self.command_buffer_allocator.clone(),
+                &self.queue,
+                staging_buffer,
+                &sparse.image,
+                levels,
+            );
+            sparse.bound = level;
+        }
+        if level > streamed.resident {
+            sparse.narrowed = frame;
+        }
+        streamed.resident = level;
+
+        let image = sparse.image.clone();
+        let view = ImageView::new(
+            image.clone(),
+            ImageViewCreateInfo {
+                subresource_range: ImageSubresourceRange {
+                    mip_levels: level..image.mip_levels(),
+                    ..image.subresource_range()
+                },
+                ..ImageViewCreateInfo::from_image(&image)
+            },
+        )
+        .unwrap();
+        Some(Arc::new(Texture {
+            image,
+            view,
+            downscaled: streamed.finest,
+        }))
+    }
+
+    /// Stops `streamed` from streaming in `level` again, which didn't fit in memory.
+    fn note_out_of_memory(&self, streamed: &mut StreamedTexture, level: u32) {
+        let (width, height) = streamed.mips[level as usize].dimensions();
+        warn!("Out of memory streaming in a {width}x{height} mip level, keeping the texture below");
+        streamed.finest = (level + 1).min(streamed.coarsest);
+    }
+}
+
+impl SparseTexture {
+    /// A sparse image of `mips`, with the mip tail bound through `queue`, or `None` if the device
+    /// can't make one.
+    fn new(queue: &Arc<Queue>, mips: &[RgbaImage]) -> Option<Self> {
+        let device = queue.device();
+        let (width, height) = mips[0].dimensions();
+        let raw_image = RawImage::new(
+            device.clone(),
+            ImageCreateInfo {
+                flags: ImageCreateFlags::SPARSE_BINDING | ImageCreateFlags::SPARSE_RESIDENCY,
+                image_type: ImageType::Dim2d,
+                format: Texture::FORMAT,
+                extent: [width, height, 1],
+                mip_levels: mips.len() as u32,
+                usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+        )
+        .inspect_err(|err| debug!("Failed to create a sparse texture: {err}"))
+        .ok()?;
+        let sparse_requirements = raw_image
+            .sparse_memory_requirements()
+            .iter()
+            .find(|requirements| {
+                requirements
+                    .format_properties
+                    .aspects
+                    .intersects(ImageAspects::COLOR)
+            })?
+            .clone();
+        let requirements = raw_image.memory_requirements()[0];
+        let memory_properties = device.physical_device().memory_properties();
+        let memory_type_index = (0..memory_properties.memory_types.len() as u32).find(|&i| {
+            requirements.memory_type_bits & (1 << i) != 0
+                && memory_properties.memory_types[i as usize]
+                    .property_flags
+                    .intersects(MemoryPropertyFlags::DEVICE_LOCAL)
+        })?;
+        // SAFETY: the mip tail is bound below, before anything samples the image, and the shaders
+        // only sample it through views of levels that are bound.
+        let image = Arc::new(unsafe { raw_image.assume_bound() });
+
+        let tail_start = sparse_requirements
+            .image_mip_tail_first_lod
+            .min(image.mip_levels());
+        let [granularity_width, granularity_height, _] =
+            sparse_requirements.format_properties.image_granularity;
+        let sparse = SparseTexture {
+            image: image.clone(),
+            levels: vec![None; tail_start as usize],
+            _mip_tail: None,
+            bound: image.mip_levels(),
+            narrowed: 0,
+            granularity: [granularity_width, granularity_height],
+            block_size: requirements.layout.alignment().as_devicesize(),
+            memory_type_index,
+        };
+        if tail_start == image.mip_levels() {
+            return Some(sparse);
+        }
+
+        let mip_tail = sparse
+            .allocate(sparse_requirements.image_mip_tail_size)
+            .inspect_err(|err| debug!("Failed to allocate the mip tail of a sparse texture: {err}"))
+            .ok()?;
+        let mut opaque_binds = SparseImageOpaqueMemoryBindInfo::new(image);
+        opaque_binds.binds.push(SparseImageOpaqueMemoryBind {
+            offset: sparse_requirements.image_mip_tail_offset,
+            size: sparse_requirements.image_mip_tail_size,
+            memory: Some((mip_tail.clone(), 0)),
+            ..Default::default()
+        });
+        bind_sparse(
+            queue,
+            BindSparseInfo {
+                image_opaque_binds: vec![opaque_binds],
+                ..Default::default()
+            },
+        )
+        .inspect_err(|err| debug!("Failed to bind the mip tail of a sparse texture: {err}"))
+        .ok()?;
+        Some(SparseTexture {
+            _mip_tail: Some(mip_tail),
+            ..sparse
+        })
+    }
+
+    fn allocate(&self, size: DeviceSize) -> Result<Arc<DeviceMemory>, Validated<VulkanError>> {
+        DeviceMemory::allocate(
+            self.image.device().clone(),
+            MemoryAllocateInfo {
+                allocation_size: size,
+                memory_type_index: self.memory_type_index,
+                ..Default::default()
+            },
+        )
+        .map(Arc::new)
+    }
+
+    /// Binds memory to those of `levels` that aren't in the mip tail, which have none.
+    fn bind(
+        &mut self,
+        queue: &Arc<Queue>,
+        levels: Range<u32>,
+    ) -> Result<(), Validated<VulkanError>> {
+        let levels = levels.start..levels.end.min(self.levels.len() as u32);
+        let mut image_binds = SparseImageMemoryBindInfo::new(self.image.clone());
+        let mut memories = Vec::new();
+        for mip_level in levels.clone() {
+            let extent = self.level_extent(mip_level);
+            let blocks = [0, 1].map(|axis| extent[axis].div_ceil(self.granularity[axis]));
+            let memory =
+                self.allocate(DeviceSize::from(blocks[0] * blocks[1]) * self.block_size)?;
+            image_binds.binds.push(SparseImageMemoryBind {
+                aspects: ImageAspects::COLOR,
+                mip_level,
+                extent: [extent[0], extent[1], 1],
+                memory: Some((memory.clone(), 0)),
+                ..Default::default()
+            });
+            memories.push(memory);
+        }
+        if memories.is_empty() {
+            return Ok(());
+        }
+
+        bind_sparse(
+            queue,
+            BindSparseInfo {
+                image_binds: vec![image_binds],
+                ..Default::default()
+            },
+        )?;
+        for (mip_level, memory) in levels.zip(memories) {
+            self.levels[mip_level as usize] = Some(memory);
+        }
+        Ok(())
+    }
+
+    /// Unbinds the memory of `levels`, which nothing pending may be sampling, and frees it.
+    fn unbind(&mut self, queue: &Arc<Queue>, levels: Range<u32>) {
+        let levels = levels.start..levels.end.min(self.levels.len() as u32);
+        let mut image_binds = SparseImageMemoryBindInfo::new(self.image.clone());
+        for mip_level in levels.clone() {
+            let extent = self.level_extent(mip_level);
+            image_binds.binds.push(SparseImageMemoryBind {
+                aspects: ImageAspects::COLOR,
+                mip_level,
+                extent: [extent[0], extent[1], 1],
+                memory: None,
+                ..Default::default()
+            });
+        }
+        if image_binds.binds.is_empty() {
+            return;
+        }
+
+        match bind_sparse(
+            queue,
+            BindSparseInfo {
+                image_binds: vec![image_binds],
+                ..Default::default()
+            },
+        ) {
+            Ok(()) => {
+                for mip_level in levels {
+                    self.levels[mip_level as usize] = None;
+                }
+            }
+            // The memory stays bound then, and is freed along with the image.
+            Err(err) => warn!("Failed to unbind mip levels {levels:?}: {err}"),
+        }
+    }
+
+    fn level_extent(&self, mip_level: u32) -> [u32; 2] {
+        let [width, height, _] = self.image.extent();
+        [width, height].map(|side| (side >> mip_level).max(1))
+    }
+}
+
+/// Binds sparse memory as `bind_info` says, and waits for the binding to finish.
+fn bind_sparse(
+    queue: &Arc<Queue>,
+    bind_info: BindSparseInfo,
+) -> Result<(), Validated<VulkanError>> {
+    let fence = Arc::new(Fence::new(
+        queue.device().clone(),
+        FenceCreateInfo::default(),
+    )?);
+    // SAFETY: nothing pending uses the parts of the images that are bound, the memory is kept
+    // alive for as long as it stays bound, and the fence is waited on before it is dropped.
+    queue.with(|mut queue| unsafe { queue.bind_sparse(&[bind_info], Some(&fence)) })?;
+    fence.wait(None)?;
+    Ok(())
+}
@@ -0,0 +1,169 @@
+// The clock that everything animated runs on: the simulation's fixed steps, the particles and the
+// water's waves. It follows the wall clock scaled by a factor, from a sixteenth of real time for
+// slow motion to four times as fast, and stands still while paused, except for single steps of
+// the simulation asked for one at a time. Space pauses and resumes, `.` steps while paused, and
+// `-` and `=` halve and double the scale.
+//
+// With the debug overlay, the scale is also shown as a slider along the bottom of the window, on a
+// logarithmic scale with a tick at every power of two, which can be dragged with the mouse. It is
+// drawn through the debug-draw layer straight in normalized device coordinates.
+
+use glam::{Vec2, Vec3, Vec4};
+use std::time::{Duration, Instant};
+use vulkano::pipeline::graphics::viewport::Viewport;
+
+use crate::debug_draw::DebugDraw;
+
+pub const MIN_SCALE: f32 = 1.0 / 16.0;
+pub const MAX_SCALE: f32 = 4.0;
+
+/// Where the slider's track runs, in normalized device coordinates, which have Y pointing down.
+const SLIDER_LEFT: f32 = -0.9;
+const SLIDER_RIGHT: f32 = -0.3;
+const SLIDER_Y: f32 = 0.9;
+
+/// How far above or below the track a press still grabs the slider, in normalized device
+/// coordinates.
+const SLIDER_REACH: f32 = 0.04;
+
+/// How close to real time, in halvings, a dragged slider snaps to it.
+const SNAP: f32 = 0.15;
+
+pub struct Time {
+    scale: f32,
+    paused: bool,
+    /// Whether a single step was asked for while paused, for the next update to make.
+    step_requested: bool,
+    /// The scaled time that has passed since the clock started.
+    elapsed: Duration,
+    last: Option<Instant>,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Time {
+            scale: 1.0,
+            paused: false,
+            step_requested: false,
+            elapsed: Duration::ZERO,
+            last: None,
+        }
+    }
+}
+
+impl Time {
+    /// Advances the clock to `now`, returning how much scaled time passed since the last update:
+    /// none while paused, unless a step of `step` was asked for. The first update only starts
+    /// the clock.
+    pub fn update(&mut self, now: Instant, step: Duration) -> Duration {
+        let last = self.last.replace(now).unwrap_or(now);
+        let delta = if self.paused {
+            if std::mem::take(&mut self.step_requested) {
+                step
+            } else {
+                Duration::ZERO
+            }
+        } else {
+            now.saturating_duration_since(last).mul_f32(self.scale)
+        };
+        self.elapsed += delta;
+        delta
+    }
+
+    /// Advances the clock by `delta`, as it was recorded to, whether it is paused or not. The
+    /// next update starts the clock again.
+    pub fn replay(&mut self, delta: Duration) {
+        self.elapsed += delta;
+        self.last = None;
+    }
+
+    /// The scaled time that has passed since the clock started.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.step_requested = false;
+    }
+
+    /// Asks for a single step while paused. Does nothing while running.
+    pub fn step(&mut self) {
+        if self.paused {
+            self.step_requested = true;
+        }
+    }
+
+    /// How the clock differs from real time, for the window title, or `None` if it doesn't.
+    pub fn describe(&self) -> Option<String> {
+        if self.paused {
+            Some("paused".to_owned())
+        } else if self.scale != 1.0 {
+            Some(format!("{}x time", self.scale))
+        } else {
+            None
+        }
+    }
+
+    /// Queues the slider of the time scale, in normalized device coordinates.
+    pub fn draw_slider(&self, debug_draw: &mut DebugDraw) {
+        let track = Vec4::new(0.7, 0.7, 0.7, 1.0);
+        let point = |x: f32, y: f32| Vec3::new(x, SLIDER_Y + y, 0.0);
+        debug_draw.line(point(SLIDER_LEFT, 0.0), point(SLIDER_RIGHT, 0.0), track);
+        // A tick at every power of two, the longest at real time.
+        for halvings in MIN_SCALE.log2() as i32..=MAX_SCALE.log2() as i32 {
+            let x = slider_x(2f32.powi(halvings));
+            let height = if halvings == 0 { 0.03 } else { 0.015 };
+            debug_draw.line(point(x, -height), point(x, height), track);
+        }
+        let knob = if self.paused {
+            Vec4::new(1.0, 0.2, 0.2, 1.0)
+        } else {
+            Vec4::new(1.0, 1.0, 0.0, 1.0)
+        };
+        let x = slider_x(self.scale);
+        debug_draw.wire_box(point(x - 0.008, -0.025), point(x + 0.008, 0.025), knob);
+    }
+}
+
+/// Whether `pixel`, in physical pixels from the top left of `viewport`'s target, is on the
+/// slider drawn in `viewport`.
+pub fn is_on_slider(viewport: &Viewport, pixel: Vec2) -> bool {
+    let ndc = to_ndc(viewport, pixel);
+    (SLIDER_LEFT - SLIDER_REACH..=SLIDER_RIGHT + SLIDER_REACH).contains(&ndc.x)
+        && (ndc.y - SLIDER_Y).abs() <= SLIDER_REACH
+}
+
+/// The time scale that the slider drawn in `viewport` is dragged to with the cursor at `pixel`.
+pub fn slider_scale(viewport: &Viewport, pixel: Vec2) -> f32 {
+    let ndc = to_ndc(viewport, pixel);
+    let fraction = ((ndc.x - SLIDER_LEFT) / (SLIDER_RIGHT - SLIDER_LEFT)).clamp(0.0, 1.0);
+    let halvings = MIN_SCALE.log2() + fraction * (MAX_SCALE.log2() - MIN_SCALE.log2());
+    if halvings.abs() < SNAP {
+        1.0
+    } else {
+        2f32.powf(halvings)
+    }
+}
+
+/// Where `scale` is along the slider, as an X coordinate.
+fn slider_x(scale: f32) -> f32 {
+    let fraction = (scale.log2() - MIN_SCALE.log2()) / (MAX_SCALE.log2() - MIN_SCALE.log2());
+    SLIDER_LEFT + fraction * (SLIDER_RIGHT - SLIDER_LEFT)
+}
+
+fn to_ndc(viewport: &Viewport, pixel: Vec2) -> Vec2 {
+    (pixel - Vec2::from(viewport.offset)) / Vec2::from(viewport.extent) * 2.0 - 1.0
+}
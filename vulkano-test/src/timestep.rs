@@ -0,0 +1,58 @@
+// A fixed-rate clock for the simulation. Rendering happens whenever the event loop gets around to
+// it, while the simulation advances in steps of a constant length, so that it behaves the same at
+// any frame rate. Frames that fall between two steps blend the last two simulated states. The time
+// that it is given comes from `Time`, which may be paused or scaled.
+
+use std::time::Duration;
+
+/// The most steps run to catch up at once. After a long stall, such as the window being dragged,
+/// the simulation skips ahead instead of spending several frames running steps.
+const MAX_STEPS: u32 = 8;
+
+pub struct FixedTimestep {
+    step: Duration,
+    /// Time that has passed but hasn't been simulated yet, always less than `step` after
+    /// `advance`.
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    /// A clock running `rate` steps per second.
+    pub fn new(rate: u32) -> Self {
+        FixedTimestep {
+            step: Duration::from_secs(1) / rate,
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    /// The length of a step, in seconds.
+    pub fn step(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    pub fn step_duration(&self) -> Duration {
+        self.step
+    }
+
+    /// Adds `elapsed`, the time passed since the last call, returning how many steps to simulate.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed;
+
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps > MAX_STEPS {
+            steps = MAX_STEPS;
+        }
+
+        steps
+    }
+
+    /// How far between the last two steps the current time is, from 0 to 1, for interpolating
+    /// what is rendered.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
@@ -0,0 +1,53 @@
+// Shader permutations. A shader with specialization constants for optional features becomes one
+// pipeline per combination of them, and most combinations are never drawn with. `ShaderVariants`
+// creates the pipeline of a combination the first time a draw asks for it and keeps it for the
+// ones after, so only the variants in use are ever compiled.
+//
+// The key is a small `Copy` struct of the features, made for each draw from whatever decides them,
+// such as the material and the render settings. Every variant has the same pipeline layout, so
+// switching between them keeps the descriptor sets and push constants that are bound.
+
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, hash::Hash, sync::Arc};
+use tracing::debug;
+use vulkano::pipeline::GraphicsPipeline;
+
+use crate::error::AppError;
+
+type CreatePipeline<K> = dyn Fn(K) -> Result<Arc<GraphicsPipeline>, AppError>;
+
+pub struct ShaderVariants<K> {
+    create: Box<CreatePipeline<K>>,
+    pipelines: RefCell<HashMap<K, Arc<GraphicsPipeline>>>,
+}
+
+impl<K: Copy + Eq + Hash + Debug> ShaderVariants<K> {
+    /// Creates an empty cache, in which `create` makes the pipeline for each key.
+    pub fn new(create: impl Fn(K) -> Result<Arc<GraphicsPipeline>, AppError> + 'static) -> Self {
+        ShaderVariants {
+            create: Box::new(create),
+            pipelines: RefCell::default(),
+        }
+    }
+
+    /// The pipeline for `key`, which is created if this is the first time it is asked for. A
+    /// variant that fails to be created is tried again the next time.
+    pub fn get(&self, key: K) -> Result<Arc<GraphicsPipeline>, AppError> {
+        if let Some(pipeline) = self.pipelines.borrow().get(&key) {
+            return Ok(pipeline.clone());
+        }
+
+        debug!("Creating the pipeline variant {key:?}");
+        let pipeline = (self.create)(key)?;
+        self.pipelines.borrow_mut().insert(key, pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// How many variants have been created so far.
+    pub fn len(&self) -> usize {
+        self.pipelines.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.borrow().is_empty()
+    }
+}
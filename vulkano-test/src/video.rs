@@ -0,0 +1,217 @@
+// Recording the window to a video, with F9 to start and stop. Every presented frame is copied to
+// host memory after it is rendered, and a worker thread pipes the pixels to `ffmpeg`, which must
+// be on the `PATH`, to be encoded as H.264 into an MP4 file. This is only built with the `video`
+// feature.
+//
+// Recording never holds up rendering. A frame is dropped, and counted as such, when every readback
+// buffer is still waiting for the GPU or when the encoder has fallen too far behind. The video
+// plays at a fixed `FRAME_RATE`, so dropped frames and a slower frame rate speed it up.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+use tracing::{error, info};
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    command_buffer::{AutoCommandBufferBuilder, CopyImageToBufferInfo},
+    format::Format,
+    image::{Image, ImageUsage},
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+};
+
+/// The frame rate the video is encoded at.
+const FRAME_RATE: u32 = 60;
+
+/// How many frames can be waiting for the GPU at once.
+const READBACK_BUFFERS: usize = 4;
+
+/// How many frames can be waiting for the encoder at once.
+const ENCODER_QUEUE: usize = 8;
+
+#[derive(Debug)]
+pub enum VideoError {
+    /// Only 8-bit RGBA and BGRA images can be encoded as they are.
+    Format(Format),
+    /// The swapchain images can't be copied from.
+    Usage,
+    Spawn(io::Error),
+}
+
+impl fmt::Display for VideoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideoError::Format(format) => write!(f, "{format:?} images can't be recorded"),
+            VideoError::Usage => write!(f, "the swapchain images can't be copied from"),
+            VideoError::Spawn(err) => write!(f, "failed to start ffmpeg: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for VideoError {}
+
+pub struct VideoRecorder {
+    path: PathBuf,
+    extent: [u32; 2],
+    /// Readback buffers that the next frame can be copied into.
+    free: Vec<Subbuffer<[u8]>>,
+    /// Readback buffers that frames were copied into, oldest first, waiting for the GPU.
+    pending: VecDeque<Subbuffer<[u8]>>,
+    sender: SyncSender<Vec<u8>>,
+    worker: JoinHandle<io::Result<()>>,
+    frames: u64,
+    dropped: u64,
+}
+
+impl VideoRecorder {
+    /// Starts encoding frames the size and format of `image` into the video at `path`.
+    pub fn start(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        image: &Image,
+        path: &Path,
+    ) -> Result<Self, VideoError> {
+        let pixel_format = match image.format() {
+            Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => "rgba",
+            Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => "bgra",
+            format => return Err(VideoError::Format(format)),
+        };
+        if !image.usage().intersects(ImageUsage::TRANSFER_SRC) {
+            return Err(VideoError::Usage);
+        }
+
+        let [width, height, _] = image.extent();
+        // x264 needs even dimensions for 4:2:0 chroma, so odd ones lose their last row or column.
+        let mut child = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y", "-f", "rawvideo", "-pix_fmt"])
+            .arg(pixel_format)
+            .arg("-s")
+            .arg(format!("{width}x{height}"))
+            .arg("-r")
+            .arg(FRAME_RATE.to_string())
+            .args(["-i", "-", "-vf", "crop=trunc(iw/2)*2:trunc(ih/2)*2"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(VideoError::Spawn)?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(ENCODER_QUEUE);
+        let worker = thread::Builder::new()
+            .name("video-encoder".into())
+            .spawn(move || {
+                for pixels in receiver {
+                    stdin.write_all(&pixels)?;
+                }
+                // Closing the pipe lets ffmpeg finish the file.
+                drop(stdin);
+                let status = child.wait()?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(io::Error::other(format!("ffmpeg exited with {status}")))
+                }
+            })
+            .map_err(VideoError::Spawn)?;
+
+        let free = (0..READBACK_BUFFERS)
+            .map(|_| {
+                Buffer::new_slice::<u8>(
+                    memory_allocator.clone(),
+                    BufferCreateInfo {
+                        usage: BufferUsage::TRANSFER_DST,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                            | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                        ..Default::default()
+                    },
+                    u64::from(width) * u64::from(height) * 4,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        info!("Recording to {}", path.display());
+        Ok(VideoRecorder {
+            path: path.to_owned(),
+            extent: [width, height],
+            free,
+            pending: VecDeque::new(),
+            sender,
+            worker,
+            frames: 0,
+            dropped: 0,
+        })
+    }
+
+    /// The size of the frames being recorded.
+    pub fn extent(&self) -> [u32; 2] {
+        self.extent
+    }
+
+    /// Records a copy of `image` into `builder`, after whatever the frame drew into it, or drops
+    /// the frame if there is no buffer to copy into.
+    pub fn record<L>(&mut self, builder: &mut AutoCommandBufferBuilder<L>, image: &Arc<Image>) {
+        self.poll();
+
+        let Some(buffer) = self.free.pop() else {
+            self.dropped += 1;
+            return;
+        };
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                image.clone(),
+                buffer.clone(),
+            ))
+            .unwrap();
+        self.pending.push_back(buffer);
+    }
+
+    /// Hands the frames that the GPU has finished copying to the encoder.
+    pub fn poll(&mut self) {
+        while let Some(buffer) = self.pending.front() {
+            // The buffer stays locked until the future of its frame has been cleaned up.
+            let Ok(pixels) = buffer.read().map(|pixels| pixels.to_vec()) else {
+                break;
+            };
+            let buffer = self.pending.pop_front().unwrap();
+            self.free.push(buffer);
+
+            match self.sender.try_send(pixels) {
+                Ok(()) => self.frames += 1,
+                // If the encoder quit, `finish` reports why.
+                Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => self.dropped += 1,
+            }
+        }
+    }
+
+    /// Encodes the frames that are left and waits for the video to be written. The frames in
+    /// flight must have finished and their futures been dropped, or their copies are lost.
+    pub fn finish(mut self) {
+        self.poll();
+        self.dropped += self.pending.len() as u64;
+
+        drop(self.sender);
+        match self.worker.join() {
+            Ok(Ok(())) => info!(
+                "Saved {}: {} frames, {} dropped",
+                self.path.display(),
+                self.frames,
+                self.dropped,
+            ),
+            Ok(Err(err)) => error!("Failed to record {}: {err}", self.path.display()),
+            Err(_) => error!("The video encoder of {} panicked", self.path.display()),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// Watches individual files for changes on disk. The containing directory is watched rather than
+// the file itself, because most editors save by writing a new file and renaming it over the old
+// one, which would silently end a watch placed on the original inode. Whole directories can be
+// watched too, for every file in them, including those that don't exist yet.
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    files: Arc<Mutex<HashSet<PathBuf>>>,
+    /// The directories whose every file is watched.
+    watched_directories: Arc<Mutex<HashSet<PathBuf>>>,
+    directories: HashSet<PathBuf>,
+}
+
+impl FileWatcher {
+    /// Creates a watcher that calls `on_change` with the absolute path of a watched file whenever
+    /// it is created or modified. `on_change` runs on the watcher's own thread.
+    pub fn new(on_change: impl Fn(PathBuf) + Send + 'static) -> notify::Result<Self> {
+        let files = Arc::new(Mutex::new(HashSet::<PathBuf>::new()));
+        let watched_directories = Arc::new(Mutex::new(HashSet::<PathBuf>::new()));
+
+        let watcher = notify::recommended_watcher({
+            let files = files.clone();
+            let watched_directories = watched_directories.clone();
+
+            move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                if !(event.kind.is_create() || event.kind.is_modify()) {
+                    return;
+                }
+
+                let files = files.lock().unwrap();
+                let watched_directories = watched_directories.lock().unwrap();
+                for path in event.paths {
+                    if files.contains(&path)
+                        || path
+                            .parent()
+                            .is_some_and(|directory| watched_directories.contains(directory))
+                    {
+                        on_change(path);
+                    }
+                }
+            }
+        })?;
+
+        Ok(FileWatcher {
+            watcher,
+            files,
+            watched_directories,
+            directories: HashSet::new(),
+        })
+    }
+
+    /// Starts watching `path`, which doesn't need to exist yet. Returns the absolute path that
+    /// will be passed to the change callback.
+    pub fn watch(&mut self, path: &Path) -> notify::Result<PathBuf> {
+        let path = std::path::absolute(path)?;
+        let directory = path.parent().unwrap_or(Path::new("/")).to_owned();
+
+        if !self.directories.contains(&directory) {
+            self.watcher
+                .watch(&directory, RecursiveMode::NonRecursive)?;
+            self.directories.insert(directory);
+        }
+        self.files.lock().unwrap().insert(path.clone());
+
+        Ok(path)
+    }
+
+    /// Starts watching every file directly in `path`, which must exist. Returns the absolute path
+    /// of the directory, which the paths passed to the change callback start with.
+    pub fn watch_directory(&mut self, path: &Path) -> notify::Result<PathBuf> {
+        let path = std::path::absolute(path)?;
+
+        if !self.directories.contains(&path) {
+            self.watcher.watch(&path, RecursiveMode::NonRecursive)?;
+            self.directories.insert(path.clone());
+        }
+        self.watched_directories
+            .lock()
+            .unwrap()
+            .insert(path.clone());
+
+        Ok(path)
+    }
+}
@@ -0,0 +1,317 @@
+// An animated water surface. The surface is a flat grid around the origin, made in the vertex
+// shader from the vertex index alone, which a sum of sine waves moves up and down. It is shaded
+// from the scene behind it: once the scene has been drawn into an `OffscreenTarget`, `capture`
+// copies the target's color and depth aside, and `draw` then draws the surface into the target
+// again in a render pass that keeps what it holds, refracting the copy of the scene and reflecting
+// the sky. The copies are needed as a render pass can't sample the images it draws into.
+//
+// Only what is on screen can be refracted, so the scene near the edges is seen through the water
+// as if it were straight behind it. The sky is all that the surface reflects.
+
+use glam::{Mat4, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use vulkano::{
+    buffer::{
+        allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo},
+        BufferContents, BufferUsage,
+    },
+    command_buffer::{AutoCommandBufferBuilder, CopyImageInfo},
+    descriptor_set::{
+        allocator::StandardDescriptorSetAllocator, DescriptorSet, WriteDescriptorSet,
+    },
+    device::DeviceOwned,
+    image::{
+        sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+        Image, ImageCreateInfo, ImageType, ImageUsage,
+    },
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator},
+    pipeline::{
+        graphics::{
+            color_blend::ColorBlendAttachmentState,
+            depth_stencil::{DepthState, DepthStencilState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineShaderStageCreateInfo,
+    },
+    render_pass::Subpass,
+};
+
+use crate::{
+    bounds::Aabb,
+    components::Light,
+    debug_utils,
+    error::AppError,
+    gamma::Output,
+    offscreen::{self, OffscreenTarget},
+    scene_pipeline::FrameUniforms,
+    shader::{self, ShaderStage},
+};
+
+/// How many quads wide and deep the grid of the surface is.
+const GRID_QUADS: u32 = 128;
+
+/// How far the waves reach above and below the level of the surface, which is the sum of their
+/// amplitudes in `shaders/water.vert`.
+const WAVE_HEIGHT: f32 = 0.14;
+
+/// The water of a scene.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Water {
+    /// The height of the surface at rest.
+    pub level: f32,
+    /// How large the surface is along x and z. It is centered on the origin.
+    pub size: Vec2,
+}
+
+impl Water {
+    /// The bounds of the surface, with the waves at their highest and lowest.
+    pub fn bounds(&self) -> Aabb {
+        let half_size = self.size * 0.5;
+        Aabb::new(
+            Vec3::new(-half_size.x, self.level - WAVE_HEIGHT, -half_size.y),
+            Vec3::new(half_size.x, self.level + WAVE_HEIGHT, half_size.y),
+        )
+    }
+}
+
+/// The `PushConstants` block of `shaders/include/water.glsl`.
+#[derive(BufferContents)]
+#[repr(C)]
+struct PushConstants {
+    inverse_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    sky_color: [f32; 4],
+    surface: [f32; 4],
+}
+
+/// The copies of a target that the surface refracts.
+struct Captured {
+    color: Arc<ImageView>,
+    depth: Arc<ImageView>,
+}
+
+pub struct WaterPass {
+    pipeline: Arc<GraphicsPipeline>,
+    memory_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+    uniform_buffer_allocator: SubbufferAllocator,
+    sampler: Arc<Sampler>,
+    /// Recreated when the size or formats of the target change.
+    captured: Option<Captured>,
+    output: Output,
+}
+
+impl WaterPass {
+    /// Creates the pipeline of the surface, for drawing into `subpass` of a target with a depth
+    /// buffer.
+    pub fn new(
+        memory_allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
+        subpass: Subpass,
+    ) -> Result<Self, AppError> {
+        let device = memory_allocator.device().clone();
+
+        let vs = shader::load_with_defines(
+            device.clone(),
+            include_str!("shaders/water.vert"),
+            ShaderStage::Vertex,
+            &[("GRID_QUADS", GRID_QUADS.to_string())],
+        )?
+        .entry_point("main")
+        .unwrap();
+        let fs = shader::load(
+            device.clone(),
+            include_str!("shaders/water.frag"),
+            ShaderStage::Fragment,
+        )?
+        .entry_point("main")
+        .unwrap();
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vs),
+            PipelineShaderStageCreateInfo::new(fs),
+        ];
+        let layout = shader::reflect_layout::<PushConstants>(device.clone(), &stages)?;
+        let pipeline = GraphicsPipeline::new(
+            device.clone(),
+            None,
+            GraphicsPipelineCreateInfo {
+                stages: stages.into_iter().collect(),
+                // The grid is made from the vertex index.
+                vertex_input_state: Some(VertexInputState::default()),
+                input_assembly_state: Some(InputAssemblyState::default()),
+                viewport_state: Some(ViewportState::default()),
+                // The surface is seen from below as well.
+                rasterization_state: Some(RasterizationState::default()),
+                depth_stencil_state: Some(DepthStencilState {
+                    depth: Some(DepthState::simple()),
+                    ..Default::default()
+                }),
+                multisample_state: Some(MultisampleState::default()),
+                color_blend_state: Some(offscreen::color_blend_state(
+                    &subpass,
+                    &[ColorBlendAttachmentState::default()],
+                )),
+                dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::layout(layout)
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+        debug_utils::name(&pipeline, "water");
+
+        let uniform_buffer_allocator = SubbufferAllocator::new(
+            memory_allocator.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::UNIFORM_BUFFER,
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+        );
+        // The depth is fetched without filtering, which not every depth format supports.
+        let sampler = Sampler::new(
+            device,
+            SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            },
+        )
+        .map_err(AppError::Pipeline)?;
+
+        Ok(WaterPass {
+            pipeline,
+            memory_allocator,
+            descriptor_set_allocator,
+            uniform_buffer_allocator,
+            sampler,
+            captured: None,
+            output: Output::default(),
+        })
+    }
+
+    /// Records copies of the color and depth of `target`, which must have a depth buffer, for the
+    /// next `draw` to refract. This must be outside of a render pass, once the scene has been
+    /// drawn into the target.
+    pub fn capture<L>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        target: &OffscreenTarget,
+    ) {
+        let (color, depth) = (target.color(), target.depth().unwrap());
+        if self.captured.as_ref().is_none_or(|captured| {
+            captured.color.image().extent() != color.image().extent()
+                || captured.color.format() != color.format()
+        }) {
+            let image = |source: &Arc<ImageView>| {
+                let image = Image::new(
+                    self.memory_allocator.clone(),
+                    ImageCreateInfo {
+                        image_type: ImageType::Dim2d,
+                        format: source.format(),
+                        extent: source.image().extent(),
+                        usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+                        ..Default::default()
+                    },
+                    AllocationCreateInfo::default(),
+                )
+                .unwrap();
+                ImageView::new_default(image).unwrap()
+            };
+            self.captured = Some(Captured {
+                color: image(color),
+                depth: image(depth),
+            });
+        }
+        let captured = self.captured.as_ref().unwrap();
+
+        for (source, copy) in [(color, &captured.color), (depth, &captured.depth)] {
+            builder
+                .copy_image(CopyImageInfo::images(
+                    source.image().clone(),
+                    copy.image().clone(),
+                ))
+                .unwrap();
+        }
+    }
+
+    /// Writes colors out as `output` asks. The scene that is refracted has to have been
+    /// drawn with the same.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    /// Records a draw of `water` into the current subpass, refracting what the last `capture`
+    /// copied, which must have been of a target of the size of `viewport`. The surface is lit by
+    /// `light`, reflects `sky_color` and is seen through `view_proj` from `eye`, with its waves as
+    /// they are `time` seconds into the clock of `Time`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw<L>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L>,
+        water: &Water,
+        view_proj: Mat4,
+        eye: Vec3,
+        time: f32,
+        light: &Light,
+        sky_color: [f32; 4],
+        viewport: Viewport,
+    ) {
+        let Some(captured) = &self.captured else {
+            return;
+        };
+
+        let uniform_buffer = self.uniform_buffer_allocator.allocate_sized().unwrap();
+        *uniform_buffer.write().unwrap() =
+            FrameUniforms::new(view_proj, light).with_output(self.output);
+        let layout = self.pipeline.layout();
+        let descriptor_set = DescriptorSet::new(
+            self.descriptor_set_allocator.clone(),
+            layout.set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::buffer(0, uniform_buffer),
+                WriteDescriptorSet::image_view(1, captured.color.clone()),
+                WriteDescriptorSet::image_view(2, captured.depth.clone()),
+                WriteDescriptorSet::sampler(3, self.sampler.clone()),
+            ],
+            [],
+        )
+        .unwrap();
+
+        builder
+            .set_viewport(0, [viewport].into_iter().collect())
+            .unwrap()
+            .bind_pipeline_graphics(self.pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                layout.clone(),
+                0,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                layout.clone(),
+                0,
+                PushConstants {
+                    inverse_view_proj: view_proj.inverse().to_cols_array_2d(),
+                    eye: eye.extend(time).to_array(),
+                    sky_color,
+                    surface: [water.level, water.size.x, water.size.y, 0.0],
+                },
+            )
+            .unwrap();
+
+        // SAFETY: the shaders make the vertices from their index, without reading any buffer.
+        unsafe { builder.draw(GRID_QUADS * GRID_QUADS * 6, 1, 0, 0) }.unwrap();
+    }
+}
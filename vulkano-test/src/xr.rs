@@ -0,0 +1,466 @@
+// Rendering to a headset through OpenXR, for `vulkano-test --xr`, which is only built with the
+// `xr` feature. The runtime is set up before the GPU, to tell which one the headset is connected
+// to and what Vulkan extensions it needs, and `Gpu::xr` creates the instance and the device with
+// those, as `XR_KHR_vulkan_enable` lets apps do. The session is then created from that device and
+// its queue.
+//
+// Each eye has a swapchain of its own, whose images are wrapped as `OffscreenTarget`s and drawn
+// into through `ScenePipeline` by a `HeadlessRenderer`, like any offscreen frame. The runtime
+// times the frames: `wait` blocks until the next one should start, and the eyes are drawn from
+// where they will be when it is displayed, between `begin` and `end`. Each eye's image is only
+// released once the GPU has finished with it, which keeps its synchronization simple at the cost
+// of the eyes not overlapping.
+//
+// The headset's local space, whose origin is where the head was when the session started, is put
+// at the scene's camera, facing the way it looks but kept level, with a scene unit to the meter.
+// There are no controllers, and the scene doesn't move. The session ends when the runtime stops
+// it, such as from its menu.
+
+use ash::vk::Handle;
+use glam::{camera::rh::proj, Mat4, Quat, Vec3};
+use openxr as xr;
+use std::{ffi::c_void, fmt, sync::Arc, thread, time::Duration};
+use tracing::{info, warn};
+use vulkano::{
+    device::{physical::PhysicalDevice, DeviceExtensions},
+    image::{sys::RawImage, view::ImageView, ImageCreateInfo, ImageType, ImageUsage},
+    instance::InstanceExtensions,
+    Validated, VulkanObject,
+};
+
+use crate::{
+    app::SceneSource,
+    assets::{AssetError, Assets},
+    bounds::Aabb,
+    camera::Camera,
+    error::AppError,
+    headless::HeadlessRenderer,
+    offscreen::OffscreenTarget,
+    scene::Scene,
+    scene_file,
+    settings::RenderSettings,
+};
+
+/// A view for each eye.
+const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+/// How often the runtime is asked whether the session can start, while it can't.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum XrError {
+    /// The OpenXR loader isn't installed, or isn't one.
+    Loader(xr::LoadError),
+    /// A call to the runtime failed, including those to its Vulkan extension, which fail with
+    /// `ERROR_EXTENSION_NOT_PRESENT` where it can't render with Vulkan.
+    Runtime(xr::sys::Result),
+    /// The device's Vulkan version is outside of what the runtime supports.
+    VulkanVersion {
+        version: vulkano::Version,
+        min: xr::Version,
+        max: xr::Version,
+    },
+    /// The runtime can't make swapchains of `HeadlessRenderer::FORMAT`.
+    Format,
+    /// The GPU or what renders with it couldn't be set up.
+    Gpu(AppError),
+    Scene(Box<dyn std::error::Error + Send + Sync>),
+    /// A mesh or texture of the scene failed to load.
+    Asset(AssetError),
+}
+
+impl fmt::Display for XrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XrError::Loader(err) => write!(f, "failed to load the OpenXR loader: {err}"),
+            XrError::Runtime(result) => write!(f, "OpenXR error: {result}"),
+            XrError::VulkanVersion { version, min, max } => write!(
+                f,
+                "the OpenXR runtime supports Vulkan {min} to {max}, not {version}",
+            ),
+            XrError::Format => write!(
+                f,
+                "the OpenXR runtime can't make swapchains of {:?}",
+                HeadlessRenderer::FORMAT,
+            ),
+            XrError::Gpu(err) => err.fmt(f),
+            XrError::Scene(err) => write!(f, "failed to load scene: {err}"),
+            XrError::Asset(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for XrError {}
+
+impl From<xr::sys::Result> for XrError {
+    fn from(result: xr::sys::Result) -> Self {
+        XrError::Runtime(result)
+    }
+}
+
+/// The OpenXR instance and the headset it renders to.
+pub struct XrRuntime {
+    instance: xr::Instance,
+    system: xr::SystemId,
+    requirements: xr::vulkan::Requirements,
+    instance_extensions: InstanceExtensions,
+    device_extensions: DeviceExtensions,
+}
+
+impl XrRuntime {
+    /// Loads the OpenXR loader and connects to the runtime it picks, which fails if there is no
+    /// headset.
+    pub fn new() -> Result<Self, XrError> {
+        // SAFETY: the loader is a library made for being loaded like this.
+        let entry = unsafe { xr::Entry::load(&()) }.map_err(|err| match err {
+            xr::EntryError::Load(err) => XrError::Loader(err),
+            xr::EntryError::Xr(result) => XrError::Runtime(result),
+        })?;
+        if !entry.enumerate_extensions()?.khr_vulkan_enable {
+            return Err(XrError::Runtime(
+                xr::sys::Result::ERROR_EXTENSION_NOT_PRESENT,
+            ));
+        }
+
+        let mut extensions = xr::ExtensionSet::default();
+        extensions.khr_vulkan_enable = true;
+        let instance = entry.create_instance(
+            &xr::ApplicationInfo {
+                application_name: "vulkano-test",
+                engine_name: "vulkano-test",
+                api_version: xr::Version::new(1, 0, 0),
+                ..Default::default()
+            },
+            &extensions,
+            &[],
+            &(),
+        )?;
+        let properties = instance.properties()?;
+        info!(
+            "Using OpenXR runtime: {} {}",
+            properties.runtime_name, properties.runtime_version,
+        );
+
+        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+        // The runtime has to be asked for these before a session is created.
+        let requirements = instance.graphics_requirements::<xr::Vulkan>(system)?;
+        let instance_extensions = instance.vulkan_legacy_instance_extensions(system)?;
+        let device_extensions = instance.vulkan_legacy_device_extensions(system)?;
+
+        Ok(XrRuntime {
+            instance,
+            system,
+            requirements,
+            instance_extensions: instance_extensions.split_whitespace().collect(),
+            device_extensions: device_extensions.split_whitespace().collect(),
+        })
+    }
+
+    /// The instance extensions that the runtime needs.
+    pub fn instance_extensions(&self) -> InstanceExtensions {
+        self.instance_extensions
+    }
+
+    /// The device extensions that the runtime needs.
+    pub fn device_extensions(&self) -> DeviceExtensions {
+        self.device_extensions
+    }
+
+    /// Whether `physical_device` is the GPU that the headset is connected to.
+    pub fn is_graphics_device(&self, physical_device: &PhysicalDevice) -> bool {
+        let instance = physical_device.instance().handle().as_raw() as usize as *const c_void;
+        // SAFETY: the instance is a valid one while a physical device of it is alive.
+        match unsafe { self.instance.vulkan_graphics_device(self.system, instance) } {
+            Ok(device) => device as usize as u64 == physical_device.handle().as_raw(),
+            Err(err) => {
+                warn!("Failed to get the GPU of the headset: {err}");
+                false
+            }
+        }
+    }
+}
+
+/// The swapchain of an eye.
+struct Eye {
+    /// A target for each image of the swapchain, in the order the runtime lists them. They are
+    /// dropped before the swapchain, which destroys the images.
+    targets: Vec<OffscreenTarget>,
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    extent: [u32; 2],
+}
+
+/// Shows the scene of `scene_source` on the headset of `runtime`, drawing it with `renderer`,
+/// whose GPU must have been created by `Gpu::xr`, until the runtime ends the session.
+pub fn run(
+    runtime: &XrRuntime,
+    renderer: &HeadlessRenderer,
+    scene_source: &SceneSource,
+) -> Result<(), XrError> {
+    let gpu = renderer.gpu();
+    let version = gpu.device.api_version();
+    let xr_version = xr::Version::new(version.major as u16, version.minor as u16, version.patch);
+    let xr::vulkan::Requirements {
+        min_api_version_supported: min,
+        max_api_version_supported: max,
+    } = runtime.requirements;
+    // Any minor version of the newest major version is fine.
+    if xr_version < min || xr_version.major() > max.major() {
+        return Err(XrError::VulkanVersion { version, min, max });
+    }
+
+    let mut assets = Assets::new(
+        gpu.memory_allocator.clone(),
+        gpu.command_buffer_allocator.clone(),
+        gpu.queue.clone(),
+        || {},
+    );
+    let scene = match scene_source {
+        SceneSource::Demo => Scene::demo(&mut assets),
+        SceneSource::Gltf(path) => {
+            Scene::load_gltf(&mut assets, path).map_err(|err| XrError::Scene(err.into()))?
+        }
+        SceneSource::Ron(path) => {
+            scene_file::load(&mut assets, path).map_err(|err| XrError::Scene(err.into()))?
+        }
+    };
+    if let Some(err) = assets.wait_for_loads().into_iter().next() {
+        return Err(XrError::Asset(err));
+    }
+    let bounds = scene.bounds();
+    let placement = Placement::new(
+        &scene.camera.unwrap_or_else(|| Camera::framing(&bounds)),
+        &bounds,
+    );
+    let clear_color = RenderSettings::default().clear_color;
+
+    // SAFETY: the handles are those of the renderer's device, which outlives the session, and
+    // `Gpu::xr` created it with the extensions and on the GPU that the runtime asked for.
+    let (session, mut frame_waiter, mut frame_stream) = unsafe {
+        runtime.instance.create_session::<xr::Vulkan>(
+            runtime.system,
+            &xr::vulkan::SessionCreateInfo {
+                instance: gpu.instance.handle().as_raw() as usize as *const c_void,
+                physical_device: gpu.device.physical_device().handle().as_raw() as usize
+                    as *const c_void,
+                device: gpu.device.handle().as_raw() as usize as *const c_void,
+                queue_family_index: gpu.queue.queue_family_index(),
+                queue_index: gpu.queue.queue_index(),
+            },
+        )
+    }?;
+    let space =
+        session.create_reference_space(xr::ReferenceSpaceType::LOCAL, xr::Posef::IDENTITY)?;
+    let blend_mode = runtime
+        .instance
+        .enumerate_environment_blend_modes(runtime.system, VIEW_TYPE)?[0];
+    let mut eyes = create_eyes(runtime, &session, renderer)?;
+
+    let mut events = xr::EventDataBuffer::new();
+    let mut running = false;
+    loop {
+        while let Some(event) = runtime.instance.poll_event(&mut events)? {
+            match event {
+                xr::Event::SessionStateChanged(change) => match change.state() {
+                    xr::SessionState::READY => {
+                        session.begin(VIEW_TYPE)?;
+                        running = true;
+                        info!("The XR session started");
+                    }
+                    xr::SessionState::STOPPING => {
+                        session.end()?;
+                        running = false;
+                        info!("The XR session stopped");
+                    }
+                    xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => return Ok(()),
+                    _ => {}
+                },
+                xr::Event::InstanceLossPending(_) => return Ok(()),
+                _ => {}
+            }
+        }
+        if !running {
+            thread::sleep(IDLE_POLL_INTERVAL);
+            continue;
+        }
+
+        let frame = frame_waiter.wait()?;
+        frame_stream.begin()?;
+        if !frame.should_render {
+            frame_stream.end(frame.predicted_display_time, blend_mode, &[])?;
+            continue;
+        }
+
+        let (_, views) = session.locate_views(VIEW_TYPE, frame.predicted_display_time, &space)?;
+        for (eye, view) in eyes.iter_mut().zip(&views) {
+            let index = eye.swapchain.acquire_image()?;
+            eye.swapchain.wait_image(xr::Duration::INFINITE)?;
+            renderer.render_to(
+                &eye.targets[index as usize],
+                &scene,
+                placement.view_proj(view),
+                clear_color,
+            );
+            eye.swapchain.release_image()?;
+        }
+
+        let projection_views: Vec<_> = eyes
+            .iter()
+            .zip(&views)
+            .map(|(eye, view)| {
+                xr::CompositionLayerProjectionView::new()
+                    .pose(view.pose)
+                    .fov(view.fov)
+                    .sub_image(
+                        xr::SwapchainSubImage::new()
+                            .swapchain(&eye.swapchain)
+                            .image_array_index(0)
+                            .image_rect(xr::Rect2Di {
+                                offset: xr::Offset2Di { x: 0, y: 0 },
+                                extent: xr::Extent2Di {
+                                    width: eye.extent[0] as i32,
+                                    height: eye.extent[1] as i32,
+                                },
+                            }),
+                    )
+            })
+            .collect();
+        frame_stream.end(
+            frame.predicted_display_time,
+            blend_mode,
+            &[&xr::CompositionLayerProjection::new()
+                .space(&space)
+                .views(&projection_views)],
+        )?;
+    }
+}
+
+/// Creates a swapchain for each eye, of the size the runtime recommends, with targets drawing
+/// into its images.
+fn create_eyes(
+    runtime: &XrRuntime,
+    session: &xr::Session<xr::Vulkan>,
+    renderer: &HeadlessRenderer,
+) -> Result<Vec<Eye>, XrError> {
+    let format = ash::vk::Format::from(HeadlessRenderer::FORMAT).as_raw() as u32;
+    if !session.enumerate_swapchain_formats()?.contains(&format) {
+        return Err(XrError::Format);
+    }
+
+    let device = &renderer.gpu().device;
+    runtime
+        .instance
+        .enumerate_view_configuration_views(runtime.system, VIEW_TYPE)?
+        .into_iter()
+        .map(|view| {
+            let extent = [
+                view.recommended_image_rect_width,
+                view.recommended_image_rect_height,
+            ];
+            let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+                create_flags: xr::SwapchainCreateFlags::EMPTY,
+                usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT,
+                format,
+                sample_count: 1,
+                width: extent[0],
+                height: extent[1],
+                face_count: 1,
+                array_size: 1,
+                mip_count: 1,
+            })?;
+            let targets = swapchain
+                .enumerate_images()?
+                .into_iter()
+                .map(|handle| {
+                    // SAFETY: the runtime created the image from the device, as described, and
+                    // keeps it until the swapchain is destroyed, after the target.
+                    let image = unsafe {
+                        RawImage::from_handle_borrowed(
+                            device.clone(),
+                            ash::vk::Image::from_raw(handle),
+                            ImageCreateInfo {
+                                image_type: ImageType::Dim2d,
+                                format: HeadlessRenderer::FORMAT,
+                                extent: [extent[0], extent[1], 1],
+                                usage: ImageUsage::COLOR_ATTACHMENT,
+                                ..Default::default()
+                            },
+                        )
+                    }
+                    .map_err(|err| XrError::Gpu(AppError::Swapchain(Validated::Error(err))))?;
+                    // SAFETY: the runtime bound memory to the image.
+                    let image = Arc::new(unsafe { image.assume_bound() });
+                    let view = ImageView::new_default(image).map_err(|source| {
+                        XrError::Gpu(AppError::ImageView {
+                            name: "OpenXR swapchain image",
+                            source,
+                        })
+                    })?;
+                    renderer.create_target_for(view).map_err(XrError::Gpu)
+                })
+                .collect::<Result<_, XrError>>()?;
+
+            Ok(Eye {
+                targets,
+                swapchain,
+                extent,
+            })
+        })
+        .collect()
+}
+
+/// Where the headset's local space is in the scene, and the clip planes to draw it with.
+struct Placement {
+    world_from_local: Mat4,
+    near: f32,
+    far: f32,
+}
+
+impl Placement {
+    /// Puts the headset at `camera`, facing the way it looks but kept level, with the clip
+    /// planes that `camera` has for `bounds`.
+    fn new(camera: &Camera, bounds: &Aabb) -> Self {
+        let forward = camera.target - camera.eye;
+        // Turns -Z, which is forward in local space, to the direction of `forward` on the ground.
+        let yaw = (-forward.x).atan2(-forward.z);
+        let (near, far) = camera.clip_planes(bounds);
+
+        Placement {
+            world_from_local: Mat4::from_rotation_translation(
+                Quat::from_rotation_y(yaw),
+                camera.eye,
+            ),
+            near,
+            far,
+        }
+    }
+
+    /// The combined view and projection matrix of `view`, located in local space.
+    fn view_proj(&self, view: &xr::View) -> Mat4 {
+        let xr::Posef {
+            orientation,
+            position,
+        } = view.pose;
+        let local_from_eye = Mat4::from_rotation_translation(
+            Quat::from_xyzw(orientation.x, orientation.y, orientation.z, orientation.w).normalize(),
+            Vec3::new(position.x, position.y, position.z),
+        );
+        // The angles are to the left, right, top and bottom edges of the view, those to the left
+        // and bottom negative.
+        let xr::Fovf {
+            angle_left,
+            angle_right,
+            angle_up,
+            angle_down,
+        } = view.fov;
+        let proj = proj::vulkan::frustum(
+            self.near * angle_left.tan(),
+            self.near * angle_right.tan(),
+            self.near * angle_down.tan(),
+            self.near * angle_up.tan(),
+            self.near,
+            self.far,
+        );
+
+        proj * (self.world_from_local * local_from_eye).inverse()
+    }
+}
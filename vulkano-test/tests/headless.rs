@@ -0,0 +1,56 @@
+// Renders the demo scene on a software implementation of Vulkan, so that the results don't depend
+// on the machine. Where none is installed, or there is no Vulkan at all, the test is skipped.
+
+use vulkano_test::{
+    assets::Assets, camera::Camera, device_requirements::DeviceRequirements, error::AppError,
+    gpu::Gpu, headless::HeadlessRenderer, scene::Scene,
+};
+
+const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+fn renderer() -> Option<HeadlessRenderer> {
+    let mut requirements = DeviceRequirements::new();
+    HeadlessRenderer::register_requirements(&mut requirements);
+    match Gpu::software(requirements) {
+        Ok(gpu) => Some(HeadlessRenderer::new(gpu).unwrap()),
+        Err(err @ (AppError::NoDevice | AppError::Library(_))) => {
+            eprintln!("skipped: {err}");
+            None
+        }
+        Err(err) => panic!("{err}"),
+    }
+}
+
+#[test]
+fn renders_demo_scene() {
+    let Some(renderer) = renderer() else {
+        return;
+    };
+    let gpu = renderer.gpu();
+    let mut assets = Assets::new(
+        gpu.memory_allocator.clone(),
+        gpu.command_buffer_allocator.clone(),
+        gpu.queue.clone(),
+        || {},
+    );
+    let scene = Scene::demo(&mut assets);
+    assert!(assets.wait_for_loads().is_empty());
+
+    let [width, height] = [64, 48];
+    let bounds = scene.bounds();
+    let view_proj = Camera::framing(&bounds).view_proj(width as f32 / height as f32, &bounds);
+    let image = renderer
+        .render(&scene, view_proj, [width, height], CLEAR_COLOR)
+        .unwrap();
+
+    assert_eq!(image.dimensions(), (width, height));
+    // The camera frames the scene, so the objects cover the middle and leave the corners clear.
+    assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 255]);
+    assert_ne!(image.get_pixel(width / 2, height / 2).0, [0, 0, 0, 255]);
+
+    // The same scene renders the same on a software device.
+    let again = renderer
+        .render(&scene, view_proj, [width, height], CLEAR_COLOR)
+        .unwrap();
+    assert_eq!(image, again);
+}